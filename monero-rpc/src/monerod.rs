@@ -1,9 +1,11 @@
 use anyhow::{Context, Result};
+use curve25519_dalek::edwards::CompressedEdwardsY;
 use monero::cryptonote::hash::Hash;
 use monero::util::ringct;
 use monero::PublicKey;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize, Serializer};
+use std::convert::TryFrom;
 
 #[jsonrpc_client::api(version = "2.0")]
 pub trait MonerodRpc {
@@ -12,6 +14,15 @@ pub trait MonerodRpc {
     async fn get_block_header_by_height(&self, height: u32) -> BlockHeader;
     async fn get_block_count(&self) -> BlockCount;
     async fn get_block(&self, height: u32) -> GetBlockResponse;
+    async fn get_fee_estimate(&self) -> FeeEstimate;
+    async fn get_output_distribution(
+        &self,
+        amounts: Vec<u64>,
+        from_height: u64,
+        to_height: u64,
+        cumulative: bool,
+        binary: bool,
+    ) -> GetOutputDistribution;
 }
 
 #[jsonrpc_client::implement(MonerodRpc)]
@@ -21,6 +32,8 @@ pub struct Client {
     base_url: reqwest::Url,
     get_o_indexes_bin_url: reqwest::Url,
     get_outs_bin_url: reqwest::Url,
+    is_key_image_spent_url: reqwest::Url,
+    send_raw_transaction_url: reqwest::Url,
 }
 
 impl Client {
@@ -29,7 +42,7 @@ impl Client {
         Self::new("127.0.0.1".to_owned(), port)
     }
 
-    fn new(host: String, port: u16) -> Result<Self> {
+    pub fn new(host: String, port: u16) -> Result<Self> {
         Ok(Self {
             inner: reqwest::ClientBuilder::new()
                 .connection_verbose(true)
@@ -43,6 +56,12 @@ impl Client {
             get_outs_bin_url: format!("http://{}:{}/get_outs.bin", host, port)
                 .parse()
                 .context("url is well formed")?,
+            is_key_image_spent_url: format!("http://{}:{}/is_key_image_spent", host, port)
+                .parse()
+                .context("url is well formed")?,
+            send_raw_transaction_url: format!("http://{}:{}/send_raw_transaction", host, port)
+                .parse()
+                .context("url is well formed")?,
         })
     }
 
@@ -58,6 +77,78 @@ impl Client {
             .await
     }
 
+    /// Check whether the given key images have been spent, either in the
+    /// blockchain or in the transaction pool.
+    ///
+    /// The returned statuses are in the same order as the given key images.
+    pub async fn is_key_image_spent(
+        &self,
+        key_images: &[CompressedEdwardsY],
+    ) -> Result<Vec<KeyImageSpentStatus>> {
+        let payload = IsKeyImageSpentPayload {
+            key_images: key_images
+                .iter()
+                .map(|key_image| hex::encode(key_image.as_bytes()))
+                .collect(),
+        };
+
+        let response = self
+            .inner
+            .post(self.is_key_image_spent_url.clone())
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Request failed with status code {}", response.status())
+        }
+
+        let response = response.json::<IsKeyImageSpentResponse>().await?;
+
+        if response.status != Status::Ok {
+            anyhow::bail!("Failed to check if key images are spent")
+        }
+
+        if response.spent_status.len() != key_images.len() {
+            anyhow::bail!(
+                "Expected {} spent statuses, received {}",
+                key_images.len(),
+                response.spent_status.len()
+            )
+        }
+
+        response
+            .spent_status
+            .into_iter()
+            .map(KeyImageSpentStatus::try_from)
+            .collect()
+    }
+
+    /// Submit the given transaction to the daemon and relay it to the
+    /// network.
+    pub async fn send_raw_transaction(&self, transaction: &monero::Transaction) -> Result<()> {
+        let payload = SendRawTransactionPayload {
+            tx_as_hex: hex::encode(monero::consensus::encode::serialize(transaction)),
+            do_not_relay: false,
+        };
+
+        let response = self
+            .inner
+            .post(self.send_raw_transaction_url.clone())
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Request failed with status code {}", response.status())
+        }
+
+        response
+            .json::<SendRawTransactionResponse>()
+            .await?
+            .into_result()
+    }
+
     async fn binary_request<Req, Res>(&self, url: reqwest::Url, request: Req) -> Result<Res>
     where
         Req: Serialize,
@@ -173,6 +264,107 @@ pub struct GetOIndexesResponse {
     pub o_indexes: Vec<u64>,
 }
 
+#[derive(Clone, Debug, Serialize)]
+struct IsKeyImageSpentPayload {
+    key_images: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IsKeyImageSpentResponse {
+    #[serde(default)]
+    spent_status: Vec<u8>,
+    status: Status,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyImageSpentStatus {
+    Unspent,
+    SpentInBlockchain,
+    SpentInTxPool,
+}
+
+impl KeyImageSpentStatus {
+    pub fn is_spent(&self) -> bool {
+        !matches!(self, KeyImageSpentStatus::Unspent)
+    }
+}
+
+impl TryFrom<u8> for KeyImageSpentStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => KeyImageSpentStatus::Unspent,
+            1 => KeyImageSpentStatus::SpentInBlockchain,
+            2 => KeyImageSpentStatus::SpentInTxPool,
+            other => anyhow::bail!("Unknown key image spent status {}", other),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GetOutputDistribution {
+    pub distributions: Vec<OutputDistribution>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OutputDistribution {
+    pub amount: u64,
+    pub base: u64,
+    /// Number of outputs per block starting at `start_height`, cumulative if
+    /// requested.
+    pub distribution: Vec<u64>,
+    pub start_height: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct FeeEstimate {
+    /// Estimated fee per byte in piconero.
+    pub fee: u64,
+    /// The final fee should be rounded up to a multiple of this mask.
+    #[serde(default)]
+    pub quantization_mask: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct SendRawTransactionPayload {
+    tx_as_hex: String,
+    do_not_relay: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SendRawTransactionResponse {
+    status: Status,
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    double_spend: bool,
+    #[serde(default)]
+    fee_too_low: bool,
+    #[serde(default)]
+    not_relayed: bool,
+}
+
+impl SendRawTransactionResponse {
+    fn into_result(self) -> Result<()> {
+        if self.status == Status::Ok && !self.not_relayed {
+            return Ok(());
+        }
+
+        let reason = if self.double_spend {
+            "double spend".to_owned()
+        } else if self.fee_too_low {
+            "fee too low".to_owned()
+        } else if self.not_relayed {
+            "transaction was not relayed".to_owned()
+        } else {
+            self.reason
+        };
+
+        anyhow::bail!("Failed to send raw transaction: {}", reason)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
 pub enum Status {
     #[serde(rename = "OK")]
@@ -257,3 +449,77 @@ mod byte_array {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_deserialize_is_key_image_spent_response() {
+        let response = r#"{
+          "credits": 0,
+          "spent_status": [0, 1, 2],
+          "status": "OK",
+          "top_hash": "",
+          "untrusted": false
+        }"#;
+
+        let response: IsKeyImageSpentResponse = serde_json::from_str(response).unwrap();
+        let statuses = response
+            .spent_status
+            .into_iter()
+            .map(KeyImageSpentStatus::try_from)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(statuses, vec![
+            KeyImageSpentStatus::Unspent,
+            KeyImageSpentStatus::SpentInBlockchain,
+            KeyImageSpentStatus::SpentInTxPool
+        ]);
+    }
+
+    #[test]
+    fn unknown_key_image_spent_status_is_an_error() {
+        assert!(KeyImageSpentStatus::try_from(3).is_err());
+    }
+
+    #[test]
+    fn rejected_raw_transaction_is_an_error() {
+        let response = r#"{
+          "double_spend": true,
+          "fee_too_low": false,
+          "not_relayed": false,
+          "reason": "",
+          "status": "Failed",
+          "untrusted": false
+        }"#;
+
+        let response: SendRawTransactionResponse = serde_json::from_str(response).unwrap();
+        let error = response.into_result().unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Failed to send raw transaction: double spend"
+        );
+    }
+
+    #[test]
+    fn can_deserialize_fee_estimate() {
+        let response = r#"{
+          "credits": 0,
+          "fee": 7874,
+          "quantization_mask": 10000,
+          "status": "OK",
+          "top_hash": "",
+          "untrusted": false
+        }"#;
+
+        let fee_estimate: FeeEstimate = serde_json::from_str(response).unwrap();
+
+        assert_eq!(fee_estimate, FeeEstimate {
+            fee: 7874,
+            quantization_mask: 10000
+        });
+    }
+}
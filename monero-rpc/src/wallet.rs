@@ -33,6 +33,20 @@ pub trait MoneroWalletRpc {
     async fn refresh(&self) -> Refreshed;
     async fn sweep_all(&self, address: String) -> SweepAll;
     async fn get_version(&self) -> Version;
+    async fn set_daemon(&self, address: String, trusted: bool) -> DaemonSet;
+    async fn get_reserve_proof(
+        &self,
+        all: bool,
+        account_index: u32,
+        amount: u64,
+        message: String,
+    ) -> GetReserveProof;
+    async fn check_reserve_proof(
+        &self,
+        address: String,
+        message: String,
+        signature: String,
+    ) -> CheckReserveProof;
 }
 
 #[jsonrpc_client::implement(MoneroWalletRpc)]
@@ -197,9 +211,22 @@ pub struct Version {
     pub version: u32,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetReserveProof {
+    pub signature: String,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct CheckReserveProof {
+    pub good: bool,
+    pub spent: u64,
+    pub total: u64,
+}
+
 pub type WalletCreated = Empty;
 pub type WalletClosed = Empty;
 pub type WalletOpened = Empty;
+pub type DaemonSet = Empty;
 
 /// Zero-sized struct to allow serde to deserialize an empty JSON object.
 ///
@@ -258,4 +285,19 @@ mod tests {
 
         let _: Response<WalletCreated> = serde_json::from_str(&response).unwrap();
     }
+
+    #[test]
+    fn can_deserialize_check_reserve_proof() {
+        let response = r#"{
+          "id": "0",
+          "jsonrpc": "2.0",
+          "result": {
+            "good": true,
+            "spent": 0,
+            "total": 100000000000
+          }
+        }"#;
+
+        let _: Response<CheckReserveProof> = serde_json::from_str(response).unwrap();
+    }
 }
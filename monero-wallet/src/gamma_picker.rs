@@ -0,0 +1,188 @@
+//! Decoy selection following the spending pattern assumed by monero's own
+//! wallet, see `gamma_picker` in `src/wallet/wallet2.cpp` of monero.
+//!
+//! The age of a decoy is drawn from a gamma distribution that was fitted to
+//! the age of real spends (Möser et al., "An Empirical Analysis of
+//! Traceability in the Monero Blockchain"), converted into an output index
+//! using the average time between two outputs over the last year.
+
+use anyhow::{bail, Result};
+use rand::Rng;
+
+const GAMMA_SHAPE: f64 = 19.28;
+const GAMMA_SCALE: f64 = 1.0 / 1.61;
+
+const DIFFICULTY_TARGET_SECS: f64 = 120.0;
+/// Outputs can only be spent once they are this many blocks deep.
+const SPENDABLE_AGE: usize = 10;
+const DEFAULT_UNLOCK_TIME_SECS: f64 = 10.0 * DIFFICULTY_TARGET_SECS;
+const RECENT_SPEND_WINDOW_SECS: f64 = 15.0 * DIFFICULTY_TARGET_SECS;
+const BLOCKS_IN_A_YEAR: usize = 365 * 24 * 60 * 60 / 120;
+
+#[derive(Debug)]
+pub struct GammaPicker {
+    rct_offsets: Vec<u64>,
+    num_spendable_outputs: u64,
+    average_output_time: f64,
+}
+
+impl GammaPicker {
+    /// Creates a picker over the cumulative number of RingCT outputs per
+    /// block, as returned by monerod's `get_output_distribution`.
+    pub fn new(rct_offsets: Vec<u64>) -> Result<Self> {
+        let num_blocks = rct_offsets.len();
+
+        if num_blocks <= SPENDABLE_AGE {
+            bail!("Not enough blocks to select decoys from")
+        }
+
+        let blocks_to_consider = num_blocks.min(BLOCKS_IN_A_YEAR);
+        let outputs_before = if blocks_to_consider < num_blocks {
+            rct_offsets[num_blocks - blocks_to_consider - 1]
+        } else {
+            0
+        };
+        let outputs_to_consider = rct_offsets[num_blocks - 1] - outputs_before;
+        let num_spendable_outputs = rct_offsets[num_blocks - SPENDABLE_AGE - 1];
+
+        if outputs_to_consider == 0 || num_spendable_outputs == 0 {
+            bail!("No spendable outputs to select decoys from")
+        }
+
+        let average_output_time =
+            DIFFICULTY_TARGET_SECS * blocks_to_consider as f64 / outputs_to_consider as f64;
+
+        Ok(Self {
+            rct_offsets,
+            num_spendable_outputs,
+            average_output_time,
+        })
+    }
+
+    pub fn num_spendable_outputs(&self) -> u64 {
+        self.num_spendable_outputs
+    }
+
+    /// Picks the global index of a decoy.
+    ///
+    /// Returns `None` if the drawn age lies outside of the chain, in which case
+    /// the caller should simply pick again.
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> Option<u64> {
+        let age = sample_gamma(rng, GAMMA_SHAPE, GAMMA_SCALE).exp();
+
+        let age = if age > DEFAULT_UNLOCK_TIME_SECS {
+            age - DEFAULT_UNLOCK_TIME_SECS
+        } else {
+            rng.gen_range(0.0, RECENT_SPEND_WINDOW_SECS)
+        };
+
+        // Float to int casts saturate, an age beyond the chain is rejected below.
+        let output_age = (age / self.average_output_time) as u64;
+        if output_age >= self.num_spendable_outputs {
+            return None;
+        }
+        let output_index = self.num_spendable_outputs - 1 - output_age;
+
+        // Pick a random output of the block that contains the chosen index, as
+        // outputs within one block are indistinguishable by age.
+        let spendable_blocks = &self.rct_offsets[..self.rct_offsets.len() - SPENDABLE_AGE];
+        let block = spendable_blocks.partition_point(|offset| *offset <= output_index);
+
+        let first_output = match block {
+            0 => 0,
+            block => self.rct_offsets[block - 1],
+        };
+        let outputs_in_block = self.rct_offsets[block] - first_output;
+        if outputs_in_block == 0 {
+            return None;
+        }
+
+        Some(first_output + rng.gen_range(0, outputs_in_block))
+    }
+}
+
+/// Samples a gamma distribution using the method of Marsaglia and Tsang, which
+/// is valid for `shape >= 1`.
+fn sample_gamma<R: Rng>(rng: &mut R, shape: f64, scale: f64) -> f64 {
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+
+    loop {
+        let x = sample_standard_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+
+        let u = rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v * scale;
+        }
+    }
+}
+
+/// Samples a standard normal distribution using the Box-Muller transform.
+fn sample_standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    // Shift into (0, 1] to avoid taking the logarithm of zero.
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SAMPLES: usize = 20_000;
+
+    #[test]
+    fn gamma_samples_match_reference_moments() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let samples = (0..SAMPLES)
+            .map(|_| sample_gamma(&mut rng, GAMMA_SHAPE, GAMMA_SCALE))
+            .collect::<Vec<_>>();
+
+        let mean = samples.iter().sum::<f64>() / SAMPLES as f64;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / SAMPLES as f64;
+
+        // mean = shape * scale, variance = shape * scale^2
+        assert!((mean - 11.975).abs() < 0.1, "mean was {}", mean);
+        assert!((variance - 7.438).abs() < 0.4, "variance was {}", variance);
+    }
+
+    #[test]
+    fn picks_spendable_outputs_with_reference_age() {
+        let outputs_per_block = 10;
+        let num_blocks = 100_000;
+        let rct_offsets = (1..=num_blocks)
+            .map(|block| block * outputs_per_block)
+            .collect::<Vec<_>>();
+        let picker = GammaPicker::new(rct_offsets).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut ages_in_blocks = (0..SAMPLES)
+            .filter_map(|_| picker.pick(&mut rng))
+            .map(|index| {
+                assert!(index < picker.num_spendable_outputs());
+                (num_blocks * outputs_per_block - 1 - index) / outputs_per_block
+            })
+            .collect::<Vec<_>>();
+        ages_in_blocks.sort_unstable();
+
+        // The median age of a real spend according to the fitted distribution is
+        // exp(11.77) seconds, roughly 1070 blocks.
+        let median = ages_in_blocks[ages_in_blocks.len() / 2];
+        assert!((950..1200).contains(&median), "median was {}", median);
+        assert!(ages_in_blocks[0] >= SPENDABLE_AGE as u64);
+    }
+
+    #[test]
+    fn cannot_pick_from_too_few_blocks() {
+        assert!(GammaPicker::new(vec![1; SPENDABLE_AGE]).is_err());
+        assert!(GammaPicker::new(vec![0; 100]).is_err());
+    }
+}
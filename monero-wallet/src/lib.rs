@@ -1,9 +1,16 @@
+mod gamma_picker;
+
+pub use gamma_picker::GammaPicker;
+
 use anyhow::{Context, Result};
 use monero::consensus::encode::VarInt;
-use monero::cryptonote::hash::Hashable;
 use monero_rpc::monerod;
-use monero_rpc::monerod::{GetBlockResponse, MonerodRpc as _};
-use rand::Rng;
+use monero_rpc::monerod::MonerodRpc as _;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+
+/// Number of picks after which we give up on finding enough decoys.
+const MAX_DECOY_PICKS: usize = 10_000;
 
 pub struct Wallet {
     client: monerod::Client,
@@ -13,45 +20,43 @@ impl Wallet {
     /// Chooses 10 random key offsets for use within a new confidential
     /// transactions.
     ///
-    /// Choosing these offsets randomly is not ideal for privacy, instead they
-    /// should be chosen in a way that mimics a real spending pattern as much as
-    /// possible.
+    /// The offsets are picked with the same gamma distribution over the age of
+    /// outputs that monero's wallet uses, so that they mimic a real spending
+    /// pattern. The offsets are distinct and returned in ascending order.
     pub async fn choose_ten_random_key_offsets(&self) -> Result<[VarInt; 10]> {
-        let latest_block = self.client.get_block_count().await?;
-        let latest_spendable_block = latest_block.count - 10;
+        let response = self
+            .client
+            .get_output_distribution(vec![0], 0, 0, true, false)
+            .await?;
+        let rct_offsets = response
+            .distributions
+            .into_iter()
+            .next()
+            .context("Expected output distribution of RingCT outputs")?
+            .distribution;
 
-        let block: GetBlockResponse = self.client.get_block(latest_spendable_block).await?;
+        let picker = GammaPicker::new(rct_offsets)?;
+        let mut rng = rand::thread_rng();
+        let mut offsets = BTreeSet::new();
 
-        let tx_hash = block
-            .blob
-            .tx_hashes
-            .first()
-            .copied()
-            .unwrap_or_else(|| block.blob.miner_tx.hash());
+        for _ in 0..MAX_DECOY_PICKS {
+            if offsets.len() == 10 {
+                break;
+            }
 
-        let indices = self.client.get_o_indexes(tx_hash).await?;
+            if let Some(index) = picker.pick(&mut rng) {
+                offsets.insert(index);
+            }
+        }
 
-        let last_index = indices
-            .o_indexes
+        let offsets: [VarInt; 10] = offsets
             .into_iter()
-            .max()
-            .context("Expected at least one output index")?;
-        let oldest_index = last_index - (last_index / 100) * 40; // oldest index must be within last 40% TODO: CONFIRM THIS
-
-        let mut rng = rand::thread_rng();
+            .map(VarInt)
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Failed to pick 10 distinct decoys"))?;
 
-        Ok([
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-            VarInt(rng.gen_range(oldest_index, last_index)),
-        ])
+        Ok(offsets)
     }
 }
 
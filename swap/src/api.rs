@@ -0,0 +1,193 @@
+//! The operations of the CLI as a library, for applications that embed the
+//! swap logic instead of running the `swap` binary.
+//!
+//! Every operation has a module with a `Request`, which is called with a
+//! [`Context`] and returns the `Response` of the operation. Requests don't
+//! print their results, progress is reported through `tracing`.
+
+pub mod balance;
+pub mod buy_xmr;
+pub mod cancel;
+pub mod history;
+pub mod resume;
+
+use crate::database::Database;
+use crate::encryption::Passphrase;
+use crate::protocol::bob;
+use crate::protocol::bob::{BobState, Swap};
+use crate::seed::Seed;
+use crate::shutdown::Shutdown;
+use crate::{bitcoin, env, monero};
+use anyhow::{bail, Context as _, Result};
+use libp2p::identity;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// What the requests have in common, regardless of the operation.
+pub struct Context {
+    pub env_config: env::Config,
+    pub data_dir: PathBuf,
+    /// Encrypts the seed and the database at rest.
+    pub passphrase: Option<Passphrase>,
+    /// Signs with a hardware wallet instead of the internal wallet.
+    pub hardware_wallet: Option<bitcoin::Hwi>,
+}
+
+impl Context {
+    pub fn database_path(&self) -> PathBuf {
+        self.data_dir.join("database")
+    }
+
+    pub fn open_database(&self) -> Result<Database> {
+        let path = self.database_path();
+
+        match &self.passphrase {
+            Some(passphrase) => Database::open_encrypted(&path, passphrase),
+            None => Database::open(&path),
+        }
+        .context("Failed to open database")
+    }
+
+    pub fn read_seed(&self) -> Result<Seed> {
+        match &self.passphrase {
+            Some(passphrase) => Seed::from_encrypted_file_or_generate(&self.data_dir, passphrase),
+            None => Seed::from_file_or_generate(&self.data_dir),
+        }
+        .context("Failed to read in seed file")
+    }
+
+    /// Reads the seed for commands that must not create one, e.g. because
+    /// they decrypt data that was encrypted with an existing seed.
+    pub fn read_existing_seed(&self) -> Result<Seed> {
+        Seed::from_existing_file(&self.data_dir, self.passphrase.as_ref())
+            .context("Failed to read in seed file")
+    }
+
+    pub fn libp2p_identity(&self, seed: &Seed) -> Result<identity::Keypair> {
+        seed.libp2p_identity(&self.data_dir, self.passphrase.as_ref())
+    }
+
+    pub async fn init_bitcoin_wallet(
+        &self,
+        seed: &Seed,
+        electrum_rpc_urls: Vec<Url>,
+        bitcoin_target_block: usize,
+    ) -> Result<bitcoin::Wallet> {
+        let wallet = match self.hardware_wallet.clone() {
+            Some(hwi) => {
+                // The watch-only wallet cannot share the database of the internal
+                // wallet because its descriptors differ.
+                let wallet_dir = self
+                    .data_dir
+                    .join("hardware-wallets")
+                    .join(hwi.fingerprint().to_string());
+
+                bitcoin::Wallet::new_hardware(
+                    electrum_rpc_urls,
+                    &wallet_dir,
+                    hwi,
+                    self.env_config,
+                    bitcoin_target_block,
+                )
+                .await
+            }
+            None => {
+                bitcoin::Wallet::new(
+                    electrum_rpc_urls,
+                    &self.data_dir.join("wallet"),
+                    seed.derive_extended_private_key(self.env_config.bitcoin_network)?,
+                    self.env_config,
+                    bitcoin_target_block,
+                    None,
+                )
+                .await
+            }
+        }
+        .context("Failed to initialize Bitcoin wallet")?;
+
+        wallet.sync().await?;
+
+        Ok(wallet)
+    }
+
+    /// Starts the wallet RPC with the healthiest of the given Monero nodes and
+    /// keeps failing over to another node in the background.
+    ///
+    /// The wallet RPC stops once the returned process is dropped.
+    pub async fn init_monero_wallet(
+        &self,
+        monero_daemon_addresses: Vec<String>,
+    ) -> Result<(Arc<monero::Wallet>, monero::WalletRpcProcess)> {
+        let network = self.env_config.monero_network;
+
+        const MONERO_BLOCKCHAIN_MONITORING_WALLET_NAME: &str =
+            "swap-tool-blockchain-monitoring-wallet";
+
+        let mut node_pool = monero::NodePool::new(monero_daemon_addresses)?;
+        let monero_daemon_address = node_pool.select_healthiest().await.to_owned();
+        tracing::debug!(%monero_daemon_address, "Selected Monero node");
+
+        let monero_wallet_rpc = monero::WalletRpc::new(self.data_dir.join("monero")).await?;
+
+        let monero_wallet_rpc_process = monero_wallet_rpc
+            .run(network, monero_daemon_address.as_str())
+            .await?;
+
+        let monero_wallet = Arc::new(
+            monero::Wallet::open_or_create(
+                monero_wallet_rpc_process.endpoint(),
+                MONERO_BLOCKCHAIN_MONITORING_WALLET_NAME.to_string(),
+                self.env_config,
+            )
+            .await?,
+        );
+
+        tokio::spawn(node_pool.run(monero_wallet.clone()));
+
+        Ok((monero_wallet, monero_wallet_rpc_process))
+    }
+}
+
+/// Runs the swap until it completes or a shutdown is requested, returning the
+/// final state or `None` if the swap was suspended.
+///
+/// On shutdown the swap is suspended at the next point where this is safe and
+/// the Monero wallet is stored.
+async fn run_swap(
+    swap: Swap,
+    mut event_loop: JoinHandle<()>,
+    monero_wallet: Arc<monero::Wallet>,
+    shutdown: Shutdown,
+) -> Result<Option<BobState>> {
+    let db = swap.db.clone();
+    let swap_id = swap.id;
+
+    tokio::select! {
+        result = &mut event_loop => {
+            result.context("EventLoop panicked")?;
+
+            match bob::swap::completed_state(&db, swap_id)? {
+                Some(state) => {
+                    tracing::warn!("Lost the connection to the seller before the receipt was signed");
+                    Ok(Some(state))
+                }
+                None => bail!("EventLoop stopped before the swap completed"),
+            }
+        },
+        result = bob::run_until_shutdown(swap, shutdown) => {
+            let state = result.context("Failed to complete swap")?;
+            event_loop.abort();
+
+            if state.is_none() {
+                monero_wallet
+                    .close()
+                    .await
+                    .context("Failed to close Monero wallet")?;
+            }
+
+            Ok(state)
+        }
+    }
+}
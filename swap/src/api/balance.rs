@@ -0,0 +1,41 @@
+//! The Bitcoin that is available for swaps.
+
+use crate::api::Context;
+use crate::bitcoin;
+use crate::bitcoin::TxLock;
+use anyhow::Result;
+use serde::Serialize;
+use url::Url;
+
+pub struct Request {
+    pub bitcoin_electrum_rpc_urls: Vec<Url>,
+    pub bitcoin_target_block: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Response {
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub balance: bitcoin::Amount,
+    /// The most that can be locked in a swap after paying the fee of the lock
+    /// transaction.
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub max_giveable: bitcoin::Amount,
+}
+
+impl Request {
+    pub async fn call(self, context: &Context) -> Result<Response> {
+        let seed = context.read_seed()?;
+        let bitcoin_wallet = context
+            .init_bitcoin_wallet(
+                &seed,
+                self.bitcoin_electrum_rpc_urls,
+                self.bitcoin_target_block,
+            )
+            .await?;
+
+        Ok(Response {
+            balance: bitcoin_wallet.balance().await?,
+            max_giveable: bitcoin_wallet.max_giveable(TxLock::script_size()).await?,
+        })
+    }
+}
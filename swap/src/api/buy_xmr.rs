@@ -0,0 +1,1035 @@
+//! Buying XMR from a single seller.
+
+use crate::api::{run_swap, Context};
+use crate::bitcoin::{CoinControl, TxCancel, TxLock, TxRefund};
+use crate::libp2p_ext::MultiAddrExt;
+use crate::network::quote::{BidQuote, SignedBidQuote};
+use crate::network::swap_setup::SessionNonce;
+use crate::network::swarm;
+use crate::protocol::bob::{BobState, Swap};
+use crate::shutdown::Shutdown;
+use crate::{bitcoin, cli, monero};
+use anyhow::{bail, Context as _, Result};
+use libp2p::Multiaddr;
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+use uuid::Uuid;
+
+pub struct Request {
+    /// Drawn by the caller, the id of the swap is derived from it and the
+    /// seller, see [`Request::swap_id`].
+    pub session_nonce: SessionNonce,
+    pub seller: Multiaddr,
+    pub bitcoin_electrum_rpc_urls: Vec<Url>,
+    pub bitcoin_target_block: usize,
+    /// Both addresses must be of the network of the context. Unlike the
+    /// `swap` binary, integrated receive addresses are not refused.
+    pub bitcoin_change_address: bitcoin::Address,
+    /// May be a subaddress.
+    pub monero_receive_address: monero::Address,
+    /// Swaps all available Bitcoin if not given.
+    pub monero_amount: Option<monero::Amount>,
+    pub monero_daemon_addresses: Vec<String>,
+    /// Merged with the coin control of the config file.
+    pub coin_control: CoinControl,
+    pub tor_socks5_port: u16,
+    /// Refuses to swap if a XMR would cost more than this, including fees.
+    pub max_effective_price: Option<bitcoin::Amount>,
+    /// Asked to accept the expected costs before the Bitcoin is locked, the
+    /// swap is aborted if it returns `false`.
+    pub confirm: Option<Box<dyn Fn(&CostPreview) -> Result<bool> + Send + Sync>>,
+    /// Told the address to deposit Bitcoin to if the wallet holds too little
+    /// to swap, e.g. to show it as a QR code.
+    pub on_deposit_address: Option<Box<dyn Fn(&bitcoin::Address) -> Result<()> + Send + Sync>>,
+}
+
+/// What a swap is expected to cost, known once the Bitcoin to swap was
+/// deposited.
+///
+/// The Monero amount is an estimate unless a specific amount was requested,
+/// the seller determines it from the Bitcoin amount when the swap is set up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostPreview {
+    /// The seller's price per XMR.
+    pub price: bitcoin::Amount,
+    /// The part of the price the seller keeps on top of the market price,
+    /// unknown for sellers that don't tell.
+    pub maker_fee: Option<bitcoin::Amount>,
+    pub bitcoin_amount: bitcoin::Amount,
+    /// The miner fee of the lock transaction.
+    pub bitcoin_lock_fee: bitcoin::Amount,
+    /// The miner fees of the cancel and refund transactions, which are only
+    /// paid out of the locked Bitcoin if the swap is refunded.
+    pub bitcoin_refund_fees: bitcoin::Amount,
+    pub monero_amount: monero::Amount,
+    /// The fee of sweeping the Monero to the receive address.
+    pub monero_fee: monero::Amount,
+    /// The Bitcoin spent per XMR received, including the fees of a
+    /// successful swap.
+    pub effective_price: bitcoin::Amount,
+}
+
+impl CostPreview {
+    /// Returns `None` if the price is zero or the Monero does not cover the
+    /// fee of sweeping it.
+    fn new(
+        quote: &SignedBidQuote,
+        bitcoin_amount: bitcoin::Amount,
+        bitcoin_lock_fee: bitcoin::Amount,
+        bitcoin_refund_fees: bitcoin::Amount,
+        monero_amount: Option<monero::Amount>,
+    ) -> Option<Self> {
+        let price = quote.quote.price;
+        let monero_amount = match monero_amount {
+            Some(monero_amount) => monero_amount,
+            None => monero::Amount::for_bitcoin_at_price(bitcoin_amount, price)?,
+        };
+        let monero_fee = monero::MONERO_FEE;
+        let monero_received = monero::Amount::from_piconero(
+            monero_amount
+                .as_piconero()
+                .checked_sub(monero_fee.as_piconero())?,
+        );
+
+        Some(Self {
+            price,
+            maker_fee: quote.maker_fee(),
+            bitcoin_amount,
+            bitcoin_lock_fee,
+            bitcoin_refund_fees,
+            monero_amount,
+            monero_fee,
+            effective_price: effective_price(bitcoin_amount + bitcoin_lock_fee, monero_received)?,
+        })
+    }
+
+    /// The least Monero the seller has to offer for the same Bitcoin so that
+    /// the effective price does not exceed `max_effective_price`.
+    ///
+    /// Returns `None` if the maximum price is zero.
+    fn min_monero_at(&self, max_effective_price: bitcoin::Amount) -> Option<monero::Amount> {
+        let monero_received = monero::Amount::for_bitcoin_at_price(
+            self.bitcoin_amount + self.bitcoin_lock_fee,
+            max_effective_price,
+        )?;
+
+        Some(monero_received + self.monero_fee)
+    }
+}
+
+impl fmt::Display for CostPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Price per XMR:           {}", self.price)?;
+        if let Some(maker_fee) = self.maker_fee {
+            write!(f, " ({} of it seller fee)", maker_fee)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Bitcoin to lock:         {}", self.bitcoin_amount)?;
+        writeln!(f, "Bitcoin lock fee:        {}", self.bitcoin_lock_fee)?;
+        writeln!(
+            f,
+            "Bitcoin refund fees:     {} (only paid if the swap is refunded)",
+            self.bitcoin_refund_fees
+        )?;
+        writeln!(f, "Monero to receive:       ~{}", self.monero_amount)?;
+        writeln!(f, "Monero network fee:      ~{}", self.monero_fee)?;
+        write!(f, "Effective price per XMR: ~{}", self.effective_price)
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub swap_id: Uuid,
+    pub costs: CostPreview,
+    /// The final state of the swap, `None` if it was suspended on shutdown.
+    pub state: Option<BobState>,
+}
+
+impl Request {
+    /// The id of the swap, known before the swap starts.
+    pub fn swap_id(&self) -> Result<Uuid> {
+        let seller_peer_id = self
+            .seller
+            .extract_peer_id()
+            .context("Seller address must contain peer ID")?;
+
+        Ok(self.session_nonce.swap_id(seller_peer_id))
+    }
+
+    /// Waits for enough Bitcoin to be deposited and runs the swap until it
+    /// completes or a shutdown is requested.
+    pub async fn call(self, context: &Context, shutdown: Shutdown) -> Result<Response> {
+        let swap_id = self.swap_id()?;
+        let Request {
+            session_nonce,
+            seller,
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+            bitcoin_change_address,
+            monero_receive_address,
+            monero_amount,
+            monero_daemon_addresses,
+            coin_control,
+            tor_socks5_port,
+            max_effective_price,
+            confirm,
+            on_deposit_address,
+        } = self;
+        let env_config = context.env_config;
+
+        if bitcoin_change_address.network != env_config.bitcoin_network {
+            bail!(
+                "Change address {} is not an address on network {}",
+                bitcoin_change_address,
+                env_config.bitcoin_network
+            )
+        }
+        if bitcoin::is_burn_address(&bitcoin_change_address) {
+            bail!(bitcoin::BurnAddress(bitcoin_change_address))
+        }
+        if monero_receive_address.network != env_config.monero_network {
+            bail!(
+                "Receive address {} is not an address on network {:?}",
+                monero_receive_address,
+                env_config.monero_network
+            )
+        }
+        if monero::is_burn_address(&monero_receive_address) {
+            bail!(monero::BurnAddress(monero_receive_address))
+        }
+
+        let db = context.open_database()?;
+        let seed = context.read_seed()?;
+        let identity = context.libp2p_identity(&seed)?;
+
+        let coin_control = cli::config::Config::read(&context.data_dir)?
+            .coin_control
+            .merge(coin_control);
+        let bitcoin_wallet = context
+            .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+            .await?
+            .with_coin_control(coin_control);
+        let emergency_kit_path = cli::emergency_kit::default_path(&context.data_dir, swap_id);
+        let (monero_wallet, _process) = context.init_monero_wallet(monero_daemon_addresses).await?;
+        let bitcoin_wallet = Arc::new(bitcoin_wallet);
+
+        let seller_peer_id = seller
+            .extract_peer_id()
+            .context("Seller address must contain peer ID")?;
+        db.insert_address(seller_peer_id, seller.clone()).await?;
+
+        let behaviour = cli::Behaviour::new(seller_peer_id, env_config, bitcoin_wallet.clone());
+        let mut swarm = swarm::cli(identity, tor_socks5_port, behaviour).await?;
+        swarm.behaviour_mut().add_address(seller_peer_id, seller);
+
+        tracing::debug!(peer_id = %swarm.local_peer_id(), "Network layer initialized");
+
+        let (event_loop, mut event_loop_handle) =
+            cli::EventLoop::new(swap_id, swarm, seller_peer_id, env_config)?;
+        let event_loop = tokio::spawn(event_loop.run());
+
+        tracing::debug!("Requesting quote");
+        let quote = event_loop_handle.request_quote().await?;
+
+        let max_givable = || bitcoin_wallet.max_giveable(TxLock::script_size());
+        let fee_for_amount = |amount| bitcoin_wallet.fee_for_amount(TxLock::script_size(), amount);
+        let (amount, fees) = determine_btc_to_swap(
+            on_deposit_address.as_deref(),
+            async { Ok(quote.quote) },
+            monero_amount,
+            bitcoin_wallet.new_address(),
+            || bitcoin_wallet.balance(),
+            max_givable,
+            fee_for_amount,
+            || bitcoin_wallet.sync(),
+        )
+        .await?;
+
+        let refund_fees = bitcoin_wallet
+            .estimate_fee(TxCancel::weight(), amount)
+            .await?
+            + bitcoin_wallet
+                .estimate_fee(TxRefund::weight(), amount)
+                .await?;
+        let costs = CostPreview::new(&quote, amount, fees, refund_fees, monero_amount)
+            .context("Failed to calculate the costs of the swap")?;
+
+        // The preview is based on a quote that may be hours old, the seller
+        // determines the Monero amount at its current price during the swap
+        // setup. The swap is aborted before locking the Bitcoin if that amount
+        // is less than what the checks below accepted.
+        let mut min_monero = monero::Amount::ZERO;
+        if let Some(max_effective_price) = max_effective_price {
+            if costs.effective_price > max_effective_price {
+                bail!(EffectivePriceTooHigh {
+                    effective_price: costs.effective_price,
+                    max_effective_price
+                })
+            }
+            min_monero = costs
+                .min_monero_at(max_effective_price)
+                .context("Failed to calculate the least Monero to accept")?;
+        }
+        if let Some(confirm) = confirm {
+            if !confirm(&costs)? {
+                bail!(SwapDeclined)
+            }
+            if costs.monero_amount > min_monero {
+                min_monero = costs.monero_amount;
+            }
+        }
+
+        tracing::info!(%amount, %fees, %swap_id,  "Starting new swap");
+
+        db.insert_peer_id(swap_id, seller_peer_id).await?;
+        db.insert_monero_address(swap_id, monero_receive_address)
+            .await?;
+
+        tokio::spawn(cli::auto_refund::refund_expired_swaps(
+            bitcoin_wallet.clone(),
+            db.clone(),
+            Some(swap_id),
+            env_config.bitcoin_avg_block_time,
+        ));
+        tokio::spawn(cli::emergency_kit::write_once_btc_locked(
+            db.clone(),
+            swap_id,
+            emergency_kit_path,
+            Duration::from_secs(5),
+        ));
+
+        let swap = Swap::new(
+            db,
+            session_nonce,
+            bitcoin_wallet,
+            monero_wallet.clone(),
+            env_config,
+            event_loop_handle,
+            monero_receive_address,
+            bitcoin_change_address,
+            amount,
+        )
+        .with_min_monero(min_monero);
+
+        let state = run_swap(swap, event_loop, monero_wallet, shutdown).await?;
+
+        Ok(Response {
+            swap_id,
+            costs,
+            state,
+        })
+    }
+}
+
+async fn determine_btc_to_swap<FB, TB, FMG, TMG, FF, TF, FS, TS>(
+    on_deposit_address: Option<&(dyn Fn(&bitcoin::Address) -> Result<()> + Send + Sync)>,
+    bid_quote: impl Future<Output = Result<BidQuote>>,
+    monero_amount: Option<monero::Amount>,
+    get_new_address: impl Future<Output = Result<bitcoin::Address>>,
+    balance: FB,
+    max_giveable_fn: FMG,
+    fee_for_amount_fn: FF,
+    sync: FS,
+) -> Result<(bitcoin::Amount, bitcoin::Amount)>
+where
+    TB: Future<Output = Result<bitcoin::Amount>>,
+    FB: Fn() -> TB,
+    TMG: Future<Output = Result<bitcoin::Amount>>,
+    FMG: Fn() -> TMG,
+    TF: Future<Output = Result<bitcoin::Amount>>,
+    FF: Fn(bitcoin::Amount) -> TF,
+    TS: Future<Output = Result<()>>,
+    FS: Fn() -> TS,
+{
+    let bid_quote = bid_quote.await?;
+    tracing::info!(
+        price = %bid_quote.price,
+        minimum_amount = %bid_quote.min_quantity,
+        maximum_amount = %bid_quote.max_quantity,
+        "Received quote",
+    );
+
+    if bid_quote.max_quantity == bitcoin::Amount::ZERO {
+        bail!(ZeroQuoteReceived)
+    }
+
+    let requested_amount = match monero_amount {
+        Some(monero_amount) => {
+            let amount = monero_amount
+                .bitcoin_for_price(bid_quote.price)
+                .context("Failed to calculate the Bitcoin amount for the requested Monero")?;
+
+            if amount < bid_quote.min_quantity || amount > bid_quote.max_quantity {
+                bail!(AmountOutsideOfQuote {
+                    amount,
+                    min_quantity: bid_quote.min_quantity,
+                    max_quantity: bid_quote.max_quantity
+                })
+            }
+
+            Some(amount)
+        }
+        None => None,
+    };
+    let required_amount = requested_amount.unwrap_or(bid_quote.min_quantity);
+
+    let mut max_giveable = max_giveable_fn().await?;
+
+    if max_giveable == bitcoin::Amount::ZERO || max_giveable < required_amount {
+        let deposit_address = get_new_address.await?;
+        let minimum_amount = bid_quote.min_quantity;
+        let maximum_amount = bid_quote.max_quantity;
+
+        if let Some(on_deposit_address) = on_deposit_address {
+            on_deposit_address(&deposit_address)?;
+        }
+
+        loop {
+            tracing::info!(
+                %deposit_address,
+                %max_giveable,
+                %minimum_amount,
+                %maximum_amount,
+                "Waiting for Bitcoin deposit",
+            );
+
+            max_giveable = loop {
+                sync().await?;
+                let new_max_givable = max_giveable_fn().await?;
+
+                if new_max_givable > max_giveable {
+                    break new_max_givable;
+                }
+
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            };
+
+            let new_balance = balance().await?;
+            tracing::info!(%new_balance, %max_giveable, "Received Bitcoin");
+
+            if max_giveable < bid_quote.min_quantity {
+                tracing::info!("Deposited amount is less than `min_quantity`");
+                continue;
+            }
+
+            if max_giveable < required_amount {
+                tracing::info!(%required_amount, "Deposited amount is less than the amount required for the requested Monero");
+                continue;
+            }
+
+            break;
+        }
+    };
+
+    let (btc_swap_amount, fees) = match requested_amount {
+        Some(amount) => {
+            // Only the requested amount is locked, the rest of the balance is sent back as
+            // change.
+            let fees = fee_for_amount_fn(amount).await?;
+
+            (amount, fees)
+        }
+        None => {
+            let balance = balance().await?;
+            let fees = balance - max_giveable;
+
+            (min(max_giveable, bid_quote.max_quantity), fees)
+        }
+    };
+
+    let monero_amount = monero_amount
+        .or_else(|| monero::Amount::for_bitcoin_at_price(btc_swap_amount, bid_quote.price));
+    if let Some(monero_amount) = monero_amount {
+        if let Some(effective_price) = effective_price(btc_swap_amount + fees, monero_amount) {
+            tracing::info!(
+                %monero_amount,
+                %effective_price,
+                "Expected Monero and price per XMR including Bitcoin fees"
+            );
+        }
+    }
+
+    Ok((btc_swap_amount, fees))
+}
+
+/// The price paid per XMR when spending the given total amount of Bitcoin,
+/// including fees, on the given amount of Monero.
+fn effective_price(
+    total: bitcoin::Amount,
+    monero_amount: monero::Amount,
+) -> Option<bitcoin::Amount> {
+    let sats = u128::from(total.as_sat())
+        .checked_mul(u128::from(monero::PICONERO_OFFSET))?
+        .checked_div(u128::from(monero_amount.as_piconero()))?;
+
+    Some(bitcoin::Amount::from_sat(u64::try_from(sats).ok()?))
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The requested amount of {amount} is outside of the range accepted by the seller ({min_quantity} - {max_quantity})")]
+struct AmountOutsideOfQuote {
+    amount: bitcoin::Amount,
+    min_quantity: bitcoin::Amount,
+    max_quantity: bitcoin::Amount,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Received quote of 0, the seller currently does not accept any swaps")]
+struct ZeroQuoteReceived;
+
+#[derive(Debug, thiserror::Error)]
+#[error("The swap would cost {effective_price} per XMR including fees, more than the maximum of {max_effective_price}")]
+pub struct EffectivePriceTooHigh {
+    pub effective_price: bitcoin::Amount,
+    pub max_effective_price: bitcoin::Amount,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The costs of the swap were declined, no Bitcoin was locked")]
+pub struct SwapDeclined;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracing_ext::capture_logs;
+    use ::bitcoin::Amount;
+    use std::sync::Mutex;
+    use tracing::level_filters::LevelFilter;
+
+    #[tokio::test]
+    async fn given_no_balance_and_transfers_less_than_max_swaps_max_giveable() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::ZERO,
+            Amount::from_btc(0.0009).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            None,
+            get_dummy_address(),
+            || async { Ok(Amount::from_btc(0.001)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.0009).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.00100000 BTC max_giveable=0.00090000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=0.900000000000 XMR effective_price=0.00111111 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_no_balance_and_transfers_more_then_swaps_max_quantity_from_quote() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::ZERO,
+            Amount::from_btc(0.1).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            None,
+            get_dummy_address(),
+            || async { Ok(Amount::from_btc(0.1001)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.01).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.10010000 BTC max_giveable=0.10000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=10.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_initial_balance_below_max_quantity_swaps_max_givable() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::from_btc(0.0049).unwrap(),
+            Amount::from_btc(99.9).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            None,
+            async { panic!("should not request new address when initial balance  is > 0") },
+            || async { Ok(Amount::from_btc(0.005)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.0049).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=4.900000000000 XMR effective_price=0.00102040 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_initial_balance_above_max_quantity_swaps_max_quantity() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::from_btc(0.1).unwrap(),
+            Amount::from_btc(99.9).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            None,
+            async { panic!("should not request new address when initial balance is > 0") },
+            || async { Ok(Amount::from_btc(0.1001)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.01).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=10.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_no_initial_balance_then_min_wait_for_sufficient_deposit() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::ZERO,
+            Amount::from_btc(0.01).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_min(0.01)) },
+            None,
+            get_dummy_address(),
+            || async { Ok(Amount::from_btc(0.0101)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.01).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=10.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_balance_less_then_min_wait_for_sufficient_deposit() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::from_btc(0.0001).unwrap(),
+            Amount::from_btc(0.01).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_min(0.01)) },
+            None,
+            get_dummy_address(),
+            || async { Ok(Amount::from_btc(0.0101)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.01).unwrap();
+        let expected_fees = Amount::from_btc(0.0001).unwrap();
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00010000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=10.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_no_initial_balance_and_transfers_less_than_min_keep_waiting() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::ZERO,
+            Amount::from_btc(0.01).unwrap(),
+            Amount::from_btc(0.01).unwrap(),
+            Amount::from_btc(0.01).unwrap(),
+            Amount::from_btc(0.01).unwrap(),
+        ])));
+
+        let error = tokio::time::timeout(
+            Duration::from_secs(1),
+            determine_btc_to_swap(
+                None,
+                async { Ok(quote_with_min(0.1)) },
+                None,
+                get_dummy_address(),
+                || async { Ok(Amount::from_btc(0.0101)?) },
+                || async {
+                    let mut result = givable.lock().unwrap();
+                    result.give()
+                },
+                no_fee_for_amount,
+                || async { Ok(()) },
+            ),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(error, tokio::time::error::Elapsed { .. }));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
+ INFO swap::api::buy_xmr: Deposited amount is less than `min_quantity`
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.01000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_longer_delay_until_deposit_should_not_spam_user() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::ZERO,
+            Amount::from_btc(0.2).unwrap(),
+        ])));
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            determine_btc_to_swap(
+                None,
+                async { Ok(quote_with_min(0.1)) },
+                None,
+                get_dummy_address(),
+                || async { Ok(Amount::from_btc(0.21)?) },
+                || async {
+                    let mut result = givable.lock().unwrap();
+
+                    result.give()
+                },
+                no_fee_for_amount,
+                || async { Ok(()) },
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.21000000 BTC max_giveable=0.20000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=200.000000000000 XMR effective_price=0.00105000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_zero_quote_should_not_wait_for_deposit() {
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![Amount::ZERO])));
+
+        let error = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.0)) },
+            None,
+            get_dummy_address(),
+            || async { Ok(Amount::ZERO) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.downcast_ref::<ZeroQuoteReceived>().is_some());
+    }
+
+    #[tokio::test]
+    async fn given_monero_amount_and_sufficient_balance_swaps_requested_amount() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::from_btc(0.005).unwrap()
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            Some(monero::Amount::from_monero(2.0).unwrap()),
+            async { panic!("should not request new address when initial balance is sufficient") },
+            || async { Ok(Amount::from_btc(0.0051)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            |_| async { Ok(Amount::from_sat(2_000)) },
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.002).unwrap();
+        let expected_fees = Amount::from_sat(2_000);
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=2.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_monero_amount_and_insufficient_balance_wait_for_sufficient_deposit() {
+        let writer = capture_logs(LevelFilter::INFO);
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
+            Amount::from_btc(0.001).unwrap(),
+            Amount::from_btc(0.005).unwrap(),
+        ])));
+
+        let (amount, fees) = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            Some(monero::Amount::from_monero(2.0).unwrap()),
+            get_dummy_address(),
+            || async { Ok(Amount::from_btc(0.0051)?) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            |_| async { Ok(Amount::from_sat(2_000)) },
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap();
+
+        let expected_amount = Amount::from_btc(0.002).unwrap();
+        let expected_fees = Amount::from_sat(2_000);
+
+        assert_eq!((amount, fees), (expected_amount, expected_fees));
+        assert_eq!(
+            writer.captured(),
+            r" INFO swap::api::buy_xmr: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
+ INFO swap::api::buy_xmr: Received Bitcoin new_balance=0.00510000 BTC max_giveable=0.00500000 BTC
+ INFO swap::api::buy_xmr: Expected Monero and price per XMR including Bitcoin fees monero_amount=2.000000000000 XMR effective_price=0.00101000 BTC
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn given_monero_amount_above_max_quantity_should_not_wait_for_deposit() {
+        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![Amount::ZERO])));
+
+        let error = determine_btc_to_swap(
+            None,
+            async { Ok(quote_with_max(0.01)) },
+            Some(monero::Amount::from_monero(20.0).unwrap()),
+            get_dummy_address(),
+            || async { Ok(Amount::ZERO) },
+            || async {
+                let mut result = givable.lock().unwrap();
+                result.give()
+            },
+            no_fee_for_amount,
+            || async { Ok(()) },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.downcast_ref::<AmountOutsideOfQuote>().is_some());
+    }
+
+    struct MaxGiveable {
+        amounts: Vec<Amount>,
+        call_counter: usize,
+    }
+
+    impl MaxGiveable {
+        fn new(amounts: Vec<Amount>) -> Self {
+            Self {
+                amounts,
+                call_counter: 0,
+            }
+        }
+        fn give(&mut self) -> Result<Amount> {
+            let amount = self
+                .amounts
+                .get(self.call_counter)
+                .ok_or_else(|| anyhow::anyhow!("No more balances available"))?;
+            self.call_counter += 1;
+            Ok(*amount)
+        }
+    }
+
+    #[test]
+    fn cost_preview_includes_fees_of_successful_swap() {
+        let quote = SignedBidQuote {
+            market_price: Some(Amount::from_btc(0.00099).unwrap()),
+            ..SignedBidQuote::from(quote_with_max(0.01))
+        };
+
+        let costs = CostPreview::new(
+            &quote,
+            Amount::from_btc(0.001).unwrap(),
+            Amount::from_sat(1000),
+            Amount::from_sat(2000),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(costs.maker_fee, Some(Amount::from_sat(1000)));
+        assert_eq!(costs.monero_amount, monero::Amount::ONE_XMR);
+        // 0.00101 BTC for the 0.99997 XMR left after sweeping
+        assert_eq!(costs.effective_price, Amount::from_sat(101_003));
+        assert_eq!(
+            costs.to_string(),
+            "Price per XMR:           0.00100000 BTC (0.00001000 BTC of it seller fee)
+Bitcoin to lock:         0.00100000 BTC
+Bitcoin lock fee:        0.00001000 BTC
+Bitcoin refund fees:     0.00002000 BTC (only paid if the swap is refunded)
+Monero to receive:       ~1.000000000000 XMR
+Monero network fee:      ~0.000030000000 XMR
+Effective price per XMR: ~0.00101003 BTC"
+        );
+    }
+
+    #[test]
+    fn min_monero_keeps_effective_price_below_maximum() {
+        let costs = CostPreview::new(
+            &SignedBidQuote::from(quote_with_max(0.01)),
+            Amount::from_btc(0.001).unwrap(),
+            Amount::from_sat(1000),
+            Amount::from_sat(2000),
+            None,
+        )
+        .unwrap();
+        let max_effective_price = Amount::from_sat(110_000);
+
+        let min_monero = costs.min_monero_at(max_effective_price).unwrap();
+
+        assert!(min_monero < costs.monero_amount);
+        let total = costs.bitcoin_amount + costs.bitcoin_lock_fee;
+        assert_eq!(
+            effective_price(total, min_monero - costs.monero_fee),
+            Some(max_effective_price)
+        );
+    }
+
+    fn quote_with_max(btc: f64) -> BidQuote {
+        BidQuote {
+            price: Amount::from_btc(0.001).unwrap(),
+            max_quantity: Amount::from_btc(btc).unwrap(),
+            min_quantity: Amount::ZERO,
+        }
+    }
+
+    fn quote_with_min(btc: f64) -> BidQuote {
+        BidQuote {
+            price: Amount::from_btc(0.001).unwrap(),
+            max_quantity: Amount::max_value(),
+            min_quantity: Amount::from_btc(btc).unwrap(),
+        }
+    }
+
+    async fn no_fee_for_amount(_: Amount) -> Result<Amount> {
+        panic!("should not estimate fee for amount when swapping the maximum")
+    }
+
+    async fn get_dummy_address() -> Result<bitcoin::Address> {
+        Ok("1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6".parse()?)
+    }
+}
@@ -0,0 +1,47 @@
+//! Publishing the cancel transaction of a swap.
+
+use crate::api::Context;
+use crate::bitcoin::Txid;
+use crate::cli;
+use crate::protocol::bob::BobState;
+use anyhow::Result;
+use std::sync::Arc;
+use url::Url;
+use uuid::Uuid;
+
+pub struct Request {
+    pub swap_id: Uuid,
+    /// Publish the transaction even if the swap is not in a state that
+    /// expects it, the timelock still has to be expired.
+    pub force: bool,
+    pub bitcoin_electrum_rpc_urls: Vec<Url>,
+    pub bitcoin_target_block: usize,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub txid: Txid,
+    pub state: BobState,
+}
+
+impl Request {
+    /// Fails with [`cli::cancel::Error`] if the cancel timelock has not
+    /// expired yet.
+    pub async fn call(self, context: &Context) -> Result<Response> {
+        let db = context.open_database()?;
+        let seed = context.read_seed()?;
+
+        let bitcoin_wallet = context
+            .init_bitcoin_wallet(
+                &seed,
+                self.bitcoin_electrum_rpc_urls,
+                self.bitcoin_target_block,
+            )
+            .await?;
+
+        let (txid, state) =
+            cli::cancel(self.swap_id, Arc::new(bitcoin_wallet), db, self.force).await??;
+
+        Ok(Response { txid, state })
+    }
+}
@@ -0,0 +1,60 @@
+//! The swaps in the database and the states they went through.
+
+use crate::api::Context;
+use crate::database::StateTransition;
+use crate::protocol::bob::BobState;
+use anyhow::Result;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+pub struct Request {
+    /// Only the transitions of this swap instead of the list of all swaps.
+    pub swap_id: Option<Uuid>,
+}
+
+#[derive(Debug)]
+pub enum Response {
+    /// The latest state of every swap.
+    Swaps(Vec<(Uuid, BobState)>),
+    /// The states of a single swap, oldest first.
+    Transitions {
+        transitions: Vec<StateTransition>,
+        /// Swaps started before the history was recorded only have their
+        /// latest state, stamped with the time of the request.
+        recorded: bool,
+    },
+}
+
+impl Request {
+    pub async fn call(self, context: &Context) -> Result<Response> {
+        let db = context.open_database()?;
+
+        let swap_id = match self.swap_id {
+            None => {
+                let swaps = db
+                    .all_bob()?
+                    .into_iter()
+                    .map(|(swap_id, state)| (swap_id, BobState::from(state)))
+                    .collect();
+
+                return Ok(Response::Swaps(swaps));
+            }
+            Some(swap_id) => swap_id,
+        };
+
+        let mut transitions = db.get_history(swap_id)?;
+        let recorded = !transitions.is_empty();
+
+        if !recorded {
+            transitions.push(StateTransition::new(
+                SystemTime::now(),
+                db.get_state(swap_id)?,
+            ));
+        }
+
+        Ok(Response::Transitions {
+            transitions,
+            recorded,
+        })
+    }
+}
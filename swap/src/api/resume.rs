@@ -0,0 +1,106 @@
+//! Continuing a swap that was suspended or interrupted.
+
+use crate::api::{run_swap, Context};
+use crate::bitcoin::CoinControl;
+use crate::cli;
+use crate::network::swarm;
+use crate::protocol::bob::{BobState, Swap};
+use crate::shutdown::Shutdown;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+use uuid::Uuid;
+
+pub struct Request {
+    pub swap_id: Uuid,
+    pub bitcoin_electrum_rpc_urls: Vec<Url>,
+    pub bitcoin_target_block: usize,
+    pub monero_daemon_addresses: Vec<String>,
+    /// Merged with the coin control of the config file.
+    pub coin_control: CoinControl,
+    pub tor_socks5_port: u16,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    /// The final state of the swap, `None` if it was suspended on shutdown.
+    pub state: Option<BobState>,
+}
+
+impl Request {
+    /// Reconnects to the seller and runs the swap from its latest state until
+    /// it completes or a shutdown is requested.
+    pub async fn call(self, context: &Context, shutdown: Shutdown) -> Result<Response> {
+        let Request {
+            swap_id,
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+            monero_daemon_addresses,
+            coin_control,
+            tor_socks5_port,
+        } = self;
+        let env_config = context.env_config;
+
+        let db = context.open_database()?;
+        let seed = context.read_seed()?;
+        let identity = context.libp2p_identity(&seed)?;
+
+        let coin_control = cli::config::Config::read(&context.data_dir)?
+            .coin_control
+            .merge(coin_control);
+        let bitcoin_wallet = context
+            .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+            .await?
+            .with_coin_control(coin_control);
+        let emergency_kit_path = cli::emergency_kit::default_path(&context.data_dir, swap_id);
+        let (monero_wallet, _process) = context.init_monero_wallet(monero_daemon_addresses).await?;
+        let bitcoin_wallet = Arc::new(bitcoin_wallet);
+
+        let seller_peer_id = db.get_peer_id(swap_id)?;
+        let seller_addresses = db.get_addresses(seller_peer_id)?;
+
+        let behaviour = cli::Behaviour::new(seller_peer_id, env_config, bitcoin_wallet.clone());
+        let mut swarm = swarm::cli(identity, tor_socks5_port, behaviour).await?;
+        let our_peer_id = swarm.local_peer_id();
+        tracing::debug!(peer_id = %our_peer_id, "Network layer initialized");
+
+        for seller_address in seller_addresses {
+            swarm
+                .behaviour_mut()
+                .add_address(seller_peer_id, seller_address);
+        }
+
+        let (event_loop, event_loop_handle) =
+            cli::EventLoop::new(swap_id, swarm, seller_peer_id, env_config)?;
+        let event_loop = tokio::spawn(event_loop.run());
+
+        tokio::spawn(cli::auto_refund::refund_expired_swaps(
+            bitcoin_wallet.clone(),
+            db.clone(),
+            Some(swap_id),
+            env_config.bitcoin_avg_block_time,
+        ));
+        tokio::spawn(cli::emergency_kit::write_once_btc_locked(
+            db.clone(),
+            swap_id,
+            emergency_kit_path,
+            Duration::from_secs(5),
+        ));
+
+        let monero_receive_address = db.get_monero_address(swap_id)?;
+        let swap = Swap::from_db(
+            db,
+            swap_id,
+            bitcoin_wallet,
+            monero_wallet.clone(),
+            env_config,
+            event_loop_handle,
+            monero_receive_address,
+        )?;
+
+        let state = run_swap(swap, event_loop, monero_wallet, shutdown).await?;
+
+        Ok(Response { state })
+    }
+}
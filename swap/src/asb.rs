@@ -1,15 +1,25 @@
+pub mod accounting;
 pub mod command;
 pub mod config;
 mod event_loop;
+pub mod metrics;
 mod network;
+pub mod notifications;
 mod rate;
+mod rate_limit;
 mod recovery;
+pub mod reputation;
+pub mod rpc;
+pub mod spread;
 pub mod tracing;
+pub mod withdraw;
 
-pub use event_loop::{EventLoop, EventLoopHandle, FixedRate, KrakenRate, LatestRate};
+pub use event_loop::{
+    EventLoop, EventLoopHandle, FixedRate, KrakenRate, LatestRate, PeerBans, QuoteSwitch,
+};
 pub use network::behaviour::{Behaviour, OutEvent};
 pub use network::transport;
-pub use rate::Rate;
+pub use rate::{PriceGuard, Rate, RateUnavailable};
 pub use recovery::cancel::cancel;
 pub use recovery::punish::punish;
 pub use recovery::redeem::{redeem, Finality};
@@ -0,0 +1,357 @@
+//! Bookkeeping of the swaps of the ASB.
+//!
+//! The amounts and fees of a swap, the market price when the swap was set up
+//! and the fee of the Monero lock transaction are recorded in the database
+//! while the swap runs. Reports list the finished swaps of a period one by one
+//! or summed up per day.
+
+use crate::database::{Alice, AliceEndState, Database};
+use crate::protocol::alice::State3;
+use crate::{bitcoin, monero};
+use ::bitcoin::{Denomination, SignedAmount};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+/// What is recorded about a swap while it runs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct SwapAccounting {
+    /// The price of 1 XMR before the spread when the swap was set up.
+    #[serde(default, with = "::bitcoin::util::amount::serde::as_sat::opt")]
+    pub market_price: Option<bitcoin::Amount>,
+    #[serde(default)]
+    pub xmr_lock_fee: Option<monero::Amount>,
+    #[serde(default)]
+    pub amounts: Option<SwapAmounts>,
+}
+
+/// The amounts and fees a swap was set up with.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SwapAmounts {
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub btc: bitcoin::Amount,
+    pub xmr: monero::Amount,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub redeem_fee: bitcoin::Amount,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub punish_fees: bitcoin::Amount,
+}
+
+impl From<&State3> for SwapAmounts {
+    fn from(state3: &State3) -> Self {
+        Self {
+            btc: state3.tx_lock.lock_amount(),
+            xmr: state3.xmr,
+            redeem_fee: state3.redeem_fee(),
+            punish_fees: state3.punish_fees(),
+        }
+    }
+}
+
+/// The accounting data of a finished swap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapReport {
+    pub swap_id: Uuid,
+    /// The day the swap finished, in UTC.
+    pub date: Date,
+    pub outcome: AliceEndState,
+    /// The Bitcoin we received, before fees.
+    pub btc: bitcoin::Amount,
+    /// The Monero we sold, before fees.
+    pub xmr: monero::Amount,
+    pub market_price: Option<bitcoin::Amount>,
+    /// What we received above the market value of the Monero, not known if
+    /// the market price was not recorded.
+    pub realized_spread: Option<SignedAmount>,
+    pub btc_fee: bitcoin::Amount,
+    pub xmr_fee: monero::Amount,
+}
+
+impl SwapReport {
+    pub const HEADER: [&'static str; 9] = [
+        "swap_id",
+        "date",
+        "outcome",
+        "btc",
+        "xmr",
+        "market_price_btc",
+        "realized_spread_btc",
+        "btc_fee",
+        "xmr_fee",
+    ];
+
+    fn new(
+        swap_id: Uuid,
+        date: Date,
+        outcome: AliceEndState,
+        amounts: SwapAmounts,
+        accounting: SwapAccounting,
+    ) -> Self {
+        let (btc, xmr, btc_fee) = match outcome {
+            AliceEndState::BtcRedeemed => (amounts.btc, amounts.xmr, amounts.redeem_fee),
+            // The Monero stays locked, without Bob's key nobody can spend it.
+            AliceEndState::BtcPunished => (amounts.btc, amounts.xmr, amounts.punish_fees),
+            AliceEndState::XmrRefunded
+            | AliceEndState::SafelyAborted
+            | AliceEndState::BtcEarlyRefunded => (
+                bitcoin::Amount::ZERO,
+                monero::Amount::ZERO,
+                bitcoin::Amount::ZERO,
+            ),
+        };
+        let realized_spread = match accounting.market_price {
+            Some(market_price) if xmr != monero::Amount::ZERO => {
+                realized_spread(btc, xmr, market_price)
+            }
+            _ => None,
+        };
+
+        Self {
+            swap_id,
+            date,
+            outcome,
+            btc,
+            xmr,
+            market_price: accounting.market_price,
+            realized_spread,
+            btc_fee,
+            xmr_fee: accounting.xmr_lock_fee.unwrap_or(monero::Amount::ZERO),
+        }
+    }
+
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.swap_id.to_string(),
+            self.date.to_string(),
+            self.outcome.to_string(),
+            format_btc(self.btc),
+            format_xmr(self.xmr),
+            self.market_price.map(format_btc).unwrap_or_default(),
+            self.realized_spread
+                .map(|spread| spread.to_string_in(Denomination::Bitcoin))
+                .unwrap_or_default(),
+            format_btc(self.btc_fee),
+            format_xmr(self.xmr_fee),
+        ]
+    }
+}
+
+/// The swaps that finished on one day, summed up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DailyReport {
+    pub date: Date,
+    pub swaps: usize,
+    pub btc: bitcoin::Amount,
+    pub xmr: monero::Amount,
+    /// The realized spread of the swaps whose market price was recorded.
+    pub realized_spread: SignedAmount,
+    pub btc_fees: bitcoin::Amount,
+    pub xmr_fees: monero::Amount,
+}
+
+impl DailyReport {
+    pub const HEADER: [&'static str; 7] = [
+        "date",
+        "swaps",
+        "btc",
+        "xmr",
+        "realized_spread_btc",
+        "btc_fees",
+        "xmr_fees",
+    ];
+
+    fn empty(date: Date) -> Self {
+        Self {
+            date,
+            swaps: 0,
+            btc: bitcoin::Amount::ZERO,
+            xmr: monero::Amount::ZERO,
+            realized_spread: SignedAmount::ZERO,
+            btc_fees: bitcoin::Amount::ZERO,
+            xmr_fees: monero::Amount::ZERO,
+        }
+    }
+
+    fn add(&mut self, swap: &SwapReport) {
+        self.swaps += 1;
+        self.btc += swap.btc;
+        self.xmr = self.xmr + swap.xmr;
+        self.realized_spread += swap.realized_spread.unwrap_or(SignedAmount::ZERO);
+        self.btc_fees += swap.btc_fee;
+        self.xmr_fees = self.xmr_fees + swap.xmr_fee;
+    }
+
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.date.to_string(),
+            self.swaps.to_string(),
+            format_btc(self.btc),
+            format_xmr(self.xmr),
+            self.realized_spread.to_string_in(Denomination::Bitcoin),
+            format_btc(self.btc_fees),
+            format_xmr(self.xmr_fees),
+        ]
+    }
+}
+
+/// The swaps that finished between `from` and `to`, both inclusive, in the
+/// order they finished.
+///
+/// Swaps that finished before their history was recorded are not included.
+pub fn swap_reports(
+    db: &Database,
+    from: Option<Date>,
+    to: Option<Date>,
+) -> Result<Vec<SwapReport>> {
+    let mut reports = Vec::new();
+
+    for (swap_id, state) in db.all_alice()? {
+        let outcome = match state {
+            Alice::Done(outcome) => outcome,
+            _ => continue,
+        };
+
+        let accounting = db.get_accounting(swap_id)?;
+        let (finished_at, amounts) = match (db.get_history(swap_id)?.last(), accounting.amounts) {
+            (Some(last), Some(amounts)) => (last.timestamp, amounts),
+            _ => continue,
+        };
+
+        let date = OffsetDateTime::from(finished_at).date();
+        if from.map_or(false, |from| date < from) || to.map_or(false, |to| date > to) {
+            continue;
+        }
+
+        reports.push((
+            finished_at,
+            SwapReport::new(swap_id, date, outcome, amounts, accounting),
+        ));
+    }
+
+    reports.sort_by_key(|(finished_at, _)| *finished_at);
+
+    Ok(reports.into_iter().map(|(_, report)| report).collect())
+}
+
+/// Sums up the swap reports per day, the reports have to be ordered by date.
+pub fn daily_reports(swaps: &[SwapReport]) -> Vec<DailyReport> {
+    let mut days: Vec<DailyReport> = Vec::new();
+
+    for swap in swaps {
+        match days.last_mut() {
+            Some(day) if day.date == swap.date => day.add(swap),
+            _ => {
+                let mut day = DailyReport::empty(swap.date);
+                day.add(swap);
+                days.push(day);
+            }
+        }
+    }
+
+    days
+}
+
+/// Formats a report as CSV. None of the fields contain a comma, so no field
+/// needs to be quoted.
+pub fn to_csv(header: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+    let mut csv = header.join(",");
+    csv.push('\n');
+
+    for row in rows {
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// The Bitcoin received above the market value of the Monero.
+fn realized_spread(
+    btc: bitcoin::Amount,
+    xmr: monero::Amount,
+    market_price: bitcoin::Amount,
+) -> Option<SignedAmount> {
+    let market_value = xmr.bitcoin_for_price(market_price)?;
+
+    Some(btc.to_signed().ok()? - market_value.to_signed().ok()?)
+}
+
+fn format_btc(amount: bitcoin::Amount) -> String {
+    amount.to_string_in(Denomination::Bitcoin)
+}
+
+fn format_xmr(amount: monero::Amount) -> String {
+    let mut decimal = Decimal::from(amount.as_piconero());
+    decimal
+        .set_scale(12)
+        .expect("12 is smaller than max precision of 28");
+
+    decimal.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realized_spread_is_the_difference_to_the_market_value() {
+        let spread = realized_spread(
+            bitcoin::Amount::from_sat(1_020_000),
+            monero::Amount::ONE_XMR,
+            bitcoin::Amount::from_sat(1_000_000),
+        );
+
+        assert_eq!(spread, Some(SignedAmount::from_sat(20_000)));
+    }
+
+    #[test]
+    fn swaps_are_summed_up_per_day() {
+        let first_day = Date::try_from_ymd(2021, 7, 1).unwrap();
+        let second_day = Date::try_from_ymd(2021, 7, 2).unwrap();
+        let swaps = vec![
+            report(first_day, Some(SignedAmount::from_sat(100))),
+            report(first_day, None),
+            report(second_day, Some(SignedAmount::from_sat(-50))),
+        ];
+
+        let days = daily_reports(&swaps);
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].swaps, 2);
+        assert_eq!(days[0].btc, bitcoin::Amount::from_sat(2_000_000));
+        assert_eq!(days[0].realized_spread, SignedAmount::from_sat(100));
+        assert_eq!(days[1].realized_spread, SignedAmount::from_sat(-50));
+    }
+
+    #[test]
+    fn csv_has_a_row_per_report() {
+        let date = Date::try_from_ymd(2021, 7, 1).unwrap();
+        let swap = report(date, None);
+
+        let csv = to_csv(&SwapReport::HEADER, vec![swap.row()]);
+
+        assert_eq!(
+            csv,
+            format!(
+                "swap_id,date,outcome,btc,xmr,market_price_btc,realized_spread_btc,btc_fee,xmr_fee\n{},2021-07-01,BtcRedeemed,0.01000000,1.000000000000,,,0.00001000,0.000030000000\n",
+                swap.swap_id
+            )
+        );
+    }
+
+    fn report(date: Date, realized_spread: Option<SignedAmount>) -> SwapReport {
+        SwapReport {
+            swap_id: Uuid::new_v4(),
+            date,
+            outcome: AliceEndState::BtcRedeemed,
+            btc: bitcoin::Amount::from_sat(1_000_000),
+            xmr: monero::Amount::ONE_XMR,
+            market_price: None,
+            realized_spread,
+            btc_fee: bitcoin::Amount::from_sat(1_000),
+            xmr_fee: monero::Amount::from_piconero(30_000_000),
+        }
+    }
+}
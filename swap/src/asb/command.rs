@@ -1,13 +1,15 @@
 use crate::asb::config::GetDefaults;
 use crate::bitcoin::Amount;
-use crate::env;
 use crate::env::GetConfig;
+use crate::{env, monero};
 use anyhow::{bail, Result};
 use bitcoin::Address;
 use serde::Serialize;
 use std::ffi::OsString;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
+use time::Date;
 use uuid::Uuid;
 
 pub fn parse_args<I, T>(raw_args: I) -> Result<Arguments>
@@ -38,14 +40,32 @@ where
             env_config: env_config(is_testnet),
             cmd: Command::History,
         },
-        RawCommand::WithdrawBtc { amount, address } => Arguments {
+        RawCommand::WithdrawBtc {
+            amount,
+            all: _,
+            address,
+        } => Arguments {
             testnet: is_testnet,
             json: is_json,
             config_path: config_path(config, is_testnet)?,
             env_config: env_config(is_testnet),
             cmd: Command::WithdrawBtc {
                 amount,
-                address: bitcoin_address(address, is_testnet)?,
+                address: bitcoin_address(address, env_config(is_testnet).bitcoin_network)?,
+            },
+        },
+        RawCommand::WithdrawXmr {
+            amount,
+            all: _,
+            address,
+        } => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::WithdrawXmr {
+                amount,
+                address: monero_address(address, env_config(is_testnet).monero_network)?,
             },
         },
         RawCommand::Balance => Arguments {
@@ -55,6 +75,58 @@ where
             env_config: env_config(is_testnet),
             cmd: Command::Balance,
         },
+        RawCommand::Receipt { swap_id, output } => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::Receipt { swap_id, output },
+        },
+        RawCommand::Export { swap_id, output } => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::Export { swap_id, output },
+        },
+        RawCommand::Import { input } => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::Import { input },
+        },
+        RawCommand::Report {
+            from,
+            to,
+            csv,
+            daily,
+        } => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::Report {
+                from,
+                to,
+                csv,
+                daily,
+            },
+        },
+        RawCommand::Identity(Identity::PeerId) => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::PeerId,
+        },
+        RawCommand::Identity(Identity::Rotate) => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::RotateIdentity,
+        },
         RawCommand::ManualRecovery(ManualRecovery::Redeem {
             redeem_params: RecoverCommandParams { swap_id, force },
             do_not_await_finality,
@@ -103,24 +175,42 @@ where
             env_config: env_config(is_testnet),
             cmd: Command::SafelyAbort { swap_id },
         },
+        RawCommand::Config(ConfigCommand::Init { force }) => Arguments {
+            testnet: is_testnet,
+            json: is_json,
+            config_path: config_path(config, is_testnet)?,
+            env_config: env_config(is_testnet),
+            cmd: Command::InitConfig { force },
+        },
     };
 
     Ok(arguments)
 }
 
-fn bitcoin_address(address: Address, is_testnet: bool) -> Result<Address> {
-    let network = if is_testnet {
-        bitcoin::Network::Testnet
-    } else {
-        bitcoin::Network::Bitcoin
-    };
-
+fn bitcoin_address(address: Address, network: bitcoin::Network) -> Result<Address> {
     if address.network != network {
         bail!(BitcoinAddressNetworkMismatch {
             expected: network,
             actual: address.network
         });
     }
+    if crate::bitcoin::is_burn_address(&address) {
+        bail!(crate::bitcoin::BurnAddress(address))
+    }
+
+    Ok(address)
+}
+
+fn monero_address(address: monero::Address, network: monero::Network) -> Result<monero::Address> {
+    if address.network != network {
+        bail!(MoneroAddressNetworkMismatch {
+            expected: network,
+            actual: address.network
+        });
+    }
+    if monero::is_burn_address(&address) {
+        bail!(monero::BurnAddress(address))
+    }
 
     Ok(address)
 }
@@ -137,6 +227,10 @@ fn config_path(config: Option<PathBuf>, is_testnet: bool) -> Result<PathBuf> {
     Ok(config_path)
 }
 
+fn parse_date(date: &str) -> Result<Date, time::ParseError> {
+    Date::parse(date, "%F")
+}
+
 fn env_config(is_testnet: bool) -> env::Config {
     if is_testnet {
         env::Testnet::get_config()
@@ -154,6 +248,13 @@ pub struct BitcoinAddressNetworkMismatch {
     actual: bitcoin::Network,
 }
 
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Invalid Monero address provided, expected address on network {expected:?}  but address provided is on {actual:?}")]
+pub struct MoneroAddressNetworkMismatch {
+    expected: monero::Network,
+    actual: monero::Network,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Arguments {
     pub testnet: bool,
@@ -173,7 +274,30 @@ pub enum Command {
         amount: Option<Amount>,
         address: Address,
     },
+    WithdrawXmr {
+        amount: Option<monero::Amount>,
+        address: monero::Address,
+    },
     Balance,
+    Receipt {
+        swap_id: Uuid,
+        output: Option<PathBuf>,
+    },
+    Export {
+        swap_id: Uuid,
+        output: PathBuf,
+    },
+    Import {
+        input: PathBuf,
+    },
+    Report {
+        from: Option<Date>,
+        to: Option<Date>,
+        csv: bool,
+        daily: bool,
+    },
+    PeerId,
+    RotateIdentity,
     Redeem {
         swap_id: Uuid,
         force: bool,
@@ -194,6 +318,9 @@ pub enum Command {
     SafelyAbort {
         swap_id: Uuid,
     },
+    InitConfig {
+        force: bool,
+    },
 }
 
 #[derive(structopt::StructOpt, Debug)]
@@ -242,18 +369,124 @@ pub enum RawCommand {
     WithdrawBtc {
         #[structopt(
             long = "amount",
-            help = "Optionally specify the amount of Bitcoin to be withdrawn. If not specified the wallet will be drained."
+            help = "Optionally specify the amount of Bitcoin to be withdrawn, e.g. 0.5 or \"50000000 sat\". If not specified the wallet will be drained.",
+            parse(try_from_str = crate::bitcoin::parse_amount)
         )]
         amount: Option<Amount>,
+        #[structopt(
+            long = "all",
+            conflicts_with = "amount",
+            help = "Drain the wallet, this is the default if no amount is specified."
+        )]
+        all: bool,
         #[structopt(long = "address", help = "The address to receive the Bitcoin.")]
         address: Address,
     },
+    #[structopt(
+        about = "Allows withdrawing XMR from the Monero wallet. The Monero still needed by swaps that did not lock it yet cannot be withdrawn. Requires the monero-wallet-rpc to be running."
+    )]
+    WithdrawXmr {
+        #[structopt(
+            long = "amount",
+            help = "Optionally specify the amount of Monero to be withdrawn, e.g. 1.5 or \"1.5 XMR\". If not specified everything that is not reserved for swaps will be withdrawn.",
+            parse(try_from_str = monero::Amount::from_str)
+        )]
+        amount: Option<monero::Amount>,
+        #[structopt(
+            long = "all",
+            conflicts_with = "amount",
+            help = "Withdraw everything that is not reserved for swaps, this is the default if no amount is specified."
+        )]
+        all: bool,
+        #[structopt(long = "address", help = "The address to receive the Monero.")]
+        address: monero::Address,
+    },
     #[structopt(
         about = "Prints the Bitcoin and Monero balance. Requires the monero-wallet-rpc to be running."
     )]
     Balance,
+    #[structopt(
+        about = "Prints the receipt of a completed swap signed by both parties as JSON. Receipts exist once the taker asked for one."
+    )]
+    Receipt {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+        #[structopt(long = "output", help = "Write the receipt to this file instead.")]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        about = "Exports a swap into a file encrypted with a key derived from the seed, so it can be resumed by an ASB with the same seed on another machine."
+    )]
+    Export {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+        #[structopt(long = "output", help = "The file to write the export to.")]
+        output: PathBuf,
+    },
+    #[structopt(
+        about = "Imports a swap exported with the export subcommand, it is resumed on the next start. Refuses to overwrite a swap that already exists."
+    )]
+    Import {
+        #[structopt(long = "input", help = "The swap export file to import.")]
+        input: PathBuf,
+    },
+    #[structopt(
+        about = "Prints the amounts, realized spread and fees of the finished swaps for bookkeeping. Days are in UTC."
+    )]
+    Report {
+        #[structopt(
+            long = "from",
+            help = "Only include swaps that finished on or after this day, formatted as YYYY-MM-DD.",
+            parse(try_from_str = parse_date)
+        )]
+        from: Option<Date>,
+        #[structopt(
+            long = "to",
+            help = "Only include swaps that finished on or before this day, formatted as YYYY-MM-DD.",
+            parse(try_from_str = parse_date)
+        )]
+        to: Option<Date>,
+        #[structopt(long = "csv", help = "Print the report as CSV instead of a table.")]
+        csv: bool,
+        #[structopt(
+            long = "daily",
+            help = "Sum up the swaps per day instead of listing them one by one."
+        )]
+        daily: bool,
+    },
+    #[structopt(about = "Contains sub-commands for managing the network identity of the ASB.")]
+    Identity(Identity),
     #[structopt(about = "Contains sub-commands for recovering a swap manually.")]
     ManualRecovery(ManualRecovery),
+    #[structopt(about = "Contains sub-commands for managing the config file.")]
+    Config(ConfigCommand),
+}
+
+#[derive(structopt::StructOpt, Debug)]
+pub enum ConfigCommand {
+    #[structopt(
+        about = "Asks for the settings and writes a commented config file. This happens on the first start as well."
+    )]
+    Init {
+        #[structopt(long, help = "Overwrite an existing config file.")]
+        force: bool,
+    },
+}
+
+#[derive(structopt::StructOpt, Debug)]
+pub enum Identity {
+    #[structopt(about = "Prints the peer id under which the ASB is reachable.")]
+    PeerId,
+    #[structopt(
+        about = "Replaces the network identity with a random one. Takers have to learn the new peer id, for example through a rendezvous point. Refuses to run while swaps are unfinished."
+    )]
+    Rotate,
 }
 
 #[derive(structopt::StructOpt, Debug)]
@@ -326,6 +559,8 @@ mod tests {
     const BINARY_NAME: &str = "asb";
     const BITCOIN_MAINNET_ADDRESS: &str = "1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY";
     const BITCOIN_TESTNET_ADDRESS: &str = "tb1qyccwk4yun26708qg5h6g6we8kxln232wclxf5a";
+    const MONERO_STAGENET_ADDRESS: &str = "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a";
+    const MONERO_MAINNET_ADDRESS: &str = "44Ato7HveWidJYUAVw5QffEcEtSH1DwzSP3FPPkHxNAS4LX9CqgucphTisH978FLHE34YNEx7FcbBfQLQUU8m3NUC4VqsRa";
     const SWAP_ID: &str = "ea030832-3be9-454f-bb98-5ea9a788406b";
 
     #[test]
@@ -366,6 +601,92 @@ mod tests {
         let args = parse_args(raw_ars).unwrap();
         assert_eq!(expected_args, args);
 
+        let raw_ars = vec![BINARY_NAME, "receipt", "--swap-id", SWAP_ID];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::Receipt {
+                swap_id: Uuid::parse_str(SWAP_ID).unwrap(),
+                output: None,
+            },
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
+        let raw_ars = vec![
+            BINARY_NAME,
+            "export",
+            "--swap-id",
+            SWAP_ID,
+            "--output",
+            "swap.export",
+        ];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::Export {
+                swap_id: Uuid::parse_str(SWAP_ID).unwrap(),
+                output: PathBuf::from("swap.export"),
+            },
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
+        let raw_ars = vec![BINARY_NAME, "import", "--input", "swap.export"];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::Import {
+                input: PathBuf::from("swap.export"),
+            },
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
+        let raw_ars = vec![BINARY_NAME, "report", "--from", "2021-07-01", "--csv"];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::Report {
+                from: Some(Date::try_from_ymd(2021, 7, 1).unwrap()),
+                to: None,
+                csv: true,
+                daily: false,
+            },
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
+        let raw_ars = vec![BINARY_NAME, "identity", "peer-id"];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::PeerId,
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
+        let raw_ars = vec![BINARY_NAME, "identity", "rotate"];
+        let expected_args = Arguments {
+            testnet: false,
+            json: false,
+            config_path: default_mainnet_conf_path.clone(),
+            env_config: mainnet_env_config,
+            cmd: Command::RotateIdentity,
+        };
+        let args = parse_args(raw_ars).unwrap();
+        assert_eq!(expected_args, args);
+
         let raw_ars = vec![
             BINARY_NAME,
             "withdraw-btc",
@@ -607,6 +928,23 @@ mod tests {
         assert_eq!(expected_args, args);
     }
 
+    #[test]
+    fn given_config_init_then_config_path_and_force_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--config",
+            "/some/config/path",
+            "config",
+            "init",
+            "--force",
+        ];
+
+        let args = parse_args(raw_ars).unwrap();
+
+        assert_eq!(args.config_path, PathBuf::from("/some/config/path"));
+        assert_eq!(args.cmd, Command::InitConfig { force: true });
+    }
+
     #[test]
     fn given_user_provides_config_path_then_no_default_config_path_returned() {
         let cp = PathBuf::from_str("/some/config/path").unwrap();
@@ -620,8 +958,11 @@ mod tests {
 
     #[test]
     fn given_bitcoin_address_network_mismatch_then_error() {
-        let error =
-            bitcoin_address(Address::from_str(BITCOIN_MAINNET_ADDRESS).unwrap(), true).unwrap_err();
+        let error = bitcoin_address(
+            Address::from_str(BITCOIN_MAINNET_ADDRESS).unwrap(),
+            bitcoin::Network::Testnet,
+        )
+        .unwrap_err();
 
         assert_eq!(
             error
@@ -633,8 +974,11 @@ mod tests {
             }
         );
 
-        let error = bitcoin_address(Address::from_str(BITCOIN_TESTNET_ADDRESS).unwrap(), false)
-            .unwrap_err();
+        let error = bitcoin_address(
+            Address::from_str(BITCOIN_TESTNET_ADDRESS).unwrap(),
+            bitcoin::Network::Bitcoin,
+        )
+        .unwrap_err();
 
         assert_eq!(
             error
@@ -646,4 +990,107 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn given_burn_address_then_error() {
+        let burn_address = Address::from_str("1111111111111111111114oLvT2").unwrap();
+
+        let error = bitcoin_address(burn_address.clone(), bitcoin::Network::Bitcoin).unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<crate::bitcoin::BurnAddress>().unwrap(),
+            &crate::bitcoin::BurnAddress(burn_address)
+        );
+    }
+
+    #[test]
+    fn given_withdraw_xmr_then_amount_and_address_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--testnet",
+            "withdraw-xmr",
+            "--amount",
+            "1.5",
+            "--address",
+            MONERO_STAGENET_ADDRESS,
+        ];
+
+        let args = parse_args(raw_ars).unwrap();
+
+        assert_eq!(args.cmd, Command::WithdrawXmr {
+            amount: Some(monero::Amount::parse_monero("1.5").unwrap()),
+            address: monero::Address::from_str(MONERO_STAGENET_ADDRESS).unwrap(),
+        });
+    }
+
+    #[test]
+    fn given_withdraw_btc_then_amount_can_be_given_in_sat() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "withdraw-btc",
+            "--amount",
+            "1500000 sat",
+            "--address",
+            BITCOIN_MAINNET_ADDRESS,
+        ];
+
+        let args = parse_args(raw_ars).unwrap();
+
+        assert_eq!(args.cmd, Command::WithdrawBtc {
+            amount: Some(Amount::from_sat(1_500_000)),
+            address: Address::from_str(BITCOIN_MAINNET_ADDRESS).unwrap(),
+        });
+    }
+
+    #[test]
+    fn given_withdraw_with_all_then_no_amount_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "withdraw-xmr",
+            "--all",
+            "--address",
+            MONERO_MAINNET_ADDRESS,
+        ];
+
+        let args = parse_args(raw_ars).unwrap();
+
+        assert_eq!(args.cmd, Command::WithdrawXmr {
+            amount: None,
+            address: monero::Address::from_str(MONERO_MAINNET_ADDRESS).unwrap(),
+        });
+
+        let raw_ars = vec![
+            BINARY_NAME,
+            "withdraw-btc",
+            "--all",
+            "--amount",
+            "0.1 BTC",
+            "--address",
+            BITCOIN_MAINNET_ADDRESS,
+        ];
+
+        assert!(parse_args(raw_ars).is_err());
+    }
+
+    #[test]
+    fn given_monero_address_network_mismatch_then_error() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "withdraw-xmr",
+            "--address",
+            MONERO_STAGENET_ADDRESS,
+        ];
+
+        let error = parse_args(raw_ars).unwrap_err();
+
+        assert_eq!(
+            error
+                .downcast_ref::<MoneroAddressNetworkMismatch>()
+                .unwrap(),
+            &MoneroAddressNetworkMismatch {
+                expected: monero::Network::Mainnet,
+                actual: monero::Network::Stagenet
+            }
+        );
+    }
 }
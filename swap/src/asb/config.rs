@@ -1,3 +1,5 @@
+use crate::asb::notifications::EventKind;
+use crate::config::{commented, one_or_many, Invalid, Validator};
 use crate::env::{Mainnet, Testnet};
 use crate::fs::{ensure_directory_exists, system_config_dir, system_data_dir};
 use crate::tor::{DEFAULT_CONTROL_PORT, DEFAULT_SOCKS5_PORT};
@@ -6,11 +8,12 @@ use config::ConfigError;
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Input;
 use libp2p::core::Multiaddr;
-use rust_decimal::prelude::FromPrimitive;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use std::ffi::OsStr;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use url::Url;
@@ -80,9 +83,9 @@ fn default_asb_data_dir() -> Result<PathBuf> {
         .context("Could not generate default config file path")
 }
 
-const DEFAULT_MIN_BUY_AMOUNT: f64 = 0.002f64;
-const DEFAULT_MAX_BUY_AMOUNT: f64 = 0.02f64;
-const DEFAULT_SPREAD: f64 = 0.02f64;
+const DEFAULT_MIN_BUY_AMOUNT: &str = "0.002";
+const DEFAULT_MAX_BUY_AMOUNT: &str = "0.02";
+const DEFAULT_SPREAD: &str = "0.02";
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
@@ -93,6 +96,12 @@ pub struct Config {
     pub monero: Monero,
     pub tor: TorConf,
     pub maker: Maker,
+    #[serde(default)]
+    pub rpc: Option<Rpc>,
+    #[serde(default)]
+    pub metrics: Option<Metrics>,
+    #[serde(default)]
+    pub notifications: Option<Notifications>,
 }
 
 impl Config {
@@ -106,8 +115,167 @@ impl Config {
         config.merge(config::File::from(config_file))?;
         config.try_into()
     }
+
+    /// Checks the settings that cannot be checked when parsing the config.
+    pub fn validate(&self) -> Result<(), Invalid> {
+        let mut validator = Validator::default();
+
+        validator.check(
+            !self.network.listen.is_empty(),
+            "network.listen",
+            "at least one address to listen on is required",
+        );
+        let limits = &self.network.connection_limits;
+        for (key, limit) in &[
+            ("max_established_per_peer", limits.max_established_per_peer),
+            ("max_established_per_ip", limits.max_established_per_ip),
+            ("max_established_incoming", limits.max_established_incoming),
+            ("max_pending_incoming", limits.max_pending_incoming),
+        ] {
+            validator.check(
+                *limit > 0,
+                &format!("network.connection_limits.{}", key),
+                "must be at least 1",
+            );
+        }
+
+        validator.check(
+            !self.bitcoin.electrum_rpc_url.is_empty(),
+            "bitcoin.electrum_rpc_url",
+            "at least one Electrum server is required",
+        );
+        validator.electrum_rpc_urls("bitcoin.electrum_rpc_url", &self.bitcoin.electrum_rpc_url);
+        validator.target_block("bitcoin.target_block", self.bitcoin.target_block);
+        validator.check(
+            self.bitcoin.finality_confirmations != Some(0),
+            "bitcoin.finality_confirmations",
+            "must be at least 1",
+        );
+        validator.check(
+            matches!(
+                (self.bitcoin.network, self.monero.network),
+                (bitcoin::Network::Bitcoin, monero::Network::Mainnet)
+                    | (bitcoin::Network::Testnet, monero::Network::Stagenet)
+                    | (bitcoin::Network::Regtest, _)
+            ),
+            "monero.network",
+            format_args!(
+                "{:?} does not go together with the Bitcoin network {:?}",
+                self.monero.network, self.bitcoin.network
+            ),
+        );
+
+        validator.check(
+            matches!(self.monero.wallet_rpc_url.scheme(), "http" | "https"),
+            "monero.wallet_rpc_url",
+            "must be an http or https URL",
+        );
+        validator.daemon_addresses("monero.daemon_addresses", &self.monero.daemon_addresses);
+        validator.check(
+            self.monero.finality_confirmations != Some(0),
+            "monero.finality_confirmations",
+            "must be at least 1",
+        );
+        if let Some(cold_wallet) = &self.monero.cold_wallet {
+            validator.check(
+                cold_wallet.address.network == self.monero.network,
+                "monero.cold_wallet.address",
+                format_args!("is not an address on {:?}", self.monero.network),
+            );
+            validator.check(
+                !crate::monero::is_burn_address(&cold_wallet.address),
+                "monero.cold_wallet.address",
+                "nobody can spend from this address",
+            );
+            validator.check(
+                cold_wallet.sweep_interval_mins > 0,
+                "monero.cold_wallet.sweep_interval_mins",
+                "must be at least 1",
+            );
+        }
+
+        validator.port("tor.control_port", self.tor.control_port);
+        validator.port("tor.socks5_port", self.tor.socks5_port);
+
+        let maker = &self.maker;
+        validator.check(
+            maker.min_buy_btc <= maker.max_buy_btc,
+            "maker.min_buy_btc",
+            format_args!("exceeds max_buy_btc of {}", maker.max_buy_btc),
+        );
+        validator.check(
+            maker.max_buy_btc > bitcoin::Amount::ZERO,
+            "maker.max_buy_btc",
+            "must be larger than 0",
+        );
+        validator.fraction("maker.ask_spread", maker.ask_spread);
+        validator.fraction("maker.max_price_deviation", maker.max_price_deviation);
+        for tier in &maker.spread_tiers {
+            validator.fraction("maker.spread_tiers.spread", tier.spread);
+        }
+        if let Some(volatility) = &maker.volatility {
+            validator.check(
+                volatility.window_mins > 0,
+                "maker.volatility.window_mins",
+                "must be at least 1",
+            );
+            validator.fraction("maker.volatility.threshold", volatility.threshold);
+            validator.fraction("maker.volatility.extra_spread", volatility.extra_spread);
+        }
+
+        validator.finish()
+    }
 }
 
+/// Comments of the config file written by the initial setup.
+const COMMENTS: &[(&str, &str)] = &[
+    ("data.dir", "Where the database, the seed and the wallets are stored."),
+    (
+        "network.listen",
+        "The addresses to listen on for peer-to-peer communication with takers.",
+    ),
+    (
+        "network.rendezvous_point",
+        "Rendezvous points to register at, so takers can discover the ASB.",
+    ),
+    (
+        "network.connection_limits",
+        "Limits on the connections other peers can open to the ASB.",
+    ),
+    (
+        "bitcoin.electrum_rpc_url",
+        "One or more Electrum servers, the wallet fails over to the next one if the connection breaks.",
+    ),
+    (
+        "bitcoin.target_block",
+        "Transaction fees are estimated for a confirmation within this many blocks.",
+    ),
+    (
+        "monero.wallet_rpc_url",
+        "The monero-wallet-rpc holding the Monero to sell.",
+    ),
+    (
+        "tor.control_port",
+        "Without Tor running on these ports, no onion service is created.",
+    ),
+    (
+        "maker.min_buy_btc",
+        "The smallest amount of Bitcoin accepted per swap.",
+    ),
+    (
+        "maker.max_buy_btc",
+        "The largest amount of Bitcoin accepted per swap.",
+    ),
+    (
+        "maker.ask_spread",
+        "Added on top of the market price, 0.02 is 2%.",
+    ),
+    (
+        "maker.price_ticker_ws_url",
+        "The websocket of a price ticker following the Kraken protocol.",
+    ),
+];
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Data {
@@ -118,29 +286,89 @@ pub struct Data {
 #[serde(deny_unknown_fields)]
 pub struct Network {
     pub listen: Vec<Multiaddr>,
-    #[serde(default)]
-    pub rendezvous_point: Option<Multiaddr>,
+    #[serde(default, deserialize_with = "one_or_many::deserialize")]
+    pub rendezvous_point: Vec<Multiaddr>,
     #[serde(default)]
     pub external_addresses: Vec<Multiaddr>,
+    #[serde(default)]
+    pub connection_limits: ConnectionLimits,
+}
+
+/// Limits on the connections other peers can open to the ASB.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimits {
+    pub max_established_per_peer: u32,
+    /// Connections from a loopback address, e.g. from Tor, are not limited
+    /// per IP address.
+    pub max_established_per_ip: u32,
+    pub max_established_incoming: u32,
+    pub max_pending_incoming: u32,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_established_per_peer: 4,
+            max_established_per_ip: 16,
+            max_established_incoming: 1024,
+            max_pending_incoming: 128,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Bitcoin {
-    pub electrum_rpc_url: Url,
+    /// The wallet fails over to the next server if the connection breaks or
+    /// the server stops seeing new blocks.
+    #[serde(deserialize_with = "one_or_many::deserialize")]
+    pub electrum_rpc_url: Vec<Url>,
     pub target_block: usize,
     pub finality_confirmations: Option<u32>,
     #[serde(with = "crate::bitcoin::network")]
     pub network: bitcoin::Network,
+    /// Upper bound for the fee of a single transaction, defaults to 100_000
+    /// sats.
+    #[serde(default, with = "crate::bitcoin::as_btc::opt")]
+    pub max_tx_fee_btc: Option<bitcoin::Amount>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Monero {
     pub wallet_rpc_url: Url,
+    /// Monero nodes given as `<host>:<port>` that the wallet RPC is switched
+    /// between, whenever the selected one stops responding or falls behind.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub daemon_addresses: Vec<String>,
     pub finality_confirmations: Option<u64>,
     #[serde(with = "crate::monero::network")]
     pub network: monero::Network,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cold_wallet: Option<ColdWallet>,
+}
+
+/// Keeps the Monero wallet of the ASB small by periodically sweeping whatever
+/// exceeds `max_hot_balance` to a cold wallet, which is disabled if not
+/// configured.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColdWallet {
+    /// An address of the cold wallet, whose keys the ASB does not need.
+    #[serde_as(as = "DisplayFromStr")]
+    pub address: monero::Address,
+    /// The amount of XMR to keep in the hot wallet for locking. More is kept
+    /// while swaps in flight still need it.
+    #[serde(with = "crate::monero::as_xmr")]
+    pub max_hot_balance: crate::monero::Amount,
+    #[serde(default = "default_sweep_interval_mins")]
+    pub sweep_interval_mins: u64,
+}
+
+fn default_sweep_interval_mins() -> u64 {
+    60
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -153,12 +381,148 @@ pub struct TorConf {
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Maker {
-    #[serde(with = "::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "crate::bitcoin::as_btc")]
     pub min_buy_btc: bitcoin::Amount,
-    #[serde(with = "::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "crate::bitcoin::as_btc")]
     pub max_buy_btc: bitcoin::Amount,
     pub ask_spread: Decimal,
     pub price_ticker_ws_url: Url,
+    /// A second price ticker following the Kraken protocol, no quotes are
+    /// given while its price diverges from the price of `price_ticker_ws_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secondary_price_ticker_ws_url: Option<Url>,
+    /// The largest tolerated deviation between the price tickers, relative to
+    /// the price of the secondary ticker.
+    #[serde(default = "default_max_price_deviation")]
+    pub max_price_deviation: Decimal,
+    /// Whether takers have to prove that they can fund the swap before we
+    /// commit to it. Takers of older versions cannot provide a proof.
+    #[serde(default)]
+    pub require_funding_proof: bool,
+    /// Whether to attach a proof of our Monero reserves to quotes, which
+    /// allows takers to skip sellers that cannot cover their quotes.
+    #[serde(default)]
+    pub attach_reserve_proof: bool,
+    /// Whether to let takers refund their Bitcoin right away if we do not
+    /// have the Monero to lock, instead of waiting for the Monero until the
+    /// cancel timelock expires.
+    #[serde(default = "default_allow_early_refund")]
+    pub allow_early_refund: bool,
+    /// Spreads replacing `ask_spread` for swaps of at least a minimum amount.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spread_tiers: Vec<SpreadTier>,
+    /// Widens the spread while the market is volatile.
+    #[serde(default)]
+    pub volatility: Option<VolatilitySpread>,
+}
+
+fn default_max_price_deviation() -> Decimal {
+    Decimal::new(5, 2)
+}
+
+fn default_allow_early_refund() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpreadTier {
+    #[serde(with = "crate::bitcoin::as_btc")]
+    pub min_btc: bitcoin::Amount,
+    pub spread: Decimal,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VolatilitySpread {
+    /// The period of market prices to consider.
+    pub window_mins: u64,
+    /// The standard deviation of the market prices, relative to their mean,
+    /// above which the market counts as volatile.
+    pub threshold: Decimal,
+    /// Added to the spread while the market is volatile.
+    pub extra_spread: Decimal,
+}
+
+/// Settings of the local control API, which is disabled if not configured.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rpc {
+    pub listen: SocketAddr,
+    /// Whether to serve the dashboard on the same address.
+    #[serde(default)]
+    pub dashboard: bool,
+    /// Hosts other than the listen address and `localhost` under which the
+    /// API is reached, e.g. through a reverse proxy, given as `host:port`.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+}
+
+/// Settings of the Prometheus metrics endpoint, which is disabled if not
+/// configured.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    pub listen: SocketAddr,
+}
+
+/// Settings of notifications about swaps and the health of the ASB, which are
+/// disabled if not configured.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notifications {
+    /// The events to notify about, all events if not given.
+    #[serde(default = "EventKind::all")]
+    pub events: Vec<EventKind>,
+    /// Notify once the Bitcoin balance drops below this amount.
+    #[serde(default, with = "crate::bitcoin::as_btc::opt")]
+    pub min_bitcoin_balance: Option<bitcoin::Amount>,
+    /// Notify once the Monero balance drops below this amount of XMR.
+    #[serde(default, with = "crate::monero::as_xmr::opt")]
+    pub min_monero_balance: Option<crate::monero::Amount>,
+    #[serde(default = "default_price_feed_down_after_mins")]
+    pub price_feed_down_after_mins: u64,
+    #[serde(default)]
+    pub webhook: Option<Webhook>,
+    #[serde(default)]
+    pub smtp: Option<Smtp>,
+    #[serde(default)]
+    pub telegram: Option<Telegram>,
+}
+
+fn default_price_feed_down_after_mins() -> u64 {
+    10
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: Url,
+}
+
+/// An SMTP server that accepts implicit TLS.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Smtp {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    465
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Telegram {
+    pub bot_token: String,
+    /// The id of the chat or `@channelusername`.
+    pub chat_id: String,
 }
 
 impl Default for TorConf {
@@ -186,12 +550,15 @@ pub fn read_config(config_path: PathBuf) -> Result<Result<Config, ConfigNotIniti
 
     let file = Config::read(&config_path)
         .with_context(|| format!("Failed to read config file at {}", config_path.display()))?;
+    file.validate()
+        .with_context(|| format!("Invalid config file at {}", config_path.display()))?;
 
     Ok(Ok(file))
 }
 
 pub fn initial_setup(config_path: PathBuf, config: Config) -> Result<()> {
-    let toml = toml::to_string(&config)?;
+    config.validate()?;
+    let toml = commented(&toml::to_string(&config)?, COMMENTS);
 
     ensure_directory_exists(config_path.as_path())?;
     fs::write(&config_path, toml)?;
@@ -270,24 +637,24 @@ pub fn query_user_for_initial_config(testnet: bool) -> Result<Config> {
 
     let min_buy = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter minimum Bitcoin amount you are willing to accept per swap or hit enter to use default.")
-        .default(DEFAULT_MIN_BUY_AMOUNT)
+        .default(DEFAULT_MIN_BUY_AMOUNT.to_owned())
         .interact_text()?;
-    let min_buy = bitcoin::Amount::from_btc(min_buy)?;
+    let min_buy = crate::bitcoin::parse_amount(&min_buy)?;
 
     let max_buy = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter maximum Bitcoin amount you are willing to accept per swap or hit enter to use default.")
-        .default(DEFAULT_MAX_BUY_AMOUNT)
+        .default(DEFAULT_MAX_BUY_AMOUNT.to_owned())
         .interact_text()?;
-    let max_buy = bitcoin::Amount::from_btc(max_buy)?;
+    let max_buy = crate::bitcoin::parse_amount(&max_buy)?;
 
     let ask_spread = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter spread (in percent; value between 0.x and 1.0) to be used on top of the market rate or hit enter to use default.")
-        .default(DEFAULT_SPREAD)
+        .default(DEFAULT_SPREAD.to_owned())
         .interact_text()?;
-    if !(0.0..=1.0).contains(&ask_spread) {
+    let ask_spread = Decimal::from_str(&ask_spread).context("Unable to parse spread")?;
+    if ask_spread.is_sign_negative() || ask_spread > Decimal::new(1, 0) {
         bail!(format!("Invalid spread {}. For the spread value floating point number in interval [0..1] are allowed.", ask_spread))
     }
-    let ask_spread = Decimal::from_f64(ask_spread).context("Unable to parse spread")?;
 
     let rendezvous_point = Input::<Multiaddr>::with_theme(&ColorfulTheme::default())
         .with_prompt("Do you want to advertise your ASB instance with a rendezvous node? Enter an empty string if not.")
@@ -301,22 +668,26 @@ pub fn query_user_for_initial_config(testnet: bool) -> Result<Config> {
         network: Network {
             listen: listen_addresses,
             rendezvous_point: if rendezvous_point.is_empty() {
-                None
+                vec![]
             } else {
-                Some(rendezvous_point)
+                vec![rendezvous_point]
             },
             external_addresses: vec![],
+            connection_limits: ConnectionLimits::default(),
         },
         bitcoin: Bitcoin {
-            electrum_rpc_url,
+            electrum_rpc_url: vec![electrum_rpc_url],
             target_block,
             finality_confirmations: None,
             network: bitcoin_network,
+            max_tx_fee_btc: None,
         },
         monero: Monero {
             wallet_rpc_url: monero_wallet_rpc_url,
+            daemon_addresses: vec![],
             finality_confirmations: None,
             network: monero_network,
+            cold_wallet: None,
         },
         tor: TorConf {
             control_port: tor_control_port,
@@ -327,7 +698,17 @@ pub fn query_user_for_initial_config(testnet: bool) -> Result<Config> {
             max_buy_btc: max_buy,
             ask_spread,
             price_ticker_ws_url: defaults.price_ticker_ws_url,
+            secondary_price_ticker_ws_url: None,
+            max_price_deviation: default_max_price_deviation(),
+            require_funding_proof: false,
+            attach_reserve_proof: false,
+            allow_early_refund: true,
+            spread_tiers: vec![],
+            volatility: None,
         },
+        rpc: None,
+        metrics: None,
+        notifications: None,
     })
 }
 
@@ -348,29 +729,43 @@ mod tests {
                 dir: Default::default(),
             },
             bitcoin: Bitcoin {
-                electrum_rpc_url: defaults.electrum_rpc_url,
+                electrum_rpc_url: vec![defaults.electrum_rpc_url],
                 target_block: defaults.bitcoin_confirmation_target,
                 finality_confirmations: None,
                 network: bitcoin::Network::Testnet,
+                max_tx_fee_btc: None,
             },
             network: Network {
                 listen: vec![defaults.listen_address_tcp, defaults.listen_address_ws],
-                rendezvous_point: None,
+                rendezvous_point: vec![],
                 external_addresses: vec![],
+                connection_limits: ConnectionLimits::default(),
             },
 
             monero: Monero {
                 wallet_rpc_url: defaults.monero_wallet_rpc_url,
+                daemon_addresses: vec![],
                 finality_confirmations: None,
                 network: monero::Network::Stagenet,
+                cold_wallet: None,
             },
             tor: Default::default(),
             maker: Maker {
-                min_buy_btc: bitcoin::Amount::from_btc(DEFAULT_MIN_BUY_AMOUNT).unwrap(),
-                max_buy_btc: bitcoin::Amount::from_btc(DEFAULT_MAX_BUY_AMOUNT).unwrap(),
-                ask_spread: Decimal::from_f64(DEFAULT_SPREAD).unwrap(),
+                min_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MIN_BUY_AMOUNT).unwrap(),
+                max_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MAX_BUY_AMOUNT).unwrap(),
+                ask_spread: Decimal::from_str(DEFAULT_SPREAD).unwrap(),
                 price_ticker_ws_url: defaults.price_ticker_ws_url,
+                secondary_price_ticker_ws_url: None,
+                max_price_deviation: default_max_price_deviation(),
+                require_funding_proof: false,
+                attach_reserve_proof: false,
+                allow_early_refund: true,
+                spread_tiers: vec![],
+                volatility: None,
             },
+            rpc: None,
+            metrics: None,
+            notifications: None,
         };
 
         initial_setup(config_path.clone(), expected.clone()).unwrap();
@@ -391,29 +786,43 @@ mod tests {
                 dir: Default::default(),
             },
             bitcoin: Bitcoin {
-                electrum_rpc_url: defaults.electrum_rpc_url,
+                electrum_rpc_url: vec![defaults.electrum_rpc_url],
                 target_block: defaults.bitcoin_confirmation_target,
                 finality_confirmations: None,
                 network: bitcoin::Network::Bitcoin,
+                max_tx_fee_btc: None,
             },
             network: Network {
                 listen: vec![defaults.listen_address_tcp, defaults.listen_address_ws],
-                rendezvous_point: None,
+                rendezvous_point: vec![],
                 external_addresses: vec![],
+                connection_limits: ConnectionLimits::default(),
             },
 
             monero: Monero {
                 wallet_rpc_url: defaults.monero_wallet_rpc_url,
+                daemon_addresses: vec![],
                 finality_confirmations: None,
                 network: monero::Network::Mainnet,
+                cold_wallet: None,
             },
             tor: Default::default(),
             maker: Maker {
-                min_buy_btc: bitcoin::Amount::from_btc(DEFAULT_MIN_BUY_AMOUNT).unwrap(),
-                max_buy_btc: bitcoin::Amount::from_btc(DEFAULT_MAX_BUY_AMOUNT).unwrap(),
-                ask_spread: Decimal::from_f64(DEFAULT_SPREAD).unwrap(),
+                min_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MIN_BUY_AMOUNT).unwrap(),
+                max_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MAX_BUY_AMOUNT).unwrap(),
+                ask_spread: Decimal::from_str(DEFAULT_SPREAD).unwrap(),
                 price_ticker_ws_url: defaults.price_ticker_ws_url,
+                secondary_price_ticker_ws_url: None,
+                max_price_deviation: default_max_price_deviation(),
+                require_funding_proof: false,
+                attach_reserve_proof: false,
+                allow_early_refund: true,
+                spread_tiers: vec![],
+                volatility: None,
             },
+            rpc: None,
+            metrics: None,
+            notifications: None,
         };
 
         initial_setup(config_path.clone(), expected.clone()).unwrap();
@@ -421,4 +830,319 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn initial_setup_writes_comments() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+
+        initial_setup(config_path.clone(), example_config()).unwrap();
+
+        let content = fs::read_to_string(config_path).unwrap();
+        assert!(
+            content.contains("# Added on top of the market price, 0.02 is 2%.\nask_spread = 0.02")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let mut config = example_config();
+        config.bitcoin.target_block = 0;
+        config.monero.network = monero::Network::Mainnet;
+        config.maker.min_buy_btc = bitcoin::Amount::from_btc(1.0).unwrap();
+        config.maker.ask_spread = Decimal::new(2, 0);
+
+        let problems = config.validate().unwrap_err().problems;
+
+        assert_eq!(problems, vec![
+            "bitcoin.target_block: must be between 1 and 1008 blocks",
+            "monero.network: Mainnet does not go together with the Bitcoin network Testnet",
+            "maker.min_buy_btc: exceeds max_buy_btc of 0.02000000 BTC",
+            "maker.ask_spread: 2 is not between 0 and 1, e.g. 0.02 for 2%",
+        ]);
+        assert!(initial_setup(tempdir().unwrap().path().join("config.toml"), config).is_err());
+    }
+
+    fn example_config() -> Config {
+        let defaults = Testnet::getConfigFileDefaults().unwrap();
+
+        Config {
+            data: Data {
+                dir: defaults.data_dir,
+            },
+            bitcoin: Bitcoin {
+                electrum_rpc_url: vec![defaults.electrum_rpc_url],
+                target_block: defaults.bitcoin_confirmation_target,
+                finality_confirmations: None,
+                network: bitcoin::Network::Testnet,
+                max_tx_fee_btc: None,
+            },
+            network: Network {
+                listen: vec![defaults.listen_address_tcp],
+                rendezvous_point: vec![],
+                external_addresses: vec![],
+                connection_limits: ConnectionLimits::default(),
+            },
+            monero: Monero {
+                wallet_rpc_url: defaults.monero_wallet_rpc_url,
+                daemon_addresses: vec![],
+                finality_confirmations: None,
+                network: monero::Network::Stagenet,
+                cold_wallet: None,
+            },
+            tor: Default::default(),
+            maker: Maker {
+                min_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MIN_BUY_AMOUNT).unwrap(),
+                max_buy_btc: crate::bitcoin::parse_amount(DEFAULT_MAX_BUY_AMOUNT).unwrap(),
+                ask_spread: Decimal::new(2, 2),
+                price_ticker_ws_url: defaults.price_ticker_ws_url,
+                secondary_price_ticker_ws_url: None,
+                max_price_deviation: default_max_price_deviation(),
+                require_funding_proof: false,
+                attach_reserve_proof: false,
+                allow_early_refund: true,
+                spread_tiers: vec![],
+                volatility: None,
+            },
+            rpc: None,
+            metrics: None,
+            notifications: None,
+        }
+    }
+
+    #[test]
+    fn can_read_single_rendezvous_point() {
+        let network = toml::from_str::<Network>(
+            r#"
+            listen = ["/ip4/0.0.0.0/tcp/9939"]
+            rendezvous_point = "/dnsaddr/rendezvous.coblox.tech/p2p/12D3KooWQUt9DkNZxEn2R5ymJzWj15MpG6mTW84kyd8vDaRZi46o"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(network.rendezvous_point, vec!["/dnsaddr/rendezvous.coblox.tech/p2p/12D3KooWQUt9DkNZxEn2R5ymJzWj15MpG6mTW84kyd8vDaRZi46o".parse::<Multiaddr>().unwrap()]);
+    }
+
+    #[test]
+    fn can_read_multiple_rendezvous_points() {
+        let network = toml::from_str::<Network>(
+            r#"
+            listen = ["/ip4/0.0.0.0/tcp/9939"]
+            rendezvous_point = [
+                "/dnsaddr/rendezvous.coblox.tech/p2p/12D3KooWQUt9DkNZxEn2R5ymJzWj15MpG6mTW84kyd8vDaRZi46o",
+                "/ip4/127.0.0.1/tcp/8888/p2p/12D3KooWQUt9DkNZxEn2R5ymJzWj15MpG6mTW84kyd8vDaRZi46o",
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(network.rendezvous_point.len(), 2);
+    }
+
+    #[test]
+    fn can_read_single_and_multiple_electrum_servers() {
+        let single = toml::from_str::<Bitcoin>(
+            r#"
+            electrum_rpc_url = "ssl://electrum.blockstream.info:50002"
+            target_block = 3
+            network = "Mainnet"
+            "#,
+        )
+        .unwrap();
+        let multiple = toml::from_str::<Bitcoin>(
+            r#"
+            electrum_rpc_url = ["ssl://electrum.blockstream.info:50002", "tcp://localhost:50001"]
+            target_block = 3
+            network = "Mainnet"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(single.electrum_rpc_url, vec![Url::parse(
+            "ssl://electrum.blockstream.info:50002"
+        )
+        .unwrap()]);
+        assert_eq!(multiple.electrum_rpc_url, vec![
+            Url::parse("ssl://electrum.blockstream.info:50002").unwrap(),
+            Url::parse("tcp://localhost:50001").unwrap()
+        ]);
+    }
+
+    #[test]
+    fn rendezvous_point_defaults_to_empty_list() {
+        let network = toml::from_str::<Network>(
+            r#"
+            listen = ["/ip4/0.0.0.0/tcp/9939"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(network.rendezvous_point.is_empty());
+    }
+
+    #[test]
+    fn can_read_rpc_listen_address() {
+        let rpc = toml::from_str::<Rpc>(
+            r#"
+            listen = "127.0.0.1:9944"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rpc.listen, "127.0.0.1:9944".parse::<SocketAddr>().unwrap());
+        assert!(!rpc.dashboard);
+        assert!(rpc.allowed_hosts.is_empty());
+
+        let rpc = toml::from_str::<Rpc>(
+            r#"
+            listen = "127.0.0.1:9944"
+            dashboard = true
+            "#,
+        )
+        .unwrap();
+
+        assert!(rpc.dashboard);
+    }
+
+    #[test]
+    fn notifications_default_to_all_events() {
+        let notifications = toml::from_str::<Notifications>(
+            r#"
+            min_bitcoin_balance = 0.01
+
+            [webhook]
+            url = "https://example.com/asb-events"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(notifications.events, EventKind::all());
+        assert_eq!(
+            notifications.min_bitcoin_balance,
+            Some(bitcoin::Amount::from_sat(1_000_000))
+        );
+        assert_eq!(notifications.min_monero_balance, None);
+        assert_eq!(notifications.price_feed_down_after_mins, 10);
+        assert!(notifications.smtp.is_none());
+    }
+
+    #[test]
+    fn amounts_can_be_given_with_unit() {
+        let notifications = toml::from_str::<Notifications>(
+            r#"
+            min_bitcoin_balance = "1000000 sat"
+            min_monero_balance = "1.5 XMR"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            notifications.min_bitcoin_balance,
+            Some(bitcoin::Amount::from_sat(1_000_000))
+        );
+        assert_eq!(
+            notifications.min_monero_balance,
+            Some(crate::monero::Amount::from_piconero(1_500_000_000_000))
+        );
+
+        let result = toml::from_str::<Notifications>(r#"min_monero_balance = "1,5""#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn can_read_cold_wallet() {
+        let monero = toml::from_str::<Monero>(
+            r#"
+            wallet_rpc_url = "http://127.0.0.1:38083/json_rpc"
+            network = "Stagenet"
+
+            [cold_wallet]
+            address = "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a"
+            max_hot_balance = 5.0
+            "#,
+        )
+        .unwrap();
+
+        let cold_wallet = monero.cold_wallet.unwrap();
+        assert_eq!(cold_wallet.address.network, monero::Network::Stagenet);
+        assert_eq!(
+            cold_wallet.max_hot_balance,
+            crate::monero::Amount::from_piconero(5_000_000_000_000)
+        );
+        assert_eq!(cold_wallet.sweep_interval_mins, 60);
+
+        let mut config = example_config();
+        config.monero.cold_wallet = Some(cold_wallet);
+        assert!(config.validate().is_ok());
+
+        config.monero.network = monero::Network::Mainnet;
+        config.bitcoin.network = bitcoin::Network::Bitcoin;
+        let problems = config.validate().unwrap_err().problems;
+        assert_eq!(problems, vec![
+            "monero.cold_wallet.address: is not an address on Mainnet"
+        ]);
+    }
+
+    #[test]
+    fn can_read_spread_strategy() {
+        let maker = toml::from_str::<Maker>(
+            r#"
+            min_buy_btc = 0.0001
+            max_buy_btc = 1.0
+            ask_spread = 0.02
+            price_ticker_ws_url = "wss://ws.kraken.com"
+
+            [[spread_tiers]]
+            min_btc = 0.5
+            spread = 0.015
+
+            [volatility]
+            window_mins = 60
+            threshold = 0.03
+            extra_spread = 0.02
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(maker.spread_tiers.len(), 1);
+        assert_eq!(
+            maker.spread_tiers[0].min_btc,
+            bitcoin::Amount::from_sat(50_000_000)
+        );
+        assert_eq!(maker.volatility.unwrap().window_mins, 60);
+    }
+
+    #[test]
+    fn secondary_price_ticker_is_optional() {
+        let maker = toml::from_str::<Maker>(
+            r#"
+            min_buy_btc = 0.0001
+            max_buy_btc = 1.0
+            ask_spread = 0.02
+            price_ticker_ws_url = "wss://ws.kraken.com"
+            "#,
+        )
+        .unwrap();
+
+        assert!(maker.secondary_price_ticker_ws_url.is_none());
+        assert_eq!(maker.max_price_deviation, Decimal::new(5, 2));
+    }
+
+    #[test]
+    fn connection_limits_can_be_partially_configured() {
+        let network = toml::from_str::<Network>(
+            r#"
+            listen = ["/ip4/0.0.0.0/tcp/9939"]
+
+            [connection_limits]
+            max_established_per_ip = 2
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(network.connection_limits, ConnectionLimits {
+            max_established_per_ip: 2,
+            ..ConnectionLimits::default()
+        });
+    }
 }
@@ -1,23 +1,36 @@
-use crate::asb::{Behaviour, OutEvent, Rate};
-use crate::database::Database;
-use crate::network::quote::BidQuote;
-use crate::network::swap_setup::alice::WalletSnapshot;
-use crate::network::transfer_proof;
+use crate::asb::accounting::SwapAmounts;
+use crate::asb::metrics::METRICS;
+use crate::asb::rate_limit::{ConnectionsPerIp, RequestLimits};
+use crate::asb::reputation::{Misbehavior, PeerStats};
+use crate::asb::spread::SpreadStrategy;
+use crate::asb::{withdraw, Behaviour, OutEvent, PriceGuard, Rate, RateUnavailable};
+use crate::database::{self, Alice, AliceEndState, Database};
+use crate::network::protocol_error::ProtocolError;
+use crate::network::quote::{BidQuote, ReserveProof, SignedBidQuote};
+use crate::network::swap_setup::alice::{self, SwapRequest, WalletSnapshot};
+use crate::network::{early_refund, receipt, transfer_proof};
 use crate::protocol::alice::{AliceState, State3, Swap};
+use crate::receipt::SignedReceipt;
 use crate::{bitcoin, env, kraken, monero};
-use anyhow::{Context, Result};
+use ::bitcoin::OutPoint;
+use anyhow::{anyhow, bail, Context, Result};
 use futures::future;
 use futures::future::{BoxFuture, FutureExt};
 use futures::stream::{FuturesUnordered, StreamExt};
+use libp2p::core::multiaddr::Protocol;
+use libp2p::core::ConnectedPoint;
 use libp2p::request_response::{RequestId, ResponseChannel};
 use libp2p::swarm::SwarmEvent;
-use libp2p::{PeerId, Swarm};
+use libp2p::{identity, PeerId, Swarm};
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fmt::Debug;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
 /// A future that resolves to a tuple of `PeerId`, `transfer_proof::Request` and
@@ -30,12 +43,32 @@ use uuid::Uuid;
 type OutgoingTransferProof =
     BoxFuture<'static, Result<(PeerId, transfer_proof::Request, bmrng::Responder<()>)>>;
 
+/// Like [`OutgoingTransferProof`] but for the signature that allows Bob to
+/// refund early.
+type OutgoingEarlyRefund =
+    BoxFuture<'static, Result<(PeerId, early_refund::Request, bmrng::Responder<()>)>>;
+
+/// How long a quote handed out to a taker is valid.
+const QUOTE_VALIDITY: Duration = Duration::from_secs(60);
+
+/// How often the reserve proof attached to quotes is renewed, well within
+/// [`crate::network::quote::RESERVE_PROOF_MAX_AGE`].
+const RESERVE_PROOF_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The window within which the requests of a peer are limited.
+const REQUEST_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const MAX_QUOTE_REQUESTS_PER_PEER: usize = 10;
+const MAX_QUOTE_REQUESTS_PER_IP: usize = 50;
+const MAX_SWAP_SETUPS_PER_PEER: usize = 3;
+const MAX_SWAP_SETUPS_PER_IP: usize = 10;
+
 #[allow(missing_debug_implementations)]
 pub struct EventLoop<LR>
 where
     LR: LatestRate + Send + 'static + Debug + Clone,
 {
     swarm: libp2p::Swarm<Behaviour<LR>>,
+    identity: identity::Keypair,
     env_config: env::Config,
     bitcoin_wallet: Arc<bitcoin::Wallet>,
     monero_wallet: Arc<monero::Wallet>,
@@ -43,11 +76,30 @@ where
     latest_rate: LR,
     min_buy: bitcoin::Amount,
     max_buy: bitcoin::Amount,
+    require_funding_proof: bool,
+    attach_reserve_proof: bool,
+    allow_early_refund: bool,
+    /// The reserve proof attached to quotes and when it was created.
+    reserve_proof: Option<(Instant, ReserveProof)>,
+    quotes: QuoteSwitch,
+    peer_bans: PeerBans,
+    lifted_bans: mpsc::UnboundedReceiver<PeerId>,
+
+    quote_limits: RequestLimits,
+    swap_setup_limits: RequestLimits,
+    /// The IP address of every connected peer, used to limit requests per IP.
+    peer_ips: HashMap<PeerId, IpAddr>,
+    connections_per_ip: ConnectionsPerIp,
+    /// The outputs spent by the funding proof of every pending swap setup, so
+    /// that the same Bitcoin cannot back several setups at once.
+    reserved_funding: HashMap<PeerId, Vec<OutPoint>>,
 
     swap_sender: mpsc::Sender<Swap>,
 
     /// Stores incoming [`EncryptedSignature`]s per swap.
     recv_encrypted_signature: HashMap<Uuid, bmrng::RequestSender<bitcoin::EncryptedSignature, ()>>,
+    /// Stops the running swap if the taker sets it up again.
+    supersede: HashMap<Uuid, oneshot::Sender<()>>,
     inflight_encrypted_signatures: FuturesUnordered<BoxFuture<'static, ResponseChannel<()>>>,
 
     send_transfer_proof: FuturesUnordered<OutgoingTransferProof>,
@@ -59,6 +111,10 @@ where
     /// Tracks [`transfer_proof::Request`]s which are currently inflight and
     /// awaiting an acknowledgement.
     inflight_transfer_proofs: HashMap<RequestId, bmrng::Responder<()>>,
+
+    send_early_refund: FuturesUnordered<OutgoingEarlyRefund>,
+    buffered_early_refunds: HashMap<PeerId, Vec<(early_refund::Request, bmrng::Responder<()>)>>,
+    inflight_early_refunds: HashMap<RequestId, bmrng::Responder<()>>,
 }
 
 impl<LR> EventLoop<LR>
@@ -68,6 +124,7 @@ where
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         swarm: Swarm<Behaviour<LR>>,
+        identity: identity::Keypair,
         env_config: env::Config,
         bitcoin_wallet: Arc<bitcoin::Wallet>,
         monero_wallet: Arc<monero::Wallet>,
@@ -75,11 +132,17 @@ where
         latest_rate: LR,
         min_buy: bitcoin::Amount,
         max_buy: bitcoin::Amount,
+        require_funding_proof: bool,
+        attach_reserve_proof: bool,
+        allow_early_refund: bool,
+        max_connections_per_ip: u32,
     ) -> Result<(Self, mpsc::Receiver<Swap>)> {
         let swap_channel = MpscChannels::default();
+        let (lift_ban, lifted_bans) = mpsc::unbounded_channel();
 
         let event_loop = EventLoop {
             swarm,
+            identity,
             env_config,
             bitcoin_wallet,
             monero_wallet,
@@ -88,11 +151,35 @@ where
             swap_sender: swap_channel.sender,
             min_buy,
             max_buy,
+            require_funding_proof,
+            attach_reserve_proof,
+            allow_early_refund,
+            reserve_proof: None,
+            quotes: QuoteSwitch::default(),
+            peer_bans: PeerBans(lift_ban),
+            lifted_bans,
+            quote_limits: RequestLimits::new(
+                MAX_QUOTE_REQUESTS_PER_PEER,
+                MAX_QUOTE_REQUESTS_PER_IP,
+                REQUEST_LIMIT_WINDOW,
+            ),
+            swap_setup_limits: RequestLimits::new(
+                MAX_SWAP_SETUPS_PER_PEER,
+                MAX_SWAP_SETUPS_PER_IP,
+                REQUEST_LIMIT_WINDOW,
+            ),
+            peer_ips: HashMap::new(),
+            connections_per_ip: ConnectionsPerIp::new(max_connections_per_ip),
+            reserved_funding: HashMap::new(),
             recv_encrypted_signature: Default::default(),
+            supersede: Default::default(),
             inflight_encrypted_signatures: Default::default(),
             send_transfer_proof: Default::default(),
             buffered_transfer_proofs: Default::default(),
             inflight_transfer_proofs: Default::default(),
+            send_early_refund: Default::default(),
+            buffered_early_refunds: Default::default(),
+            inflight_early_refunds: Default::default(),
         };
         Ok((event_loop, swap_channel.receiver))
     }
@@ -101,10 +188,23 @@ where
         *Swarm::local_peer_id(&self.swarm)
     }
 
+    /// Returns a handle that allows pausing and resuming quotes while the
+    /// event loop is running.
+    pub fn quote_switch(&self) -> QuoteSwitch {
+        self.quotes.clone()
+    }
+
+    /// Returns a handle that allows lifting bans of peers while the event
+    /// loop is running.
+    pub fn peer_bans(&self) -> PeerBans {
+        self.peer_bans.clone()
+    }
+
     pub async fn run(mut self) {
         // ensure that these streams are NEVER empty, otherwise it will
         // terminate forever.
         self.send_transfer_proof.push(future::pending().boxed());
+        self.send_early_refund.push(future::pending().boxed());
         self.inflight_encrypted_signatures
             .push(future::pending().boxed());
 
@@ -116,6 +216,18 @@ where
             }
         };
 
+        match self.db.all_peer_stats() {
+            Ok(peer_stats) => {
+                let now = SystemTime::now();
+                for (peer, stats) in peer_stats {
+                    if stats.is_banned(now) {
+                        self.ban(peer, &stats);
+                    }
+                }
+            }
+            Err(error) => tracing::warn!("Failed to load banned peers: {:#}", error),
+        }
+
         for (swap_id, state) in unfinished_swaps {
             let peer_id = match self.db.get_peer_id(swap_id) {
                 Ok(peer_id) => peer_id,
@@ -135,6 +247,7 @@ where
                 db: self.db.clone(),
                 state: state.into(),
                 swap_id,
+                allow_early_refund: self.allow_early_refund,
             };
 
             match self.swap_sender.send(swap).await {
@@ -149,18 +262,29 @@ where
             tokio::select! {
                 swarm_event = self.swarm.select_next_some() => {
                     match swarm_event {
-                        SwarmEvent::Behaviour(OutEvent::SwapSetupInitiated { mut send_wallet_snapshot }) => {
+                        SwarmEvent::Behaviour(OutEvent::SwapSetupInitiated { peer, mut send_wallet_snapshot }) => {
 
-                            let (btc, responder) = match send_wallet_snapshot.recv().await {
-                                Ok((btc, responder)) => (btc, responder),
+                            let (request, responder) = match send_wallet_snapshot.recv().await {
+                                Ok((request, responder)) => (request, responder),
                                 Err(error) => {
                                     tracing::error!("Swap request will be ignored because of a failure when requesting information for the wallet snapshot: {:#}", error);
                                     continue;
                                 }
                             };
 
-                            let wallet_snapshot = match WalletSnapshot::capture(&self.bitcoin_wallet, &self.monero_wallet, btc).await {
-                                Ok(wallet_snapshot) => wallet_snapshot,
+                            let stored_xmr = match self.check_swap_request(peer, &request).await {
+                                Ok(stored_xmr) => stored_xmr,
+                                Err(error) => {
+                                    let _ = responder.respond(Err(error));
+                                    continue;
+                                }
+                            };
+
+                            let wallet_snapshot = match WalletSnapshot::capture(&self.bitcoin_wallet, &self.monero_wallet, request.btc).await {
+                                Ok(wallet_snapshot) => match stored_xmr {
+                                    Some(stored_xmr) => wallet_snapshot.retrying(stored_xmr),
+                                    None => wallet_snapshot,
+                                },
                                 Err(error) => {
                                     tracing::error!("Swap request will be ignored because we were unable to create wallet snapshot for swap: {:#}", error);
                                     continue;
@@ -168,15 +292,35 @@ where
                             };
 
                             // Ignore result, we should never hit this because the receiver will alive as long as the connection is.
-                            let _ = responder.respond(wallet_snapshot);
+                            let _ = responder.respond(Ok(wallet_snapshot));
                         }
                         SwarmEvent::Behaviour(OutEvent::SwapSetupCompleted{peer_id, swap_id, state3}) => {
+                            self.reserved_funding.remove(&peer_id);
                             let _ = self.handle_execution_setup_done(peer_id, swap_id, state3).await;
                         }
                         SwarmEvent::Behaviour(OutEvent::SwapDeclined { peer, error }) => {
-                            tracing::warn!(%peer, "Ignoring spot price request: {}", error);
+                            self.reserved_funding.remove(&peer);
+                            tracing::warn!(%peer, "Ignoring spot price request: {:#}", error);
+                        }
+                        SwarmEvent::Behaviour(OutEvent::SwapSetupFailed { peer, error }) => {
+                            self.reserved_funding.remove(&peer);
+                            tracing::warn!(%peer, "Swap setup failed: {:#}", error);
+                            if !error.is::<alice::LocalFailure>() {
+                                self.record_misbehavior(peer, Misbehavior::AbortedSetup).await;
+                            }
+                        }
+                        SwarmEvent::Behaviour(OutEvent::InvalidMessage { peer, error }) => {
+                            tracing::warn!(%peer, "Invalid message: {:#}", error);
+                            self.record_misbehavior(peer, Misbehavior::InvalidMessage).await;
                         }
                         SwarmEvent::Behaviour(OutEvent::QuoteRequested { channel, peer }) => {
+                            let ip = self.peer_ips.get(&peer).copied();
+                            if !self.quote_limits.allow(peer, ip, Instant::now()) {
+                                tracing::debug!(%peer, "Ignoring quote request because the peer exceeded the rate limit");
+                                continue;
+                            }
+
+                            let started = Instant::now();
                             let quote = match self.make_quote(self.min_buy, self.max_buy).await {
                                 Ok(quote) => quote,
                                 Err(error) => {
@@ -188,6 +332,7 @@ where
                             if self.swarm.behaviour_mut().quote.send_response(channel, quote).is_err() {
                                 tracing::debug!(%peer, "Failed to respond with quote");
                             }
+                            METRICS.observe_quote_duration(started.elapsed());
                         }
                         SwarmEvent::Behaviour(OutEvent::TransferProofAcknowledged { peer, id }) => {
                             tracing::debug!(%peer, "Bob acknowledged transfer proof");
@@ -195,6 +340,25 @@ where
                                 let _ = responder.respond(());
                             }
                         }
+                        SwarmEvent::Behaviour(OutEvent::EarlyRefundAcknowledged { peer, id }) => {
+                            tracing::debug!(%peer, "Bob acknowledged early refund signature");
+                            if let Some(responder) = self.inflight_early_refunds.remove(&id) {
+                                let _ = responder.respond(());
+                            }
+                        }
+                        SwarmEvent::Behaviour(OutEvent::ReceiptRequested { msg, channel, peer }) => {
+                            let swap_id = msg.swap_id;
+                            let response = self.countersign_receipt(peer, *msg).await;
+
+                            match &response {
+                                Ok(_) => tracing::info!(%swap_id, "Countersigned receipt of swap"),
+                                Err(error) => tracing::debug!(%swap_id, %peer, "Refused to countersign receipt: {}", error),
+                            }
+
+                            if self.swarm.behaviour_mut().receipt.send_response(channel, response).is_err() {
+                                tracing::debug!(%peer, "Failed to respond with receipt signature");
+                            }
+                        }
                         SwarmEvent::Behaviour(OutEvent::EncryptedSignatureReceived{ msg, channel, peer }) => {
                             let swap_id = msg.swap_id;
                             let swap_peer = self.db.get_peer_id(swap_id);
@@ -207,6 +371,7 @@ where
                                         unknown_swap_id = %swap_id,
                                         from = %peer,
                                         "Ignoring encrypted signature for unknown swap");
+                                    self.record_misbehavior(peer, Misbehavior::InvalidMessage).await;
                                     continue;
                                 }
                             };
@@ -218,11 +383,21 @@ where
                                     expected_from = %swap_peer,
                                     "Ignoring malicious encrypted signature which was not expected from this peer",
                                     );
+                                self.record_misbehavior(peer, Misbehavior::InvalidMessage).await;
                                 continue;
                             }
 
+                            // The Bitcoin is locked, the taker cannot set the swap up again
+                            self.supersede.remove(&swap_id);
+
                             let sender = match self.recv_encrypted_signature.remove(&swap_id) {
                                 Some(sender) => sender,
+                                None if self.learned_encrypted_signature(swap_id) => {
+                                    // The taker did not learn that we received it and sent it again
+                                    tracing::debug!(%swap_id, "Acknowledging encrypted signature we already learned");
+                                    let _ = self.swarm.behaviour_mut().encrypted_signature.send_response(channel, ());
+                                    continue;
+                                }
                                 None => {
                                     // TODO: Don't just drop encsig if we currently don't have a running swap for it, save in db
                                     tracing::warn!(%swap_id, "No sender for encrypted signature, maybe already handled?");
@@ -257,6 +432,28 @@ where
                         }
                         SwarmEvent::ConnectionEstablished { peer_id: peer, endpoint, .. } => {
                             tracing::debug!(%peer, address = %endpoint.get_remote_address(), "New connection established");
+                            METRICS.connection_established();
+
+                            if endpoint.is_listener() {
+                                if let Some(ip) = ip_address(endpoint.get_remote_address()) {
+                                    if !self.connections_per_ip.connected(ip) {
+                                        tracing::warn!(%peer, %ip, "Too many connections from IP address, disconnecting peer");
+                                        let _ = self.swarm.disconnect_peer_id(peer);
+                                        continue;
+                                    }
+                                }
+                            }
+
+                            if let Some(ip) = ip_address(endpoint.get_remote_address()) {
+                                self.peer_ips.insert(peer, ip);
+                            }
+
+                            // Bans are deferred while we have swaps with the peer.
+                            if let Ok(stats) = self.db.get_peer_stats(peer) {
+                                if stats.is_banned(SystemTime::now()) {
+                                    self.ban(peer, &stats);
+                                }
+                            }
 
                             if let Some(transfer_proofs) = self.buffered_transfer_proofs.remove(&peer) {
                                 for (transfer_proof, responder) in transfer_proofs {
@@ -266,16 +463,32 @@ where
                                     self.inflight_transfer_proofs.insert(id, responder);
                                 }
                             }
+
+                            if let Some(early_refunds) = self.buffered_early_refunds.remove(&peer) {
+                                for (early_refund, responder) in early_refunds {
+                                    tracing::debug!(%peer, "Found buffered early refund signature for peer");
+
+                                    let id = self.swarm.behaviour_mut().early_refund.send_request(&peer, early_refund);
+                                    self.inflight_early_refunds.insert(id, responder);
+                                }
+                            }
                         }
                         SwarmEvent::IncomingConnectionError { send_back_addr: address, error, .. } => {
                             tracing::warn!(%address, "Failed to set up connection with peer: {:#}", error);
                         }
                         SwarmEvent::ConnectionClosed { peer_id: peer, num_established, endpoint, cause: Some(error) } if num_established == 0 => {
+                            self.connection_closed(&endpoint);
+                            self.peer_ips.remove(&peer);
                             tracing::warn!(%peer, address = %endpoint.get_remote_address(), "Lost connection to peer: {:#}", error);
                         }
                         SwarmEvent::ConnectionClosed { peer_id: peer, num_established, endpoint, cause: None } if num_established == 0 => {
+                            self.connection_closed(&endpoint);
+                            self.peer_ips.remove(&peer);
                             tracing::info!(%peer, address = %endpoint.get_remote_address(), "Successfully closed connection");
                         }
+                        SwarmEvent::ConnectionClosed { endpoint, .. } => {
+                            self.connection_closed(&endpoint);
+                        }
                         SwarmEvent::NewListenAddr(address) => {
                             tracing::info!(%address, "New listen address reported");
                         }
@@ -302,25 +515,391 @@ where
                         }
                     }
                 }
+                next_early_refund = self.send_early_refund.next() => {
+                    match next_early_refund {
+                        Some(Ok((peer, early_refund, responder))) => {
+                            if !self.swarm.behaviour_mut().early_refund.is_connected(&peer) {
+                                tracing::warn!(%peer, "No active connection to peer, buffering early refund signature");
+                                self.buffered_early_refunds.entry(peer).or_insert_with(Vec::new).push((early_refund, responder));
+                                continue;
+                            }
+
+                            let id = self.swarm.behaviour_mut().early_refund.send_request(&peer, early_refund);
+                            self.inflight_early_refunds.insert(id, responder);
+                        },
+                        Some(Err(error)) => {
+                            tracing::trace!("A swap stopped without sending an early refund signature: {:#}", error);
+                        }
+                        None => {
+                            unreachable!("stream of early refund receivers must never terminate")
+                        }
+                    }
+                }
                 Some(response_channel) = self.inflight_encrypted_signatures.next() => {
                     let _ = self.swarm.behaviour_mut().encrypted_signature.send_response(response_channel, ());
                 }
+                Some(peer) = self.lifted_bans.recv() => {
+                    // The peer may have been banned again since the ban was scheduled to expire.
+                    if matches!(self.db.get_peer_stats(peer), Ok(stats) if stats.is_banned(SystemTime::now())) {
+                        continue;
+                    }
+
+                    tracing::info!(%peer, "Lifted ban of peer");
+                    self.swarm.unban_peer_id(peer);
+                }
             }
         }
     }
 
+    /// Checks a swap request before we commit wallet resources to it.
+    ///
+    /// Returns the Monero of the stored setup if the taker sets up a swap
+    /// again, which is only accepted from the same taker for the same amount
+    /// while we still wait for its Bitcoin.
+    async fn check_swap_request(
+        &mut self,
+        peer: PeerId,
+        request: &SwapRequest,
+    ) -> Result<Option<monero::Amount>, alice::Error> {
+        if self.quotes.is_paused() {
+            return Err(alice::Error::QuotesPaused);
+        }
+
+        let ip = self.peer_ips.get(&peer).copied();
+        if !self.swap_setup_limits.allow(peer, ip, Instant::now()) {
+            return Err(alice::Error::TooManyRequests);
+        }
+
+        let mut stored_xmr = None;
+        if let Some(swap_id) = request.swap_id {
+            if let Ok(stored_peer) = self.db.get_peer_id(swap_id) {
+                match self.unfunded_setup(swap_id) {
+                    Some(state3)
+                        if stored_peer == peer && state3.tx_lock.lock_amount() == request.btc =>
+                    {
+                        stored_xmr = Some(state3.xmr);
+                    }
+                    _ => return Err(alice::Error::SwapAlreadyExists { swap_id }),
+                }
+            }
+        }
+
+        let funding_proof = match &request.funding_proof {
+            Some(funding_proof) => funding_proof,
+            None if self.require_funding_proof => return Err(alice::Error::FundingProofRequired),
+            None => return Ok(stored_xmr),
+        };
+
+        // Without the nonce anyone who saw the proof could replay it.
+        let session_nonce = match request.session_nonce {
+            Some(session_nonce) => session_nonce,
+            None => return Err(alice::Error::InvalidFundingProof),
+        };
+
+        let outpoints = funding_proof
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<Vec<_>>();
+        let reserved = self.reserved_funding.iter().any(|(other, reserved)| {
+            *other != peer && reserved.iter().any(|outpoint| outpoints.contains(outpoint))
+        });
+        if reserved {
+            return Err(alice::Error::FundsUnavailable);
+        }
+
+        match self
+            .bitcoin_wallet
+            .verify_funding_proof(funding_proof, request.btc, session_nonce.as_bytes())
+            .await
+        {
+            Ok(()) => {
+                self.reserved_funding.insert(peer, outpoints);
+                Ok(stored_xmr)
+            }
+            // Happens to honest takers whose wallet is not synced.
+            Err(error) if error.is::<bitcoin::FundsUnavailable>() => {
+                tracing::debug!(%peer, "Received funding proof that spends unavailable outputs: {:#}", error);
+                Err(alice::Error::FundsUnavailable)
+            }
+            Err(error)
+                if error
+                    .downcast_ref::<bitcoin::InvalidFundingProof>()
+                    .is_some() =>
+            {
+                tracing::warn!(%peer, "Received invalid funding proof: {:#}", error);
+                self.record_misbehavior(peer, Misbehavior::InvalidMessage)
+                    .await;
+
+                Err(alice::Error::InvalidFundingProof)
+            }
+            Err(error) => Err(alice::Error::FundingProofVerificationFailed(error)),
+        }
+    }
+
+    /// Signs the receipt Bob sent if it matches the swap as we recorded it,
+    /// storing the receipt signed by both of us.
+    async fn countersign_receipt(
+        &mut self,
+        peer: PeerId,
+        request: receipt::Request,
+    ) -> Result<bitcoin::Signature, receipt::Error> {
+        let swap_id = request.swap_id;
+
+        match self.db.get_peer_id(swap_id) {
+            Ok(swap_peer) if swap_peer == peer => {}
+            _ => return Err(receipt::Error::UnknownSwap),
+        }
+
+        let (terms, btc_locked_at) = match completed_swap(&self.db, swap_id) {
+            Ok(Some(completed)) => completed,
+            Ok(None) => return Err(receipt::Error::NotCompleted),
+            Err(error) => {
+                tracing::warn!(%swap_id, "Failed to load completed swap: {:#}", error);
+                return Err(receipt::Error::Internal);
+            }
+        };
+
+        let receipt = request.receipt;
+        let expected = terms.receipt(receipt.started_at, receipt.completed_at);
+        if receipt != expected
+            || !receipt.has_plausible_timestamps(btc_locked_at, SystemTime::now())
+        {
+            return Err(receipt::Error::Mismatch);
+        }
+        if receipt
+            .verify(&receipt.buyer, &request.buyer_signature)
+            .is_err()
+        {
+            self.record_misbehavior(peer, Misbehavior::InvalidMessage)
+                .await;
+            return Err(receipt::Error::Mismatch);
+        }
+
+        let seller_signature = terms.sign(&receipt);
+        let signed = SignedReceipt {
+            receipt,
+            seller_signature: seller_signature.clone(),
+            buyer_signature: request.buyer_signature,
+        };
+        if let Err(error) = self.db.insert_receipt(swap_id, &signed).await {
+            tracing::warn!(%swap_id, "Failed to store receipt: {:#}", error);
+            return Err(receipt::Error::Internal);
+        }
+
+        Ok(seller_signature)
+    }
+
+    async fn record_misbehavior(&mut self, peer: PeerId, misbehavior: Misbehavior) {
+        match self.db.record_misbehavior(peer, misbehavior).await {
+            Ok(stats) if stats.is_banned(SystemTime::now()) => self.ban(peer, &stats),
+            Ok(_) => {}
+            Err(error) => {
+                tracing::warn!(%peer, ?misbehavior, "Failed to record misbehavior of peer: {:#}", error)
+            }
+        }
+    }
+
+    fn connection_closed(&mut self, endpoint: &ConnectedPoint) {
+        METRICS.connection_closed();
+
+        if endpoint.is_listener() {
+            if let Some(ip) = ip_address(endpoint.get_remote_address()) {
+                self.connections_per_ip.disconnected(ip);
+            }
+        }
+    }
+
+    /// Bans the peer until the ban expires, unless we have unfinished swaps
+    /// with them which could not be completed without a connection.
+    fn ban(&mut self, peer: PeerId, stats: &PeerStats) {
+        if self.has_unfinished_swap_with(peer) {
+            tracing::warn!(%peer, "Deferring ban of peer until our swaps with them are finished");
+            return;
+        }
+
+        let remaining = match stats.ban_remaining(SystemTime::now()) {
+            Some(remaining) => remaining,
+            None => return,
+        };
+
+        tracing::warn!(%peer, hours = %(remaining.as_secs() / 3600), "Banning peer because of repeated misbehavior");
+        self.swarm.ban_peer_id(peer);
+
+        let peer_bans = self.peer_bans.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(remaining).await;
+            peer_bans.lift(peer);
+        });
+    }
+
+    /// The setup of a swap that is still waiting for the Bitcoin of the
+    /// taker.
+    fn unfunded_setup(&self, swap_id: Uuid) -> Option<State3> {
+        match self
+            .db
+            .get_state(swap_id)
+            .and_then(|swap| swap.try_into_alice())
+        {
+            Ok(Alice::Started { state3 }) => Some(state3),
+            _ => None,
+        }
+    }
+
+    fn learned_encrypted_signature(&self, swap_id: Uuid) -> bool {
+        matches!(
+            self.db
+                .get_state(swap_id)
+                .and_then(|swap| swap.try_into_alice()),
+            Ok(Alice::EncSigLearned { .. })
+                | Ok(Alice::BtcRedeemTransactionPublished { .. })
+                | Ok(Alice::Done(AliceEndState::BtcRedeemed))
+        )
+    }
+
+    fn has_unfinished_swap_with(&self, peer: PeerId) -> bool {
+        match self.db.unfinished_alice() {
+            Ok(swaps) => swaps
+                .into_iter()
+                .any(|(swap_id, _)| matches!(self.db.get_peer_id(swap_id), Ok(swap_peer) if swap_peer == peer)),
+            // Rather not ban a peer than risk cutting off a swap.
+            Err(_) => true,
+        }
+    }
+
     async fn make_quote(
         &mut self,
         min_buy: bitcoin::Amount,
         max_buy: bitcoin::Amount,
+    ) -> Result<SignedBidQuote> {
+        // The price of a swap is determined by its amount once it is set up,
+        // advertise the highest price of all amounts we accept so that no
+        // swap costs more than quoted.
+        let rate = match self.latest_rate.latest_rate_between(min_buy, max_buy) {
+            Ok(rate) => rate,
+            Err(error) => {
+                tracing::warn!("Failed to get latest rate: {}", error);
+                return Ok(SignedBidQuote::refused(ProtocolError::RateUnavailable));
+            }
+        };
+
+        let ask_price = rate.ask().context("Failed to compute asking price")?;
+        let quote = self.make_bid_quote(ask_price, min_buy, max_buy).await?;
+
+        let mut quote =
+            SignedBidQuote::sign(quote, rate.market_price(), QUOTE_VALIDITY, &self.identity)?;
+        if self.attach_reserve_proof {
+            quote.reserve_proof = self.reserve_proof().await;
+        }
+        // A zero quote while quotes are not paused means our Monero does not
+        // cover the minimum swap.
+        if quote.quote.max_quantity == bitcoin::Amount::ZERO && !self.quotes.is_paused() {
+            quote.error = Some(ProtocolError::InsufficientLiquidity);
+        }
+
+        Ok(quote)
+    }
+
+    /// The reserve proof to attach to quotes, renewed once it is older than
+    /// [`RESERVE_PROOF_REFRESH_INTERVAL`].
+    ///
+    /// Quotes are handed out without a proof if none can be created.
+    async fn reserve_proof(&mut self) -> Option<ReserveProof> {
+        if let Some((created, proof)) = &self.reserve_proof {
+            if created.elapsed() < RESERVE_PROOF_REFRESH_INTERVAL {
+                return Some(proof.clone());
+            }
+        }
+
+        match self.create_reserve_proof().await {
+            Ok(proof) => {
+                self.reserve_proof = Some((Instant::now(), proof.clone()));
+                Some(proof)
+            }
+            Err(error) => {
+                tracing::warn!("Failed to create reserve proof: {:#}", error);
+                None
+            }
+        }
+    }
+
+    /// Proves only the unlocked Monero that is not reserved for swaps in
+    /// flight, as that is all the ASB can still offer.
+    async fn create_reserve_proof(&self) -> Result<ReserveProof> {
+        let swaps = self.db.all_alice()?.into_iter().map(|(_, state)| state);
+        let reserved = withdraw::reserved_monero(swaps);
+        let unlocked = self.monero_wallet.get_unlocked_balance().await?;
+        let available = unlocked.saturating_sub(reserved);
+
+        if available == monero::Amount::ZERO {
+            bail!(
+                "All unlocked Monero ({}) is reserved for swaps in flight",
+                unlocked
+            )
+        }
+
+        ReserveProof::create(
+            &self.monero_wallet,
+            available,
+            self.peer_id(),
+            SystemTime::now(),
+        )
+        .await
+    }
+
+    async fn make_bid_quote(
+        &self,
+        ask_price: bitcoin::Amount,
+        min_buy: bitcoin::Amount,
+        max_buy: bitcoin::Amount,
     ) -> Result<BidQuote> {
-        let rate = self
-            .latest_rate
-            .latest_rate()
-            .context("Failed to get latest rate")?;
+        if self.quotes.is_paused() {
+            tracing::debug!("Quotes are paused, responding with a zero quote");
+
+            return Ok(BidQuote {
+                price: ask_price,
+                min_quantity: bitcoin::Amount::ZERO,
+                max_quantity: bitcoin::Amount::ZERO,
+            });
+        }
+
+        let balance = self.monero_wallet.get_balance().await?;
+        let max_bitcoin_for_monero = balance.max_bitcoin_for_price(ask_price).ok_or_else(|| {
+            anyhow!(
+                "Bitcoin price ({}) x Monero ({}) overflow",
+                ask_price,
+                balance
+            )
+        })?;
+
+        if min_buy > max_bitcoin_for_monero {
+            tracing::warn!(
+                "Your Monero balance is too low to initiate a swap, as your minimum swap amount is {}. You could at most swap {}",
+                min_buy, max_bitcoin_for_monero
+            );
+
+            return Ok(BidQuote {
+                price: ask_price,
+                min_quantity: bitcoin::Amount::ZERO,
+                max_quantity: bitcoin::Amount::ZERO,
+            });
+        }
+
+        if max_buy > max_bitcoin_for_monero {
+            tracing::warn!(
+                "Your Monero balance is too low to initiate a swap with the maximum swap amount {} that you have specified in your config. You can at most swap {}",
+                max_buy, max_bitcoin_for_monero
+            );
+
+            return Ok(BidQuote {
+                price: ask_price,
+                min_quantity: min_buy,
+                max_quantity: max_bitcoin_for_monero,
+            });
+        }
 
         Ok(BidQuote {
-            price: rate.ask().context("Failed to compute asking price")?,
+            price: ask_price,
             min_quantity: min_buy,
             max_quantity: max_buy,
         })
@@ -332,8 +911,53 @@ where
         swap_id: Uuid,
         state3: State3,
     ) {
+        let replaced = match self.db.get_peer_id(swap_id) {
+            // The taker did not learn that the first setup completed, we stop
+            // the swap of the first setup before it is replaced.
+            Ok(stored_peer)
+                if stored_peer == bob_peer_id && self.unfunded_setup(swap_id).is_some() =>
+            {
+                if let Some(supersede) = self.supersede.remove(&swap_id) {
+                    let _ = supersede.send(());
+                }
+                tracing::info!(%swap_id, "Replacing the setup of a swap the taker set up again");
+                true
+            }
+            Ok(_) => {
+                tracing::warn!(%swap_id, "Ignoring swap setup, swap already exists");
+                return;
+            }
+            Err(_) => false,
+        };
+
         let handle = self.new_handle(bob_peer_id, swap_id);
 
+        // Only needed for the bookkeeping, the swap goes on without it.
+        if let Err(error) = self
+            .db
+            .record_swap_amounts(swap_id, SwapAmounts::from(&state3))
+            .await
+        {
+            tracing::warn!(%swap_id, "Failed to record the amounts of the swap: {:#}", error);
+        }
+        match self
+            .latest_rate
+            .latest_rate_for(state3.tx_lock.lock_amount())
+        {
+            Ok(rate) => {
+                if let Err(error) = self
+                    .db
+                    .record_market_price(swap_id, rate.market_price())
+                    .await
+                {
+                    tracing::warn!(%swap_id, "Failed to record the market price: {:#}", error);
+                }
+            }
+            Err(error) => {
+                tracing::warn!(%swap_id, "Failed to get the market price to record: {}", error);
+            }
+        }
+
         let initial_state = AliceState::Started {
             state3: Box::new(state3),
         };
@@ -346,19 +970,32 @@ where
             db: self.db.clone(),
             state: initial_state,
             swap_id,
+            allow_early_refund: self.allow_early_refund,
         };
 
         // TODO: Consider adding separate components for start/resume of swaps
 
-        // swaps save peer id so we can resume
-        match self.db.insert_peer_id(swap_id, bob_peer_id).await {
+        // swaps save peer id and the setup so we can resume and recognise a
+        // taker setting the swap up again
+        let stored = async {
+            self.db.insert_peer_id(swap_id, bob_peer_id).await?;
+            self.db
+                .insert_latest_state(swap_id, database::Swap::Alice((&swap.state).into()))
+                .await
+        };
+        match stored.await {
             Ok(_) => {
                 if let Err(error) = self.swap_sender.send(swap).await {
                     tracing::warn!(%swap_id, "Failed to start swap: {}", error);
+                    return;
+                }
+
+                if !replaced {
+                    METRICS.swap_started();
                 }
             }
             Err(error) => {
-                tracing::warn!(%swap_id, "Unable to save peer-id in database: {}", error);
+                tracing::warn!(%swap_id, "Unable to save swap in database: {}", error);
             }
         }
     }
@@ -370,10 +1007,13 @@ where
         // races these futures against a timelock
 
         let (transfer_proof_sender, mut transfer_proof_receiver) = bmrng::channel(1);
+        let (early_refund_sender, mut early_refund_receiver) = bmrng::channel(1);
         let encrypted_signature = bmrng::channel(1);
 
         self.recv_encrypted_signature
             .insert(swap_id, encrypted_signature.0);
+        let (supersede, superseded) = oneshot::channel();
+        self.supersede.insert(swap_id, supersede);
 
         self.send_transfer_proof.push(
             async move {
@@ -389,17 +1029,124 @@ where
             .boxed(),
         );
 
+        self.send_early_refund.push(
+            async move {
+                let (tx_early_refund_sig, responder) = early_refund_receiver.recv().await?;
+
+                let request = early_refund::Request {
+                    swap_id,
+                    tx_early_refund_sig,
+                };
+
+                Ok((peer, request, responder))
+            }
+            .boxed(),
+        );
+
         EventLoopHandle {
+            peer,
             recv_encrypted_signature: Some(encrypted_signature.1),
             send_transfer_proof: Some(transfer_proof_sender),
+            send_early_refund: Some(early_refund_sender),
+            superseded: Some(superseded),
         }
     }
 }
 
+/// Shared switch to pause and resume handing out quotes.
+///
+/// While paused, every quote request is answered with a maximum quantity of
+/// zero and swap setups are declined, so that no new swaps are started.
+/// Swaps that are already running are not affected.
+#[derive(Debug, Clone, Default)]
+pub struct QuoteSwitch(Arc<AtomicBool>);
+
+impl QuoteSwitch {
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The terms of the receipt of the swap, together with when we saw the
+/// Bitcoin being locked.
+///
+/// Returns `None` if we did not publish the redeem transaction (yet).
+fn completed_swap(
+    db: &Database,
+    swap_id: Uuid,
+) -> Result<Option<(crate::receipt::Terms, SystemTime)>> {
+    let redeemed = matches!(
+        db.get_state(swap_id)?.try_into_alice()?,
+        Alice::BtcRedeemTransactionPublished { .. } | Alice::Done(AliceEndState::BtcRedeemed)
+    );
+    if !redeemed {
+        return Ok(None);
+    }
+
+    let btc_locked_at = db
+        .get_history(swap_id)?
+        .into_iter()
+        .find(|transition| {
+            matches!(
+                transition.state.as_str(),
+                "bitcoin lock transaction in mempool" | "btc is locked"
+            )
+        })
+        .map(|transition| transition.timestamp)
+        .context("The history of the swap does not record when the Bitcoin was locked")?;
+    let terms = db.get_receipt_terms(swap_id)?;
+
+    Ok(Some((terms, btc_locked_at)))
+}
+
+fn ip_address(address: &libp2p::Multiaddr) -> Option<IpAddr> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::from(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::from(ip)),
+        _ => None,
+    })
+}
+
+/// Handle to lift bans of peers, the statistics that led to the ban have to be
+/// cleared in the database separately.
+#[derive(Debug, Clone)]
+pub struct PeerBans(mpsc::UnboundedSender<PeerId>);
+
+impl PeerBans {
+    pub fn lift(&self, peer: PeerId) {
+        // The event loop only stops when the ASB shuts down.
+        let _ = self.0.send(peer);
+    }
+}
+
 pub trait LatestRate {
     type Error: std::error::Error + Send + Sync + 'static;
 
     fn latest_rate(&mut self) -> Result<Rate, Self::Error>;
+
+    /// The rate for selling XMR worth `btc`, which differs from
+    /// [`LatestRate::latest_rate`] if the spread depends on the amount.
+    fn latest_rate_for(&mut self, _btc: bitcoin::Amount) -> Result<Rate, Self::Error> {
+        self.latest_rate()
+    }
+
+    /// The least favourable rate for the taker among all amounts from `min`
+    /// to `max`, so that no swap within a quote is priced above it.
+    fn latest_rate_between(
+        &mut self,
+        _min: bitcoin::Amount,
+        _max: bitcoin::Amount,
+    ) -> Result<Rate, Self::Error> {
+        self.latest_rate()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -431,40 +1178,118 @@ impl LatestRate for FixedRate {
 }
 
 /// Produces [`Rate`]s based on [`PriceUpdate`]s from kraken and a configured
-/// spread.
+/// spread strategy.
+///
+/// The strategy is shared between all clones and can be replaced at runtime.
 #[derive(Debug, Clone)]
 pub struct KrakenRate {
-    ask_spread: Decimal,
+    spread: Arc<RwLock<Arc<dyn SpreadStrategy>>>,
     price_updates: kraken::PriceUpdates,
+    guard: Option<PriceGuard>,
 }
 
 impl KrakenRate {
-    pub fn new(ask_spread: Decimal, price_updates: kraken::PriceUpdates) -> Self {
+    pub fn new(spread: Arc<dyn SpreadStrategy>, price_updates: kraken::PriceUpdates) -> Self {
         Self {
-            ask_spread,
+            spread: Arc::new(RwLock::new(spread)),
             price_updates,
+            guard: None,
+        }
+    }
+
+    /// Only provide rates while the price agrees with the secondary feed of
+    /// the guard.
+    pub fn with_guard(self, guard: PriceGuard) -> Self {
+        Self {
+            guard: Some(guard),
+            ..self
+        }
+    }
+
+    /// Replaces the spread the strategy applies by default, tiers and the
+    /// volatility settings are kept.
+    pub fn set_ask_spread(&self, ask_spread: Decimal) {
+        let mut spread = self.spread.write().expect("lock is never poisoned");
+        *spread = Arc::from(spread.with_ask_spread(ask_spread));
+    }
+
+    /// The latest market asking price, if the guard agrees with it.
+    fn latest_ask(&mut self) -> Result<bitcoin::Amount, RateUnavailable> {
+        let update = self
+            .price_updates
+            .latest_update()
+            .map_err(RateUnavailable::PrimaryFeed)?;
+        if let Some(guard) = &mut self.guard {
+            guard.check(update.ask)?;
         }
+
+        Ok(update.ask)
+    }
+
+    /// Feeds a market asking price to the spread strategy.
+    pub fn observe(&self, ask: bitcoin::Amount) {
+        self.spread
+            .read()
+            .expect("lock is never poisoned")
+            .observe(ask, Instant::now());
     }
 }
 
 impl LatestRate for KrakenRate {
-    type Error = kraken::Error;
+    type Error = RateUnavailable;
 
+    /// The rate for the smallest amounts.
     fn latest_rate(&mut self) -> Result<Rate, Self::Error> {
-        let update = self.price_updates.latest_update()?;
-        let rate = Rate::new(update.ask, self.ask_spread);
+        self.latest_rate_for(bitcoin::Amount::ZERO)
+    }
+
+    fn latest_rate_for(&mut self, btc: bitcoin::Amount) -> Result<Rate, Self::Error> {
+        let ask = self.latest_ask()?;
+        let ask_spread = self
+            .spread
+            .read()
+            .expect("lock is never poisoned")
+            .spread(btc);
 
-        Ok(rate)
+        Ok(Rate::new(ask, ask_spread))
+    }
+
+    fn latest_rate_between(
+        &mut self,
+        min: bitcoin::Amount,
+        max: bitcoin::Amount,
+    ) -> Result<Rate, Self::Error> {
+        let ask = self.latest_ask()?;
+        let ask_spread = self
+            .spread
+            .read()
+            .expect("lock is never poisoned")
+            .max_spread(min, max);
+
+        Ok(Rate::new(ask, ask_spread))
     }
 }
 
 #[derive(Debug)]
 pub struct EventLoopHandle {
+    peer: PeerId,
     recv_encrypted_signature: Option<bmrng::RequestReceiver<bitcoin::EncryptedSignature, ()>>,
     send_transfer_proof: Option<bmrng::RequestSender<monero::TransferProof, ()>>,
+    send_early_refund: Option<bmrng::RequestSender<bitcoin::Signature, ()>>,
+    superseded: Option<oneshot::Receiver<()>>,
 }
 
 impl EventLoopHandle {
+    /// The peer this handle communicates with.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer
+    }
+
+    /// Tells when the taker set the swap up again, which replaces it.
+    pub fn superseded(&mut self) -> Superseded {
+        Superseded(self.superseded.take())
+    }
+
     pub async fn recv_encrypted_signature(&mut self) -> Result<bitcoin::EncryptedSignature> {
         let (tx_redeem_encsig, responder) = self
             .recv_encrypted_signature
@@ -490,6 +1315,38 @@ impl EventLoopHandle {
 
         Ok(())
     }
+
+    pub async fn send_early_refund(
+        &mut self,
+        tx_early_refund_sig: bitcoin::Signature,
+    ) -> Result<()> {
+        self.send_early_refund
+            .take()
+            .context("Early refund signature was already sent")?
+            .send_receive(tx_early_refund_sig)
+            .await
+            .context("Failed to send early refund signature")?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Superseded(Option<oneshot::Receiver<()>>);
+
+impl Superseded {
+    /// Resolves once the taker set the swap up again, never if the event loop
+    /// is gone.
+    pub async fn requested(&mut self) {
+        if let Some(receiver) = self.0.as_mut() {
+            if receiver.await.is_ok() {
+                return;
+            }
+            self.0 = None;
+        }
+
+        future::pending().await
+    }
 }
 
 #[allow(missing_debug_implementations)]
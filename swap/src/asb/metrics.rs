@@ -0,0 +1,376 @@
+//! Prometheus metrics of a running ASB.
+//!
+//! Metrics are collected in the global [`METRICS`] registry and exposed in the
+//! Prometheus text format under `/metrics`.
+
+use crate::protocol::alice::AliceState;
+use crate::{bitcoin, monero};
+use anyhow::{Context, Result};
+use conquer_once::Lazy;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use std::convert::{Infallible, TryFrom};
+use std::fmt::{Display, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+const QUOTE_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+const CONFIRMATION_BUCKETS: &[f64] = &[60.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0, 7200.0];
+
+#[derive(Debug)]
+pub struct Metrics {
+    swaps_started: AtomicU64,
+    swaps_btc_redeemed: AtomicU64,
+    swaps_xmr_refunded: AtomicU64,
+    swaps_btc_punished: AtomicU64,
+    swaps_safely_aborted: AtomicU64,
+    swaps_failed: AtomicU64,
+    open_connections: AtomicI64,
+    quote_duration: Histogram,
+    btc_lock_confirmation_duration: Histogram,
+    last_price_update: Mutex<Option<Instant>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            swaps_started: AtomicU64::default(),
+            swaps_btc_redeemed: AtomicU64::default(),
+            swaps_xmr_refunded: AtomicU64::default(),
+            swaps_btc_punished: AtomicU64::default(),
+            swaps_safely_aborted: AtomicU64::default(),
+            swaps_failed: AtomicU64::default(),
+            open_connections: AtomicI64::default(),
+            quote_duration: Histogram::new(QUOTE_BUCKETS),
+            btc_lock_confirmation_duration: Histogram::new(CONFIRMATION_BUCKETS),
+            last_price_update: Mutex::new(None),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn swap_started(&self) {
+        self.swaps_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the final state of a swap.
+    pub fn swap_finished(&self, state: &AliceState) {
+        let counter = match state {
+            AliceState::BtcRedeemed => &self.swaps_btc_redeemed,
+            AliceState::XmrRefunded => &self.swaps_xmr_refunded,
+            AliceState::BtcPunished => &self.swaps_btc_punished,
+            AliceState::SafelyAborted | AliceState::BtcEarlyRefunded => &self.swaps_safely_aborted,
+            _ => return,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn swap_failed(&self) {
+        self.swaps_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_established(&self) {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_quote_duration(&self, duration: Duration) {
+        self.quote_duration.observe(duration);
+    }
+
+    pub fn observe_btc_lock_confirmation_duration(&self, duration: Duration) {
+        self.btc_lock_confirmation_duration.observe(duration);
+    }
+
+    pub fn price_updated(&self) {
+        *self
+            .last_price_update
+            .lock()
+            .expect("lock is never poisoned") = Some(Instant::now());
+    }
+
+    /// Time since the last price update was received, `None` if none was
+    /// received yet.
+    pub fn price_update_age(&self) -> Option<Duration> {
+        self.last_price_update
+            .lock()
+            .expect("lock is never poisoned")
+            .map(|instant| instant.elapsed())
+    }
+
+    fn encode(&self, balances: Option<(bitcoin::Amount, monero::Amount)>) -> String {
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "asb_swaps_started_total",
+            "counter",
+            "Number of swaps that were set up with a taker.",
+            &[("", self.swaps_started.load(Ordering::Relaxed))],
+        );
+        write_metric(
+            &mut out,
+            "asb_swaps_finished_total",
+            "counter",
+            "Number of swaps that reached a final state, by final state.",
+            &[
+                (
+                    r#"{state="btc_redeemed"}"#,
+                    self.swaps_btc_redeemed.load(Ordering::Relaxed),
+                ),
+                (
+                    r#"{state="xmr_refunded"}"#,
+                    self.swaps_xmr_refunded.load(Ordering::Relaxed),
+                ),
+                (
+                    r#"{state="btc_punished"}"#,
+                    self.swaps_btc_punished.load(Ordering::Relaxed),
+                ),
+                (
+                    r#"{state="safely_aborted"}"#,
+                    self.swaps_safely_aborted.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "asb_swaps_failed_total",
+            "counter",
+            "Number of swaps that stopped with an error and need to be resumed.",
+            &[("", self.swaps_failed.load(Ordering::Relaxed))],
+        );
+        write_metric(
+            &mut out,
+            "asb_open_connections",
+            "gauge",
+            "Number of open libp2p connections.",
+            &[("", self.open_connections.load(Ordering::Relaxed))],
+        );
+
+        let price_update_age = self
+            .price_update_age()
+            .map_or(f64::NAN, |age| age.as_secs_f64());
+        write_metric(
+            &mut out,
+            "asb_price_update_age_seconds",
+            "gauge",
+            "Seconds since the last price update was received, NaN if none was received yet.",
+            &[("", price_update_age)],
+        );
+
+        if let Some((bitcoin_balance, monero_balance)) = balances {
+            write_metric(
+                &mut out,
+                "asb_bitcoin_balance_sats",
+                "gauge",
+                "Balance of the Bitcoin wallet in satoshi.",
+                &[("", bitcoin_balance.as_sat())],
+            );
+            write_metric(
+                &mut out,
+                "asb_monero_balance_piconero",
+                "gauge",
+                "Balance of the Monero wallet in piconero.",
+                &[("", monero_balance.as_piconero())],
+            );
+        }
+
+        self.quote_duration.encode(
+            &mut out,
+            "asb_quote_duration_seconds",
+            "Time taken to respond to a quote request.",
+        );
+        self.btc_lock_confirmation_duration.encode(
+            &mut out,
+            "asb_btc_lock_confirmation_seconds",
+            "Time between seeing the Bitcoin lock transaction and it reaching finality.",
+        );
+
+        out
+    }
+}
+
+/// A histogram with fixed buckets in seconds.
+#[derive(Debug)]
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: buckets.iter().map(|_| AtomicU64::default()).collect(),
+            count: AtomicU64::default(),
+            sum_micros: AtomicU64::default(),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, count) in self.buckets.iter().zip(&self.counts) {
+            if seconds <= *bucket {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn encode(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        for (bucket, count) in self.buckets.iter().zip(&self.counts) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bucket,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)).as_secs_f64();
+
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+fn write_metric<V>(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, V)])
+where
+    V: Display,
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// Serves the global [`METRICS`] under `/metrics`.
+///
+/// Wallet balances are queried on every scrape.
+pub async fn serve(
+    listen: SocketAddr,
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    monero_wallet: Arc<monero::Wallet>,
+) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let bitcoin_wallet = bitcoin_wallet.clone();
+        let monero_wallet = monero_wallet.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let bitcoin_wallet = bitcoin_wallet.clone();
+                let monero_wallet = monero_wallet.clone();
+
+                async move {
+                    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+                        return Ok::<_, Infallible>(
+                            hyper::Response::builder()
+                                .status(StatusCode::NOT_FOUND)
+                                .body(Body::empty())
+                                .expect("static response parts are valid"),
+                        );
+                    }
+
+                    let balances = match balances(&bitcoin_wallet, &monero_wallet).await {
+                        Ok(balances) => Some(balances),
+                        Err(error) => {
+                            tracing::warn!("Failed to get balances for metrics: {:#}", error);
+                            None
+                        }
+                    };
+
+                    Ok(hyper::Response::builder()
+                        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(Body::from(METRICS.encode(balances)))
+                        .expect("static response parts are valid"))
+                }
+            }))
+        }
+    });
+
+    let http = hyper::Server::try_bind(&listen)
+        .with_context(|| format!("Failed to bind metrics server to {}", listen))?
+        .serve(make_service);
+
+    tracing::info!(%listen, "Serving metrics");
+
+    http.await.context("Metrics server failed")
+}
+
+async fn balances(
+    bitcoin_wallet: &bitcoin::Wallet,
+    monero_wallet: &monero::Wallet,
+) -> Result<(bitcoin::Amount, monero::Amount)> {
+    let bitcoin = bitcoin_wallet.balance().await?;
+    let monero = monero_wallet.get_balance().await?;
+
+    Ok((bitcoin, monero))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(5));
+
+        let mut out = String::new();
+        histogram.encode(&mut out, "test_seconds", "Test.");
+
+        assert_eq!(
+            out,
+            r#"# HELP test_seconds Test.
+# TYPE test_seconds histogram
+test_seconds_bucket{le="0.1"} 1
+test_seconds_bucket{le="1"} 2
+test_seconds_bucket{le="+Inf"} 3
+test_seconds_sum 5.55
+test_seconds_count 3
+"#
+        );
+    }
+
+    #[test]
+    fn encodes_swap_outcomes_with_labels() {
+        let metrics = Metrics::default();
+        metrics.swap_started();
+        metrics.swap_finished(&AliceState::BtcRedeemed);
+        metrics.swap_finished(&AliceState::BtcRedeemed);
+
+        let out = metrics.encode(None);
+
+        assert!(out.contains("asb_swaps_started_total 1\n"));
+        assert!(out.contains("asb_swaps_finished_total{state=\"btc_redeemed\"} 2\n"));
+        assert!(out.contains("asb_price_update_age_seconds NaN\n"));
+        assert!(!out.contains("asb_bitcoin_balance_sats"));
+    }
+}
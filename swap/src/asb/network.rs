@@ -1,12 +1,12 @@
 use crate::asb::event_loop::LatestRate;
 use crate::env;
-use crate::network::quote::BidQuote;
+use crate::network::quote::SignedBidQuote;
 use crate::network::rendezvous::XmrBtcNamespace;
 use crate::network::swap_setup::alice;
-use crate::network::swap_setup::alice::WalletSnapshot;
+use crate::network::swap_setup::alice::{SwapRequest, WalletSnapshot};
 use crate::network::transport::authenticate_and_multiplex;
-use crate::network::{encrypted_signature, quote, transfer_proof};
-use crate::protocol::alice::State3;
+use crate::network::{early_refund, encrypted_signature, quote, receipt, transfer_proof};
+use crate::protocol::alice::{KeyPool, State3};
 use anyhow::{anyhow, Error, Result};
 use futures::FutureExt;
 use libp2p::core::connection::ConnectionId;
@@ -48,7 +48,9 @@ pub mod behaviour {
     #[derive(Debug)]
     pub enum OutEvent {
         SwapSetupInitiated {
-            send_wallet_snapshot: bmrng::RequestReceiver<bitcoin::Amount, WalletSnapshot>,
+            peer: PeerId,
+            send_wallet_snapshot:
+                bmrng::RequestReceiver<SwapRequest, Result<WalletSnapshot, alice::Error>>,
         },
         SwapSetupCompleted {
             peer_id: PeerId,
@@ -60,7 +62,7 @@ pub mod behaviour {
             error: alice::Error,
         },
         QuoteRequested {
-            channel: ResponseChannel<BidQuote>,
+            channel: ResponseChannel<SignedBidQuote>,
             peer: PeerId,
         },
         TransferProofAcknowledged {
@@ -72,6 +74,26 @@ pub mod behaviour {
             channel: ResponseChannel<()>,
             peer: PeerId,
         },
+        EarlyRefundAcknowledged {
+            peer: PeerId,
+            id: RequestId,
+        },
+        ReceiptRequested {
+            msg: Box<receipt::Request>,
+            channel: ResponseChannel<receipt::Response>,
+            peer: PeerId,
+        },
+        /// The swap setup failed for a reason other than us declining the
+        /// swap, e.g. the peer aborted it or sent an invalid message.
+        SwapSetupFailed {
+            peer: PeerId,
+            error: Error,
+        },
+        /// The peer sent a message that is not valid in the protocol.
+        InvalidMessage {
+            peer: PeerId,
+            error: Error,
+        },
         Rendezvous(libp2p::rendezvous::Event),
         Failure {
             peer: PeerId,
@@ -84,14 +106,14 @@ pub mod behaviour {
 
     impl OutEvent {
         pub fn unexpected_request(peer: PeerId) -> OutEvent {
-            OutEvent::Failure {
+            OutEvent::InvalidMessage {
                 peer,
                 error: anyhow!("Unexpected request received"),
             }
         }
 
         pub fn unexpected_response(peer: PeerId) -> OutEvent {
-            OutEvent::Failure {
+            OutEvent::InvalidMessage {
                 peer,
                 error: anyhow!("Unexpected response received"),
             }
@@ -111,6 +133,8 @@ pub mod behaviour {
         pub swap_setup: alice::Behaviour<LR>,
         pub transfer_proof: transfer_proof::Behaviour,
         pub encrypted_signature: encrypted_signature::Behaviour,
+        pub early_refund: early_refund::Behaviour,
+        pub receipt: receipt::Behaviour,
 
         /// Ping behaviour that ensures that the underlying network connection
         /// is still alive. If the ping fails a connection close event
@@ -128,30 +152,33 @@ pub mod behaviour {
             latest_rate: LR,
             resume_only: bool,
             env_config: env::Config,
-            rendezvous_params: Option<(identity::Keypair, PeerId, Multiaddr, XmrBtcNamespace)>,
+            identity: identity::Keypair,
+            rendezvous_nodes: Vec<rendezous::RendezvousNode>,
+            key_pool: KeyPool,
         ) -> Self {
+            let peer_id = identity.public().into_peer_id();
+            let rendezvous = if rendezvous_nodes.is_empty() {
+                None
+            } else {
+                Some(rendezous::Behaviour::new(identity, rendezvous_nodes))
+            };
+
             Self {
-                rendezvous: libp2p::swarm::toggle::Toggle::from(rendezvous_params.map(
-                    |(identity, rendezvous_peer_id, rendezvous_address, namespace)| {
-                        rendezous::Behaviour::new(
-                            identity,
-                            rendezvous_peer_id,
-                            rendezvous_address,
-                            namespace,
-                            None, // use default ttl on rendezvous point
-                        )
-                    },
-                )),
+                rendezvous: libp2p::swarm::toggle::Toggle::from(rendezvous),
                 quote: quote::asb(),
                 swap_setup: alice::Behaviour::new(
+                    peer_id,
                     min_buy,
                     max_buy,
                     env_config,
                     latest_rate,
                     resume_only,
+                    key_pool,
                 ),
                 transfer_proof: transfer_proof::alice(),
                 encrypted_signature: encrypted_signature::alice(),
+                early_refund: early_refund::alice(),
+                receipt: receipt::alice(),
                 ping: Ping::new(PingConfig::new().with_keep_alive(true)),
             }
         }
@@ -191,42 +218,55 @@ pub mod rendezous {
 
     pub struct Behaviour {
         inner: libp2p::rendezvous::Rendezvous,
-        rendezvous_point: Multiaddr,
-        rendezvous_peer_id: PeerId,
-        namespace: XmrBtcNamespace,
-        registration_status: RegistrationStatus,
+        rendezvous_nodes: Vec<RendezvousNode>,
+    }
+
+    /// A node running the rendezvous server protocol.
+    pub struct RendezvousNode {
+        pub address: Multiaddr,
         connection_status: ConnectionStatus,
-        registration_ttl: Option<u64>,
+        pub peer_id: PeerId,
+        registration_status: RegistrationStatus,
+        pub registration_ttl: Option<u64>,
+        pub namespace: XmrBtcNamespace,
     }
 
-    impl Behaviour {
+    impl RendezvousNode {
         pub fn new(
-            identity: identity::Keypair,
-            rendezvous_peer_id: PeerId,
-            rendezvous_address: Multiaddr,
+            address: &Multiaddr,
+            peer_id: PeerId,
             namespace: XmrBtcNamespace,
             registration_ttl: Option<u64>,
         ) -> Self {
             Self {
-                inner: libp2p::rendezvous::Rendezvous::new(
-                    identity,
-                    libp2p::rendezvous::Config::default(),
-                ),
-                rendezvous_point: rendezvous_address,
-                rendezvous_peer_id,
+                address: address.to_owned(),
+                connection_status: ConnectionStatus::Disconnected,
                 namespace,
+                peer_id,
                 registration_status: RegistrationStatus::RegisterOnNextConnection,
-                connection_status: ConnectionStatus::Disconnected,
                 registration_ttl,
             }
         }
 
-        fn register(&mut self) {
-            self.inner.register(
-                self.namespace.into(),
-                self.rendezvous_peer_id,
-                self.registration_ttl,
-            );
+        fn set_connection(&mut self, status: ConnectionStatus) {
+            self.connection_status = status;
+        }
+
+        fn register(&mut self, inner: &mut libp2p::rendezvous::Rendezvous) {
+            inner.register(self.namespace.into(), self.peer_id, self.registration_ttl);
+            self.registration_status = RegistrationStatus::Pending;
+        }
+    }
+
+    impl Behaviour {
+        pub fn new(identity: identity::Keypair, rendezvous_nodes: Vec<RendezvousNode>) -> Self {
+            Self {
+                inner: libp2p::rendezvous::Rendezvous::new(
+                    identity,
+                    libp2p::rendezvous::Config::default(),
+                ),
+                rendezvous_nodes,
+            }
         }
     }
 
@@ -240,21 +280,24 @@ pub mod rendezous {
         }
 
         fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
-            if peer_id == &self.rendezvous_peer_id {
-                return vec![self.rendezvous_point.clone()];
-            }
-
-            vec![]
+            self.rendezvous_nodes
+                .iter()
+                .filter(|node| &node.peer_id == peer_id)
+                .map(|node| node.address.clone())
+                .collect()
         }
 
         fn inject_connected(&mut self, peer_id: &PeerId) {
-            if peer_id == &self.rendezvous_peer_id {
-                self.connection_status = ConnectionStatus::Connected;
+            for node in self
+                .rendezvous_nodes
+                .iter_mut()
+                .filter(|node| &node.peer_id == peer_id)
+            {
+                node.set_connection(ConnectionStatus::Connected);
 
-                match &self.registration_status {
+                match &node.registration_status {
                     RegistrationStatus::RegisterOnNextConnection => {
-                        self.register();
-                        self.registration_status = RegistrationStatus::Pending;
+                        node.register(&mut self.inner);
                     }
                     RegistrationStatus::Registered { .. } => {}
                     RegistrationStatus::Pending => {}
@@ -263,8 +306,12 @@ pub mod rendezous {
         }
 
         fn inject_disconnected(&mut self, peer_id: &PeerId) {
-            if peer_id == &self.rendezvous_peer_id {
-                self.connection_status = ConnectionStatus::Disconnected;
+            for node in self
+                .rendezvous_nodes
+                .iter_mut()
+                .filter(|node| &node.peer_id == peer_id)
+            {
+                node.set_connection(ConnectionStatus::Disconnected);
             }
         }
 
@@ -278,64 +325,80 @@ pub mod rendezous {
         }
 
         fn inject_dial_failure(&mut self, peer_id: &PeerId) {
-            if peer_id == &self.rendezvous_peer_id {
-                self.connection_status = ConnectionStatus::Disconnected;
+            for node in self
+                .rendezvous_nodes
+                .iter_mut()
+                .filter(|node| &node.peer_id == peer_id)
+            {
+                node.set_connection(ConnectionStatus::Disconnected);
             }
         }
 
         #[allow(clippy::type_complexity)]
         fn poll(&mut self, cx: &mut std::task::Context<'_>, params: &mut impl PollParameters) -> Poll<NetworkBehaviourAction<<<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent, Self::OutEvent>>{
-            match &mut self.registration_status {
-                RegistrationStatus::RegisterOnNextConnection => match self.connection_status {
-                    ConnectionStatus::Disconnected => {
-                        self.connection_status = ConnectionStatus::Dialling;
-
-                        return Poll::Ready(NetworkBehaviourAction::DialPeer {
-                            peer_id: self.rendezvous_peer_id,
-                            condition: DialPeerCondition::Disconnected,
-                        });
-                    }
-                    ConnectionStatus::Dialling => {}
-                    ConnectionStatus::Connected => {
-                        self.registration_status = RegistrationStatus::Pending;
-                        self.register();
-                    }
-                },
-                RegistrationStatus::Registered { re_register_in } => {
-                    if let Poll::Ready(()) = re_register_in.poll_unpin(cx) {
-                        match self.connection_status {
-                            ConnectionStatus::Connected => {
-                                self.registration_status = RegistrationStatus::Pending;
-                                self.register();
-                            }
-                            ConnectionStatus::Disconnected => {
-                                self.registration_status =
-                                    RegistrationStatus::RegisterOnNextConnection;
-
-                                return Poll::Ready(NetworkBehaviourAction::DialPeer {
-                                    peer_id: self.rendezvous_peer_id,
-                                    condition: DialPeerCondition::Disconnected,
-                                });
+            for node in self.rendezvous_nodes.iter_mut() {
+                let peer_id = node.peer_id;
+
+                match &mut node.registration_status {
+                    RegistrationStatus::RegisterOnNextConnection => match node.connection_status {
+                        ConnectionStatus::Disconnected => {
+                            node.set_connection(ConnectionStatus::Dialling);
+
+                            return Poll::Ready(NetworkBehaviourAction::DialPeer {
+                                peer_id,
+                                condition: DialPeerCondition::Disconnected,
+                            });
+                        }
+                        ConnectionStatus::Dialling => {}
+                        ConnectionStatus::Connected => {
+                            node.register(&mut self.inner);
+                        }
+                    },
+                    RegistrationStatus::Registered { re_register_in } => {
+                        if let Poll::Ready(()) = re_register_in.poll_unpin(cx) {
+                            match node.connection_status {
+                                ConnectionStatus::Connected => {
+                                    node.register(&mut self.inner);
+                                }
+                                ConnectionStatus::Disconnected => {
+                                    node.registration_status =
+                                        RegistrationStatus::RegisterOnNextConnection;
+
+                                    return Poll::Ready(NetworkBehaviourAction::DialPeer {
+                                        peer_id,
+                                        condition: DialPeerCondition::Disconnected,
+                                    });
+                                }
+                                ConnectionStatus::Dialling => {}
                             }
-                            ConnectionStatus::Dialling => {}
                         }
                     }
+                    RegistrationStatus::Pending => {}
                 }
-                RegistrationStatus::Pending => {}
             }
 
             let inner_poll = self.inner.poll(cx, params);
 
-            // reset the timer if we successfully registered
+            // reset the timer for the node we successfully registered with
             if let Poll::Ready(NetworkBehaviourAction::GenerateEvent(
-                libp2p::rendezvous::Event::Registered { ttl, .. },
+                libp2p::rendezvous::Event::Registered {
+                    rendezvous_node,
+                    ttl,
+                    ..
+                },
             )) = &inner_poll
             {
-                let half_of_ttl = Duration::from_secs(*ttl) / 2;
-
-                self.registration_status = RegistrationStatus::Registered {
-                    re_register_in: Box::pin(tokio::time::sleep(half_of_ttl)),
-                };
+                if let Some(node) = self
+                    .rendezvous_nodes
+                    .iter_mut()
+                    .find(|node| &node.peer_id == rendezvous_node)
+                {
+                    let half_of_ttl = Duration::from_secs(*ttl) / 2;
+
+                    node.registration_status = RegistrationStatus::Registered {
+                        re_register_in: Box::pin(tokio::time::sleep(half_of_ttl)),
+                    };
+                }
             }
 
             inner_poll
@@ -358,13 +421,12 @@ pub mod rendezous {
             let rendezvous_address = rendezvous_node.listen_on_random_memory_address().await;
 
             let mut asb = new_swarm(|_, identity| {
-                rendezous::Behaviour::new(
-                    identity,
+                rendezous::Behaviour::new(identity, vec![RendezvousNode::new(
+                    &rendezvous_address,
                     *rendezvous_node.local_peer_id(),
-                    rendezvous_address,
                     XmrBtcNamespace::Testnet,
                     None,
-                )
+                )])
             });
             asb.listen_on_random_memory_address().await; // this adds an external address
 
@@ -401,13 +463,12 @@ pub mod rendezous {
             let rendezvous_address = rendezvous_node.listen_on_random_memory_address().await;
 
             let mut asb = new_swarm(|_, identity| {
-                rendezous::Behaviour::new(
-                    identity,
+                rendezous::Behaviour::new(identity, vec![RendezvousNode::new(
+                    &rendezvous_address,
                     *rendezvous_node.local_peer_id(),
-                    rendezvous_address,
                     XmrBtcNamespace::Testnet,
                     Some(5),
-                )
+                )])
             });
             asb.listen_on_random_memory_address().await; // this adds an external address
 
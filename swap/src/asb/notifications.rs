@@ -0,0 +1,674 @@
+//! Notifying the operator about swaps and the health of the ASB.
+//!
+//! Events are sent to every configured [`Sink`]. A failing sink only logs a
+//! warning, notifications are never retried and never block a swap.
+
+use crate::asb::config;
+use crate::asb::metrics::METRICS;
+use crate::database::{Alice, Database};
+use crate::protocol::alice::AliceState;
+use crate::{bitcoin, monero};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use url::Url;
+use uuid::Uuid;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    SwapCompleted,
+    RefundNeeded,
+    PunishExecuted,
+    BalanceLow,
+    PriceFeedDown,
+}
+
+impl EventKind {
+    pub fn all() -> Vec<Self> {
+        vec![
+            EventKind::SwapCompleted,
+            EventKind::RefundNeeded,
+            EventKind::PunishExecuted,
+            EventKind::BalanceLow,
+            EventKind::PriceFeedDown,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The Bitcoin of the swap was redeemed.
+    SwapCompleted {
+        swap_id: Uuid,
+    },
+    /// The swap stopped with an error while its Monero was locked, the
+    /// Monero has to be recovered manually.
+    RefundNeeded {
+        swap_id: Uuid,
+        state: String,
+    },
+    PunishExecuted {
+        swap_id: Uuid,
+    },
+    BitcoinBalanceLow {
+        balance: bitcoin::Amount,
+        threshold: bitcoin::Amount,
+    },
+    MoneroBalanceLow {
+        balance: monero::Amount,
+        threshold: monero::Amount,
+    },
+    PriceFeedDown {
+        minutes: u64,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::SwapCompleted { .. } => EventKind::SwapCompleted,
+            Event::RefundNeeded { .. } => EventKind::RefundNeeded,
+            Event::PunishExecuted { .. } => EventKind::PunishExecuted,
+            Event::BitcoinBalanceLow { .. } | Event::MoneroBalanceLow { .. } => {
+                EventKind::BalanceLow
+            }
+            Event::PriceFeedDown { .. } => EventKind::PriceFeedDown,
+        }
+    }
+
+    pub fn swap_id(&self) -> Option<Uuid> {
+        match self {
+            Event::SwapCompleted { swap_id }
+            | Event::RefundNeeded { swap_id, .. }
+            | Event::PunishExecuted { swap_id } => Some(*swap_id),
+            _ => None,
+        }
+    }
+
+    pub fn subject(&self) -> &'static str {
+        match self.kind() {
+            EventKind::SwapCompleted => "Swap completed",
+            EventKind::RefundNeeded => "Refund needed",
+            EventKind::PunishExecuted => "Punish executed",
+            EventKind::BalanceLow => "Wallet balance low",
+            EventKind::PriceFeedDown => "Price feed down",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::SwapCompleted { swap_id } => {
+                write!(f, "Swap {} completed, the Bitcoin was redeemed.", swap_id)
+            }
+            Event::RefundNeeded { swap_id, state } => write!(
+                f,
+                "Swap {} stopped in state {} while its Monero is locked. Recover it with `asb cancel` and `asb refund`, or resume it by restarting the ASB.",
+                swap_id, state
+            ),
+            Event::PunishExecuted { swap_id } => write!(
+                f,
+                "Swap {} was punished, the taker did not refund in time.",
+                swap_id
+            ),
+            Event::BitcoinBalanceLow { balance, threshold } => write!(
+                f,
+                "The Bitcoin balance of {} is below {}.",
+                balance, threshold
+            ),
+            Event::MoneroBalanceLow { balance, threshold } => write!(
+                f,
+                "The Monero balance of {} is below {}.",
+                balance, threshold
+            ),
+            Event::PriceFeedDown { minutes } => write!(
+                f,
+                "No price update was received for {} minutes, quotes cannot be made.",
+                minutes
+            ),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn send(&self, event: &Event) -> Result<()>;
+}
+
+/// Sends events to all sinks, unless their kind is filtered out.
+///
+/// A disabled notifier has no sinks and drops all events.
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct Notifier {
+    sinks: Vec<Arc<dyn Sink>>,
+    events: Arc<HashSet<EventKind>>,
+}
+
+impl Notifier {
+    pub fn new(config: &config::Notifications) -> Result<Self> {
+        let mut sinks = Vec::<Arc<dyn Sink>>::new();
+
+        if let Some(webhook) = &config.webhook {
+            sinks.push(Arc::new(Webhook::new(webhook.url.clone())));
+        }
+        if let Some(smtp) = &config.smtp {
+            sinks.push(Arc::new(Smtp::new(smtp.clone())));
+        }
+        if let Some(telegram) = &config.telegram {
+            sinks.push(Arc::new(Telegram::new(telegram.clone())));
+        }
+
+        if sinks.is_empty() {
+            bail!("Notifications are configured without a webhook, SMTP or Telegram sink")
+        }
+
+        Ok(Self {
+            sinks,
+            events: Arc::new(config.events.iter().copied().collect()),
+        })
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            sinks: Vec::new(),
+            events: Arc::new(HashSet::new()),
+        }
+    }
+
+    pub fn notify(&self, event: Event) {
+        if !self.events.contains(&event.kind()) {
+            return;
+        }
+
+        tracing::debug!(kind = ?event.kind(), "Sending notification");
+
+        for sink in &self.sinks {
+            let sink = sink.clone();
+            let event = event.clone();
+
+            tokio::spawn(async move {
+                if let Err(error) = sink.send(&event).await {
+                    tracing::warn!(kind = ?event.kind(), "Failed to send notification: {:#}", error);
+                }
+            });
+        }
+    }
+
+    pub fn swap_finished(&self, swap_id: Uuid, state: &AliceState) {
+        match state {
+            AliceState::BtcRedeemed => self.notify(Event::SwapCompleted { swap_id }),
+            AliceState::BtcPunished => self.notify(Event::PunishExecuted { swap_id }),
+            _ => {}
+        }
+    }
+
+    /// Notifies that a refund is needed if the failed swap had locked its
+    /// Monero.
+    pub fn swap_failed(&self, db: &Database, swap_id: Uuid) {
+        let state = match db
+            .get_state(swap_id)
+            .and_then(|state| state.try_into_alice())
+        {
+            Ok(state) => state,
+            Err(error) => {
+                tracing::warn!(%swap_id, "Failed to load state of failed swap: {:#}", error);
+                return;
+            }
+        };
+
+        if xmr_locked(&state) {
+            self.notify(Event::RefundNeeded {
+                swap_id,
+                state: state.to_string(),
+            });
+        }
+    }
+}
+
+/// Whether the Monero of the swap is locked and not redeemed by the taker yet.
+fn xmr_locked(state: &Alice) -> bool {
+    match state {
+        Alice::XmrLockTransactionSent { .. }
+        | Alice::XmrLocked { .. }
+        | Alice::XmrLockTransferProofSent { .. }
+        | Alice::EncSigLearned { .. }
+        | Alice::CancelTimelockExpired { .. }
+        | Alice::BtcCancelled { .. }
+        | Alice::BtcPunishable { .. }
+        | Alice::BtcRefunded { .. } => true,
+        Alice::Started { .. }
+        | Alice::BtcLockTransactionSeen { .. }
+        | Alice::BtcLocked { .. }
+        | Alice::BtcEarlyRefundable { .. }
+        | Alice::BtcRedeemTransactionPublished { .. }
+        | Alice::Done(_) => false,
+    }
+}
+
+/// Thresholds of the periodic health checks.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub min_bitcoin_balance: Option<bitcoin::Amount>,
+    pub min_monero_balance: Option<monero::Amount>,
+    pub price_feed_down_after: Duration,
+}
+
+impl Thresholds {
+    pub fn from_config(config: &config::Notifications) -> Self {
+        Self {
+            min_bitcoin_balance: config.min_bitcoin_balance,
+            min_monero_balance: config.min_monero_balance,
+            price_feed_down_after: Duration::from_secs(config.price_feed_down_after_mins * 60),
+        }
+    }
+}
+
+/// Periodically checks the wallet balances and the price feed.
+///
+/// Every period in which a balance is low or the price feed is down is
+/// notified once.
+pub async fn watch_health(
+    notifier: Notifier,
+    thresholds: Thresholds,
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    monero_wallet: Arc<monero::Wallet>,
+) {
+    let started = Instant::now();
+    let mut bitcoin_balance_low = Alarm::default();
+    let mut monero_balance_low = Alarm::default();
+    let mut price_feed_down = Alarm::default();
+
+    loop {
+        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+
+        if let Some(threshold) = thresholds.min_bitcoin_balance {
+            match bitcoin_wallet.balance().await {
+                Ok(balance) if bitcoin_balance_low.update(balance < threshold) => {
+                    notifier.notify(Event::BitcoinBalanceLow { balance, threshold })
+                }
+                Ok(_) => {}
+                Err(error) => tracing::warn!("Failed to check Bitcoin balance: {:#}", error),
+            }
+        }
+
+        if let Some(threshold) = thresholds.min_monero_balance {
+            match monero_wallet.get_balance().await {
+                Ok(balance) if monero_balance_low.update(balance < threshold) => {
+                    notifier.notify(Event::MoneroBalanceLow { balance, threshold })
+                }
+                Ok(_) => {}
+                Err(error) => tracing::warn!("Failed to check Monero balance: {:#}", error),
+            }
+        }
+
+        // Without any update the feed is down since the start.
+        let age = METRICS
+            .price_update_age()
+            .unwrap_or_else(|| started.elapsed());
+        if price_feed_down.update(age > thresholds.price_feed_down_after) {
+            notifier.notify(Event::PriceFeedDown {
+                minutes: age.as_secs() / 60,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Alarm {
+    raised: bool,
+}
+
+impl Alarm {
+    /// Returns whether the condition started to hold with this update.
+    fn update(&mut self, condition: bool) -> bool {
+        let raise = condition && !self.raised;
+        self.raised = condition;
+
+        raise
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Payload {
+    event: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    swap_id: Option<Uuid>,
+    message: String,
+}
+
+impl From<&Event> for Payload {
+    fn from(event: &Event) -> Self {
+        Self {
+            event: event.kind(),
+            swap_id: event.swap_id(),
+            message: event.to_string(),
+        }
+    }
+}
+
+/// Posts every event as JSON to a URL.
+#[derive(Debug)]
+pub struct Webhook {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Webhook {
+    async fn send(&self, event: &Event) -> Result<()> {
+        let body = serde_json::to_vec(&Payload::from(event))?;
+
+        self.client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .context("Failed to call webhook")?
+            .error_for_status()
+            .context("Webhook rejected notification")?;
+
+        Ok(())
+    }
+}
+
+/// Sends every event as message through a Telegram bot.
+#[derive(Debug)]
+pub struct Telegram {
+    config: config::Telegram,
+    client: reqwest::Client,
+}
+
+impl Telegram {
+    pub fn new(config: config::Telegram) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Telegram {
+    async fn send(&self, event: &Event) -> Result<()> {
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.config.bot_token
+        );
+        let body = serde_json::to_vec(&serde_json::json!({
+            "chat_id": self.config.chat_id,
+            "text": format!("{}: {}", event.subject(), event),
+        }))?;
+
+        // Errors of reqwest contain the URL, which contains the bot token and
+        // must not end up in the logs.
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|_| anyhow!("Failed to call Telegram API"))?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Telegram API rejected notification with status {}",
+                response.status()
+            )
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends every event as mail through an SMTP server that accepts implicit
+/// TLS, usually on port 465.
+#[derive(Debug)]
+pub struct Smtp {
+    config: config::Smtp,
+}
+
+impl Smtp {
+    pub fn new(config: config::Smtp) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Sink for Smtp {
+    async fn send(&self, event: &Event) -> Result<()> {
+        let mut tls_config = ClientConfig::new();
+        tls_config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        let connector = TlsConnector::from(Arc::new(tls_config));
+
+        let host = DNSNameRef::try_from_ascii_str(&self.config.host)
+            .with_context(|| format!("Invalid SMTP host {}", self.config.host))?;
+        let tcp = TcpStream::connect((self.config.host.as_str(), self.config.port))
+            .await
+            .with_context(|| format!("Failed to connect to SMTP server {}", self.config.host))?;
+        let tls = connector
+            .connect(host, tcp)
+            .await
+            .context("TLS handshake with SMTP server failed")?;
+
+        send_mail(tls, &self.config, event).await
+    }
+}
+
+async fn send_mail<S>(stream: S, config: &config::Smtp, event: &Event) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+
+    expect_reply(&mut stream, 220).await?;
+    command(&mut stream, "EHLO localhost", 250).await?;
+
+    let credentials = base64::encode(format!("\0{}\0{}", config.username, config.password));
+    command(&mut stream, &format!("AUTH PLAIN {}", credentials), 235)
+        .await
+        .context("SMTP authentication failed")?;
+
+    command(&mut stream, &format!("MAIL FROM:<{}>", config.from), 250).await?;
+    for recipient in &config.to {
+        command(&mut stream, &format!("RCPT TO:<{}>", recipient), 250).await?;
+    }
+    command(&mut stream, "DATA", 354).await?;
+
+    let mail = mail(config, event);
+    stream.write_all(mail.as_bytes()).await?;
+    expect_reply(&mut stream, 250).await?;
+
+    command(&mut stream, "QUIT", 221).await?;
+
+    Ok(())
+}
+
+/// The mail including the terminating line, lines starting with a dot are
+/// escaped.
+fn mail(config: &config::Smtp, event: &Event) -> String {
+    let body = event
+        .to_string()
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}\r\n", line)
+            } else {
+                format!("{}\r\n", line)
+            }
+        })
+        .collect::<String>();
+
+    format!(
+        "From: <{}>\r\nTo: {}\r\nSubject: ASB: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}.\r\n",
+        config.from,
+        config
+            .to
+            .iter()
+            .map(|recipient| format!("<{}>", recipient))
+            .collect::<Vec<_>>()
+            .join(", "),
+        event.subject(),
+        body
+    )
+}
+
+async fn command<S>(stream: &mut BufReader<S>, line: &str, expected: u16) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    stream.flush().await?;
+
+    expect_reply(stream, expected).await
+}
+
+/// Reads a possibly multi-line reply and checks its code.
+async fn expect_reply<S>(stream: &mut BufReader<S>, expected: u16) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            bail!("SMTP server closed the connection")
+        }
+
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .with_context(|| format!("Invalid SMTP reply: {}", line.trim_end()))?;
+
+        if code != expected {
+            bail!("Unexpected SMTP reply: {}", line.trim_end())
+        }
+
+        // Continuation lines have a dash after the code.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn smtp_config() -> config::Smtp {
+        config::Smtp {
+            host: "smtp.example.com".to_owned(),
+            port: 465,
+            username: "asb".to_owned(),
+            password: "secret".to_owned(),
+            from: "asb@example.com".to_owned(),
+            to: vec!["operator@example.com".to_owned()],
+        }
+    }
+
+    #[test]
+    fn alarm_is_raised_once_per_period() {
+        let mut alarm = Alarm::default();
+
+        assert!(!alarm.update(false));
+        assert!(alarm.update(true));
+        assert!(!alarm.update(true));
+        assert!(!alarm.update(false));
+        assert!(alarm.update(true));
+    }
+
+    #[test]
+    fn webhook_payload_contains_kind_and_swap_id() {
+        let swap_id = Uuid::nil();
+        let payload =
+            serde_json::to_value(Payload::from(&Event::SwapCompleted { swap_id })).unwrap();
+
+        assert_eq!(payload["event"], "swap_completed");
+        assert_eq!(payload["swap_id"], swap_id.to_string());
+
+        let payload =
+            serde_json::to_value(Payload::from(&Event::PriceFeedDown { minutes: 5 })).unwrap();
+
+        assert_eq!(payload["event"], "price_feed_down");
+        assert!(payload.get("swap_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_mail_through_smtp_conversation() {
+        let (client, server) = tokio::io::duplex(4096);
+        let config = smtp_config();
+        let event = Event::PunishExecuted {
+            swap_id: Uuid::nil(),
+        };
+
+        let server = tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let mut received = String::new();
+            let replies = [
+                "220 smtp.example.com ESMTP\r\n",
+                "250-smtp.example.com\r\n250 AUTH PLAIN\r\n",
+                "235 Authenticated\r\n",
+                "250 OK\r\n",
+                "250 OK\r\n",
+                "354 Go ahead\r\n",
+                "250 Queued\r\n",
+                "221 Bye\r\n",
+            ];
+
+            for (index, reply) in replies.iter().enumerate() {
+                // The greeting is sent without a command, the mail is read
+                // up to its terminating line.
+                if index == 6 {
+                    while !received.ends_with("\r\n.\r\n") {
+                        server.read_line(&mut received).await.unwrap();
+                    }
+                } else if index > 0 {
+                    server.read_line(&mut received).await.unwrap();
+                }
+                server.write_all(reply.as_bytes()).await.unwrap();
+            }
+
+            let mut rest = String::new();
+            server.read_to_string(&mut rest).await.unwrap();
+
+            received
+        });
+
+        send_mail(client, &config, &event).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert!(received.contains(&format!(
+            "AUTH PLAIN {}\r\n",
+            base64::encode("\0asb\0secret")
+        )));
+        assert!(received.contains("RCPT TO:<operator@example.com>\r\n"));
+        assert!(received.contains("Subject: ASB: Punish executed\r\n"));
+        assert!(received.ends_with("\r\n.\r\nQUIT\r\n"));
+    }
+}
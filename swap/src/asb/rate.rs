@@ -1,4 +1,4 @@
-use crate::{bitcoin, monero};
+use crate::{bitcoin, kraken, monero};
 use anyhow::{Context, Result};
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
@@ -25,6 +25,16 @@ impl Rate {
         Self { ask, ask_spread }
     }
 
+    /// The market asking price for 1 XMR, before the spread is applied.
+    pub fn market_price(&self) -> bitcoin::Amount {
+        self.ask
+    }
+
+    /// The spread applied to the market asking price.
+    pub fn ask_spread(&self) -> Decimal {
+        self.ask_spread
+    }
+
     /// Computes the asking price at which we are willing to sell 1 XMR.
     ///
     /// This applies the spread to the market asking price.
@@ -79,6 +89,79 @@ impl Display for Rate {
     }
 }
 
+/// Cross-checks the market asking price against a secondary price feed.
+#[derive(Debug, Clone)]
+pub struct PriceGuard {
+    secondary: kraken::PriceUpdates,
+    /// The largest tolerated deviation between the feeds, relative to the
+    /// secondary price.
+    max_deviation: Decimal,
+}
+
+impl PriceGuard {
+    pub fn new(secondary: kraken::PriceUpdates, max_deviation: Decimal) -> Self {
+        Self {
+            secondary,
+            max_deviation,
+        }
+    }
+
+    /// Fails if the secondary feed has no price or its price diverges too
+    /// far from `primary`.
+    pub fn check(&mut self, primary: bitcoin::Amount) -> Result<(), RateUnavailable> {
+        let secondary = self
+            .secondary
+            .latest_update()
+            .map_err(RateUnavailable::SecondaryFeed)?
+            .ask;
+
+        check_deviation(primary, secondary, self.max_deviation)
+    }
+}
+
+fn check_deviation(
+    primary: bitcoin::Amount,
+    secondary: bitcoin::Amount,
+    max_deviation: Decimal,
+) -> Result<(), RateUnavailable> {
+    let deviation = if secondary == bitcoin::Amount::ZERO {
+        Decimal::MAX
+    } else {
+        let difference = if primary > secondary {
+            primary - secondary
+        } else {
+            secondary - primary
+        };
+
+        Decimal::from(difference.as_sat()) / Decimal::from(secondary.as_sat())
+    };
+
+    if deviation > max_deviation {
+        return Err(RateUnavailable::Diverging {
+            primary,
+            secondary,
+            deviation,
+        });
+    }
+
+    Ok(())
+}
+
+/// The reasons for not quoting a rate.
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum RateUnavailable {
+    #[error("Failed to get the rate from the price feed")]
+    PrimaryFeed(#[source] kraken::Error),
+    #[error("Failed to get the rate from the secondary price feed")]
+    SecondaryFeed(#[source] kraken::Error),
+    #[error("The price feeds diverge by {deviation}, the primary feed reports {primary} and the secondary feed {secondary}")]
+    Diverging {
+        primary: bitcoin::Amount,
+        secondary: bitcoin::Amount,
+        deviation: Decimal,
+    },
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,4 +213,37 @@ mod tests {
                                                          // it is really close
                                                          // to two percent
     }
+
+    #[test]
+    fn refuses_rate_if_feeds_diverge_too_far() {
+        let max_deviation = TWO_PERCENT;
+
+        assert!(check_deviation(
+            bitcoin::Amount::from_sat(10_200),
+            bitcoin::Amount::from_sat(10_000),
+            max_deviation
+        )
+        .is_ok());
+        assert!(check_deviation(
+            bitcoin::Amount::from_sat(9_800),
+            bitcoin::Amount::from_sat(10_000),
+            max_deviation
+        )
+        .is_ok());
+
+        assert!(matches!(
+            check_deviation(
+                bitcoin::Amount::from_sat(10_201),
+                bitcoin::Amount::from_sat(10_000),
+                max_deviation
+            ),
+            Err(RateUnavailable::Diverging { .. })
+        ));
+        assert!(check_deviation(
+            bitcoin::Amount::from_sat(10_000),
+            bitcoin::Amount::ZERO,
+            max_deviation
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,211 @@
+//! Limiting how often peers can make requests that are expensive for us to
+//! answer and how many connections they can keep open.
+
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Limits the number of requests per peer and per IP address within a sliding
+/// window.
+///
+/// Connections from a loopback address are only limited per peer, because
+/// all peers connecting through Tor share the address of the local Tor
+/// daemon.
+#[derive(Debug)]
+pub struct RequestLimits {
+    per_peer: RateLimiter<PeerId>,
+    per_ip: RateLimiter<IpAddr>,
+}
+
+impl RequestLimits {
+    pub fn new(max_per_peer: usize, max_per_ip: usize, window: Duration) -> Self {
+        Self {
+            per_peer: RateLimiter::new(max_per_peer, window),
+            per_ip: RateLimiter::new(max_per_ip, window),
+        }
+    }
+
+    /// Records a request of the peer, returns `false` if the peer or its IP
+    /// address exceeded its limit.
+    pub fn allow(&mut self, peer: PeerId, ip: Option<IpAddr>, now: Instant) -> bool {
+        let ip_allowed = match ip {
+            Some(ip) if !ip.is_loopback() => self.per_ip.allow(ip, now),
+            _ => true,
+        };
+
+        ip_allowed && self.per_peer.allow(peer, now)
+    }
+}
+
+#[derive(Debug)]
+struct RateLimiter<K> {
+    max_requests: usize,
+    window: Duration,
+    requests: HashMap<K, VecDeque<Instant>>,
+    next_cleanup: Option<Instant>,
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Eq + Hash,
+{
+    fn new(max_requests: usize, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            requests: HashMap::new(),
+            next_cleanup: None,
+        }
+    }
+
+    fn allow(&mut self, key: K, now: Instant) -> bool {
+        self.forget_inactive_keys(now);
+
+        let window = self.window;
+        let requests = self.requests.entry(key).or_default();
+        forget_expired(requests, window, now);
+
+        if requests.len() >= self.max_requests {
+            return false;
+        }
+
+        requests.push_back(now);
+        true
+    }
+
+    /// Keeps the map from growing with every key we ever saw.
+    fn forget_inactive_keys(&mut self, now: Instant) {
+        if matches!(self.next_cleanup, Some(next_cleanup) if now < next_cleanup) {
+            return;
+        }
+
+        let window = self.window;
+        self.requests.retain(|_, requests| {
+            forget_expired(requests, window, now);
+            !requests.is_empty()
+        });
+        self.next_cleanup = Some(now + window);
+    }
+}
+
+/// Limits the number of established connections per IP address.
+///
+/// Like [`RequestLimits`], connections from a loopback address are not
+/// limited.
+#[derive(Debug)]
+pub struct ConnectionsPerIp {
+    max_connections: u32,
+    connections: HashMap<IpAddr, u32>,
+}
+
+impl ConnectionsPerIp {
+    pub fn new(max_connections: u32) -> Self {
+        Self {
+            max_connections,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// Records a new connection, returns `false` if the IP address exceeded
+    /// its limit.
+    pub fn connected(&mut self, ip: IpAddr) -> bool {
+        if ip.is_loopback() {
+            return true;
+        }
+
+        let connections = self.connections.entry(ip).or_default();
+        *connections += 1;
+
+        *connections <= self.max_connections
+    }
+
+    pub fn disconnected(&mut self, ip: IpAddr) {
+        if let Some(connections) = self.connections.get_mut(&ip) {
+            *connections = connections.saturating_sub(1);
+
+            if *connections == 0 {
+                self.connections.remove(&ip);
+            }
+        }
+    }
+}
+
+fn forget_expired(requests: &mut VecDeque<Instant>, window: Duration, now: Instant) {
+    while matches!(requests.front(), Some(request) if now.duration_since(*request) >= window) {
+        requests.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn limits_requests_per_peer_within_window() {
+        let mut limits = RequestLimits::new(2, 10, WINDOW);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert!(limits.allow(peer, None, now));
+        assert!(limits.allow(peer, None, now));
+        assert!(!limits.allow(peer, None, now));
+        assert!(limits.allow(PeerId::random(), None, now));
+
+        assert!(limits.allow(peer, None, now + WINDOW));
+    }
+
+    #[test]
+    fn limits_requests_per_ip_across_peers() {
+        let mut limits = RequestLimits::new(10, 2, WINDOW);
+        let ip = "203.0.113.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limits.allow(PeerId::random(), Some(ip), now));
+        assert!(limits.allow(PeerId::random(), Some(ip), now));
+        assert!(!limits.allow(PeerId::random(), Some(ip), now));
+    }
+
+    #[test]
+    fn does_not_limit_loopback_address_across_peers() {
+        let mut limits = RequestLimits::new(10, 1, WINDOW);
+        let localhost = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limits.allow(PeerId::random(), Some(localhost), now));
+        assert!(limits.allow(PeerId::random(), Some(localhost), now));
+    }
+
+    #[test]
+    fn limits_connections_per_ip() {
+        let mut connections = ConnectionsPerIp::new(2);
+        let ip = "203.0.113.1".parse().unwrap();
+        let localhost = "127.0.0.1".parse().unwrap();
+
+        assert!(connections.connected(ip));
+        assert!(connections.connected(ip));
+        assert!(!connections.connected(ip));
+
+        connections.disconnected(ip);
+        connections.disconnected(ip);
+        assert!(connections.connected(ip));
+
+        for _ in 0..3 {
+            assert!(connections.connected(localhost));
+        }
+    }
+
+    #[test]
+    fn forgets_inactive_peers() {
+        let mut limiter = RateLimiter::new(1, WINDOW);
+        let now = Instant::now();
+
+        assert!(limiter.allow(PeerId::random(), now));
+        assert!(limiter.allow(PeerId::random(), now + WINDOW));
+
+        assert_eq!(limiter.requests.len(), 1);
+    }
+}
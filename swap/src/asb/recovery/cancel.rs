@@ -24,7 +24,8 @@ pub async fn cancel(
         // In case no XMR has been locked, move to Safely Aborted
         AliceState::Started { .. }
         | AliceState::BtcLockTransactionSeen { .. }
-        | AliceState::BtcLocked { .. } => bail!("Cannot cancel swap {} because it is in state {} where no XMR was locked.", swap_id, state),
+        | AliceState::BtcLocked { .. }
+        | AliceState::BtcEarlyRefundable { .. } => bail!("Cannot cancel swap {} because it is in state {} where no XMR was locked.", swap_id, state),
 
         AliceState::XmrLockTransactionSent { monero_wallet_restore_blockheight, transfer_proof, state3,  }
         | AliceState::XmrLocked { monero_wallet_restore_blockheight, transfer_proof, state3 }
@@ -47,6 +48,7 @@ pub async fn cancel(
         | AliceState::BtcRedeemed
         | AliceState::XmrRefunded
         | AliceState::BtcPunished
+        | AliceState::BtcEarlyRefunded
         | AliceState::SafelyAborted => bail!("Swap is is in state {} which is not cancelable", state),
     };
 
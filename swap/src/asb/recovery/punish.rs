@@ -53,6 +53,9 @@ pub async fn punish(
             // If the swap was refunded it cannot be punished
             AliceState::BtcRedeemTransactionPublished { .. }
             | AliceState::BtcRefunded {..}
+            // Bob was allowed to refund early, punishing him would take his Bitcoin for nothing
+            | AliceState::BtcEarlyRefundable { .. }
+            | AliceState::BtcEarlyRefunded
             // Alice already in final state
             | AliceState::BtcRedeemed
             | AliceState::XmrRefunded
@@ -70,9 +73,11 @@ pub async fn punish(
             }
 
             AliceState::BtcRefunded { .. }
+            | AliceState::BtcEarlyRefundable { .. }
             | AliceState::BtcRedeemed
             | AliceState::XmrRefunded
             | AliceState::BtcPunished
+            | AliceState::BtcEarlyRefunded
             | AliceState::SafelyAborted => bail!(Error::SwapNotPunishable(state)),
 
             _ => return Ok(Err(Error::SwapNotCancelled)),
@@ -86,6 +86,7 @@ pub async fn redeem(
         AliceState::Started { .. }
         | AliceState::BtcLockTransactionSeen { .. }
         | AliceState::BtcLocked { .. }
+        | AliceState::BtcEarlyRefundable { .. }
         | AliceState::XmrLockTransactionSent { .. }
         | AliceState::XmrLocked { .. }
         | AliceState::XmrLockTransferProofSent { .. }
@@ -96,6 +97,7 @@ pub async fn redeem(
         | AliceState::BtcRedeemed
         | AliceState::XmrRefunded
         | AliceState::BtcPunished
+        | AliceState::BtcEarlyRefunded
         | AliceState::SafelyAborted => bail!(
             "Cannot redeem swap {} because it is in state {} which cannot be manually redeemed",
             swap_id,
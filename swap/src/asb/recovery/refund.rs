@@ -40,7 +40,8 @@ pub async fn refund(
             // In case no XMR has been locked, move to Safely Aborted
             AliceState::Started { .. }
             | AliceState::BtcLockTransactionSeen { .. }
-            | AliceState::BtcLocked { .. } => bail!(Error::NoXmrLocked(state)),
+            | AliceState::BtcLocked { .. }
+            | AliceState::BtcEarlyRefundable { .. } => bail!(Error::NoXmrLocked(state)),
 
             // Refund potentially possible (no knowledge of cancel transaction)
             AliceState::XmrLockTransactionSent { monero_wallet_restore_blockheight, transfer_proof, state3, }
@@ -61,13 +62,14 @@ pub async fn refund(
             | AliceState::BtcRedeemed
             | AliceState::XmrRefunded
             | AliceState::BtcPunished
+            | AliceState::BtcEarlyRefunded
             | AliceState::SafelyAborted => bail!(Error::SwapNotRefundable(state)),
         }
     } else {
         match state {
-            AliceState::Started { .. } | AliceState::BtcLocked { .. } => {
-                bail!(Error::NoXmrLocked(state))
-            }
+            AliceState::Started { .. }
+            | AliceState::BtcLocked { .. }
+            | AliceState::BtcEarlyRefundable { .. } => bail!(Error::NoXmrLocked(state)),
 
             AliceState::BtcCancelled {
                 monero_wallet_restore_blockheight,
@@ -90,6 +92,7 @@ pub async fn refund(
             AliceState::BtcRedeemed
             | AliceState::XmrRefunded
             | AliceState::BtcPunished
+            | AliceState::BtcEarlyRefunded
             | AliceState::SafelyAborted => bail!(Error::SwapNotRefundable(state)),
 
             _ => return Ok(Err(Error::SwapNotCancelled)),
@@ -10,7 +10,8 @@ pub async fn safely_abort(swap_id: Uuid, db: Arc<Database>) -> Result<AliceState
     match state {
         AliceState::Started { .. }
         | AliceState::BtcLockTransactionSeen { .. }
-        | AliceState::BtcLocked { .. } => {
+        | AliceState::BtcLocked { .. }
+        | AliceState::BtcEarlyRefundable { .. } => {
             let state = AliceState::SafelyAborted;
 
             let db_state = (&state).into();
@@ -32,6 +33,7 @@ pub async fn safely_abort(swap_id: Uuid, db: Arc<Database>) -> Result<AliceState
         | AliceState::BtcRedeemed
         | AliceState::XmrRefunded
         | AliceState::BtcPunished
+        | AliceState::BtcEarlyRefunded
         | AliceState::SafelyAborted => bail!(
             "Cannot safely abort swap {} because it is in state {} which cannot be safely aborted",
             swap_id,
@@ -0,0 +1,152 @@
+//! Keeping track of peers that misbehave and banning them for a while once
+//! they do so too often.
+//!
+//! Only misbehavior that is clearly the peer's fault is recorded, e.g. a swap
+//! setup that failed because our wallets were unavailable is not. Peers below
+//! the thresholds are treated like any other peer.
+
+use crate::receipt::unix_timestamp;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// Peers that abort this many swap setups are banned.
+const MAX_ABORTED_SETUPS: u32 = 10;
+/// Peers that send this many invalid messages are banned.
+const MAX_INVALID_MESSAGES: u32 = 5;
+/// Peers that do not lock their Bitcoin in time for this many swaps are
+/// banned. Every such swap ties up our Monero until the lock times out.
+const MAX_LOCK_TIMEOUTS: u32 = 3;
+/// How long a ban lasts, the misbehavior recorded so far is forgotten once it
+/// expired.
+pub const BAN_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// The peer aborted the swap setup or it failed because of them.
+    AbortedSetup,
+    /// The peer sent a message that is not valid in the protocol, e.g. an
+    /// encrypted signature for a swap of another peer.
+    InvalidMessage,
+    /// The peer did not lock their Bitcoin before the lock timed out.
+    ///
+    /// Swaps we abort for other reasons, e.g. manually or because we were
+    /// offline until the cancel timelock expired, do not count.
+    LockTimeout,
+}
+
+/// The misbehavior of a peer recorded so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub aborted_setups: u32,
+    pub invalid_messages: u32,
+    pub lock_timeouts: u32,
+    /// Unix timestamp until which the peer is banned.
+    #[serde(default)]
+    pub banned_until: Option<u64>,
+}
+
+impl PeerStats {
+    /// Records the misbehavior, banning the peer for [`BAN_DURATION`] if it
+    /// exceeds a threshold.
+    ///
+    /// A ban can be lifted earlier by the operator.
+    pub fn record(&mut self, misbehavior: Misbehavior, now: SystemTime) {
+        if self.ban_expired(now) {
+            *self = PeerStats::default();
+        }
+
+        match misbehavior {
+            Misbehavior::AbortedSetup => {
+                self.aborted_setups = self.aborted_setups.saturating_add(1)
+            }
+            Misbehavior::InvalidMessage => {
+                self.invalid_messages = self.invalid_messages.saturating_add(1)
+            }
+            Misbehavior::LockTimeout => self.lock_timeouts = self.lock_timeouts.saturating_add(1),
+        }
+
+        if self.banned_until.is_none() && self.exceeds_thresholds() {
+            self.banned_until = Some(unix_timestamp(now + BAN_DURATION));
+        }
+    }
+
+    pub fn is_banned(&self, now: SystemTime) -> bool {
+        self.ban_remaining(now).is_some()
+    }
+
+    /// How long the ban lasts from now on, `None` if the peer is not banned.
+    pub fn ban_remaining(&self, now: SystemTime) -> Option<Duration> {
+        let remaining = self.banned_until?.checked_sub(unix_timestamp(now))?;
+
+        if remaining == 0 {
+            return None;
+        }
+
+        Some(Duration::from_secs(remaining))
+    }
+
+    fn ban_expired(&self, now: SystemTime) -> bool {
+        self.banned_until.is_some() && !self.is_banned(now)
+    }
+
+    fn exceeds_thresholds(&self) -> bool {
+        self.aborted_setups >= MAX_ABORTED_SETUPS
+            || self.invalid_messages >= MAX_INVALID_MESSAGES
+            || self.lock_timeouts >= MAX_LOCK_TIMEOUTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_peer_once_a_threshold_is_reached() {
+        let now = SystemTime::now();
+        let mut stats = PeerStats::default();
+
+        for _ in 1..MAX_LOCK_TIMEOUTS {
+            stats.record(Misbehavior::LockTimeout, now);
+        }
+        assert!(!stats.is_banned(now));
+
+        stats.record(Misbehavior::LockTimeout, now);
+        assert!(stats.is_banned(now));
+        assert_eq!(stats.lock_timeouts, MAX_LOCK_TIMEOUTS);
+    }
+
+    #[test]
+    fn counts_misbehavior_separately() {
+        let now = SystemTime::now();
+        let mut stats = PeerStats::default();
+
+        stats.record(Misbehavior::AbortedSetup, now);
+        stats.record(Misbehavior::InvalidMessage, now);
+        stats.record(Misbehavior::LockTimeout, now);
+
+        assert_eq!(stats, PeerStats {
+            aborted_setups: 1,
+            invalid_messages: 1,
+            lock_timeouts: 1,
+            banned_until: None
+        });
+    }
+
+    #[test]
+    fn ban_expires_and_misbehavior_is_forgotten() {
+        let now = SystemTime::now();
+        let mut stats = PeerStats::default();
+
+        for _ in 0..MAX_INVALID_MESSAGES {
+            stats.record(Misbehavior::InvalidMessage, now);
+        }
+        assert_eq!(stats.ban_remaining(now), Some(BAN_DURATION));
+
+        let later = now + BAN_DURATION;
+        assert!(!stats.is_banned(later));
+
+        stats.record(Misbehavior::InvalidMessage, later);
+        assert!(!stats.is_banned(later));
+        assert_eq!(stats.invalid_messages, 1);
+    }
+}
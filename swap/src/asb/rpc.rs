@@ -0,0 +1,896 @@
+//! A JSON-RPC 2.0 API to monitor and control a running ASB.
+//!
+//! Requests are sent as HTTP `POST` with the JSON-RPC request as body. Every
+//! request has to carry the token of the cookie file written on startup as
+//! bearer token, and is rejected if it is not JSON or its `Host` or `Origin`
+//! header names a host the server is not reachable under, which prevents
+//! websites from using the browser of the operator to call the API.
+//!
+//! If enabled, the same server serves a dashboard under `/`, a static page that
+//! shows the data of the API. It is subject to the same host check as the API
+//! and calls the API with the token given in the fragment of its address.
+
+use crate::asb::metrics::METRICS;
+use crate::asb::reputation::PeerStats;
+use crate::asb::withdraw::{withdraw_btc, withdraw_xmr};
+use crate::asb::{KrakenRate, LatestRate, PeerBans, QuoteSwitch};
+use crate::bitcoin::{CancelTimelock, PunishTimelock, Txid};
+use crate::database::{Alice, AliceEndState, Database};
+use crate::monero::wallet::SyncProgress;
+use crate::{bitcoin, monero};
+use anyhow::{bail, Context, Result};
+use hyper::header::{
+    HeaderMap, AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN, WWW_AUTHENTICATE, X_FRAME_OPTIONS,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use libp2p::PeerId;
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::{serde_as, DisplayFromStr};
+use std::convert::Infallible;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const JSONRPC_VERSION: &str = "2.0";
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Name of the file in the data directory that holds the token of the API.
+pub const COOKIE_FILE: &str = "rpc.cookie";
+
+/// Credentials a request needs to be served.
+#[derive(Debug, Clone)]
+pub struct Auth {
+    token: String,
+    allowed_hosts: Vec<String>,
+}
+
+impl Auth {
+    /// Generates a new token and writes it to the cookie file, replacing the
+    /// token of a previous run.
+    ///
+    /// Besides the listen address, requests may name `localhost` and the
+    /// given hosts in their `Host` header.
+    pub fn generate(
+        cookie_file: &Path,
+        listen: SocketAddr,
+        allowed_hosts: Vec<String>,
+    ) -> Result<Self> {
+        let token = base64::encode_config(
+            rand::thread_rng().gen::<[u8; 32]>(),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(cookie_file)
+            .and_then(|mut file| file.write_all(token.as_bytes()))
+            .with_context(|| format!("Failed to write RPC cookie to {}", cookie_file.display()))?;
+
+        Ok(Self::new(token, listen, allowed_hosts))
+    }
+
+    fn new(token: String, listen: SocketAddr, mut allowed_hosts: Vec<String>) -> Self {
+        allowed_hosts.push(listen.to_string());
+        allowed_hosts.push(format!("localhost:{}", listen.port()));
+
+        Self {
+            token,
+            allowed_hosts,
+        }
+    }
+
+    /// Whether the request names an allowed host and, if sent by a browser,
+    /// originates from one.
+    fn is_allowed_origin(&self, headers: &HeaderMap) -> bool {
+        let host = match headers.get(HOST).and_then(|host| host.to_str().ok()) {
+            Some(host) => host,
+            None => return false,
+        };
+        if !self.allowed_hosts.iter().any(|allowed| allowed == host) {
+            return false;
+        }
+
+        match headers.get(ORIGIN) {
+            None => true,
+            Some(origin) => origin.to_str().map_or(false, |origin| {
+                self.allowed_hosts
+                    .iter()
+                    .any(|allowed| origin == format!("http://{}", allowed))
+            }),
+        }
+    }
+
+    fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let token = match headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => token.as_bytes(),
+            None => return false,
+        };
+
+        // Compare in constant time to not leak the token through timing.
+        token.len() == self.token.len()
+            && token
+                .iter()
+                .zip(self.token.as_bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map_or(false, |media_type| {
+            media_type.trim().eq_ignore_ascii_case("application/json")
+        })
+}
+
+#[allow(missing_debug_implementations)]
+pub struct RpcServer {
+    db: Arc<Database>,
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    monero_wallet: Arc<monero::Wallet>,
+    kraken_rate: KrakenRate,
+    quotes: QuoteSwitch,
+    peer_bans: PeerBans,
+    min_buy: bitcoin::Amount,
+    max_buy: bitcoin::Amount,
+    dashboard: bool,
+    auth: Auth,
+}
+
+impl RpcServer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db: Arc<Database>,
+        bitcoin_wallet: Arc<bitcoin::Wallet>,
+        monero_wallet: Arc<monero::Wallet>,
+        kraken_rate: KrakenRate,
+        quotes: QuoteSwitch,
+        peer_bans: PeerBans,
+        min_buy: bitcoin::Amount,
+        max_buy: bitcoin::Amount,
+        dashboard: bool,
+        auth: Auth,
+    ) -> Self {
+        Self {
+            db,
+            bitcoin_wallet,
+            monero_wallet,
+            kraken_rate,
+            quotes,
+            peer_bans,
+            min_buy,
+            max_buy,
+            dashboard,
+            auth,
+        }
+    }
+
+    pub async fn run(self, listen: SocketAddr) -> Result<()> {
+        let server_has_dashboard = self.dashboard;
+        let server = Arc::new(self);
+        let make_service = make_service_fn(move |_| {
+            let server = server.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let server = server.clone();
+
+                    async move { server.handle_http(request).await }
+                }))
+            }
+        });
+
+        let http = hyper::Server::try_bind(&listen)
+            .with_context(|| format!("Failed to bind RPC server to {}", listen))?
+            .serve(make_service);
+
+        tracing::info!(%listen, "Started RPC server");
+        if server_has_dashboard {
+            tracing::info!("Serving dashboard on http://{}", listen);
+        }
+
+        http.await.context("RPC server failed")
+    }
+
+    async fn handle_http(
+        &self,
+        request: hyper::Request<Body>,
+    ) -> Result<hyper::Response<Body>, Infallible> {
+        match route(request.method(), request.uri().path(), self.dashboard) {
+            Route::Rpc => {
+                if !self.auth.is_allowed_origin(request.headers()) {
+                    return Ok(empty_response(StatusCode::FORBIDDEN));
+                }
+                if !is_json(request.headers()) {
+                    return Ok(empty_response(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+                }
+                if !self.auth.is_authorized(request.headers()) {
+                    return Ok(hyper::Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .header(WWW_AUTHENTICATE, "Bearer")
+                        .body(Body::empty())
+                        .expect("static response parts are valid"));
+                }
+            }
+            Route::Dashboard => {
+                // Like the API, the page is only served to browsers that visit it under an
+                // allowed host, and must not be embedded into other sites.
+                if !self.auth.is_allowed_origin(request.headers()) {
+                    return Ok(empty_response(StatusCode::FORBIDDEN));
+                }
+
+                return Ok(hyper::Response::builder()
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .header(X_FRAME_OPTIONS, "DENY")
+                    .body(Body::from(DASHBOARD))
+                    .expect("static response parts are valid"));
+            }
+            Route::NotFound => return Ok(empty_response(StatusCode::NOT_FOUND)),
+            Route::MethodNotAllowed => return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED)),
+        }
+
+        let body = match hyper::body::to_bytes(request.into_body()).await {
+            Ok(body) => body,
+            Err(_) => return Ok(empty_response(StatusCode::BAD_REQUEST)),
+        };
+
+        let response = self.handle(&body).await;
+        let json = serde_json::to_vec(&response).expect("responses can always be serialized");
+
+        Ok(hyper::Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .expect("static response parts are valid"))
+    }
+
+    async fn handle(&self, body: &[u8]) -> Response {
+        let request = match serde_json::from_slice::<Request>(body) {
+            Ok(request) => request,
+            Err(_) => return Response::error(Value::Null, RpcError::parse_error()),
+        };
+
+        if request.jsonrpc != JSONRPC_VERSION {
+            return Response::error(request.id, RpcError::invalid_request());
+        }
+
+        let call = match Call::parse(&request.method, request.params) {
+            Ok(call) => call,
+            Err(error) => return Response::error(request.id, error),
+        };
+
+        tracing::debug!(method = %request.method, "Handling RPC request");
+
+        match self.execute(call).await {
+            Ok(result) => Response::result(request.id, result),
+            Err(error) => {
+                tracing::warn!(method = %request.method, "RPC request failed: {:#}", error);
+                Response::error(request.id, RpcError::internal(error))
+            }
+        }
+    }
+
+    async fn execute(&self, call: Call) -> Result<Value> {
+        let result = match call {
+            Call::GetSwaps => {
+                let swaps = self
+                    .db
+                    .all_alice()?
+                    .into_iter()
+                    .map(|(swap_id, state)| SwapSummary {
+                        swap_id,
+                        active: !matches!(state, Alice::Done(_)),
+                        state: state.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+
+                serde_json::to_value(swaps)?
+            }
+            Call::GetSwapDetails { swap_id } => {
+                let state = self.db.get_state(swap_id)?.try_into_alice()?;
+                let peer_id = self.db.get_peer_id(swap_id).ok();
+                let lock = state.state3().map(|state3| LockDetails {
+                    tx_lock_id: state3.tx_lock.txid(),
+                    btc: state3.tx_lock.lock_amount(),
+                    cancel_timelock: state3.cancel_timelock,
+                    punish_timelock: state3.punish_timelock,
+                });
+
+                serde_json::to_value(SwapDetails {
+                    swap_id,
+                    state: state.to_string(),
+                    peer_id: peer_id.map(|peer_id| peer_id.to_string()),
+                    lock,
+                })?
+            }
+            Call::GetBalances => {
+                let bitcoin = self.bitcoin_wallet.balance().await?;
+                let monero = self.monero_wallet.get_balance().await?;
+
+                serde_json::to_value(Balances { bitcoin, monero })?
+            }
+            Call::GetQuote => {
+                let rate = self.kraken_rate.clone().latest_rate_for(self.max_buy)?;
+
+                serde_json::to_value(QuoteSummary {
+                    price: rate.ask()?,
+                    market_price: rate.market_price(),
+                    ask_spread: rate.ask_spread(),
+                    min_quantity: self.min_buy,
+                    max_quantity: self.max_buy,
+                    paused: self.quotes.is_paused(),
+                })?
+            }
+            Call::GetVolume => serde_json::to_value(self.volume()?)?,
+            Call::GetHealth => serde_json::to_value(self.health().await)?,
+            Call::WithdrawBtc { address, amount } => {
+                let txid = withdraw_btc(&self.bitcoin_wallet, address, amount).await?;
+
+                serde_json::json!({ "txid": txid })
+            }
+            Call::WithdrawXmr { address, amount } => {
+                let swaps = self.db.all_alice()?.into_iter().map(|(_, state)| state);
+                let tx_hashes = withdraw_xmr(&self.monero_wallet, swaps, address, amount)
+                    .await?
+                    .into_iter()
+                    .map(|tx_hash| tx_hash.0)
+                    .collect::<Vec<_>>();
+
+                serde_json::json!({ "tx_hashes": tx_hashes })
+            }
+            Call::SetSpread { ask_spread } => {
+                if !(Decimal::from(0)..=Decimal::from(1)).contains(&ask_spread) {
+                    bail!(
+                        "Invalid spread {}, the spread must be in the interval [0..1]",
+                        ask_spread
+                    )
+                }
+
+                self.kraken_rate.set_ask_spread(ask_spread);
+                tracing::info!(%ask_spread, "Updated ask spread");
+
+                serde_json::json!({ "ask_spread": ask_spread })
+            }
+            Call::PauseQuotes => {
+                self.quotes.pause();
+                tracing::info!("Paused quotes");
+
+                serde_json::json!({ "paused": self.quotes.is_paused() })
+            }
+            Call::ResumeQuotes => {
+                self.quotes.resume();
+                tracing::info!("Resumed quotes");
+
+                serde_json::json!({ "paused": self.quotes.is_paused() })
+            }
+            Call::GetPeers => {
+                let peers = self
+                    .db
+                    .all_peer_stats()?
+                    .into_iter()
+                    .map(|(peer_id, stats)| PeerSummary { peer_id, stats })
+                    .collect::<Vec<_>>();
+
+                serde_json::to_value(peers)?
+            }
+            Call::ClearBan { peer_id } => {
+                self.db.clear_peer_stats(peer_id).await?;
+                self.peer_bans.lift(peer_id);
+                tracing::info!(%peer_id, "Cleared misbehavior of peer");
+
+                serde_json::json!({ "peer_id": peer_id.to_string(), "banned": false })
+            }
+        };
+
+        Ok(result)
+    }
+
+    /// Sums up the amounts of all swaps in which the Bitcoin was redeemed.
+    ///
+    /// The amounts are recorded when a swap is set up, swaps that finished
+    /// before the amounts were recorded are not included.
+    fn volume(&self) -> Result<Volume> {
+        let mut volume = Volume {
+            swaps: 0,
+            bitcoin: bitcoin::Amount::ZERO,
+            monero: monero::Amount::ZERO,
+        };
+
+        for (swap_id, state) in self.db.all_alice()? {
+            if !matches!(state, Alice::Done(AliceEndState::BtcRedeemed)) {
+                continue;
+            }
+
+            if let Some(amounts) = self.db.get_accounting(swap_id)?.amounts {
+                volume.swaps += 1;
+                volume.bitcoin += amounts.btc;
+                volume.monero = volume.monero + amounts.xmr;
+            }
+        }
+
+        Ok(volume)
+    }
+
+    /// Collects the health of the price feed and the wallets, a failing check
+    /// is reported as missing value instead of failing the request.
+    async fn health(&self) -> Health {
+        let bitcoin_block_height = match self.bitcoin_wallet.block_height().await {
+            Ok(height) => Some(u32::from(height)),
+            Err(error) => {
+                tracing::warn!("Failed to get Bitcoin block height: {:#}", error);
+                None
+            }
+        };
+        let monero_block_height = match self.monero_wallet.block_height().await {
+            Ok(height) => Some(height.height),
+            Err(error) => {
+                tracing::warn!("Failed to get Monero block height: {:#}", error);
+                None
+            }
+        };
+
+        Health {
+            price_update_age_secs: METRICS.price_update_age().map(|age| age.as_secs()),
+            bitcoin_block_height,
+            monero_block_height,
+            monero_sync: self.monero_wallet.sync_progress(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Route {
+    Rpc,
+    Dashboard,
+    NotFound,
+    MethodNotAllowed,
+}
+
+fn route(method: &Method, path: &str, dashboard: bool) -> Route {
+    match (method, path) {
+        (&Method::POST, _) => Route::Rpc,
+        (&Method::GET, "/") | (&Method::GET, "/index.html") if dashboard => Route::Dashboard,
+        (&Method::GET, _) if dashboard => Route::NotFound,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+fn empty_response(status: StatusCode) -> hyper::Response<Body> {
+    hyper::Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("static response parts are valid")
+}
+
+#[derive(Debug, PartialEq)]
+enum Call {
+    GetSwaps,
+    GetSwapDetails {
+        swap_id: Uuid,
+    },
+    GetBalances,
+    GetQuote,
+    GetVolume,
+    GetHealth,
+    WithdrawBtc {
+        address: bitcoin::Address,
+        amount: Option<bitcoin::Amount>,
+    },
+    WithdrawXmr {
+        address: monero::Address,
+        amount: Option<monero::Amount>,
+    },
+    SetSpread {
+        ask_spread: Decimal,
+    },
+    PauseQuotes,
+    ResumeQuotes,
+    GetPeers,
+    ClearBan {
+        peer_id: PeerId,
+    },
+}
+
+impl Call {
+    fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        let call = match method {
+            "get_swaps" => Call::GetSwaps,
+            "get_swap_details" => {
+                let SwapIdParams { swap_id } = params_from(params)?;
+                Call::GetSwapDetails { swap_id }
+            }
+            "get_balances" => Call::GetBalances,
+            "get_quote" => Call::GetQuote,
+            "get_volume" => Call::GetVolume,
+            "get_health" => Call::GetHealth,
+            "withdraw_btc" => {
+                let WithdrawBtcParams { address, amount } = params_from(params)?;
+                Call::WithdrawBtc {
+                    address,
+                    amount: amount.map(bitcoin::Amount::from_sat),
+                }
+            }
+            "withdraw_xmr" => {
+                let WithdrawXmrParams { address, amount } = params_from(params)?;
+                Call::WithdrawXmr {
+                    address,
+                    amount: amount.map(monero::Amount::from_piconero),
+                }
+            }
+            "set_spread" => {
+                let SetSpreadParams { ask_spread } = params_from(params)?;
+                Call::SetSpread { ask_spread }
+            }
+            "pause_quotes" => Call::PauseQuotes,
+            "resume_quotes" => Call::ResumeQuotes,
+            "get_peers" => Call::GetPeers,
+            "clear_ban" => {
+                let PeerIdParams { peer_id } = params_from(params)?;
+                Call::ClearBan { peer_id }
+            }
+            _ => return Err(RpcError::method_not_found(method)),
+        };
+
+        Ok(call)
+    }
+}
+
+fn params_from<T>(params: Value) -> Result<T, RpcError>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+#[derive(Debug, Deserialize)]
+struct SwapIdParams {
+    swap_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithdrawBtcParams {
+    address: bitcoin::Address,
+    /// Amount in satoshi, withdraws everything if not given.
+    #[serde(default)]
+    amount: Option<u64>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct WithdrawXmrParams {
+    #[serde_as(as = "DisplayFromStr")]
+    address: monero::Address,
+    /// Amount in piconero, withdraws everything that is not reserved if not
+    /// given.
+    #[serde(default)]
+    amount: Option<u64>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct PeerIdParams {
+    #[serde_as(as = "DisplayFromStr")]
+    peer_id: PeerId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetSpreadParams {
+    ask_spread: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct SwapSummary {
+    swap_id: Uuid,
+    /// Whether the swap has not finished yet.
+    active: bool,
+    state: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize)]
+struct PeerSummary {
+    #[serde_as(as = "DisplayFromStr")]
+    peer_id: PeerId,
+    #[serde(flatten)]
+    stats: PeerStats,
+}
+
+#[derive(Debug, Serialize)]
+struct SwapDetails {
+    swap_id: Uuid,
+    state: String,
+    peer_id: Option<String>,
+    lock: Option<LockDetails>,
+}
+
+#[derive(Debug, Serialize)]
+struct LockDetails {
+    tx_lock_id: Txid,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    btc: bitcoin::Amount,
+    cancel_timelock: CancelTimelock,
+    punish_timelock: PunishTimelock,
+}
+
+#[derive(Debug, Serialize)]
+struct Balances {
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    bitcoin: bitcoin::Amount,
+    monero: monero::Amount,
+}
+
+#[derive(Debug, Serialize)]
+struct QuoteSummary {
+    /// Price of 1 XMR in satoshi, including the spread.
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    price: bitcoin::Amount,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    market_price: bitcoin::Amount,
+    ask_spread: Decimal,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    min_quantity: bitcoin::Amount,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    max_quantity: bitcoin::Amount,
+    paused: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Volume {
+    swaps: u64,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    bitcoin: bitcoin::Amount,
+    monero: monero::Amount,
+}
+
+#[derive(Debug, Serialize)]
+struct Health {
+    /// Seconds since the last price update, `None` if none was received yet.
+    price_update_age_secs: Option<u64>,
+    /// `None` if the Bitcoin wallet failed to get the block height.
+    bitcoin_block_height: Option<u32>,
+    /// `None` if the Monero wallet failed to get the block height.
+    monero_block_height: Option<u32>,
+    monero_sync: SyncProgress,
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+impl Response {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION,
+            id,
+            outcome: Outcome::Result(result),
+        }
+    }
+
+    fn error(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION,
+            id,
+            outcome: Outcome::Error(error),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+/// Error object as defined by the JSON-RPC 2.0 specification.
+#[derive(Debug, Serialize, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn parse_error() -> Self {
+        Self {
+            code: -32700,
+            message: "Parse error".to_owned(),
+        }
+    }
+
+    fn invalid_request() -> Self {
+        Self {
+            code: -32600,
+            message: "Invalid request".to_owned(),
+        }
+    }
+
+    fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("Method not found: {}", method),
+        }
+    }
+
+    fn invalid_params(error: serde_json::Error) -> Self {
+        Self {
+            code: -32602,
+            message: format!("Invalid params: {}", error),
+        }
+    }
+
+    fn internal(error: anyhow::Error) -> Self {
+        Self {
+            code: -32000,
+            message: format!("{:#}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn parses_calls_with_params() {
+        let swap_id = Uuid::new_v4();
+
+        let call = Call::parse("get_swap_details", json!({ "swap_id": swap_id })).unwrap();
+        assert_eq!(call, Call::GetSwapDetails { swap_id });
+
+        let call = Call::parse(
+            "withdraw_btc",
+            json!({ "address": "tb1qr3em6k3gfnyl8r7q0v7t4tlnyxzgxma3lressv", "amount": 10_000 }),
+        )
+        .unwrap();
+        assert_eq!(call, Call::WithdrawBtc {
+            address: bitcoin::Address::from_str("tb1qr3em6k3gfnyl8r7q0v7t4tlnyxzgxma3lressv")
+                .unwrap(),
+            amount: Some(bitcoin::Amount::from_sat(10_000))
+        });
+
+        let call = Call::parse(
+            "withdraw_xmr",
+            json!({ "address": "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a" }),
+        )
+        .unwrap();
+        assert_eq!(call, Call::WithdrawXmr {
+            address: monero::Address::from_str("53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a").unwrap(),
+            amount: None
+        });
+
+        let peer_id = PeerId::random();
+        let call = Call::parse("clear_ban", json!({ "peer_id": peer_id.to_string() })).unwrap();
+        assert_eq!(call, Call::ClearBan { peer_id });
+
+        let call = Call::parse("set_spread", json!({ "ask_spread": 0.05 })).unwrap();
+        assert_eq!(call, Call::SetSpread {
+            ask_spread: Decimal::from_str("0.05").unwrap()
+        });
+    }
+
+    #[test]
+    fn rejects_unknown_method_and_invalid_params() {
+        let error = Call::parse("get_all_the_keys", Value::Null).unwrap_err();
+        assert_eq!(error.code, -32601);
+
+        let error =
+            Call::parse("get_swap_details", json!({ "swap_id": "not-a-uuid" })).unwrap_err();
+        assert_eq!(error.code, -32602);
+    }
+
+    #[test]
+    fn serves_dashboard_only_if_enabled() {
+        assert_eq!(route(&Method::POST, "/", true), Route::Rpc);
+        assert_eq!(route(&Method::POST, "/", false), Route::Rpc);
+        assert_eq!(route(&Method::GET, "/", true), Route::Dashboard);
+        assert_eq!(route(&Method::GET, "/swaps", true), Route::NotFound);
+        assert_eq!(route(&Method::GET, "/", false), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::PUT, "/", true), Route::MethodNotAllowed);
+    }
+
+    fn headers(headers: &[(hyper::header::HeaderName, &str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    fn auth() -> Auth {
+        Auth::new(
+            "secret".to_owned(),
+            "127.0.0.1:9944".parse().unwrap(),
+            vec!["asb.lan:9944".to_owned()],
+        )
+    }
+
+    #[test]
+    fn accepts_only_requests_with_token() {
+        let auth = auth();
+
+        assert!(auth.is_authorized(&headers(&[(AUTHORIZATION, "Bearer secret")])));
+        assert!(!auth.is_authorized(&headers(&[(AUTHORIZATION, "Bearer secreT")])));
+        assert!(!auth.is_authorized(&headers(&[(AUTHORIZATION, "Bearer secret2")])));
+        assert!(!auth.is_authorized(&headers(&[(AUTHORIZATION, "secret")])));
+        assert!(!auth.is_authorized(&headers(&[])));
+    }
+
+    #[test]
+    fn accepts_only_requests_for_allowed_hosts() {
+        let auth = auth();
+
+        assert!(auth.is_allowed_origin(&headers(&[(HOST, "127.0.0.1:9944")])));
+        assert!(auth.is_allowed_origin(&headers(&[(HOST, "localhost:9944")])));
+        assert!(auth.is_allowed_origin(&headers(&[(HOST, "asb.lan:9944")])));
+        assert!(auth.is_allowed_origin(&headers(&[
+            (HOST, "127.0.0.1:9944"),
+            (ORIGIN, "http://127.0.0.1:9944")
+        ])));
+
+        assert!(!auth.is_allowed_origin(&headers(&[])));
+        assert!(!auth.is_allowed_origin(&headers(&[(HOST, "evil.com:9944")])));
+        assert!(!auth.is_allowed_origin(&headers(&[
+            (HOST, "127.0.0.1:9944"),
+            (ORIGIN, "https://evil.com")
+        ])));
+    }
+
+    #[test]
+    fn accepts_only_json_requests() {
+        assert!(is_json(&headers(&[(CONTENT_TYPE, "application/json")])));
+        assert!(is_json(&headers(&[(
+            CONTENT_TYPE,
+            "application/json; charset=utf-8"
+        )])));
+        assert!(!is_json(&headers(&[(CONTENT_TYPE, "text/plain")])));
+        assert!(!is_json(&headers(&[])));
+    }
+
+    #[test]
+    fn serializes_responses_according_to_spec() {
+        let result = Response::result(json!(1), json!({ "paused": true }));
+        assert_eq!(
+            serde_json::to_value(result).unwrap(),
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "paused": true } })
+        );
+
+        let error = Response::error(Value::Null, RpcError::parse_error());
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "Parse error" } })
+        );
+    }
+}
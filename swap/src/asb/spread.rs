@@ -0,0 +1,303 @@
+//! Strategies deciding the spread applied to the market asking price.
+//!
+//! The ASB only sells XMR, hence there is only an ask spread. Strategies can
+//! take the amount of a swap and the recent market prices into account, custom
+//! strategies are plugged in by implementing [`SpreadStrategy`] and passing
+//! them to [`KrakenRate::new`](crate::asb::KrakenRate::new).
+
+use crate::asb::config;
+use crate::bitcoin;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait SpreadStrategy: Debug + Send + Sync {
+    /// The spread applied to the market asking price when selling XMR worth
+    /// `btc`.
+    fn spread(&self, btc: bitcoin::Amount) -> Decimal;
+
+    /// The largest spread applied to any amount from `min` to `max`, which is
+    /// the spread a quote for these amounts has to advertise.
+    ///
+    /// The default only looks at both bounds, strategies whose spread is not
+    /// monotonic in the amount have to override it.
+    fn max_spread(&self, min: bitcoin::Amount, max: bitcoin::Amount) -> Decimal {
+        self.spread(min).max(self.spread(max))
+    }
+
+    /// Called with every market asking price received from the price feed.
+    fn observe(&self, _ask: bitcoin::Amount, _at: Instant) {}
+
+    /// The same strategy with the spread it applies by default replaced.
+    ///
+    /// The default replaces the strategy with a flat spread.
+    fn with_ask_spread(&self, ask_spread: Decimal) -> Box<dyn SpreadStrategy> {
+        Box::new(Flat(ask_spread))
+    }
+}
+
+/// Builds the strategy configured for the maker.
+pub fn from_config(maker: &config::Maker) -> Arc<dyn SpreadStrategy> {
+    let base: Box<dyn SpreadStrategy> = if maker.spread_tiers.is_empty() {
+        Box::new(Flat(maker.ask_spread))
+    } else {
+        Box::new(Tiered::new(
+            maker.ask_spread,
+            maker
+                .spread_tiers
+                .iter()
+                .map(|tier| (tier.min_btc, tier.spread))
+                .collect(),
+        ))
+    };
+
+    match &maker.volatility {
+        None => Arc::from(base),
+        Some(volatility) => Arc::new(VolatilitySensitive::new(
+            base,
+            Duration::from_secs(volatility.window_mins * 60),
+            volatility.threshold,
+            volatility.extra_spread,
+        )),
+    }
+}
+
+/// The same spread regardless of the amount.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flat(pub Decimal);
+
+impl SpreadStrategy for Flat {
+    fn spread(&self, _btc: bitcoin::Amount) -> Decimal {
+        self.0
+    }
+
+    fn with_ask_spread(&self, ask_spread: Decimal) -> Box<dyn SpreadStrategy> {
+        Box::new(Flat(ask_spread))
+    }
+}
+
+/// A spread depending on the amount, each tier applies from its minimum
+/// amount up to the minimum amount of the next tier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tiered {
+    /// Applies below the minimum amount of the first tier.
+    base: Decimal,
+    /// Sorted by the minimum amount.
+    tiers: Vec<(bitcoin::Amount, Decimal)>,
+}
+
+impl Tiered {
+    pub fn new(base: Decimal, mut tiers: Vec<(bitcoin::Amount, Decimal)>) -> Self {
+        tiers.sort_by_key(|(min_btc, _)| *min_btc);
+
+        Self { base, tiers }
+    }
+}
+
+impl SpreadStrategy for Tiered {
+    fn spread(&self, btc: bitcoin::Amount) -> Decimal {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|(min_btc, _)| btc >= *min_btc)
+            .map_or(self.base, |(_, spread)| *spread)
+    }
+
+    fn max_spread(&self, min: bitcoin::Amount, max: bitcoin::Amount) -> Decimal {
+        self.tiers
+            .iter()
+            .filter(|(min_btc, _)| min < *min_btc && *min_btc <= max)
+            .map(|(_, spread)| *spread)
+            .fold(self.spread(min), Decimal::max)
+    }
+
+    /// Only replaces the spread below the first tier.
+    fn with_ask_spread(&self, ask_spread: Decimal) -> Box<dyn SpreadStrategy> {
+        Box::new(Self {
+            base: ask_spread,
+            tiers: self.tiers.clone(),
+        })
+    }
+}
+
+/// Widens the spread of another strategy while the market is volatile.
+///
+/// The market counts as volatile if the standard deviation of the asking
+/// prices within the window exceeds the threshold, relative to their mean.
+#[derive(Debug)]
+pub struct VolatilitySensitive {
+    inner: Box<dyn SpreadStrategy>,
+    window: Duration,
+    threshold: Decimal,
+    extra_spread: Decimal,
+    prices: Mutex<VecDeque<(Instant, bitcoin::Amount)>>,
+}
+
+impl VolatilitySensitive {
+    pub fn new(
+        inner: Box<dyn SpreadStrategy>,
+        window: Duration,
+        threshold: Decimal,
+        extra_spread: Decimal,
+    ) -> Self {
+        Self {
+            inner,
+            window,
+            threshold,
+            extra_spread,
+            prices: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn is_volatile(&self) -> bool {
+        let prices = self.prices.lock().expect("lock is never poisoned");
+        if prices.len() < 2 {
+            return false;
+        }
+
+        let count = Decimal::from(prices.len());
+        let prices = prices
+            .iter()
+            .map(|(_, price)| Decimal::from(price.as_sat()))
+            .collect::<Vec<_>>();
+
+        let mean = prices.iter().sum::<Decimal>() / count;
+        if mean.is_zero() {
+            return false;
+        }
+        let variance = prices
+            .iter()
+            .map(|price| (*price - mean) * (*price - mean))
+            .sum::<Decimal>()
+            / count;
+
+        // Compare squares to avoid taking the square root.
+        variance / (mean * mean) > self.threshold * self.threshold
+    }
+}
+
+impl SpreadStrategy for VolatilitySensitive {
+    fn spread(&self, btc: bitcoin::Amount) -> Decimal {
+        let spread = self.inner.spread(btc);
+
+        if self.is_volatile() {
+            spread + self.extra_spread
+        } else {
+            spread
+        }
+    }
+
+    fn max_spread(&self, min: bitcoin::Amount, max: bitcoin::Amount) -> Decimal {
+        let spread = self.inner.max_spread(min, max);
+
+        if self.is_volatile() {
+            spread + self.extra_spread
+        } else {
+            spread
+        }
+    }
+
+    /// Keeps the window, the threshold and the prices observed so far.
+    fn with_ask_spread(&self, ask_spread: Decimal) -> Box<dyn SpreadStrategy> {
+        let prices = self.prices.lock().expect("lock is never poisoned").clone();
+
+        Box::new(Self {
+            inner: self.inner.with_ask_spread(ask_spread),
+            window: self.window,
+            threshold: self.threshold,
+            extra_spread: self.extra_spread,
+            prices: Mutex::new(prices),
+        })
+    }
+
+    fn observe(&self, ask: bitcoin::Amount, at: Instant) {
+        self.inner.observe(ask, at);
+
+        let mut prices = self.prices.lock().expect("lock is never poisoned");
+        prices.push_back((at, ask));
+        while let Some((observed, _)) = prices.front() {
+            if at.saturating_duration_since(*observed) <= self.window {
+                break;
+            }
+            prices.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn btc(btc: f64) -> bitcoin::Amount {
+        bitcoin::Amount::from_btc(btc).unwrap()
+    }
+
+    #[test]
+    fn tier_applies_from_its_minimum_amount() {
+        let strategy = Tiered::new(dec!(0.02), vec![
+            (btc(1.0), dec!(0.01)),
+            (btc(0.1), dec!(0.015)),
+        ]);
+
+        assert_eq!(strategy.spread(btc(0.05)), dec!(0.02));
+        assert_eq!(strategy.spread(btc(0.1)), dec!(0.015));
+        assert_eq!(strategy.spread(btc(0.5)), dec!(0.015));
+        assert_eq!(strategy.spread(btc(2.0)), dec!(0.01));
+    }
+
+    #[test]
+    fn quotes_the_largest_spread_within_the_range() {
+        let strategy = Tiered::new(dec!(0.01), vec![
+            (btc(0.1), dec!(0.03)),
+            (btc(1.0), dec!(0.02)),
+        ]);
+
+        assert_eq!(strategy.max_spread(btc(0.01), btc(0.05)), dec!(0.01));
+        assert_eq!(strategy.max_spread(btc(0.01), btc(2.0)), dec!(0.03));
+        assert_eq!(strategy.max_spread(btc(0.5), btc(2.0)), dec!(0.03));
+        assert_eq!(strategy.max_spread(btc(1.0), btc(2.0)), dec!(0.02));
+    }
+
+    #[test]
+    fn replacing_the_ask_spread_keeps_tiers_and_volatility() {
+        let strategy = VolatilitySensitive::new(
+            Box::new(Tiered::new(dec!(0.02), vec![(btc(1.0), dec!(0.01))])),
+            Duration::from_secs(60),
+            dec!(0.05),
+            dec!(0.03),
+        );
+        let start = Instant::now();
+        strategy.observe(btc(0.005), start);
+        strategy.observe(btc(0.006), start + Duration::from_secs(10));
+
+        let replaced = strategy.with_ask_spread(dec!(0.04));
+
+        assert_eq!(replaced.spread(btc(0.5)), dec!(0.07));
+        assert_eq!(replaced.spread(btc(2.0)), dec!(0.04));
+    }
+
+    #[test]
+    fn widens_spread_while_prices_within_window_vary() {
+        let strategy = VolatilitySensitive::new(
+            Box::new(Flat(dec!(0.02))),
+            Duration::from_secs(60),
+            dec!(0.05),
+            dec!(0.03),
+        );
+        let start = Instant::now();
+
+        strategy.observe(btc(0.005), start);
+        strategy.observe(btc(0.0051), start + Duration::from_secs(10));
+        assert_eq!(strategy.spread(btc(1.0)), dec!(0.02));
+
+        strategy.observe(btc(0.006), start + Duration::from_secs(20));
+        assert_eq!(strategy.spread(btc(1.0)), dec!(0.05));
+
+        strategy.observe(btc(0.006), start + Duration::from_secs(90));
+        strategy.observe(btc(0.006), start + Duration::from_secs(100));
+        assert_eq!(strategy.spread(btc(1.0)), dec!(0.02));
+    }
+}
@@ -0,0 +1,245 @@
+//! Withdrawing the funds the ASB accumulated in its wallets.
+
+use crate::asb::config::ColdWallet;
+use crate::database::{Alice, Database};
+use crate::monero::MONERO_FEE;
+use crate::{bitcoin, monero};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Withdraws Bitcoin from the internal wallet, everything if no amount is
+/// given.
+///
+/// Nothing has to be kept back for swaps in flight: all transactions that
+/// follow the lock transaction pay their fee from the locked output.
+pub async fn withdraw_btc(
+    bitcoin_wallet: &bitcoin::Wallet,
+    address: bitcoin::Address,
+    amount: Option<bitcoin::Amount>,
+) -> Result<bitcoin::Txid> {
+    let network = bitcoin_wallet.get_network();
+    if address.network != network {
+        bail!(
+            "Address {} is not a valid address on network {}",
+            address,
+            network
+        )
+    }
+    if bitcoin::is_burn_address(&address) {
+        bail!(bitcoin::BurnAddress(address))
+    }
+
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            bitcoin_wallet
+                .max_giveable(address.script_pubkey().len())
+                .await?
+        }
+    };
+
+    let psbt = bitcoin_wallet
+        .send_to_address(address, amount, None)
+        .await?;
+    let signed_tx = bitcoin_wallet.sign_and_finalize(psbt).await?;
+    let (txid, _) = bitcoin_wallet.broadcast(signed_tx, "withdraw").await?;
+
+    tracing::info!(%txid, %amount, "Withdrew Bitcoin");
+
+    Ok(txid)
+}
+
+/// Withdraws Monero from the wallet, everything that is not reserved if no
+/// amount is given.
+///
+/// The Monero of swaps that still have to lock it is reserved, see
+/// [`reserved_monero`].
+pub async fn withdraw_xmr(
+    monero_wallet: &monero::Wallet,
+    swaps: impl IntoIterator<Item = Alice>,
+    address: monero::Address,
+    amount: Option<monero::Amount>,
+) -> Result<Vec<monero::TxHash>> {
+    let network = monero_wallet.get_main_address().network;
+    if address.network != network {
+        bail!(
+            "Address {} is not a valid address on network {:?}",
+            address,
+            network
+        )
+    }
+    if monero::is_burn_address(&address) {
+        bail!(monero::BurnAddress(address))
+    }
+
+    let reserved = reserved_monero(swaps);
+    let unlocked = monero_wallet.get_unlocked_balance().await?;
+    let available = withdrawable_monero(unlocked, reserved);
+
+    let tx_hashes = match amount {
+        None if reserved == monero::Amount::ZERO => monero_wallet.sweep_all(address).await?,
+        None => {
+            if available == monero::Amount::ZERO {
+                bail!(MoneroReserved {
+                    requested: unlocked,
+                    available,
+                    reserved
+                })
+            }
+
+            vec![
+                monero_wallet
+                    .transfer_to_address(address, available)
+                    .await?,
+            ]
+        }
+        Some(amount) => {
+            if amount > available {
+                bail!(MoneroReserved {
+                    requested: amount,
+                    available,
+                    reserved
+                })
+            }
+
+            vec![monero_wallet.transfer_to_address(address, amount).await?]
+        }
+    };
+
+    tracing::info!(?tx_hashes, %reserved, "Withdrew Monero");
+
+    Ok(tx_hashes)
+}
+
+/// The Monero, including the fee of the lock transaction, that swaps which
+/// did not lock their Monero yet still need.
+pub fn reserved_monero(swaps: impl IntoIterator<Item = Alice>) -> monero::Amount {
+    swaps
+        .into_iter()
+        .filter_map(|state| match state {
+            Alice::Started { state3 }
+            | Alice::BtcLockTransactionSeen { state3 }
+            | Alice::BtcLocked { state3 } => Some(state3.xmr + MONERO_FEE),
+            _ => None,
+        })
+        .fold(monero::Amount::ZERO, |total, amount| total + amount)
+}
+
+/// Periodically sweeps the Monero exceeding the maximum balance of the hot
+/// wallet to the cold wallet.
+pub async fn sweep_to_cold_wallet(
+    cold_wallet: ColdWallet,
+    monero_wallet: Arc<monero::Wallet>,
+    db: Arc<Database>,
+) -> Result<()> {
+    let max_hot_balance = cold_wallet.max_hot_balance;
+    let interval = Duration::from_secs(cold_wallet.sweep_interval_mins * 60);
+
+    loop {
+        if let Err(error) =
+            sweep_once(&monero_wallet, &db, cold_wallet.address, max_hot_balance).await
+        {
+            tracing::warn!("Failed to sweep Monero to the cold wallet: {:#}", error);
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn sweep_once(
+    monero_wallet: &monero::Wallet,
+    db: &Database,
+    address: monero::Address,
+    max_hot_balance: monero::Amount,
+) -> Result<()> {
+    let swaps = db.all_alice()?.into_iter().map(|(_, state)| state);
+    let reserved = reserved_monero(swaps);
+    let unlocked = monero_wallet.get_unlocked_balance().await?;
+    let amount = sweepable_monero(unlocked, reserved, max_hot_balance);
+
+    if amount == monero::Amount::ZERO {
+        tracing::debug!(%unlocked, %reserved, %max_hot_balance, "Nothing to sweep to the cold wallet");
+        return Ok(());
+    }
+
+    let tx_hash = monero_wallet.transfer_to_address(address, amount).await?;
+
+    tracing::info!(%tx_hash, %amount, %address, "Swept Monero to the cold wallet");
+
+    Ok(())
+}
+
+/// The part of the unlocked balance exceeding what the hot wallet keeps, which
+/// is the larger of its maximum balance and the reserved Monero.
+fn sweepable_monero(
+    unlocked: monero::Amount,
+    reserved: monero::Amount,
+    max_hot_balance: monero::Amount,
+) -> monero::Amount {
+    let kept = if reserved > max_hot_balance {
+        reserved
+    } else {
+        max_hot_balance
+    };
+
+    withdrawable_monero(unlocked, kept)
+}
+
+/// The part of the unlocked balance that can be withdrawn while leaving enough
+/// for the reserved Monero and the fee of the withdrawal.
+fn withdrawable_monero(unlocked: monero::Amount, reserved: monero::Amount) -> monero::Amount {
+    unlocked.saturating_sub(reserved).saturating_sub(MONERO_FEE)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Cannot withdraw {requested}, only {available} can be withdrawn because {reserved} is reserved for swaps in flight")]
+pub struct MoneroReserved {
+    pub requested: monero::Amount,
+    pub available: monero::Amount,
+    pub reserved: monero::Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn withdrawable_leaves_reserved_amount_and_fee() {
+        let withdrawable = withdrawable_monero(xmr(10.0), xmr(4.0));
+
+        assert_eq!(withdrawable, xmr(6.0) - MONERO_FEE);
+    }
+
+    #[test]
+    fn nothing_withdrawable_if_reserved_exceeds_balance() {
+        let withdrawable = withdrawable_monero(xmr(1.0), xmr(4.0));
+
+        assert_eq!(withdrawable, monero::Amount::ZERO);
+    }
+
+    #[test]
+    fn sweep_keeps_maximum_hot_balance() {
+        let sweepable = sweepable_monero(xmr(10.0), xmr(1.0), xmr(4.0));
+
+        assert_eq!(sweepable, xmr(6.0) - MONERO_FEE);
+    }
+
+    #[test]
+    fn sweep_keeps_reserved_amount_above_maximum_hot_balance() {
+        let sweepable = sweepable_monero(xmr(10.0), xmr(7.0), xmr(4.0));
+
+        assert_eq!(sweepable, xmr(3.0) - MONERO_FEE);
+    }
+
+    #[test]
+    fn nothing_to_sweep_below_maximum_hot_balance() {
+        let sweepable = sweepable_monero(xmr(3.0), xmr(0.0), xmr(4.0));
+
+        assert_eq!(sweepable, monero::Amount::ZERO);
+    }
+
+    fn xmr(amount: f64) -> monero::Amount {
+        monero::Amount::from_monero(amount).unwrap()
+    }
+}
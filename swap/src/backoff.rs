@@ -0,0 +1,250 @@
+//! Retrying calls to external services with a capped and jittered
+//! exponential backoff.
+//!
+//! Every kind of call has a [`Policy`] that caps how long to wait between two
+//! attempts and how long to keep retrying at all. A [`CircuitBreaker`] stops
+//! calling a service that keeps failing for a while, so callers fail fast
+//! instead of piling up behind yet another timeout.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub use ::backoff::backoff::Backoff;
+pub use ::backoff::{Error, ExponentialBackoff};
+
+/// Each wait is randomised by up to half of its length, so clients that
+/// failed at the same time don't retry in lockstep.
+const JITTER: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    pub initial_interval: Duration,
+    pub max_interval: Duration,
+    /// Retrying stops once this much time passed since the first attempt,
+    /// `None` retries until the call succeeds.
+    pub max_elapsed_time: Option<Duration>,
+}
+
+impl Policy {
+    /// Polling an Electrum server for the status of a transaction.
+    pub const ELECTRUM: Policy = Policy {
+        initial_interval: Duration::from_secs(5),
+        max_interval: Duration::from_secs(120),
+        max_elapsed_time: None,
+    };
+
+    /// Requests to the monero-wallet-rpc, and through it to the Monero node.
+    pub const MONERO: Policy = Policy {
+        initial_interval: Duration::from_secs(1),
+        max_interval: Duration::from_secs(60),
+        max_elapsed_time: None,
+    };
+
+    /// Reconnecting to the price feed, without a rate no quotes are given.
+    pub const PRICE_FEED: Policy = Policy {
+        initial_interval: Duration::from_millis(500),
+        max_interval: Duration::from_secs(60),
+        max_elapsed_time: None,
+    };
+
+    /// Requests to the counterparty that the swap cannot go on without.
+    pub const PEER_REQUEST: Policy = Policy {
+        initial_interval: Duration::from_millis(500),
+        max_interval: Duration::from_secs(60),
+        max_elapsed_time: None,
+    };
+
+    /// Requests to the counterparty that are nice to have, like the receipt of
+    /// a completed swap.
+    pub const RECEIPT: Policy = Policy {
+        initial_interval: Duration::from_secs(1),
+        max_interval: Duration::from_secs(60),
+        max_elapsed_time: Some(Duration::from_secs(5 * 60)),
+    };
+
+    /// Dialling the counterparty after the connection was lost.
+    pub const REDIAL: Policy = Policy {
+        initial_interval: Duration::from_secs(1),
+        max_interval: Duration::from_secs(60),
+        max_elapsed_time: Some(Duration::from_secs(5 * 60)),
+    };
+
+    pub fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoff {
+            initial_interval: self.initial_interval,
+            current_interval: self.initial_interval,
+            randomization_factor: JITTER,
+            max_interval: self.max_interval,
+            max_elapsed_time: self.max_elapsed_time,
+            ..ExponentialBackoff::default()
+        }
+    }
+}
+
+/// Calls `operation` until it succeeds, fails permanently or the policy gives
+/// up, logging every failure as a warning.
+pub async fn retry<T, F, Fut>(
+    description: &str,
+    policy: Policy,
+    operation: F,
+) -> Result<T, anyhow::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error<anyhow::Error>>>,
+{
+    ::backoff::future::retry_notify(
+        policy.backoff(),
+        operation,
+        |error: anyhow::Error, next: Duration| {
+            tracing::warn!(
+                "Failed to {}, retrying in {}s. Error {:#}",
+                description,
+                next.as_secs(),
+                error
+            );
+        },
+    )
+    .await
+}
+
+/// Stops calling a service after a number of consecutive failures until a
+/// cooldown passed.
+///
+/// Once the cooldown passed a single call is let through, the circuit opens
+/// again right away if that call fails as well.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    service: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[error("{service} failed repeatedly, not calling it for another {}s", .remaining.as_secs())]
+pub struct CircuitOpen {
+    service: &'static str,
+    remaining: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(service: &'static str, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            service,
+            threshold,
+            cooldown,
+            circuit: Arc::default(),
+        }
+    }
+
+    /// Fails if the service should not be called right now.
+    pub fn check(&self) -> Result<(), CircuitOpen> {
+        let circuit = self.circuit.lock().expect("lock is never poisoned");
+
+        match circuit.open_until {
+            Some(open_until) if open_until > Instant::now() => Err(CircuitOpen {
+                service: self.service,
+                remaining: open_until.saturating_duration_since(Instant::now()),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut circuit = self.circuit.lock().expect("lock is never poisoned");
+
+        if circuit.open_until.is_some() {
+            tracing::info!(service = %self.service, "Service recovered");
+        }
+        *circuit = Circuit::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut circuit = self.circuit.lock().expect("lock is never poisoned");
+
+        circuit.consecutive_failures += 1;
+        if circuit.consecutive_failures >= self.threshold {
+            tracing::warn!(
+                service = %self.service,
+                failures = %circuit.consecutive_failures,
+                "Service keeps failing, not calling it for {}s",
+                self.cooldown.as_secs()
+            );
+            circuit.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures_until_cooldown_passed() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_millis(50));
+
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+
+        // The probing call failed as well.
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn success_closes_the_circuit() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+
+        assert!(breaker.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn gives_up_once_the_policy_is_exhausted() {
+        let attempts = AtomicU32::new(0);
+        let policy = Policy {
+            initial_interval: Duration::from_millis(10),
+            max_interval: Duration::from_millis(10),
+            max_elapsed_time: Some(Duration::from_millis(100)),
+        };
+
+        let result = retry("test", policy, || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>(Error::Transient(anyhow::anyhow!("unavailable"))) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert!(attempts.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let attempts = AtomicU32::new(0);
+
+        let result = retry("test", Policy::PEER_REQUEST, || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>(Error::Permanent(anyhow::anyhow!("rejected"))) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -23,19 +23,30 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use structopt::clap;
 use structopt::clap::ErrorKind;
+use swap::asb::accounting::{self, DailyReport, SwapReport};
 use swap::asb::command::{parse_args, Arguments, Command};
 use swap::asb::config::{
     initial_setup, query_user_for_initial_config, read_config, Config, ConfigNotInitialized,
 };
-use swap::asb::{cancel, punish, redeem, refund, safely_abort, EventLoop, Finality, KrakenRate};
-use swap::database::Database;
+use swap::asb::metrics::{self, METRICS};
+use swap::asb::notifications::{self, Notifier, Thresholds};
+use swap::asb::rpc::{self, RpcServer};
+use swap::asb::withdraw::{sweep_to_cold_wallet, withdraw_btc, withdraw_xmr};
+use swap::asb::{
+    cancel, punish, redeem, refund, safely_abort, spread, EventLoop, Finality, KrakenRate,
+    PriceGuard,
+};
+use swap::database::{self, Database, SwapExport};
+use swap::encryption::Passphrase;
 use swap::monero::Amount;
 use swap::network::rendezvous::XmrBtcNamespace;
 use swap::network::swarm;
-use swap::protocol::alice::run;
+use swap::protocol::alice::{run_until_shutdown, KeyPool};
 use swap::seed::Seed;
+use swap::shutdown::Shutdown;
 use swap::tor::AuthenticatedClient;
 use swap::{asb, bitcoin, kraken, monero, tor};
+use tokio::sync::mpsc;
 use tracing_subscriber::filter::LevelFilter;
 
 const DEFAULT_WALLET_NAME: &str = "asb-wallet";
@@ -68,6 +79,17 @@ async fn main() -> Result<()> {
 
     asb::tracing::init(LevelFilter::DEBUG, json).expect("initialize tracing");
 
+    if let Command::InitConfig { force } = cmd {
+        if config_path.exists() && !force {
+            bail!(
+                "Config file {} already exists, pass --force to overwrite it",
+                config_path.display()
+            );
+        }
+
+        return initial_setup(config_path, query_user_for_initial_config(testnet)?);
+    }
+
     let config = match read_config(config_path.clone())? {
         Ok(config) => config,
         Err(ConfigNotInitialized {}) => {
@@ -91,11 +113,24 @@ async fn main() -> Result<()> {
 
     let db_path = config.data.dir.join("database");
 
-    let db = Database::open(config.data.dir.join(db_path).as_path())
-        .context("Could not open database")?;
+    let passphrase = Passphrase::from_env()?;
 
-    let seed =
-        Seed::from_file_or_generate(&config.data.dir).expect("Could not retrieve/initialize seed");
+    let db = match &passphrase {
+        Some(passphrase) => Database::open_encrypted(db_path.as_path(), passphrase),
+        None => Database::open(db_path.as_path()),
+    }
+    .context("Could not open database")?;
+
+    // Unlike the CLI, the ASB has no command to migrate the database.
+    for (migration, changes) in db.migrate(false)? {
+        tracing::info!(version = %migration.version, %changes, "Applied migration: {}", migration.description);
+    }
+
+    let seed = match &passphrase {
+        Some(passphrase) => Seed::from_encrypted_file_or_generate(&config.data.dir, passphrase),
+        None => Seed::from_file_or_generate(&config.data.dir),
+    }
+    .expect("Could not retrieve/initialize seed");
 
     match cmd {
         Command::Start { resume_only } => {
@@ -119,6 +154,29 @@ async fn main() -> Result<()> {
 
             let kraken_price_updates = kraken::connect(config.maker.price_ticker_ws_url.clone())?;
 
+            let mut kraken_rate = KrakenRate::new(
+                spread::from_config(&config.maker),
+                kraken_price_updates.clone(),
+            );
+            if let Some(url) = config.maker.secondary_price_ticker_ws_url.clone() {
+                let secondary_price_updates = kraken::connect(url)?;
+                kraken_rate = kraken_rate.with_guard(PriceGuard::new(
+                    secondary_price_updates,
+                    config.maker.max_price_deviation,
+                ));
+            }
+
+            let mut price_updates = kraken_price_updates;
+            let observer = kraken_rate.clone();
+            tokio::spawn(async move {
+                while let Ok(update) = price_updates.wait_for_next_update().await {
+                    if let Ok(update) = update {
+                        METRICS.price_updated();
+                        observer.observe(update.ask);
+                    }
+                }
+            });
+
             // setup Tor hidden services
             let tor_client =
                 tor::Client::new(config.tor.socks5_port).with_control_port(config.tor.control_port);
@@ -136,24 +194,21 @@ async fn main() -> Result<()> {
                 }
             };
 
-            let kraken_rate = KrakenRate::new(config.maker.ask_spread, kraken_price_updates);
+            let key_pool = KeyPool::default();
+            tokio::spawn(key_pool.clone().run(KeyPool::DEFAULT_SIZE));
+
+            let identity = seed.libp2p_identity(&config.data.dir, passphrase.as_ref())?;
             let mut swarm = swarm::asb(
-                &seed,
+                identity.clone(),
                 config.maker.min_buy_btc,
                 config.maker.max_buy_btc,
                 kraken_rate.clone(),
                 resume_only,
                 env_config,
-                config.network.rendezvous_point.map(|rendezvous_point| {
-                    (
-                        rendezvous_point,
-                        if testnet {
-                            XmrBtcNamespace::Testnet
-                        } else {
-                            XmrBtcNamespace::Mainnet
-                        },
-                    )
-                }),
+                XmrBtcNamespace::from_is_testnet(testnet),
+                &config.network.rendezvous_point,
+                config.network.connection_limits,
+                key_pool,
             )?;
 
             for listen in config.network.listen.clone() {
@@ -171,36 +226,185 @@ async fn main() -> Result<()> {
                 );
             }
 
+            let bitcoin_wallet = Arc::new(bitcoin_wallet);
+            let monero_wallet = Arc::new(monero_wallet);
+            let db = Arc::new(db);
+
+            if !config.monero.daemon_addresses.is_empty() {
+                let mut node_pool = monero::NodePool::new(config.monero.daemon_addresses.clone())?;
+                let monero_daemon_address = node_pool.select_healthiest().await.to_owned();
+                monero_wallet.set_daemon(monero_daemon_address).await?;
+
+                tokio::spawn(node_pool.run(monero_wallet.clone()));
+            }
+
+            tokio::spawn(monero::wallet::refresh_when_idle(
+                monero_wallet.clone(),
+                env_config.monero_avg_block_time,
+            ));
+
+            if let Some(cold_wallet) = config.monero.cold_wallet.clone() {
+                tracing::info!(address = %cold_wallet.address, max_hot_balance = %cold_wallet.max_hot_balance, "Sweeping Monero above the maximum hot wallet balance to the cold wallet");
+
+                let monero_wallet = monero_wallet.clone();
+                let db = db.clone();
+
+                tokio::spawn(async move {
+                    if let Err(error) = sweep_to_cold_wallet(cold_wallet, monero_wallet, db).await {
+                        tracing::error!("Stopped sweeping Monero to the cold wallet: {:#}", error);
+                    }
+                });
+            }
+
             let (event_loop, mut swap_receiver) = EventLoop::new(
                 swarm,
+                identity,
                 env_config,
-                Arc::new(bitcoin_wallet),
-                Arc::new(monero_wallet),
-                Arc::new(db),
+                bitcoin_wallet.clone(),
+                monero_wallet.clone(),
+                db.clone(),
                 kraken_rate.clone(),
                 config.maker.min_buy_btc,
                 config.maker.max_buy_btc,
+                config.maker.require_funding_proof,
+                config.maker.attach_reserve_proof,
+                config.maker.allow_early_refund,
+                config.network.connection_limits.max_established_per_ip,
             )
             .unwrap();
 
-            tokio::spawn(async move {
-                while let Some(swap) = swap_receiver.recv().await {
-                    let rate = kraken_rate.clone();
-                    tokio::spawn(async move {
+            if let Some(metrics) = config.metrics {
+                let bitcoin_wallet = bitcoin_wallet.clone();
+                let monero_wallet = monero_wallet.clone();
+
+                tokio::spawn(async move {
+                    if let Err(error) =
+                        metrics::serve(metrics.listen, bitcoin_wallet, monero_wallet).await
+                    {
+                        tracing::error!("Metrics server stopped: {:#}", error);
+                    }
+                });
+            }
+
+            let notifier = match &config.notifications {
+                Some(notifications) => {
+                    let notifier = Notifier::new(notifications)?;
+                    let thresholds = Thresholds::from_config(notifications);
+
+                    tokio::spawn(notifications::watch_health(
+                        notifier.clone(),
+                        thresholds,
+                        bitcoin_wallet.clone(),
+                        monero_wallet.clone(),
+                    ));
+
+                    notifier
+                }
+                None => Notifier::disabled(),
+            };
+
+            if let Some(rpc_config) = config.rpc {
+                let cookie_file = config.data.dir.join(rpc::COOKIE_FILE);
+                let auth =
+                    rpc::Auth::generate(&cookie_file, rpc_config.listen, rpc_config.allowed_hosts)?;
+                tracing::info!("Wrote RPC token to {}", cookie_file.display());
+
+                let rpc_server = RpcServer::new(
+                    db.clone(),
+                    bitcoin_wallet,
+                    monero_wallet.clone(),
+                    kraken_rate.clone(),
+                    event_loop.quote_switch(),
+                    event_loop.peer_bans(),
+                    config.maker.min_buy_btc,
+                    config.maker.max_buy_btc,
+                    rpc_config.dashboard,
+                    auth,
+                );
+
+                tokio::spawn(async move {
+                    if let Err(error) = rpc_server.run(rpc_config.listen).await {
+                        tracing::error!("RPC server stopped: {:#}", error);
+                    }
+                });
+            }
+
+            let mut shutdown = Shutdown::on_signal();
+            // Every running swap holds a sender, the receiver resolves once all of them
+            // are dropped.
+            let (running_swap, mut no_running_swaps) = mpsc::channel::<()>(1);
+
+            let swap_runner = {
+                let shutdown = shutdown.clone();
+
+                tokio::spawn(async move {
+                    while let Some(swap) = swap_receiver.recv().await {
                         let swap_id = swap.swap_id;
-                        match run(swap, rate).await {
-                            Ok(state) => {
-                                tracing::debug!(%swap_id, final_state=%state, "Swap completed")
-                            }
-                            Err(error) => {
-                                tracing::error!(%swap_id, "Swap failed: {:#}", error)
+
+                        // Swaps set up while shutting down are resumed on the next start.
+                        if shutdown.is_requested() {
+                            let state = database::Swap::Alice((&swap.state).into());
+                            if let Err(error) = swap.db.insert_latest_state(swap_id, state).await {
+                                tracing::error!(%swap_id, "Failed to store swap: {:#}", error);
                             }
+                            continue;
                         }
-                    });
-                }
-            });
 
-            event_loop.run().await;
+                        let rate = kraken_rate.clone();
+                        let shutdown = shutdown.clone();
+                        let running_swap = running_swap.clone();
+                        let db = swap.db.clone();
+                        let notifier = notifier.clone();
+                        tokio::spawn(async move {
+                            match run_until_shutdown(swap, rate, shutdown).await {
+                                Ok(Some(state)) => {
+                                    METRICS.swap_finished(&state);
+                                    notifier.swap_finished(swap_id, &state);
+                                    tracing::debug!(%swap_id, final_state=%state, "Swap completed")
+                                }
+                                Ok(None) => {}
+                                Err(error) => {
+                                    METRICS.swap_failed();
+                                    tracing::error!(%swap_id, "Swap failed: {:#}", error);
+                                    notifier.swap_failed(&db, swap_id);
+                                }
+                            }
+
+                            drop(running_swap);
+                        });
+                    }
+                })
+            };
+
+            let event_loop = tokio::spawn(event_loop.run());
+
+            shutdown.requested().await;
+
+            // Stops accepting new swaps and closes all connections. Transitions that
+            // cannot be interrupted do not need the network.
+            event_loop.abort();
+            let _ = event_loop.await;
+
+            // Ends once the event loop is gone, after storing swaps that were set up
+            // while shutting down.
+            let _ = swap_runner.await;
+            let _ = no_running_swaps.recv().await;
+
+            monero_wallet
+                .close()
+                .await
+                .context("Failed to close Monero wallet")?;
+
+            let suspended = db.unfinished_alice()?;
+
+            for (swap_id, state) in &suspended {
+                tracing::info!(%swap_id, %state, "Suspended swap");
+            }
+
+            tracing::info!(
+                suspended_swaps = suspended.len(),
+                "Stopped the ASB, suspended swaps are resumed on the next start. Use `asb start --resume-only` to only resume them without accepting new swaps"
+            );
         }
         Command::History => {
             let mut table = Table::new();
@@ -216,21 +420,13 @@ async fn main() -> Result<()> {
         Command::WithdrawBtc { amount, address } => {
             let bitcoin_wallet = init_bitcoin_wallet(&config, &seed, env_config).await?;
 
-            let amount = match amount {
-                Some(amount) => amount,
-                None => {
-                    bitcoin_wallet
-                        .max_giveable(address.script_pubkey().len())
-                        .await?
-                }
-            };
-
-            let psbt = bitcoin_wallet
-                .send_to_address(address, amount, None)
-                .await?;
-            let signed_tx = bitcoin_wallet.sign_and_finalize(psbt).await?;
+            withdraw_btc(&bitcoin_wallet, address, amount).await?;
+        }
+        Command::WithdrawXmr { amount, address } => {
+            let monero_wallet = init_monero_wallet(&config, env_config).await?;
+            let swaps = db.all_alice()?.into_iter().map(|(_, state)| state);
 
-            bitcoin_wallet.broadcast(signed_tx, "withdraw").await?;
+            withdraw_xmr(&monero_wallet, swaps, address, amount).await?;
         }
         Command::Balance => {
             let bitcoin_wallet = init_bitcoin_wallet(&config, &seed, env_config).await?;
@@ -244,6 +440,25 @@ async fn main() -> Result<()> {
                 %monero_balance,
                 "Current balance");
         }
+        Command::PeerId => {
+            let identity = seed.libp2p_identity(&config.data.dir, passphrase.as_ref())?;
+
+            println!("{}", identity.public().into_peer_id());
+        }
+        Command::RotateIdentity => {
+            let unfinished_swaps = db.unfinished_alice()?;
+            if !unfinished_swaps.is_empty() {
+                bail!(
+                    "Cannot rotate the identity while {} swaps are unfinished, takers could no longer reach the ASB to complete them",
+                    unfinished_swaps.len()
+                );
+            }
+
+            let identity = Seed::rotate_libp2p_identity(&config.data.dir, passphrase.as_ref())?;
+            let peer_id = identity.public().into_peer_id();
+
+            tracing::info!(%peer_id, "Rotated network identity");
+        }
         Command::Cancel { swap_id, force } => {
             let bitcoin_wallet = init_bitcoin_wallet(&config, &seed, env_config).await?;
 
@@ -275,6 +490,78 @@ async fn main() -> Result<()> {
 
             tracing::info!("Punish transaction successfully published with id {}", txid);
         }
+        Command::Receipt { swap_id, output } => {
+            let receipt = db.get_receipt(swap_id)?;
+
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, receipt.to_json()).with_context(|| {
+                        format!("Failed to write receipt to {}", output.display())
+                    })?;
+
+                    tracing::info!(%swap_id, "Wrote receipt to {}", output.display());
+                }
+                None => println!("{}", receipt.to_json()),
+            }
+        }
+        Command::Export { swap_id, output } => {
+            let export = db.export_swap(swap_id)?;
+            let file = export.encrypt(&seed.derive_swap_export_key())?;
+
+            std::fs::write(&output, file)
+                .with_context(|| format!("Failed to write swap export to {}", output.display()))?;
+
+            tracing::info!(%swap_id, "Exported swap to {}", output.display());
+        }
+        Command::Import { input } => {
+            let file = std::fs::read(&input)
+                .with_context(|| format!("Failed to read swap export from {}", input.display()))?;
+            let export = SwapExport::decrypt(&file, &seed.derive_swap_export_key())?;
+            let swap_id = export.swap_id;
+
+            export
+                .state
+                .clone()
+                .try_into_alice()
+                .context("Swap export does not contain a swap of the ASB")?;
+            db.import_swap(export).await?;
+
+            tracing::info!(%swap_id, "Imported swap, it is resumed on the next start");
+        }
+        Command::Report {
+            from,
+            to,
+            csv,
+            daily,
+        } => {
+            let swaps = accounting::swap_reports(&db, from, to)?;
+
+            let (header, rows): (&[&str], Vec<Vec<String>>) = if daily {
+                let days = accounting::daily_reports(&swaps);
+                (
+                    &DailyReport::HEADER,
+                    days.iter().map(DailyReport::row).collect(),
+                )
+            } else {
+                (
+                    &SwapReport::HEADER,
+                    swaps.iter().map(SwapReport::row).collect(),
+                )
+            };
+
+            if csv {
+                print!("{}", accounting::to_csv(header, rows));
+            } else {
+                let mut table = Table::new();
+
+                table.set_header(header.to_vec());
+                for row in rows {
+                    table.add_row(row);
+                }
+
+                println!("{}", table);
+            }
+        }
         Command::SafelyAbort { swap_id } => {
             safely_abort(swap_id, Arc::new(db)).await?;
 
@@ -298,6 +585,7 @@ async fn main() -> Result<()> {
 
             tracing::info!("Redeem transaction successfully published with id {}", txid);
         }
+        Command::InitConfig { .. } => unreachable!("handled before the config is read"),
     }
 
     Ok(())
@@ -317,6 +605,7 @@ async fn init_bitcoin_wallet(
         seed.derive_extended_private_key(env_config.bitcoin_network)?,
         env_config,
         config.bitcoin.target_block,
+        config.bitcoin.max_tx_fee_btc,
     )
     .await
     .context("Failed to initialize Bitcoin wallet")?;
@@ -12,29 +12,34 @@
 #![forbid(unsafe_code)]
 #![allow(non_snake_case)]
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use comfy_table::Table;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Confirm;
+use libp2p::identity;
 use qrcode::render::unicode;
 use qrcode::QrCode;
-use std::cmp::min;
 use std::env;
-use std::future::Future;
-use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
-use swap::bitcoin::TxLock;
+use swap::bitcoin::{ExpiredTimelocks, TxLock};
 use swap::cli::command::{parse_args_and_apply_defaults, Arguments, Command, ParseResult};
-use swap::cli::{list_sellers, EventLoop, SellerStatus};
-use swap::database::Database;
+use swap::cli::emergency_kit::EmergencyKit;
+use swap::cli::view_key::ViewKeyExport;
+use swap::cli::{list_sellers, select_sellers, EventLoop, Reserve, SellerStatus};
+use swap::database::{self, Database, SwapExport};
+use swap::encryption::Passphrase;
 use swap::env::Config;
 use swap::libp2p_ext::MultiAddrExt;
-use swap::network::quote::BidQuote;
+use swap::network::swap_setup::SessionNonce;
 use swap::network::swarm;
 use swap::protocol::bob;
-use swap::protocol::bob::Swap;
+use swap::protocol::bob::swap::is_complete;
+use swap::protocol::bob::{Action, BobState, Swap};
 use swap::seed::Seed;
-use swap::{bitcoin, cli, monero};
-use url::Url;
+use swap::shutdown::Shutdown;
+use swap::{api, bitcoin, cli, monero};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
 #[tokio::main]
@@ -44,236 +49,483 @@ async fn main() -> Result<()> {
         data_dir,
         debug,
         json,
+        hardware_wallet,
         cmd,
-    } = match parse_args_and_apply_defaults(env::args_os())? {
+    } = match parse_args_and_apply_defaults(env::args_os(), cli::config::Config::read)? {
         ParseResult::Arguments(args) => args,
         ParseResult::PrintAndExitZero { message } => {
             println!("{}", message);
             std::process::exit(0);
         }
     };
+    let context = api::Context {
+        env_config,
+        data_dir: data_dir.clone(),
+        passphrase: Passphrase::from_env()?,
+        hardware_wallet,
+    };
 
     match cmd {
         Command::BuyXmr {
             seller,
-            bitcoin_electrum_rpc_url,
+            bitcoin_electrum_rpc_urls,
             bitcoin_target_block,
             bitcoin_change_address,
             monero_receive_address,
-            monero_daemon_address,
+            monero_amount,
+            monero_daemon_addresses,
+            coin_control,
             tor_socks5_port,
+            max_effective_price,
         } => {
-            let swap_id = Uuid::new_v4();
+            let request = api::buy_xmr::Request {
+                session_nonce: SessionNonce::random(),
+                seller,
+                bitcoin_electrum_rpc_urls,
+                bitcoin_target_block,
+                bitcoin_change_address,
+                monero_receive_address,
+                monero_amount,
+                monero_daemon_addresses,
+                coin_control,
+                tor_socks5_port,
+                max_effective_price,
+                confirm: match max_effective_price {
+                    Some(_) => None,
+                    None => Some(Box::new(confirm_costs)),
+                },
+                on_deposit_address: if json {
+                    None
+                } else {
+                    Some(Box::new(print_qr_code))
+                },
+            };
+            let swap_id = request.swap_id()?;
 
             cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
-            let db = Database::open(data_dir.join("database").as_path())
-                .context("Failed to open database")?;
-            let seed = Seed::from_file_or_generate(data_dir.as_path())
-                .context("Failed to read in seed file")?;
-
-            let bitcoin_wallet = init_bitcoin_wallet(
-                bitcoin_electrum_rpc_url,
-                &seed,
-                data_dir.clone(),
-                env_config,
-                bitcoin_target_block,
-            )
-            .await?;
+
+            let response = request.call(&context, Shutdown::on_signal()).await?;
+
+            if response.state.is_none() {
+                log_suspended(swap_id);
+            }
+        }
+        Command::BuyXmrSplit {
+            rendezvous_point,
+            namespace,
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+            bitcoin_change_address,
+            monero_receive_address,
+            monero_amount,
+            monero_daemon_addresses,
+            coin_control,
+            tor_socks5_port,
+        } => {
+            let rendezvous_node_peer_id = rendezvous_point
+                .extract_peer_id()
+                .context("Rendezvous node address must contain peer ID")?;
+
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
+            let db = context.open_database()?;
+            let seed = context.read_seed()?;
+            let identity = context.libp2p_identity(&seed)?;
+
+            let coin_control = cli::config::Config::read(&data_dir)?
+                .coin_control
+                .merge(coin_control);
+            let bitcoin_wallet = context
+                .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+                .await?
+                .with_coin_control(coin_control);
+            let emergency_kit_dir = data_dir.clone();
             let (monero_wallet, _process) =
-                init_monero_wallet(data_dir, monero_daemon_address, env_config).await?;
+                context.init_monero_wallet(monero_daemon_addresses).await?;
             let bitcoin_wallet = Arc::new(bitcoin_wallet);
 
-            let seller_peer_id = seller
-                .extract_peer_id()
-                .context("Seller address must contain peer ID")?;
-            db.insert_address(seller_peer_id, seller.clone()).await?;
-
-            let behaviour = cli::Behaviour::new(seller_peer_id, env_config, bitcoin_wallet.clone());
-            let mut swarm =
-                swarm::cli(seed.derive_libp2p_identity(), tor_socks5_port, behaviour).await?;
-            swarm.behaviour_mut().add_address(seller_peer_id, seller);
-
-            tracing::debug!(peer_id = %swarm.local_peer_id(), "Network layer initialized");
-
-            let (event_loop, mut event_loop_handle) =
-                EventLoop::new(swap_id, swarm, seller_peer_id, env_config)?;
-            let event_loop = tokio::spawn(event_loop.run());
-
-            let max_givable = || bitcoin_wallet.max_giveable(TxLock::script_size());
-            let (amount, fees) = determine_btc_to_swap(
-                json,
-                event_loop_handle.request_quote(),
-                bitcoin_wallet.new_address(),
-                || bitcoin_wallet.balance(),
-                max_givable,
-                || bitcoin_wallet.sync(),
+            let sellers = list_sellers(
+                rendezvous_node_peer_id,
+                rendezvous_point,
+                namespace,
+                tor_socks5_port,
+                identity.clone(),
+                None,
             )
             .await?;
+            remember_sellers(&db, &sellers).await?;
+            let quotes = sellers
+                .into_iter()
+                .filter_map(|seller| match seller.status {
+                    SellerStatus::Online(quote) => Some((seller.multiaddr, quote)),
+                    SellerStatus::Refused(_) | SellerStatus::Unreachable => None,
+                });
+            let legs = cli::split::split(monero_amount, quotes)?;
+
+            let mut total_btc_amount = bitcoin::Amount::ZERO;
+            for leg in &legs {
+                tracing::info!(seller = %leg.seller, btc_amount = %leg.btc_amount, monero_amount = %leg.monero_amount, "Planned swap");
+                total_btc_amount += leg.btc_amount;
+            }
 
-            tracing::info!(%amount, %fees, %swap_id,  "Starting new swap");
+            let max_giveable = bitcoin_wallet.max_giveable(TxLock::script_size()).await?;
+            if max_giveable < total_btc_amount {
+                bail!(
+                    "Insufficient funds, the swaps require {} but only {} can be spent. Deposit to {} and try again",
+                    total_btc_amount,
+                    max_giveable,
+                    bitcoin_wallet.new_address().await?
+                )
+            }
 
-            db.insert_peer_id(swap_id, seller_peer_id).await?;
-            db.insert_monero_address(swap_id, monero_receive_address)
-                .await?;
+            // The swaps lock their Bitcoin one after another, otherwise their lock
+            // transactions could spend the same outputs. Everything after that runs
+            // concurrently.
+            let redeem_lock = Arc::new(tokio::sync::Mutex::new(()));
+            let mut running = Vec::new();
+            let mut failed = 0;
+
+            for leg in legs {
+                let session_nonce = SessionNonce::random();
+                let swap_id = session_nonce.swap_id(
+                    leg.seller
+                        .extract_peer_id()
+                        .context("Seller address must contain peer ID")?,
+                );
+
+                let locked = lock_bitcoin_for_leg(
+                    session_nonce,
+                    &leg,
+                    db.clone(),
+                    identity.clone(),
+                    bitcoin_wallet.clone(),
+                    monero_wallet.clone(),
+                    env_config,
+                    tor_socks5_port,
+                    monero_receive_address,
+                    bitcoin_change_address.clone(),
+                )
+                .await;
+
+                let (mut swap, event_loop) = match locked {
+                    Ok(locked) => locked,
+                    Err(error) => {
+                        tracing::error!(%swap_id, seller = %leg.seller, "Failed to lock Bitcoin, not starting any further swaps: {:#}", error);
+                        failed += 1;
+                        break;
+                    }
+                };
+
+                let emergency_kit_path =
+                    cli::emergency_kit::default_path(&emergency_kit_dir, swap_id);
+                match EmergencyKit::from_db(&db, swap_id)
+                    .and_then(|kit| kit.write_to(&emergency_kit_path))
+                {
+                    Ok(()) => {
+                        tracing::info!(%swap_id, "Wrote emergency kit to {}, copy it to another device to be able to refund without this machine", emergency_kit_path.display())
+                    }
+                    Err(error) => {
+                        tracing::warn!(%swap_id, "Failed to write emergency kit: {:#}", error)
+                    }
+                }
 
-            let swap = Swap::new(
-                db,
-                swap_id,
-                bitcoin_wallet,
-                Arc::new(monero_wallet),
-                env_config,
-                event_loop_handle,
-                monero_receive_address,
-                bitcoin_change_address,
-                amount,
-            );
+                bitcoin_wallet.sync().await?;
 
-            tokio::select! {
-                result = event_loop => {
-                    result
-                        .context("EventLoop panicked")?;
-                },
-                result = bob::run(swap) => {
-                    result.context("Failed to complete swap")?;
-                }
+                let redeem_lock = redeem_lock.clone();
+                let db = db.clone();
+                let handle = tokio::spawn(async move {
+                    let run = async {
+                        let state = bob::advance_until(&mut swap, is_btc_redeemed).await?;
+                        if is_complete(&state) {
+                            return Ok(state);
+                        }
+
+                        // Redeeming switches the wallet loaded by monero-wallet-rpc, so only one
+                        // swap can redeem at a time.
+                        let _guard = redeem_lock.lock().await;
+                        bob::advance_until(&mut swap, is_complete).await
+                    };
+
+                    tokio::select! {
+                        result = event_loop => {
+                            result.context("EventLoop panicked")?;
+
+                            match bob::swap::completed_state(&db, swap_id)? {
+                                Some(state) => {
+                                    tracing::warn!(%swap_id, "Lost the connection to the seller before the receipt was signed");
+                                    Ok(state)
+                                }
+                                None => bail!("EventLoop stopped before the swap completed"),
+                            }
+                        },
+                        result = run => result,
+                    }
+                });
+
+                running.push((swap_id, leg, handle));
+            }
+
+            let mut table = Table::new();
+
+            table.set_header(vec!["SWAP ID", "SELLER", "BTC", "XMR", "STATE"]);
+
+            for (swap_id, leg, handle) in running {
+                let state = match handle.await.context("Swap panicked")? {
+                    Ok(state) => {
+                        tracing::info!(%swap_id, %state, "Swap finished");
+                        state.to_string()
+                    }
+                    Err(error) => {
+                        tracing::error!(%swap_id, "Swap failed: {:#}", error);
+                        failed += 1;
+                        "failed".to_owned()
+                    }
+                };
+
+                table.add_row(vec![
+                    swap_id.to_string(),
+                    leg.seller.to_string(),
+                    leg.btc_amount.to_string(),
+                    leg.monero_amount.to_string(),
+                    state,
+                ]);
+            }
+
+            println!("{}", table);
+
+            if failed > 0 {
+                bail!(
+                    "{} of the planned swaps did not complete, resume started swaps using their swap id",
+                    failed
+                )
             }
         }
-        Command::History => {
-            let db = Database::open(data_dir.join("database").as_path())
-                .context("Failed to open database")?;
+        Command::History { swap_id } => {
+            let response = api::history::Request { swap_id }.call(&context).await?;
 
             let mut table = Table::new();
 
-            table.set_header(vec!["SWAP ID", "STATE"]);
+            match response {
+                api::history::Response::Swaps(swaps) => {
+                    table.set_header(vec!["SWAP ID", "STATE"]);
 
-            for (swap_id, state) in db.all_bob()? {
-                table.add_row(vec![swap_id.to_string(), state.to_string()]);
+                    for (swap_id, state) in swaps {
+                        table.add_row(vec![swap_id.to_string(), state.to_string()]);
+                    }
+                }
+                api::history::Response::Transitions {
+                    transitions,
+                    recorded,
+                } => {
+                    if !recorded {
+                        println!(
+                            "No history recorded for swap {}, showing latest state",
+                            swap_id.expect("transitions are only returned for a single swap")
+                        );
+                    }
+
+                    table.set_header(vec!["TIME (UTC)", "STATE", "DETAILS"]);
+
+                    for transition in transitions {
+                        table.add_row(vec![
+                            OffsetDateTime::from(transition.timestamp).format("%F %T"),
+                            transition.state,
+                            transition.details,
+                        ]);
+                    }
+                }
             }
 
             println!("{}", table);
         }
+        Command::Balance {
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+        } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
+
+            let api::balance::Response {
+                balance,
+                max_giveable,
+            } = api::balance::Request {
+                bitcoin_electrum_rpc_urls,
+                bitcoin_target_block,
+            }
+            .call(&context)
+            .await?;
+
+            tracing::info!(%balance, %max_giveable, "Checked Bitcoin balance");
+        }
         Command::Resume {
             swap_id,
-            bitcoin_electrum_rpc_url,
+            monitor_only,
+            bitcoin_electrum_rpc_urls,
             bitcoin_target_block,
-            monero_daemon_address,
+            monero_daemon_addresses,
+            coin_control,
             tor_socks5_port,
         } => {
             cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
-            let db = Database::open(data_dir.join("database").as_path())
-                .context("Failed to open database")?;
-            let seed = Seed::from_file_or_generate(data_dir.as_path())
-                .context("Failed to read in seed file")?;
-
-            let bitcoin_wallet = init_bitcoin_wallet(
-                bitcoin_electrum_rpc_url,
-                &seed,
-                data_dir.clone(),
-                env_config,
-                bitcoin_target_block,
-            )
-            .await?;
-            let (monero_wallet, _process) =
-                init_monero_wallet(data_dir, monero_daemon_address, env_config).await?;
-            let bitcoin_wallet = Arc::new(bitcoin_wallet);
 
-            let seller_peer_id = db.get_peer_id(swap_id)?;
-            let seller_addresses = db.get_addresses(seller_peer_id)?;
+            if monitor_only {
+                let db = context.open_database()?;
+                let seed = context.read_seed()?;
+                let bitcoin_wallet = context
+                    .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+                    .await?;
+
+                let state = BobState::from(db.get_state(swap_id)?.try_into_bob()?);
+                let monero_wallet = if cli::monitor::watches_monero(&state) {
+                    Some(context.init_monero_wallet(monero_daemon_addresses).await?)
+                } else {
+                    None
+                };
+
+                cli::monitor::monitor(
+                    swap_id,
+                    state,
+                    &bitcoin_wallet,
+                    monero_wallet.as_ref().map(|(wallet, _)| wallet.as_ref()),
+                    env_config,
+                )
+                .await?;
 
-            let behaviour = cli::Behaviour::new(seller_peer_id, env_config, bitcoin_wallet.clone());
-            let mut swarm =
-                swarm::cli(seed.derive_libp2p_identity(), tor_socks5_port, behaviour).await?;
-            let our_peer_id = swarm.local_peer_id();
-            tracing::debug!(peer_id = %our_peer_id, "Network layer initialized");
+                return Ok(());
+            }
 
-            for seller_address in seller_addresses {
-                swarm
-                    .behaviour_mut()
-                    .add_address(seller_peer_id, seller_address);
+            let response = api::resume::Request {
+                swap_id,
+                bitcoin_electrum_rpc_urls,
+                bitcoin_target_block,
+                monero_daemon_addresses,
+                coin_control,
+                tor_socks5_port,
             }
+            .call(&context, Shutdown::on_signal())
+            .await?;
 
-            let (event_loop, event_loop_handle) =
-                EventLoop::new(swap_id, swarm, seller_peer_id, env_config)?;
-            let handle = tokio::spawn(event_loop.run());
+            if response.state.is_none() {
+                log_suspended(swap_id);
+            }
+        }
+        Command::Status {
+            swap_id,
+            verbose,
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+        } => {
+            let db = context.open_database()?;
 
-            let monero_receive_address = db.get_monero_address(swap_id)?;
-            let swap = Swap::from_db(
-                db,
-                swap_id,
-                bitcoin_wallet,
-                Arc::new(monero_wallet),
-                env_config,
-                event_loop_handle,
-                monero_receive_address,
-            )?;
+            let state = BobState::from(db.get_state(swap_id)?.try_into_bob()?);
+            let kind = state.kind();
 
-            tokio::select! {
-                event_loop_result = handle => {
-                    event_loop_result?;
-                },
-                swap_result = bob::run(swap) => {
-                    swap_result?;
+            let mut table = Table::new();
+
+            table.add_row(vec!["SWAP ID".to_owned(), swap_id.to_string()]);
+            table.add_row(vec!["STATE".to_owned(), state.to_string()]);
+            table.add_row(vec!["DETAILS".to_owned(), state.details()]);
+            table.add_row(vec![
+                "NEXT STATES".to_owned(),
+                kind.successors()
+                    .iter()
+                    .map(|successor| successor.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ]);
+            table.add_row(vec![
+                "ACTIONS".to_owned(),
+                kind.actions()
+                    .into_iter()
+                    .map(action_help)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ]);
+
+            if verbose {
+                let seed = context.read_seed()?;
+                let bitcoin_wallet = context
+                    .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+                    .await?;
+
+                if let Some(expired_timelocks) = state.expired_timelocks(&bitcoin_wallet).await? {
+                    let timelocks = match expired_timelocks {
+                        ExpiredTimelocks::None => {
+                            "no timelock expired, the swap cannot be cancelled yet"
+                        }
+                        ExpiredTimelocks::Cancel => {
+                            "cancel timelock expired, the swap can be cancelled and refunded"
+                        }
+                        ExpiredTimelocks::Punish => {
+                            "punish timelock expired, the seller can punish"
+                        }
+                    };
+                    table.add_row(vec!["TIMELOCKS".to_owned(), timelocks.to_owned()]);
+                }
+
+                if let BobState::CancelTimelockExpired(state6) | BobState::BtcCancelled(state6) =
+                    &state
+                {
+                    let blocks_left = state6.blocks_until_punish(&bitcoin_wallet).await?;
+                    table.add_row(vec![
+                        "BLOCKS UNTIL PUNISH".to_owned(),
+                        blocks_left.to_string(),
+                    ]);
                 }
             }
+
+            println!("{}", table);
         }
         Command::Cancel {
             swap_id,
             force,
-            bitcoin_electrum_rpc_url,
+            bitcoin_electrum_rpc_urls,
             bitcoin_target_block,
         } => {
             cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
-            let db = Database::open(data_dir.join("database").as_path())
-                .context("Failed to open database")?;
-            let seed = Seed::from_file_or_generate(data_dir.as_path())
-                .context("Failed to read in seed file")?;
-
-            let bitcoin_wallet = init_bitcoin_wallet(
-                bitcoin_electrum_rpc_url,
-                &seed,
-                data_dir,
-                env_config,
-                bitcoin_target_block,
-            )
-            .await?;
 
-            let cancel = cli::cancel(swap_id, Arc::new(bitcoin_wallet), db, force).await?;
+            let cancel = api::cancel::Request {
+                swap_id,
+                force,
+                bitcoin_electrum_rpc_urls,
+                bitcoin_target_block,
+            }
+            .call(&context)
+            .await;
 
             match cancel {
-                Ok((txid, _)) => {
+                Ok(api::cancel::Response { txid, .. }) => {
                     tracing::debug!("Cancel transaction successfully published with id {}", txid)
                 }
-                Err(cli::cancel::Error::CancelTimelockNotExpiredYet) => tracing::error!(
-                    "The cancel transaction cannot be published yet, because the timelock has not expired. Please try again later"
-                ),
+                Err(error) => match error.downcast_ref::<cli::cancel::Error>() {
+                    Some(cli::cancel::Error::CancelTimelockNotExpiredYet) => tracing::error!(
+                        "The cancel transaction cannot be published yet, because the timelock has not expired. Please try again later"
+                    ),
+                    None => return Err(error),
+                },
             }
         }
         Command::Refund {
             swap_id,
             force,
-            bitcoin_electrum_rpc_url,
+            bitcoin_electrum_rpc_urls,
             bitcoin_target_block,
         } => {
             cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
-            let db = Database::open(data_dir.join("database").as_path())
-                .context("Failed to open database")?;
-            let seed = Seed::from_file_or_generate(data_dir.as_path())
-                .context("Failed to read in seed file")?;
-
-            let bitcoin_wallet = init_bitcoin_wallet(
-                bitcoin_electrum_rpc_url,
-                &seed,
-                data_dir,
-                env_config,
-                bitcoin_target_block,
-            )
-            .await?;
+            let db = context.open_database()?;
+            let seed = context.read_seed()?;
+
+            let bitcoin_wallet = context
+                .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+                .await?;
 
             cli::refund(swap_id, Arc::new(bitcoin_wallet), db, force).await??;
         }
         Command::ListSellers {
             rendezvous_point,
             namespace,
+            require_reserve_proof,
+            filter,
+            sort_by,
+            monero_daemon_addresses,
             tor_socks5_port,
         } => {
             let rendezvous_node_peer_id = rendezvous_point
@@ -281,19 +533,37 @@ async fn main() -> Result<()> {
                 .context("Rendezvous node address must contain peer ID")?;
 
             cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
-            let seed = Seed::from_file_or_generate(data_dir.as_path())
-                .context("Failed to read in seed file")?;
-            let identity = seed.derive_libp2p_identity();
+            let seed = context.read_seed()?;
+            let identity = context.libp2p_identity(&seed)?;
 
-            let sellers = list_sellers(
+            let monero_wallet = if require_reserve_proof {
+                Some(context.init_monero_wallet(monero_daemon_addresses).await?)
+            } else {
+                None
+            };
+
+            let mut sellers = list_sellers(
                 rendezvous_node_peer_id,
                 rendezvous_point,
                 namespace,
                 tor_socks5_port,
                 identity,
+                monero_wallet.as_ref().map(|(wallet, _)| wallet.clone()),
             )
             .await?;
 
+            // Remembering the sellers speeds up reconnecting to them later, the
+            // database is in use while a swap is running though.
+            match context.open_database() {
+                Ok(db) => remember_sellers(&db, &sellers).await?,
+                Err(error) => tracing::debug!("Not remembering sellers: {:#}", error),
+            }
+
+            if require_reserve_proof {
+                sellers.retain(|seller| seller.reserve == Reserve::Sufficient);
+            }
+            let sellers = select_sellers(sellers, filter, sort_by);
+
             if json {
                 for seller in sellers {
                     println!("{}", serde_json::to_string(&seller)?);
@@ -303,9 +573,11 @@ async fn main() -> Result<()> {
 
                 table.set_header(vec![
                     "PRICE",
+                    "EFFECTIVE_PRICE",
                     "MIN_QUANTITY",
                     "MAX_QUANTITY",
                     "STATUS",
+                    "RESERVE",
                     "ADDRESS",
                 ]);
 
@@ -314,9 +586,24 @@ async fn main() -> Result<()> {
                         SellerStatus::Online(quote) => {
                             vec![
                                 quote.price.to_string(),
+                                seller
+                                    .effective_price
+                                    .map_or_else(|| "???".to_owned(), |price| price.to_string()),
                                 quote.min_quantity.to_string(),
                                 quote.max_quantity.to_string(),
                                 "Online".to_owned(),
+                                seller.reserve.to_string(),
+                                seller.multiaddr.to_string(),
+                            ]
+                        }
+                        SellerStatus::Refused(error) => {
+                            vec![
+                                "???".to_owned(),
+                                "???".to_owned(),
+                                "???".to_owned(),
+                                "???".to_owned(),
+                                format!("Refused: {}", error),
+                                "???".to_owned(),
                                 seller.multiaddr.to_string(),
                             ]
                         }
@@ -325,7 +612,9 @@ async fn main() -> Result<()> {
                                 "???".to_owned(),
                                 "???".to_owned(),
                                 "???".to_owned(),
+                                "???".to_owned(),
                                 "Unreachable".to_owned(),
+                                "???".to_owned(),
                                 seller.multiaddr.to_string(),
                             ]
                         }
@@ -337,485 +626,386 @@ async fn main() -> Result<()> {
                 println!("{}", table);
             }
         }
-    };
-    Ok(())
-}
-
-async fn init_bitcoin_wallet(
-    electrum_rpc_url: Url,
-    seed: &Seed,
-    data_dir: PathBuf,
-    env_config: Config,
-    bitcoin_target_block: usize,
-) -> Result<bitcoin::Wallet> {
-    let wallet_dir = data_dir.join("wallet");
-
-    let wallet = bitcoin::Wallet::new(
-        electrum_rpc_url.clone(),
-        &wallet_dir,
-        seed.derive_extended_private_key(env_config.bitcoin_network)?,
-        env_config,
-        bitcoin_target_block,
-    )
-    .await
-    .context("Failed to initialize Bitcoin wallet")?;
+        Command::Export { swap_id, output } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
+            let db = context.open_database()?;
+            let seed = context.read_existing_seed()?;
 
-    wallet.sync().await?;
+            let export = db.export_swap(swap_id)?;
+            let file = export.encrypt(&seed.derive_swap_export_key())?;
 
-    Ok(wallet)
-}
+            std::fs::write(&output, file)
+                .with_context(|| format!("Failed to write swap export to {}", output.display()))?;
 
-async fn init_monero_wallet(
-    data_dir: PathBuf,
-    monero_daemon_address: String,
-    env_config: Config,
-) -> Result<(monero::Wallet, monero::WalletRpcProcess)> {
-    let network = env_config.monero_network;
+            tracing::info!(%swap_id, "Exported swap to {}", output.display());
+        }
+        Command::Import { input } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
+            let db = context.open_database()?;
+            let seed = context.read_existing_seed()?;
+
+            let file = std::fs::read(&input)
+                .with_context(|| format!("Failed to read swap export from {}", input.display()))?;
+            let export = SwapExport::decrypt(&file, &seed.derive_swap_export_key())?;
+            let swap_id = export.swap_id;
+
+            export
+                .state
+                .clone()
+                .try_into_bob()
+                .context("Swap export does not contain a swap of the CLI")?;
+            db.import_swap(export).await?;
+
+            tracing::info!(%swap_id, "Imported swap, it can now be resumed");
+        }
+        Command::EmergencyKit { swap_id, output } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
+            let db = context.open_database()?;
 
-    const MONERO_BLOCKCHAIN_MONITORING_WALLET_NAME: &str = "swap-tool-blockchain-monitoring-wallet";
+            let output =
+                output.unwrap_or_else(|| cli::emergency_kit::default_path(&data_dir, swap_id));
+            EmergencyKit::from_db(&db, swap_id)?.write_to(&output)?;
 
-    let monero_wallet_rpc = monero::WalletRpc::new(data_dir.join("monero")).await?;
+            tracing::info!(%swap_id, "Wrote emergency kit to {}, copy it to another device to be able to refund without this machine", output.display());
+        }
+        Command::Receipt { swap_id, output } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
+            let db = context.open_database()?;
 
-    let monero_wallet_rpc_process = monero_wallet_rpc
-        .run(network, monero_daemon_address.as_str())
-        .await?;
+            let receipt = db.get_receipt(swap_id)?;
 
-    let monero_wallet = monero::Wallet::open_or_create(
-        monero_wallet_rpc_process.endpoint(),
-        MONERO_BLOCKCHAIN_MONITORING_WALLET_NAME.to_string(),
-        env_config,
-    )
-    .await?;
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, receipt.to_json()).with_context(|| {
+                        format!("Failed to write receipt to {}", output.display())
+                    })?;
 
-    Ok((monero_wallet, monero_wallet_rpc_process))
-}
+                    tracing::info!(%swap_id, "Wrote receipt to {}", output.display());
+                }
+                None => println!("{}", receipt.to_json()),
+            }
+        }
+        Command::ExportViewKey { swap_id, output } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), Some(swap_id))?;
+            let db = context.open_database()?;
 
-fn qr_code(value: &impl ToString) -> Result<String> {
-    let code = QrCode::new(value.to_string())?;
-    let qr_code = code
-        .render::<unicode::Dense1x2>()
-        .dark_color(unicode::Dense1x2::Light)
-        .light_color(unicode::Dense1x2::Dark)
-        .build();
-    Ok(qr_code)
-}
+            let export = ViewKeyExport::from_db(&db, swap_id)?;
 
-async fn determine_btc_to_swap<FB, TB, FMG, TMG, FS, TS>(
-    json: bool,
-    bid_quote: impl Future<Output = Result<BidQuote>>,
-    get_new_address: impl Future<Output = Result<bitcoin::Address>>,
-    balance: FB,
-    max_giveable_fn: FMG,
-    sync: FS,
-) -> Result<(bitcoin::Amount, bitcoin::Amount)>
-where
-    TB: Future<Output = Result<bitcoin::Amount>>,
-    FB: Fn() -> TB,
-    TMG: Future<Output = Result<bitcoin::Amount>>,
-    FMG: Fn() -> TMG,
-    TS: Future<Output = Result<()>>,
-    FS: Fn() -> TS,
-{
-    tracing::debug!("Requesting quote");
-    let bid_quote = bid_quote.await?;
-    tracing::info!(
-        price = %bid_quote.price,
-        minimum_amount = %bid_quote.min_quantity,
-        maximum_amount = %bid_quote.max_quantity,
-        "Received quote",
-    );
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, export.to_json()).with_context(|| {
+                        format!("Failed to write view key to {}", output.display())
+                    })?;
 
-    let mut max_giveable = max_giveable_fn().await?;
+                    tracing::info!(%swap_id, "Wrote view key to {}", output.display());
+                }
+                None => println!("{}", export.to_json()),
+            }
+        }
+        Command::VerifyViewKey {
+            input,
+            monero_daemon_addresses,
+        } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
 
-    if max_giveable == bitcoin::Amount::ZERO || max_giveable < bid_quote.min_quantity {
-        let deposit_address = get_new_address.await?;
-        let minimum_amount = bid_quote.min_quantity;
-        let maximum_amount = bid_quote.max_quantity;
+            let file = std::fs::read_to_string(&input)
+                .with_context(|| format!("Failed to read view key from {}", input.display()))?;
+            let export = ViewKeyExport::from_json(&file)?;
+            export.check_view_key()?;
+            export.check_signature()?;
 
-        if !json {
-            eprintln!("{}", qr_code(&deposit_address)?);
-        }
+            let (monero_wallet, process) =
+                context.init_monero_wallet(monero_daemon_addresses).await?;
+            let received = export.verify(&monero_wallet, process.wallet_dir()).await?;
 
-        loop {
             tracing::info!(
-                %deposit_address,
-                %max_giveable,
-                %minimum_amount,
-                %maximum_amount,
-                "Waiting for Bitcoin deposit",
+                swap_id = %export.swap_id,
+                tx_lock_id = %export.tx_lock_id,
+                address = %export.address,
+                expected = %export.amount,
+                %received,
+                "The Monero of the swap was locked"
             );
+        }
+        Command::MigrateDb { dry_run, rollback } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
+            let path = context.database_path();
 
-            max_giveable = loop {
-                sync().await?;
-                let new_max_givable = max_giveable_fn().await?;
+            if rollback {
+                let replaced = database::rollback(&path)?;
 
-                if new_max_givable > max_giveable {
-                    break new_max_givable;
+                tracing::info!(
+                    "Restored the database from its backup, the replaced database was moved to {}",
+                    replaced.display()
+                );
+            } else {
+                let db = context.open_database()?;
+
+                if db.pending_migrations()?.is_empty() {
+                    tracing::info!(version = %db.schema_version()?, "The database is up to date");
+                } else if dry_run {
+                    for (migration, changes) in db.migrate(true)? {
+                        tracing::info!(version = %migration.version, %changes, "Would apply migration: {}", migration.description);
+                    }
+                } else {
+                    // The database has to be closed to copy it.
+                    drop(db);
+                    let backup = database::backup(&path)?;
+                    tracing::info!("Backed up the database to {}", backup.display());
+
+                    let db = context.open_database()?;
+                    for (migration, changes) in db.migrate(false)? {
+                        tracing::info!(version = %migration.version, %changes, "Applied migration: {}", migration.description);
+                    }
                 }
+            }
+        }
+        Command::PeerId => {
+            let seed = context.read_seed()?;
+            let identity = context.libp2p_identity(&seed)?;
 
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            };
+            println!("{}", identity.public().into_peer_id());
+        }
+        Command::RotateIdentity => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
+            let db = context.open_database()?;
+
+            let unfinished_swaps = db
+                .all_bob()?
+                .into_iter()
+                .map(|(_, state)| BobState::from(state))
+                .filter(|state| !is_complete(state))
+                .count();
+            if unfinished_swaps > 0 {
+                bail!(
+                    "Cannot rotate the identity while {} swaps are unfinished, the sellers could no longer send us their transfer proofs",
+                    unfinished_swaps
+                );
+            }
 
-            let new_balance = balance().await?;
-            tracing::info!(%new_balance, %max_giveable, "Received Bitcoin");
+            let identity = Seed::rotate_libp2p_identity(&data_dir, context.passphrase.as_ref())?;
+            let peer_id = identity.public().into_peer_id();
 
-            if max_giveable < bid_quote.min_quantity {
-                tracing::info!("Deposited amount is less than `min_quantity`");
-                continue;
-            }
+            tracing::info!(%peer_id, "Rotated network identity");
+        }
+        Command::ExportSeed => {
+            let seed = context.read_existing_seed()?;
 
-            break;
+            let backup = seed.to_backup(&data_dir, context.passphrase.as_ref())?;
+
+            eprintln!("Anyone who knows this seed can spend the Bitcoin of the CLI's wallet, keep it in a safe place");
+            println!("{}", backup);
         }
-    };
+        Command::ImportSeed {
+            bitcoin_electrum_rpc_urls,
+            bitcoin_target_block,
+        } => {
+            cli::tracing::init(debug, json, data_dir.join("logs"), None)?;
 
-    let balance = balance().await?;
-    let fees = balance - max_giveable;
+            eprintln!("Enter the seed printed by `swap wallet export-seed`:");
+            let mut encoded = String::new();
+            std::io::stdin()
+                .read_line(&mut encoded)
+                .context("Failed to read seed from stdin")?;
+            let seed =
+                Seed::restore_backup(&encoded, data_dir.as_path(), context.passphrase.as_ref())
+                    .context("Failed to restore seed")?;
+
+            let peer_id = context.libp2p_identity(&seed)?.public().into_peer_id();
+            tracing::info!(%peer_id, "Restored seed");
+
+            let bitcoin_wallet = Arc::new(
+                context
+                    .init_bitcoin_wallet(&seed, bitcoin_electrum_rpc_urls, bitcoin_target_block)
+                    .await?,
+            );
+            tracing::info!(balance = %bitcoin_wallet.balance().await?, "Restored Bitcoin wallet");
+
+            let db = context.open_database()?;
+
+            let pending = db
+                .all_bob()?
+                .into_iter()
+                .map(|(swap_id, state)| (swap_id, BobState::from(state)))
+                .filter(|(_, state)| !is_complete(state))
+                .collect::<Vec<_>>();
+
+            for (swap_id, state) in &pending {
+                tracing::info!(
+                    %swap_id,
+                    %state,
+                    "Swap is not finished, continue it with `swap resume --swap-id {}`",
+                    swap_id
+                );
+            }
 
-    let max_accepted = bid_quote.max_quantity;
+            if !pending.is_empty() {
+                tracing::info!(
+                    "Monitoring the unfinished swaps until their Bitcoin is redeemed or refunded, expired swaps are refunded automatically"
+                );
+
+                let monitors =
+                    futures::future::try_join_all(pending.into_iter().map(|(swap_id, state)| {
+                        cli::monitor::monitor(swap_id, state, &bitcoin_wallet, None, env_config)
+                    }));
+
+                tokio::select! {
+                    result = monitors => {
+                        result?;
+                    }
+                    _ = cli::auto_refund::refund_expired_swaps(
+                        bitcoin_wallet.clone(),
+                        db,
+                        None,
+                        env_config.bitcoin_avg_block_time,
+                    ) => unreachable!("refunding expired swaps never finishes"),
+                }
+            }
+        }
+        Command::InitConfig { force } => {
+            let path = cli::config::Config::path(&data_dir);
+            if path.exists() && !force {
+                bail!(
+                    "Config file {} already exists, pass --force to overwrite it",
+                    path.display()
+                );
+            }
 
-    let btc_swap_amount = min(max_giveable, max_accepted);
+            let testnet = env_config.bitcoin_network != bitcoin::Network::Bitcoin;
+            let path = cli::config::query_user_for_initial_config(testnet)?.write(&data_dir)?;
 
-    Ok((btc_swap_amount, fees))
+            println!("Config file written to {}", path.display());
+        }
+    };
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::determine_btc_to_swap;
-    use ::bitcoin::Amount;
-    use std::sync::Mutex;
-    use swap::tracing_ext::capture_logs;
-    use tracing::level_filters::LevelFilter;
-
-    #[tokio::test]
-    async fn given_no_balance_and_transfers_less_than_max_swaps_max_giveable() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::ZERO,
-            Amount::from_btc(0.0009).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_max(0.01)) },
-            get_dummy_address(),
-            || async { Ok(Amount::from_btc(0.001)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.0009).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
- INFO swap: Received Bitcoin new_balance=0.00100000 BTC max_giveable=0.00090000 BTC
-"
-        );
-    }
+fn confirm_costs(costs: &api::buy_xmr::CostPreview) -> Result<bool> {
+    eprintln!("\n{}\n", costs);
 
-    #[tokio::test]
-    async fn given_no_balance_and_transfers_more_then_swaps_max_quantity_from_quote() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::ZERO,
-            Amount::from_btc(0.1).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_max(0.01)) },
-            get_dummy_address(),
-            || async { Ok(Amount::from_btc(0.1001)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.01).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
- INFO swap: Received Bitcoin new_balance=0.10010000 BTC max_giveable=0.10000000 BTC
-"
-        );
-    }
+    Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Lock the Bitcoin and start the swap?")
+        .default(false)
+        .interact()
+        .context("Failed to ask for confirmation, use --max-effective-rate to swap without it")
+}
 
-    #[tokio::test]
-    async fn given_initial_balance_below_max_quantity_swaps_max_givable() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::from_btc(0.0049).unwrap(),
-            Amount::from_btc(99.9).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_max(0.01)) },
-            async { panic!("should not request new address when initial balance  is > 0") },
-            || async { Ok(Amount::from_btc(0.005)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.0049).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
-"
-        );
-    }
+/// Prints the deposit address as a QR code to stderr, only useful in a
+/// terminal.
+fn print_qr_code(deposit_address: &bitcoin::Address) -> Result<()> {
+    let qr_code = QrCode::new(deposit_address.to_string())?
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build();
+    eprintln!("{}", qr_code);
 
-    #[tokio::test]
-    async fn given_initial_balance_above_max_quantity_swaps_max_quantity() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::from_btc(0.1).unwrap(),
-            Amount::from_btc(99.9).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_max(0.01)) },
-            async { panic!("should not request new address when initial balance is > 0") },
-            || async { Ok(Amount::from_btc(0.1001)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.01).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.00000000 BTC maximum_amount=0.01000000 BTC
-"
-        );
-    }
+    Ok(())
+}
 
-    #[tokio::test]
-    async fn given_no_initial_balance_then_min_wait_for_sufficient_deposit() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::ZERO,
-            Amount::from_btc(0.01).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_min(0.01)) },
-            get_dummy_address(),
-            || async { Ok(Amount::from_btc(0.0101)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.01).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
-"
-        );
-    }
+fn log_suspended(swap_id: Uuid) {
+    tracing::info!(
+        %swap_id,
+        "Swap suspended, resume it with `swap resume --swap-id {}`",
+        swap_id
+    );
+}
 
-    #[tokio::test]
-    async fn given_balance_less_then_min_wait_for_sufficient_deposit() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::from_btc(0.0001).unwrap(),
-            Amount::from_btc(0.01).unwrap(),
-        ])));
-
-        let (amount, fees) = determine_btc_to_swap(
-            true,
-            async { Ok(quote_with_min(0.01)) },
-            get_dummy_address(),
-            || async { Ok(Amount::from_btc(0.0101)?) },
-            || async {
-                let mut result = givable.lock().unwrap();
-                result.give()
-            },
-            || async { Ok(()) },
-        )
-        .await
-        .unwrap();
-
-        let expected_amount = Amount::from_btc(0.01).unwrap();
-        let expected_fees = Amount::from_btc(0.0001).unwrap();
-
-        assert_eq!((amount, fees), (expected_amount, expected_fees));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00010000 BTC minimum_amount=0.01000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
-"
-        );
-    }
+/// Starts the swap of a single leg of a split purchase and runs it until the
+/// Bitcoin is locked.
+#[allow(clippy::too_many_arguments)]
+async fn lock_bitcoin_for_leg(
+    session_nonce: SessionNonce,
+    leg: &cli::split::Leg,
+    db: Database,
+    identity: identity::Keypair,
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    monero_wallet: Arc<monero::Wallet>,
+    env_config: Config,
+    tor_socks5_port: u16,
+    monero_receive_address: monero::Address,
+    bitcoin_change_address: bitcoin::Address,
+) -> Result<(Swap, JoinHandle<()>)> {
+    let seller_peer_id = leg
+        .seller
+        .extract_peer_id()
+        .context("Seller address must contain peer ID")?;
+    db.insert_address(seller_peer_id, leg.seller.clone())
+        .await?;
 
-    #[tokio::test]
-    async fn given_no_initial_balance_and_transfers_less_than_min_keep_waiting() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::ZERO,
-            Amount::from_btc(0.01).unwrap(),
-            Amount::from_btc(0.01).unwrap(),
-            Amount::from_btc(0.01).unwrap(),
-            Amount::from_btc(0.01).unwrap(),
-        ])));
-
-        let error = tokio::time::timeout(
-            Duration::from_secs(1),
-            determine_btc_to_swap(
-                true,
-                async { Ok(quote_with_min(0.1)) },
-                get_dummy_address(),
-                || async { Ok(Amount::from_btc(0.0101)?) },
-                || async {
-                    let mut result = givable.lock().unwrap();
-                    result.give()
-                },
-                || async { Ok(()) },
-            ),
-        )
-        .await
-        .unwrap_err();
-
-        assert!(matches!(error, tokio::time::error::Elapsed { .. }));
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Received Bitcoin new_balance=0.01010000 BTC max_giveable=0.01000000 BTC
- INFO swap: Deposited amount is less than `min_quantity`
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.01000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
-"
-        );
-    }
+    let behaviour = cli::Behaviour::new(seller_peer_id, env_config, bitcoin_wallet.clone());
+    let mut swarm = swarm::cli(identity, tor_socks5_port, behaviour).await?;
+    swarm
+        .behaviour_mut()
+        .add_address(seller_peer_id, leg.seller.clone());
 
-    #[tokio::test]
-    async fn given_longer_delay_until_deposit_should_not_spam_user() {
-        let writer = capture_logs(LevelFilter::INFO);
-        let givable = Arc::new(Mutex::new(MaxGiveable::new(vec![
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::ZERO,
-            Amount::from_btc(0.2).unwrap(),
-        ])));
-
-        tokio::time::timeout(
-            Duration::from_secs(10),
-            determine_btc_to_swap(
-                true,
-                async { Ok(quote_with_min(0.1)) },
-                get_dummy_address(),
-                || async { Ok(Amount::from_btc(0.21)?) },
-                || async {
-                    let mut result = givable.lock().unwrap();
-
-                    result.give()
-                },
-                || async { Ok(()) },
-            ),
-        )
-        .await
-        .unwrap()
-        .unwrap();
-
-        assert_eq!(
-            writer.captured(),
-            r" INFO swap: Received quote price=0.00100000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Waiting for Bitcoin deposit deposit_address=1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6 max_giveable=0.00000000 BTC minimum_amount=0.10000000 BTC maximum_amount=184467440737.09551615 BTC
- INFO swap: Received Bitcoin new_balance=0.21000000 BTC max_giveable=0.20000000 BTC
-"
-        );
-    }
+    let swap_id = session_nonce.swap_id(seller_peer_id);
+    let (event_loop, event_loop_handle) =
+        EventLoop::new(swap_id, swarm, seller_peer_id, env_config)?;
+    let mut event_loop = tokio::spawn(event_loop.run());
 
-    struct MaxGiveable {
-        amounts: Vec<Amount>,
-        call_counter: usize,
-    }
+    tracing::info!(%swap_id, seller = %leg.seller, amount = %leg.btc_amount, "Starting new swap");
 
-    impl MaxGiveable {
-        fn new(amounts: Vec<Amount>) -> Self {
-            Self {
-                amounts,
-                call_counter: 0,
-            }
-        }
-        fn give(&mut self) -> Result<Amount> {
-            let amount = self
-                .amounts
-                .get(self.call_counter)
-                .ok_or_else(|| anyhow::anyhow!("No more balances available"))?;
-            self.call_counter += 1;
-            Ok(*amount)
+    db.insert_peer_id(swap_id, seller_peer_id).await?;
+    db.insert_monero_address(swap_id, monero_receive_address)
+        .await?;
+
+    let mut swap = Swap::new(
+        db,
+        session_nonce,
+        bitcoin_wallet,
+        monero_wallet,
+        env_config,
+        event_loop_handle,
+        monero_receive_address,
+        bitcoin_change_address,
+        leg.btc_amount,
+    )
+    .with_min_monero(leg.monero_amount);
+
+    tokio::select! {
+        result = &mut event_loop => {
+            result.context("EventLoop panicked")?;
+            bail!("EventLoop stopped before the Bitcoin was locked")
+        },
+        result = bob::advance_until(&mut swap, is_btc_locked) => {
+            result?;
         }
     }
 
-    fn quote_with_max(btc: f64) -> BidQuote {
-        BidQuote {
-            price: Amount::from_btc(0.001).unwrap(),
-            max_quantity: Amount::from_btc(btc).unwrap(),
-            min_quantity: Amount::ZERO,
+    Ok((swap, event_loop))
+}
+
+/// Stores the addresses of the sellers that are online, so swaps with them can
+/// be resumed even if the rendezvous point forgets about them.
+async fn remember_sellers(db: &Database, sellers: &[cli::Seller]) -> Result<()> {
+    for seller in sellers {
+        if let (SellerStatus::Online(_), Some(peer_id)) =
+            (&seller.status, seller.multiaddr.extract_peer_id())
+        {
+            db.insert_address(peer_id, seller.multiaddr.clone()).await?;
         }
     }
 
-    fn quote_with_min(btc: f64) -> BidQuote {
-        BidQuote {
-            price: Amount::from_btc(0.001).unwrap(),
-            max_quantity: Amount::max_value(),
-            min_quantity: Amount::from_btc(btc).unwrap(),
+    Ok(())
+}
+
+fn is_btc_locked(state: &BobState) -> bool {
+    matches!(state, BobState::BtcLocked(..)) || is_complete(state)
+}
+
+fn is_btc_redeemed(state: &BobState) -> bool {
+    matches!(state, BobState::BtcRedeemed(..)) || is_complete(state)
+}
+
+fn action_help(action: Action) -> String {
+    let help = match action {
+        Action::Resume => "continue the swap with `resume` unless it is still running",
+        Action::Cancel => {
+            "publish the cancel transaction with `cancel` once the cancel timelock expired"
         }
-    }
+        Action::Refund => "get the Bitcoin back with `refund`",
+    };
 
-    async fn get_dummy_address() -> Result<bitcoin::Address> {
-        Ok("1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6".parse()?)
-    }
+    format!("{}: {}", action, help)
 }
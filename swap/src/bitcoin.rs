@@ -1,17 +1,26 @@
 pub mod wallet;
 
 mod cancel;
+mod coin_control;
+mod early_refund;
+mod electrum;
+mod funding_proof;
 mod lock;
 mod punish;
 mod redeem;
 mod refund;
+mod signer;
 mod timelocks;
 
 pub use crate::bitcoin::cancel::{CancelTimelock, PunishTimelock, TxCancel};
+pub use crate::bitcoin::coin_control::CoinControl;
+pub use crate::bitcoin::early_refund::TxEarlyRefund;
+pub use crate::bitcoin::funding_proof::{FundsUnavailable, InvalidFundingProof};
 pub use crate::bitcoin::lock::TxLock;
 pub use crate::bitcoin::punish::TxPunish;
 pub use crate::bitcoin::redeem::TxRedeem;
 pub use crate::bitcoin::refund::TxRefund;
+pub use crate::bitcoin::signer::{Hwi, Signer};
 pub use crate::bitcoin::timelocks::{BlockHeight, ExpiredTimelocks};
 pub use ::bitcoin::util::amount::Amount;
 pub use ::bitcoin::util::psbt::PartiallySignedTransaction;
@@ -249,6 +258,120 @@ pub fn current_epoch(
     ExpiredTimelocks::None
 }
 
+/// The number of blocks left until the punish timelock expires.
+///
+/// The punish timelock only starts once the cancel transaction is confirmed.
+pub fn blocks_until_punish(punish_timelock: PunishTimelock, tx_cancel_status: ScriptStatus) -> u32 {
+    match tx_cancel_status {
+        ScriptStatus::Confirmed(confirmed) => {
+            u32::from(punish_timelock).saturating_sub(confirmed.confirmations())
+        }
+        ScriptStatus::Unseen | ScriptStatus::InMempool => u32::from(punish_timelock),
+    }
+}
+
+/// Whether the address pays to a hash or witness program of only zeros,
+/// which nobody can spend from.
+pub fn is_burn_address(address: &Address) -> bool {
+    let payload = match &address.payload {
+        ::bitcoin::util::address::Payload::PubkeyHash(hash) => &hash[..],
+        ::bitcoin::util::address::Payload::ScriptHash(hash) => &hash[..],
+        ::bitcoin::util::address::Payload::WitnessProgram { program, .. } => &program[..],
+    };
+
+    payload.iter().all(|byte| *byte == 0)
+}
+
+#[derive(Clone, thiserror::Error, Debug, PartialEq)]
+#[error("Refusing to send Bitcoin to {0}, nobody can spend from this address")]
+pub struct BurnAddress(pub Address);
+
+/// Parses an amount of BTC, with or without the unit, or an amount of
+/// satoshi, e.g. `0.5`, `0.5 BTC` or `1500000 sat`. Only a `.` is accepted as
+/// decimal separator, regardless of the locale.
+pub fn parse_amount(s: &str) -> Result<Amount> {
+    let mut parts = s.split_whitespace();
+    let (amount, denomination) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(amount), None, None) => (amount, ::bitcoin::Denomination::Bitcoin),
+        (Some(amount), Some(unit), None) => {
+            let denomination = match unit.to_lowercase().as_str() {
+                "btc" => ::bitcoin::Denomination::Bitcoin,
+                "sat" | "sats" | "satoshi" | "satoshis" => ::bitcoin::Denomination::Satoshi,
+                _ => bail!("Unknown unit {}, expected BTC or sat", unit),
+            };
+            (amount, denomination)
+        }
+        _ => bail!("Failed to parse {:?} as an amount of Bitcoin", s),
+    };
+
+    Amount::from_str_in(amount, denomination)
+        .with_context(|| format!("Failed to parse {} as an amount of Bitcoin", s))
+}
+
+/// (De)serializes an amount of BTC for config files. Amounts are written as
+/// numbers, strings are read with [`parse_amount`].
+pub mod as_btc {
+    use crate::bitcoin::{parse_amount, Amount};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Integer(u64),
+        Float(f64),
+        String(String),
+    }
+
+    pub fn serialize<S>(x: &Amount, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ::bitcoin::util::amount::serde::as_btc::serialize(x, s)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Amount, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // A float is parsed from its shortest decimal representation, which
+        // is exact for every amount of satoshi.
+        let amount = match Raw::deserialize(deserializer)? {
+            Raw::Integer(btc) => parse_amount(&btc.to_string()),
+            Raw::Float(btc) => parse_amount(&btc.to_string()),
+            Raw::String(amount) => parse_amount(&amount),
+        };
+
+        amount.map_err(|error| D::Error::custom(format!("{:#}", error)))
+    }
+
+    pub mod opt {
+        use crate::bitcoin::Amount;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S>(x: &Option<Amount>, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            ::bitcoin::util::amount::serde::as_btc::opt::serialize(x, s)
+        }
+
+        pub fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> Result<Option<Amount>, <D as Deserializer<'de>>::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Amount);
+
+            let amount = Option::<Wrapper>::deserialize(deserializer)?;
+
+            Ok(amount.map(|Wrapper(amount)| amount))
+        }
+    }
+}
+
 #[derive(Clone, Copy, thiserror::Error, Debug)]
 #[error("transaction does not spend anything")]
 pub struct NoInputs;
@@ -273,6 +396,27 @@ mod tests {
     use rand::rngs::OsRng;
     use uuid::Uuid;
 
+    #[test]
+    fn detects_burn_addresses() {
+        let burn = Address::from_str("1111111111111111111114oLvT2").unwrap();
+        let regular = Address::from_str("bc1qe4epnfklcaa0mun26yz5g8k24em5u9f92hy325").unwrap();
+
+        assert!(is_burn_address(&burn));
+        assert!(!is_burn_address(&regular));
+    }
+
+    #[test]
+    fn parses_amounts_with_and_without_unit() {
+        let half = Amount::from_sat(50_000_000);
+
+        assert_eq!(parse_amount("0.5").unwrap(), half);
+        assert_eq!(parse_amount("0.5 BTC").unwrap(), half);
+        assert_eq!(parse_amount("50000000 sat").unwrap(), half);
+        assert!(parse_amount("0,5").is_err());
+        assert!(parse_amount("0.000000001").is_err());
+        assert!(parse_amount("0.5 XMR").is_err());
+    }
+
     #[test]
     fn lock_confirmations_le_to_cancel_timelock_no_timelock_expired() {
         let tx_lock_status = ScriptStatus::from_confirmations(4);
@@ -318,8 +462,75 @@ mod tests {
         assert_eq!(expired_timelock, ExpiredTimelocks::Punish)
     }
 
+    #[test]
+    fn unconfirmed_cancel_leaves_full_punish_timelock() {
+        let blocks_left = blocks_until_punish(PunishTimelock::new(5), ScriptStatus::InMempool);
+
+        assert_eq!(blocks_left, 5)
+    }
+
+    #[test]
+    fn cancel_confirmations_count_towards_punish_timelock() {
+        let blocks_left =
+            blocks_until_punish(PunishTimelock::new(5), ScriptStatus::from_confirmations(3));
+
+        assert_eq!(blocks_left, 2)
+    }
+
+    #[test]
+    fn expired_punish_timelock_leaves_no_blocks() {
+        let blocks_left =
+            blocks_until_punish(PunishTimelock::new(5), ScriptStatus::from_confirmations(8));
+
+        assert_eq!(blocks_left, 0)
+    }
+
     #[tokio::test]
     async fn calculate_transaction_weights() {
+        let (alice_state3, bob_state3) = lock_btc().await;
+
+        let early_refund_transaction = bob_state3
+            .signed_early_refund_transaction(alice_state3.tx_early_refund_sig())
+            .unwrap();
+        let bob_state4 = bob_state3.xmr_locked(monero_rpc::wallet::BlockHeight { height: 0 });
+        let encrypted_signature = bob_state4.tx_redeem_encsig();
+        let bob_state6 = bob_state4.cancel();
+
+        let cancel_transaction = alice_state3.signed_cancel_transaction().unwrap();
+        let punish_transaction = alice_state3.signed_punish_transaction().unwrap();
+        let redeem_transaction = alice_state3
+            .signed_redeem_transaction(encrypted_signature)
+            .unwrap();
+        let refund_transaction = bob_state6.signed_refund_transaction().unwrap();
+
+        assert_weight(redeem_transaction, TxRedeem::weight(), "TxRedeem");
+        assert_weight(cancel_transaction, TxCancel::weight(), "TxCancel");
+        assert_weight(punish_transaction, TxPunish::weight(), "TxPunish");
+        assert_weight(refund_transaction, TxRefund::weight(), "TxRefund");
+        assert_weight(
+            early_refund_transaction,
+            TxEarlyRefund::weight(),
+            "TxEarlyRefund",
+        );
+    }
+
+    #[tokio::test]
+    async fn early_refund_requires_signature_of_alice() {
+        let (alice_state3, bob_state3) = lock_btc().await;
+        let forged_sig =
+            SecretKey::new_random(&mut OsRng).sign(bob_state3.tx_early_refund().digest());
+
+        assert!(bob_state3
+            .signed_early_refund_transaction(forged_sig)
+            .is_err());
+        assert!(bob_state3
+            .signed_early_refund_transaction(alice_state3.tx_early_refund_sig())
+            .is_ok());
+    }
+
+    /// Sets up a swap between Alice and Bob up to the point where Bob locked
+    /// the Bitcoin.
+    async fn lock_btc() -> (alice::State3, bob::State3) {
         let alice_wallet = WalletBuilder::new(Amount::ONE_BTC.as_sat()).build();
         let bob_wallet = WalletBuilder::new(Amount::ONE_BTC.as_sat()).build();
         let spending_fee = Amount::from_sat(1_000);
@@ -382,21 +593,8 @@ mod tests {
         let alice_state3 = alice_state2.receive(bob_message4).unwrap();
 
         let (bob_state3, _tx_lock) = bob_state2.lock_btc().await.unwrap();
-        let bob_state4 = bob_state3.xmr_locked(monero_rpc::wallet::BlockHeight { height: 0 });
-        let encrypted_signature = bob_state4.tx_redeem_encsig();
-        let bob_state6 = bob_state4.cancel();
-
-        let cancel_transaction = alice_state3.signed_cancel_transaction().unwrap();
-        let punish_transaction = alice_state3.signed_punish_transaction().unwrap();
-        let redeem_transaction = alice_state3
-            .signed_redeem_transaction(encrypted_signature)
-            .unwrap();
-        let refund_transaction = bob_state6.signed_refund_transaction().unwrap();
 
-        assert_weight(redeem_transaction, TxRedeem::weight(), "TxRedeem");
-        assert_weight(cancel_transaction, TxCancel::weight(), "TxCancel");
-        assert_weight(punish_transaction, TxPunish::weight(), "TxPunish");
-        assert_weight(refund_transaction, TxRefund::weight(), "TxRefund");
+        (alice_state3, bob_state3)
     }
 
     // Weights fluctuate because of the length of the signatures. Valid ecdsa
@@ -68,6 +68,12 @@ impl PunishTimelock {
     }
 }
 
+impl From<PunishTimelock> for u32 {
+    fn from(punish_timelock: PunishTimelock) -> Self {
+        punish_timelock.0
+    }
+}
+
 impl Add<PunishTimelock> for BlockHeight {
     type Output = BlockHeight;
 
@@ -88,6 +94,12 @@ impl PartialEq<PunishTimelock> for u32 {
     }
 }
 
+impl fmt::Display for PunishTimelock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} blocks", self.0)
+    }
+}
+
 #[derive(Debug)]
 pub struct TxCancel {
     inner: Transaction,
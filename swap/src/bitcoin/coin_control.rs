@@ -0,0 +1,176 @@
+//! Restricting which outputs of the wallet fund a transaction.
+
+use ::bitcoin::OutPoint;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+/// A policy for the outputs that fund a transaction, the default policy
+/// allows all outputs of the wallet.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoinControl {
+    /// Skip outputs paying to an address that received funds more than once.
+    #[serde(default)]
+    pub avoid_reused_addresses: bool,
+    /// Skip outputs of unconfirmed transactions.
+    #[serde(default)]
+    pub confirmed_only: bool,
+    /// Only fund from these outputs, given as `<txid>:<vout>`.
+    #[serde(default)]
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub utxos: Vec<OutPoint>,
+}
+
+impl CoinControl {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Combines two policies, the restrictions of both apply.
+    ///
+    /// Manually selected outputs of `other` replace those of `self`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            avoid_reused_addresses: self.avoid_reused_addresses || other.avoid_reused_addresses,
+            confirmed_only: self.confirmed_only || other.confirmed_only,
+            utxos: if other.utxos.is_empty() {
+                self.utxos
+            } else {
+                other.utxos
+            },
+        }
+    }
+
+    /// Decides which of the unspent outputs of the wallet may fund a
+    /// transaction.
+    ///
+    /// Fails if a manually selected output is unknown or violates the policy.
+    pub fn select(&self, utxos: &[Utxo]) -> Result<Selection> {
+        if self.utxos.is_empty() {
+            let excluded = utxos
+                .iter()
+                .filter(|utxo| !self.allows(utxo))
+                .map(|utxo| utxo.outpoint)
+                .collect();
+
+            return Ok(Selection::Exclude(excluded));
+        }
+
+        for outpoint in &self.utxos {
+            match utxos.iter().find(|utxo| utxo.outpoint == *outpoint) {
+                None => bail!("{} is not an unspent output of the wallet", outpoint),
+                Some(utxo) if !utxo.confirmed && self.confirmed_only => {
+                    bail!("{} is not confirmed yet", outpoint)
+                }
+                Some(utxo) if utxo.address_reused && self.avoid_reused_addresses => {
+                    bail!("{} pays to a reused address", outpoint)
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(Selection::Only(self.utxos.clone()))
+    }
+
+    fn allows(&self, utxo: &Utxo) -> bool {
+        (utxo.confirmed || !self.confirmed_only)
+            && (!utxo.address_reused || !self.avoid_reused_addresses)
+    }
+}
+
+/// An unspent output of the wallet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub confirmed: bool,
+    pub address_reused: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selection {
+    /// Fund only from these outputs.
+    Only(Vec<OutPoint>),
+    /// Fund from any output except these.
+    Exclude(Vec<OutPoint>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::Txid;
+
+    fn utxo(vout: u32, confirmed: bool, address_reused: bool) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid::default(), vout),
+            confirmed,
+            address_reused,
+        }
+    }
+
+    #[test]
+    fn excludes_outputs_violating_the_policy() {
+        let utxos = [
+            utxo(0, true, false),
+            utxo(1, false, false),
+            utxo(2, true, true),
+        ];
+
+        assert_eq!(
+            CoinControl::default().select(&utxos).unwrap(),
+            Selection::Exclude(vec![])
+        );
+
+        let policy = CoinControl {
+            avoid_reused_addresses: true,
+            confirmed_only: true,
+            utxos: vec![],
+        };
+        assert_eq!(
+            policy.select(&utxos).unwrap(),
+            Selection::Exclude(vec![utxos[1].outpoint, utxos[2].outpoint])
+        );
+    }
+
+    #[test]
+    fn manually_selected_outputs_have_to_satisfy_the_policy() {
+        let utxos = [utxo(0, true, false), utxo(1, false, false)];
+
+        let policy = CoinControl {
+            utxos: vec![utxos[1].outpoint],
+            ..CoinControl::default()
+        };
+        assert_eq!(
+            policy.select(&utxos).unwrap(),
+            Selection::Only(vec![utxos[1].outpoint])
+        );
+
+        let policy = CoinControl {
+            confirmed_only: true,
+            ..policy
+        };
+        assert!(policy.select(&utxos).is_err());
+
+        let policy = CoinControl {
+            utxos: vec![OutPoint::new(Txid::default(), 5)],
+            ..CoinControl::default()
+        };
+        assert!(policy.select(&utxos).is_err());
+    }
+
+    #[test]
+    fn parses_manually_selected_outputs_as_txid_and_vout() {
+        let policy = toml::from_str::<CoinControl>(
+            r#"
+            confirmed_only = true
+            utxos = ["0000000000000000000000000000000000000000000000000000000000000000:1"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(policy.confirmed_only);
+        assert!(!policy.avoid_reused_addresses);
+        assert_eq!(policy.utxos, vec![OutPoint::new(Txid::default(), 1)]);
+    }
+}
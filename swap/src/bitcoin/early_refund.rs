@@ -0,0 +1,101 @@
+use crate::bitcoin::wallet::Watchable;
+use crate::bitcoin::{
+    verify_sig, Address, Amount, PublicKey, SecretKey, Signature, Transaction, TxLock,
+};
+use ::bitcoin::util::bip143::SigHashCache;
+use ::bitcoin::{Script, SigHash, SigHashType, Txid};
+use anyhow::{Context, Result};
+use miniscript::{Descriptor, DescriptorTrait};
+use std::collections::HashMap;
+
+/// Spends the lock output back to Bob without waiting for the cancel
+/// timelock.
+///
+/// Alice only signs it if she decided not to lock the Monero, Bob can publish
+/// it right away once he has her signature.
+#[derive(Clone, Debug)]
+pub struct TxEarlyRefund {
+    inner: Transaction,
+    digest: SigHash,
+    lock_output_descriptor: Descriptor<::bitcoin::PublicKey>,
+    watch_script: Script,
+}
+
+impl TxEarlyRefund {
+    pub fn new(tx_lock: &TxLock, refund_address: &Address, spending_fee: Amount) -> Self {
+        let tx_early_refund = tx_lock.build_spend_transaction(refund_address, None, spending_fee);
+
+        let digest = SigHashCache::new(&tx_early_refund).signature_hash(
+            0, // Only one input: lock_input (lock transaction)
+            &tx_lock.output_descriptor.script_code(),
+            tx_lock.lock_amount().as_sat(),
+            SigHashType::All,
+        );
+
+        Self {
+            inner: tx_early_refund,
+            digest,
+            lock_output_descriptor: tx_lock.output_descriptor.clone(),
+            watch_script: refund_address.script_pubkey(),
+        }
+    }
+
+    pub fn txid(&self) -> Txid {
+        self.inner.txid()
+    }
+
+    pub fn digest(&self) -> SigHash {
+        self.digest
+    }
+
+    pub fn complete_as_bob(
+        mut self,
+        A: PublicKey,
+        b: SecretKey,
+        tx_early_refund_sig_a: Signature,
+    ) -> Result<Transaction> {
+        verify_sig(&A, &self.digest(), &tx_early_refund_sig_a)
+            .context("Invalid signature of Alice for the early refund transaction")?;
+
+        let sig_b = b.sign(self.digest());
+
+        let satisfier = {
+            let mut satisfier = HashMap::with_capacity(2);
+
+            let A = ::bitcoin::PublicKey {
+                compressed: true,
+                key: A.0.into(),
+            };
+            let B = ::bitcoin::PublicKey {
+                compressed: true,
+                key: b.public.into(),
+            };
+
+            // The order in which these are inserted doesn't matter
+            satisfier.insert(A, (tx_early_refund_sig_a.into(), SigHashType::All));
+            satisfier.insert(B, (sig_b.into(), SigHashType::All));
+
+            satisfier
+        };
+
+        self.lock_output_descriptor
+            .satisfy(&mut self.inner.input[0], satisfier)
+            .context("Failed to sign Bitcoin early refund transaction")?;
+
+        Ok(self.inner)
+    }
+
+    pub fn weight() -> usize {
+        548
+    }
+}
+
+impl Watchable for TxEarlyRefund {
+    fn id(&self) -> Txid {
+        self.txid()
+    }
+
+    fn script(&self) -> Script {
+        self.watch_script.clone()
+    }
+}
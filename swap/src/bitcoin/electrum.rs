@@ -0,0 +1,360 @@
+//! Failing over between several Electrum servers.
+//!
+//! A [`Pool`] holds a connection to one server at a time. Once a request fails
+//! because the connection broke, the pool connects to the next reachable
+//! server and repeats the request there. Nothing is subscribed on the servers:
+//! the block height and the script histories are polled, so a new server is
+//! in sync with the wallet after the next poll.
+//!
+//! If no server is reachable for a few requests in a row, requests fail right
+//! away for a while instead of trying to connect to every server again.
+
+use crate::backoff::{CircuitBreaker, CircuitOpen};
+use anyhow::{bail, Result};
+use bdk::blockchain::{noop_progress, Blockchain, Capability, ElectrumBlockchain, Progress};
+use bdk::database::BatchDatabase;
+use bdk::{electrum_client, FeeRate};
+use bitcoin::{Transaction, Txid};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+/// How many requests in a row may fail on all servers before requests fail
+/// right away.
+const CIRCUIT_BREAKER_THRESHOLD: u32 = 3;
+const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+pub struct Pool<T> {
+    urls: Vec<Url>,
+    current: Mutex<Current<T>>,
+    breaker: CircuitBreaker,
+}
+
+struct Current<T> {
+    index: usize,
+    connection: Arc<T>,
+}
+
+impl<T> Pool<T>
+where
+    T: Connect,
+{
+    /// Connects to the first reachable server, in the given order.
+    pub fn new(urls: Vec<Url>) -> Result<Self> {
+        if urls.is_empty() {
+            bail!("At least one Electrum server is required")
+        }
+
+        let (index, connection) = connect_from(&urls, 0)?;
+
+        Ok(Self {
+            urls,
+            current: Mutex::new(Current {
+                index,
+                connection: Arc::new(connection),
+            }),
+            breaker: CircuitBreaker::new(
+                "Electrum",
+                CIRCUIT_BREAKER_THRESHOLD,
+                CIRCUIT_BREAKER_COOLDOWN,
+            ),
+        })
+    }
+
+    pub fn number_of_servers(&self) -> usize {
+        self.urls.len()
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.current
+            .lock()
+            .expect("lock is never poisoned")
+            .connection
+            .clone()
+    }
+
+    pub fn url(&self) -> Url {
+        let index = self.current.lock().expect("lock is never poisoned").index;
+
+        self.urls[index].clone()
+    }
+
+    /// Replaces the given connection with one to the next reachable server,
+    /// which is the same server again if no other one is reachable.
+    ///
+    /// Does nothing if the connection was already replaced by a concurrent
+    /// caller. Returns whether a new connection is available.
+    pub fn fail_over(&self, failed: &Arc<T>) -> bool {
+        let mut current = self.current.lock().expect("lock is never poisoned");
+
+        if !Arc::ptr_eq(&current.connection, failed) {
+            return true;
+        }
+
+        match connect_from(&self.urls, current.index + 1) {
+            Ok((index, connection)) => {
+                if index != current.index {
+                    tracing::info!(
+                        from = %self.urls[current.index],
+                        to = %self.urls[index],
+                        "Switched to another Electrum server"
+                    );
+                }
+                *current = Current {
+                    index,
+                    connection: Arc::new(connection),
+                };
+
+                true
+            }
+            Err(error) => {
+                tracing::warn!("{:#}", error);
+                false
+            }
+        }
+    }
+
+    /// Calls `f` with the current connection, retrying on the other servers
+    /// as long as the connection fails.
+    pub fn call<R, E>(&self, f: impl Fn(&T) -> Result<R, E>) -> Result<R, E>
+    where
+        E: ConnectionError + From<CircuitOpen> + fmt::Display,
+    {
+        self.breaker.check()?;
+
+        let mut attempts = self.urls.len();
+
+        loop {
+            let connection = self.get();
+
+            match f(&connection) {
+                Err(error) if attempts > 1 && error.is_connection_error() => {
+                    tracing::warn!(url = %self.url(), "Request to Electrum server failed: {}", error);
+                    attempts -= 1;
+
+                    if !self.fail_over(&connection) {
+                        self.breaker.record_failure();
+                        return Err(error);
+                    }
+                }
+                Err(error) if error.is_connection_error() => {
+                    self.breaker.record_failure();
+                    return Err(error);
+                }
+                result => {
+                    self.breaker.record_success();
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+/// Tries the servers in order, starting at `start` and wrapping around.
+fn connect_from<T>(urls: &[Url], start: usize) -> Result<(usize, T)>
+where
+    T: Connect,
+{
+    for offset in 0..urls.len() {
+        let index = (start + offset) % urls.len();
+
+        match T::connect(&urls[index]) {
+            Ok(connection) => return Ok((index, connection)),
+            Err(error) => {
+                tracing::warn!(url = %urls[index], "Failed to connect to Electrum server: {}", error)
+            }
+        }
+    }
+
+    bail!("Failed to connect to any of the Electrum servers")
+}
+
+pub trait Connect: Sized {
+    fn connect(url: &Url) -> Result<Self, electrum_client::Error>;
+}
+
+impl Connect for electrum_client::Client {
+    fn connect(url: &Url) -> Result<Self, electrum_client::Error> {
+        electrum_client::Client::new(url.as_str())
+    }
+}
+
+impl Connect for ElectrumBlockchain {
+    fn connect(url: &Url) -> Result<Self, electrum_client::Error> {
+        electrum_client::Client::new(url.as_str()).map(ElectrumBlockchain::from)
+    }
+}
+
+/// Distinguishes a broken connection from an error returned by the server,
+/// only the former is worth retrying on another server.
+pub trait ConnectionError {
+    fn is_connection_error(&self) -> bool;
+}
+
+impl ConnectionError for electrum_client::Error {
+    fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            electrum_client::Error::IOError(_)
+                | electrum_client::Error::SharedIOError(_)
+                | electrum_client::Error::AllAttemptsErrored(_)
+        )
+    }
+}
+
+impl ConnectionError for bdk::Error {
+    fn is_connection_error(&self) -> bool {
+        match self {
+            bdk::Error::Electrum(error) => error.is_connection_error(),
+            _ => false,
+        }
+    }
+}
+
+impl From<CircuitOpen> for electrum_client::Error {
+    fn from(error: CircuitOpen) -> Self {
+        electrum_client::Error::Message(error.to_string())
+    }
+}
+
+impl From<CircuitOpen> for bdk::Error {
+    fn from(error: CircuitOpen) -> Self {
+        bdk::Error::Electrum(error.into())
+    }
+}
+
+impl Blockchain for Pool<ElectrumBlockchain> {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        self.get().get_capabilities()
+    }
+
+    fn setup<D: BatchDatabase, P: 'static + Progress>(
+        &self,
+        stop_gap: Option<usize>,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), bdk::Error> {
+        let blockchain = self.get();
+
+        // The progress is consumed by the first attempt, the retry on another
+        // server does not report any.
+        match blockchain.setup(stop_gap, database, progress_update) {
+            Err(error) if error.is_connection_error() && self.fail_over(&blockchain) => {
+                self.get().setup(stop_gap, database, noop_progress())
+            }
+            result => result,
+        }
+    }
+
+    fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, bdk::Error> {
+        self.call(|blockchain| blockchain.get_tx(txid))
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), bdk::Error> {
+        self.call(|blockchain| blockchain.broadcast(tx))
+    }
+
+    fn get_height(&self) -> Result<u32, bdk::Error> {
+        self.call(|blockchain| blockchain.get_height())
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, bdk::Error> {
+        self.call(|blockchain| blockchain.estimate_fee(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Connects to every server but the one on port 1, requests fail on port
+    /// 2.
+    struct FakeConnection {
+        port: u16,
+    }
+
+    impl Connect for FakeConnection {
+        fn connect(url: &Url) -> Result<Self, electrum_client::Error> {
+            match url.port() {
+                Some(1) => Err(electrum_client::Error::Message("unreachable".to_owned())),
+                port => Ok(Self {
+                    port: port.expect("test urls have a port"),
+                }),
+            }
+        }
+    }
+
+    impl FakeConnection {
+        fn request(&self) -> Result<u16, electrum_client::Error> {
+            match self.port {
+                2 => Err(electrum_client::Error::IOError(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "connection reset",
+                ))),
+                port => Ok(port),
+            }
+        }
+    }
+
+    fn urls(ports: &[u16]) -> Vec<Url> {
+        ports
+            .iter()
+            .map(|port| Url::parse(&format!("tcp://localhost:{}", port)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn skips_unreachable_servers_when_connecting() {
+        let pool = Pool::<FakeConnection>::new(urls(&[1, 3])).unwrap();
+
+        assert_eq!(pool.url().port(), Some(3));
+    }
+
+    #[test]
+    fn fails_over_to_next_server_on_connection_errors() {
+        let pool = Pool::<FakeConnection>::new(urls(&[2, 1, 4])).unwrap();
+
+        let port = pool.call(FakeConnection::request).unwrap();
+
+        assert_eq!(port, 4);
+        assert_eq!(pool.url().port(), Some(4));
+    }
+
+    #[test]
+    fn does_not_fail_over_on_errors_of_the_server() {
+        let pool = Pool::<FakeConnection>::new(urls(&[3, 4])).unwrap();
+        let calls = AtomicUsize::new(0);
+
+        let result = pool.call(|_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(electrum_client::Error::Message("rejected".to_owned()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.url().port(), Some(3));
+    }
+
+    #[test]
+    fn fails_right_away_once_no_server_is_reachable_repeatedly() {
+        let pool = Pool::<FakeConnection>::new(urls(&[2])).unwrap();
+        let calls = AtomicUsize::new(0);
+        let request = |connection: &FakeConnection| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            connection.request()
+        };
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            assert!(pool.call(request).is_err());
+        }
+        assert!(pool.call(request).is_err());
+
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            CIRCUIT_BREAKER_THRESHOLD as usize
+        );
+    }
+}
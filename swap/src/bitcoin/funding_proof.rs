@@ -0,0 +1,206 @@
+//! Proof that a taker controls the Bitcoin they want to swap.
+//!
+//! The proof is a signed transaction that spends the taker's coins. Its lock
+//! time lies so far in the future that it can never be mined, which allows
+//! the maker to verify the signatures without being able to move the coins.
+//! An `OP_RETURN` output commits the proof to the session nonce of the taker,
+//! so that a proof cannot be replayed by someone else in another swap setup.
+//!
+//! The proof reveals the outputs it spends, and with them the addresses of
+//! the taker's wallet, to the maker.
+
+use crate::bitcoin::{Amount, Transaction};
+use ::bitcoin::secp256k1::{Message, Secp256k1, Signature};
+use ::bitcoin::util::bip143::SigHashCache;
+use ::bitcoin::{PublicKey, Script, SigHashType, TxIn, TxOut};
+use anyhow::{bail, Context, Result};
+
+/// Interpreted as a timestamp in the year 2106.
+pub const LOCK_TIME: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[error("The funding proof is invalid")]
+pub struct InvalidFundingProof;
+
+/// The proof is well-formed, but spends outputs that are spent already, which
+/// happens to honest takers whose wallet is not synced.
+#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[error("The funding proof spends outputs that are not unspent")]
+pub struct FundsUnavailable;
+
+/// The script of the P2WPKH output the input spends, derived from the public
+/// key in its witness.
+pub fn spent_script(input: &TxIn) -> Result<Script> {
+    let public_key = match input.witness.as_slice() {
+        [_, public_key] => PublicKey::from_slice(public_key)?,
+        _ => bail!(
+            "Input {} does not spend a P2WPKH output",
+            input.previous_output
+        ),
+    };
+    let wpubkey_hash = public_key
+        .wpubkey_hash()
+        .context("Public key is not compressed")?;
+
+    Ok(Script::new_v0_wpkh(&wpubkey_hash))
+}
+
+/// Verifies that the proof spends outputs worth at least the given amount, that
+/// all inputs are signed by the owner of the spent output and that the proof
+/// commits to the given data.
+///
+/// The spent outputs have to be given in the order of the inputs.
+pub fn verify(
+    proof: &Transaction,
+    prevouts: &[TxOut],
+    amount: Amount,
+    commitment: &[u8],
+) -> Result<()> {
+    let commitment = Script::new_op_return(commitment);
+    if !proof
+        .output
+        .iter()
+        .any(|output| output.script_pubkey == commitment)
+    {
+        bail!("Proof does not commit to the swap setup")
+    }
+
+    if proof.input.len() != prevouts.len() {
+        bail!(
+            "Expected {} spent outputs, got {}",
+            proof.input.len(),
+            prevouts.len()
+        )
+    }
+
+    let total = Amount::from_sat(prevouts.iter().map(|prevout| prevout.value).sum());
+    if total < amount {
+        bail!("Proof only covers {} of {}", total, amount)
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut sighash_cache = SigHashCache::new(proof);
+
+    for (index, (input, prevout)) in proof.input.iter().zip(prevouts).enumerate() {
+        if spent_script(input)? != prevout.script_pubkey {
+            bail!(
+                "Input {} does not spend the given output",
+                input.previous_output
+            )
+        }
+
+        let (signature, public_key) = match input.witness.as_slice() {
+            [signature, public_key] => (signature, PublicKey::from_slice(public_key)?),
+            _ => unreachable!("spent script can only be derived from P2WPKH witnesses"),
+        };
+
+        // Only accept signatures that commit to the whole transaction.
+        let (sighash_type, signature) = signature.split_last().context("Empty signature")?;
+        if u32::from(*sighash_type) != SigHashType::All.as_u32() {
+            bail!(
+                "Input {} is not signed with SIGHASH_ALL",
+                input.previous_output
+            )
+        }
+        let signature = Signature::from_der(signature)?;
+
+        let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+        let sighash =
+            sighash_cache.signature_hash(index, &script_code, prevout.value, SigHashType::All);
+        let message = Message::from_slice(&sighash[..])?;
+
+        secp.verify(&message, &signature, &public_key.key)
+            .with_context(|| format!("Invalid signature for input {}", input.previous_output))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::secp256k1::SecretKey;
+    use ::bitcoin::{Network, OutPoint, PrivateKey};
+
+    const COMMITMENT: &[u8] = &[7; 32];
+
+    #[test]
+    fn accepts_signed_proof_over_amount() {
+        let (proof, prevouts) = signed_proof(100_000);
+
+        verify(&proof, &prevouts, Amount::from_sat(100_000), COMMITMENT).unwrap();
+    }
+
+    #[test]
+    fn rejects_proof_below_amount() {
+        let (proof, prevouts) = signed_proof(100_000);
+
+        assert!(verify(&proof, &prevouts, Amount::from_sat(100_001), COMMITMENT).is_err());
+    }
+
+    #[test]
+    fn rejects_proof_with_inflated_prevout_value() {
+        let (proof, mut prevouts) = signed_proof(100_000);
+        prevouts[0].value = 200_000;
+
+        assert!(verify(&proof, &prevouts, Amount::from_sat(200_000), COMMITMENT).is_err());
+    }
+
+    #[test]
+    fn rejects_proof_of_another_swap_setup() {
+        let (proof, prevouts) = signed_proof(100_000);
+
+        assert!(verify(&proof, &prevouts, Amount::from_sat(100_000), &[8; 32]).is_err());
+    }
+
+    fn signed_proof(value: u64) -> (Transaction, Vec<TxOut>) {
+        let secp = Secp256k1::new();
+        let private_key = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: SecretKey::from_slice(&[1; 32]).unwrap(),
+        };
+        let public_key = private_key.public_key(&secp);
+
+        let prevout = TxOut {
+            value,
+            script_pubkey: Script::new_v0_wpkh(&public_key.wpubkey_hash().unwrap()),
+        };
+        let mut proof = Transaction {
+            version: 2,
+            lock_time: LOCK_TIME,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFD,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: value - 1_000,
+                    script_pubkey: prevout.script_pubkey.clone(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: Script::new_op_return(COMMITMENT),
+                },
+            ],
+        };
+
+        let sighash = SigHashCache::new(&proof).signature_hash(
+            0,
+            &Script::new_p2pkh(&public_key.pubkey_hash()),
+            value,
+            SigHashType::All,
+        );
+        let signature = secp.sign(
+            &Message::from_slice(&sighash[..]).unwrap(),
+            &private_key.key,
+        );
+        let mut signature = signature.serialize_der().to_vec();
+        signature.push(0x01);
+        proof.input[0].witness = vec![signature, public_key.to_bytes()];
+
+        (proof, vec![prevout])
+    }
+}
@@ -0,0 +1,196 @@
+//! Signing the transactions of the Bitcoin wallet outside of this process.
+//!
+//! Only the inputs spending the coins of the wallet, i.e. the lock transaction
+//! and the funding proof, are signed by a [`Signer`]. The keys of the swap
+//! itself are generated per swap and never leave the process.
+
+use ::bitcoin::consensus::encode::{deserialize, serialize};
+use ::bitcoin::util::bip32::{ExtendedPubKey, Fingerprint};
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
+use ::bitcoin::Network;
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+#[async_trait::async_trait]
+pub trait Signer: Send + Sync {
+    /// Adds the signatures for all inputs that spend coins of the wallet.
+    async fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction>;
+}
+
+/// A hardware wallet accessed through the [HWI](https://github.com/bitcoin-core/HWI)
+/// command line tool.
+///
+/// The wallet uses the first BIP84 account of the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hwi {
+    executable: PathBuf,
+    fingerprint: Fingerprint,
+    network: Network,
+}
+
+impl Hwi {
+    pub fn new(executable: PathBuf, fingerprint: Fingerprint, network: Network) -> Self {
+        Self {
+            executable,
+            fingerprint,
+            network,
+        }
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// The watch-only descriptors of the external and internal keychain of the
+    /// account, asks the device for the public key of the account.
+    pub async fn descriptors(&self) -> Result<(String, String)> {
+        let response: GetXpub = self
+            .call(&["getxpub", &self.account_path()])
+            .await
+            .context("Failed to get the public key of the hardware wallet")?;
+
+        Ok((
+            self.descriptor(&response.xpub, 0),
+            self.descriptor(&response.xpub, 1),
+        ))
+    }
+
+    fn account_path(&self) -> String {
+        let coin_type = match self.network {
+            Network::Bitcoin => 0,
+            Network::Testnet | Network::Signet | Network::Regtest => 1,
+        };
+
+        format!("m/84h/{}h/0h", coin_type)
+    }
+
+    /// The key origin allows the device to recognize the inputs it has to
+    /// sign.
+    fn descriptor(&self, xpub: &ExtendedPubKey, keychain: u32) -> String {
+        let origin = self
+            .account_path()
+            .replacen("m", &self.fingerprint.to_string(), 1);
+
+        format!("wpkh([{}]{}/{}/*)", origin, xpub, keychain)
+    }
+
+    async fn call<T>(&self, args: &[&str]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let chain = match self.network {
+            Network::Bitcoin => "main",
+            Network::Testnet => "test",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+
+        let output = Command::new(&self.executable)
+            .arg("--fingerprint")
+            .arg(self.fingerprint.to_string())
+            .arg("--chain")
+            .arg(chain)
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed to run {}", self.executable.display()))?;
+
+        match parse_response(&output.stdout) {
+            Ok(response) => Ok(response),
+            Err(error) if output.status.success() => Err(error),
+            Err(_) => bail!(
+                "HWI exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Signer for Hwi {
+    async fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction> {
+        tracing::info!("Confirm the transaction on your hardware wallet");
+
+        let response: SignTx = self
+            .call(&["signtx", &base64::encode(serialize(&psbt))])
+            .await
+            .context("Failed to sign transaction with hardware wallet")?;
+
+        if !response.signed {
+            bail!("Hardware wallet did not sign the transaction")
+        }
+
+        let signed = base64::decode(&response.psbt).context("Failed to decode signed PSBT")?;
+
+        deserialize(&signed).context("Failed to deserialize signed PSBT")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Response<T> {
+    Error { error: String, code: i64 },
+    Ok(T),
+}
+
+#[derive(Debug, Deserialize)]
+struct GetXpub {
+    xpub: ExtendedPubKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignTx {
+    psbt: String,
+    signed: bool,
+}
+
+fn parse_response<T>(stdout: &[u8]) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    match serde_json::from_slice(stdout).context("Failed to parse response of HWI")? {
+        Response::Ok(response) => Ok(response),
+        Response::Error { error, code } => bail!("HWI failed with code {}: {}", code, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The master public key of the first BIP32 test vector.
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    #[test]
+    fn descriptors_carry_the_key_origin() {
+        let hwi = Hwi::new(
+            PathBuf::from("hwi"),
+            "d34db33f".parse().unwrap(),
+            Network::Testnet,
+        );
+
+        let descriptor = hwi.descriptor(&XPUB.parse().unwrap(), 1);
+
+        assert_eq!(
+            descriptor,
+            format!("wpkh([d34db33f/84h/1h/0h]{}/1/*)", XPUB)
+        );
+    }
+
+    #[test]
+    fn errors_of_hwi_are_reported() {
+        let stdout = br#"{"error": "No device found", "code": -3}"#;
+
+        let error = parse_response::<SignTx>(stdout).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "HWI failed with code -3: No device found"
+        );
+    }
+}
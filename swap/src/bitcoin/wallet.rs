@@ -1,22 +1,29 @@
+use crate::backoff::{Backoff, Policy};
+use crate::bitcoin::coin_control::{Selection, Utxo};
 use crate::bitcoin::timelocks::BlockHeight;
-use crate::bitcoin::{Address, Amount, Transaction};
+use crate::bitcoin::{
+    electrum, funding_proof, Address, Amount, CoinControl, FundsUnavailable, Hwi,
+    InvalidFundingProof, Signer, Transaction,
+};
 use crate::env;
 use ::bitcoin::util::psbt::PartiallySignedTransaction;
 use ::bitcoin::Txid;
 use anyhow::{bail, Context, Result};
 use bdk::blockchain::{noop_progress, Blockchain, ElectrumBlockchain};
 use bdk::database::BatchDatabase;
-use bdk::descriptor::Segwitv0;
+use bdk::descriptor::{IntoWalletDescriptor, Segwitv0};
 use bdk::electrum_client::{ElectrumApi, GetHistoryRes};
 use bdk::keys::DerivableKey;
-use bdk::wallet::AddressIndex;
+use bdk::wallet::coin_selection::DefaultCoinSelectionAlgorithm;
+use bdk::wallet::tx_builder::CreateTx;
+use bdk::wallet::{AddressIndex, TxBuilder};
 use bdk::{FeeRate, KeychainKind, SignOptions};
-use bitcoin::{Network, Script};
+use bitcoin::{Network, OutPoint, Script, TxOut};
 use reqwest::Url;
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::path::Path;
@@ -26,42 +33,101 @@ use tokio::sync::{watch, Mutex};
 
 const SLED_TREE_NAME: &str = "default_tree";
 
+/// After this many blocks worth of time without a new block, the Electrum
+/// server is assumed to be stuck and another one is tried.
+const STALE_TIP_AFTER_BLOCKS: u32 = 6;
+
 /// Assuming we add a spread of 3% we don't want to pay more than 3% of the
 /// amount for tx fees.
 const MAX_RELATIVE_TX_FEE: Decimal = dec!(0.03);
 const MAX_ABSOLUTE_TX_FEE: Decimal = dec!(100_000);
 const DUST_AMOUNT: u64 = 546;
 
-pub struct Wallet<B = ElectrumBlockchain, D = bdk::sled::Tree, C = Client> {
+pub struct Wallet<B = electrum::Pool<ElectrumBlockchain>, D = bdk::sled::Tree, C = Client> {
     client: Arc<Mutex<C>>,
     wallet: Arc<Mutex<bdk::Wallet<B, D>>>,
     finality_confirmations: u32,
     network: Network,
     target_block: usize,
+    max_absolute_tx_fee: Decimal,
+    /// Signs the transactions of the wallet if its keys are not held in
+    /// software.
+    signer: Option<Arc<dyn Signer>>,
+    /// Restricts the outputs that fund transactions.
+    coin_control: CoinControl,
 }
 
 impl Wallet {
     pub async fn new(
-        electrum_rpc_url: Url,
+        electrum_rpc_urls: Vec<Url>,
         wallet_dir: &Path,
         key: impl DerivableKey<Segwitv0> + Clone,
         env_config: env::Config,
         target_block: usize,
+        max_absolute_tx_fee: Option<Amount>,
+    ) -> Result<Self> {
+        Self::with_descriptors(
+            electrum_rpc_urls,
+            wallet_dir,
+            bdk::template::Bip84(key.clone(), KeychainKind::External),
+            bdk::template::Bip84(key, KeychainKind::Internal),
+            env_config,
+            target_block,
+            max_absolute_tx_fee,
+            None,
+        )
+        .await
+    }
+
+    /// Creates a watch-only wallet whose transactions are signed on the
+    /// hardware wallet.
+    pub async fn new_hardware(
+        electrum_rpc_urls: Vec<Url>,
+        wallet_dir: &Path,
+        hwi: Hwi,
+        env_config: env::Config,
+        target_block: usize,
     ) -> Result<Self> {
-        let client = bdk::electrum_client::Client::new(electrum_rpc_url.as_str())
+        let (external, internal) = hwi.descriptors().await?;
+
+        Self::with_descriptors(
+            electrum_rpc_urls,
+            wallet_dir,
+            external,
+            internal,
+            env_config,
+            target_block,
+            None,
+            Some(Arc::new(hwi)),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn with_descriptors(
+        electrum_rpc_urls: Vec<Url>,
+        wallet_dir: &Path,
+        external: impl IntoWalletDescriptor,
+        internal: impl IntoWalletDescriptor,
+        env_config: env::Config,
+        target_block: usize,
+        max_absolute_tx_fee: Option<Amount>,
+        signer: Option<Arc<dyn Signer>>,
+    ) -> Result<Self> {
+        let blockchain = electrum::Pool::<ElectrumBlockchain>::new(electrum_rpc_urls.clone())
             .context("Failed to initialize Electrum RPC client")?;
 
         let db = bdk::sled::open(wallet_dir)?.open_tree(SLED_TREE_NAME)?;
 
         let wallet = bdk::Wallet::new(
-            bdk::template::Bip84(key.clone(), KeychainKind::External),
-            Some(bdk::template::Bip84(key, KeychainKind::Internal)),
+            external,
+            Some(internal),
             env_config.bitcoin_network,
             db,
-            ElectrumBlockchain::from(client),
+            blockchain,
         )?;
 
-        let electrum = bdk::electrum_client::Client::new(electrum_rpc_url.as_str())
+        let electrum = electrum::Pool::new(electrum_rpc_urls)
             .context("Failed to initialize Electrum RPC client")?;
 
         let network = wallet.network();
@@ -70,11 +136,16 @@ impl Wallet {
             client: Arc::new(Mutex::new(Client::new(
                 electrum,
                 env_config.bitcoin_sync_interval(),
+                env_config.bitcoin_avg_block_time * STALE_TIP_AFTER_BLOCKS,
             )?)),
             wallet: Arc::new(Mutex::new(wallet)),
             finality_confirmations: env_config.bitcoin_finality_confirmations,
             network,
             target_block,
+            max_absolute_tx_fee: max_absolute_tx_fee
+                .map_or(MAX_ABSOLUTE_TX_FEE, |fee| Decimal::from(fee.as_sat())),
+            signer,
+            coin_control: CoinControl::default(),
         })
     }
 
@@ -114,6 +185,48 @@ impl Wallet {
             .with_context(|| format!("Could not get raw tx with id: {}", txid))
     }
 
+    /// Verifies the proof of a taker that they control the Bitcoin they want to
+    /// swap.
+    ///
+    /// Fails with [`InvalidFundingProof`] if the proof does not spend outputs
+    /// worth at least the given amount or does not commit to the given data,
+    /// and with [`FundsUnavailable`] if an output it spends is not unspent.
+    pub async fn verify_funding_proof(
+        &self,
+        proof: &Transaction,
+        amount: Amount,
+        commitment: &[u8],
+    ) -> Result<()> {
+        let mut prevouts = Vec::with_capacity(proof.input.len());
+
+        for input in &proof.input {
+            let script = funding_proof::spent_script(input).context(InvalidFundingProof)?;
+            let outpoint = input.previous_output;
+            let vout = usize::try_from(outpoint.vout)?;
+
+            let unspent = self
+                .client
+                .lock()
+                .await
+                .electrum
+                .call(|electrum| electrum.script_list_unspent(&script))
+                .context("Failed to get unspent outputs of funding proof")?;
+            let value = unspent
+                .iter()
+                .find(|output| output.tx_hash == outpoint.txid && output.tx_pos == vout)
+                .map(|output| output.value)
+                .with_context(|| format!("Output {} is spent or does not exist", outpoint))
+                .context(FundsUnavailable)?;
+
+            prevouts.push(TxOut {
+                value,
+                script_pubkey: script,
+            });
+        }
+
+        funding_proof::verify(proof, &prevouts, amount, commitment).context(InvalidFundingProof)
+    }
+
     pub async fn status_of_script<T>(&self, tx: &T) -> Result<ScriptStatus>
     where
         T: Watchable,
@@ -121,6 +234,70 @@ impl Wallet {
         self.client.lock().await.status_of_script(tx)
     }
 
+    /// Returns the transaction that spends the output of `tx`, if one was
+    /// seen, together with its status.
+    ///
+    /// Only transactions in the history of the output script of `tx` are
+    /// considered, so this finds spends of outputs whose script is not reused.
+    pub async fn spending_transaction<T>(
+        &self,
+        tx: &T,
+        outpoint: OutPoint,
+    ) -> Result<Option<(Transaction, ScriptStatus)>>
+    where
+        T: Watchable,
+    {
+        let history = self
+            .client
+            .lock()
+            .await
+            .electrum
+            .call(|electrum| electrum.script_get_history(&tx.script()))
+            .context("Failed to get script history")?;
+
+        for entry in history.iter().filter(|entry| entry.tx_hash != tx.id()) {
+            let transaction = self.get_raw_transaction(entry.tx_hash).await?;
+
+            if transaction
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+            {
+                let status = self.client.lock().await.status_from_height(entry.height)?;
+
+                return Ok(Some((transaction, status)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The height of the latest block seen by the chain watcher.
+    pub async fn block_height(&self) -> Result<BlockHeight> {
+        let mut client = self.client.lock().await;
+        client.update_state()?;
+
+        Ok(client.latest_block_height)
+    }
+
+    /// Resolves once the chain watcher has seen a block at the given height.
+    ///
+    /// Unlike a timer, this deadline is neither shifted by a drifting clock
+    /// nor by the process being suspended.
+    pub async fn wait_until_block_height(&self, height: BlockHeight) {
+        loop {
+            match self.block_height().await {
+                Ok(current) if current >= height => return,
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::warn!("Failed to get the latest block height: {:#}", error)
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
     pub async fn subscribe_to(&self, tx: impl Watchable + Send + 'static) -> Subscription {
         let txid = tx.id();
         let script = tx.script();
@@ -137,15 +314,30 @@ impl Wallet {
 
                 tokio::spawn(async move {
                     let mut last_status = None;
+                    // Polling continues while the Electrum servers are down, only less often.
+                    let mut backoff = Policy::ELECTRUM.backoff();
+                    let mut poll_in = Policy::ELECTRUM.initial_interval;
 
                     loop {
-                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        tokio::time::sleep(poll_in).await;
 
                         let new_status = match client.lock().await.status_of_script(&tx) {
-                            Ok(new_status) => new_status,
+                            Ok(new_status) => {
+                                backoff.reset();
+                                poll_in = Policy::ELECTRUM.initial_interval;
+                                new_status
+                            }
                             Err(error) => {
-                                tracing::warn!(%txid, "Failed to get status of script: {:#}", error);
-                                return;
+                                poll_in = backoff
+                                    .next_backoff()
+                                    .unwrap_or(Policy::ELECTRUM.max_interval);
+                                tracing::warn!(
+                                    %txid,
+                                    "Failed to get status of script, retrying in {}s: {:#}",
+                                    poll_in.as_secs(),
+                                    error
+                                );
+                                continue;
                             }
                         };
 
@@ -178,6 +370,11 @@ fn print_status_change(txid: Txid, old: Option<ScriptStatus>, new: ScriptStatus)
         (None, new_status) => {
             tracing::debug!(%txid, status = %new_status, "Found relevant Bitcoin transaction");
         }
+        (Some(old_status), new_status)
+            if new_status.confirmations() < old_status.confirmations() =>
+        {
+            tracing::warn!(%txid, %new_status, %old_status, "Bitcoin transaction lost confirmations, the blockchain was reorganised");
+        }
         (Some(old_status), new_status) if old_status != new_status => {
             tracing::debug!(%txid, %new_status, %old_status, "Bitcoin transaction status changed");
         }
@@ -197,7 +394,13 @@ pub struct Subscription {
 
 impl Subscription {
     pub async fn wait_until_final(&self) -> Result<()> {
-        let conf_target = self.finality_confirmations;
+        self.wait_until_final_with(self.finality_confirmations)
+            .await
+    }
+
+    /// Like [`Subscription::wait_until_final`] but with a confirmation target
+    /// that depends on the transaction, e.g. on the amount of a swap.
+    pub async fn wait_until_final_with(&self, conf_target: u32) -> Result<()> {
         let txid = self.txid;
 
         tracing::info!(%txid, required_confirmation=%conf_target, "Waiting for Bitcoin transaction finality");
@@ -255,10 +458,14 @@ where
     C: EstimateFeeRate,
     D: BatchDatabase,
 {
-    pub async fn sign_and_finalize(
-        &self,
-        mut psbt: PartiallySignedTransaction,
-    ) -> Result<Transaction> {
+    pub async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
+        let mut psbt = match &self.signer {
+            Some(signer) => signer.sign(psbt).await?,
+            None => psbt,
+        };
+
+        // Without keys of its own, the wallet only finalizes the signatures of
+        // the signer.
         let finalized = self
             .wallet
             .lock()
@@ -274,6 +481,39 @@ where
         Ok(tx)
     }
 
+    /// Builds a proof that this wallet controls the given amount, to be
+    /// verified with [`Wallet::verify_funding_proof`].
+    ///
+    /// The proof pays to the given address and commits to the given data, but
+    /// it can never be mined. It spends the outputs the coin control policy
+    /// allows, like the lock transaction it proves.
+    pub async fn funding_proof(
+        &self,
+        amount: Amount,
+        address: Address,
+        commitment: &[u8],
+    ) -> Result<Transaction> {
+        let psbt = {
+            let wallet = self.wallet.lock().await;
+            let client = self.client.lock().await;
+            let fee_rate = client.estimate_feerate(self.target_block)?;
+
+            let mut tx_builder = wallet.build_tx();
+            tx_builder.add_recipient(address.script_pubkey(), amount.as_sat());
+            tx_builder.add_data(commitment);
+            tx_builder.fee_rate(fee_rate);
+            tx_builder.nlocktime(funding_proof::LOCK_TIME);
+            // Makes the lock time apply to the transaction.
+            tx_builder.enable_rbf();
+            self.apply_coin_control(&wallet, &mut tx_builder)?;
+            let (psbt, _details) = tx_builder.finish()?;
+
+            psbt
+        };
+
+        self.sign_and_finalize(psbt).await
+    }
+
     pub async fn balance(&self) -> Result<Amount> {
         let balance = self
             .wallet
@@ -340,6 +580,7 @@ where
         let mut tx_builder = wallet.build_tx();
         tx_builder.add_recipient(script.clone(), amount.as_sat());
         tx_builder.fee_rate(fee_rate);
+        self.apply_coin_control(&wallet, &mut tx_builder)?;
         let (psbt, _details) = tx_builder.finish()?;
         let mut psbt: PartiallySignedTransaction = psbt;
 
@@ -397,6 +638,7 @@ where
         let dummy_script = Script::from(vec![0u8; locking_script_size]);
         tx_builder.drain_to(dummy_script);
         tx_builder.fee_rate(fee_rate);
+        self.apply_coin_control(&wallet, &mut tx_builder)?;
 
         let response = tx_builder.finish();
         match response {
@@ -412,9 +654,95 @@ where
         }
     }
 
+    /// Calculates the fee of a transaction that pays the given amount to a
+    /// single output and sends the remaining balance back as change.
+    pub async fn fee_for_amount(
+        &self,
+        locking_script_size: usize,
+        amount: Amount,
+    ) -> Result<Amount> {
+        let wallet = self.wallet.lock().await;
+        let client = self.client.lock().await;
+        let fee_rate = client.estimate_feerate(self.target_block)?;
+
+        let mut tx_builder = wallet.build_tx();
+
+        let dummy_script = Script::from(vec![0u8; locking_script_size]);
+        tx_builder.add_recipient(dummy_script, amount.as_sat());
+        tx_builder.fee_rate(fee_rate);
+        self.apply_coin_control(&wallet, &mut tx_builder)?;
+
+        let (_, details) = tx_builder.finish().context("Failed to build transaction")?;
+        let fee = details
+            .fee
+            .expect("fees are always present with Electrum backend");
+
+        Ok(Amount::from_sat(fee))
+    }
+
+    /// Restricts the outputs the transaction is funded from according to the
+    /// coin control policy of the wallet.
+    fn apply_coin_control(
+        &self,
+        wallet: &bdk::Wallet<B, D>,
+        tx_builder: &mut TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, CreateTx>,
+    ) -> Result<()> {
+        if self.coin_control.is_default() {
+            return Ok(());
+        }
+
+        let transactions = wallet.list_transactions(true)?;
+        let confirmed = transactions
+            .iter()
+            .filter(|tx| tx.confirmation_time.is_some())
+            .map(|tx| tx.txid)
+            .collect::<HashSet<_>>();
+
+        let mut receive_counts = HashMap::<Script, usize>::new();
+        for output in transactions
+            .iter()
+            .filter_map(|tx| tx.transaction.as_ref())
+            .flat_map(|tx| tx.output.iter())
+        {
+            if wallet.is_mine(&output.script_pubkey)? {
+                *receive_counts
+                    .entry(output.script_pubkey.clone())
+                    .or_default() += 1;
+            }
+        }
+
+        let utxos = wallet
+            .list_unspent()?
+            .into_iter()
+            .map(|utxo| Utxo {
+                outpoint: utxo.outpoint,
+                confirmed: confirmed.contains(&utxo.outpoint.txid),
+                address_reused: receive_counts
+                    .get(&utxo.txout.script_pubkey)
+                    .map_or(false, |count| *count > 1),
+            })
+            .collect::<Vec<_>>();
+
+        match self
+            .coin_control
+            .select(&utxos)
+            .context("Coin control policy cannot be applied")?
+        {
+            Selection::Only(outpoints) => {
+                tx_builder.add_utxos(&outpoints)?;
+                tx_builder.manually_selected_only();
+            }
+            Selection::Exclude(outpoints) => {
+                tx_builder.unspendable(outpoints);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Estimate total tx fee for a pre-defined target block based on the
     /// transaction weight. The max fee cannot be more than MAX_PERCENTAGE_FEE
-    /// of amount
+    /// of amount and not more than the configured max absolute fee
     pub async fn estimate_fee(
         &self,
         weight: usize,
@@ -424,7 +752,13 @@ where
         let fee_rate = client.estimate_feerate(self.target_block)?;
         let min_relay_fee = client.min_relay_fee()?;
 
-        estimate_fee(weight, transfer_amount, fee_rate, min_relay_fee)
+        estimate_fee(
+            weight,
+            transfer_amount,
+            fee_rate,
+            min_relay_fee,
+            self.max_absolute_tx_fee,
+        )
     }
 }
 
@@ -433,6 +767,7 @@ fn estimate_fee(
     transfer_amount: Amount,
     fee_rate: FeeRate,
     min_relay_fee: Amount,
+    max_absolute_fee: Decimal,
 ) -> Result<Amount> {
     if transfer_amount.as_sat() <= 546 {
         bail!("Amounts needs to be greater than Bitcoin dust amount.")
@@ -476,12 +811,12 @@ fn estimate_fee(
             min_relay_fee
         );
         min_relay_fee.to_u64()
-    } else if sats_per_vbyte > max_allowed_fee && sats_per_vbyte > MAX_ABSOLUTE_TX_FEE {
+    } else if sats_per_vbyte > max_absolute_fee && max_absolute_fee <= max_allowed_fee {
         tracing::warn!(
             "Hard bound of transaction fees reached. Falling back to: {} sats",
-            MAX_ABSOLUTE_TX_FEE
+            max_absolute_fee
         );
-        MAX_ABSOLUTE_TX_FEE.to_u64()
+        max_absolute_fee.to_u64()
     } else if sats_per_vbyte > max_allowed_fee {
         tracing::warn!(
             "Relative bound of transaction fees reached. Falling back to: {} sats",
@@ -525,6 +860,13 @@ impl<B, D, C> Wallet<B, D, C> {
     pub fn get_network(&self) -> bitcoin::Network {
         self.network
     }
+
+    pub fn with_coin_control(self, coin_control: CoinControl) -> Self {
+        Self {
+            coin_control,
+            ..self
+        }
+    }
 }
 
 pub trait EstimateFeeRate {
@@ -633,6 +975,9 @@ impl WalletBuilder {
             finality_confirmations: 1,
             network: Network::Regtest,
             target_block: 1,
+            max_absolute_tx_fee: MAX_ABSOLUTE_TX_FEE,
+            signer: None,
+            coin_control: CoinControl::default(),
         }
     }
 }
@@ -659,20 +1004,26 @@ impl Watchable for (Txid, Script) {
 }
 
 pub struct Client {
-    electrum: bdk::electrum_client::Client,
+    electrum: electrum::Pool<bdk::electrum_client::Client>,
     latest_block_height: BlockHeight,
     last_sync: Instant,
     sync_interval: Duration,
+    last_new_block: Instant,
+    stale_tip_timeout: Duration,
     script_history: BTreeMap<Script, Vec<GetHistoryRes>>,
     subscriptions: HashMap<(Txid, Script), Subscription>,
 }
 
 impl Client {
-    fn new(electrum: bdk::electrum_client::Client, interval: Duration) -> Result<Self> {
+    fn new(
+        electrum: electrum::Pool<bdk::electrum_client::Client>,
+        interval: Duration,
+        stale_tip_timeout: Duration,
+    ) -> Result<Self> {
         // Initially fetch the latest block for storing the height.
         // We do not act on this subscription after this call.
         let latest_block = electrum
-            .block_headers_subscribe()
+            .call(|electrum| electrum.block_headers_subscribe())
             .context("Failed to subscribe to header notifications")?;
 
         Ok(Self {
@@ -680,6 +1031,8 @@ impl Client {
             latest_block_height: BlockHeight::try_from(latest_block)?,
             last_sync: Instant::now(),
             sync_interval: interval,
+            last_new_block: Instant::now(),
+            stale_tip_timeout,
             script_history: Default::default(),
             subscriptions: Default::default(),
         })
@@ -725,20 +1078,26 @@ impl Client {
                     tracing::warn!("Found more than a single history entry for script. This is highly unexpected and those history entries will be ignored")
                 }
 
-                if last.height <= 0 {
-                    Ok(ScriptStatus::InMempool)
-                } else {
-                    Ok(ScriptStatus::Confirmed(
-                        Confirmed::from_inclusion_and_latest_block(
-                            u32::try_from(last.height)?,
-                            u32::from(self.latest_block_height),
-                        ),
-                    ))
-                }
+                self.status_from_height(last.height)
             }
         }
     }
 
+    /// The status of a transaction included at the height of an entry of a
+    /// script history.
+    fn status_from_height(&self, height: i32) -> Result<ScriptStatus> {
+        if height <= 0 {
+            Ok(ScriptStatus::InMempool)
+        } else {
+            Ok(ScriptStatus::Confirmed(
+                Confirmed::from_inclusion_and_latest_block(
+                    u32::try_from(height)?,
+                    u32::from(self.latest_block_height),
+                ),
+            ))
+        }
+    }
+
     fn update_latest_block(&mut self) -> Result<()> {
         // Fetch the latest block for storing the height.
         // We do not act on this subscription after this call, as we cannot rely on
@@ -747,7 +1106,7 @@ impl Client {
         // upon renewing the connection.
         let latest_block = self
             .electrum
-            .block_headers_subscribe()
+            .call(|electrum| electrum.block_headers_subscribe())
             .context("Failed to subscribe to header notifications")?;
         let latest_block_height = BlockHeight::try_from(latest_block)?;
 
@@ -757,6 +1116,27 @@ impl Client {
                 "Got notification for new block"
             );
             self.latest_block_height = latest_block_height;
+            self.last_new_block = Instant::now();
+        } else if latest_block_height < self.latest_block_height {
+            // Keeping the higher block height would overstate the depth of transactions
+            // that were moved to a later block or back into the mempool.
+            tracing::warn!(
+                block_height = u32::from(latest_block_height),
+                previous_block_height = u32::from(self.latest_block_height),
+                "Latest block moved back, the blockchain was reorganised"
+            );
+            self.latest_block_height = latest_block_height;
+        } else if self.electrum.number_of_servers() > 1
+            && self.last_new_block.elapsed() > self.stale_tip_timeout
+        {
+            // The script histories are fetched from the new server right after this, so
+            // nothing else needs to be resynchronized.
+            tracing::info!(
+                url = %self.electrum.url(),
+                "No new block for a while, trying another Electrum server"
+            );
+            self.electrum.fail_over(&self.electrum.get());
+            self.last_new_block = Instant::now();
         }
 
         Ok(())
@@ -765,7 +1145,7 @@ impl Client {
     fn update_script_histories(&mut self) -> Result<()> {
         let histories = self
             .electrum
-            .batch_script_get_history(self.script_history.keys())
+            .call(|electrum| electrum.batch_script_get_history(self.script_history.keys()))
             .context("Failed to get script histories")?;
 
         if histories.len() != self.script_history.len() {
@@ -789,7 +1169,9 @@ impl EstimateFeeRate for Client {
     fn estimate_feerate(&self, target_block: usize) -> Result<FeeRate> {
         // https://github.com/romanz/electrs/blob/f9cf5386d1b5de6769ee271df5eef324aa9491bc/src/rpc.rs#L213
         // Returned estimated fees are per BTC/kb.
-        let fee_per_byte = self.electrum.estimate_fee(target_block)?;
+        let fee_per_byte = self
+            .electrum
+            .call(|electrum| electrum.estimate_fee(target_block))?;
         // we do not expect fees being that high.
         #[allow(clippy::cast_possible_truncation)]
         Ok(FeeRate::from_btc_per_kvb(fee_per_byte as f32))
@@ -798,7 +1180,8 @@ impl EstimateFeeRate for Client {
     fn min_relay_fee(&self) -> Result<bitcoin::Amount> {
         // https://github.com/romanz/electrs/blob/f9cf5386d1b5de6769ee271df5eef324aa9491bc/src/rpc.rs#L219
         // Returned fee is in BTC/kb
-        let relay_fee = bitcoin::Amount::from_btc(self.electrum.relay_fee()?)?;
+        let relay_fee =
+            bitcoin::Amount::from_btc(self.electrum.call(|electrum| electrum.relay_fee())?)?;
         Ok(relay_fee)
     }
 }
@@ -876,6 +1259,15 @@ impl ScriptStatus {
     pub fn has_been_seen(&self) -> bool {
         matches!(self, ScriptStatus::InMempool | ScriptStatus::Confirmed(_))
     }
+
+    /// The number of confirmations, zero if the script is not included in a
+    /// block.
+    pub fn confirmations(&self) -> u32 {
+        match self {
+            ScriptStatus::Confirmed(inner) => inner.confirmations(),
+            _ => 0,
+        }
+    }
 }
 
 impl fmt::Display for ScriptStatus {
@@ -936,7 +1328,8 @@ mod tests {
         let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
         let relay_fee = bitcoin::Amount::ONE_SAT;
-        let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+        let is_fee =
+            estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
         // weight / 4.0 *  sat_per_vb
         let should_fee = bitcoin::Amount::from_sat(10_000);
@@ -953,7 +1346,8 @@ mod tests {
         let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
         let relay_fee = bitcoin::Amount::from_sat(100_000);
-        let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+        let is_fee =
+            estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
         // weight / 4.0 *  sat_per_vb would be smaller than relay fee hence we take min
         // relay fee
@@ -971,7 +1365,8 @@ mod tests {
         let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
         let relay_fee = bitcoin::Amount::ONE_SAT;
-        let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+        let is_fee =
+            estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
         // weight / 4.0 *  sat_per_vb would be greater than 3% hence we take max
         // relative fee.
@@ -990,13 +1385,31 @@ mod tests {
         let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
         let relay_fee = bitcoin::Amount::ONE_SAT;
-        let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+        let is_fee =
+            estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
         // weight / 4.0 *  sat_per_vb would be greater than 3% hence we take total
         // max allowed fee.
         assert_eq!(is_fee.as_sat(), MAX_ABSOLUTE_TX_FEE.to_u64().unwrap());
     }
 
+    #[test]
+    fn given_1BTC_and_1k_sats_per_vb_fees_should_hit_configured_total_max() {
+        // 400 weight = 100 vbyte
+        let weight = 400;
+        let amount = bitcoin::Amount::from_sat(100_000_000);
+
+        let sat_per_vb = 1_000.0;
+        let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
+
+        let relay_fee = bitcoin::Amount::ONE_SAT;
+        let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee, dec!(50_000)).unwrap();
+
+        // weight / 4.0 *  sat_per_vb is below the default max but above the
+        // configured one
+        assert_eq!(is_fee, bitcoin::Amount::from_sat(50_000));
+    }
+
     proptest! {
         #[test]
         fn given_randon_amount_random_fee_and_random_relay_rate_but_fix_weight_does_not_error(
@@ -1010,7 +1423,7 @@ mod tests {
             let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
             let relay_fee = bitcoin::Amount::from_sat(relay_fee);
-            let _is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+            let _is_fee = estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
         }
     }
@@ -1027,7 +1440,7 @@ mod tests {
             let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
             let relay_fee = bitcoin::Amount::ONE_SAT;
-            let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+            let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
             // weight / 4 * 1_000 is always lower than MAX_ABSOLUTE_TX_FEE
             assert!(is_fee.as_sat() < MAX_ABSOLUTE_TX_FEE.to_u64().unwrap());
@@ -1046,7 +1459,7 @@ mod tests {
             let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
             let relay_fee = bitcoin::Amount::ONE_SAT;
-            let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee).unwrap();
+            let is_fee = estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).unwrap();
 
             // weight / 4 * 1_000  is always higher than MAX_ABSOLUTE_TX_FEE
             assert!(is_fee.as_sat() >= MAX_ABSOLUTE_TX_FEE.to_u64().unwrap());
@@ -1064,7 +1477,7 @@ mod tests {
             let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
 
             let relay_fee = bitcoin::Amount::from_sat(1);
-            assert!(estimate_fee(weight, amount, fee_rate, relay_fee).is_err());
+            assert!(estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).is_err());
 
         }
     }
@@ -1080,7 +1493,7 @@ mod tests {
             let fee_rate = FeeRate::from_sat_per_vb(1.0);
 
             let relay_fee = bitcoin::Amount::from_sat(relay_fee);
-            assert!(estimate_fee(weight, amount, fee_rate, relay_fee).is_err());
+            assert!(estimate_fee(weight, amount, fee_rate, relay_fee, MAX_ABSOLUTE_TX_FEE).is_err());
         }
     }
 
@@ -1171,6 +1584,42 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn funds_only_from_manually_selected_outputs() {
+        let wallet = WalletBuilder::new(50_000).with_num_utxos(2).build();
+        let selected = wallet.wallet.lock().await.list_unspent().unwrap()[0].outpoint;
+        let wallet = wallet.with_coin_control(CoinControl {
+            utxos: vec![selected],
+            ..CoinControl::default()
+        });
+
+        let psbt = wallet
+            .send_to_address(
+                wallet.new_address().await.unwrap(),
+                Amount::from_sat(10_000),
+                None,
+            )
+            .await
+            .unwrap();
+        let inputs = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, vec![selected]);
+
+        let result = wallet
+            .send_to_address(
+                wallet.new_address().await.unwrap(),
+                Amount::from_sat(60_000),
+                None,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+
     #[test]
     fn printing_status_change_doesnt_spam_on_same_status() {
         let writer = capture_logs(LevelFilter::DEBUG);
@@ -1201,6 +1650,24 @@ DEBUG swap::bitcoin::wallet: Bitcoin transaction status changed txid=00000000000
         )
     }
 
+    #[test]
+    fn warns_about_lost_confirmations() {
+        let writer = capture_logs(LevelFilter::WARN);
+
+        let tx = Txid::default();
+        let mut old = None;
+        old = Some(print_status_change(tx, old, confs(2)));
+        old = Some(print_status_change(tx, old, confs(3)));
+        old = Some(print_status_change(tx, old, ScriptStatus::InMempool));
+        print_status_change(tx, old, confs(1));
+
+        assert_eq!(
+            writer.captured(),
+            r" WARN swap::bitcoin::wallet: Bitcoin transaction lost confirmations, the blockchain was reorganised txid=0000000000000000000000000000000000000000000000000000000000000000 new_status=in mempool old_status=confirmed with 3 blocks
+"
+        )
+    }
+
     fn confs(confirmations: u32) -> ScriptStatus {
         ScriptStatus::from_confirmations(confirmations)
     }
@@ -1,23 +1,32 @@
+pub mod auto_refund;
 mod behaviour;
 pub mod cancel;
 pub mod command;
+pub mod config;
+pub mod emergency_kit;
 mod event_loop;
 mod list_sellers;
+pub mod monitor;
 pub mod refund;
+pub mod split;
 pub mod tracing;
 pub mod transport;
+pub mod view_key;
 
 pub use behaviour::{Behaviour, OutEvent};
 pub use cancel::cancel;
-pub use event_loop::{EventLoop, EventLoopHandle};
-pub use list_sellers::{list_sellers, Seller, Status as SellerStatus};
+pub use event_loop::{BtcLockedMessage, EventLoop, EventLoopHandle};
+pub use list_sellers::{
+    list_sellers, select as select_sellers, Filter as SellerFilter, Reserve, Seller, SortBy,
+    Status as SellerStatus,
+};
 pub use refund::refund;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::asb;
-    use crate::cli::list_sellers::{Seller, Status};
+    use crate::cli::list_sellers::{Reserve, Seller, Status};
     use crate::network::quote;
     use crate::network::quote::BidQuote;
     use crate::network::rendezvous::XmrBtcNamespace;
@@ -46,6 +55,7 @@ mod tests {
             namespace,
             0,
             identity::Keypair::generate_ed25519(),
+            None,
         );
         let sellers = tokio::time::timeout(Duration::from_secs(15), list_sellers)
             .await
@@ -90,13 +100,14 @@ mod tests {
         };
 
         let mut asb = new_swarm(|_, identity| StaticQuoteAsbBehaviour {
-            rendezvous: asb::rendezous::Behaviour::new(
-                identity,
-                rendezvous_peer_id,
-                rendezvous_address,
-                namespace,
-                None,
-            ),
+            rendezvous: asb::rendezous::Behaviour::new(identity, vec![
+                asb::rendezous::RendezvousNode::new(
+                    &rendezvous_address,
+                    rendezvous_peer_id,
+                    namespace,
+                    None,
+                ),
+            ]),
             ping: Default::default(),
             quote: quote::asb(),
             static_quote,
@@ -123,6 +134,8 @@ mod tests {
         Seller {
             multiaddr: asb_address.with(Protocol::P2p(asb_peer_id.into())),
             status: Status::Online(static_quote),
+            reserve: Reserve::NotProvided,
+            effective_price: None,
         }
     }
 
@@ -157,7 +170,7 @@ mod tests {
             } = event
             {
                 self.quote
-                    .send_response(channel, self.static_quote)
+                    .send_response(channel, self.static_quote.into())
                     .unwrap();
             }
         }
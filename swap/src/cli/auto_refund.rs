@@ -0,0 +1,128 @@
+use crate::bitcoin::{ExpiredTimelocks, Wallet};
+use crate::cli::{cancel, refund};
+use crate::database::Database;
+use crate::protocol::bob::BobState;
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Periodically cancels and refunds swaps in the database whose cancel
+/// timelock has expired.
+///
+/// The swap that is currently run by the CLI, if any, is skipped, its own state
+/// machine takes care of cancelling and refunding it.
+pub async fn refund_expired_swaps(
+    bitcoin_wallet: Arc<Wallet>,
+    db: Database,
+    running_swap_id: Option<Uuid>,
+    interval: Duration,
+) {
+    loop {
+        refund_all_expired_swaps(bitcoin_wallet.clone(), db.clone(), running_swap_id).await;
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Cancels and refunds all swaps in the database whose cancel timelock has
+/// expired, but not their punish timelock, except for the given swap.
+pub async fn refund_all_expired_swaps(
+    bitcoin_wallet: Arc<Wallet>,
+    db: Database,
+    skip_swap_id: Option<Uuid>,
+) {
+    let swaps = match db.all_bob() {
+        Ok(swaps) => swaps,
+        Err(error) => {
+            tracing::warn!("Failed to load swaps to refund: {:#}", error);
+            return;
+        }
+    };
+
+    for (swap_id, state) in swaps {
+        if Some(swap_id) == skip_swap_id {
+            continue;
+        }
+
+        if let Err(error) =
+            refund_if_expired(swap_id, state.into(), bitcoin_wallet.clone(), db.clone()).await
+        {
+            tracing::warn!(%swap_id, "Failed to automatically refund swap: {:#}", error);
+        }
+    }
+}
+
+async fn refund_if_expired(
+    swap_id: Uuid,
+    state: BobState,
+    bitcoin_wallet: Arc<Wallet>,
+    db: Database,
+) -> Result<()> {
+    let (state6, cancelled) = match state {
+        BobState::BtcLocked(state3) => (state3.cancel(), false),
+        BobState::XmrLockProofReceived { state, .. }
+        | BobState::BtcEarlyRefundable { state, .. } => (state.cancel(), false),
+        BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => (state4.cancel(), false),
+        BobState::CancelTimelockExpired(state6) => (state6, false),
+        BobState::BtcCancelled(state6) => (state6, true),
+        BobState::Started { .. }
+        | BobState::SwapSetupCompleted(_)
+        | BobState::BtcRedeemed(_)
+        | BobState::BtcRefunded(_)
+        | BobState::BtcEarlyRefunded(_)
+        | BobState::XmrRedeemed { .. }
+        | BobState::BtcPunished { .. }
+        | BobState::SafelyAborted => return Ok(()),
+    };
+
+    let expired_timelock = state6.expired_timelock(bitcoin_wallet.as_ref()).await?;
+    if !is_refundable(expired_timelock) {
+        if let ExpiredTimelocks::Punish = expired_timelock {
+            tracing::warn!(%swap_id, "Punish timelock expired, not refunding swap automatically");
+        }
+
+        return Ok(());
+    }
+
+    if !cancelled {
+        tracing::info!(%swap_id, "Cancel timelock expired, automatically cancelling swap");
+
+        let (txid, _) = cancel(swap_id, bitcoin_wallet.clone(), db.clone(), false)
+            .await?
+            .map_err(|error| anyhow!(error))?;
+
+        tracing::info!(%swap_id, %txid, "Cancel transaction published");
+    }
+
+    tracing::info!(%swap_id, "Automatically refunding swap");
+
+    refund(swap_id, bitcoin_wallet, db, false)
+        .await?
+        .map_err(|error| anyhow!(error))?;
+
+    tracing::info!(%swap_id, "Bitcoin refunded");
+
+    Ok(())
+}
+
+/// Swaps are only refunded automatically between the cancel and the punish
+/// timelock.
+///
+/// Once the punish timelock expired the seller may punish at any time, the
+/// user has to decide whether to still try a refund.
+fn is_refundable(expired_timelock: ExpiredTimelocks) -> bool {
+    matches!(expired_timelock, ExpiredTimelocks::Cancel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_refunds_between_cancel_and_punish_timelock() {
+        assert!(!is_refundable(ExpiredTimelocks::None));
+        assert!(is_refundable(ExpiredTimelocks::Cancel));
+        assert!(!is_refundable(ExpiredTimelocks::Punish));
+    }
+}
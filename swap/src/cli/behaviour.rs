@@ -1,6 +1,6 @@
-use crate::network::quote::BidQuote;
+use crate::network::quote::SignedBidQuote;
 use crate::network::swap_setup::bob;
-use crate::network::{encrypted_signature, quote, redial, transfer_proof};
+use crate::network::{early_refund, encrypted_signature, quote, receipt, redial, transfer_proof};
 use crate::protocol::bob::State2;
 use crate::{bitcoin, env};
 use anyhow::{anyhow, Error, Result};
@@ -15,7 +15,7 @@ use std::time::Duration;
 pub enum OutEvent {
     QuoteReceived {
         id: RequestId,
-        response: BidQuote,
+        response: SignedBidQuote,
     },
     SwapSetupCompleted(Box<Result<State2>>),
     TransferProofReceived {
@@ -26,6 +26,19 @@ pub enum OutEvent {
     EncryptedSignatureAcknowledged {
         id: RequestId,
     },
+    EarlyRefundReceived {
+        msg: Box<early_refund::Request>,
+        channel: ResponseChannel<()>,
+        peer: PeerId,
+    },
+    ReceiptReceived {
+        id: RequestId,
+        response: receipt::Response,
+    },
+    ReceiptRequestFailed {
+        id: RequestId,
+        error: Error,
+    },
     AllRedialAttemptsExhausted {
         peer: PeerId,
     },
@@ -63,6 +76,8 @@ pub struct Behaviour {
     pub swap_setup: bob::Behaviour,
     pub transfer_proof: transfer_proof::Behaviour,
     pub encrypted_signature: encrypted_signature::Behaviour,
+    pub early_refund: early_refund::Behaviour,
+    pub receipt: receipt::Behaviour,
     pub redial: redial::Behaviour,
 
     /// Ping behaviour that ensures that the underlying network connection is
@@ -82,6 +97,8 @@ impl Behaviour {
             swap_setup: bob::Behaviour::new(env_config, bitcoin_wallet),
             transfer_proof: transfer_proof::bob(),
             encrypted_signature: encrypted_signature::bob(),
+            early_refund: early_refund::bob(),
+            receipt: receipt::bob(),
             redial: redial::Behaviour::new(alice, Duration::from_secs(2)),
             ping: Ping::new(PingConfig::new().with_keep_alive(true)),
         }
@@ -91,7 +108,10 @@ impl Behaviour {
     pub fn add_address(&mut self, peer_id: PeerId, address: Multiaddr) {
         self.quote.add_address(&peer_id, address.clone());
         self.transfer_proof.add_address(&peer_id, address.clone());
-        self.encrypted_signature.add_address(&peer_id, address);
+        self.encrypted_signature
+            .add_address(&peer_id, address.clone());
+        self.early_refund.add_address(&peer_id, address.clone());
+        self.receipt.add_address(&peer_id, address);
     }
 }
 
@@ -21,7 +21,8 @@ pub async fn cancel(
 
     let state6 = match state {
         BobState::BtcLocked(state3) => state3.cancel(),
-        BobState::XmrLockProofReceived { state, .. } => state.cancel(),
+        BobState::XmrLockProofReceived { state, .. }
+        | BobState::BtcEarlyRefundable { state, .. } => state.cancel(),
         BobState::XmrLocked(state4) => state4.cancel(),
         BobState::EncSigSent(state4) => state4.cancel(),
         BobState::CancelTimelockExpired(state6) => state6,
@@ -30,6 +31,7 @@ pub async fn cancel(
         | BobState::BtcRedeemed(_)
         | BobState::BtcCancelled(_)
         | BobState::BtcRefunded(_)
+        | BobState::BtcEarlyRefunded(_)
         | BobState::XmrRedeemed { .. }
         | BobState::BtcPunished { .. }
         | BobState::SafelyAborted => bail!(
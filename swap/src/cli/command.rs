@@ -1,12 +1,17 @@
+use crate::bitcoin::CoinControl;
+use crate::cli::config::{self, Config};
+use crate::cli::list_sellers::{Filter, SortBy};
 use crate::env::GetConfig;
 use crate::fs::system_data_dir;
 use crate::network::rendezvous::XmrBtcNamespace;
+use crate::tor::DEFAULT_SOCKS5_PORT;
 use crate::{env, monero};
-use anyhow::{Context, Result};
-use bitcoin::AddressType;
+use anyhow::{bail, Context, Result};
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::{AddressType, OutPoint};
 use libp2p::core::Multiaddr;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use structopt::{clap, StructOpt};
 use url::Url;
@@ -17,20 +22,19 @@ pub const DEFAULT_MONERO_DAEMON_ADDRESS: &str = "node.melo.tools:18081";
 pub const DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET: &str = "stagenet.melo.tools:38081";
 
 // See: https://1209k.com/bitcoin-eye/ele.php?chain=btc
-const DEFAULT_ELECTRUM_RPC_URL: &str = "ssl://electrum.blockstream.info:50002";
+pub const DEFAULT_ELECTRUM_RPC_URL: &str = "ssl://electrum.blockstream.info:50002";
 // See: https://1209k.com/bitcoin-eye/ele.php?chain=tbtc
 pub const DEFAULT_ELECTRUM_RPC_URL_TESTNET: &str = "ssl://electrum.blockstream.info:60002";
 
-const DEFAULT_BITCOIN_CONFIRMATION_TARGET: usize = 3;
-const DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET: usize = 1;
-
-const DEFAULT_TOR_SOCKS5_PORT: &str = "9050";
+pub const DEFAULT_BITCOIN_CONFIRMATION_TARGET: usize = 3;
+pub const DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET: usize = 1;
 
 #[derive(Debug, PartialEq)]
 pub struct Arguments {
     pub env_config: env::Config,
     pub debug: bool,
     pub json: bool,
+    pub hardware_wallet: Option<crate::bitcoin::Hwi>,
     pub data_dir: PathBuf,
     pub cmd: Command,
 }
@@ -47,10 +51,14 @@ pub enum ParseResult {
     PrintAndExitZero { message: String },
 }
 
-pub fn parse_args_and_apply_defaults<I, T>(raw_args: I) -> Result<ParseResult>
+/// Parses the command line, settings that are not given fall back to the
+/// config file returned by `read_config` for the data directory, usually
+/// [`Config::read`].
+pub fn parse_args_and_apply_defaults<I, T, C>(raw_args: I, read_config: C) -> Result<ParseResult>
 where
     I: IntoIterator<Item = T>,
     T: Into<OsString> + Clone,
+    C: FnOnce(&Path) -> Result<Config>,
 {
     let args = match RawArguments::clap().get_matches_from_safe(raw_args) {
         Ok(matches) => RawArguments::from_clap(&matches),
@@ -66,67 +74,152 @@ where
     let json = args.json;
     let is_testnet = args.testnet;
     let data = args.data;
+    let hardware_wallet = args.hardware_wallet.map(|fingerprint| {
+        crate::bitcoin::Hwi::new(
+            args.hwi_path,
+            fingerprint,
+            env_config_from(is_testnet).bitcoin_network,
+        )
+    });
+
+    let data_dir = data::data_dir_from(data, is_testnet)?;
+    // A broken config file must not prevent replacing it.
+    let config_file = match args.cmd {
+        RawCommand::Config { .. } => Config::default(),
+        _ => read_config(&data_dir)?,
+    };
 
     let arguments = match args.cmd {
         RawCommand::BuyXmr {
             seller: Seller { seller },
+            split,
+            rendezvous_point,
             bitcoin,
             bitcoin_change_address,
             monero,
             monero_receive_address,
-            tor: Tor { tor_socks5_port },
+            i_am_sure,
+            monero_amount,
+            max_effective_price,
+            coin_control,
+            tor,
         } => {
-            let (bitcoin_electrum_rpc_url, bitcoin_target_block) =
-                bitcoin.apply_defaults(is_testnet)?;
-            let monero_daemon_address = monero.apply_defaults(is_testnet);
-            let monero_receive_address =
-                validate_monero_address(monero_receive_address, is_testnet)?;
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
+            let monero_daemon_addresses = monero.apply_defaults(is_testnet, &config_file.monero);
+            let tor_socks5_port = tor.apply_defaults(&config_file.tor);
+            let coin_control = coin_control.into_policy();
+            let env_config = env_config_from(is_testnet);
+            let monero_receive_address = validate_monero_address(
+                monero_receive_address,
+                env_config.monero_network,
+                i_am_sure,
+            )?;
             let bitcoin_change_address =
-                validate_bitcoin_address(bitcoin_change_address, is_testnet)?;
+                validate_bitcoin_address(bitcoin_change_address, env_config.bitcoin_network)?;
 
-            Arguments {
-                env_config: env_config_from(is_testnet),
-                debug,
-                json,
-                data_dir: data::data_dir_from(data, is_testnet)?,
-                cmd: Command::BuyXmr {
+            let cmd = match (split, seller, rendezvous_point, monero_amount) {
+                (false, Some(seller), None, monero_amount) => Command::BuyXmr {
                     seller,
-                    bitcoin_electrum_rpc_url,
+                    bitcoin_electrum_rpc_urls,
+                    bitcoin_target_block,
+                    bitcoin_change_address,
+                    monero_receive_address,
+                    monero_amount,
+                    monero_daemon_addresses,
+                    coin_control,
+                    tor_socks5_port,
+                    max_effective_price,
+                },
+                (true, None, Some(_), Some(_)) if max_effective_price.is_some() => {
+                    bail!("--max-effective-rate can only be given when buying from a single seller")
+                }
+                (true, None, Some(rendezvous_point), Some(monero_amount)) => Command::BuyXmrSplit {
+                    rendezvous_point,
+                    namespace: XmrBtcNamespace::from_is_testnet(is_testnet),
+                    bitcoin_electrum_rpc_urls,
                     bitcoin_target_block,
                     bitcoin_change_address,
                     monero_receive_address,
-                    monero_daemon_address,
+                    monero_amount,
+                    monero_daemon_addresses,
+                    coin_control,
                     tor_socks5_port,
                 },
+                (false, None, ..) => bail!("A seller is required unless buying with --split"),
+                (false, Some(_), Some(_), _) => {
+                    bail!("A rendezvous point can only be given when buying with --split")
+                }
+                (true, Some(_), ..) => {
+                    bail!("Buying with --split discovers the sellers, no seller can be given")
+                }
+                (true, None, None, _) => {
+                    bail!("Buying with --split requires a rendezvous point to discover sellers")
+                }
+                (true, None, Some(_), None) => {
+                    bail!("Buying with --split requires the amount of XMR to buy")
+                }
+            };
+
+            Arguments {
+                env_config,
+                debug,
+                json,
+                hardware_wallet,
+                data_dir,
+                cmd,
             }
         }
-        RawCommand::History => Arguments {
+        RawCommand::History { swap_id } => Arguments {
             env_config: env_config_from(is_testnet),
             debug,
             json,
-            data_dir: data::data_dir_from(data, is_testnet)?,
-            cmd: Command::History,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::History { swap_id },
         },
+        RawCommand::Balance { bitcoin } => {
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
+
+            Arguments {
+                env_config: env_config_from(is_testnet),
+                debug,
+                json,
+                hardware_wallet,
+                data_dir,
+                cmd: Command::Balance {
+                    bitcoin_electrum_rpc_urls,
+                    bitcoin_target_block,
+                },
+            }
+        }
         RawCommand::Resume {
             swap_id: SwapId { swap_id },
+            monitor_only,
             bitcoin,
             monero,
-            tor: Tor { tor_socks5_port },
+            coin_control,
+            tor,
         } => {
-            let (bitcoin_electrum_rpc_url, bitcoin_target_block) =
-                bitcoin.apply_defaults(is_testnet)?;
-            let monero_daemon_address = monero.apply_defaults(is_testnet);
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
+            let monero_daemon_addresses = monero.apply_defaults(is_testnet, &config_file.monero);
+            let tor_socks5_port = tor.apply_defaults(&config_file.tor);
 
             Arguments {
                 env_config: env_config_from(is_testnet),
                 debug,
                 json,
-                data_dir: data::data_dir_from(data, is_testnet)?,
+                hardware_wallet,
+                data_dir,
                 cmd: Command::Resume {
                     swap_id,
-                    bitcoin_electrum_rpc_url,
+                    monitor_only,
+                    bitcoin_electrum_rpc_urls,
                     bitcoin_target_block,
-                    monero_daemon_address,
+                    monero_daemon_addresses,
+                    coin_control: coin_control.into_policy(),
                     tor_socks5_port,
                 },
             }
@@ -136,18 +229,41 @@ where
             force,
             bitcoin,
         } => {
-            let (bitcoin_electrum_rpc_url, bitcoin_target_block) =
-                bitcoin.apply_defaults(is_testnet)?;
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
 
             Arguments {
                 env_config: env_config_from(is_testnet),
                 debug,
                 json,
-                data_dir: data::data_dir_from(data, is_testnet)?,
+                hardware_wallet,
+                data_dir,
                 cmd: Command::Cancel {
                     swap_id,
                     force,
-                    bitcoin_electrum_rpc_url,
+                    bitcoin_electrum_rpc_urls,
+                    bitcoin_target_block,
+                },
+            }
+        }
+        RawCommand::Status {
+            swap_id: SwapId { swap_id },
+            verbose,
+            bitcoin,
+        } => {
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
+
+            Arguments {
+                env_config: env_config_from(is_testnet),
+                debug,
+                json,
+                hardware_wallet,
+                data_dir,
+                cmd: Command::Status {
+                    swap_id,
+                    verbose,
+                    bitcoin_electrum_rpc_urls,
                     bitcoin_target_block,
                 },
             }
@@ -157,36 +273,190 @@ where
             force,
             bitcoin,
         } => {
-            let (bitcoin_electrum_rpc_url, bitcoin_target_block) =
-                bitcoin.apply_defaults(is_testnet)?;
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
 
             Arguments {
                 env_config: env_config_from(is_testnet),
                 debug,
                 json,
-                data_dir: data::data_dir_from(data, is_testnet)?,
+                hardware_wallet,
+                data_dir,
                 cmd: Command::Refund {
                     swap_id,
                     force,
-                    bitcoin_electrum_rpc_url,
+                    bitcoin_electrum_rpc_urls,
                     bitcoin_target_block,
                 },
             }
         }
         RawCommand::ListSellers {
             rendezvous_point,
-            tor: Tor { tor_socks5_port },
+            require_reserve_proof,
+            min_amount,
+            max_amount,
+            amount,
+            tor_only,
+            sort_by,
+            monero,
+            tor,
+        } => {
+            let filter = Filter {
+                min_amount,
+                max_amount,
+                amount,
+                tor_only,
+            };
+            if sort_by == SortBy::EffectivePrice && filter.price_amount().is_none() {
+                bail!("Sorting by effective price requires --amount or --min-amount to compare the sellers at")
+            }
+
+            Arguments {
+                env_config: env_config_from(is_testnet),
+                debug,
+                json,
+                hardware_wallet,
+                data_dir,
+                cmd: Command::ListSellers {
+                    rendezvous_point,
+                    namespace: XmrBtcNamespace::from_is_testnet(is_testnet),
+                    require_reserve_proof,
+                    filter,
+                    sort_by,
+                    monero_daemon_addresses: monero.apply_defaults(is_testnet, &config_file.monero),
+                    tor_socks5_port: tor.apply_defaults(&config_file.tor),
+                },
+            }
+        }
+        RawCommand::Export {
+            swap_id: SwapId { swap_id },
+            output,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::Export { swap_id, output },
+        },
+        RawCommand::EmergencyKit {
+            swap_id: SwapId { swap_id },
+            output,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::EmergencyKit { swap_id, output },
+        },
+        RawCommand::Receipt {
+            swap_id: SwapId { swap_id },
+            output,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::Receipt { swap_id, output },
+        },
+        RawCommand::ExportViewKey {
+            swap_id: SwapId { swap_id },
+            output,
         } => Arguments {
             env_config: env_config_from(is_testnet),
             debug,
             json,
-            data_dir: data::data_dir_from(data, is_testnet)?,
-            cmd: Command::ListSellers {
-                rendezvous_point,
-                namespace: rendezvous_namespace_from(is_testnet),
-                tor_socks5_port,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::ExportViewKey { swap_id, output },
+        },
+        RawCommand::VerifyViewKey { input, monero } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::VerifyViewKey {
+                input,
+                monero_daemon_addresses: monero.apply_defaults(is_testnet, &config_file.monero),
             },
         },
+        RawCommand::Import { input } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::Import { input },
+        },
+        RawCommand::MigrateDb { dry_run, rollback } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::MigrateDb { dry_run, rollback },
+        },
+        RawCommand::Identity {
+            cmd: RawIdentityCommand::PeerId,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::PeerId,
+        },
+        RawCommand::Identity {
+            cmd: RawIdentityCommand::Rotate,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::RotateIdentity,
+        },
+        RawCommand::Wallet {
+            cmd: RawWalletCommand::ExportSeed,
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::ExportSeed,
+        },
+        RawCommand::Wallet {
+            cmd: RawWalletCommand::ImportSeed { bitcoin },
+        } => {
+            let (bitcoin_electrum_rpc_urls, bitcoin_target_block) =
+                bitcoin.apply_defaults(is_testnet, &config_file.bitcoin)?;
+
+            Arguments {
+                env_config: env_config_from(is_testnet),
+                debug,
+                json,
+                hardware_wallet,
+                data_dir,
+                cmd: Command::ImportSeed {
+                    bitcoin_electrum_rpc_urls,
+                    bitcoin_target_block,
+                },
+            }
+        }
+        RawCommand::Config {
+            cmd: RawConfigCommand::Init { force },
+        } => Arguments {
+            env_config: env_config_from(is_testnet),
+            debug,
+            json,
+            hardware_wallet,
+            data_dir,
+            cmd: Command::InitConfig { force },
+        },
     };
 
     Ok(ParseResult::Arguments(arguments))
@@ -196,38 +466,109 @@ where
 pub enum Command {
     BuyXmr {
         seller: Multiaddr,
-        bitcoin_electrum_rpc_url: Url,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
+        bitcoin_target_block: usize,
+        bitcoin_change_address: bitcoin::Address,
+        monero_receive_address: monero::Address,
+        monero_amount: Option<monero::Amount>,
+        monero_daemon_addresses: Vec<String>,
+        coin_control: CoinControl,
+        tor_socks5_port: u16,
+        /// The costs of the swap are confirmed interactively if not given.
+        max_effective_price: Option<bitcoin::Amount>,
+    },
+    BuyXmrSplit {
+        rendezvous_point: Multiaddr,
+        namespace: XmrBtcNamespace,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
         bitcoin_target_block: usize,
         bitcoin_change_address: bitcoin::Address,
         monero_receive_address: monero::Address,
-        monero_daemon_address: String,
+        monero_amount: monero::Amount,
+        monero_daemon_addresses: Vec<String>,
+        coin_control: CoinControl,
         tor_socks5_port: u16,
     },
-    History,
+    History {
+        swap_id: Option<Uuid>,
+    },
+    Balance {
+        bitcoin_electrum_rpc_urls: Vec<Url>,
+        bitcoin_target_block: usize,
+    },
     Resume {
         swap_id: Uuid,
-        bitcoin_electrum_rpc_url: Url,
+        monitor_only: bool,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
         bitcoin_target_block: usize,
-        monero_daemon_address: String,
+        monero_daemon_addresses: Vec<String>,
+        coin_control: CoinControl,
         tor_socks5_port: u16,
     },
     Cancel {
         swap_id: Uuid,
         force: bool,
-        bitcoin_electrum_rpc_url: Url,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
         bitcoin_target_block: usize,
     },
     Refund {
         swap_id: Uuid,
         force: bool,
-        bitcoin_electrum_rpc_url: Url,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
+        bitcoin_target_block: usize,
+    },
+    Status {
+        swap_id: Uuid,
+        verbose: bool,
+        bitcoin_electrum_rpc_urls: Vec<Url>,
         bitcoin_target_block: usize,
     },
     ListSellers {
         rendezvous_point: Multiaddr,
         namespace: XmrBtcNamespace,
+        require_reserve_proof: bool,
+        filter: Filter,
+        sort_by: SortBy,
+        monero_daemon_addresses: Vec<String>,
         tor_socks5_port: u16,
     },
+    Export {
+        swap_id: Uuid,
+        output: PathBuf,
+    },
+    Import {
+        input: PathBuf,
+    },
+    EmergencyKit {
+        swap_id: Uuid,
+        output: Option<PathBuf>,
+    },
+    Receipt {
+        swap_id: Uuid,
+        output: Option<PathBuf>,
+    },
+    ExportViewKey {
+        swap_id: Uuid,
+        output: Option<PathBuf>,
+    },
+    VerifyViewKey {
+        input: PathBuf,
+        monero_daemon_addresses: Vec<String>,
+    },
+    MigrateDb {
+        dry_run: bool,
+        rollback: bool,
+    },
+    PeerId,
+    RotateIdentity,
+    ExportSeed,
+    ImportSeed {
+        bitcoin_electrum_rpc_urls: Vec<Url>,
+        bitcoin_target_block: usize,
+    },
+    InitConfig {
+        force: bool,
+    },
 }
 
 #[derive(structopt::StructOpt, Debug)]
@@ -262,6 +603,19 @@ struct RawArguments {
     )]
     json: bool,
 
+    #[structopt(
+        long = "hardware-wallet",
+        help = "Fund swaps from the hardware wallet with this fingerprint instead of the internal Bitcoin wallet"
+    )]
+    hardware_wallet: Option<Fingerprint>,
+
+    #[structopt(
+        long = "hwi-path",
+        help = "The HWI executable used to access the hardware wallet",
+        default_value = "hwi"
+    )]
+    hwi_path: PathBuf,
+
     #[structopt(subcommand)]
     cmd: RawCommand,
 }
@@ -273,6 +627,18 @@ enum RawCommand {
         #[structopt(flatten)]
         seller: Seller,
 
+        #[structopt(
+            long,
+            help = "Split the amount across the cheapest sellers registered at the rendezvous point and swap with all of them at once"
+        )]
+        split: bool,
+
+        #[structopt(
+            long,
+            help = "Address of the rendezvous point used to discover sellers when buying with --split"
+        )]
+        rendezvous_point: Option<Multiaddr>,
+
         #[structopt(flatten)]
         bitcoin: Bitcoin,
 
@@ -286,27 +652,72 @@ enum RawCommand {
         monero: Monero,
 
         #[structopt(long = "receive-address",
-            help = "The monero address where you would like to receive monero",
+            help = "The monero address where you would like to receive monero, subaddresses are supported",
             parse(try_from_str = parse_monero_address)
         )]
         monero_receive_address: monero::Address,
 
+        #[structopt(
+            long = "i-am-sure",
+            help = "Receive the Monero at an integrated address, which usually belongs to an exchange"
+        )]
+        i_am_sure: bool,
+
+        #[structopt(
+            long = "amount",
+            help = "The amount of monero to buy, e.g. 0.5 or \"0.5 XMR\", by default all available bitcoin is swapped",
+            parse(try_from_str = monero::Amount::from_str)
+        )]
+        monero_amount: Option<monero::Amount>,
+
+        #[structopt(
+            long = "max-effective-rate",
+            help = "Abort if a XMR would cost more than this amount of BTC including all fees, instead of asking to confirm the costs",
+            parse(try_from_str = crate::bitcoin::parse_amount)
+        )]
+        max_effective_price: Option<bitcoin::Amount>,
+
+        #[structopt(flatten)]
+        coin_control: CoinControlArgs,
+
         #[structopt(flatten)]
         tor: Tor,
     },
-    /// Show a list of past, ongoing and completed swaps
-    History,
+    /// Show a list of past, ongoing and completed swaps or the timeline of a
+    /// single swap
+    History {
+        #[structopt(
+            long = "swap-id",
+            help = "Show the timeline of the given swap instead of the list of all swaps"
+        )]
+        swap_id: Option<Uuid>,
+    },
+    /// Show the Bitcoin balance of the wallet and how much of it can be
+    /// swapped
+    Balance {
+        #[structopt(flatten)]
+        bitcoin: Bitcoin,
+    },
     /// Resume a swap
     Resume {
         #[structopt(flatten)]
         swap_id: SwapId,
 
+        #[structopt(
+            long = "monitor-only",
+            help = "Only report the transactions of the swap as they appear, without broadcasting anything or contacting the seller"
+        )]
+        monitor_only: bool,
+
         #[structopt(flatten)]
         bitcoin: Bitcoin,
 
         #[structopt(flatten)]
         monero: Monero,
 
+        #[structopt(flatten)]
+        coin_control: CoinControlArgs,
+
         #[structopt(flatten)]
         tor: Tor,
     },
@@ -321,6 +732,21 @@ enum RawCommand {
         #[structopt(flatten)]
         bitcoin: Bitcoin,
     },
+    /// Show the state of a swap, the states it can move on to and what can be
+    /// done about it
+    Status {
+        #[structopt(flatten)]
+        swap_id: SwapId,
+
+        #[structopt(
+            long,
+            help = "Also show the status of the timelocks, this requires a connection to the Bitcoin network"
+        )]
+        verbose: bool,
+
+        #[structopt(flatten)]
+        bitcoin: Bitcoin,
+    },
     /// Try to cancel a swap and refund the BTC (expert users only)
     Refund {
         #[structopt(flatten)]
@@ -340,74 +766,285 @@ enum RawCommand {
         )]
         rendezvous_point: Multiaddr,
 
-        #[structopt(flatten)]
-        tor: Tor,
-    },
-}
+        #[structopt(
+            long = "require-reserve-proof",
+            help = "Only list sellers that prove to hold enough Monero for their maximum quantity. Starts a Monero wallet to verify the proofs"
+        )]
+        require_reserve_proof: bool,
 
-#[derive(structopt::StructOpt, Debug)]
-struct Monero {
-    #[structopt(
-        long = "monero-daemon-address",
-        help = "Specify to connect to a monero daemon of your choice: <host>:<port>"
-    )]
-    monero_daemon_address: Option<String>,
-}
+        #[structopt(
+            long = "min-amount",
+            help = "Only list sellers that accept swapping at least this amount of BTC",
+            parse(try_from_str = crate::bitcoin::parse_amount)
+        )]
+        min_amount: Option<bitcoin::Amount>,
 
-impl Monero {
-    fn apply_defaults(self, testnet: bool) -> String {
-        if let Some(address) = self.monero_daemon_address {
-            address
-        } else if testnet {
-            DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string()
-        } else {
-            DEFAULT_MONERO_DAEMON_ADDRESS.to_string()
-        }
-    }
-}
+        #[structopt(
+            long = "max-amount",
+            help = "Only list sellers that accept swapping at most this amount of BTC",
+            parse(try_from_str = crate::bitcoin::parse_amount)
+        )]
+        max_amount: Option<bitcoin::Amount>,
 
-#[derive(structopt::StructOpt, Debug)]
-struct Bitcoin {
-    #[structopt(long = "electrum-rpc", help = "Provide the Bitcoin Electrum RPC URL")]
-    bitcoin_electrum_rpc_url: Option<Url>,
+        #[structopt(
+            long = "amount",
+            help = "Only list sellers that accept swapping this amount of BTC, the effective prices are calculated for it",
+            parse(try_from_str = crate::bitcoin::parse_amount)
+        )]
+        amount: Option<bitcoin::Amount>,
 
-    #[structopt(
-        long = "bitcoin-target-block",
-        help = "Estimate Bitcoin fees such that transactions are confirmed within the specified number of blocks"
-    )]
-    bitcoin_target_block: Option<usize>,
-}
+        #[structopt(
+            long = "tor-only",
+            help = "Only list sellers that are reachable through an onion address"
+        )]
+        tor_only: bool,
 
-impl Bitcoin {
-    fn apply_defaults(self, testnet: bool) -> Result<(Url, usize)> {
-        let bitcoin_electrum_rpc_url = if let Some(url) = self.bitcoin_electrum_rpc_url {
-            url
-        } else if testnet {
-            Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)?
-        } else {
-            Url::from_str(DEFAULT_ELECTRUM_RPC_URL)?
-        };
+        #[structopt(
+            long = "sort-by",
+            help = "Sort sellers by the asking price or by the price per XMR received after fees, the latter requires --amount or --min-amount",
+            default_value = "price",
+            possible_values = &["price", "effective-price"]
+        )]
+        sort_by: SortBy,
 
-        let bitcoin_target_block = if let Some(target_block) = self.bitcoin_target_block {
-            target_block
-        } else if testnet {
-            DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET
-        } else {
-            DEFAULT_BITCOIN_CONFIRMATION_TARGET
-        };
+        #[structopt(flatten)]
+        monero: Monero,
 
-        Ok((bitcoin_electrum_rpc_url, bitcoin_target_block))
-    }
-}
+        #[structopt(flatten)]
+        tor: Tor,
+    },
+    /// Export a swap into an encrypted file so it can be resumed on another
+    /// machine using the same seed
+    Export {
+        #[structopt(flatten)]
+        swap_id: SwapId,
 
-#[derive(structopt::StructOpt, Debug)]
-struct Tor {
-    #[structopt(
-        long = "tor-socks5-port",
-        help = "Your local Tor socks5 proxy port",
-        default_value = DEFAULT_TOR_SOCKS5_PORT
-    )]
-    tor_socks5_port: u16,
+        #[structopt(long, help = "The file the encrypted swap export should be written to")]
+        output: PathBuf,
+    },
+    /// Import a swap previously exported with the export subcommand
+    Import {
+        #[structopt(long, help = "The swap export file to import")]
+        input: PathBuf,
+    },
+    /// Write the pre-signed cancel and refund transactions of a swap, so its
+    /// Bitcoin can be refunded without the CLI
+    EmergencyKit {
+        #[structopt(flatten)]
+        swap_id: SwapId,
+
+        #[structopt(
+            long,
+            help = "The file the emergency kit should be written to, defaults to the emergency-kits directory in the data directory"
+        )]
+        output: Option<PathBuf>,
+    },
+    /// Export the receipt of a completed swap signed by both parties as JSON
+    Receipt {
+        #[structopt(flatten)]
+        swap_id: SwapId,
+
+        #[structopt(
+            long,
+            help = "The file the receipt should be written to, it is printed if omitted"
+        )]
+        output: Option<PathBuf>,
+    },
+    /// Export the view key of the Monero lock of a swap as JSON, so an auditor
+    /// can confirm the lock without being able to spend the Monero
+    ExportViewKey {
+        #[structopt(flatten)]
+        swap_id: SwapId,
+
+        #[structopt(
+            long,
+            help = "The file the view key should be written to, it is printed if omitted"
+        )]
+        output: Option<PathBuf>,
+    },
+    /// Confirm the Monero lock of a swap with a view key exported by
+    /// export-view-key
+    VerifyViewKey {
+        #[structopt(long, help = "The view key export to verify")]
+        input: PathBuf,
+
+        #[structopt(flatten)]
+        monero: Monero,
+    },
+    /// Migrate the database to the schema of this version, the database is
+    /// backed up before
+    MigrateDb {
+        #[structopt(
+            long = "dry-run",
+            help = "Print the migrations that would be applied without changing the database"
+        )]
+        dry_run: bool,
+
+        #[structopt(
+            long,
+            help = "Restore the backup taken before the last migration",
+            conflicts_with = "dry_run"
+        )]
+        rollback: bool,
+    },
+    /// Back up or restore the seed of the Bitcoin wallet and the network
+    /// identity
+    Wallet {
+        #[structopt(subcommand)]
+        cmd: RawWalletCommand,
+    },
+    /// Print or rotate the network identity, sellers see the peer id of the
+    /// identity
+    Identity {
+        #[structopt(subcommand)]
+        cmd: RawIdentityCommand,
+    },
+    /// Manage the config file in the data directory, whose settings apply
+    /// unless given on the command line
+    Config {
+        #[structopt(subcommand)]
+        cmd: RawConfigCommand,
+    },
+}
+
+#[derive(structopt::StructOpt, Debug)]
+enum RawIdentityCommand {
+    /// Print the peer id
+    PeerId,
+    /// Replace the network identity with a random one, refuses to run while
+    /// swaps are unfinished
+    Rotate,
+}
+
+#[derive(structopt::StructOpt, Debug)]
+enum RawConfigCommand {
+    /// Ask for the settings and write a commented config file
+    Init {
+        #[structopt(long, help = "Overwrite an existing config file")]
+        force: bool,
+    },
+}
+
+#[derive(structopt::StructOpt, Debug)]
+enum RawWalletCommand {
+    /// Print the seed so it can be backed up
+    ExportSeed,
+    /// Restore a seed printed by export-seed, the seed is read from stdin
+    ImportSeed {
+        #[structopt(flatten)]
+        bitcoin: Bitcoin,
+    },
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct Monero {
+    #[structopt(
+        long = "monero-daemon-address",
+        help = "Specify to connect to a monero daemon of your choice: <host>:<port>. Can be given multiple times to fail over to another daemon if the selected one stops responding or falls behind"
+    )]
+    monero_daemon_addresses: Vec<String>,
+}
+
+impl Monero {
+    fn apply_defaults(self, testnet: bool, config: &config::Monero) -> Vec<String> {
+        if !self.monero_daemon_addresses.is_empty() {
+            self.monero_daemon_addresses
+        } else if !config.daemon_addresses.is_empty() {
+            config.daemon_addresses.clone()
+        } else if testnet {
+            vec![DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string()]
+        } else {
+            vec![DEFAULT_MONERO_DAEMON_ADDRESS.to_string()]
+        }
+    }
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct Bitcoin {
+    #[structopt(
+        long = "electrum-rpc",
+        help = "Provide the Bitcoin Electrum RPC URL. Can be given multiple times to fail over to another server if the connection breaks or the server stops seeing new blocks"
+    )]
+    bitcoin_electrum_rpc_urls: Vec<Url>,
+
+    #[structopt(
+        long = "bitcoin-target-block",
+        help = "Estimate Bitcoin fees such that transactions are confirmed within the specified number of blocks"
+    )]
+    bitcoin_target_block: Option<usize>,
+}
+
+impl Bitcoin {
+    fn apply_defaults(self, testnet: bool, config: &config::Bitcoin) -> Result<(Vec<Url>, usize)> {
+        let bitcoin_electrum_rpc_urls = if !self.bitcoin_electrum_rpc_urls.is_empty() {
+            self.bitcoin_electrum_rpc_urls
+        } else if !config.electrum_rpc_url.is_empty() {
+            config.electrum_rpc_url.clone()
+        } else if testnet {
+            vec![Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)?]
+        } else {
+            vec![Url::from_str(DEFAULT_ELECTRUM_RPC_URL)?]
+        };
+
+        let bitcoin_target_block =
+            if let Some(target_block) = self.bitcoin_target_block.or(config.target_block) {
+                target_block
+            } else if testnet {
+                DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET
+            } else {
+                DEFAULT_BITCOIN_CONFIRMATION_TARGET
+            };
+
+        Ok((bitcoin_electrum_rpc_urls, bitcoin_target_block))
+    }
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct CoinControlArgs {
+    #[structopt(
+        long = "avoid-reused-addresses",
+        help = "Do not fund the Bitcoin lock transaction from addresses that received funds more than once"
+    )]
+    avoid_reused_addresses: bool,
+
+    #[structopt(
+        long = "confirmed-only",
+        help = "Only fund the Bitcoin lock transaction from confirmed outputs"
+    )]
+    confirmed_only: bool,
+
+    #[structopt(
+        long = "utxo",
+        help = "Only fund the Bitcoin lock transaction from this output, given as <txid>:<vout>. Can be given multiple times"
+    )]
+    utxos: Vec<OutPoint>,
+}
+
+impl CoinControlArgs {
+    fn into_policy(self) -> CoinControl {
+        CoinControl {
+            avoid_reused_addresses: self.avoid_reused_addresses,
+            confirmed_only: self.confirmed_only,
+            utxos: self.utxos,
+        }
+    }
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct Tor {
+    #[structopt(
+        long = "tor-socks5-port",
+        help = "Your local Tor socks5 proxy port [default: 9050]"
+    )]
+    tor_socks5_port: Option<u16>,
+}
+
+impl Tor {
+    fn apply_defaults(self, config: &config::Tor) -> u16 {
+        self.tor_socks5_port
+            .or(config.socks5_port)
+            .unwrap_or(DEFAULT_SOCKS5_PORT)
+    }
 }
 
 #[derive(structopt::StructOpt, Debug)]
@@ -425,7 +1062,7 @@ struct Seller {
         long,
         help = "The seller's address. Must include a peer ID part, i.e. `/p2p/`"
     )]
-    seller: Multiaddr,
+    seller: Option<Multiaddr>,
 }
 
 mod data {
@@ -447,14 +1084,6 @@ mod data {
     }
 }
 
-fn rendezvous_namespace_from(is_testnet: bool) -> XmrBtcNamespace {
-    if is_testnet {
-        XmrBtcNamespace::Testnet
-    } else {
-        XmrBtcNamespace::Mainnet
-    }
-}
-
 fn env_config_from(testnet: bool) -> env::Config {
     if testnet {
         env::Testnet::get_config()
@@ -465,31 +1094,29 @@ fn env_config_from(testnet: bool) -> env::Config {
 
 fn validate_monero_address(
     address: monero::Address,
-    testnet: bool,
-) -> Result<monero::Address, MoneroAddressNetworkMismatch> {
-    let expected_network = if testnet {
-        monero::Network::Stagenet
-    } else {
-        monero::Network::Mainnet
-    };
-
+    expected_network: monero::Network,
+    allow_integrated: bool,
+) -> Result<monero::Address> {
     if address.network != expected_network {
-        return Err(MoneroAddressNetworkMismatch {
+        bail!(MoneroAddressNetworkMismatch {
             expected: expected_network,
             actual: address.network,
         });
     }
+    if monero::is_burn_address(&address) {
+        bail!(monero::BurnAddress(address))
+    }
+    if monero::is_integrated_address(&address) && !allow_integrated {
+        bail!(IntegratedReceiveAddress(address))
+    }
 
     Ok(address)
 }
 
-fn validate_bitcoin_address(address: bitcoin::Address, testnet: bool) -> Result<bitcoin::Address> {
-    let expected_network = if testnet {
-        bitcoin::Network::Testnet
-    } else {
-        bitcoin::Network::Bitcoin
-    };
-
+fn validate_bitcoin_address(
+    address: bitcoin::Address,
+    expected_network: bitcoin::Network,
+) -> Result<bitcoin::Address> {
     if address.network != expected_network {
         anyhow::bail!(
             "Invalid Bitcoin address provided; expected network {} but provided address is for {}",
@@ -498,206 +1125,911 @@ fn validate_bitcoin_address(address: bitcoin::Address, testnet: bool) -> Result<
         );
     }
 
-    if address.address_type() != Some(AddressType::P2wpkh) {
-        anyhow::bail!("Invalid Bitcoin address provided, only bech32 format is supported!")
-    }
+    if address.address_type() != Some(AddressType::P2wpkh) {
+        anyhow::bail!("Invalid Bitcoin address provided, only bech32 format is supported!")
+    }
+
+    if crate::bitcoin::is_burn_address(&address) {
+        bail!(crate::bitcoin::BurnAddress(address))
+    }
+
+    Ok(address)
+}
+
+fn parse_monero_address(s: &str) -> Result<monero::Address> {
+    monero::Address::from_str(s).with_context(|| {
+        format!(
+            "Failed to parse {} as a monero address, please make sure it is a valid address",
+            s
+        )
+    })
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Invalid monero address provided, expected address on network {expected:?} but address provided is on {actual:?}")]
+pub struct MoneroAddressNetworkMismatch {
+    expected: monero::Network,
+    actual: monero::Network,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("{0} is an integrated address, these usually belong to an exchange. An exchange may not credit a deposit that arrives late or is split into several transactions, pass --i-am-sure to receive the Monero at this address anyway")]
+pub struct IntegratedReceiveAddress(monero::Address);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tor::DEFAULT_SOCKS5_PORT;
+
+    const BINARY_NAME: &str = "swap";
+
+    const TESTNET: &str = "testnet";
+    const MAINNET: &str = "mainnet";
+
+    const MONERO_STAGENET_ADDRESS: &str = "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a";
+    const BITCOIN_TESTNET_ADDRESS: &str = "tb1qr3em6k3gfnyl8r7q0v7t4tlnyxzgxma3lressv";
+    const MONERO_MAINNET_ADDRESS: &str = "44Ato7HveWidJYUAVw5QffEcEtSH1DwzSP3FPPkHxNAS4LX9CqgucphTisH978FLHE34YNEx7FcbBfQLQUU8m3NUC4VqsRa";
+    const BITCOIN_MAINNET_ADDRESS: &str = "bc1qe4epnfklcaa0mun26yz5g8k24em5u9f92hy325";
+    const MULTI_ADDRESS: &str =
+        "/ip4/127.0.0.1/tcp/9939/p2p/12D3KooWCdMKjesXMJz1SiZ7HgotrxuqhQJbP5sgBm2BwP1cqThi";
+    const SWAP_ID: &str = "ea030832-3be9-454f-bb98-5ea9a788406b";
+
+    fn no_config(_: &Path) -> Result<Config> {
+        Ok(Config::default())
+    }
+
+    #[test]
+    fn given_buy_xmr_on_mainnet_then_defaults_to_mainnet() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+        ];
+
+        let expected_args = ParseResult::Arguments(Arguments::buy_xmr_mainnet_defaults());
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(expected_args, args);
+    }
+
+    #[test]
+    fn given_buy_xmr_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--testnet",
+            "buy-xmr",
+            "--receive-address",
+            MONERO_STAGENET_ADDRESS,
+            "--change-address",
+            BITCOIN_TESTNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::buy_xmr_testnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_buy_xmr_with_amount_then_monero_amount_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+            "--amount",
+            "0.5",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(
+                Arguments::buy_xmr_mainnet_defaults()
+                    .with_monero_amount(monero::Amount::from_piconero(500_000_000_000))
+            )
+        );
+    }
+
+    #[test]
+    fn given_buy_xmr_with_split_then_sellers_are_discovered() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--split",
+            "--rendezvous-point",
+            MULTI_ADDRESS,
+            "--amount",
+            "0.5",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::BuyXmrSplit {
+                    rendezvous_point: Multiaddr::from_str(MULTI_ADDRESS).unwrap(),
+                    namespace: XmrBtcNamespace::Mainnet,
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
+                    bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
+                    bitcoin_change_address: BITCOIN_MAINNET_ADDRESS.parse().unwrap(),
+                    monero_receive_address: monero::Address::from_str(MONERO_MAINNET_ADDRESS)
+                        .unwrap(),
+                    monero_amount: monero::Amount::from_piconero(500_000_000_000),
+                    monero_daemon_addresses: vec![DEFAULT_MONERO_DAEMON_ADDRESS.to_string()],
+                    coin_control: CoinControl::default(),
+                    tor_socks5_port: DEFAULT_SOCKS5_PORT,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn given_buy_xmr_with_split_and_no_amount_then_fails() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--split",
+            "--rendezvous-point",
+            MULTI_ADDRESS,
+        ];
+
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn given_buy_xmr_without_seller_and_split_then_fails() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+        ];
+
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn given_buy_xmr_on_mainnet_with_testnet_address_then_fails() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_STAGENET_ADDRESS,
+            "--change-address",
+            BITCOIN_TESTNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+        ];
+
+        let err = parse_args_and_apply_defaults(raw_ars, no_config).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<MoneroAddressNetworkMismatch>().unwrap(),
+            &MoneroAddressNetworkMismatch {
+                expected: monero::Network::Mainnet,
+                actual: monero::Network::Stagenet
+            }
+        );
+    }
+
+    #[test]
+    fn given_buy_xmr_on_testnet_with_mainnet_address_then_fails() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--testnet",
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+        ];
+
+        let err = parse_args_and_apply_defaults(raw_ars, no_config).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<MoneroAddressNetworkMismatch>().unwrap(),
+            &MoneroAddressNetworkMismatch {
+                expected: monero::Network::Stagenet,
+                actual: monero::Network::Mainnet
+            }
+        );
+    }
+
+    #[test]
+    fn given_buy_xmr_with_integrated_address_then_requires_i_am_sure() {
+        let standard = monero::Address::from_str(MONERO_MAINNET_ADDRESS).unwrap();
+        let integrated = monero::Address::integrated(
+            monero::Network::Mainnet,
+            standard.public_spend,
+            standard.public_view,
+            ::monero::util::address::PaymentId::zero(),
+        )
+        .to_string();
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            integrated.as_str(),
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+        ];
+
+        let err = parse_args_and_apply_defaults(raw_ars.clone(), no_config).unwrap_err();
+        assert!(err.downcast_ref::<IntegratedReceiveAddress>().is_some());
+
+        let args = parse_args_and_apply_defaults(
+            raw_ars.into_iter().chain(Some("--i-am-sure")),
+            no_config,
+        );
+        assert!(args.is_ok());
+    }
+
+    #[test]
+    fn given_buy_xmr_with_max_effective_rate_then_no_confirmation_required() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "buy-xmr",
+            "--receive-address",
+            MONERO_MAINNET_ADDRESS,
+            "--change-address",
+            BITCOIN_MAINNET_ADDRESS,
+            "--seller",
+            MULTI_ADDRESS,
+            "--max-effective-rate",
+            "0.0075",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        let mut expected = Arguments::buy_xmr_mainnet_defaults();
+        if let Command::BuyXmr {
+            max_effective_price,
+            ..
+        } = &mut expected.cmd
+        {
+            *max_effective_price = Some(bitcoin::Amount::from_sat(750_000));
+        }
+        assert_eq!(args, ParseResult::Arguments(expected));
+    }
+
+    #[test]
+    fn given_resume_on_mainnet_then_defaults_to_mainnet() {
+        let raw_ars = vec![BINARY_NAME, "resume", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::resume_mainnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_resume_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![BINARY_NAME, "--testnet", "resume", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::resume_testnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_multiple_monero_daemons_then_all_are_kept_in_order() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "resume",
+            "--swap-id",
+            SWAP_ID,
+            "--monero-daemon-address",
+            "first.example.com:18081",
+            "--monero-daemon-address",
+            "second.example.com:18081",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        let mut expected = Arguments::resume_mainnet_defaults();
+        if let Command::Resume {
+            monero_daemon_addresses,
+            ..
+        } = &mut expected.cmd
+        {
+            *monero_daemon_addresses = vec![
+                "first.example.com:18081".to_owned(),
+                "second.example.com:18081".to_owned(),
+            ];
+        }
+        assert_eq!(args, ParseResult::Arguments(expected));
+    }
+
+    #[test]
+    fn given_resume_with_monitor_only_then_monitor_only_is_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "resume",
+            "--swap-id",
+            SWAP_ID,
+            "--monitor-only",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        let mut expected = Arguments::resume_mainnet_defaults();
+        if let Command::Resume { monitor_only, .. } = &mut expected.cmd {
+            *monitor_only = true;
+        }
+        assert_eq!(args, ParseResult::Arguments(expected));
+    }
+
+    #[test]
+    fn given_resume_with_coin_control_then_policy_is_set() {
+        let utxo = "0000000000000000000000000000000000000000000000000000000000000000:1";
+        let raw_ars = vec![
+            BINARY_NAME,
+            "resume",
+            "--swap-id",
+            SWAP_ID,
+            "--confirmed-only",
+            "--utxo",
+            utxo,
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        let mut expected = Arguments::resume_mainnet_defaults();
+        if let Command::Resume { coin_control, .. } = &mut expected.cmd {
+            *coin_control = CoinControl {
+                avoid_reused_addresses: false,
+                confirmed_only: true,
+                utxos: vec![OutPoint::from_str(utxo).unwrap()],
+            };
+        }
+        assert_eq!(args, ParseResult::Arguments(expected));
+    }
+
+    #[test]
+    fn given_cancel_on_mainnet_then_defaults_to_mainnet() {
+        let raw_ars = vec![BINARY_NAME, "cancel", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::cancel_mainnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_status_with_verbose_then_verbose_set() {
+        let raw_ars = vec![BINARY_NAME, "status", "--swap-id", SWAP_ID, "--verbose"];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::Status {
+                    swap_id: Uuid::from_str(SWAP_ID).unwrap(),
+                    verbose: true,
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
+                    bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn given_cancel_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![BINARY_NAME, "--testnet", "cancel", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::cancel_testnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_refund_on_mainnet_then_defaults_to_mainnet() {
+        let raw_ars = vec![BINARY_NAME, "refund", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::refund_mainnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_refund_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![BINARY_NAME, "--testnet", "refund", "--swap-id", SWAP_ID];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::refund_testnet_defaults())
+        );
+    }
+
+    #[test]
+    fn given_export_then_output_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "export",
+            "--swap-id",
+            SWAP_ID,
+            "--output",
+            "/some/path/to/swap.export",
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::Export {
+                    swap_id: Uuid::from_str(SWAP_ID).unwrap(),
+                    output: PathBuf::from("/some/path/to/swap.export"),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn given_emergency_kit_without_output_then_output_none() {
+        let raw_ars = vec![BINARY_NAME, "emergency-kit", "--swap-id", SWAP_ID];
 
-    Ok(address)
-}
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
-fn parse_monero_address(s: &str) -> Result<monero::Address> {
-    monero::Address::from_str(s).with_context(|| {
-        format!(
-            "Failed to parse {} as a monero address, please make sure it is a valid address",
-            s
-        )
-    })
-}
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::EmergencyKit {
+                    swap_id: Uuid::from_str(SWAP_ID).unwrap(),
+                    output: None,
+                },
+            })
+        );
+    }
 
-#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
-#[error("Invalid monero address provided, expected address on network {expected:?} but address provided is on {actual:?}")]
-pub struct MoneroAddressNetworkMismatch {
-    expected: monero::Network,
-    actual: monero::Network,
-}
+    #[test]
+    fn given_receipt_with_output_then_output_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "receipt",
+            "--swap-id",
+            SWAP_ID,
+            "--output",
+            "/some/path/to/receipt.json",
+        ];
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::tor::DEFAULT_SOCKS5_PORT;
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
-    const BINARY_NAME: &str = "swap";
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::Receipt {
+                    swap_id: Uuid::from_str(SWAP_ID).unwrap(),
+                    output: Some(PathBuf::from("/some/path/to/receipt.json")),
+                },
+            })
+        );
+    }
 
-    const TESTNET: &str = "testnet";
-    const MAINNET: &str = "mainnet";
+    #[test]
+    fn given_export_view_key_then_swap_id_set() {
+        let raw_ars = vec![BINARY_NAME, "export-view-key", "--swap-id", SWAP_ID];
 
-    const MONERO_STAGENET_ADDRESS: &str = "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a";
-    const BITCOIN_TESTNET_ADDRESS: &str = "tb1qr3em6k3gfnyl8r7q0v7t4tlnyxzgxma3lressv";
-    const MONERO_MAINNET_ADDRESS: &str = "44Ato7HveWidJYUAVw5QffEcEtSH1DwzSP3FPPkHxNAS4LX9CqgucphTisH978FLHE34YNEx7FcbBfQLQUU8m3NUC4VqsRa";
-    const BITCOIN_MAINNET_ADDRESS: &str = "bc1qe4epnfklcaa0mun26yz5g8k24em5u9f92hy325";
-    const MULTI_ADDRESS: &str =
-        "/ip4/127.0.0.1/tcp/9939/p2p/12D3KooWCdMKjesXMJz1SiZ7HgotrxuqhQJbP5sgBm2BwP1cqThi";
-    const SWAP_ID: &str = "ea030832-3be9-454f-bb98-5ea9a788406b";
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::ExportViewKey {
+                    swap_id: Uuid::from_str(SWAP_ID).unwrap(),
+                    output: None,
+                },
+            })
+        );
+    }
 
     #[test]
-    fn given_buy_xmr_on_mainnet_then_defaults_to_mainnet() {
+    fn given_verify_view_key_on_testnet_then_defaults_to_stagenet_daemon() {
         let raw_ars = vec![
             BINARY_NAME,
-            "buy-xmr",
-            "--receive-address",
-            MONERO_MAINNET_ADDRESS,
-            "--change-address",
-            BITCOIN_MAINNET_ADDRESS,
-            "--seller",
-            MULTI_ADDRESS,
+            "--testnet",
+            "verify-view-key",
+            "--input",
+            "/some/path/to/view-key.json",
         ];
 
-        let expected_args = ParseResult::Arguments(Arguments::buy_xmr_mainnet_defaults());
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
-        assert_eq!(expected_args, args);
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Testnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(TESTNET),
+                cmd: Command::VerifyViewKey {
+                    input: PathBuf::from("/some/path/to/view-key.json"),
+                    monero_daemon_addresses: vec![
+                        DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string()
+                    ],
+                },
+            })
+        );
     }
 
     #[test]
-    fn given_buy_xmr_on_testnet_then_defaults_to_testnet() {
-        let raw_ars = vec![
-            BINARY_NAME,
-            "--testnet",
-            "buy-xmr",
-            "--receive-address",
-            MONERO_STAGENET_ADDRESS,
-            "--change-address",
-            BITCOIN_TESTNET_ADDRESS,
-            "--seller",
-            MULTI_ADDRESS,
-        ];
+    fn given_history_with_swap_id_then_swap_id_set() {
+        let raw_ars = vec![BINARY_NAME, "history", "--swap-id", SWAP_ID];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::buy_xmr_testnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::History {
+                    swap_id: Some(Uuid::from_str(SWAP_ID).unwrap()),
+                },
+            })
         );
     }
 
     #[test]
-    fn given_buy_xmr_on_mainnet_with_testnet_address_then_fails() {
-        let raw_ars = vec![
-            BINARY_NAME,
-            "buy-xmr",
-            "--receive-address",
-            MONERO_STAGENET_ADDRESS,
-            "--change-address",
-            BITCOIN_TESTNET_ADDRESS,
-            "--seller",
-            MULTI_ADDRESS,
-        ];
+    fn given_balance_then_bitcoin_defaults_applied() {
+        let raw_ars = vec![BINARY_NAME, "balance"];
 
-        let err = parse_args_and_apply_defaults(raw_ars).unwrap_err();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
-            err.downcast_ref::<MoneroAddressNetworkMismatch>().unwrap(),
-            &MoneroAddressNetworkMismatch {
-                expected: monero::Network::Mainnet,
-                actual: monero::Network::Stagenet
-            }
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::Balance {
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
+                    bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
+                },
+            })
         );
     }
 
     #[test]
-    fn given_buy_xmr_on_testnet_with_mainnet_address_then_fails() {
+    fn given_config_file_then_it_applies_unless_overridden_by_flags() {
+        let config_file = |_: &Path| -> Result<Config> {
+            Ok(Config {
+                bitcoin: config::Bitcoin {
+                    electrum_rpc_url: vec![Url::from_str("tcp://localhost:50001").unwrap()],
+                    target_block: Some(6),
+                },
+                tor: config::Tor {
+                    socks5_port: Some(9150),
+                },
+                ..Config::default()
+            })
+        };
+
         let raw_ars = vec![
             BINARY_NAME,
-            "--testnet",
-            "buy-xmr",
-            "--receive-address",
-            MONERO_MAINNET_ADDRESS,
-            "--change-address",
-            BITCOIN_MAINNET_ADDRESS,
-            "--seller",
+            "list-sellers",
+            "--rendezvous-point",
             MULTI_ADDRESS,
+            "--tor-socks5-port",
+            "9050",
         ];
+        let args = parse_args_and_apply_defaults(raw_ars, config_file).unwrap();
+
+        match args {
+            ParseResult::Arguments(Arguments {
+                cmd:
+                    Command::ListSellers {
+                        tor_socks5_port, ..
+                    },
+                ..
+            }) => assert_eq!(tor_socks5_port, 9050),
+            _ => panic!("Expected list-sellers"),
+        }
 
-        let err = parse_args_and_apply_defaults(raw_ars).unwrap_err();
+        let raw_ars = vec![BINARY_NAME, "balance", "--bitcoin-target-block", "2"];
+        let args = parse_args_and_apply_defaults(raw_ars, config_file).unwrap();
 
         assert_eq!(
-            err.downcast_ref::<MoneroAddressNetworkMismatch>().unwrap(),
-            &MoneroAddressNetworkMismatch {
-                expected: monero::Network::Stagenet,
-                actual: monero::Network::Mainnet
-            }
+            args,
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::Balance {
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str("tcp://localhost:50001").unwrap()],
+                    bitcoin_target_block: 2,
+                },
+            })
         );
     }
 
     #[test]
-    fn given_resume_on_mainnet_then_defaults_to_mainnet() {
-        let raw_ars = vec![BINARY_NAME, "resume", "--swap-id", SWAP_ID];
+    fn given_config_init_then_config_file_not_read() {
+        let broken_config_file = |_: &Path| -> Result<Config> { bail!("Invalid config file") };
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let raw_ars = vec![BINARY_NAME, "config", "init"];
+        let args = parse_args_and_apply_defaults(raw_ars, broken_config_file).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::resume_mainnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::InitConfig { force: false },
+            })
         );
     }
 
     #[test]
-    fn given_resume_on_testnet_then_defaults_to_testnet() {
-        let raw_ars = vec![BINARY_NAME, "--testnet", "resume", "--swap-id", SWAP_ID];
+    fn given_import_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--testnet",
+            "import",
+            "--input",
+            "/some/path/to/swap.export",
+        ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::resume_testnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Testnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(TESTNET),
+                cmd: Command::Import {
+                    input: PathBuf::from("/some/path/to/swap.export"),
+                },
+            })
         );
     }
 
     #[test]
-    fn given_cancel_on_mainnet_then_defaults_to_mainnet() {
-        let raw_ars = vec![BINARY_NAME, "cancel", "--swap-id", SWAP_ID];
+    fn given_wallet_export_seed_then_export_seed_command() {
+        let raw_ars = vec![BINARY_NAME, "wallet", "export-seed"];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::cancel_mainnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::ExportSeed,
+            })
         );
     }
 
     #[test]
-    fn given_cancel_on_testnet_then_defaults_to_testnet() {
-        let raw_ars = vec![BINARY_NAME, "--testnet", "cancel", "--swap-id", SWAP_ID];
+    fn given_migrate_db_with_dry_run_then_dry_run_set() {
+        let raw_ars = vec![BINARY_NAME, "migrate-db", "--dry-run"];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::cancel_testnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::MigrateDb {
+                    dry_run: true,
+                    rollback: false
+                },
+            })
         );
+
+        let raw_ars = vec![BINARY_NAME, "migrate-db", "--dry-run", "--rollback"];
+        assert!(parse_args_and_apply_defaults(raw_ars, no_config).is_err());
     }
 
     #[test]
-    fn given_refund_on_mainnet_then_defaults_to_mainnet() {
-        let raw_ars = vec![BINARY_NAME, "refund", "--swap-id", SWAP_ID];
+    fn given_list_sellers_with_filters_then_filter_and_sorting_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "list-sellers",
+            "--rendezvous-point",
+            MULTI_ADDRESS,
+            "--min-amount",
+            "0.01",
+            "--max-amount",
+            "0.5",
+            "--tor-only",
+            "--sort-by",
+            "effective-price",
+        ];
+
+        match parse_args_and_apply_defaults(raw_ars, no_config).unwrap() {
+            ParseResult::Arguments(Arguments {
+                cmd:
+                    Command::ListSellers {
+                        filter, sort_by, ..
+                    },
+                ..
+            }) => {
+                assert_eq!(filter, Filter {
+                    min_amount: Some(bitcoin::Amount::from_sat(1_000_000)),
+                    max_amount: Some(bitcoin::Amount::from_sat(50_000_000)),
+                    amount: None,
+                    tor_only: true,
+                });
+                assert_eq!(sort_by, SortBy::EffectivePrice);
+            }
+            result => panic!("unexpected parse result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn given_list_sellers_sorted_by_effective_price_without_amount_then_error() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "list-sellers",
+            "--rendezvous-point",
+            MULTI_ADDRESS,
+            "--sort-by",
+            "effective-price",
+        ];
+        assert!(parse_args_and_apply_defaults(raw_ars, no_config).is_err());
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let raw_ars = vec![
+            BINARY_NAME,
+            "list-sellers",
+            "--rendezvous-point",
+            MULTI_ADDRESS,
+            "--amount",
+            "0.01",
+            "--sort-by",
+            "effective-price",
+        ];
+        assert!(parse_args_and_apply_defaults(raw_ars, no_config).is_ok());
+    }
+
+    #[test]
+    fn given_identity_rotate_then_rotate_identity_command() {
+        let raw_ars = vec![BINARY_NAME, "identity", "rotate"];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::refund_mainnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Mainnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(MAINNET),
+                cmd: Command::RotateIdentity,
+            })
         );
     }
 
     #[test]
-    fn given_refund_on_testnet_then_defaults_to_testnet() {
-        let raw_ars = vec![BINARY_NAME, "--testnet", "refund", "--swap-id", SWAP_ID];
+    fn given_wallet_import_seed_on_testnet_then_defaults_to_testnet() {
+        let raw_ars = vec![BINARY_NAME, "--testnet", "wallet", "import-seed"];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
-            ParseResult::Arguments(Arguments::refund_testnet_defaults())
+            ParseResult::Arguments(Arguments {
+                env_config: env::Testnet::get_config(),
+                debug: false,
+                json: false,
+                hardware_wallet: None,
+                data_dir: data_dir_path_cli().join(TESTNET),
+                cmd: Command::ImportSeed {
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str(
+                        DEFAULT_ELECTRUM_RPC_URL_TESTNET
+                    )
+                    .unwrap()],
+                    bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
+                },
+            })
         );
     }
 
@@ -718,7 +2050,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
@@ -742,7 +2074,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
@@ -761,7 +2093,7 @@ mod tests {
             SWAP_ID,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
@@ -781,7 +2113,7 @@ mod tests {
             SWAP_ID,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
 
         assert_eq!(
             args,
@@ -806,7 +2138,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::buy_xmr_mainnet_defaults().with_debug())
@@ -825,7 +2157,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::buy_xmr_testnet_defaults().with_debug())
@@ -833,7 +2165,7 @@ mod tests {
 
         let raw_ars = vec![BINARY_NAME, "--debug", "resume", "--swap-id", SWAP_ID];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::resume_mainnet_defaults().with_debug())
@@ -848,7 +2180,7 @@ mod tests {
             SWAP_ID,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::resume_testnet_defaults().with_debug())
@@ -869,7 +2201,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::buy_xmr_mainnet_defaults().with_json())
@@ -888,7 +2220,7 @@ mod tests {
             MULTI_ADDRESS,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::buy_xmr_testnet_defaults().with_json())
@@ -896,7 +2228,7 @@ mod tests {
 
         let raw_ars = vec![BINARY_NAME, "--json", "resume", "--swap-id", SWAP_ID];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::resume_mainnet_defaults().with_json())
@@ -911,13 +2243,39 @@ mod tests {
             SWAP_ID,
         ];
 
-        let args = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert_eq!(
             args,
             ParseResult::Arguments(Arguments::resume_testnet_defaults().with_json())
         );
     }
 
+    #[test]
+    fn given_hardware_wallet_then_hwi_set() {
+        let raw_ars = vec![
+            BINARY_NAME,
+            "--testnet",
+            "--hardware-wallet",
+            "d34db33f",
+            "resume",
+            "--swap-id",
+            SWAP_ID,
+        ];
+
+        let args = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
+
+        assert_eq!(
+            args,
+            ParseResult::Arguments(Arguments::resume_testnet_defaults().with_hardware_wallet(
+                crate::bitcoin::Hwi::new(
+                    PathBuf::from("hwi"),
+                    Fingerprint::from_str("d34db33f").unwrap(),
+                    bitcoin::Network::Testnet
+                )
+            ))
+        );
+    }
+
     #[test]
     fn only_bech32_addresses_mainnet_are_allowed() {
         let raw_ars = vec![
@@ -930,7 +2288,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars);
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
         assert_eq!(
             result.unwrap_err().to_string(),
             "Invalid Bitcoin address provided, only bech32 format is supported!"
@@ -946,7 +2304,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars);
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
         assert_eq!(
             result.unwrap_err().to_string(),
             "Invalid Bitcoin address provided, only bech32 format is supported!"
@@ -962,7 +2320,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let result = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert!(matches!(result, ParseResult::Arguments(_)));
     }
 
@@ -979,7 +2337,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars);
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
         assert_eq!(
             result.unwrap_err().to_string(),
             "Invalid Bitcoin address provided, only bech32 format is supported!"
@@ -996,7 +2354,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars);
+        let result = parse_args_and_apply_defaults(raw_ars, no_config);
         assert_eq!(
             result.unwrap_err().to_string(),
             "Invalid Bitcoin address provided, only bech32 format is supported!"
@@ -1013,7 +2371,7 @@ mod tests {
             "--seller",
             MULTI_ADDRESS,
         ];
-        let result = parse_args_and_apply_defaults(raw_ars).unwrap();
+        let result = parse_args_and_apply_defaults(raw_ars, no_config).unwrap();
         assert!(matches!(result, ParseResult::Arguments(_)));
     }
 
@@ -1023,17 +2381,25 @@ mod tests {
                 env_config: env::Testnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(TESTNET),
                 cmd: Command::BuyXmr {
                     seller: Multiaddr::from_str(MULTI_ADDRESS).unwrap(),
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)
-                        .unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str(
+                        DEFAULT_ELECTRUM_RPC_URL_TESTNET,
+                    )
+                    .unwrap()],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
                     bitcoin_change_address: BITCOIN_TESTNET_ADDRESS.parse().unwrap(),
                     monero_receive_address: monero::Address::from_str(MONERO_STAGENET_ADDRESS)
                         .unwrap(),
-                    monero_daemon_address: DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string(),
+                    monero_amount: None,
+                    monero_daemon_addresses: vec![
+                        DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string()
+                    ],
+                    coin_control: CoinControl::default(),
                     tor_socks5_port: DEFAULT_SOCKS5_PORT,
+                    max_effective_price: None,
                 },
             }
         }
@@ -1043,16 +2409,22 @@ mod tests {
                 env_config: env::Mainnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(MAINNET),
                 cmd: Command::BuyXmr {
                     seller: Multiaddr::from_str(MULTI_ADDRESS).unwrap(),
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
                     bitcoin_change_address: BITCOIN_MAINNET_ADDRESS.parse().unwrap(),
                     monero_receive_address: monero::Address::from_str(MONERO_MAINNET_ADDRESS)
                         .unwrap(),
-                    monero_daemon_address: DEFAULT_MONERO_DAEMON_ADDRESS.to_string(),
+                    monero_amount: None,
+                    monero_daemon_addresses: vec![DEFAULT_MONERO_DAEMON_ADDRESS.to_string()],
+                    coin_control: CoinControl::default(),
                     tor_socks5_port: DEFAULT_SOCKS5_PORT,
+                    max_effective_price: None,
                 },
             }
         }
@@ -1062,13 +2434,20 @@ mod tests {
                 env_config: env::Testnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(TESTNET),
                 cmd: Command::Resume {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)
-                        .unwrap(),
+                    monitor_only: false,
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str(
+                        DEFAULT_ELECTRUM_RPC_URL_TESTNET,
+                    )
+                    .unwrap()],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
-                    monero_daemon_address: DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string(),
+                    monero_daemon_addresses: vec![
+                        DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET.to_string()
+                    ],
+                    coin_control: CoinControl::default(),
                     tor_socks5_port: DEFAULT_SOCKS5_PORT,
                 },
             }
@@ -1079,12 +2458,17 @@ mod tests {
                 env_config: env::Mainnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(MAINNET),
                 cmd: Command::Resume {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap(),
+                    monitor_only: false,
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
-                    monero_daemon_address: DEFAULT_MONERO_DAEMON_ADDRESS.to_string(),
+                    monero_daemon_addresses: vec![DEFAULT_MONERO_DAEMON_ADDRESS.to_string()],
+                    coin_control: CoinControl::default(),
                     tor_socks5_port: DEFAULT_SOCKS5_PORT,
                 },
             }
@@ -1095,12 +2479,15 @@ mod tests {
                 env_config: env::Testnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(TESTNET),
                 cmd: Command::Cancel {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
                     force: false,
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)
-                        .unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str(
+                        DEFAULT_ELECTRUM_RPC_URL_TESTNET,
+                    )
+                    .unwrap()],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
                 },
             }
@@ -1111,11 +2498,14 @@ mod tests {
                 env_config: env::Mainnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(MAINNET),
                 cmd: Command::Cancel {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
                     force: false,
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
                 },
             }
@@ -1126,12 +2516,15 @@ mod tests {
                 env_config: env::Testnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(TESTNET),
                 cmd: Command::Refund {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
                     force: false,
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL_TESTNET)
-                        .unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![Url::from_str(
+                        DEFAULT_ELECTRUM_RPC_URL_TESTNET,
+                    )
+                    .unwrap()],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
                 },
             }
@@ -1142,11 +2535,14 @@ mod tests {
                 env_config: env::Mainnet::get_config(),
                 debug: false,
                 json: false,
+                hardware_wallet: None,
                 data_dir: data_dir_path_cli().join(MAINNET),
                 cmd: Command::Refund {
                     swap_id: Uuid::from_str(SWAP_ID).unwrap(),
                     force: false,
-                    bitcoin_electrum_rpc_url: Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap(),
+                    bitcoin_electrum_rpc_urls: vec![
+                        Url::from_str(DEFAULT_ELECTRUM_RPC_URL).unwrap()
+                    ],
                     bitcoin_target_block: DEFAULT_BITCOIN_CONFIRMATION_TARGET,
                 },
             }
@@ -1166,6 +2562,18 @@ mod tests {
             self.json = true;
             self
         }
+
+        pub fn with_hardware_wallet(mut self, hwi: crate::bitcoin::Hwi) -> Self {
+            self.hardware_wallet = Some(hwi);
+            self
+        }
+
+        pub fn with_monero_amount(mut self, amount: monero::Amount) -> Self {
+            if let Command::BuyXmr { monero_amount, .. } = &mut self.cmd {
+                *monero_amount = Some(amount);
+            }
+            self
+        }
     }
 
     fn data_dir_path_cli() -> PathBuf {
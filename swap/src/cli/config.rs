@@ -0,0 +1,260 @@
+//! Optional settings of the CLI, read from `config.toml` in the data
+//! directory.
+//!
+//! Settings given on the command line take precedence over the config file.
+
+use crate::bitcoin::CoinControl;
+use crate::cli::command::{
+    DEFAULT_BITCOIN_CONFIRMATION_TARGET, DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
+    DEFAULT_ELECTRUM_RPC_URL, DEFAULT_ELECTRUM_RPC_URL_TESTNET, DEFAULT_MONERO_DAEMON_ADDRESS,
+    DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET,
+};
+use crate::config::{commented, one_or_many, Invalid, Validator};
+use crate::fs::ensure_directory_exists;
+use crate::tor::DEFAULT_SOCKS5_PORT;
+use anyhow::{Context, Result};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Input;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const FILE_NAME: &str = "config.toml";
+
+/// Comments of the config file written by `config init`.
+const COMMENTS: &[(&str, &str)] = &[
+    (
+        "bitcoin.electrum_rpc_url",
+        "One or more Electrum servers, the wallet fails over to the next one if the connection breaks.",
+    ),
+    (
+        "bitcoin.target_block",
+        "Transaction fees are estimated for a confirmation within this many blocks.",
+    ),
+    (
+        "monero.daemon_addresses",
+        "Monero nodes given as <host>:<port>, the healthiest one is used.",
+    ),
+    ("tor.socks5_port", "The port of the local Tor socks5 proxy."),
+    (
+        "coin_control",
+        "Restricts the outputs that fund the Bitcoin lock transaction.",
+    ),
+];
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub bitcoin: Bitcoin,
+    #[serde(default)]
+    pub monero: Monero,
+    #[serde(default)]
+    pub tor: Tor,
+    /// Restricts the outputs that fund the Bitcoin lock transaction, flags
+    /// given on the command line add to this policy.
+    #[serde(default)]
+    pub coin_control: CoinControl,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Bitcoin {
+    #[serde(
+        default,
+        deserialize_with = "one_or_many::deserialize",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub electrum_rpc_url: Vec<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_block: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Monero {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub daemon_addresses: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socks5_port: Option<u16>,
+}
+
+impl Config {
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(FILE_NAME)
+    }
+
+    /// Reads the config file of the data directory, all settings take their
+    /// default value if there is none.
+    pub fn read(data_dir: &Path) -> Result<Self> {
+        let path = Self::path(data_dir);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let config = toml::from_str::<Self>(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Writes the config file of the data directory with comments explaining
+    /// the settings, returning its path.
+    pub fn write(&self, data_dir: &Path) -> Result<PathBuf> {
+        self.validate()?;
+
+        let path = Self::path(data_dir);
+        ensure_directory_exists(&path)?;
+        std::fs::write(&path, commented(&toml::to_string(self)?, COMMENTS))
+            .with_context(|| format!("Failed to write config file {}", path.display()))?;
+
+        Ok(path)
+    }
+
+    pub fn validate(&self) -> Result<(), Invalid> {
+        let mut validator = Validator::default();
+
+        validator.electrum_rpc_urls("bitcoin.electrum_rpc_url", &self.bitcoin.electrum_rpc_url);
+        if let Some(target_block) = self.bitcoin.target_block {
+            validator.target_block("bitcoin.target_block", target_block);
+        }
+        validator.daemon_addresses("monero.daemon_addresses", &self.monero.daemon_addresses);
+        if let Some(port) = self.tor.socks5_port {
+            validator.port("tor.socks5_port", port);
+        }
+
+        validator.finish()
+    }
+}
+
+pub fn query_user_for_initial_config(testnet: bool) -> Result<Config> {
+    let (electrum_rpc_url, target_block, monero_daemon_address) = if testnet {
+        (
+            DEFAULT_ELECTRUM_RPC_URL_TESTNET,
+            DEFAULT_BITCOIN_CONFIRMATION_TARGET_TESTNET,
+            DEFAULT_MONERO_DAEMON_ADDRESS_STAGENET,
+        )
+    } else {
+        (
+            DEFAULT_ELECTRUM_RPC_URL,
+            DEFAULT_BITCOIN_CONFIRMATION_TARGET,
+            DEFAULT_MONERO_DAEMON_ADDRESS,
+        )
+    };
+
+    println!();
+    let electrum_rpc_url = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter Electrum RPC URL or hit return to use default")
+        .default(Url::parse(electrum_rpc_url)?)
+        .interact_text()?;
+
+    let target_block = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("How fast should your Bitcoin transactions be confirmed? Your transaction fee will be calculated based on this target. Hit return to use default")
+        .default(target_block)
+        .interact_text()?;
+
+    let monero_daemon_address = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(
+            "Enter the address of a Monero node as <host>:<port> or hit return to use default",
+        )
+        .default(monero_daemon_address.to_owned())
+        .interact_text()?;
+
+    let tor_socks5_port = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter Tor socks5 port or hit return to use default")
+        .default(DEFAULT_SOCKS5_PORT)
+        .interact_text()?;
+    println!();
+
+    Ok(Config {
+        bitcoin: Bitcoin {
+            electrum_rpc_url: vec![electrum_rpc_url],
+            target_block: Some(target_block),
+        },
+        monero: Monero {
+            daemon_addresses: vec![monero_daemon_address],
+        },
+        tor: Tor {
+            socks5_port: Some(tor_socks5_port),
+        },
+        coin_control: CoinControl::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_config_file_yields_defaults() {
+        let data_dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(Config::read(data_dir.path()).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn reads_coin_control_section() {
+        let data_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            data_dir.path().join(FILE_NAME),
+            "[coin_control]\navoid_reused_addresses = true\n",
+        )
+        .unwrap();
+
+        let config = Config::read(data_dir.path()).unwrap();
+
+        assert!(config.coin_control.avoid_reused_addresses);
+        assert!(!config.coin_control.confirmed_only);
+    }
+
+    #[test]
+    fn written_config_can_be_read() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            bitcoin: Bitcoin {
+                electrum_rpc_url: vec![Url::parse(DEFAULT_ELECTRUM_RPC_URL).unwrap()],
+                target_block: Some(2),
+            },
+            monero: Monero {
+                daemon_addresses: vec![DEFAULT_MONERO_DAEMON_ADDRESS.to_owned()],
+            },
+            tor: Tor {
+                socks5_port: Some(9150),
+            },
+            coin_control: CoinControl::default(),
+        };
+
+        let path = config.write(data_dir.path()).unwrap();
+
+        assert!(std::fs::read_to_string(path)
+            .unwrap()
+            .contains("# The port of the local Tor socks5 proxy.\nsocks5_port = 9150"));
+        assert_eq!(Config::read(data_dir.path()).unwrap(), config);
+    }
+
+    #[test]
+    fn refuses_invalid_config_file() {
+        let data_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            data_dir.path().join(FILE_NAME),
+            "[bitcoin]\nelectrum_rpc_url = \"https://blockstream.info\"\n\n[monero]\ndaemon_addresses = [\"localhost\"]\n",
+        )
+        .unwrap();
+
+        let error = Config::read(data_dir.path()).unwrap_err();
+
+        assert_eq!(error.downcast_ref::<Invalid>().unwrap().problems.len(), 2);
+    }
+}
@@ -0,0 +1,232 @@
+//! Recovering the Bitcoin of a swap without the CLI.
+//!
+//! Once the Bitcoin is locked, the cancel and the refund transaction can be
+//! fully signed by the CLI. An emergency kit contains both transactions
+//! together with instructions, so the Bitcoin can be refunded through any
+//! broadcaster if the machine running the CLI is lost.
+
+use crate::bitcoin::{Address, CancelTimelock, PunishTimelock, Transaction, Txid};
+use crate::database::Database;
+use crate::protocol::bob::BobState;
+use ::bitcoin::consensus::encode::serialize_hex;
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyKit {
+    swap_id: Uuid,
+    tx_lock_id: Txid,
+    cancel_timelock: CancelTimelock,
+    punish_timelock: PunishTimelock,
+    refund_address: Address,
+    tx_cancel: Transaction,
+    tx_refund: Transaction,
+}
+
+impl EmergencyKit {
+    /// Returns `None` if the Bitcoin of the swap is not locked yet or was
+    /// already redeemed, refunded or punished.
+    pub fn new(swap_id: Uuid, state: BobState) -> Result<Option<Self>> {
+        let state6 = match state {
+            BobState::BtcLocked(state3) => state3.cancel(),
+            BobState::XmrLockProofReceived { state, .. }
+            | BobState::BtcEarlyRefundable { state, .. } => state.cancel(),
+            BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => state4.cancel(),
+            BobState::CancelTimelockExpired(state6) | BobState::BtcCancelled(state6) => state6,
+            BobState::Started { .. }
+            | BobState::SwapSetupCompleted(_)
+            | BobState::BtcRedeemed(_)
+            | BobState::BtcRefunded(_)
+            | BobState::BtcEarlyRefunded(_)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            swap_id,
+            tx_lock_id: state6.tx_lock_id(),
+            cancel_timelock: state6.cancel_timelock(),
+            punish_timelock: state6.punish_timelock(),
+            refund_address: state6.refund_address().clone(),
+            tx_cancel: state6.signed_cancel_transaction()?,
+            tx_refund: state6.signed_refund_transaction()?,
+        }))
+    }
+
+    pub fn from_db(db: &Database, swap_id: Uuid) -> Result<Self> {
+        let state = db.get_state(swap_id)?.try_into_bob()?.into();
+
+        Self::new(swap_id, state)?.with_context(|| {
+            format!(
+                "Swap {} has no emergency kit, its Bitcoin is not locked or was already spent",
+                swap_id
+            )
+        })
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to write emergency kit to {}", path.display()))
+    }
+}
+
+impl fmt::Display for EmergencyKit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Emergency kit for swap {}", self.swap_id)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Use this kit to get your Bitcoin back if you lost access to the CLI that ran the swap."
+        )?;
+        writeln!(
+            f,
+            "Both transactions are already signed, they can be broadcast through any Bitcoin node or block explorer."
+        )?;
+        writeln!(f)?;
+        writeln!(f, "Lock transaction: {}", self.tx_lock_id)?;
+        writeln!(f, "Refund address:   {}", self.refund_address)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "1. Once the lock transaction is confirmed for {}, broadcast the cancel transaction. Earlier attempts are rejected by the network. Skip this step if the cancel transaction was already broadcast by the seller.",
+            self.cancel_timelock
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", serialize_hex(&self.tx_cancel))?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "2. Right after that, broadcast the refund transaction. It has to be confirmed within {} of the cancel transaction, afterwards the seller can punish you and keep the Bitcoin.",
+            self.punish_timelock
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", serialize_hex(&self.tx_refund))?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "If the lock transaction was already spent by the seller, the seller redeemed the Bitcoin and these transactions are invalid. The Monero can then only be redeemed with the state of the swap, the seed alone is not enough. Import the latest backup made with `swap export --swap-id {}` into a CLI using the same seed and resume the swap. Without such a backup the Monero is lost.",
+            self.swap_id
+        )
+    }
+}
+
+/// The file the emergency kit of a swap is written to by default.
+pub fn default_path(data_dir: &Path, swap_id: Uuid) -> PathBuf {
+    data_dir
+        .join("emergency-kits")
+        .join(format!("{}.txt", swap_id))
+}
+
+/// Waits until the Bitcoin of the swap is locked and writes its emergency kit
+/// to `path`.
+///
+/// The kit only recovers the Bitcoin, redeeming the Monero on another machine
+/// requires a swap export.
+///
+/// Gives up once the swap has ended without its Bitcoin being locked.
+pub async fn write_once_btc_locked(db: Database, swap_id: Uuid, path: PathBuf, interval: Duration) {
+    loop {
+        match write_if_btc_locked(&db, swap_id, &path) {
+            Ok(true) => {
+                tracing::info!(
+                    %swap_id,
+                    "Wrote emergency kit to {}, copy it to another device to be able to refund without this machine, and back up the swap with `swap export --swap-id {}` once the Monero is locked",
+                    path.display(),
+                    swap_id
+                );
+                return;
+            }
+            Ok(false) => {}
+            Err(error) => {
+                tracing::warn!(%swap_id, "Failed to write emergency kit: {:#}", error);
+                return;
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Returns whether the kit was written. A swap whose Bitcoin is not locked
+/// yet is retried later.
+fn write_if_btc_locked(db: &Database, swap_id: Uuid, path: &Path) -> Result<bool> {
+    let state: BobState = match db.get_state(swap_id) {
+        Ok(state) => state.try_into_bob()?.into(),
+        // The swap is only saved once the swap setup completed.
+        Err(_) => return Ok(false),
+    };
+
+    match state {
+        BobState::Started { .. } | BobState::SwapSetupCompleted(_) => Ok(false),
+        BobState::SafelyAborted => bail!("Swap was aborted before the Bitcoin was locked"),
+        state => match EmergencyKit::new(swap_id, state)? {
+            Some(kit) => {
+                kit.write_to(path)?;
+                Ok(true)
+            }
+            None => bail!("The Bitcoin of the swap was already spent"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::hashes::hex::FromHex;
+    use ::bitcoin::{OutPoint, Script, TxIn, TxOut};
+    use std::str::FromStr;
+
+    #[test]
+    fn instructions_contain_the_transactions_and_timelocks() {
+        let tx_cancel = transaction(1);
+        let tx_refund = transaction(2);
+        let kit = EmergencyKit {
+            swap_id: Uuid::nil(),
+            tx_lock_id: Txid::from_hex(
+                "ad2c7cb1c8f1e4e1dbd1c5a5cdb6baaf2ec2f7e8c3a44e3c1d1d5e2e8a9f0c11",
+            )
+            .unwrap(),
+            cancel_timelock: CancelTimelock::new(72),
+            punish_timelock: PunishTimelock::new(144),
+            refund_address: Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+                .unwrap(),
+            tx_cancel: tx_cancel.clone(),
+            tx_refund: tx_refund.clone(),
+        };
+
+        let instructions = kit.to_string();
+
+        assert!(instructions.contains("confirmed for 72 blocks"));
+        assert!(instructions.contains("within 144 blocks"));
+        assert!(instructions.contains(&serialize_hex(&tx_cancel)));
+        assert!(instructions.contains(&serialize_hex(&tx_refund)));
+        assert!(instructions.contains("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"));
+        assert!(instructions.contains(&format!("swap export --swap-id {}", Uuid::nil())));
+    }
+
+    fn transaction(vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::default(), vout),
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use crate::bitcoin::EncryptedSignature;
+use crate::backoff::{self, Policy};
+use crate::bitcoin::{EncryptedSignature, Signature};
 use crate::cli::behaviour::{Behaviour, OutEvent};
-use crate::network::encrypted_signature;
-use crate::network::quote::BidQuote;
+use crate::network::quote::SignedBidQuote;
 use crate::network::swap_setup::bob::NewSwap;
+use crate::network::{encrypted_signature, receipt};
 use crate::protocol::bob::State2;
+use crate::receipt::Receipt;
 use crate::{env, monero};
 use anyhow::{Context, Result};
 use futures::future::{BoxFuture, OptionFuture};
@@ -12,7 +14,7 @@ use libp2p::request_response::{RequestId, ResponseChannel};
 use libp2p::swarm::SwarmEvent;
 use libp2p::{PeerId, Swarm};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[allow(missing_debug_implementations)]
@@ -22,16 +24,18 @@ pub struct EventLoop {
     alice_peer_id: PeerId,
 
     // these streams represents outgoing requests that we have to make
-    quote_requests: bmrng::RequestReceiverStream<(), BidQuote>,
+    quote_requests: bmrng::RequestReceiverStream<(), SignedBidQuote>,
     encrypted_signatures: bmrng::RequestReceiverStream<EncryptedSignature, ()>,
     swap_setup_requests: bmrng::RequestReceiverStream<NewSwap, Result<State2>>,
+    receipt_requests: bmrng::RequestReceiverStream<(Receipt, Signature), Result<receipt::Response>>,
 
     // these represents requests that are currently in-flight.
     // once we get a response to a matching [`RequestId`], we will use the responder to relay the
     // response.
-    inflight_quote_requests: HashMap<RequestId, bmrng::Responder<BidQuote>>,
+    inflight_quote_requests: HashMap<RequestId, bmrng::Responder<SignedBidQuote>>,
     inflight_encrypted_signature_requests: HashMap<RequestId, bmrng::Responder<()>>,
     inflight_swap_setup: Option<bmrng::Responder<Result<State2>>>,
+    inflight_receipt_requests: HashMap<RequestId, bmrng::Responder<Result<receipt::Response>>>,
 
     /// The sender we will use to relay incoming transfer proofs.
     transfer_proof: bmrng::RequestSender<monero::TransferProof, ()>,
@@ -43,6 +47,11 @@ pub struct EventLoop {
     /// resolves, we use the `ResponseChannel` returned from it to send an ACK
     /// to Alice that we have successfully processed the transfer proof.
     pending_transfer_proof: OptionFuture<BoxFuture<'static, ResponseChannel<()>>>,
+
+    /// The sender we will use to relay incoming early refund signatures,
+    /// acknowledged like transfer proofs.
+    early_refund: bmrng::RequestSender<Signature, ()>,
+    pending_early_refund: OptionFuture<BoxFuture<'static, ResponseChannel<()>>>,
 }
 
 impl EventLoop {
@@ -54,8 +63,10 @@ impl EventLoop {
     ) -> Result<(Self, EventLoopHandle)> {
         let execution_setup = bmrng::channel_with_timeout(1, Duration::from_secs(60));
         let transfer_proof = bmrng::channel_with_timeout(1, Duration::from_secs(60));
+        let early_refund = bmrng::channel_with_timeout(1, Duration::from_secs(60));
         let encrypted_signature = bmrng::channel_with_timeout(1, Duration::from_secs(60));
         let quote = bmrng::channel_with_timeout(1, Duration::from_secs(60));
+        let receipt = bmrng::channel_with_timeout(1, Duration::from_secs(60));
 
         let event_loop = EventLoop {
             swap_id,
@@ -65,23 +76,31 @@ impl EventLoop {
             transfer_proof: transfer_proof.0,
             encrypted_signatures: encrypted_signature.1.into(),
             quote_requests: quote.1.into(),
+            receipt_requests: receipt.1.into(),
             inflight_quote_requests: HashMap::default(),
             inflight_swap_setup: None,
             inflight_encrypted_signature_requests: HashMap::default(),
+            inflight_receipt_requests: HashMap::default(),
             pending_transfer_proof: OptionFuture::from(None),
+            early_refund: early_refund.0,
+            pending_early_refund: OptionFuture::from(None),
         };
 
         let handle = EventLoopHandle {
+            peer: alice_peer_id,
             swap_setup: execution_setup.0,
             transfer_proof: transfer_proof.1,
+            early_refund: early_refund.1,
             encrypted_signature: encrypted_signature.0,
             quote: quote.0,
+            receipt: receipt.0,
             env_config,
         };
 
         Ok((event_loop, handle))
     }
 
+    #[tracing::instrument(name = "event_loop", skip(self), fields(swap_id = %self.swap_id, peer = %self.alice_peer_id))]
     pub async fn run(mut self) {
         match self.swarm.dial(&self.alice_peer_id) {
             Ok(()) => {}
@@ -142,11 +161,54 @@ impl EventLoop {
                                 channel
                             }.boxed()));
                         }
+                        SwarmEvent::Behaviour(OutEvent::EarlyRefundReceived { msg, channel, peer }) => {
+                            let swap_id = msg.swap_id;
+
+                            if peer != self.alice_peer_id {
+                                tracing::warn!(
+                                    %swap_id,
+                                    "Ignoring malicious early refund signature from {}, expected to receive it from {}",
+                                    peer,
+                                    self.alice_peer_id);
+                                continue;
+                            }
+
+                            if swap_id != self.swap_id {
+                                tracing::warn!("Received unexpected early refund signature for swap {} while running swap {}. This signature will be ignored", swap_id, self.swap_id);
+
+                                let _ = self.swarm.behaviour_mut().early_refund.send_response(channel, ());
+                                continue;
+                            }
+
+                            let mut responder = match self.early_refund.send(msg.tx_early_refund_sig).await {
+                                Ok(responder) => responder,
+                                Err(e) => {
+                                    tracing::warn!("Failed to pass on early refund signature: {:#}", e);
+                                    continue;
+                                }
+                            };
+
+                            self.pending_early_refund = OptionFuture::from(Some(async move {
+                                let _ = responder.recv().await;
+
+                                channel
+                            }.boxed()));
+                        }
                         SwarmEvent::Behaviour(OutEvent::EncryptedSignatureAcknowledged { id }) => {
                             if let Some(responder) = self.inflight_encrypted_signature_requests.remove(&id) {
                                 let _ = responder.respond(());
                             }
                         }
+                        SwarmEvent::Behaviour(OutEvent::ReceiptReceived { id, response }) => {
+                            if let Some(responder) = self.inflight_receipt_requests.remove(&id) {
+                                let _ = responder.respond(Ok(response));
+                            }
+                        }
+                        SwarmEvent::Behaviour(OutEvent::ReceiptRequestFailed { id, error }) => {
+                            if let Some(responder) = self.inflight_receipt_requests.remove(&id) {
+                                let _ = responder.respond(Err(error));
+                            }
+                        }
                         SwarmEvent::Behaviour(OutEvent::AllRedialAttemptsExhausted { peer }) if peer == self.alice_peer_id => {
                             tracing::error!("Exhausted all re-dial attempts to Alice");
                             return;
@@ -199,12 +261,28 @@ impl EventLoop {
                     let id = self.swarm.behaviour_mut().encrypted_signature.send_request(&self.alice_peer_id, request);
                     self.inflight_encrypted_signature_requests.insert(id, responder);
                 },
+                Some(((receipt, buyer_signature), responder)) = self.receipt_requests.next().fuse(), if self.is_connected_to_alice() => {
+                    let request = receipt::Request {
+                        swap_id: receipt.swap_id,
+                        receipt,
+                        buyer_signature,
+                    };
+
+                    let id = self.swarm.behaviour_mut().receipt.send_request(&self.alice_peer_id, request);
+                    self.inflight_receipt_requests.insert(id, responder);
+                },
 
                 Some(response_channel) = &mut self.pending_transfer_proof => {
                     let _ = self.swarm.behaviour_mut().transfer_proof.send_response(response_channel, ());
 
                     self.pending_transfer_proof = OptionFuture::from(None);
                 }
+
+                Some(response_channel) = &mut self.pending_early_refund => {
+                    let _ = self.swarm.behaviour_mut().early_refund.send_response(response_channel, ());
+
+                    self.pending_early_refund = OptionFuture::from(None);
+                }
             }
         }
     }
@@ -214,44 +292,134 @@ impl EventLoop {
     }
 }
 
+/// What Alice sends once the Bitcoin is locked.
+#[derive(Debug)]
+pub enum BtcLockedMessage {
+    TransferProof(monero::TransferProof),
+    EarlyRefund(Signature),
+}
+
 #[derive(Debug)]
 pub struct EventLoopHandle {
+    peer: PeerId,
     swap_setup: bmrng::RequestSender<NewSwap, Result<State2>>,
     transfer_proof: bmrng::RequestReceiver<monero::TransferProof, ()>,
+    early_refund: bmrng::RequestReceiver<Signature, ()>,
     encrypted_signature: bmrng::RequestSender<EncryptedSignature, ()>,
-    quote: bmrng::RequestSender<(), BidQuote>,
+    quote: bmrng::RequestSender<(), SignedBidQuote>,
+    receipt: bmrng::RequestSender<(Receipt, Signature), Result<receipt::Response>>,
     env_config: env::Config,
 }
 
 impl EventLoopHandle {
+    /// The peer this handle communicates with.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer
+    }
+
     pub async fn setup_swap(&mut self, swap: NewSwap) -> Result<State2> {
         self.swap_setup.send_receive(swap).await?
     }
 
-    pub async fn recv_transfer_proof(&mut self) -> Result<monero::TransferProof> {
-        let (transfer_proof, responder) = self
-            .transfer_proof
-            .recv()
-            .await
-            .context("Failed to receive transfer proof")?;
-        responder
-            .respond(())
-            .context("Failed to acknowledge receipt of transfer proof")?;
-
-        Ok(transfer_proof)
+    /// Waits for Alice to either send the proof that she locked the Monero or
+    /// her signature of the early refund transaction.
+    pub async fn recv_transfer_proof_or_early_refund(&mut self) -> Result<BtcLockedMessage> {
+        tokio::select! {
+            received = self.transfer_proof.recv() => {
+                let (transfer_proof, responder) = received.context("Failed to receive transfer proof")?;
+                responder
+                    .respond(())
+                    .context("Failed to acknowledge receipt of transfer proof")?;
+
+                Ok(BtcLockedMessage::TransferProof(transfer_proof))
+            }
+            received = self.early_refund.recv() => {
+                let (tx_early_refund_sig, responder) = received.context("Failed to receive early refund signature")?;
+                responder
+                    .respond(())
+                    .context("Failed to acknowledge receipt of early refund signature")?;
+
+                Ok(BtcLockedMessage::EarlyRefund(tx_early_refund_sig))
+            }
+        }
     }
 
-    pub async fn request_quote(&mut self) -> Result<BidQuote> {
-        Ok(self.quote.send_receive(()).await?)
+    /// Requests a quote from Alice, rejecting it if it has expired or was not
+    /// signed by her.
+    pub async fn request_quote(&mut self) -> Result<SignedBidQuote> {
+        let quote = self.quote.send_receive(()).await?;
+
+        if let Some(maker_fee) = quote.maker_fee() {
+            tracing::debug!(%maker_fee, "Seller charges a fee per XMR on top of the market price");
+        }
+
+        quote.verify(self.peer, SystemTime::now())?;
+
+        Ok(quote)
     }
 
+    /// Send the encrypted signature to Alice.
+    ///
+    /// Sending is retried with an exponential backoff until Alice
+    /// acknowledges the receipt. Without the encrypted signature Alice cannot
+    /// redeem, so giving up early would only leave the swap stuck until the
+    /// cancel timelock expires.
     pub async fn send_encrypted_signature(
         &mut self,
         tx_redeem_encsig: EncryptedSignature,
     ) -> Result<()> {
-        Ok(self
-            .encrypted_signature
-            .send_receive(tx_redeem_encsig)
-            .await?)
+        let encrypted_signature = &self.encrypted_signature;
+
+        backoff::retry(
+            "send encrypted signature to Alice",
+            Policy::PEER_REQUEST,
+            || {
+                let tx_redeem_encsig = tx_redeem_encsig.clone();
+
+                async move {
+                    encrypted_signature
+                        .send_receive(tx_redeem_encsig)
+                        .await
+                        .map_err(|e| backoff::Error::Transient(anyhow::Error::from(e)))
+                }
+            },
+        )
+        .await
+    }
+
+    /// Asks Alice to countersign the receipt of the completed swap, returning
+    /// her signature.
+    ///
+    /// Alice only signs once she published the redeem transaction, until then
+    /// the request is retried.
+    pub async fn request_receipt(
+        &mut self,
+        receipt: Receipt,
+        buyer_signature: Signature,
+    ) -> Result<Signature> {
+        let sender = &self.receipt;
+
+        backoff::retry("request receipt from Alice", Policy::RECEIPT, || {
+            let request = (receipt.clone(), buyer_signature.clone());
+
+            async move {
+                match sender.send_receive(request).await {
+                    Ok(Ok(Ok(seller_signature))) => Ok(seller_signature),
+                    Ok(Ok(Err(receipt::Error::NotCompleted))) => Err(backoff::Error::Transient(
+                        anyhow::Error::from(receipt::Error::NotCompleted),
+                    )),
+                    Ok(Ok(Err(error))) => {
+                        Err(backoff::Error::Permanent(anyhow::Error::from(error)))
+                    }
+                    Ok(Err(error)) => Err(backoff::Error::Permanent(
+                        error.context("Failed to request the receipt"),
+                    )),
+                    Err(error) => Err(backoff::Error::Permanent(
+                        anyhow::Error::from(error).context("Seller did not respond"),
+                    )),
+                }
+            }
+        })
+        .await
     }
 }
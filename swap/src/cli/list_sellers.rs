@@ -1,6 +1,8 @@
-use crate::network::quote::BidQuote;
+use crate::network::protocol_error::ProtocolError;
+use crate::network::quote::{BidQuote, ReserveProof};
 use crate::network::rendezvous::XmrBtcNamespace;
 use crate::network::{quote, swarm};
+use crate::{bitcoin, monero};
 use anyhow::{Context, Result};
 use futures::StreamExt;
 use libp2p::multiaddr::Protocol;
@@ -9,24 +11,32 @@ use libp2p::rendezvous::{Namespace, Rendezvous};
 use libp2p::request_response::{RequestResponseEvent, RequestResponseMessage};
 use libp2p::swarm::SwarmEvent;
 use libp2p::{identity, rendezvous, Multiaddr, PeerId, Swarm};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 use serde::Serialize;
 use serde_with::{serde_as, DisplayFromStr};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 /// Returns sorted list of sellers, with [Online](Status::Online) listed first.
 ///
 /// First uses the rendezvous node to discover peers in the given namespace,
 /// then fetches a quote from each peer that was discovered. If fetching a quote
 /// from a discovered peer fails the seller's status will be
-/// [Unreachable](Status::Unreachable).
+/// [Unreachable](Status::Unreachable), if the seller refuses to quote it will
+/// be [Refused](Status::Refused).
+///
+/// Reserve proofs attached to the quotes are only verified if a Monero wallet
+/// is given.
 pub async fn list_sellers(
     rendezvous_node_peer_id: PeerId,
     rendezvous_node_addr: Multiaddr,
     namespace: XmrBtcNamespace,
     tor_socks5_port: u16,
     identity: identity::Keypair,
+    monero_wallet: Option<Arc<monero::Wallet>>,
 ) -> Result<Vec<Seller>> {
     let behaviour = Behaviour {
         rendezvous: Rendezvous::new(identity.clone(), rendezvous::Config::default()),
@@ -52,6 +62,7 @@ pub async fn list_sellers(
         rendezvous_node_peer_id,
         rendezvous_node_addr,
         namespace,
+        monero_wallet,
     );
     let sellers = event_loop.run().await;
 
@@ -59,19 +70,190 @@ pub async fn list_sellers(
 }
 
 #[serde_as]
-#[derive(Debug, Serialize, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct Seller {
     pub status: Status,
     #[serde_as(as = "DisplayFromStr")]
     pub multiaddr: Multiaddr,
+    pub reserve: Reserve,
+    /// Only known once the sellers are [selected](select).
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat::opt")]
+    pub effective_price: Option<bitcoin::Amount>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq, Hash, Copy, Clone, Ord, PartialOrd)]
 pub enum Status {
     Online(BidQuote),
+    /// The seller is online but refused to quote, e.g. because it is out of
+    /// Monero.
+    Refused(ProtocolError),
     Unreachable,
 }
 
+/// Whether the seller proved to hold enough Monero for its quote.
+#[derive(Debug, Serialize, PartialEq, Eq, Hash, Copy, Clone, Ord, PartialOrd, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Reserve {
+    /// The proven reserves cover the maximum quantity of the quote.
+    Sufficient,
+    /// The proof is invalid, too old or does not cover the maximum quantity.
+    Insufficient,
+    /// A proof was attached but not checked.
+    Unverified,
+    NotProvided,
+}
+
+impl Reserve {
+    async fn check(
+        proof: Option<ReserveProof>,
+        quote: BidQuote,
+        seller: PeerId,
+        monero_wallet: Option<&monero::Wallet>,
+    ) -> Self {
+        let (proof, monero_wallet) = match (proof, monero_wallet) {
+            (None, _) => return Reserve::NotProvided,
+            (Some(_), None) => return Reserve::Unverified,
+            (Some(proof), Some(monero_wallet)) => (proof, monero_wallet),
+        };
+
+        match proof.verify(monero_wallet, seller, SystemTime::now()).await {
+            Ok(Some(reserves)) if covers(reserves, quote) => Reserve::Sufficient,
+            Ok(_) => Reserve::Insufficient,
+            Err(error) => {
+                tracing::warn!(%seller, "Failed to verify reserve proof: {:#}", error);
+                Reserve::Unverified
+            }
+        }
+    }
+}
+
+/// Whether the reserves suffice to sell the maximum quantity of the quote.
+fn covers(reserves: monero::Amount, quote: BidQuote) -> bool {
+    reserves
+        .max_bitcoin_for_price(quote.price)
+        .map_or(false, |max_quantity| max_quantity >= quote.max_quantity)
+}
+
+/// Restricts which of the discovered sellers are listed.
+///
+/// The quote of unreachable sellers is unknown, hence they are never within
+/// the requested amounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Filter {
+    /// Only sellers whose maximum quantity is at least this amount.
+    pub min_amount: Option<bitcoin::Amount>,
+    /// Only sellers whose minimum quantity is at most this amount.
+    pub max_amount: Option<bitcoin::Amount>,
+    /// Only sellers that accept swapping this amount.
+    pub amount: Option<bitcoin::Amount>,
+    /// Only sellers that are reachable through an onion address.
+    pub tor_only: bool,
+}
+
+impl Filter {
+    fn allows(&self, seller: &Seller) -> bool {
+        if self.tor_only && !is_onion(&seller.multiaddr) {
+            return false;
+        }
+        if self.min_amount.is_none() && self.max_amount.is_none() && self.amount.is_none() {
+            return true;
+        }
+
+        match seller.status {
+            Status::Online(quote) => {
+                self.min_amount
+                    .map_or(true, |min| quote.max_quantity >= min)
+                    && self
+                        .max_amount
+                        .map_or(true, |max| quote.min_quantity <= max)
+                    && self.amount.map_or(true, |amount| accepts(quote, amount))
+            }
+            Status::Refused(_) | Status::Unreachable => false,
+        }
+    }
+
+    /// The amount the effective prices of all sellers are compared at.
+    pub fn price_amount(&self) -> Option<bitcoin::Amount> {
+        self.amount.or(self.min_amount)
+    }
+}
+
+fn accepts(quote: BidQuote, amount: bitcoin::Amount) -> bool {
+    quote.min_quantity <= amount && amount <= quote.max_quantity
+}
+
+/// Onion services are also announced as DNS names ending in `.onion`.
+fn is_onion(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| match protocol {
+        Protocol::Onion(..) | Protocol::Onion3(_) => true,
+        Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host) => {
+            host.ends_with(".onion")
+        }
+        _ => false,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum SortBy {
+    /// The asking price of the quote.
+    Price,
+    /// The price paid per XMR that ends up in the wallet, see
+    /// [`effective_price`].
+    EffectivePrice,
+}
+
+/// Filters and sorts the sellers, [Online](Status::Online) sellers still come
+/// first.
+///
+/// The effective prices are calculated for the same amount for all sellers,
+/// see [`Filter::price_amount`]. Sellers that do not accept that amount have
+/// none.
+pub fn select(sellers: Vec<Seller>, filter: Filter, sort_by: SortBy) -> Vec<Seller> {
+    let mut sellers = sellers
+        .into_iter()
+        .filter(|seller| filter.allows(seller))
+        .map(|seller| Seller {
+            effective_price: match (seller.status, filter.price_amount()) {
+                (Status::Online(quote), Some(amount)) if accepts(quote, amount) => {
+                    effective_price(quote, amount)
+                }
+                _ => None,
+            },
+            ..seller
+        })
+        .collect::<Vec<_>>();
+
+    match sort_by {
+        SortBy::Price => sellers.sort(),
+        SortBy::EffectivePrice => sellers.sort_by(|a, b| {
+            let key = |seller: &Seller| (seller.effective_price.is_none(), seller.effective_price);
+            key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+        }),
+    }
+
+    sellers
+}
+
+/// The price per XMR that ends up in the wallet when swapping `btc` at the
+/// price of the quote, after paying the fee for sweeping the Monero.
+///
+/// The Bitcoin fees are the same for all sellers and not included.
+pub fn effective_price(quote: BidQuote, btc: bitcoin::Amount) -> Option<bitcoin::Amount> {
+    let xmr = monero::Amount::for_bitcoin_at_price(btc, quote.price)?;
+    let received = xmr
+        .as_piconero()
+        .checked_sub(monero::MONERO_FEE.as_piconero())
+        .filter(|piconero| *piconero > 0)?;
+
+    let sats = Decimal::from(btc.as_sat())
+        .checked_mul(Decimal::from(monero::PICONERO_OFFSET))?
+        .checked_div(Decimal::from(received))?
+        .ceil();
+
+    Some(bitcoin::Amount::from_sat(sats.to_u64()?))
+}
+
 #[derive(Debug)]
 enum OutEvent {
     Rendezvous(rendezvous::Event),
@@ -120,6 +302,8 @@ struct EventLoop {
     reachable_asb_address: HashMap<PeerId, Multiaddr>,
     unreachable_asb_address: HashMap<PeerId, Multiaddr>,
     asb_quote_status: HashMap<PeerId, QuoteStatus>,
+    asb_reserve: HashMap<PeerId, Reserve>,
+    monero_wallet: Option<Arc<monero::Wallet>>,
     state: State,
 }
 
@@ -129,6 +313,7 @@ impl EventLoop {
         rendezvous_peer_id: PeerId,
         rendezvous_addr: Multiaddr,
         namespace: XmrBtcNamespace,
+        monero_wallet: Option<Arc<monero::Wallet>>,
     ) -> Self {
         Self {
             swarm,
@@ -138,6 +323,8 @@ impl EventLoop {
             reachable_asb_address: Default::default(),
             unreachable_asb_address: Default::default(),
             asb_quote_status: Default::default(),
+            asb_reserve: Default::default(),
+            monero_wallet,
             state: State::WaitForDiscovery,
         }
     }
@@ -227,9 +414,26 @@ impl EventLoop {
                                 RequestResponseEvent::Message { peer, message } => {
                                     match message {
                                         RequestResponseMessage::Response { response, .. } => {
-                                            if self.asb_quote_status.insert(peer, QuoteStatus::Received(Status::Online(response))).is_none() {
-                                                tracing::error!(%peer, "Received bid quote from unexpected peer, this record will be removed!");
-                                                self.asb_quote_status.remove(&peer);
+                                            match response.verify(peer, SystemTime::now()) {
+                                                Ok(quote) => {
+                                                    let reserve = Reserve::check(response.reserve_proof, quote, peer, self.monero_wallet.as_deref()).await;
+                                                    self.asb_reserve.insert(peer, reserve);
+
+                                                    if self.asb_quote_status.insert(peer, QuoteStatus::Received(Status::Online(quote))).is_none() {
+                                                        tracing::error!(%peer, "Received bid quote from unexpected peer, this record will be removed!");
+                                                        self.asb_quote_status.remove(&peer);
+                                                    }
+                                                }
+                                                Err(error) => match response.error {
+                                                    Some(refused) => {
+                                                        tracing::debug!(%peer, "Seller refused to quote: {}", refused);
+                                                        self.asb_quote_status.insert(peer, QuoteStatus::Received(Status::Refused(refused)));
+                                                    }
+                                                    None => {
+                                                        tracing::debug!(%peer, "Ignoring seller, because the quote is invalid: {:#}", error);
+                                                        self.asb_quote_status.remove(&peer);
+                                                    }
+                                                },
                                             }
                                         }
                                         RequestResponseMessage::Request { .. } => unreachable!()
@@ -278,6 +482,25 @@ impl EventLoop {
                                 Ok(Seller {
                                     multiaddr: address.clone(),
                                     status: Status::Online(*quote),
+                                    reserve: self
+                                        .asb_reserve
+                                        .get(peer_id)
+                                        .copied()
+                                        .unwrap_or(Reserve::NotProvided),
+                                    effective_price: None,
+                                })
+                            }
+                            QuoteStatus::Received(Status::Refused(error)) => {
+                                let address = self
+                                    .reachable_asb_address
+                                    .get(&peer_id)
+                                    .expect("if we got a quote we must have stored an address");
+
+                                Ok(Seller {
+                                    multiaddr: address.clone(),
+                                    status: Status::Refused(*error),
+                                    reserve: Reserve::NotProvided,
+                                    effective_price: None,
                                 })
                             }
                             QuoteStatus::Received(Status::Unreachable) => {
@@ -289,6 +512,8 @@ impl EventLoop {
                                 Ok(Seller {
                                     multiaddr: address.clone(),
                                     status: Status::Unreachable,
+                                    reserve: Reserve::NotProvided,
+                                    effective_price: None,
                                 })
                             }
                         })
@@ -326,10 +551,14 @@ mod tests {
             Seller {
                 multiaddr: "/ip4/127.0.0.1/tcp/1234".parse().unwrap(),
                 status: Status::Unreachable,
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
             Seller {
                 multiaddr: Multiaddr::empty(),
                 status: Status::Unreachable,
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
             Seller {
                 multiaddr: "/ip4/127.0.0.1/tcp/5678".parse().unwrap(),
@@ -338,6 +567,8 @@ mod tests {
                     min_quantity: Default::default(),
                     max_quantity: Default::default(),
                 }),
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
         ];
 
@@ -350,16 +581,164 @@ mod tests {
                     price: Default::default(),
                     min_quantity: Default::default(),
                     max_quantity: Default::default(),
-                })
+                }),
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
             Seller {
                 multiaddr: Multiaddr::empty(),
-                status: Status::Unreachable
+                status: Status::Unreachable,
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
             Seller {
                 multiaddr: "/ip4/127.0.0.1/tcp/1234".parse().unwrap(),
-                status: Status::Unreachable
+                status: Status::Unreachable,
+                reserve: Reserve::NotProvided,
+                effective_price: None,
             },
         ])
     }
+
+    #[test]
+    fn refusing_sellers_sort_between_online_and_unreachable() {
+        let refused = Seller {
+            multiaddr: "/ip4/127.0.0.1/tcp/1234".parse().unwrap(),
+            status: Status::Refused(ProtocolError::InsufficientLiquidity),
+            reserve: Reserve::NotProvided,
+            effective_price: None,
+        };
+        let unreachable = Seller {
+            multiaddr: Multiaddr::empty(),
+            status: Status::Unreachable,
+            reserve: Reserve::NotProvided,
+            effective_price: None,
+        };
+        let online = online("/ip4/127.0.0.1/tcp/5678", 100_000, 0, 1_000_000);
+
+        let sellers = select(
+            vec![unreachable.clone(), refused.clone(), online.clone()],
+            Filter::default(),
+            SortBy::Price,
+        );
+
+        assert_eq!(sellers, vec![online, refused, unreachable]);
+        assert_eq!(
+            serde_json::to_value(&sellers[1].status).unwrap(),
+            serde_json::json!({ "Refused": "InsufficientLiquidity" })
+        );
+    }
+
+    #[test]
+    fn reserves_have_to_cover_the_maximum_quantity() {
+        let quote = BidQuote {
+            price: bitcoin::Amount::from_sat(500_000),
+            min_quantity: bitcoin::Amount::ZERO,
+            max_quantity: bitcoin::Amount::from_sat(1_000_000),
+        };
+
+        assert!(covers(monero::Amount::from_monero(3.0).unwrap(), quote));
+        assert!(!covers(monero::Amount::from_monero(1.0).unwrap(), quote));
+    }
+
+    fn online(address: &str, price: u64, min_quantity: u64, max_quantity: u64) -> Seller {
+        Seller {
+            status: Status::Online(BidQuote {
+                price: bitcoin::Amount::from_sat(price),
+                min_quantity: bitcoin::Amount::from_sat(min_quantity),
+                max_quantity: bitcoin::Amount::from_sat(max_quantity),
+            }),
+            multiaddr: address.parse().unwrap(),
+            reserve: Reserve::NotProvided,
+            effective_price: None,
+        }
+    }
+
+    #[test]
+    fn filters_by_amount_and_onion_address() {
+        let onion = "/dns4/ac4hgzmsmekwekjbdl77brufqqbylddugzze4tel6qsnlympgmr46iid.onion/tcp/8765";
+        let sellers = vec![
+            online("/ip4/127.0.0.1/tcp/1", 500_000, 10_000, 100_000),
+            online(onion, 500_000, 200_000, 1_000_000),
+            Seller {
+                status: Status::Unreachable,
+                ..online(onion, 0, 0, 0)
+            },
+        ];
+        let addresses = |filter| {
+            select(sellers.clone(), filter, SortBy::Price)
+                .into_iter()
+                .map(|seller| (seller.multiaddr.to_string(), seller.status))
+                .collect::<Vec<_>>()
+        };
+
+        let filter = Filter {
+            min_amount: Some(bitcoin::Amount::from_sat(150_000)),
+            ..Filter::default()
+        };
+        assert_eq!(addresses(filter), vec![(
+            onion.to_owned(),
+            sellers[1].status
+        )]);
+
+        let filter = Filter {
+            max_amount: Some(bitcoin::Amount::from_sat(150_000)),
+            ..Filter::default()
+        };
+        assert_eq!(addresses(filter), vec![(
+            "/ip4/127.0.0.1/tcp/1".to_owned(),
+            sellers[0].status
+        )]);
+
+        let filter = Filter {
+            tor_only: true,
+            ..Filter::default()
+        };
+        assert_eq!(addresses(filter), vec![
+            (onion.to_owned(), sellers[1].status),
+            (onion.to_owned(), Status::Unreachable)
+        ]);
+        assert!(is_onion(
+            &"/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234"
+                .parse()
+                .unwrap()
+        ));
+    }
+
+    #[test]
+    fn effective_prices_are_compared_at_the_same_amount() {
+        let cheap = online("/ip4/127.0.0.1/tcp/1", 500_000, 1_000, 100_000);
+        let pricier = online("/ip4/127.0.0.1/tcp/2", 505_000, 100_000, 1_000_000);
+        let large_only = online("/ip4/127.0.0.1/tcp/3", 400_000, 200_000, 1_000_000);
+        let sellers = vec![pricier.clone(), large_only.clone(), cheap.clone()];
+
+        let filter = Filter {
+            amount: Some(bitcoin::Amount::from_sat(100_000)),
+            ..Filter::default()
+        };
+        let selected = select(sellers.clone(), filter, SortBy::EffectivePrice);
+        assert_eq!(
+            selected
+                .iter()
+                .map(|seller| seller.multiaddr.clone())
+                .collect::<Vec<_>>(),
+            vec![cheap.multiaddr.clone(), pricier.multiaddr.clone()]
+        );
+        // The fee for sweeping the Monero is the same for both at this amount.
+        assert!(selected[0].effective_price > Some(bitcoin::Amount::from_sat(500_000)));
+        assert!(selected[0].effective_price < selected[1].effective_price);
+
+        let filter = Filter {
+            min_amount: Some(bitcoin::Amount::from_sat(100_000)),
+            ..Filter::default()
+        };
+        let selected = select(sellers.clone(), filter, SortBy::EffectivePrice);
+        assert_eq!(selected[2].multiaddr, large_only.multiaddr);
+        assert_eq!(selected[2].effective_price, None);
+
+        let selected = select(sellers, Filter::default(), SortBy::EffectivePrice);
+        assert!(selected
+            .iter()
+            .all(|seller| seller.effective_price.is_none()));
+    }
 }
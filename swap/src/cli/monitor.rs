@@ -0,0 +1,363 @@
+//! Watching a swap without acting on it.
+//!
+//! The monitor never broadcasts a transaction and never contacts the seller.
+//! It only reports the transactions of the swap as they appear on the chains,
+//! which allows supervising a recovery that is carried out manually, for
+//! example with an emergency kit, or from another machine.
+
+use crate::bitcoin::wallet::{ScriptStatus, Watchable};
+use crate::bitcoin::{ExpiredTimelocks, TxCancel, Txid};
+use crate::env::Config;
+use crate::monero::wallet::WatchRequest;
+use crate::monero::InsufficientFunds;
+use crate::protocol::bob::{BobState, State6};
+use crate::{bitcoin, monero};
+use ::bitcoin::Script;
+use anyhow::Result;
+use futures::future;
+use std::time::Duration;
+use tokio::select;
+use uuid::Uuid;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether monitoring the swap in the given state requires a Monero wallet.
+pub fn watches_monero(state: &BobState) -> bool {
+    matches!(state, BobState::XmrLockProofReceived { .. })
+}
+
+/// Reports the transactions of the swap until its Bitcoin is redeemed,
+/// refunded or punished with finality.
+///
+/// The Monero lock transaction is only watched if a Monero wallet is given.
+pub async fn monitor(
+    swap_id: Uuid,
+    state: BobState,
+    bitcoin_wallet: &bitcoin::Wallet,
+    monero_wallet: Option<&monero::Wallet>,
+    env_config: Config,
+) -> Result<()> {
+    let (state6, tx_redeem, lock_xmr_watch_request) = match state {
+        BobState::BtcLocked(state3) | BobState::BtcEarlyRefundable { state: state3, .. } => {
+            (state3.cancel(), None, None)
+        }
+        BobState::XmrLockProofReceived {
+            state,
+            lock_transfer_proof,
+            ..
+        } => (
+            state.cancel(),
+            None,
+            Some(state.lock_xmr_watch_request(lock_transfer_proof)),
+        ),
+        BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => {
+            let tx_redeem = Watched::new("redeem", &state4.tx_redeem());
+
+            (state4.cancel(), Some(tx_redeem), None)
+        }
+        BobState::CancelTimelockExpired(state6)
+        | BobState::BtcCancelled(state6)
+        | BobState::BtcRefunded(state6) => (state6, None, None),
+        BobState::BtcRedeemed(_) => {
+            tracing::info!(
+                %swap_id,
+                "The seller redeemed the Bitcoin, resume the swap without --monitor-only to redeem the Monero"
+            );
+            return Ok(());
+        }
+        BobState::BtcPunished { .. } => {
+            tracing::warn!(%swap_id, "The seller punished the swap and took the Bitcoin");
+            return Ok(());
+        }
+        state => {
+            tracing::info!(
+                %swap_id,
+                %state,
+                "Nothing to monitor, no Bitcoin of the swap is locked"
+            );
+            return Ok(());
+        }
+    };
+
+    tracing::info!(
+        %swap_id,
+        "Monitoring swap, no transactions are broadcast and the seller is not contacted"
+    );
+
+    let transactions = Transactions {
+        lock: Watched::new("lock", state6.tx_lock()),
+        cancel: Watched::new("cancel", &state6.tx_cancel()),
+        refund: Watched::new("refund", &state6.tx_refund()),
+        redeem: tx_redeem,
+        punish: None,
+    };
+
+    let watch_monero = async {
+        if let (Some(monero_wallet), Some(request)) = (monero_wallet, lock_xmr_watch_request) {
+            watch_xmr_lock(monero_wallet, request).await;
+        }
+
+        future::pending::<()>().await
+    };
+
+    select! {
+        result = watch_bitcoin(transactions, &state6, bitcoin_wallet, env_config) => result,
+        _ = watch_monero => unreachable!("watching Monero never finishes"),
+    }
+}
+
+async fn watch_bitcoin(
+    mut transactions: Transactions,
+    state6: &State6,
+    bitcoin_wallet: &bitcoin::Wallet,
+    env_config: Config,
+) -> Result<()> {
+    let mut last_expired_timelocks = None;
+    let tx_cancel = state6.tx_cancel();
+
+    loop {
+        for transaction in transactions.iter_mut() {
+            transaction.check(bitcoin_wallet).await;
+        }
+        if transactions.cancel.has_been_seen() {
+            transactions.check_punish(bitcoin_wallet, &tx_cancel).await;
+        }
+
+        match state6.expired_timelock(bitcoin_wallet).await {
+            Ok(expired_timelocks) if last_expired_timelocks != Some(expired_timelocks) => {
+                report_timelocks(expired_timelocks);
+                last_expired_timelocks = Some(expired_timelocks);
+            }
+            Ok(_) => {}
+            Err(error) => tracing::warn!("Failed to check the timelocks: {:#}", error),
+        }
+
+        match transactions.outcome(env_config.bitcoin_finality_confirmations) {
+            Some(Outcome::Redeemed) => {
+                tracing::info!(
+                    "The seller redeemed the Bitcoin, resume the swap without --monitor-only to redeem the Monero"
+                );
+                return Ok(());
+            }
+            Some(Outcome::Refunded) => {
+                tracing::info!("The Bitcoin was refunded");
+                return Ok(());
+            }
+            Some(Outcome::Punished) => {
+                tracing::warn!("The seller punished the swap and took the Bitcoin");
+                return Ok(());
+            }
+            None => {}
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+async fn watch_xmr_lock(monero_wallet: &monero::Wallet, request: WatchRequest) {
+    let txid = request.transfer_proof.tx_hash();
+
+    match monero_wallet.watch_for_transfer(request).await {
+        Ok(()) => tracing::info!(%txid, "The Monero lock transaction is final"),
+        Err(InsufficientFunds { expected, actual }) => tracing::warn!(
+            %txid,
+            %expected,
+            %actual,
+            "The seller locked less Monero than agreed, do not send the encrypted signature"
+        ),
+    }
+}
+
+fn report_timelocks(expired_timelocks: ExpiredTimelocks) {
+    match expired_timelocks {
+        ExpiredTimelocks::None => tracing::info!("No timelock expired yet"),
+        ExpiredTimelocks::Cancel => tracing::info!(
+            "The cancel timelock expired, the cancel transaction can be broadcast"
+        ),
+        ExpiredTimelocks::Punish => tracing::warn!(
+            "The punish timelock expired, the seller can punish unless the refund transaction is confirmed"
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Redeemed,
+    Refunded,
+    Punished,
+}
+
+/// The transactions of the swap known to the CLI.
+struct Transactions {
+    lock: Watched,
+    cancel: Watched,
+    refund: Watched,
+    /// Only known once the Monero is locked.
+    redeem: Option<Watched>,
+    /// The punish transaction pays to an address of the seller and can
+    /// therefore not be watched. It is found as the transaction spending the
+    /// cancel transaction that is not the refund transaction.
+    punish: Option<(Txid, ScriptStatus)>,
+}
+
+impl Transactions {
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Watched> {
+        vec![&mut self.lock, &mut self.cancel, &mut self.refund]
+            .into_iter()
+            .chain(self.redeem.as_mut())
+    }
+
+    fn outcome(&self, finality_confirmations: u32) -> Option<Outcome> {
+        let is_final = |transaction: &Watched| transaction.is_final(finality_confirmations);
+
+        if self.redeem.as_ref().map_or(false, is_final) {
+            return Some(Outcome::Redeemed);
+        }
+        if is_final(&self.refund) {
+            return Some(Outcome::Refunded);
+        }
+        if self.punish.map_or(false, |(_, status)| {
+            status.is_confirmed_with(finality_confirmations)
+        }) {
+            return Some(Outcome::Punished);
+        }
+
+        None
+    }
+
+    /// Reports the punish transaction if the cancel transaction was spent by
+    /// anything but the refund transaction.
+    async fn check_punish(&mut self, bitcoin_wallet: &bitcoin::Wallet, tx_cancel: &TxCancel) {
+        let (transaction, status) = match bitcoin_wallet
+            .spending_transaction(tx_cancel, tx_cancel.as_outpoint())
+            .await
+        {
+            Ok(Some(spend)) => spend,
+            Ok(None) => return,
+            Err(error) => {
+                tracing::warn!("Failed to check whether the seller punished: {:#}", error);
+                return;
+            }
+        };
+
+        let txid = transaction.txid();
+        if txid == self.refund.txid {
+            return;
+        }
+
+        if self.punish != Some((txid, status)) {
+            tracing::warn!(%txid, "The seller punished the swap, the punish transaction is {}", status);
+            self.punish = Some((txid, status));
+        }
+    }
+}
+
+struct Watched {
+    name: &'static str,
+    txid: Txid,
+    script: Script,
+    status: Option<ScriptStatus>,
+}
+
+impl Watched {
+    fn new(name: &'static str, transaction: &impl Watchable) -> Self {
+        Self {
+            name,
+            txid: transaction.id(),
+            script: transaction.script(),
+            status: None,
+        }
+    }
+
+    fn has_been_seen(&self) -> bool {
+        self.status.map_or(false, |status| status.has_been_seen())
+    }
+
+    fn is_final(&self, finality_confirmations: u32) -> bool {
+        self.status.map_or(false, |status| {
+            status.is_confirmed_with(finality_confirmations)
+        })
+    }
+
+    /// Reports the status if it changed since the last check.
+    async fn check(&mut self, bitcoin_wallet: &bitcoin::Wallet) {
+        let status = match bitcoin_wallet
+            .status_of_script(&(self.txid, self.script.clone()))
+            .await
+        {
+            Ok(status) => status,
+            Err(error) => {
+                tracing::warn!(
+                    txid = %self.txid,
+                    "Failed to get the status of the {} transaction: {:#}",
+                    self.name,
+                    error
+                );
+                return;
+            }
+        };
+
+        if self.status != Some(status) {
+            tracing::info!(
+                txid = %self.txid,
+                "The {} transaction is {}",
+                self.name,
+                status
+            );
+            self.status = Some(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watched(name: &'static str, confirmations: Option<u32>) -> Watched {
+        Watched {
+            name,
+            txid: Txid::default(),
+            script: Script::new(),
+            status: confirmations.map(ScriptStatus::from_confirmations),
+        }
+    }
+
+    #[test]
+    fn swap_ends_once_redeem_or_refund_is_final() {
+        let mut transactions = Transactions {
+            lock: watched("lock", Some(10)),
+            cancel: watched("cancel", Some(3)),
+            refund: watched("refund", Some(1)),
+            redeem: None,
+            punish: None,
+        };
+
+        assert_eq!(transactions.outcome(2), None);
+
+        transactions.refund.status = Some(ScriptStatus::from_confirmations(2));
+        assert_eq!(transactions.outcome(2), Some(Outcome::Refunded));
+
+        transactions.refund.status = Some(ScriptStatus::Unseen);
+        transactions.redeem = Some(watched("redeem", Some(0)));
+        assert_eq!(transactions.outcome(2), None);
+
+        transactions.redeem = Some(watched("redeem", Some(2)));
+        assert_eq!(transactions.outcome(2), Some(Outcome::Redeemed));
+    }
+
+    #[test]
+    fn swap_ends_once_punish_is_final() {
+        let mut transactions = Transactions {
+            lock: watched("lock", Some(80)),
+            cancel: watched("cancel", Some(10)),
+            refund: watched("refund", None),
+            redeem: None,
+            punish: Some((Txid::default(), ScriptStatus::InMempool)),
+        };
+
+        assert_eq!(transactions.outcome(2), None);
+
+        transactions.punish = Some((Txid::default(), ScriptStatus::from_confirmations(2)));
+        assert_eq!(transactions.outcome(2), Some(Outcome::Punished));
+    }
+}
@@ -20,7 +20,8 @@ pub async fn refund(
     let state6 = if force {
         match state {
             BobState::BtcLocked(state3) => state3.cancel(),
-            BobState::XmrLockProofReceived { state, .. } => state.cancel(),
+            BobState::XmrLockProofReceived { state, .. }
+            | BobState::BtcEarlyRefundable { state, .. } => state.cancel(),
             BobState::XmrLocked(state4) => state4.cancel(),
             BobState::EncSigSent(state4) => state4.cancel(),
             BobState::CancelTimelockExpired(state6) => state6,
@@ -29,6 +30,7 @@ pub async fn refund(
             | BobState::SwapSetupCompleted(_)
             | BobState::BtcRedeemed(_)
             | BobState::BtcRefunded(_)
+            | BobState::BtcEarlyRefunded(_)
             | BobState::XmrRedeemed { .. }
             | BobState::BtcPunished { .. }
             | BobState::SafelyAborted => bail!(
@@ -46,6 +48,7 @@ pub async fn refund(
         }
     };
 
+    state6.check_refund_safety(bitcoin_wallet.as_ref()).await?;
     state6.publish_refund_btc(bitcoin_wallet.as_ref()).await?;
 
     let state = BobState::BtcRefunded(state6);
@@ -0,0 +1,176 @@
+use crate::network::quote::BidQuote;
+use crate::{bitcoin, monero};
+use anyhow::{bail, Context, Result};
+use libp2p::Multiaddr;
+use std::cmp::min;
+
+/// The part of a split purchase that is bought from a single seller.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leg {
+    pub seller: Multiaddr,
+    pub btc_amount: bitcoin::Amount,
+    pub monero_amount: monero::Amount,
+}
+
+/// Splits the purchase of the given amount of Monero across the given sellers.
+///
+/// The cheapest quotes are filled first. A seller is only used if the part
+/// bought from them lies within the minimum and maximum quantity of their
+/// quote.
+pub fn split(
+    monero_amount: monero::Amount,
+    sellers: impl IntoIterator<Item = (Multiaddr, BidQuote)>,
+) -> Result<Vec<Leg>> {
+    let mut sellers = sellers.into_iter().collect::<Vec<_>>();
+    sellers.sort_by_key(|(_, quote)| quote.price);
+
+    let mut remaining = monero_amount;
+    let mut legs = Vec::new();
+
+    for (seller, quote) in sellers {
+        if remaining == monero::Amount::ZERO {
+            break;
+        }
+
+        let needed = remaining
+            .bitcoin_for_price(quote.price)
+            .context("Failed to calculate the Bitcoin amount for the remaining Monero")?;
+        let btc_amount = min(needed, quote.max_quantity);
+
+        if btc_amount == bitcoin::Amount::ZERO || btc_amount < quote.min_quantity {
+            tracing::debug!(%seller, %btc_amount, "Skipping seller because the amount is outside of their quote");
+            continue;
+        }
+
+        let bought = if btc_amount == needed {
+            remaining
+        } else {
+            monero::Amount::for_bitcoin_at_price(btc_amount, quote.price)
+                .context("Failed to calculate the Monero amount for the quoted maximum")?
+        };
+
+        remaining = remaining - bought;
+        legs.push(Leg {
+            seller,
+            btc_amount,
+            monero_amount: bought,
+        });
+    }
+
+    if remaining != monero::Amount::ZERO {
+        bail!(InsufficientLiquidity {
+            requested: monero_amount,
+            available: monero_amount - remaining,
+        })
+    }
+
+    Ok(legs)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The available sellers only accept swaps for {available} of the requested {requested}")]
+pub struct InsufficientLiquidity {
+    pub requested: monero::Amount,
+    pub available: monero::Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_cheapest_seller_first() {
+        let sellers = vec![
+            (address(1), quote(0.002, 0.0, 1.0)),
+            (address(2), quote(0.001, 0.0, 1.0)),
+        ];
+
+        let legs = split(xmr(10.0), sellers).unwrap();
+
+        assert_eq!(legs, vec![Leg {
+            seller: address(2),
+            btc_amount: btc(0.01),
+            monero_amount: xmr(10.0),
+        }]);
+    }
+
+    #[test]
+    fn splits_across_sellers_when_cheapest_is_exhausted() {
+        let sellers = vec![
+            (address(1), quote(0.001, 0.0, 0.004)),
+            (address(2), quote(0.002, 0.0, 1.0)),
+        ];
+
+        let legs = split(xmr(10.0), sellers).unwrap();
+
+        assert_eq!(legs, vec![
+            Leg {
+                seller: address(1),
+                btc_amount: btc(0.004),
+                monero_amount: xmr(4.0),
+            },
+            Leg {
+                seller: address(2),
+                btc_amount: btc(0.012),
+                monero_amount: xmr(6.0),
+            }
+        ]);
+    }
+
+    #[test]
+    fn skips_seller_whose_minimum_is_not_reached() {
+        let sellers = vec![
+            (address(1), quote(0.001, 0.0, 0.004)),
+            (address(2), quote(0.0015, 0.01, 1.0)),
+            (address(3), quote(0.002, 0.0, 1.0)),
+        ];
+
+        let legs = split(xmr(5.0), sellers).unwrap();
+
+        assert_eq!(legs, vec![
+            Leg {
+                seller: address(1),
+                btc_amount: btc(0.004),
+                monero_amount: xmr(4.0),
+            },
+            Leg {
+                seller: address(3),
+                btc_amount: btc(0.002),
+                monero_amount: xmr(1.0),
+            }
+        ]);
+    }
+
+    #[test]
+    fn fails_if_sellers_cannot_fill_requested_amount() {
+        let sellers = vec![
+            (address(1), quote(0.001, 0.0, 0.004)),
+            (address(2), quote(0.002, 0.0, 0.002)),
+        ];
+
+        let error = split(xmr(10.0), sellers).unwrap_err();
+        let error = error.downcast_ref::<InsufficientLiquidity>().unwrap();
+
+        assert_eq!(error.available, xmr(5.0));
+    }
+
+    fn quote(price: f64, min_quantity: f64, max_quantity: f64) -> BidQuote {
+        BidQuote {
+            price: btc(price),
+            min_quantity: btc(min_quantity),
+            max_quantity: btc(max_quantity),
+        }
+    }
+
+    fn btc(amount: f64) -> bitcoin::Amount {
+        bitcoin::Amount::from_btc(amount).unwrap()
+    }
+
+    fn xmr(amount: f64) -> monero::Amount {
+        monero::Amount::from_monero(amount).unwrap()
+    }
+
+    fn address(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+}
@@ -0,0 +1,268 @@
+//! Letting an auditor confirm the Monero lock of a swap.
+//!
+//! The Monero is locked to an address whose spend key is shared between Alice
+//! and Bob, while the view key is known to both. An export contains only the
+//! view key and what was supposed to be locked, enough for anyone to scan the
+//! lock address but not to spend from it. The key of the lock transaction
+//! proves how much it sent to the address, and Bob signs the export with his
+//! key of the Bitcoin lock output, which ties the address to the swap like
+//! the signatures of a receipt.
+
+use crate::bitcoin::{verify_sig, Txid};
+use crate::database::Database;
+use crate::monero::{monero_private_key, InsufficientFunds, PrivateViewKey, TransferProof, TxHash};
+use crate::protocol::bob::State3;
+use crate::{bitcoin, monero};
+use ::bitcoin::hashes::Hash;
+use ::bitcoin::SigHash;
+use anyhow::{bail, Context, Result};
+use monero_rpc::wallet::BlockHeight;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::path::Path;
+use uuid::Uuid;
+
+const SIGNATURE_DOMAIN: &[u8] = b"/comit/xmr/btc/view-key/signature";
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ViewKeyExport {
+    pub swap_id: Uuid,
+    /// The address the Monero was locked to.
+    #[serde_as(as = "DisplayFromStr")]
+    pub address: monero::Address,
+    pub view_key: PrivateViewKey,
+    /// In piconero.
+    pub amount: monero::Amount,
+    pub tx_hash: TxHash,
+    /// Proves how much the lock transaction sent to the address.
+    #[serde(with = "monero_private_key")]
+    pub tx_key: monero::PrivateKey,
+    /// Scanning the address can start at this block height.
+    pub restore_height: u32,
+    pub tx_lock_id: Txid,
+    /// The key of the buyer in the Bitcoin lock output.
+    pub buyer: bitcoin::PublicKey,
+    /// Signature of the buyer over the swap id, the Bitcoin lock transaction,
+    /// the address, the amount and the Monero lock transaction.
+    pub signature: bitcoin::Signature,
+}
+
+impl ViewKeyExport {
+    pub fn new(
+        swap_id: Uuid,
+        state: &State3,
+        transfer_proof: TransferProof,
+        restore_height: BlockHeight,
+        network: monero::Network,
+    ) -> Self {
+        let tx_hash = transfer_proof.tx_hash();
+        let tx_key = transfer_proof.tx_key();
+        let request = state.lock_xmr_watch_request(transfer_proof);
+        let address = monero::Address::standard(
+            network,
+            request.public_spend_key,
+            request.public_view_key.into(),
+        );
+        let tx_lock_id = state.tx_lock_id();
+        let (buyer, signature) = state.sign_with_lock_key(digest(
+            swap_id,
+            tx_lock_id,
+            &address,
+            request.expected,
+            &tx_hash,
+        ));
+
+        Self {
+            swap_id,
+            address,
+            view_key: state.view_key(),
+            amount: request.expected,
+            tx_hash,
+            tx_key,
+            restore_height: restore_height.height,
+            tx_lock_id,
+            buyer,
+            signature,
+        }
+    }
+
+    /// Fails if Alice did not send the transfer proof of the Monero lock yet.
+    pub fn from_db(db: &Database, swap_id: Uuid) -> Result<Self> {
+        db.get_view_key(swap_id)?.with_context(|| {
+            format!(
+                "Swap {} has no Monero lock to export, the seller did not lock the Monero yet",
+                swap_id
+            )
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Failed to parse view key export")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("view key exports can always be serialized")
+    }
+
+    /// Fails if the view key does not belong to the address, the export was
+    /// then not produced by a swap.
+    pub fn check_view_key(&self) -> Result<()> {
+        if monero::PublicKey::from(self.view_key.public()) != self.address.public_view {
+            bail!("The view key does not belong to the address of the Monero lock")
+        }
+
+        Ok(())
+    }
+
+    /// Fails unless the buyer in the Bitcoin lock output signed the export,
+    /// the address is then not tied to the swap.
+    pub fn check_signature(&self) -> Result<()> {
+        verify_sig(&self.buyer, &self.digest(), &self.signature)
+            .context("The export is not signed by the buyer of the swap")
+    }
+
+    fn digest(&self) -> SigHash {
+        digest(
+            self.swap_id,
+            self.tx_lock_id,
+            &self.address,
+            self.amount,
+            &self.tx_hash,
+        )
+    }
+
+    /// Checks that the lock transaction sent at least the amount of the swap
+    /// to the address, then scans the address with the view key and returns
+    /// what it received.
+    ///
+    /// The view-only wallet used for scanning is created in and deleted from
+    /// `wallet_dir`.
+    pub async fn verify(
+        &self,
+        wallet: &monero::Wallet,
+        wallet_dir: &Path,
+    ) -> Result<monero::Amount> {
+        self.check_view_key()?;
+        self.check_signature()?;
+
+        let sent = wallet
+            .received_with_tx_key(&self.tx_hash, self.tx_key, self.address)
+            .await
+            .context("The transaction key does not prove the Monero lock transaction")?;
+        if sent < self.amount {
+            return Err(InsufficientFunds {
+                expected: self.amount,
+                actual: sent,
+            })
+            .context("The Monero lock transaction did not send the Monero of the swap");
+        }
+
+        let received = wallet
+            .received_with_view_key(wallet_dir, self.address, self.view_key, BlockHeight {
+                height: self.restore_height,
+            })
+            .await?;
+
+        if received < self.amount {
+            return Err(InsufficientFunds {
+                expected: self.amount,
+                actual: received,
+            })
+            .context("The lock address did not receive the Monero of the swap");
+        }
+
+        Ok(received)
+    }
+}
+
+fn digest(
+    swap_id: Uuid,
+    tx_lock_id: Txid,
+    address: &monero::Address,
+    amount: monero::Amount,
+    tx_hash: &TxHash,
+) -> SigHash {
+    let address = address.to_string();
+
+    let mut message = SIGNATURE_DOMAIN.to_vec();
+    message.extend_from_slice(swap_id.as_bytes());
+    message.extend_from_slice(&tx_lock_id.into_inner());
+    // The lengths keep the variable sized fields from blending into each
+    // other.
+    message.extend_from_slice(&(address.len() as u64).to_be_bytes());
+    message.extend_from_slice(address.as_bytes());
+    message.extend_from_slice(&amount.as_piconero().to_be_bytes());
+    message.extend_from_slice(&(tx_hash.0.len() as u64).to_be_bytes());
+    message.extend_from_slice(tx_hash.0.as_bytes());
+
+    SigHash::hash(&message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_roundtrips_through_json() {
+        let export = export(PrivateViewKey::new_random(&mut rand::thread_rng()));
+
+        assert_eq!(ViewKeyExport::from_json(&export.to_json()).unwrap(), export);
+    }
+
+    #[test]
+    fn view_key_must_belong_to_the_address() {
+        let view_key = PrivateViewKey::new_random(&mut rand::thread_rng());
+        let mut export = export(view_key);
+        assert!(export.check_view_key().is_ok());
+
+        export.view_key = PrivateViewKey::new_random(&mut rand::thread_rng());
+        assert!(export.check_view_key().is_err());
+    }
+
+    #[test]
+    fn export_must_be_signed_by_the_buyer() {
+        let export = export(PrivateViewKey::new_random(&mut rand::thread_rng()));
+        assert!(export.check_signature().is_ok());
+
+        let mut tampered = export.clone();
+        tampered.amount = monero::Amount::from_piconero(1);
+        assert!(tampered.check_signature().is_err());
+
+        let mut resigned = export.clone();
+        resigned.signature =
+            bitcoin::SecretKey::new_random(&mut rand::thread_rng()).sign(export.digest());
+        assert!(resigned.check_signature().is_err());
+    }
+
+    fn export(view_key: PrivateViewKey) -> ViewKeyExport {
+        let spend_key = monero::PublicKey::from_private_key(&monero::PrivateKey::from_scalar(
+            monero::Scalar::random(&mut rand::thread_rng()),
+        ));
+        let buyer = bitcoin::SecretKey::new_random(&mut rand::thread_rng());
+
+        let mut export = ViewKeyExport {
+            swap_id: Uuid::new_v4(),
+            address: monero::Address::standard(
+                monero::Network::Stagenet,
+                spend_key,
+                view_key.public().into(),
+            ),
+            view_key,
+            amount: monero::Amount::from_piconero(1_000_000_000_000),
+            tx_hash: TxHash(
+                "a2b3c4d5e4e1b1a1b3c3e4c1c0e1e3e6f2a7d4e5c1f7c7e0a2b8c1d4e4f1b9d4".to_owned(),
+            ),
+            tx_key: monero::PrivateKey::from_scalar(
+                monero::Scalar::random(&mut rand::thread_rng()),
+            ),
+            restore_height: 1_000_000,
+            tx_lock_id: Txid::from_inner([1; 32]),
+            buyer: buyer.public(),
+            signature: buyer.sign(SigHash::from_inner([0; 32])),
+        };
+        export.signature = buyer.sign(export.digest());
+
+        export
+    }
+}
@@ -0,0 +1,210 @@
+//! What the config files of the CLI and the ASB have in common.
+//!
+//! Both are TOML files using the same sections and keys for the settings they
+//! share, e.g. `[bitcoin] electrum_rpc_url` or `[tor] socks5_port`. Config
+//! files are validated as a whole when read, so all problems are reported at
+//! once instead of failing on the first one.
+
+use rust_decimal::Decimal;
+use std::fmt::Display;
+use url::Url;
+
+/// Fees are not estimated for targets beyond a week of blocks.
+const MAX_TARGET_BLOCK: usize = 1008;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Invalid config:\n{}", .problems.join("\n"))]
+pub struct Invalid {
+    pub problems: Vec<String>,
+}
+
+/// Collects the problems of a config, each prefixed with the key it concerns.
+#[derive(Debug, Default)]
+pub struct Validator {
+    problems: Vec<String>,
+}
+
+impl Validator {
+    pub fn check(&mut self, ok: bool, key: &str, problem: impl Display) {
+        if !ok {
+            self.problems.push(format!("{}: {}", key, problem));
+        }
+    }
+
+    pub fn electrum_rpc_urls(&mut self, key: &str, urls: &[Url]) {
+        for url in urls {
+            self.check(
+                matches!(url.scheme(), "tcp" | "ssl")
+                    && url.host().is_some()
+                    && url.port().is_some(),
+                key,
+                format_args!(
+                    "{} is not of the form tcp://<host>:<port> or ssl://<host>:<port>",
+                    url
+                ),
+            );
+        }
+    }
+
+    pub fn daemon_addresses(&mut self, key: &str, addresses: &[String]) {
+        for address in addresses {
+            let valid = matches!(
+                address.rsplit_once(':'),
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok()
+            );
+
+            self.check(
+                valid,
+                key,
+                format_args!("{} is not of the form <host>:<port>", address),
+            );
+        }
+    }
+
+    pub fn target_block(&mut self, key: &str, target_block: usize) {
+        self.check(
+            (1..=MAX_TARGET_BLOCK).contains(&target_block),
+            key,
+            format_args!("must be between 1 and {} blocks", MAX_TARGET_BLOCK),
+        );
+    }
+
+    pub fn port(&mut self, key: &str, port: u16) {
+        self.check(port != 0, key, "0 is not a valid port");
+    }
+
+    /// For spreads and other settings relative to a price.
+    pub fn fraction(&mut self, key: &str, value: Decimal) {
+        self.check(
+            value >= Decimal::ZERO && value <= Decimal::ONE,
+            key,
+            format_args!("{} is not between 0 and 1, e.g. 0.02 for 2%", value),
+        );
+    }
+
+    pub fn finish(self) -> Result<(), Invalid> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Invalid {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+/// Puts comments in front of the sections and keys of a serialized config.
+///
+/// Comments are looked up by the name of the section, e.g. `bitcoin`, or the
+/// key prefixed with its section, e.g. `bitcoin.target_block`.
+pub fn commented(toml: &str, comments: &[(&str, &str)]) -> String {
+    let comment_of = |name: &str| {
+        comments
+            .iter()
+            .find(|(commented, _)| *commented == name)
+            .map(|(_, comment)| *comment)
+    };
+
+    let mut section = String::new();
+    let mut output = String::new();
+
+    for line in toml.lines() {
+        let trimmed = line.trim();
+
+        let name = if trimmed.starts_with('[') {
+            section = trimmed.trim_matches(|c| c == '[' || c == ']').to_owned();
+            Some(section.clone())
+        } else {
+            trimmed
+                .split_once('=')
+                .map(|(key, _)| match section.as_str() {
+                    "" => key.trim().to_owned(),
+                    section => format!("{}.{}", section, key.trim()),
+                })
+        };
+
+        if let Some(comment) = name.as_deref().and_then(comment_of) {
+            for comment_line in comment.lines() {
+                output.push_str("# ");
+                output.push_str(comment_line);
+                output.push('\n');
+            }
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    output
+}
+
+pub(crate) mod one_or_many {
+    use serde::{Deserialize, Deserializer};
+
+    /// Deserializes either a single value or a list of values.
+    ///
+    /// Earlier versions of the config only allowed a single rendezvous point
+    /// and Electrum server, we keep accepting that format.
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrMany<T> {
+            One(T),
+            Many(Vec<T>),
+        }
+
+        let values = match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        };
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_all_problems_at_once() {
+        let mut validator = Validator::default();
+        validator.electrum_rpc_urls("bitcoin.electrum_rpc_url", &[
+            Url::parse("ssl://electrum.blockstream.info:50002").unwrap(),
+            Url::parse("https://electrum.blockstream.info").unwrap(),
+        ]);
+        validator.daemon_addresses("monero.daemon_addresses", &[
+            "node.moneroworld.com:18089".to_owned(),
+            "node.moneroworld.com".to_owned(),
+        ]);
+        validator.target_block("bitcoin.target_block", 0);
+        validator.fraction("maker.ask_spread", Decimal::new(2, 2));
+
+        let problems = validator.finish().unwrap_err().problems;
+
+        assert_eq!(problems, vec![
+            "bitcoin.electrum_rpc_url: https://electrum.blockstream.info/ is not of the form tcp://<host>:<port> or ssl://<host>:<port>",
+            "monero.daemon_addresses: node.moneroworld.com is not of the form <host>:<port>",
+            "bitcoin.target_block: must be between 1 and 1008 blocks",
+        ]);
+    }
+
+    #[test]
+    fn comments_sections_and_keys() {
+        let toml = "[bitcoin]\ntarget_block = 3\n\n[tor]\nsocks5_port = 9050\n";
+
+        let commented = commented(toml, &[
+            ("bitcoin", "Bitcoin settings"),
+            ("tor.socks5_port", "The port of the local\nTor proxy"),
+            ("target_block", "Not a key of the top level"),
+        ]);
+
+        assert_eq!(
+            commented,
+            "# Bitcoin settings\n[bitcoin]\ntarget_block = 3\n\n[tor]\n# The port of the local\n# Tor proxy\nsocks5_port = 9050\n"
+        );
+    }
+}
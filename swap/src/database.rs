@@ -1,18 +1,32 @@
-pub use alice::Alice;
+pub use alice::{Alice, AliceEndState};
 pub use bob::Bob;
-
+pub use export::{SwapAlreadyExists, SwapExport};
+pub use migration::{backup, rollback, Migration, SchemaTooNew, SCHEMA_VERSION};
+
+use crate::asb::accounting::{SwapAccounting, SwapAmounts};
+use crate::asb::reputation::{Misbehavior, PeerStats};
+use crate::cli::view_key::ViewKeyExport;
+use crate::encryption::{Cipher, KeyParams, Passphrase};
+use crate::protocol::alice::AliceState;
+use crate::protocol::bob::BobState;
+use crate::receipt::{self, SignedReceipt};
 use anyhow::{anyhow, bail, Context, Result};
 use itertools::Itertools;
 use libp2p::{Multiaddr, PeerId};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
 use std::fmt::Display;
+use std::fs;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::SystemTime;
 use uuid::Uuid;
 
 mod alice;
 mod bob;
+mod export;
+mod migration;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum Swap {
@@ -49,6 +63,10 @@ struct NotAlice;
 #[error("Not in the role of Bob")]
 struct NotBob;
 
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Swap with id {0} not found in database")]
+pub struct SwapNotFound(pub Uuid);
+
 impl Swap {
     pub fn try_into_alice(self) -> Result<Alice> {
         match self {
@@ -63,17 +81,213 @@ impl Swap {
             Swap::Alice(_) => bail!(NotBob),
         }
     }
+
+    /// The name of the state and the amounts, fees and transaction ids that
+    /// are relevant for it.
+    fn describe(self) -> (String, String) {
+        match self {
+            Swap::Alice(alice) => {
+                let state = AliceState::from(alice);
+                (state.to_string(), state.details())
+            }
+            Swap::Bob(bob) => {
+                let state = BobState::from(bob);
+                (state.to_string(), state.details())
+            }
+        }
+    }
+}
+
+/// A state a swap transitioned into, as recorded in the swap's history.
+///
+/// Only the name of the state and its public details are recorded, never the
+/// keys the state holds.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct StateTransition {
+    pub timestamp: SystemTime,
+    /// The name of the state, e.g. `btc is locked`.
+    pub state: String,
+    /// Amounts, fees and transaction ids that are relevant for the state.
+    pub details: String,
+}
+
+impl StateTransition {
+    pub fn new(timestamp: SystemTime, state: Swap) -> Self {
+        let (state, details) = state.describe();
+
+        Self {
+            timestamp,
+            state,
+            details,
+        }
+    }
 }
 
+/// Encrypting the database with a passphrase stores the parameters of the key
+/// derivation and a value encrypted with the key under these keys.
+const KEY_PARAMS: &[u8] = b"key_params";
+const KEY_CHECK: &[u8] = b"key_check";
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("The database is encrypted, a passphrase is required to open it")]
+pub struct DatabaseEncrypted;
+
+#[derive(Clone)]
 pub struct Database {
     swaps: sled::Tree,
     peers: sled::Tree,
     addresses: sled::Tree,
     monero_addresses: sled::Tree,
+    history: sled::Tree,
+    reputation: sled::Tree,
+    receipts: sled::Tree,
+    receipt_terms: sled::Tree,
+    view_keys: sled::Tree,
+    accounting: sled::Tree,
+    encryption: sled::Tree,
+    /// Stored in plaintext like the encryption parameters.
+    meta: sled::Tree,
+    /// Encrypts all values if the database is encrypted, keys are stored in
+    /// plaintext.
+    cipher: Option<Cipher>,
 }
 
 impl Database {
+    /// Opens a database that is not encrypted.
+    ///
+    /// Fails with [`DatabaseEncrypted`] if the database was encrypted with a
+    /// passphrase.
     pub fn open(path: &Path) -> Result<Self> {
+        let db = Self::open_trees(path)?;
+
+        if db.encryption.contains_key(KEY_CHECK)? {
+            bail!(DatabaseEncrypted)
+        }
+        db.check_schema_version()?;
+
+        Ok(db)
+    }
+
+    /// Opens a database encrypted with the passphrase.
+    ///
+    /// A database that is not encrypted yet is copied into a new encrypted
+    /// database that replaces it, as the files of the old one still contain
+    /// the plaintext values after they were overwritten. Its backup is
+    /// deleted as well. Fails if the database was encrypted with a different
+    /// passphrase.
+    pub fn open_encrypted(path: &Path, passphrase: &Passphrase) -> Result<Self> {
+        Self::replace_plaintext(path)?;
+
+        if path.exists() {
+            let plaintext = Self::open_trees(path)?;
+
+            if !plaintext.encryption.contains_key(KEY_CHECK)? {
+                plaintext.copy_encrypted(&migration::sibling(path, "encrypted"), passphrase)?;
+                drop(plaintext);
+
+                Self::replace_plaintext(path)?;
+
+                tracing::info!("Encrypted database at {}", path.display());
+            }
+        }
+
+        let mut db = Self::open_trees(path)?;
+        db.cipher = Some(db.cipher(passphrase)?);
+        db.check_schema_version()?;
+
+        Ok(db)
+    }
+
+    /// Derives the key from the passphrase, setting up the encryption of a
+    /// new database.
+    fn cipher(&self, passphrase: &Passphrase) -> Result<Cipher> {
+        let key_params = match self.encryption.get(KEY_PARAMS)? {
+            Some(encoded) => KeyParams::from_bytes(
+                encoded
+                    .as_ref()
+                    .try_into()
+                    .context("Invalid key parameters in database")?,
+            ),
+            None => {
+                let key_params = KeyParams::random();
+                self.encryption
+                    .insert(KEY_PARAMS, &key_params.to_bytes()[..])?;
+
+                key_params
+            }
+        };
+        let cipher = key_params.derive(passphrase);
+
+        match self.encryption.get(KEY_CHECK)? {
+            Some(check) => {
+                cipher
+                    .decrypt(&check)
+                    .context("Failed to open the database, is the passphrase correct?")?;
+            }
+            None => {
+                self.encryption
+                    .insert(KEY_CHECK, cipher.encrypt(KEY_CHECK)?)?;
+                self.encryption.flush()?;
+            }
+        }
+
+        Ok(cipher)
+    }
+
+    /// Replaces the plaintext database with its encrypted copy, finishing an
+    /// encryption that was interrupted.
+    ///
+    /// The plaintext database is moved aside before the copy takes its place
+    /// and only deleted afterwards, so one of them is always at `path`. A copy
+    /// without the key check was interrupted and is deleted.
+    fn replace_plaintext(path: &Path) -> Result<()> {
+        let encrypted_path = migration::sibling(path, "encrypted");
+        let plaintext_path = migration::sibling(path, "plaintext");
+
+        if encrypted_path.exists() {
+            if Self::is_encrypted(&encrypted_path) {
+                if path.exists() {
+                    fs::rename(path, &plaintext_path)?;
+                }
+                fs::rename(&encrypted_path, path)?;
+            } else {
+                fs::remove_dir_all(&encrypted_path).with_context(|| {
+                    format!(
+                        "Failed to delete incomplete encrypted database {}",
+                        encrypted_path.display()
+                    )
+                })?;
+            }
+        }
+
+        if plaintext_path.exists() {
+            let backup = migration::sibling(path, "backup");
+            if backup.exists() {
+                fs::remove_dir_all(&backup).with_context(|| {
+                    format!("Failed to delete plaintext backup {}", backup.display())
+                })?;
+            }
+
+            fs::remove_dir_all(&plaintext_path).with_context(|| {
+                format!(
+                    "Failed to delete plaintext database {}",
+                    plaintext_path.display()
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the database at `path` was completely encrypted, a database
+    /// that cannot be opened was not.
+    fn is_encrypted(path: &Path) -> bool {
+        Self::open_trees(path)
+            .and_then(|db| Ok(db.encryption.contains_key(KEY_CHECK)?))
+            .unwrap_or(false)
+    }
+
+    fn open_trees(path: &Path) -> Result<Self> {
         tracing::debug!("Opening database at {}", path.display());
 
         let db =
@@ -83,20 +297,128 @@ impl Database {
         let peers = db.open_tree("peers")?;
         let addresses = db.open_tree("addresses")?;
         let monero_addresses = db.open_tree("monero_addresses")?;
+        let history = db.open_tree("history")?;
+        let reputation = db.open_tree("reputation")?;
+        let receipts = db.open_tree("receipts")?;
+        let receipt_terms = db.open_tree("receipt_terms")?;
+        let view_keys = db.open_tree("view_keys")?;
+        let accounting = db.open_tree("accounting")?;
+        let encryption = db.open_tree("encryption")?;
+        let meta = db.open_tree("meta")?;
 
         Ok(Database {
             swaps,
             peers,
             addresses,
             monero_addresses,
+            history,
+            reputation,
+            receipts,
+            receipt_terms,
+            view_keys,
+            accounting,
+            encryption,
+            meta,
+            cipher: None,
         })
     }
 
+    /// Copies all values into a new database at `path`, encrypted with the
+    /// passphrase.
+    ///
+    /// Only the values are encrypted, the schema version in `meta` is copied
+    /// as is. The key check is written last, once everything else is flushed,
+    /// and marks the copy as complete.
+    fn copy_encrypted(&self, path: &Path, passphrase: &Passphrase) -> Result<()> {
+        let encrypted = Self::open_trees(path)?;
+        let key_params = KeyParams::random();
+        encrypted
+            .encryption
+            .insert(KEY_PARAMS, &key_params.to_bytes()[..])?;
+        let cipher = key_params.derive(passphrase);
+
+        for ((name, from), (_, to)) in self.values().iter().zip(encrypted.values().iter()) {
+            let mut count = 0;
+
+            for item in from.iter() {
+                let (key, value) = item?;
+                to.insert(key, cipher.encrypt(&value)?)?;
+                count += 1;
+            }
+
+            tracing::info!(%count, "Encrypted {} in database", name);
+        }
+
+        for item in self.meta.iter() {
+            let (key, value) = item?;
+            encrypted.meta.insert(key, value)?;
+        }
+
+        encrypted
+            .meta
+            .flush()
+            .context("Failed to flush encrypted database")?;
+
+        encrypted
+            .encryption
+            .insert(KEY_CHECK, cipher.encrypt(KEY_CHECK)?)?;
+        encrypted
+            .encryption
+            .flush()
+            .context("Failed to flush encrypted database")?;
+
+        Ok(())
+    }
+
+    /// The trees whose values are encrypted.
+    fn values(&self) -> [(&'static str, &sled::Tree); 10] {
+        [
+            ("swaps", &self.swaps),
+            ("peers", &self.peers),
+            ("addresses", &self.addresses),
+            ("monero_addresses", &self.monero_addresses),
+            ("history", &self.history),
+            ("reputation", &self.reputation),
+            ("receipts", &self.receipts),
+            ("receipt_terms", &self.receipt_terms),
+            ("view_keys", &self.view_keys),
+            ("accounting", &self.accounting),
+        ]
+    }
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        let encoded = serialize(value)?;
+
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&encoded),
+            None => Ok(encoded),
+        }
+    }
+
+    fn decode<T>(&self, value: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        deserialize(&self.decrypt(value)?)
+    }
+
+    fn decrypt(&self, value: &[u8]) -> Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(value),
+            None => Ok(value.to_vec()),
+        }
+    }
+
     pub async fn insert_peer_id(&self, swap_id: Uuid, peer_id: PeerId) -> Result<()> {
         let peer_id_str = peer_id.to_string();
 
         let key = serialize(&swap_id)?;
-        let value = serialize(&peer_id_str).context("Could not serialize peer-id")?;
+        let value = self
+            .encode(&peer_id_str)
+            .context("Could not serialize peer-id")?;
 
         self.peers.insert(key, value)?;
 
@@ -115,7 +437,9 @@ impl Database {
             .get(&key)?
             .ok_or_else(|| anyhow!("No peer-id found for swap id {} in database", swap_id))?;
 
-        let peer_id: String = deserialize(&encoded).context("Could not deserialize peer-id")?;
+        let peer_id: String = self
+            .decode(&encoded)
+            .context("Could not deserialize peer-id")?;
         Ok(PeerId::from_str(peer_id.as_str())?)
     }
 
@@ -125,7 +449,7 @@ impl Database {
         address: monero::Address,
     ) -> Result<()> {
         let key = swap_id.as_bytes();
-        let value = serialize(&address)?;
+        let value = self.encode(&address)?;
 
         self.monero_addresses.insert(key, value)?;
 
@@ -147,11 +471,133 @@ impl Database {
                 )
             })?;
 
-        let monero_address = deserialize(&encoded)?;
+        let monero_address = self.decode(&encoded)?;
 
         Ok(monero_address)
     }
 
+    /// Stores the receipt of a completed swap, replacing an earlier one.
+    pub async fn insert_receipt(&self, swap_id: Uuid, receipt: &SignedReceipt) -> Result<()> {
+        let value = self.encode(receipt)?;
+
+        self.receipts.insert(swap_id.as_bytes(), value)?;
+
+        self.receipts
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    pub fn get_receipt(&self, swap_id: Uuid) -> Result<SignedReceipt> {
+        let encoded = self
+            .receipts
+            .get(swap_id.as_bytes())?
+            .ok_or_else(|| anyhow!("No receipt found for swap id {} in database", swap_id))?;
+
+        self.decode(&encoded)
+            .context("Could not deserialize receipt")
+    }
+
+    /// Records what we need to sign the receipt of the swap once it completed.
+    pub async fn insert_receipt_terms(&self, swap_id: Uuid, terms: &receipt::Terms) -> Result<()> {
+        self.receipt_terms
+            .insert(swap_id.as_bytes(), self.encode(terms)?)?;
+
+        self.receipt_terms
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    pub fn get_receipt_terms(&self, swap_id: Uuid) -> Result<receipt::Terms> {
+        let encoded = self
+            .receipt_terms
+            .get(swap_id.as_bytes())?
+            .ok_or_else(|| anyhow!("No receipt terms found for swap id {} in database", swap_id))?;
+
+        self.decode(&encoded)
+            .context("Could not deserialize receipt terms")
+    }
+
+    /// Records the view key of the Monero lock, so it can be exported after
+    /// the state of the swap no longer holds it.
+    pub async fn insert_view_key(&self, swap_id: Uuid, export: &ViewKeyExport) -> Result<()> {
+        self.view_keys
+            .insert(swap_id.as_bytes(), self.encode(export)?)?;
+
+        self.view_keys
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    pub fn get_view_key(&self, swap_id: Uuid) -> Result<Option<ViewKeyExport>> {
+        match self.view_keys.get(swap_id.as_bytes())? {
+            Some(encoded) => Ok(Some(
+                self.decode(&encoded)
+                    .context("Could not deserialize view key")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the amounts and fees the swap was set up with.
+    pub async fn record_swap_amounts(&self, swap_id: Uuid, amounts: SwapAmounts) -> Result<()> {
+        let mut accounting = self.get_accounting(swap_id)?;
+        accounting.amounts = Some(amounts);
+
+        self.insert_accounting(swap_id, accounting).await
+    }
+
+    /// Records the market price of 1 XMR when the swap was set up.
+    pub async fn record_market_price(
+        &self,
+        swap_id: Uuid,
+        market_price: bitcoin::Amount,
+    ) -> Result<()> {
+        let mut accounting = self.get_accounting(swap_id)?;
+        accounting.market_price = Some(market_price);
+
+        self.insert_accounting(swap_id, accounting).await
+    }
+
+    /// Records the fee we paid for the Monero lock transaction.
+    pub async fn record_xmr_lock_fee(
+        &self,
+        swap_id: Uuid,
+        fee: crate::monero::Amount,
+    ) -> Result<()> {
+        let mut accounting = self.get_accounting(swap_id)?;
+        accounting.xmr_lock_fee = Some(fee);
+
+        self.insert_accounting(swap_id, accounting).await
+    }
+
+    pub fn get_accounting(&self, swap_id: Uuid) -> Result<SwapAccounting> {
+        let accounting = match self.accounting.get(swap_id.as_bytes())? {
+            Some(encoded) => self
+                .decode(&encoded)
+                .context("Failed to deserialize accounting data")?,
+            None => SwapAccounting::default(),
+        };
+
+        Ok(accounting)
+    }
+
+    async fn insert_accounting(&self, swap_id: Uuid, accounting: SwapAccounting) -> Result<()> {
+        self.accounting
+            .insert(swap_id.as_bytes(), self.encode(&accounting)?)?;
+
+        self.accounting
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
     pub async fn insert_address(&self, peer_id: PeerId, address: Multiaddr) -> Result<()> {
         let key = peer_id.to_bytes();
 
@@ -162,12 +608,15 @@ impl Database {
 
             Some(match existing_addresses {
                 Some(encoded) => {
-                    let mut addresses = deserialize::<Vec<Multiaddr>>(&encoded)?;
+                    let mut addresses = self.decode::<Vec<Multiaddr>>(&encoded)?;
+                    if addresses.contains(&address) {
+                        return Ok(());
+                    }
                     addresses.push(address);
 
-                    serialize(&addresses)?
+                    self.encode(&addresses)?
                 }
-                None => serialize(&[address])?,
+                None => self.encode(&[address])?,
             })
         };
 
@@ -185,16 +634,80 @@ impl Database {
         let key = peer_id.to_bytes();
 
         let addresses = match self.addresses.get(&key)? {
-            Some(encoded) => deserialize(&encoded).context("Failed to deserialize addresses")?,
+            Some(encoded) => self
+                .decode(&encoded)
+                .context("Failed to deserialize addresses")?,
             None => vec![],
         };
 
         Ok(addresses)
     }
 
+    /// Records the misbehavior of the peer and returns the updated statistics.
+    pub async fn record_misbehavior(
+        &self,
+        peer_id: PeerId,
+        misbehavior: Misbehavior,
+    ) -> Result<PeerStats> {
+        let key = peer_id.to_bytes();
+
+        let mut stats = self.get_peer_stats(peer_id)?;
+        stats.record(misbehavior, SystemTime::now());
+
+        self.reputation.insert(key, self.encode(&stats)?)?;
+
+        self.reputation
+            .flush_async()
+            .await
+            .context("Could not flush db")?;
+
+        Ok(stats)
+    }
+
+    pub fn get_peer_stats(&self, peer_id: PeerId) -> Result<PeerStats> {
+        let stats = match self.reputation.get(peer_id.to_bytes())? {
+            Some(encoded) => self
+                .decode(&encoded)
+                .context("Failed to deserialize peer stats")?,
+            None => PeerStats::default(),
+        };
+
+        Ok(stats)
+    }
+
+    /// Returns the statistics of all peers that misbehaved.
+    pub fn all_peer_stats(&self) -> Result<Vec<(PeerId, PeerStats)>> {
+        self.reputation
+            .iter()
+            .map(|item| {
+                let (key, value) = item.context("Failed to retrieve peer stats from DB")?;
+
+                let peer_id = PeerId::from_bytes(&key).context("Failed to decode peer-id")?;
+                let stats = self
+                    .decode(&value)
+                    .context("Failed to deserialize peer stats")?;
+
+                Ok((peer_id, stats))
+            })
+            .collect()
+    }
+
+    /// Forgets the misbehavior of the peer, lifting its ban.
+    pub async fn clear_peer_stats(&self, peer_id: PeerId) -> Result<()> {
+        self.reputation.remove(peer_id.to_bytes())?;
+
+        self.reputation
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
     pub async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()> {
         let key = serialize(&swap_id)?;
-        let new_value = serialize(&state).context("Could not serialize new state value")?;
+        let new_value = self
+            .encode(&state)
+            .context("Could not serialize new state value")?;
 
         let old_value = self.swaps.get(&key)?;
 
@@ -203,22 +716,82 @@ impl Database {
             .context("Could not write in the DB")?
             .context("Stored swap somehow changed, aborting saving")?;
 
+        self.append_to_history(swap_id, state)?;
+
         self.swaps
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")?;
+        self.history
             .flush_async()
             .await
             .map(|_| ())
             .context("Could not flush db")
     }
 
+    /// Returns all states the swap transitioned into, oldest first.
+    ///
+    /// Swaps started with a version that did not record the history yet only
+    /// have the transitions that happened after upgrading.
+    pub fn get_history(&self, swap_id: Uuid) -> Result<Vec<StateTransition>> {
+        self.history
+            .scan_prefix(swap_id.as_bytes())
+            .values()
+            .map(|value| {
+                let value = value.context("Failed to retrieve state transition from DB")?;
+                self.decode(&value)
+                    .context("Failed to deserialize state transition")
+            })
+            .collect()
+    }
+
+    /// Appends the state to the history of the swap.
+    ///
+    /// Entries are keyed by the swap id followed by a big-endian sequence
+    /// number, so that a prefix scan yields them in insertion order.
+    fn append_to_history(&self, swap_id: Uuid, state: Swap) -> Result<()> {
+        let key = self.next_history_key(swap_id)?;
+        let value = self.encode_history_entry(state)?;
+
+        self.history.insert(key, value)?;
+
+        Ok(())
+    }
+
+    fn next_history_key(&self, swap_id: Uuid) -> Result<Vec<u8>> {
+        let prefix = swap_id.as_bytes();
+
+        let sequence = match self.history.scan_prefix(prefix).keys().next_back() {
+            Some(key) => {
+                let key = key.context("Failed to retrieve state transition from DB")?;
+                let sequence: [u8; 8] = key[prefix.len()..]
+                    .try_into()
+                    .context("Invalid state transition key")?;
+
+                u64::from_be_bytes(sequence) + 1
+            }
+            None => 0,
+        };
+
+        let mut key = prefix.to_vec();
+        key.extend_from_slice(&sequence.to_be_bytes());
+
+        Ok(key)
+    }
+
+    fn encode_history_entry(&self, state: Swap) -> Result<Vec<u8>> {
+        self.encode(&StateTransition::new(SystemTime::now(), state))
+    }
+
     pub fn get_state(&self, swap_id: Uuid) -> Result<Swap> {
         let key = serialize(&swap_id)?;
 
-        let encoded = self
-            .swaps
-            .get(&key)?
-            .ok_or_else(|| anyhow!("Swap with id {} not found in database", swap_id))?;
+        let encoded = self.swaps.get(&key)?.ok_or(SwapNotFound(swap_id))?;
 
-        let state = deserialize(&encoded).context("Could not deserialize state")?;
+        let state = self
+            .decode(&encoded)
+            .context("Could not deserialize state")?;
         Ok(state)
     }
 
@@ -245,11 +818,15 @@ impl Database {
     }
 
     fn all_swaps_iter(&self) -> impl Iterator<Item = Result<(Uuid, Swap)>> {
-        self.swaps.iter().map(|item| {
+        let db = self.clone();
+
+        self.swaps.iter().map(move |item| {
             let (key, value) = item.context("Failed to retrieve swap from DB")?;
 
             let swap_id = deserialize::<Uuid>(&key)?;
-            let swap = deserialize::<Swap>(&value).context("Failed to deserialize swap")?;
+            let swap = db
+                .decode::<Swap>(&value)
+                .context("Failed to deserialize swap")?;
 
             Ok((swap_id, swap))
         })
@@ -453,6 +1030,7 @@ mod tests {
             let db = Database::open(db_dir.path())?;
             db.insert_address(peer_id, home1.clone()).await?;
             db.insert_address(peer_id, home2.clone()).await?;
+            db.insert_address(peer_id, home1.clone()).await?;
         }
 
         let addresses = Database::open(db_dir.path())?.get_addresses(peer_id)?;
@@ -462,6 +1040,33 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn records_history_of_state_transitions() -> Result<()> {
+        let db_dir = tempfile::tempdir()?;
+        let db = Database::open(db_dir.path())?;
+
+        let swap_id = Uuid::new_v4();
+        let other_swap_id = Uuid::new_v4();
+        let aborted = Swap::Bob(Bob::Done(BobEndState::SafelyAborted));
+        let redeemed = Swap::Alice(Alice::Done(AliceEndState::BtcRedeemed));
+
+        db.insert_latest_state(swap_id, aborted.clone()).await?;
+        db.insert_latest_state(other_swap_id, aborted).await?;
+        db.insert_latest_state(swap_id, redeemed).await?;
+
+        let history = db
+            .get_history(swap_id)?
+            .into_iter()
+            .map(|transition| transition.state)
+            .collect::<Vec<_>>();
+
+        assert_eq!(history, vec!["safely aborted", "btc is redeemed"]);
+        assert_eq!(db.get_history(other_swap_id)?.len(), 1);
+        assert!(db.get_history(Uuid::new_v4())?.is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn save_and_load_monero_address() -> Result<()> {
         let db_dir = tempfile::tempdir()?;
@@ -474,4 +1079,142 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn records_and_clears_misbehavior_of_peers() -> Result<()> {
+        let db_dir = tempfile::tempdir()?;
+        let db = Database::open(db_dir.path())?;
+        let peer_id = PeerId::random();
+
+        db.record_misbehavior(peer_id, Misbehavior::InvalidMessage)
+            .await?;
+        let stats = db
+            .record_misbehavior(peer_id, Misbehavior::InvalidMessage)
+            .await?;
+
+        assert_eq!(stats.invalid_messages, 2);
+        assert_eq!(db.all_peer_stats()?, vec![(peer_id, stats)]);
+
+        db.clear_peer_stats(peer_id).await?;
+
+        assert_eq!(db.get_peer_stats(peer_id)?, PeerStats::default());
+        assert!(db.all_peer_stats()?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn encrypts_plaintext_database_into_new_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("database");
+        let swap_id = Uuid::new_v4();
+        let state = Swap::Alice(Alice::Done(AliceEndState::BtcRedeemed));
+        let peer_id = PeerId::random();
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+
+        {
+            let db = Database::open(&path)?;
+            db.insert_latest_state(swap_id, state.clone()).await?;
+            db.insert_peer_id(swap_id, peer_id).await?;
+        }
+        migration::backup(&path)?;
+
+        {
+            let db = Database::open_encrypted(&path, &passphrase)?;
+            assert_eq!(db.get_state(swap_id)?, state);
+            assert_eq!(db.get_peer_id(swap_id)?, peer_id);
+        }
+
+        let mut entries = fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        assert_eq!(entries, vec!["database"]);
+        assert!(!contains_bytes(&path, peer_id.to_string().as_bytes())?);
+
+        let error = Database::open(&path).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<DatabaseEncrypted>(),
+            Some(&DatabaseEncrypted)
+        );
+
+        let wrong_passphrase = Passphrase::new("wrong".to_owned());
+        assert!(Database::open_encrypted(&path, &wrong_passphrase).is_err());
+
+        let db = Database::open_encrypted(&path, &passphrase)?;
+        assert_eq!(db.get_state(swap_id)?, state);
+        assert_eq!(db.get_history(swap_id)?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finishes_interrupted_encryption_after_every_step() -> Result<()> {
+        let swap_id = Uuid::new_v4();
+        let peer_id = PeerId::random();
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+
+        for step in 0..4 {
+            let dir = tempfile::tempdir()?;
+            let path = dir.path().join("database");
+            let encrypted_path = migration::sibling(&path, "encrypted");
+            let plaintext_path = migration::sibling(&path, "plaintext");
+
+            {
+                let db = Database::open(&path)?;
+                db.insert_peer_id(swap_id, peer_id).await?;
+                db.copy_encrypted(&encrypted_path, &passphrase)?;
+            }
+            migration::backup(&path)?;
+
+            match step {
+                // The copy was interrupted
+                0 => {
+                    let encrypted = Database::open_trees(&encrypted_path)?;
+                    encrypted.encryption.remove(KEY_CHECK)?;
+                    encrypted.encryption.flush()?;
+                }
+                // The copy is complete
+                1 => {}
+                // The plaintext database was moved aside
+                2 => fs::rename(&path, &plaintext_path)?,
+                // The copy replaced the plaintext database
+                _ => {
+                    fs::rename(&path, &plaintext_path)?;
+                    fs::rename(&encrypted_path, &path)?;
+                }
+            }
+
+            let db = Database::open_encrypted(&path, &passphrase)?;
+            assert_eq!(db.get_peer_id(swap_id)?, peer_id, "step {}", step);
+            drop(db);
+
+            let entries = fs::read_dir(dir.path())?
+                .map(|entry| Ok(entry?.file_name()))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(entries, vec!["database"], "step {}", step);
+            assert!(!contains_bytes(&path, peer_id.to_string().as_bytes())?);
+        }
+
+        Ok(())
+    }
+
+    fn contains_bytes(dir: &Path, needle: &[u8]) -> Result<bool> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+
+            let found = if entry.file_type()?.is_dir() {
+                contains_bytes(&entry.path(), needle)?
+            } else {
+                fs::read(entry.path())?
+                    .windows(needle.len())
+                    .any(|window| window == needle)
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
 }
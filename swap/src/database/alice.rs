@@ -21,6 +21,9 @@ pub enum Alice {
     BtcLocked {
         state3: alice::State3,
     },
+    BtcEarlyRefundable {
+        state3: alice::State3,
+    },
     XmrLockTransactionSent {
         monero_wallet_restore_blockheight: BlockHeight,
         transfer_proof: TransferProof,
@@ -76,6 +79,7 @@ pub enum AliceEndState {
     BtcRedeemed,
     XmrRefunded,
     BtcPunished,
+    BtcEarlyRefunded,
 }
 
 impl From<&AliceState> for Alice {
@@ -90,6 +94,9 @@ impl From<&AliceState> for Alice {
             AliceState::BtcLocked { state3 } => Alice::BtcLocked {
                 state3: state3.as_ref().clone(),
             },
+            AliceState::BtcEarlyRefundable { state3 } => Alice::BtcEarlyRefundable {
+                state3: state3.as_ref().clone(),
+            },
             AliceState::XmrLockTransactionSent {
                 monero_wallet_restore_blockheight,
                 transfer_proof,
@@ -175,6 +182,29 @@ impl From<&AliceState> for Alice {
             },
             AliceState::BtcPunished => Alice::Done(AliceEndState::BtcPunished),
             AliceState::SafelyAborted => Alice::Done(AliceEndState::SafelyAborted),
+            AliceState::BtcEarlyRefunded => Alice::Done(AliceEndState::BtcEarlyRefunded),
+        }
+    }
+}
+
+impl Alice {
+    /// The swap's parameters, unless the swap is finished.
+    pub fn state3(&self) -> Option<&alice::State3> {
+        match self {
+            Alice::Started { state3 }
+            | Alice::BtcLockTransactionSeen { state3 }
+            | Alice::BtcLocked { state3 }
+            | Alice::BtcEarlyRefundable { state3 }
+            | Alice::XmrLockTransactionSent { state3, .. }
+            | Alice::XmrLocked { state3, .. }
+            | Alice::XmrLockTransferProofSent { state3, .. }
+            | Alice::EncSigLearned { state3, .. }
+            | Alice::BtcRedeemTransactionPublished { state3 }
+            | Alice::CancelTimelockExpired { state3, .. }
+            | Alice::BtcCancelled { state3, .. }
+            | Alice::BtcPunishable { state3, .. }
+            | Alice::BtcRefunded { state3, .. } => Some(state3),
+            Alice::Done(_) => None,
         }
     }
 }
@@ -191,6 +221,9 @@ impl From<Alice> for AliceState {
             Alice::BtcLocked { state3 } => AliceState::BtcLocked {
                 state3: Box::new(state3),
             },
+            Alice::BtcEarlyRefundable { state3 } => AliceState::BtcEarlyRefundable {
+                state3: Box::new(state3),
+            },
             Alice::XmrLockTransactionSent {
                 monero_wallet_restore_blockheight,
                 transfer_proof,
@@ -278,6 +311,7 @@ impl From<Alice> for AliceState {
                 AliceEndState::BtcRedeemed => AliceState::BtcRedeemed,
                 AliceEndState::XmrRefunded => AliceState::XmrRefunded,
                 AliceEndState::BtcPunished => AliceState::BtcPunished,
+                AliceEndState::BtcEarlyRefunded => AliceState::BtcEarlyRefunded,
             },
         }
     }
@@ -291,6 +325,7 @@ impl fmt::Display for Alice {
                 write!(f, "Bitcoin lock transaction in mempool")
             }
             Alice::BtcLocked { .. } => f.write_str("Bitcoin locked"),
+            Alice::BtcEarlyRefundable { .. } => f.write_str("Bitcoin early refundable"),
             Alice::XmrLockTransactionSent { .. } => f.write_str("Monero lock transaction sent"),
             Alice::XmrLocked { .. } => f.write_str("Monero locked"),
             Alice::XmrLockTransferProofSent { .. } => {
@@ -1,4 +1,5 @@
-use crate::monero::TransferProof;
+use crate::monero::{self, TransferProof};
+use crate::network::swap_setup::SessionNonce;
 use crate::protocol::bob;
 use crate::protocol::bob::BobState;
 use monero_rpc::wallet::BlockHeight;
@@ -14,6 +15,9 @@ pub enum Bob {
         btc_amount: bitcoin::Amount,
         #[serde_as(as = "DisplayFromStr")]
         change_address: bitcoin::Address,
+        #[serde(default)]
+        min_monero: Option<monero::Amount>,
+        session_nonce: SessionNonce,
     },
     ExecutionSetupDone {
         state2: bob::State2,
@@ -35,6 +39,10 @@ pub enum Bob {
     BtcRedeemed(bob::State5),
     CancelTimelockExpired(bob::State6),
     BtcCancelled(bob::State6),
+    BtcEarlyRefundable {
+        state3: bob::State3,
+        tx_early_refund_sig: crate::bitcoin::Signature,
+    },
     Done(BobEndState),
 }
 
@@ -44,6 +52,7 @@ pub enum BobEndState {
     XmrRedeemed { tx_lock_id: bitcoin::Txid },
     BtcRefunded(Box<bob::State6>),
     BtcPunished { tx_lock_id: bitcoin::Txid },
+    BtcEarlyRefunded(Box<bob::State3>),
 }
 
 impl From<BobState> for Bob {
@@ -52,9 +61,13 @@ impl From<BobState> for Bob {
             BobState::Started {
                 btc_amount,
                 change_address,
+                min_monero,
+                session_nonce,
             } => Bob::Started {
                 btc_amount,
                 change_address,
+                min_monero,
+                session_nonce,
             },
             BobState::SwapSetupCompleted(state2) => Bob::ExecutionSetupDone { state2 },
             BobState::BtcLocked(state3) => Bob::BtcLocked { state3 },
@@ -73,6 +86,16 @@ impl From<BobState> for Bob {
             BobState::CancelTimelockExpired(state6) => Bob::CancelTimelockExpired(state6),
             BobState::BtcCancelled(state6) => Bob::BtcCancelled(state6),
             BobState::BtcRefunded(state6) => Bob::Done(BobEndState::BtcRefunded(Box::new(state6))),
+            BobState::BtcEarlyRefundable {
+                state,
+                tx_early_refund_sig,
+            } => Bob::BtcEarlyRefundable {
+                state3: state,
+                tx_early_refund_sig,
+            },
+            BobState::BtcEarlyRefunded(state3) => {
+                Bob::Done(BobEndState::BtcEarlyRefunded(Box::new(state3)))
+            }
             BobState::XmrRedeemed { tx_lock_id } => {
                 Bob::Done(BobEndState::XmrRedeemed { tx_lock_id })
             }
@@ -90,9 +113,13 @@ impl From<Bob> for BobState {
             Bob::Started {
                 btc_amount,
                 change_address,
+                min_monero,
+                session_nonce,
             } => BobState::Started {
                 btc_amount,
                 change_address,
+                min_monero,
+                session_nonce,
             },
             Bob::ExecutionSetupDone { state2 } => BobState::SwapSetupCompleted(state2),
             Bob::BtcLocked { state3 } => BobState::BtcLocked(state3),
@@ -110,11 +137,19 @@ impl From<Bob> for BobState {
             Bob::BtcRedeemed(state5) => BobState::BtcRedeemed(state5),
             Bob::CancelTimelockExpired(state6) => BobState::CancelTimelockExpired(state6),
             Bob::BtcCancelled(state6) => BobState::BtcCancelled(state6),
+            Bob::BtcEarlyRefundable {
+                state3,
+                tx_early_refund_sig,
+            } => BobState::BtcEarlyRefundable {
+                state: state3,
+                tx_early_refund_sig,
+            },
             Bob::Done(end_state) => match end_state {
                 BobEndState::SafelyAborted => BobState::SafelyAborted,
                 BobEndState::XmrRedeemed { tx_lock_id } => BobState::XmrRedeemed { tx_lock_id },
                 BobEndState::BtcRefunded(state6) => BobState::BtcRefunded(*state6),
                 BobEndState::BtcPunished { tx_lock_id } => BobState::BtcPunished { tx_lock_id },
+                BobEndState::BtcEarlyRefunded(state3) => BobState::BtcEarlyRefunded(*state3),
             },
         }
     }
@@ -132,6 +167,7 @@ impl fmt::Display for Bob {
             Bob::XmrLocked { .. } => f.write_str("Monero locked"),
             Bob::CancelTimelockExpired(_) => f.write_str("Cancel timelock is expired"),
             Bob::BtcCancelled(_) => f.write_str("Bitcoin refundable"),
+            Bob::BtcEarlyRefundable { .. } => f.write_str("Bitcoin early refundable"),
             Bob::BtcRedeemed(_) => f.write_str("Monero redeemable"),
             Bob::Done(end_state) => write!(f, "Done: {}", end_state),
             Bob::EncSigSent { .. } => f.write_str("Encrypted signature sent"),
@@ -0,0 +1,301 @@
+use crate::cli::view_key::ViewKeyExport;
+use crate::database::{deserialize, serialize, Database, Swap};
+use crate::encryption::Cipher;
+use crate::receipt;
+use anyhow::{anyhow, bail, Context, Result};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Version of the export file format, prepended to every export.
+const VERSION: u8 = 1;
+
+/// Everything the database knows about a single swap.
+///
+/// An export allows moving a swap to a different machine so that it can be
+/// resumed, cancelled or refunded there.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SwapExport {
+    pub swap_id: Uuid,
+    pub state: Swap,
+    peer_id: Option<String>,
+    peer_addresses: Vec<Multiaddr>,
+    monero_address: Option<monero::Address>,
+    #[serde(default)]
+    receipt_terms: Option<receipt::Terms>,
+    #[serde(default)]
+    view_key: Option<ViewKeyExport>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Swap {0} already exists in the database")]
+pub struct SwapAlreadyExists(pub Uuid);
+
+impl SwapExport {
+    /// Encrypts the export with the given key.
+    ///
+    /// The result is laid out as `version | nonce | ciphertext`.
+    pub fn encrypt(&self, key: &[u8; 32]) -> Result<Vec<u8>> {
+        let plaintext = serialize(self)?;
+
+        let ciphertext = Cipher::new(key)
+            .encrypt(&plaintext)
+            .context("Failed to encrypt swap export")?;
+
+        let mut bytes = Vec::with_capacity(1 + ciphertext.len());
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&ciphertext);
+
+        Ok(bytes)
+    }
+
+    /// Decrypts an export previously created with [`SwapExport::encrypt`].
+    ///
+    /// Fails if the file was tampered with or encrypted with a different key.
+    pub fn decrypt(bytes: &[u8], key: &[u8; 32]) -> Result<Self> {
+        let (version, rest) = bytes.split_first().context("Swap export file is empty")?;
+
+        if *version != VERSION {
+            bail!("Unsupported swap export version {}", version)
+        }
+
+        let plaintext = Cipher::new(key)
+            .decrypt(rest)
+            .context("Failed to decrypt swap export, was it created with a different seed?")?;
+
+        deserialize(&plaintext).context("Failed to deserialize swap export")
+    }
+}
+
+impl Database {
+    pub fn export_swap(&self, swap_id: Uuid) -> Result<SwapExport> {
+        let state = self.get_state(swap_id)?;
+
+        let peer_id = match self.peers.get(serialize(&swap_id)?)? {
+            Some(encoded) => Some(
+                self.decode::<String>(&encoded)
+                    .context("Could not deserialize peer-id")?,
+            ),
+            None => None,
+        };
+
+        let peer_addresses = match &peer_id {
+            Some(peer_id) => self.get_addresses(PeerId::from_str(peer_id)?)?,
+            None => vec![],
+        };
+
+        let monero_address = match self.monero_addresses.get(swap_id.as_bytes())? {
+            Some(encoded) => Some(self.decode(&encoded)?),
+            None => None,
+        };
+
+        let receipt_terms = match self.receipt_terms.get(swap_id.as_bytes())? {
+            Some(encoded) => Some(self.decode(&encoded)?),
+            None => None,
+        };
+
+        Ok(SwapExport {
+            swap_id,
+            state,
+            peer_id,
+            peer_addresses,
+            monero_address,
+            receipt_terms,
+            view_key: self.get_view_key(swap_id)?,
+        })
+    }
+
+    /// Inserts all data of an exported swap.
+    ///
+    /// Everything is written in a single transaction, so that a failed import
+    /// does not leave a partial swap behind. Refuses to overwrite a swap that
+    /// is already present in the database.
+    pub async fn import_swap(&self, export: SwapExport) -> Result<()> {
+        let swap_id = export.swap_id;
+        let swap_key = serialize(&swap_id)?;
+
+        let state = self
+            .encode(&export.state)
+            .context("Could not serialize new state value")?;
+        let history_key = self.next_history_key(swap_id)?;
+        let history_entry = self.encode_history_entry(export.state)?;
+
+        let peer = match export.peer_id {
+            Some(peer_id) => {
+                let peer_id =
+                    PeerId::from_str(&peer_id).context("Invalid peer-id in swap export")?;
+                let encoded = self
+                    .encode(&peer_id.to_string())
+                    .context("Could not serialize peer-id")?;
+
+                Some((peer_id, encoded))
+            }
+            None => None,
+        };
+
+        let monero_address = match export.monero_address {
+            Some(address) => Some(self.encode(&address)?),
+            None => None,
+        };
+
+        let terms = match export.receipt_terms {
+            Some(terms) => Some(self.encode(&terms)?),
+            None => None,
+        };
+
+        let view_key = match export.view_key {
+            Some(view_key) => Some(self.encode(&view_key)?),
+            None => None,
+        };
+
+        (
+            &self.swaps,
+            &self.history,
+            &self.peers,
+            &self.addresses,
+            &self.monero_addresses,
+            &self.receipt_terms,
+            &self.view_keys,
+        )
+            .transaction(
+                |(swaps, history, peers, addresses, monero_addresses, receipt_terms, view_keys)| {
+                    if swaps.get(&swap_key)?.is_some() {
+                        return Err(ConflictableTransactionError::Abort(anyhow!(
+                            SwapAlreadyExists(swap_id)
+                        )));
+                    }
+
+                    swaps.insert(swap_key.as_slice(), state.as_slice())?;
+                    history.insert(history_key.as_slice(), history_entry.as_slice())?;
+
+                    if let Some((peer_id, encoded)) = &peer {
+                        peers.insert(swap_key.as_slice(), encoded.as_slice())?;
+
+                        let key = peer_id.to_bytes();
+                        let mut known_addresses = match addresses.get(&key)? {
+                            Some(encoded) => self
+                                .decode::<Vec<Multiaddr>>(&encoded)
+                                .map_err(ConflictableTransactionError::Abort)?,
+                            None => vec![],
+                        };
+                        for address in &export.peer_addresses {
+                            if !known_addresses.contains(address) {
+                                known_addresses.push(address.clone());
+                            }
+                        }
+                        let encoded = self
+                            .encode(&known_addresses)
+                            .map_err(ConflictableTransactionError::Abort)?;
+                        addresses.insert(key, encoded)?;
+                    }
+
+                    if let Some(encoded) = &monero_address {
+                        monero_addresses.insert(&swap_id.as_bytes()[..], encoded.as_slice())?;
+                    }
+
+                    if let Some(encoded) = &terms {
+                        receipt_terms.insert(&swap_id.as_bytes()[..], encoded.as_slice())?;
+                    }
+
+                    if let Some(encoded) = &view_key {
+                        view_keys.insert(&swap_id.as_bytes()[..], encoded.as_slice())?;
+                    }
+
+                    Ok(())
+                },
+            )
+            .map_err(|error| match error {
+                TransactionError::Abort(error) => error,
+                TransactionError::Storage(error) => anyhow!(error),
+            })?;
+
+        self.swaps
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::bob::{Bob, BobEndState};
+
+    const KEY: [u8; 32] = [1u8; 32];
+
+    #[tokio::test]
+    async fn can_export_and_import_swap() -> Result<()> {
+        let swap_id = Uuid::new_v4();
+        let peer_id = PeerId::random();
+        let address = "/ip4/127.0.0.1/tcp/1".parse::<Multiaddr>()?;
+        let monero_address = "53gEuGZUhP9JMEBZoGaFNzhwEgiG7hwQdMCqFxiyiTeFPmkbt1mAoNybEUvYBKHcnrSgxnVWgZsTvRBaHBNXPa8tHiCU51a".parse::<monero::Address>()?;
+        let state = Swap::Bob(Bob::Done(BobEndState::SafelyAborted));
+
+        let source_dir = tempfile::tempdir()?;
+        let source = Database::open(source_dir.path())?;
+        source.insert_latest_state(swap_id, state.clone()).await?;
+        source.insert_peer_id(swap_id, peer_id).await?;
+        source.insert_address(peer_id, address.clone()).await?;
+        source
+            .insert_monero_address(swap_id, monero_address)
+            .await?;
+
+        let file = source.export_swap(swap_id)?.encrypt(&KEY)?;
+
+        let target_dir = tempfile::tempdir()?;
+        let target = Database::open(target_dir.path())?;
+        target
+            .import_swap(SwapExport::decrypt(&file, &KEY)?)
+            .await?;
+
+        assert_eq!(target.get_state(swap_id)?, state);
+        assert_eq!(target.get_peer_id(swap_id)?, peer_id);
+        assert_eq!(target.get_addresses(peer_id)?, vec![address]);
+        assert_eq!(target.get_monero_address(swap_id)?, monero_address);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_does_not_overwrite_existing_swap() -> Result<()> {
+        let swap_id = Uuid::new_v4();
+        let state = Swap::Bob(Bob::Done(BobEndState::SafelyAborted));
+
+        let db_dir = tempfile::tempdir()?;
+        let db = Database::open(db_dir.path())?;
+        db.insert_latest_state(swap_id, state).await?;
+
+        let export = db.export_swap(swap_id)?;
+        let error = db.import_swap(export).await.unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<SwapAlreadyExists>(),
+            Some(&SwapAlreadyExists(swap_id))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn decrypting_with_wrong_key_fails() -> Result<()> {
+        let export = SwapExport {
+            swap_id: Uuid::new_v4(),
+            state: Swap::Bob(Bob::Done(BobEndState::SafelyAborted)),
+            peer_id: None,
+            peer_addresses: vec![],
+            monero_address: None,
+            receipt_terms: None,
+            view_key: None,
+        };
+
+        let file = export.encrypt(&KEY)?;
+
+        assert_eq!(SwapExport::decrypt(&file, &KEY)?, export);
+        assert!(SwapExport::decrypt(&file, &[2u8; 32]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+//! Versioned migrations of the database schema.
+//!
+//! The schema version is stored in the `meta` tree, databases written before
+//! the version was recorded are at version 0. Every migration records the
+//! version once it is applied, so an interrupted migration can be resumed.
+
+use crate::cli::view_key::ViewKeyExport;
+use crate::database::{deserialize, serialize, Alice, Bob, Database, StateTransition, Swap};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// The schema version of databases written by this version.
+pub const SCHEMA_VERSION: u32 = 3;
+
+const KEY_SCHEMA_VERSION: &[u8] = b"schema_version";
+
+pub struct Migration {
+    /// The schema version after applying the migration.
+    pub version: u32,
+    pub description: &'static str,
+    /// Returns the number of changed entries, only counting them on a dry run.
+    apply: fn(&Database, bool) -> Result<usize>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Record the latest state of swaps started before the history was recorded",
+        apply: record_missing_history,
+    },
+    Migration {
+        version: 2,
+        description: "Re-encode swap states in the current format",
+        apply: reencode_states,
+    },
+    Migration {
+        version: 3,
+        description: "Strip the keys of the swaps from the history",
+        apply: strip_history,
+    },
+];
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error(
+    "The database has schema version {found}, but this version only supports up to version {supported}, please upgrade",
+    supported = SCHEMA_VERSION
+)]
+pub struct SchemaTooNew {
+    pub found: u32,
+}
+
+impl Database {
+    pub fn schema_version(&self) -> Result<u32> {
+        match self.meta.get(KEY_SCHEMA_VERSION)? {
+            Some(version) => Ok(u32::from_be_bytes(
+                version
+                    .as_ref()
+                    .try_into()
+                    .context("Invalid schema version in database")?,
+            )),
+            None => Ok(0),
+        }
+    }
+
+    fn set_schema_version(&self, version: u32) -> Result<()> {
+        self.meta
+            .insert(KEY_SCHEMA_VERSION, &version.to_be_bytes()[..])?;
+        self.meta.flush()?;
+
+        Ok(())
+    }
+
+    /// Records the current schema version in new databases and refuses
+    /// databases written by a newer version.
+    pub(super) fn check_schema_version(&self) -> Result<()> {
+        if !self.meta.contains_key(KEY_SCHEMA_VERSION)? && self.swaps.is_empty() {
+            return self.set_schema_version(SCHEMA_VERSION);
+        }
+
+        let found = self.schema_version()?;
+        if found > SCHEMA_VERSION {
+            bail!(SchemaTooNew { found })
+        }
+
+        Ok(())
+    }
+
+    pub fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        let version = self.schema_version()?;
+
+        Ok(MIGRATIONS
+            .iter()
+            .filter(|migration| migration.version > version)
+            .collect())
+    }
+
+    /// Applies the pending migrations in order, returning them with the
+    /// number of entries each of them changed.
+    ///
+    /// On a dry run nothing is written.
+    pub fn migrate(&self, dry_run: bool) -> Result<Vec<(&'static Migration, usize)>> {
+        self.pending_migrations()?
+            .into_iter()
+            .map(|migration| {
+                let changes = (migration.apply)(self, dry_run).with_context(|| {
+                    format!("Failed to migrate to schema version {}", migration.version)
+                })?;
+
+                if !dry_run {
+                    self.set_schema_version(migration.version)?;
+                }
+
+                Ok((migration, changes))
+            })
+            .collect()
+    }
+}
+
+/// Copies the database directory next to it, replacing an earlier backup.
+///
+/// The database must not be open.
+pub fn backup(path: &Path) -> Result<PathBuf> {
+    let backup = sibling(path, "backup");
+
+    if backup.exists() {
+        fs::remove_dir_all(&backup)
+            .with_context(|| format!("Failed to remove old backup {}", backup.display()))?;
+    }
+    copy_dir(path, &backup)
+        .with_context(|| format!("Failed to back up database to {}", backup.display()))?;
+
+    Ok(backup)
+}
+
+/// Replaces the database with the backup taken before the last migration,
+/// returning where the replaced database was moved to.
+///
+/// The database must not be open.
+pub fn rollback(path: &Path) -> Result<PathBuf> {
+    let backup = sibling(path, "backup");
+    let replaced = sibling(path, "rolled-back");
+
+    if !backup.exists() {
+        bail!("No database backup found at {}", backup.display())
+    }
+    if replaced.exists() {
+        bail!(
+            "{} is in the way of the rolled back database, please move it elsewhere",
+            replaced.display()
+        )
+    }
+
+    fs::rename(path, &replaced)
+        .with_context(|| format!("Failed to move database to {}", replaced.display()))?;
+    fs::rename(&backup, path)
+        .with_context(|| format!("Failed to restore backup {}", backup.display()))?;
+
+    Ok(replaced)
+}
+
+pub(super) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", suffix));
+
+    path.with_file_name(name)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The recorded transition carries the time of the migration.
+fn record_missing_history(db: &Database, dry_run: bool) -> Result<usize> {
+    let mut changes = 0;
+
+    for item in db.all_swaps_iter() {
+        let (swap_id, swap) = item?;
+
+        if db.history.scan_prefix(swap_id.as_bytes()).next().is_none() {
+            if !dry_run {
+                db.append_to_history(swap_id, swap)?;
+            }
+            changes += 1;
+        }
+    }
+
+    db.history.flush()?;
+
+    Ok(changes)
+}
+
+/// Writes fields added with a default value since a state was stored.
+///
+/// Fails if a state cannot be read by this version, which leaves it
+/// untouched.
+fn reencode_states(db: &Database, dry_run: bool) -> Result<usize> {
+    let mut changes = 0;
+
+    for item in db.swaps.iter() {
+        let (key, value) = item.context("Failed to retrieve swap from DB")?;
+        let swap_id = deserialize::<uuid::Uuid>(&key)?;
+
+        let plaintext = db.decrypt(&value)?;
+        let swap = deserialize::<Swap>(&plaintext)
+            .with_context(|| format!("Failed to deserialize swap {}", swap_id))?;
+
+        if serialize(&swap)? != plaintext {
+            if !dry_run {
+                db.swaps.insert(key, db.encode(&swap)?)?;
+            }
+            changes += 1;
+        }
+    }
+
+    db.swaps.flush()?;
+
+    Ok(changes)
+}
+
+/// A state transition as recorded before version 3, holding the whole state.
+#[derive(Deserialize)]
+struct LegacyStateTransition {
+    timestamp: SystemTime,
+    state: Swap,
+}
+
+/// Replaces the states in the history with their name and details.
+///
+/// What later steps of a swap took from the history is recorded separately
+/// first: the amounts of Alice's swaps, the terms of the receipt and the view
+/// key of the Monero lock.
+fn strip_history(db: &Database, dry_run: bool) -> Result<usize> {
+    let mut changes = 0;
+
+    for item in db.history.iter() {
+        let (key, value) = item.context("Failed to retrieve state transition from DB")?;
+
+        let plaintext = db.decrypt(&value)?;
+        if deserialize::<StateTransition>(&plaintext).is_ok() {
+            continue;
+        }
+
+        let LegacyStateTransition { timestamp, state } =
+            deserialize(&plaintext).context("Failed to deserialize state transition")?;
+        let swap_id = Uuid::from_slice(&key[..16]).context("Invalid state transition key")?;
+
+        if !dry_run {
+            record_swap_data(db, swap_id, &state)?;
+            db.history
+                .insert(key, db.encode(&StateTransition::new(timestamp, state))?)?;
+        }
+        changes += 1;
+    }
+
+    db.accounting.flush()?;
+    db.receipt_terms.flush()?;
+    db.view_keys.flush()?;
+    db.history.flush()?;
+
+    Ok(changes)
+}
+
+/// Keeps data that was recorded already, e.g. by a later state of the swap.
+fn record_swap_data(db: &Database, swap_id: Uuid, state: &Swap) -> Result<()> {
+    let key = swap_id.as_bytes();
+
+    match state {
+        Swap::Alice(alice) => {
+            if let Some(state3) = alice.state3() {
+                let mut accounting = db.get_accounting(swap_id)?;
+                if accounting.amounts.is_none() {
+                    accounting.amounts = Some(state3.into());
+                    db.accounting.insert(key, db.encode(&accounting)?)?;
+                }
+            }
+
+            match alice {
+                Alice::XmrLockTransactionSent {
+                    transfer_proof,
+                    state3,
+                    ..
+                }
+                | Alice::XmrLocked {
+                    transfer_proof,
+                    state3,
+                    ..
+                }
+                | Alice::XmrLockTransferProofSent {
+                    transfer_proof,
+                    state3,
+                    ..
+                }
+                | Alice::EncSigLearned {
+                    transfer_proof,
+                    state3,
+                    ..
+                } if !db.receipt_terms.contains_key(key)? => {
+                    let terms = state3.receipt_terms(swap_id, transfer_proof);
+                    db.receipt_terms.insert(key, db.encode(&terms)?)?;
+                }
+                _ => {}
+            }
+        }
+        Swap::Bob(Bob::XmrLockProofReceived {
+            state,
+            lock_transfer_proof,
+            monero_wallet_restore_blockheight,
+        }) => {
+            if !db.receipt_terms.contains_key(key)? {
+                let terms = state.receipt_terms(swap_id, lock_transfer_proof);
+                db.receipt_terms.insert(key, db.encode(&terms)?)?;
+            }
+
+            if !db.view_keys.contains_key(key)? {
+                match db.get_monero_address(swap_id) {
+                    Ok(address) => {
+                        let export = ViewKeyExport::new(
+                            swap_id,
+                            state,
+                            lock_transfer_proof.clone(),
+                            *monero_wallet_restore_blockheight,
+                            address.network,
+                        );
+                        db.view_keys.insert(key, db.encode(&export)?)?;
+                    }
+                    Err(error) => tracing::warn!(
+                        %swap_id,
+                        "Cannot record the view key of the Monero lock: {:#}",
+                        error
+                    ),
+                }
+            }
+        }
+        Swap::Bob(_) => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::alice::AliceEndState;
+    use serde::Serialize;
+
+    #[tokio::test]
+    async fn migrates_database_written_before_versioning() -> Result<()> {
+        let db_dir = tempfile::tempdir()?;
+        let db = Database::open(db_dir.path())?;
+        assert!(db.pending_migrations()?.is_empty());
+
+        // Written by a version that neither recorded the history nor the schema.
+        let swap_id = Uuid::new_v4();
+        let state = Swap::Alice(Alice::Done(AliceEndState::BtcRedeemed));
+        db.swaps.insert(serialize(&swap_id)?, serialize(&state)?)?;
+        db.meta.remove(KEY_SCHEMA_VERSION)?;
+
+        let planned = db.migrate(true)?;
+        assert_eq!(planned.len(), 3);
+        assert_eq!(planned[0].1, 1);
+        assert!(db.get_history(swap_id)?.is_empty());
+        assert_eq!(db.schema_version()?, 0);
+
+        db.migrate(false)?;
+        assert_eq!(db.get_history(swap_id)?.len(), 1);
+        assert_eq!(db.schema_version()?, SCHEMA_VERSION);
+        assert!(db.migrate(false)?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn strips_states_from_the_history() -> Result<()> {
+        #[derive(Serialize)]
+        struct LegacyStateTransition {
+            timestamp: SystemTime,
+            state: Swap,
+        }
+
+        let db_dir = tempfile::tempdir()?;
+        let db = Database::open(db_dir.path())?;
+
+        let swap_id = Uuid::new_v4();
+        let state = Swap::Alice(Alice::Done(AliceEndState::BtcRedeemed));
+        db.insert_latest_state(swap_id, state.clone()).await?;
+        db.history.clear()?;
+        db.history.insert(
+            db.next_history_key(swap_id)?,
+            serialize(&LegacyStateTransition {
+                timestamp: SystemTime::UNIX_EPOCH,
+                state,
+            })?,
+        )?;
+        db.set_schema_version(2)?;
+
+        assert_eq!(db.migrate(true)?[0].1, 1);
+        assert!(db.get_history(swap_id).is_err());
+
+        db.migrate(false)?;
+        assert_eq!(db.get_history(swap_id)?, vec![StateTransition {
+            timestamp: SystemTime::UNIX_EPOCH,
+            state: "btc is redeemed".to_owned(),
+            details: String::new(),
+        }]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_database_of_newer_version() -> Result<()> {
+        let db_dir = tempfile::tempdir()?;
+        {
+            let db = Database::open(db_dir.path())?;
+            db.set_schema_version(SCHEMA_VERSION + 1)?;
+        }
+
+        let error = Database::open(db_dir.path()).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<SchemaTooNew>(),
+            Some(&SchemaTooNew {
+                found: SCHEMA_VERSION + 1
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_the_backup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("database");
+        let before = Uuid::new_v4();
+        let after = Uuid::new_v4();
+        let state = Swap::Alice(Alice::Done(AliceEndState::BtcRedeemed));
+
+        {
+            let db = Database::open(&path)?;
+            db.insert_latest_state(before, state.clone()).await?;
+        }
+        backup(&path)?;
+        {
+            let db = Database::open(&path)?;
+            db.insert_latest_state(after, state.clone()).await?;
+        }
+
+        let replaced = rollback(&path)?;
+
+        let db = Database::open(&path)?;
+        assert_eq!(db.get_state(before)?, state);
+        assert!(db.get_state(after).is_err());
+        assert!(replaced.exists());
+        assert!(rollback(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Encrypting secrets at rest with a key derived from a passphrase.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use std::env::{self, VarError};
+use std::fmt;
+
+const NONCE_LENGTH: usize = 12;
+const SALT_LENGTH: usize = 16;
+
+/// Setting this environment variable encrypts the seed and the database of the
+/// binaries.
+pub const PASSPHRASE_ENV_VAR: &str = "XMR_BTC_SWAP_PASSPHRASE";
+
+/// Makes guessing a passphrase expensive, while deriving the key takes well
+/// below a second.
+const DEFAULT_ITERATIONS: u32 = 100_000;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    pub fn new(passphrase: String) -> Self {
+        Self(passphrase)
+    }
+
+    /// Reads the passphrase from [`PASSPHRASE_ENV_VAR`], returns `None` if it
+    /// is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var(PASSPHRASE_ENV_VAR) {
+            Ok(passphrase) if passphrase.is_empty() => {
+                bail!("{} must not be empty", PASSPHRASE_ENV_VAR)
+            }
+            Ok(passphrase) => Ok(Some(Self(passphrase))),
+            Err(VarError::NotPresent) => Ok(None),
+            Err(VarError::NotUnicode(_)) => bail!("{} is not valid unicode", PASSPHRASE_ENV_VAR),
+        }
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Passphrase([*****])")
+    }
+}
+
+/// How the key is derived from the passphrase, stored alongside the encrypted
+/// data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyParams {
+    salt: [u8; SALT_LENGTH],
+    iterations: u32,
+}
+
+impl KeyParams {
+    pub const LENGTH: usize = SALT_LENGTH + 4;
+
+    pub fn random() -> Self {
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        Self {
+            salt,
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+
+    pub fn derive(&self, passphrase: &Passphrase) -> Cipher {
+        Cipher::new(&pbkdf2_sha256(
+            passphrase.0.as_bytes(),
+            &self.salt,
+            self.iterations,
+        ))
+    }
+
+    /// Encodes the parameters as `iterations | salt`.
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        bytes[..4].copy_from_slice(&self.iterations.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.salt);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Self {
+        let mut iterations = [0u8; 4];
+        iterations.copy_from_slice(&bytes[..4]);
+        let mut salt = [0u8; SALT_LENGTH];
+        salt.copy_from_slice(&bytes[4..]);
+
+        Self {
+            salt,
+            iterations: u32::from_be_bytes(iterations),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[error("Failed to decrypt, the data was encrypted with a different key or tampered with")]
+pub struct DecryptionFailed;
+
+/// Authenticated encryption with ChaCha20-Poly1305 and a random nonce per
+/// message.
+#[derive(Clone)]
+pub struct Cipher(ChaCha20Poly1305);
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self(ChaCha20Poly1305::new(Key::from_slice(key)))
+    }
+
+    /// The result is laid out as `nonce | ciphertext`.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .0
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt"))?;
+
+        let mut bytes = Vec::with_capacity(NONCE_LENGTH + ciphertext.len());
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+
+        Ok(bytes)
+    }
+
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        if bytes.len() < NONCE_LENGTH {
+            bail!("Encrypted data is truncated")
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+
+        self.0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| DecryptionFailed)
+            .context("Failed to decrypt")
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cipher([*****])")
+    }
+}
+
+/// PBKDF2 as specified in RFC 8018, a single block suffices for a 32 byte key.
+fn pbkdf2_sha256(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let prf = HmacEngine::<sha256::Hash>::new(passphrase);
+
+    let mut engine = prf.clone();
+    engine.input(salt);
+    engine.input(&1u32.to_be_bytes());
+    let mut block = Hmac::from_engine(engine).into_inner();
+    let mut key = block;
+
+    for _ in 1..iterations {
+        let mut engine = prf.clone();
+        engine.input(&block);
+        block = Hmac::from_engine(engine).into_inner();
+
+        for (key, block) in key.iter_mut().zip(&block) {
+            *key ^= block;
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+
+    #[test]
+    fn pbkdf2_matches_test_vectors() {
+        let expected =
+            Vec::<u8>::from_hex("120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b")
+                .unwrap();
+        assert_eq!(pbkdf2_sha256(b"password", b"salt", 1).to_vec(), expected);
+
+        let expected =
+            Vec::<u8>::from_hex("c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a")
+                .unwrap();
+        assert_eq!(pbkdf2_sha256(b"password", b"salt", 4096).to_vec(), expected);
+    }
+
+    #[test]
+    fn decrypting_with_wrong_passphrase_fails() {
+        let params = KeyParams {
+            salt: [1; SALT_LENGTH],
+            iterations: 1,
+        };
+        let cipher = params.derive(&Passphrase::new("correct".to_owned()));
+
+        let encrypted = cipher.encrypt(b"secret").unwrap();
+
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"secret");
+        let error = params
+            .derive(&Passphrase::new("wrong".to_owned()))
+            .decrypt(&encrypted)
+            .unwrap_err();
+        assert!(error.is::<DecryptionFailed>());
+    }
+
+    #[test]
+    fn key_params_round_trip_through_bytes() {
+        let params = KeyParams::random();
+
+        assert_eq!(KeyParams::from_bytes(&params.to_bytes()), params);
+    }
+}
@@ -1,13 +1,21 @@
 use crate::asb;
 use crate::bitcoin::{CancelTimelock, PunishTimelock};
 use std::cmp::max;
+use std::convert::TryFrom;
 use std::time::Duration;
 use time::NumericalStdDurationShort;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Config {
+    /// How long after the swap setup the Bitcoin lock transaction has to be
+    /// seen in the mempool.
+    ///
+    /// Unlike the other deadlines it is not expressed in blocks, as the
+    /// transaction is expected in the mempool well before the next block.
     pub bitcoin_lock_mempool_timeout: Duration,
-    pub bitcoin_lock_confirmed_timeout: Duration,
+    /// The blocks after the Bitcoin lock transaction was seen within which it
+    /// has to reach the finality confirmations.
+    pub bitcoin_lock_confirmed_timeout: u32,
     pub bitcoin_finality_confirmations: u32,
     pub bitcoin_avg_block_time: Duration,
     pub bitcoin_cancel_timelock: CancelTimelock,
@@ -16,9 +24,56 @@ pub struct Config {
     pub monero_avg_block_time: Duration,
     pub monero_finality_confirmations: u64,
     pub monero_network: monero::Network,
+    /// Require more confirmations of the lock transactions for larger swaps,
+    /// see [`Config::bitcoin_lock_confirmations`].
+    pub bitcoin_lock_confirmations_scale_with_amount: bool,
+    pub monero_lock_confirmations_scale_with_amount: bool,
 }
 
+/// Swaps of at least these amounts require more confirmations of their lock
+/// transactions, one tier per threshold.
+const CONFIRMATION_TIER_THRESHOLDS_SAT: [u64; 3] = [10_000_000, 100_000_000, 1_000_000_000];
+const BITCOIN_CONFIRMATIONS_PER_TIER: u32 = 1;
+/// Covers about the same time as one Bitcoin block.
+const MONERO_CONFIRMATIONS_PER_TIER: u64 = 5;
+
 impl Config {
+    /// The confirmations the Bitcoin lock transaction of a swap of the given
+    /// amount needs before the Monero is locked.
+    ///
+    /// Double spending the lock transaction is more attractive the more is at
+    /// stake, hence larger swaps require more confirmations than the finality
+    /// confirmations unless the operator configured a fixed number.
+    pub fn bitcoin_lock_confirmations(&self, btc: bitcoin::Amount) -> u32 {
+        if !self.bitcoin_lock_confirmations_scale_with_amount {
+            return self.bitcoin_finality_confirmations;
+        }
+
+        self.bitcoin_finality_confirmations
+            + confirmation_tier(btc) * BITCOIN_CONFIRMATIONS_PER_TIER
+    }
+
+    /// How many blocks to wait for the Bitcoin lock transaction of a swap of
+    /// the given amount to reach [`Config::bitcoin_lock_confirmations`],
+    /// extended by the additional confirmations.
+    pub fn bitcoin_lock_confirmed_timeout_for(&self, btc: bitcoin::Amount) -> u32 {
+        let additional_confirmations =
+            self.bitcoin_lock_confirmations(btc) - self.bitcoin_finality_confirmations;
+
+        self.bitcoin_lock_confirmed_timeout + additional_confirmations
+    }
+
+    /// The confirmations the Monero lock transaction of a swap of the given
+    /// amount needs before the encrypted signature is sent.
+    pub fn monero_lock_confirmations(&self, btc: bitcoin::Amount) -> u64 {
+        if !self.monero_lock_confirmations_scale_with_amount {
+            return self.monero_finality_confirmations;
+        }
+
+        self.monero_finality_confirmations
+            + u64::from(confirmation_tier(btc)) * MONERO_CONFIRMATIONS_PER_TIER
+    }
+
     pub fn bitcoin_sync_interval(&self) -> Duration {
         sync_interval(self.bitcoin_avg_block_time)
     }
@@ -45,7 +100,7 @@ impl GetConfig for Mainnet {
     fn get_config() -> Config {
         Config {
             bitcoin_lock_mempool_timeout: 3.minutes(),
-            bitcoin_lock_confirmed_timeout: 2.hours(),
+            bitcoin_lock_confirmed_timeout: 12,
             bitcoin_finality_confirmations: 2,
             bitcoin_avg_block_time: 10.minutes(),
             bitcoin_cancel_timelock: CancelTimelock::new(72),
@@ -54,6 +109,8 @@ impl GetConfig for Mainnet {
             monero_avg_block_time: 2.minutes(),
             monero_finality_confirmations: 10,
             monero_network: monero::Network::Mainnet,
+            bitcoin_lock_confirmations_scale_with_amount: true,
+            monero_lock_confirmations_scale_with_amount: true,
         }
     }
 }
@@ -62,7 +119,7 @@ impl GetConfig for Testnet {
     fn get_config() -> Config {
         Config {
             bitcoin_lock_mempool_timeout: 3.minutes(),
-            bitcoin_lock_confirmed_timeout: 1.hours(),
+            bitcoin_lock_confirmed_timeout: 6,
             bitcoin_finality_confirmations: 2,
             bitcoin_avg_block_time: 10.minutes(),
             bitcoin_cancel_timelock: CancelTimelock::new(12),
@@ -71,6 +128,8 @@ impl GetConfig for Testnet {
             monero_avg_block_time: 2.minutes(),
             monero_finality_confirmations: 10,
             monero_network: monero::Network::Stagenet,
+            bitcoin_lock_confirmations_scale_with_amount: true,
+            monero_lock_confirmations_scale_with_amount: true,
         }
     }
 }
@@ -79,7 +138,7 @@ impl GetConfig for Regtest {
     fn get_config() -> Config {
         Config {
             bitcoin_lock_mempool_timeout: 30.seconds(),
-            bitcoin_lock_confirmed_timeout: 1.minutes(),
+            bitcoin_lock_confirmed_timeout: 12,
             bitcoin_finality_confirmations: 1,
             bitcoin_avg_block_time: 5.seconds(),
             bitcoin_cancel_timelock: CancelTimelock::new(100),
@@ -88,10 +147,21 @@ impl GetConfig for Regtest {
             monero_avg_block_time: 1.seconds(),
             monero_finality_confirmations: 10,
             monero_network: monero::Network::Mainnet, // yes this is strange
+            bitcoin_lock_confirmations_scale_with_amount: false,
+            monero_lock_confirmations_scale_with_amount: false,
         }
     }
 }
 
+fn confirmation_tier(btc: bitcoin::Amount) -> u32 {
+    let tiers = CONFIRMATION_TIER_THRESHOLDS_SAT
+        .iter()
+        .filter(|threshold| btc.as_sat() >= **threshold)
+        .count();
+
+    u32::try_from(tiers).expect("there are only a few tiers")
+}
+
 fn sync_interval(avg_block_time: Duration) -> Duration {
     max(avg_block_time / 10, Duration::from_secs(1))
 }
@@ -103,10 +173,12 @@ pub fn new(is_testnet: bool, asb_config: &asb::config::Config) -> Config {
         Mainnet::get_config()
     };
 
+    // Confirmations configured by the operator are taken as they are.
     let env_config =
         if let Some(bitcoin_finality_confirmations) = asb_config.bitcoin.finality_confirmations {
             Config {
                 bitcoin_finality_confirmations,
+                bitcoin_lock_confirmations_scale_with_amount: false,
                 ..env_config
             }
         } else {
@@ -116,6 +188,7 @@ pub fn new(is_testnet: bool, asb_config: &asb::config::Config) -> Config {
     if let Some(monero_finality_confirmations) = asb_config.monero.finality_confirmations {
         Config {
             monero_finality_confirmations,
+            monero_lock_confirmations_scale_with_amount: false,
             ..env_config
         }
     } else {
@@ -140,4 +213,38 @@ mod tests {
 
         assert_eq!(interval, Duration::from_secs(10))
     }
+
+    #[test]
+    fn lock_confirmations_grow_with_amount() {
+        let config = Mainnet::get_config();
+
+        assert_eq!(config.bitcoin_lock_confirmations(btc(0.01)), 2);
+        assert_eq!(config.bitcoin_lock_confirmations(btc(0.1)), 3);
+        assert_eq!(config.bitcoin_lock_confirmations(btc(5.0)), 4);
+        assert_eq!(config.bitcoin_lock_confirmations(btc(20.0)), 5);
+
+        assert_eq!(config.monero_lock_confirmations(btc(0.01)), 10);
+        assert_eq!(config.monero_lock_confirmations(btc(5.0)), 20);
+
+        assert_eq!(
+            config.bitcoin_lock_confirmed_timeout_for(btc(5.0)),
+            config.bitcoin_lock_confirmed_timeout + 2
+        );
+    }
+
+    #[test]
+    fn lock_confirmations_are_fixed_if_scaling_is_disabled() {
+        let config = Config {
+            bitcoin_lock_confirmations_scale_with_amount: false,
+            monero_lock_confirmations_scale_with_amount: false,
+            ..Mainnet::get_config()
+        };
+
+        assert_eq!(config.bitcoin_lock_confirmations(btc(20.0)), 2);
+        assert_eq!(config.monero_lock_confirmations(btc(20.0)), 10);
+    }
+
+    fn btc(amount: f64) -> bitcoin::Amount {
+        bitcoin::Amount::from_btc(amount).unwrap()
+    }
 }
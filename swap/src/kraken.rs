@@ -1,3 +1,4 @@
+use crate::backoff::{self, Policy};
 use anyhow::{anyhow, Context, Result};
 use futures::{SinkExt, StreamExt, TryStreamExt};
 use serde::Deserialize;
@@ -19,16 +20,10 @@ pub fn connect(price_ticker_ws_url: Url) -> Result<PriceUpdates> {
     let price_update = Arc::new(price_update);
 
     tokio::spawn(async move {
-        // The default backoff config is fine for us apart from one thing:
-        // `max_elapsed_time`. If we don't get an error within this timeframe,
-        // backoff won't actually retry the operation.
-        let backoff = backoff::ExponentialBackoff {
-            max_elapsed_time: None,
-            ..backoff::ExponentialBackoff::default()
-        };
+        let notify_price_update = price_update.clone();
 
-        let result = backoff::future::retry_notify::<Infallible, _, _, _, _, _>(
-            backoff,
+        let result = ::backoff::future::retry_notify::<Infallible, _, _, _, _, _>(
+            Policy::PRICE_FEED.backoff(),
             || {
                 let price_update = price_update.clone();
                 let price_ticker_ws_url = price_ticker_ws_url.clone();
@@ -49,6 +44,10 @@ pub fn connect(price_ticker_ws_url: Url) -> Result<PriceUpdates> {
                 }
             },
             |error, next: Duration| {
+                // The last rate we received belongs to a connection that is gone, don't
+                // quote based on it while we are reconnecting.
+                let _ = notify_price_update.send(Err(Error::NotYetAvailable));
+
                 tracing::info!(
                     "Kraken websocket connection failed, retrying in {}ms. Error {:#}",
                     next.as_millis(),
@@ -104,11 +103,12 @@ type PriceUpdate = Result<wire::PriceUpdate, Error>;
 /// Maps a [`connection::Error`] to a backoff error, effectively defining our
 /// retry strategy.
 fn to_backoff(e: connection::Error) -> backoff::Error<anyhow::Error> {
-    use backoff::Error::*;
+    use crate::backoff::Error::*;
 
     match e {
-        // Connection closures and websocket errors will be retried
+        // Connection closures, stale connections and websocket errors will be retried
         connection::Error::ConnectionClosed => Transient(anyhow::Error::from(e)),
+        connection::Error::Stale => Transient(anyhow::Error::from(e)),
         connection::Error::WebSocket(_) => Transient(anyhow::Error::from(e)),
 
         // Failures while parsing a message are permanent because they most likely present a
@@ -138,7 +138,17 @@ mod connection {
             .send(SUBSCRIBE_XMR_BTC_TICKER_PAYLOAD.into())
             .await?;
 
-        let stream = rate_stream.err_into().try_filter_map(parse_message).boxed();
+        // Kraken sends a heartbeat every second if there are no other messages. If we
+        // don't receive anything for a while, the connection is considered stale.
+        let stream = futures::stream::unfold(rate_stream, |mut rate_stream| async move {
+            match tokio::time::timeout(STALE_CONNECTION_TIMEOUT, rate_stream.next()).await {
+                Ok(Some(msg)) => Some((msg.map_err(Error::from), rate_stream)),
+                Ok(None) => None,
+                Err(_) => Some((Err(Error::Stale), rate_stream)),
+            }
+        })
+        .try_filter_map(parse_message)
+        .boxed();
 
         Ok(stream)
     }
@@ -207,12 +217,16 @@ mod connection {
     pub enum Error {
         #[error("The Kraken server closed the websocket connection")]
         ConnectionClosed,
+        #[error("Did not receive any message from the Kraken server for {}s", STALE_CONNECTION_TIMEOUT.as_secs())]
+        Stale,
         #[error("Failed to read message from websocket stream")]
         WebSocket(#[from] tungstenite::Error),
         #[error("Failed to parse rate from websocket message")]
         Parse(#[from] wire::Error),
     }
 
+    const STALE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
     const SUBSCRIBE_XMR_BTC_TICKER_PAYLOAD: &str = r#"
     { "event": "subscribe",
       "pair": [ "XMR/XBT" ],
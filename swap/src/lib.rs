@@ -16,10 +16,14 @@
     missing_copy_implementations
 )]
 
+pub mod api;
 pub mod asb;
+pub mod backoff;
 pub mod bitcoin;
 pub mod cli;
+pub mod config;
 pub mod database;
+pub mod encryption;
 pub mod env;
 pub mod fs;
 pub mod kraken;
@@ -27,7 +31,9 @@ pub mod libp2p_ext;
 pub mod monero;
 pub mod network;
 pub mod protocol;
+pub mod receipt;
 pub mod seed;
+pub mod shutdown;
 pub mod tor;
 pub mod tracing_ext;
 
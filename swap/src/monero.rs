@@ -1,19 +1,20 @@
+mod node_pool;
 pub mod wallet;
 mod wallet_rpc;
 
 pub use ::monero::network::Network;
 pub use ::monero::{Address, PrivateKey, PublicKey};
 pub use curve25519_dalek::scalar::Scalar;
+pub use node_pool::NodePool;
 pub use wallet::Wallet;
 pub use wallet_rpc::{WalletRpc, WalletRpcProcess};
 
 use crate::bitcoin;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use rand::{CryptoRng, RngCore};
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
 use std::fmt;
 use std::ops::{Add, Mul, Sub};
 use std::str::FromStr;
@@ -99,23 +100,112 @@ impl Amount {
         self.0
     }
 
+    /// Goes through the shortest decimal representation of the float, so e.g.
+    /// `0.1` becomes exactly 0.1 XMR instead of the closest binary fraction.
     pub fn from_monero(amount: f64) -> Result<Self> {
-        let decimal = Decimal::try_from(amount)?;
-        Self::from_decimal(decimal)
+        Self::parse_monero(&amount.to_string())
     }
 
+    /// Parses an amount of XMR without unit. Only a `.` is accepted as
+    /// decimal separator, regardless of the locale.
     pub fn parse_monero(amount: &str) -> Result<Self> {
-        let decimal = Decimal::from_str(amount)?;
+        let decimal = Decimal::from_str(amount)
+            .with_context(|| format!("Failed to parse {} as an amount of XMR", amount))?;
         Self::from_decimal(decimal)
     }
 
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Self> {
+        self.0.checked_mul(rhs).map(Self)
+    }
+
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Calculate the maximum amount of Bitcoin that can be bought at the given
+    /// asking price for this amount of Monero, taking into account the fee
+    /// for locking the Monero.
+    ///
+    /// Returns `None` if the calculation overflows.
+    pub fn max_bitcoin_for_price(&self, ask_price: bitcoin::Amount) -> Option<bitcoin::Amount> {
+        let piconero_minus_fee = self.saturating_sub(MONERO_FEE).as_piconero();
+
+        if piconero_minus_fee == 0 {
+            return Some(bitcoin::Amount::ZERO);
+        }
+
+        // The ask price is given in satoshi per XMR, convert it to satoshi per
+        // piconero
+        let ask_sats = Decimal::from(ask_price.as_sat());
+        let piconero_per_xmr = Decimal::from(PICONERO_OFFSET);
+        let ask_sats_per_piconero = ask_sats.checked_div(piconero_per_xmr)?;
+
+        let piconero = Decimal::from(piconero_minus_fee);
+        let max_sats = piconero.checked_mul(ask_sats_per_piconero)?.floor();
+
+        Some(bitcoin::Amount::from_sat(max_sats.to_u64()?))
+    }
+
+    /// Calculate the amount of Bitcoin that has to be paid at the given asking
+    /// price to receive at least this amount of Monero.
+    ///
+    /// Returns `None` if the calculation overflows.
+    pub fn bitcoin_for_price(&self, ask_price: bitcoin::Amount) -> Option<bitcoin::Amount> {
+        let ask_sats = Decimal::from(ask_price.as_sat());
+        let piconero_per_xmr = Decimal::from(PICONERO_OFFSET);
+
+        let piconero = self.as_piconero_decimal();
+        let sats = piconero
+            .checked_mul(ask_sats)?
+            .checked_div(piconero_per_xmr)?
+            .ceil();
+
+        Some(bitcoin::Amount::from_sat(sats.to_u64()?))
+    }
+
+    /// Calculate the amount of Monero that is received for the given amount of
+    /// Bitcoin at the given asking price.
+    ///
+    /// Returns `None` if the asking price is zero or the calculation
+    /// overflows.
+    pub fn for_bitcoin_at_price(
+        amount: bitcoin::Amount,
+        ask_price: bitcoin::Amount,
+    ) -> Option<Self> {
+        let sats = Decimal::from(amount.as_sat());
+        let ask_sats = Decimal::from(ask_price.as_sat());
+        let piconero_per_xmr = Decimal::from(PICONERO_OFFSET);
+
+        let piconero = sats
+            .checked_mul(piconero_per_xmr)?
+            .checked_div(ask_sats)?
+            .floor();
+
+        Some(Self::from_piconero(piconero.to_u64()?))
+    }
+
     pub fn as_piconero_decimal(&self) -> Decimal {
         Decimal::from(self.as_piconero())
     }
 
     fn from_decimal(amount: Decimal) -> Result<Self> {
+        if amount.is_sign_negative() && !amount.is_zero() {
+            bail!(NegativeAmount(amount.to_string()))
+        }
+
         let piconeros_dec =
             amount.mul(Decimal::from_u64(PICONERO_OFFSET).expect("constant to fit into u64"));
+        if !piconeros_dec.fract().is_zero() {
+            bail!(TooPrecise(amount.to_string()))
+        }
         let piconeros = piconeros_dec
             .to_u64()
             .ok_or_else(|| OverflowError(amount.to_string()))?;
@@ -163,6 +253,29 @@ impl fmt::Display for Amount {
     }
 }
 
+impl FromStr for Amount {
+    type Err = anyhow::Error;
+
+    /// Parses an amount of XMR, with or without the unit, or an amount of
+    /// piconero, e.g. `0.5`, `0.5 XMR` or `500000000000 piconero`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let (amount, unit) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(amount), None, None) => (amount, "xmr"),
+            (Some(amount), Some(unit), None) => (amount, unit),
+            _ => bail!("Failed to parse {:?} as an amount of Monero", s),
+        };
+
+        match unit.to_lowercase().as_str() {
+            "xmr" => Self::parse_monero(amount),
+            "piconero" | "piconeros" => u64::from_str(amount)
+                .map(Self::from_piconero)
+                .with_context(|| format!("Failed to parse {} as an amount of piconero", amount)),
+            _ => bail!(UnknownUnit(unit.to_owned())),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct TransferProof {
     tx_hash: TxHash,
@@ -209,12 +322,26 @@ pub struct InsufficientFunds {
 #[error("Overflow, cannot convert {0} to u64")]
 pub struct OverflowError(pub String);
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Amount {0} is negative")]
+pub struct NegativeAmount(pub String);
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Amount {0} is more precise than a piconero")]
+pub struct TooPrecise(pub String);
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Unknown unit {0}, expected XMR or piconero")]
+pub struct UnknownUnit(pub String);
+
 pub mod monero_private_key {
+    use curve25519_dalek::scalar::Scalar;
     use monero::consensus::{Decodable, Encodable};
     use monero::PrivateKey;
     use serde::de::Visitor;
     use serde::ser::Error;
     use serde::{de, Deserializer, Serializer};
+    use std::convert::TryFrom;
     use std::fmt;
     use std::io::Cursor;
 
@@ -231,6 +358,14 @@ pub mod monero_private_key {
         where
             E: de::Error,
         {
+            // The key is provided by the counterparty, reject encodings that would be
+            // silently reduced modulo the group order.
+            let bytes =
+                <[u8; 32]>::try_from(s).map_err(|_| E::invalid_length(s.len(), &"32 bytes"))?;
+            if Scalar::from_canonical_bytes(bytes).is_none() {
+                return Err(E::custom("non-canonical scalar encoding"));
+            }
+
             let mut s = s;
             PrivateKey::consensus_decode(&mut s).map_err(|err| E::custom(format!("{:?}", err)))
         }
@@ -257,6 +392,34 @@ pub mod monero_private_key {
     }
 }
 
+/// The compressed encoding of the identity point.
+const IDENTITY: [u8; 32] = {
+    let mut identity = [0; 32];
+    identity[0] = 1;
+    identity
+};
+
+/// Whether one of the public keys of the address is zero or the identity,
+/// which makes the outputs paying to it unspendable.
+pub fn is_burn_address(address: &Address) -> bool {
+    [address.public_spend, address.public_view]
+        .iter()
+        .any(|key| key.as_bytes() == [0; 32] || key.as_bytes() == IDENTITY)
+}
+
+/// Integrated addresses carry a payment id, exchanges hand them out to
+/// attribute deposits to accounts.
+pub fn is_integrated_address(address: &Address) -> bool {
+    matches!(
+        address.addr_type,
+        ::monero::util::address::AddressType::Integrated(_)
+    )
+}
+
+#[derive(Clone, Copy, thiserror::Error, Debug, PartialEq)]
+#[error("Refusing to send Monero to {0}, nobody can spend from this address")]
+pub struct BurnAddress(pub Address);
+
 pub mod monero_amount {
     use crate::monero::Amount;
     use serde::{Deserialize, Deserializer, Serializer};
@@ -279,9 +442,96 @@ pub mod monero_amount {
     }
 }
 
+/// (De)serializes an amount of XMR for config files. Amounts are written as
+/// strings because a float cannot hold every amount of piconero, plain
+/// numbers are read as XMR.
+pub mod as_xmr {
+    use crate::monero::Amount;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Integer(u64),
+        Float(f64),
+        String(String),
+    }
+
+    pub fn serialize<S>(x: &Amount, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(x)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Amount, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let amount = match Raw::deserialize(deserializer)? {
+            Raw::Integer(xmr) => Amount::parse_monero(&xmr.to_string()),
+            Raw::Float(xmr) => Amount::from_monero(xmr),
+            Raw::String(amount) => Amount::from_str(&amount),
+        };
+
+        amount.map_err(|error| D::Error::custom(format!("{:#}", error)))
+    }
+
+    pub mod opt {
+        use crate::monero::Amount;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S>(x: &Option<Amount>, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match x {
+                Some(amount) => s.collect_str(amount),
+                None => s.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> Result<Option<Amount>, <D as Deserializer<'de>>::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Amount);
+
+            let amount = Option::<Wrapper>::deserialize(deserializer)?;
+
+            Ok(amount.map(|Wrapper(amount)| amount))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::OsRng;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn detects_burn_and_integrated_addresses() {
+        let regular = Address::from_str("44Ato7HveWidJYUAVw5QffEcEtSH1DwzSP3FPPkHxNAS4LX9CqgucphTisH978FLHE34YNEx7FcbBfQLQUU8m3NUC4VqsRa").unwrap();
+        let identity = PublicKey::from_slice(&IDENTITY).unwrap();
+        let burn = Address::standard(Network::Mainnet, identity, regular.public_view);
+        let integrated = Address::integrated(
+            Network::Mainnet,
+            regular.public_spend,
+            regular.public_view,
+            ::monero::util::address::PaymentId::zero(),
+        );
+
+        assert!(!is_burn_address(&regular));
+        assert!(is_burn_address(&burn));
+        assert!(!is_integrated_address(&regular));
+        assert!(is_integrated_address(&integrated));
+    }
 
     #[test]
     fn display_monero_min() {
@@ -331,6 +581,50 @@ mod tests {
         assert_eq!(18446744073709551615, pics);
     }
 
+    #[test]
+    fn parse_monero_with_unit() {
+        assert_eq!(
+            Amount::from_str("0.5 XMR").unwrap(),
+            Amount::from_piconero(500_000_000_000)
+        );
+        assert_eq!(
+            Amount::from_str("0.5").unwrap(),
+            Amount::from_piconero(500_000_000_000)
+        );
+        assert_eq!(
+            Amount::from_str("1500 piconero").unwrap(),
+            Amount::from_piconero(1500)
+        );
+        assert!(Amount::from_str("0.5 BTC").is_err());
+    }
+
+    #[test]
+    fn parse_monero_rejects_ambiguous_input() {
+        // A comma is the decimal separator in many locales.
+        assert!(Amount::parse_monero("0,5").is_err());
+        assert!(Amount::parse_monero("-1").is_err());
+        assert!(Amount::parse_monero("0.0000000000001").is_err());
+    }
+
+    #[test]
+    fn from_monero_is_exact() {
+        let amount = Amount::from_monero(0.1).unwrap();
+        assert_eq!(amount, Amount::from_piconero(100_000_000_000));
+    }
+
+    #[test]
+    fn checked_arithmetic_detects_overflow() {
+        let max = Amount::from_piconero(u64::MAX);
+
+        assert_eq!(max.checked_add(Amount::from_piconero(1)), None);
+        assert_eq!(Amount::ZERO.checked_sub(Amount::from_piconero(1)), None);
+        assert_eq!(max.checked_mul(2), None);
+        assert_eq!(
+            Amount::ZERO.saturating_sub(Amount::from_piconero(1)),
+            Amount::ZERO
+        );
+    }
+
     #[test]
     fn parse_monero_overflows() {
         let overflow_pics = "18446744.073709551616";
@@ -341,8 +635,80 @@ mod tests {
         );
     }
 
-    use rand::rngs::OsRng;
-    use serde::{Deserialize, Serialize};
+    #[test]
+    fn max_bitcoin_to_trade() {
+        // At an asking price of 1 BTC / 1 XMR, 1 XMR plus the fee buys exactly 1 BTC
+        let ask = bitcoin::Amount::from_btc(1.0).unwrap();
+
+        let xmr = Amount::parse_monero("1.0").unwrap() + MONERO_FEE;
+        let btc = xmr.max_bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, bitcoin::Amount::from_btc(1.0).unwrap());
+
+        let xmr = Amount::parse_monero("1").unwrap();
+        let btc = xmr.max_bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, bitcoin::Amount::from_btc(0.99997).unwrap());
+
+        let ask = bitcoin::Amount::from_btc(0.005).unwrap();
+
+        let xmr = Amount::parse_monero("10").unwrap() + MONERO_FEE;
+        let btc = xmr.max_bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, bitcoin::Amount::from_btc(0.05).unwrap());
+    }
+
+    #[test]
+    fn max_bitcoin_to_trade_overflow() {
+        let xmr = Amount::from_monero(30.0).unwrap();
+        let ask = bitcoin::Amount::from_sat(728_688);
+        let btc = xmr.max_bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(bitcoin::Amount::from_sat(21_860_618), btc);
+
+        let xmr = Amount::from_piconero(u64::MAX);
+        let ask = bitcoin::Amount::from_sat(u64::MAX);
+        let btc = xmr.max_bitcoin_for_price(ask);
+
+        assert!(btc.is_none());
+    }
+
+    #[test]
+    fn max_bitcoin_for_zero_monero_is_zero() {
+        let ask = bitcoin::Amount::from_btc(0.005).unwrap();
+
+        let btc = Amount::ZERO.max_bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, bitcoin::Amount::ZERO);
+    }
+
+    #[test]
+    fn bitcoin_for_price_rounds_up() {
+        let ask = bitcoin::Amount::from_sat(3);
+        let xmr = Amount::from_piconero(PICONERO_OFFSET / 2);
+
+        let btc = xmr.bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, bitcoin::Amount::from_sat(2));
+    }
+
+    #[test]
+    fn monero_for_bitcoin_at_price_rounds_down() {
+        let ask = bitcoin::Amount::from_sat(3);
+
+        let xmr = Amount::for_bitcoin_at_price(bitcoin::Amount::from_sat(2), ask).unwrap();
+
+        assert_eq!(xmr, Amount::from_piconero(666_666_666_666));
+    }
+
+    #[test]
+    fn bitcoin_for_price_of_one_xmr_is_ask_price() {
+        let ask = bitcoin::Amount::from_btc(0.005).unwrap();
+
+        let btc = Amount::ONE_XMR.bitcoin_for_price(ask).unwrap();
+
+        assert_eq!(btc, ask);
+    }
 
     #[derive(Debug, Serialize, Deserialize, PartialEq)]
     pub struct MoneroPrivateKey(#[serde(with = "monero_private_key")] crate::monero::PrivateKey);
@@ -360,6 +726,24 @@ mod tests {
         assert_eq!(key, decoded);
     }
 
+    #[test]
+    fn serde_monero_private_key_rejects_non_canonical_scalar() {
+        let encoded = serde_cbor::to_vec(&serde_cbor::Value::Bytes(vec![0xff; 32])).unwrap();
+
+        let result = serde_cbor::from_slice::<MoneroPrivateKey>(&encoded);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn serde_monero_private_key_rejects_wrong_length() {
+        let encoded = serde_cbor::to_vec(&serde_cbor::Value::Bytes(vec![0x01; 31])).unwrap();
+
+        let result = serde_cbor::from_slice::<MoneroPrivateKey>(&encoded);
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn serde_monero_amount() {
         let amount = MoneroAmount(crate::monero::Amount::from_piconero(1000));
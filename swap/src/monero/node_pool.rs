@@ -0,0 +1,289 @@
+//! Health checking a list of Monero nodes and failing over to a healthy one.
+//!
+//! Every node is asked for its block height. The median height of all nodes
+//! that responded is taken as the majority's view of the chain, a node that
+//! fails to respond or lags behind it is replaced by the fastest node that
+//! keeps up.
+
+use crate::monero::Wallet;
+use anyhow::{bail, Context, Result};
+use futures::future;
+use monero_rpc::monerod;
+use monero_rpc::monerod::MonerodRpc as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How many blocks a node may be behind the majority before it is replaced.
+const MAX_LAG: u32 = 3;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct NodePool {
+    nodes: Vec<Node>,
+    selected: usize,
+}
+
+impl NodePool {
+    /// The addresses are given as `<host>:<port>`, the first one is selected
+    /// initially.
+    pub fn new(addresses: Vec<String>) -> Result<Self> {
+        if addresses.is_empty() {
+            bail!("At least one Monero node is required")
+        }
+
+        let nodes = addresses
+            .into_iter()
+            .map(|address| {
+                let (host, port) = parse_address(&address)?;
+                let client = monerod::Client::new(host, port)?;
+
+                Ok(Node { address, client })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { nodes, selected: 0 })
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.nodes[self.selected].address
+    }
+
+    /// Checks all nodes and selects the healthiest one, to pick the node a
+    /// wallet is started with.
+    pub async fn select_healthiest(&mut self) -> &str {
+        let health = self.check().await;
+
+        match majority_height(&health).and_then(|height| healthiest(&health, height)) {
+            Some(index) => self.selected = index,
+            None => tracing::warn!(
+                node = %self.selected(),
+                "None of the Monero nodes responded, using the first one"
+            ),
+        }
+
+        self.selected()
+    }
+
+    /// Periodically checks all nodes and switches the wallet to another node
+    /// once the selected one fails or lags behind.
+    ///
+    /// The majority's height is recorded as the height the wallet should be
+    /// synced to, which is why a single node is checked as well.
+    pub async fn run(mut self, wallet: Arc<Wallet>) {
+        loop {
+            tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+
+            let health = self.check().await;
+            if let Some(height) = majority_height(&health) {
+                wallet.set_target_height(height);
+            }
+
+            match decide(self.selected, &health) {
+                Decision::Keep => {}
+                Decision::Diverged {
+                    height,
+                    majority_height,
+                } => tracing::warn!(
+                    node = %self.selected(),
+                    %height,
+                    %majority_height,
+                    "The selected Monero node is ahead of the majority of nodes"
+                ),
+                Decision::FailOver { to } => {
+                    let address = self.nodes[to].address.clone();
+
+                    match wallet.set_daemon(address.clone()).await {
+                        Ok(()) => {
+                            tracing::info!(
+                                from = %self.selected(),
+                                to = %address,
+                                "Switched to another Monero node"
+                            );
+                            self.selected = to;
+                        }
+                        Err(error) => {
+                            tracing::warn!("Failed to fail over: {:#}", error)
+                        }
+                    }
+                }
+                Decision::NoHealthyNode => {
+                    tracing::warn!("None of the Monero nodes responded to the health check")
+                }
+            }
+        }
+    }
+
+    async fn check(&self) -> Vec<Option<Health>> {
+        future::join_all(self.nodes.iter().map(Node::check)).await
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    address: String,
+    client: monerod::Client,
+}
+
+impl Node {
+    async fn check(&self) -> Option<Health> {
+        let start = Instant::now();
+
+        match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.client.get_block_count()).await {
+            Ok(Ok(count)) => Some(Health {
+                height: count.count,
+                latency: start.elapsed(),
+            }),
+            Ok(Err(error)) => {
+                tracing::debug!(node = %self.address, "Monero node health check failed: {}", error);
+                None
+            }
+            Err(_) => {
+                tracing::debug!(node = %self.address, "Monero node health check timed out");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Health {
+    height: u32,
+    latency: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decision {
+    Keep,
+    /// The selected node is ahead of the majority, which might mean that it
+    /// follows a different chain.
+    Diverged {
+        height: u32,
+        majority_height: u32,
+    },
+    FailOver {
+        to: usize,
+    },
+    NoHealthyNode,
+}
+
+fn decide(selected: usize, health: &[Option<Health>]) -> Decision {
+    let majority_height = match majority_height(health) {
+        Some(height) => height,
+        None => return Decision::NoHealthyNode,
+    };
+
+    match health[selected] {
+        Some(Health { height, .. }) if height + MAX_LAG < majority_height => {}
+        Some(Health { height, .. }) if height > majority_height + MAX_LAG => {
+            return Decision::Diverged {
+                height,
+                majority_height,
+            }
+        }
+        Some(_) => return Decision::Keep,
+        None => {}
+    }
+
+    match healthiest(health, majority_height) {
+        Some(to) => Decision::FailOver { to },
+        None => Decision::NoHealthyNode,
+    }
+}
+
+/// The median height of the nodes that responded.
+fn majority_height(health: &[Option<Health>]) -> Option<u32> {
+    let mut heights = health
+        .iter()
+        .flatten()
+        .map(|health| health.height)
+        .collect::<Vec<_>>();
+
+    if heights.is_empty() {
+        return None;
+    }
+
+    heights.sort_unstable();
+
+    Some(heights[heights.len() / 2])
+}
+
+/// The fastest node that keeps up with the majority.
+fn healthiest(health: &[Option<Health>], majority_height: u32) -> Option<usize> {
+    health
+        .iter()
+        .enumerate()
+        .filter_map(|(index, health)| health.map(|health| (index, health)))
+        .filter(|(_, health)| health.height + MAX_LAG >= majority_height)
+        .min_by_key(|(_, health)| health.latency)
+        .map(|(index, _)| index)
+}
+
+fn parse_address(address: &str) -> Result<(String, u16)> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .with_context(|| format!("Monero node address {} is missing a port", address))?;
+    let port = port
+        .parse()
+        .with_context(|| format!("Invalid port in Monero node address {}", address))?;
+
+    Ok((host.to_owned(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy(height: u32, latency_ms: u64) -> Option<Health> {
+        Some(Health {
+            height,
+            latency: Duration::from_millis(latency_ms),
+        })
+    }
+
+    #[test]
+    fn keeps_selected_node_that_keeps_up() {
+        let health = [healthy(100, 500), healthy(101, 10), healthy(101, 20)];
+
+        assert_eq!(decide(0, &health), Decision::Keep);
+    }
+
+    #[test]
+    fn fails_over_to_fastest_node_when_selected_node_errors() {
+        let health = [None, healthy(100, 300), healthy(100, 20)];
+
+        assert_eq!(decide(0, &health), Decision::FailOver { to: 2 });
+    }
+
+    #[test]
+    fn fails_over_when_selected_node_lags() {
+        let health = [healthy(90, 10), healthy(100, 300), healthy(100, 200)];
+
+        assert_eq!(decide(0, &health), Decision::FailOver { to: 2 });
+    }
+
+    #[test]
+    fn warns_when_selected_node_is_ahead_of_majority() {
+        let health = [healthy(110, 10), healthy(100, 300), healthy(100, 200)];
+
+        assert_eq!(decide(0, &health), Decision::Diverged {
+            height: 110,
+            majority_height: 100
+        });
+    }
+
+    #[test]
+    fn no_healthy_node_if_none_responded() {
+        assert_eq!(decide(0, &[None, None]), Decision::NoHealthyNode);
+    }
+
+    #[test]
+    fn parses_host_and_port() {
+        assert_eq!(
+            parse_address("node.example.com:18081").unwrap(),
+            ("node.example.com".to_owned(), 18081)
+        );
+        assert!(parse_address("node.example.com").is_err());
+    }
+}
@@ -1,17 +1,23 @@
+use crate::backoff::{Backoff, Policy};
 use crate::env::Config;
 use crate::monero::{
     Amount, InsufficientFunds, PrivateViewKey, PublicViewKey, TransferProof, TxHash,
 };
 use ::monero::{Address, Network, PrivateKey, PublicKey};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use monero_rpc::wallet;
 use monero_rpc::wallet::{BlockHeight, CheckTxKey, MoneroWalletRpc as _, Refreshed};
+use serde::Serialize;
 use std::future::Future;
+use std::io::ErrorKind;
+use std::path::Path;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tokio::time::Interval;
 use url::Url;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct Wallet {
@@ -20,6 +26,7 @@ pub struct Wallet {
     name: String,
     main_address: monero::Address,
     sync_interval: Duration,
+    sync: std::sync::Mutex<SyncState>,
 }
 
 impl Wallet {
@@ -51,6 +58,7 @@ impl Wallet {
             name,
             main_address,
             sync_interval: env_config.monero_sync_interval(),
+            sync: std::sync::Mutex::default(),
         })
     }
 
@@ -171,7 +179,107 @@ impl Wallet {
         Ok(())
     }
 
-    pub async fn transfer(&self, request: TransferRequest) -> Result<TransferProof> {
+    /// Close the wallet, generate a view-only wallet of the given address and
+    /// return how much it received since the restore height. The view-only
+    /// wallet cannot see outgoing transfers, so spent outputs are counted as
+    /// well. The default wallet is re-loaded afterwards and the files of the
+    /// view-only wallet are deleted from the wallet directory.
+    pub async fn received_with_view_key(
+        &self,
+        wallet_dir: &Path,
+        address: Address,
+        private_view_key: PrivateViewKey,
+        restore_height: BlockHeight,
+    ) -> Result<Amount> {
+        if address.network != self.network {
+            bail!(
+                "Address is on {:?} but the wallet is on {:?}",
+                address.network,
+                self.network
+            );
+        }
+
+        let wallet = self.inner.lock().await;
+
+        let _ = wallet
+            .close_wallet()
+            .await
+            .context("Failed to close wallet")?;
+
+        let file_name = format!("view-only-{}", Uuid::new_v4());
+        let received: Result<Amount> = async {
+            let _ = wallet
+                .generate_from_keys(
+                    file_name.clone(),
+                    address.to_string(),
+                    String::from(""),
+                    PrivateKey::from(private_view_key).to_string(),
+                    restore_height.height,
+                    String::from(""),
+                    true,
+                )
+                .await
+                .context("Failed to generate view-only wallet")?;
+            let _ = wallet
+                .refresh()
+                .await
+                .context("Failed to refresh view-only wallet")?;
+            let balance = wallet
+                .get_balance(0)
+                .await
+                .context("Failed to get balance of view-only wallet")?;
+
+            Ok(Amount::from_piconero(balance.balance))
+        }
+        .await;
+
+        let _ = wallet
+            .open_wallet(self.name.clone())
+            .await
+            .context("Failed to re-open wallet")?;
+
+        for path in &[
+            wallet_dir.join(&file_name),
+            wallet_dir.join(format!("{}.keys", file_name)),
+        ] {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => {
+                    tracing::warn!(
+                        "Failed to delete view-only wallet file {}: {:#}",
+                        path.display(),
+                        error
+                    );
+                }
+            }
+        }
+
+        received
+    }
+
+    /// How much the transaction sent to the address, proven with the key of
+    /// the transaction.
+    pub async fn received_with_tx_key(
+        &self,
+        tx_hash: &TxHash,
+        tx_key: PrivateKey,
+        address: Address,
+    ) -> Result<Amount> {
+        let check = self
+            .inner
+            .lock()
+            .await
+            .check_tx_key(tx_hash.0.clone(), tx_key.to_string(), address.to_string())
+            .await
+            .context("Failed to check the transaction key")?;
+
+        Ok(Amount::from_piconero(check.received))
+    }
+
+    /// Transfers the requested amount, returning the proof of the transfer and
+    /// the fee that was paid for it.
+    pub async fn transfer(&self, request: TransferRequest) -> Result<(TransferProof, Amount)> {
         let TransferRequest {
             public_spend_key,
             public_view_key,
@@ -195,11 +303,27 @@ impl Wallet {
             "Successfully initiated Monero transfer"
         );
 
-        Ok(TransferProof::new(
+        let proof = TransferProof::new(
             TxHash(res.tx_hash),
             res.tx_key
                 .context("Missing tx_key in `transfer` response")?,
-        ))
+        );
+
+        Ok((proof, Amount::from_piconero(res.fee)))
+    }
+
+    /// Transfers the given amount to an address that is not part of a swap.
+    pub async fn transfer_to_address(&self, address: Address, amount: Amount) -> Result<TxHash> {
+        let res = self
+            .inner
+            .lock()
+            .await
+            .transfer_single(0, amount.as_piconero(), &address.to_string())
+            .await?;
+
+        tracing::debug!(%amount, to = %address, tx_id = %res.tx_hash, "Successfully initiated Monero transfer");
+
+        Ok(TxHash(res.tx_hash))
     }
 
     pub async fn watch_for_transfer(&self, request: WatchRequest) -> Result<(), InsufficientFunds> {
@@ -246,6 +370,8 @@ impl Wallet {
         Ok(())
     }
 
+    /// Sends all funds of the loaded wallet to the address, which may be a
+    /// subaddress.
     pub async fn sweep_all(&self, address: Address) -> Result<Vec<TxHash>> {
         let sweep_all = self
             .inner
@@ -265,6 +391,27 @@ impl Wallet {
         Ok(Amount::from_piconero(amount))
     }
 
+    /// Get the unlocked balance of the primary account, i.e. the part of the
+    /// balance that can be spent right away.
+    pub async fn get_unlocked_balance(&self) -> Result<Amount> {
+        let amount = self
+            .inner
+            .lock()
+            .await
+            .get_balance(0)
+            .await?
+            .unlocked_balance;
+
+        Ok(Amount::from_piconero(amount))
+    }
+
+    /// Closes the loaded wallet, which also stores it.
+    pub async fn close(&self) -> Result<()> {
+        self.inner.lock().await.close_wallet().await?;
+
+        Ok(())
+    }
+
     pub async fn block_height(&self) -> Result<BlockHeight> {
         Ok(self.inner.lock().await.get_height().await?)
     }
@@ -274,7 +421,168 @@ impl Wallet {
     }
 
     pub async fn refresh(&self) -> Result<Refreshed> {
-        Ok(self.inner.lock().await.refresh().await?)
+        let inner = self.inner.lock().await;
+
+        self.refresh_locked(&inner).await
+    }
+
+    /// Refreshes the wallet unless it is in use, returns `None` if the refresh
+    /// was skipped.
+    pub async fn refresh_if_idle(&self) -> Result<Option<Refreshed>> {
+        let inner = match self.inner.try_lock() {
+            Ok(inner) => inner,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(self.refresh_locked(&inner).await?))
+    }
+
+    async fn refresh_locked(&self, inner: &wallet::Client) -> Result<Refreshed> {
+        let refreshed = inner.refresh().await?;
+
+        // Only needed for reporting the sync progress, the refresh succeeded
+        // either way.
+        match inner.get_height().await {
+            Ok(height) => {
+                let mut sync = self.sync.lock().expect("lock is never poisoned");
+                sync.synced_height = Some(height.height);
+                sync.last_refresh = Some(Instant::now());
+            }
+            Err(error) => tracing::debug!("Failed to get height of refreshed wallet: {:#}", error),
+        }
+
+        Ok(refreshed)
+    }
+
+    /// Records the height of the Monero nodes, which the wallet is synced
+    /// against.
+    pub fn set_target_height(&self, height: u32) {
+        self.sync
+            .lock()
+            .expect("lock is never poisoned")
+            .target_height = Some(height);
+    }
+
+    /// How far the wallet got with its last refresh, without waiting for a
+    /// refresh in progress.
+    pub fn sync_progress(&self) -> SyncProgress {
+        self.sync
+            .lock()
+            .expect("lock is never poisoned")
+            .progress(Instant::now())
+    }
+
+    /// Proves that the primary account holds at least the given amount, the
+    /// proof is bound to the given message.
+    ///
+    /// The proof reveals the key images of the outputs it uses, which allows
+    /// whoever checks it to tell when they are spent.
+    pub async fn reserve_proof(&self, amount: Amount, message: String) -> Result<String> {
+        let proof = self
+            .inner
+            .lock()
+            .await
+            .get_reserve_proof(false, 0, amount.as_piconero(), message)
+            .await
+            .context("Failed to create reserve proof")?;
+
+        Ok(proof.signature)
+    }
+
+    /// Checks a reserve proof of the wallet with the given address, returns
+    /// the proven amount that is not yet spent or `None` if the proof is
+    /// invalid.
+    pub async fn check_reserve_proof(
+        &self,
+        address: String,
+        message: String,
+        signature: String,
+    ) -> Result<Option<Amount>> {
+        let checked = self
+            .inner
+            .lock()
+            .await
+            .check_reserve_proof(address, message, signature)
+            .await
+            .context("Failed to check reserve proof")?;
+
+        if !checked.good {
+            return Ok(None);
+        }
+
+        Ok(Some(Amount::from_piconero(
+            checked.total.saturating_sub(checked.spent),
+        )))
+    }
+
+    /// Switches the wallet RPC to another Monero node, which is not trusted
+    /// with the wallet's privacy.
+    pub async fn set_daemon(&self, address: String) -> Result<()> {
+        self.inner
+            .lock()
+            .await
+            .set_daemon(address.clone(), false)
+            .await
+            .with_context(|| format!("Failed to switch to Monero node {}", address))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SyncState {
+    synced_height: Option<u32>,
+    target_height: Option<u32>,
+    last_refresh: Option<Instant>,
+}
+
+impl SyncState {
+    fn progress(&self, now: Instant) -> SyncProgress {
+        let blocks_behind = match (self.synced_height, self.target_height) {
+            (Some(synced), Some(target)) => Some(target.saturating_sub(synced)),
+            _ => None,
+        };
+
+        SyncProgress {
+            synced_height: self.synced_height,
+            target_height: self.target_height,
+            blocks_behind,
+            last_refresh_secs_ago: self
+                .last_refresh
+                .map(|last_refresh| now.saturating_duration_since(last_refresh).as_secs()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SyncProgress {
+    /// The height the wallet was synced to by its last refresh, `None` if it
+    /// was not refreshed yet.
+    pub synced_height: Option<u32>,
+    /// The height of the Monero nodes, only known if the nodes are checked.
+    pub target_height: Option<u32>,
+    pub blocks_behind: Option<u32>,
+    pub last_refresh_secs_ago: Option<u64>,
+}
+
+/// Refreshes the wallet at the given interval, so it is close to the tip of
+/// the chain once a swap needs it instead of catching up on many blocks in
+/// the middle of the swap.
+///
+/// A refresh is skipped while the wallet is in use, e.g. for a transfer.
+pub async fn refresh_when_idle(wallet: Arc<Wallet>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        match wallet.refresh_if_idle().await {
+            Ok(Some(refreshed)) => tracing::debug!(
+                blocks_fetched = %refreshed.blocks_fetched,
+                progress = ?wallet.sync_progress(),
+                "Refreshed Monero wallet"
+            ),
+            Ok(None) => tracing::debug!("Monero wallet is in use, skipping refresh"),
+            Err(error) => tracing::warn!("Failed to refresh Monero wallet: {:#}", error),
+        }
     }
 }
 
@@ -305,21 +613,30 @@ where
     Fut: Future<Output = Result<CheckTxKey>>,
 {
     let mut seen_confirmations = 0u64;
+    let mut backoff = Policy::MONERO.backoff();
 
     while seen_confirmations < conf_target {
         check_interval.tick().await; // tick() at the beginning of the loop so every `continue` tick()s as well
 
         let tx = match fetch_tx(txid.clone()).await {
-            Ok(proof) => proof,
+            Ok(proof) => {
+                backoff.reset();
+                proof
+            }
             Err(error) => {
+                // Treating every error as transient and retrying is obviously wrong but the
+                // jsonrpc client is too primitive to differentiate between all the cases.
+                let retry_in = backoff
+                    .next_backoff()
+                    .unwrap_or(Policy::MONERO.max_interval);
                 tracing::debug!(
                     %txid,
-                    "Failed to retrieve tx from blockchain: {:#}", error
+                    "Failed to retrieve tx from blockchain, retrying in {}s: {:#}",
+                    retry_in.as_secs(),
+                    error
                 );
-                continue; // treating every error as transient and retrying
-                          // is obviously wrong but the jsonrpc client is
-                          // too primitive to differentiate between all the
-                          // cases
+                tokio::time::sleep(retry_in).await;
+                continue;
             }
         };
 
@@ -353,6 +670,25 @@ mod tests {
     use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
     use std::sync::Arc;
 
+    #[test]
+    fn sync_progress_counts_blocks_behind_the_nodes() {
+        let now = Instant::now();
+        let mut sync = SyncState::default();
+        assert_eq!(sync.progress(now).blocks_behind, None);
+
+        sync.synced_height = Some(100);
+        sync.target_height = Some(105);
+        sync.last_refresh = Some(now - Duration::from_secs(30));
+
+        let progress = sync.progress(now);
+        assert_eq!(progress.blocks_behind, Some(5));
+        assert_eq!(progress.last_refresh_secs_ago, Some(30));
+
+        // The nodes were checked before the last refresh.
+        sync.synced_height = Some(106);
+        assert_eq!(sync.progress(now).blocks_behind, Some(0));
+    }
+
     #[tokio::test]
     async fn given_exact_confirmations_does_not_fetch_tx_again() {
         let requests = Arc::new(AtomicU32::new(0));
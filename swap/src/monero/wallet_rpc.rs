@@ -43,6 +43,7 @@ pub struct ExecutableNotFoundInArchive;
 pub struct WalletRpcProcess {
     _child: Child,
     port: u16,
+    wallet_dir: PathBuf,
 }
 
 impl WalletRpcProcess {
@@ -50,6 +51,11 @@ impl WalletRpcProcess {
         Url::parse(&format!("http://127.0.0.1:{}/json_rpc", self.port))
             .expect("Static url template is always valid")
     }
+
+    /// Where the monero-wallet-rpc stores the wallet files.
+    pub fn wallet_dir(&self) -> &Path {
+        &self.wallet_dir
+    }
 }
 
 pub struct WalletRpc {
@@ -154,7 +160,7 @@ impl WalletRpc {
             .arg(format!("{}", port))
             .arg("--disable-rpc-login")
             .arg("--wallet-dir")
-            .arg(self.working_dir.join("monero-data"))
+            .arg(self.wallet_dir())
             .spawn()?;
 
         let stdout = child
@@ -186,9 +192,14 @@ impl WalletRpc {
         Ok(WalletRpcProcess {
             _child: child,
             port,
+            wallet_dir: self.wallet_dir(),
         })
     }
 
+    fn wallet_dir(&self) -> PathBuf {
+        self.working_dir.join("monero-data")
+    }
+
     fn archive_path(&self) -> PathBuf {
         self.working_dir.join("monero-cli-wallet.archive")
     }
@@ -1,9 +1,12 @@
 mod impl_from_rr_event;
 
 pub mod cbor_request_response;
+pub mod early_refund;
 pub mod encrypted_signature;
 pub mod json_pull_codec;
+pub mod protocol_error;
 pub mod quote;
+pub mod receipt;
 pub mod redial;
 pub mod rendezvous;
 pub mod swap_setup;
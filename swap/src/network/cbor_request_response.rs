@@ -14,17 +14,27 @@ pub const BUF_SIZE: usize = 1024 * 1024;
 
 #[derive(Clone, Copy, Debug)]
 pub struct CborCodec<P, Req, Res> {
+    /// Messages larger than this are rejected without reading them into
+    /// memory.
+    max_message_size: usize,
     phantom: PhantomData<(P, Req, Res)>,
 }
 
-impl<P, Req, Res> Default for CborCodec<P, Req, Res> {
-    fn default() -> Self {
+impl<P, Req, Res> CborCodec<P, Req, Res> {
+    pub fn new(max_message_size: usize) -> Self {
         Self {
+            max_message_size,
             phantom: PhantomData::default(),
         }
     }
 }
 
+impl<P, Req, Res> Default for CborCodec<P, Req, Res> {
+    fn default() -> Self {
+        Self::new(BUF_SIZE)
+    }
+}
+
 #[async_trait]
 impl<P, Req, Res> RequestResponseCodec for CborCodec<P, Req, Res>
 where
@@ -40,10 +50,12 @@ where
     where
         T: AsyncRead + Unpin + Send,
     {
-        let message = upgrade::read_one(io, BUF_SIZE).await.map_err(|e| match e {
-            ReadOneError::Io(err) => err,
-            e => io::Error::new(io::ErrorKind::Other, e),
-        })?;
+        let message = upgrade::read_one(io, self.max_message_size)
+            .await
+            .map_err(|e| match e {
+                ReadOneError::Io(err) => err,
+                e => io::Error::new(io::ErrorKind::Other, e),
+            })?;
         let mut de = serde_cbor::Deserializer::from_slice(&message);
         let msg = Req::deserialize(&mut de)
             .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
@@ -59,7 +71,7 @@ where
     where
         T: AsyncRead + Unpin + Send,
     {
-        let message = upgrade::read_one(io, BUF_SIZE)
+        let message = upgrade::read_one(io, self.max_message_size)
             .await
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         let mut de = serde_cbor::Deserializer::from_slice(&message);
@@ -102,3 +114,44 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[derive(Clone, Debug)]
+    struct TestProtocol;
+
+    impl ProtocolName for TestProtocol {
+        fn protocol_name(&self) -> &[u8] {
+            b"/test/1.0.0"
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_messages_larger_than_the_limit() {
+        let mut codec = CborCodec::<TestProtocol, Vec<u8>, ()>::new(64);
+
+        let mut small = Vec::new();
+        codec
+            .write_request(&TestProtocol, &mut small, vec![0; 16])
+            .await
+            .unwrap();
+        let mut large = Vec::new();
+        codec
+            .write_request(&TestProtocol, &mut large, vec![0; 128])
+            .await
+            .unwrap();
+
+        let request = codec
+            .read_request(&TestProtocol, &mut Cursor::new(small))
+            .await
+            .unwrap();
+        assert_eq!(request, vec![0; 16]);
+        assert!(codec
+            .read_request(&TestProtocol, &mut Cursor::new(large))
+            .await
+            .is_err());
+    }
+}
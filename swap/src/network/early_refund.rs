@@ -0,0 +1,86 @@
+//! Lets Alice hand Bob her signature of the early refund transaction if she
+//! decides not to lock the Monero.
+//!
+//! Bob does not have to wait for the cancel timelock to get his Bitcoin back
+//! then. CLIs that do not speak this protocol keep refunding after the
+//! cancel timelock.
+
+use crate::network::cbor_request_response::CborCodec;
+use crate::{asb, bitcoin, cli};
+use libp2p::core::ProtocolName;
+use libp2p::request_response::{
+    ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+    RequestResponseMessage,
+};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const PROTOCOL: &str = "/comit/xmr/btc/early_refund/1.0.0";
+/// The request is a few hundred bytes, anything much larger is not sent by an
+/// honest peer.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+type OutEvent = RequestResponseEvent<Request, ()>;
+type Message = RequestResponseMessage<Request, ()>;
+
+pub type Behaviour = RequestResponse<CborCodec<EarlyRefundProtocol, Request, ()>>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EarlyRefundProtocol;
+
+impl ProtocolName for EarlyRefundProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL.as_bytes()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub swap_id: Uuid,
+    pub tx_early_refund_sig: bitcoin::Signature,
+}
+
+pub fn alice() -> Behaviour {
+    Behaviour::new(
+        CborCodec::new(MAX_MESSAGE_SIZE),
+        vec![(EarlyRefundProtocol, ProtocolSupport::Outbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+pub fn bob() -> Behaviour {
+    Behaviour::new(
+        CborCodec::new(MAX_MESSAGE_SIZE),
+        vec![(EarlyRefundProtocol, ProtocolSupport::Inbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+impl From<(PeerId, Message)> for asb::OutEvent {
+    fn from((peer, message): (PeerId, Message)) -> Self {
+        match message {
+            Message::Request { .. } => Self::unexpected_request(peer),
+            Message::Response { request_id, .. } => Self::EarlyRefundAcknowledged {
+                peer,
+                id: request_id,
+            },
+        }
+    }
+}
+crate::impl_from_rr_event!(OutEvent, asb::OutEvent, PROTOCOL);
+
+impl From<(PeerId, Message)> for cli::OutEvent {
+    fn from((peer, message): (PeerId, Message)) -> Self {
+        match message {
+            Message::Request {
+                request, channel, ..
+            } => Self::EarlyRefundReceived {
+                msg: Box::new(request),
+                channel,
+                peer,
+            },
+            Message::Response { .. } => Self::unexpected_response(peer),
+        }
+    }
+}
+crate::impl_from_rr_event!(OutEvent, cli::OutEvent, PROTOCOL);
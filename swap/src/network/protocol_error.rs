@@ -0,0 +1,59 @@
+//! Why a seller refuses a request, sent to the taker in quote and swap setup
+//! responses.
+//!
+//! Instead of closing the stream, which leaves the taker with nothing but a
+//! failed request, the seller names the reason so the taker can tell the user
+//! what to do about it.
+
+use crate::network::swap_setup::version::PROTOCOL_VERSION;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    thiserror::Error,
+)]
+pub enum ProtocolError {
+    #[error("Seller does not have enough Monero for this swap right now, please try again later or swap a smaller amount")]
+    InsufficientLiquidity,
+    #[error("Seller only accepts swaps of at least {min}, please swap a larger amount")]
+    AmountBelowMinimum {
+        #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+        min: bitcoin::Amount,
+    },
+    #[error("Seller cannot determine the XMR/BTC rate right now, please try again later")]
+    RateUnavailable,
+    #[error("Seller already aborted this swap or set it up differently, please start a new swap")]
+    AlreadySwapping,
+    #[error("The Bitcoin offered for this swap is spent or used in another swap setup, please wait until the wallet is synced and try again")]
+    FundsUnavailable,
+    #[error("Seller requires at least swap protocol version {min_version}, but this CLI speaks version {version}, please update", version = PROTOCOL_VERSION)]
+    VersionMismatch { min_version: u32 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_compactly() {
+        let json = serde_json::to_string(&ProtocolError::AmountBelowMinimum {
+            min: bitcoin::Amount::from_sat(100_000),
+        })
+        .unwrap();
+
+        assert_eq!(json, r#"{"AmountBelowMinimum":{"min":100000}}"#);
+        assert_eq!(
+            serde_json::to_string(&ProtocolError::RateUnavailable).unwrap(),
+            r#""RateUnavailable""#
+        );
+    }
+}
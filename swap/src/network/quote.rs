@@ -1,18 +1,33 @@
 use crate::network::json_pull_codec::JsonPullCodec;
-use crate::{asb, bitcoin, cli};
+use crate::network::protocol_error::ProtocolError;
+use crate::{asb, bitcoin, cli, monero};
+use anyhow::{bail, Context, Result};
 use libp2p::core::ProtocolName;
+use libp2p::identity::{Keypair, PublicKey};
 use libp2p::request_response::{
     ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
     RequestResponseMessage,
 };
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const PROTOCOL: &str = "/comit/xmr/btc/bid-quote/1.0.0";
-pub type OutEvent = RequestResponseEvent<(), BidQuote>;
-pub type Message = RequestResponseMessage<(), BidQuote>;
+pub type OutEvent = RequestResponseEvent<(), SignedBidQuote>;
+pub type Message = RequestResponseMessage<(), SignedBidQuote>;
 
-pub type Behaviour = RequestResponse<JsonPullCodec<BidQuoteProtocol, BidQuote>>;
+pub type Behaviour = RequestResponse<JsonPullCodec<BidQuoteProtocol, SignedBidQuote>>;
+
+/// Domain separator of the message that is signed for a quote.
+const SIGNATURE_DOMAIN: &[u8] = b"/comit/xmr/btc/bid-quote/signature";
+
+/// Domain separator of the message a reserve proof is bound to.
+const RESERVE_PROOF_DOMAIN: &str = "/comit/xmr/btc/reserve-proof";
+
+/// Reserve proofs older than this are not trusted, the reserves might have
+/// been spent since.
+pub const RESERVE_PROOF_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const MAX_CLOCK_DRIFT: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct BidQuoteProtocol;
@@ -37,6 +52,257 @@ pub struct BidQuote {
     pub max_quantity: bitcoin::Amount,
 }
 
+/// A [`BidQuote`] together with its expiry, the market price it is based on
+/// and a signature by the seller's peer key.
+///
+/// The additional fields are optional and flattened next to the quote, which
+/// keeps the message compatible with peers that only know [`BidQuote`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedBidQuote {
+    #[serde(flatten)]
+    pub quote: BidQuote,
+    /// Seconds since the unix epoch after which the quote is no longer valid.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// The market price before the seller's spread was applied.
+    #[serde(default, with = "::bitcoin::util::amount::serde::as_sat::opt")]
+    pub market_price: Option<bitcoin::Amount>,
+    #[serde(default)]
+    pub signature: Option<QuoteSignature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reserve_proof: Option<ReserveProof>,
+    /// Why the seller does not quote, sent with a zero quote that older
+    /// takers understand as not accepting any swaps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ProtocolError>,
+}
+
+/// Signature over a quote and the public key it can be verified with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuoteSignature {
+    /// Protobuf encoded public key of the seller, base64 encoded.
+    pub public_key: String,
+    /// Base64 encoded signature.
+    pub signature: String,
+}
+
+impl SignedBidQuote {
+    /// Signs the quote with the seller's identity, valid for the given
+    /// duration.
+    pub fn sign(
+        quote: BidQuote,
+        market_price: bitcoin::Amount,
+        valid_for: Duration,
+        identity: &Keypair,
+    ) -> Result<Self> {
+        let expires_at = (SystemTime::now() + valid_for)
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the unix epoch")?
+            .as_secs();
+
+        let mut signed = Self {
+            quote,
+            expires_at: Some(expires_at),
+            market_price: Some(market_price),
+            signature: None,
+            reserve_proof: None,
+            error: None,
+        };
+
+        let signature = identity
+            .sign(&signed.message())
+            .context("Failed to sign quote")?;
+
+        signed.signature = Some(QuoteSignature {
+            public_key: base64::encode(identity.public().into_protobuf_encoding()),
+            signature: base64::encode(signature),
+        });
+
+        Ok(signed)
+    }
+
+    /// A zero quote explaining why the seller does not quote.
+    pub fn refused(error: ProtocolError) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::from(BidQuote {
+                price: bitcoin::Amount::ZERO,
+                min_quantity: bitcoin::Amount::ZERO,
+                max_quantity: bitcoin::Amount::ZERO,
+            })
+        }
+    }
+
+    /// Verifies that the quote was signed by the given seller and has not yet
+    /// expired, failing with the [`ProtocolError`] if the seller refused to
+    /// quote.
+    ///
+    /// Quotes without a signature or expiry are accepted as sent by sellers
+    /// that do not support them yet.
+    pub fn verify(&self, seller: PeerId, now: SystemTime) -> Result<BidQuote> {
+        if let Some(error) = self.error {
+            bail!(error)
+        }
+
+        if let Some(expires_at) = self.expires_at {
+            let now = now
+                .duration_since(UNIX_EPOCH)
+                .context("System time is before the unix epoch")?
+                .as_secs();
+
+            if now > expires_at {
+                bail!(QuoteExpired { expires_at })
+            }
+        }
+
+        if let Some(QuoteSignature {
+            public_key,
+            signature,
+        }) = &self.signature
+        {
+            let public_key = PublicKey::from_protobuf_encoding(&base64::decode(public_key)?)
+                .context("Failed to decode public key of quote signature")?;
+
+            if PeerId::from_public_key(public_key.clone()) != seller {
+                bail!(InvalidQuoteSignature)
+            }
+            if !public_key.verify(&self.message(), &base64::decode(signature)?) {
+                bail!(InvalidQuoteSignature)
+            }
+        }
+
+        Ok(self.quote)
+    }
+
+    /// The seller's markup on the market price, i.e. the part of the price per
+    /// XMR that is kept as fee.
+    pub fn maker_fee(&self) -> Option<bitcoin::Amount> {
+        self.market_price
+            .map(|market_price| self.quote.price - market_price.min(self.quote.price))
+    }
+
+    fn message(&self) -> Vec<u8> {
+        let mut message = SIGNATURE_DOMAIN.to_vec();
+        message.extend_from_slice(&self.quote.price.as_sat().to_be_bytes());
+        message.extend_from_slice(&self.quote.min_quantity.as_sat().to_be_bytes());
+        message.extend_from_slice(&self.quote.max_quantity.as_sat().to_be_bytes());
+        message.extend_from_slice(&self.expires_at.unwrap_or_default().to_be_bytes());
+        message.extend_from_slice(
+            &self
+                .market_price
+                .unwrap_or(bitcoin::Amount::ZERO)
+                .as_sat()
+                .to_be_bytes(),
+        );
+
+        message
+    }
+}
+
+/// An unsigned quote without expiry, as sent by older sellers.
+impl From<BidQuote> for SignedBidQuote {
+    fn from(quote: BidQuote) -> Self {
+        Self {
+            quote,
+            expires_at: None,
+            market_price: None,
+            signature: None,
+            reserve_proof: None,
+            error: None,
+        }
+    }
+}
+
+/// Proof that the seller holds the Monero it quotes, created with the
+/// `get_reserve_proof` call of monero-wallet-rpc.
+///
+/// The proof is bound to the seller's peer id and its creation time, so it
+/// can neither be passed off by another seller nor replayed after the
+/// reserves were spent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReserveProof {
+    /// The primary address of the seller's Monero wallet.
+    pub address: String,
+    /// Seconds since the unix epoch at which the proof was created.
+    pub created_at: u64,
+    pub signature: String,
+}
+
+impl ReserveProof {
+    /// Proves that the seller holds at least `amount`, which should exclude
+    /// the Monero reserved for swaps in flight.
+    pub async fn create(
+        monero_wallet: &monero::Wallet,
+        amount: monero::Amount,
+        seller: PeerId,
+        now: SystemTime,
+    ) -> Result<Self> {
+        let created_at = now
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the unix epoch")?
+            .as_secs();
+        let signature = monero_wallet
+            .reserve_proof(amount, Self::message(seller, created_at))
+            .await?;
+
+        Ok(Self {
+            address: monero_wallet.get_main_address().to_string(),
+            created_at,
+            signature,
+        })
+    }
+
+    /// The Monero proven to be held by the given seller, `None` if the proof
+    /// is invalid or too old.
+    pub async fn verify(
+        &self,
+        monero_wallet: &monero::Wallet,
+        seller: PeerId,
+        now: SystemTime,
+    ) -> Result<Option<monero::Amount>> {
+        if !self.is_fresh(now)? {
+            return Ok(None);
+        }
+
+        monero_wallet
+            .check_reserve_proof(
+                self.address.clone(),
+                Self::message(seller, self.created_at),
+                self.signature.clone(),
+            )
+            .await
+    }
+
+    /// Whether the proof is younger than [`RESERVE_PROOF_MAX_AGE`]. Proofs
+    /// dated further into the future than the tolerated clock drift are
+    /// rejected, as they would stay valid for too long.
+    pub fn is_fresh(&self, now: SystemTime) -> Result<bool> {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the unix epoch")?
+            .as_secs();
+
+        let too_old = now.saturating_sub(self.created_at) > RESERVE_PROOF_MAX_AGE.as_secs();
+        let from_the_future = self.created_at > now + MAX_CLOCK_DRIFT.as_secs();
+
+        Ok(!too_old && !from_the_future)
+    }
+
+    fn message(seller: PeerId, created_at: u64) -> String {
+        format!("{}/{}/{}", RESERVE_PROOF_DOMAIN, seller, created_at)
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Quote expired at {expires_at}")]
+pub struct QuoteExpired {
+    expires_at: u64,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+#[error("Quote is not signed by the seller")]
+pub struct InvalidQuoteSignature;
+
 /// Constructs a new instance of the `quote` behaviour to be used by the ASB.
 ///
 /// The ASB is always listening and only supports inbound connections, i.e.
@@ -86,3 +352,153 @@ impl From<(PeerId, Message)> for cli::OutEvent {
     }
 }
 crate::impl_from_rr_event!(OutEvent, cli::OutEvent, PROTOCOL);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bid_quote() -> BidQuote {
+        BidQuote {
+            price: bitcoin::Amount::from_sat(1_000),
+            min_quantity: bitcoin::Amount::ZERO,
+            max_quantity: bitcoin::Amount::ONE_BTC,
+        }
+    }
+
+    #[test]
+    fn signed_quote_can_be_verified() {
+        let identity = Keypair::generate_ed25519();
+        let seller = identity.public().into_peer_id();
+
+        let quote = SignedBidQuote::sign(
+            bid_quote(),
+            bitcoin::Amount::from_sat(980),
+            Duration::from_secs(60),
+            &identity,
+        )
+        .unwrap();
+        let quote: SignedBidQuote =
+            serde_json::from_slice(&serde_json::to_vec(&quote).unwrap()).unwrap();
+
+        assert_eq!(
+            quote.verify(seller, SystemTime::now()).unwrap(),
+            bid_quote()
+        );
+        assert_eq!(quote.maker_fee(), Some(bitcoin::Amount::from_sat(20)));
+    }
+
+    #[test]
+    fn quote_signed_by_other_peer_is_rejected() {
+        let identity = Keypair::generate_ed25519();
+        let other_seller = PeerId::random();
+
+        let quote = SignedBidQuote::sign(
+            bid_quote(),
+            bid_quote().price,
+            Duration::from_secs(60),
+            &identity,
+        )
+        .unwrap();
+        let error = quote.verify(other_seller, SystemTime::now()).unwrap_err();
+
+        assert!(error.downcast_ref::<InvalidQuoteSignature>().is_some());
+    }
+
+    #[test]
+    fn tampered_quote_is_rejected() {
+        let identity = Keypair::generate_ed25519();
+        let seller = identity.public().into_peer_id();
+
+        let mut quote = SignedBidQuote::sign(
+            bid_quote(),
+            bid_quote().price,
+            Duration::from_secs(60),
+            &identity,
+        )
+        .unwrap();
+        quote.quote.price = bitcoin::Amount::from_sat(1);
+        let error = quote.verify(seller, SystemTime::now()).unwrap_err();
+
+        assert!(error.downcast_ref::<InvalidQuoteSignature>().is_some());
+    }
+
+    #[test]
+    fn expired_quote_is_rejected() {
+        let identity = Keypair::generate_ed25519();
+        let seller = identity.public().into_peer_id();
+
+        let quote = SignedBidQuote::sign(
+            bid_quote(),
+            bid_quote().price,
+            Duration::from_secs(60),
+            &identity,
+        )
+        .unwrap();
+        let error = quote
+            .verify(seller, SystemTime::now() + Duration::from_secs(120))
+            .unwrap_err();
+
+        assert!(error.downcast_ref::<QuoteExpired>().is_some());
+    }
+
+    #[test]
+    fn quote_without_reserve_proof_omits_the_field() {
+        let quote = SignedBidQuote::from(bid_quote());
+
+        let json = serde_json::to_string(&quote).unwrap();
+
+        assert!(!json.contains("reserve_proof"));
+    }
+
+    #[test]
+    fn stale_reserve_proof_is_not_fresh() {
+        let created_at = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let proof = ReserveProof {
+            address: String::new(),
+            created_at: 1_000_000,
+            signature: String::new(),
+        };
+
+        assert!(proof
+            .is_fresh(created_at + Duration::from_secs(60))
+            .unwrap());
+        assert!(!proof
+            .is_fresh(created_at + RESERVE_PROOF_MAX_AGE + Duration::from_secs(1))
+            .unwrap());
+        assert!(proof
+            .is_fresh(created_at - Duration::from_secs(60))
+            .unwrap());
+        assert!(!proof
+            .is_fresh(created_at - MAX_CLOCK_DRIFT - Duration::from_secs(1))
+            .unwrap());
+    }
+
+    #[test]
+    fn refused_quote_fails_with_the_reason() {
+        let quote = SignedBidQuote::refused(ProtocolError::RateUnavailable);
+        let quote: SignedBidQuote =
+            serde_json::from_slice(&serde_json::to_vec(&quote).unwrap()).unwrap();
+
+        let error = quote
+            .verify(PeerId::random(), SystemTime::now())
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::RateUnavailable)
+        );
+    }
+
+    #[test]
+    fn unsigned_quote_of_older_sellers_is_accepted() {
+        let quote: SignedBidQuote =
+            serde_json::from_str(r#"{"price":1000,"min_quantity":0,"max_quantity":100000000}"#)
+                .unwrap();
+
+        assert_eq!(quote, SignedBidQuote::from(bid_quote()));
+        assert_eq!(
+            quote.verify(PeerId::random(), SystemTime::now()).unwrap(),
+            bid_quote()
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Lets Bob ask Alice to countersign the receipt of a completed swap.
+//!
+//! Bob sends the receipt signed by him, Alice responds with her signature if
+//! the receipt matches the swap she recorded. Sellers that do not speak this
+//! protocol leave Bob without a receipt, the swap itself is not affected.
+//! Failing to request the receipt is therefore reported for the request
+//! instead of as a failure of the connection.
+
+use crate::network::cbor_request_response::CborCodec;
+use crate::receipt::Receipt;
+use crate::{asb, bitcoin, cli};
+use anyhow::anyhow;
+use libp2p::core::ProtocolName;
+use libp2p::request_response::{
+    ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+    RequestResponseMessage,
+};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const PROTOCOL: &str = "/comit/xmr/btc/receipt/1.0.0";
+/// The request is a few hundred bytes, anything much larger is not sent by an
+/// honest peer.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+type OutEvent = RequestResponseEvent<Request, Response>;
+type Message = RequestResponseMessage<Request, Response>;
+
+pub type Behaviour = RequestResponse<CborCodec<ReceiptProtocol, Request, Response>>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReceiptProtocol;
+
+impl ProtocolName for ReceiptProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL.as_bytes()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub swap_id: Uuid,
+    pub receipt: Receipt,
+    pub buyer_signature: bitcoin::Signature,
+}
+
+pub type Response = Result<bitcoin::Signature, Error>;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("Seller has not completed the swap yet")]
+    NotCompleted,
+    #[error("Seller does not know the swap")]
+    UnknownSwap,
+    #[error("Seller recorded the swap differently")]
+    Mismatch,
+    #[error("Seller failed to sign the receipt")]
+    Internal,
+}
+
+pub fn alice() -> Behaviour {
+    Behaviour::new(
+        CborCodec::new(MAX_MESSAGE_SIZE),
+        vec![(ReceiptProtocol, ProtocolSupport::Inbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+pub fn bob() -> Behaviour {
+    Behaviour::new(
+        CborCodec::new(MAX_MESSAGE_SIZE),
+        vec![(ReceiptProtocol, ProtocolSupport::Outbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+impl From<(PeerId, Message)> for asb::OutEvent {
+    fn from((peer, message): (PeerId, Message)) -> Self {
+        match message {
+            Message::Request {
+                request, channel, ..
+            } => Self::ReceiptRequested {
+                msg: Box::new(request),
+                channel,
+                peer,
+            },
+            Message::Response { .. } => Self::unexpected_response(peer),
+        }
+    }
+}
+crate::impl_from_rr_event!(OutEvent, asb::OutEvent, PROTOCOL);
+
+impl From<(PeerId, Message)> for cli::OutEvent {
+    fn from((peer, message): (PeerId, Message)) -> Self {
+        match message {
+            Message::Request { .. } => Self::unexpected_request(peer),
+            Message::Response {
+                request_id,
+                response,
+            } => Self::ReceiptReceived {
+                id: request_id,
+                response,
+            },
+        }
+    }
+}
+
+impl From<OutEvent> for cli::OutEvent {
+    fn from(event: OutEvent) -> Self {
+        match event {
+            RequestResponseEvent::Message { message, peer, .. } => Self::from((peer, message)),
+            RequestResponseEvent::OutboundFailure {
+                request_id, error, ..
+            } => Self::ReceiptRequestFailed {
+                id: request_id,
+                error: anyhow!("{} failed: {}", PROTOCOL, error),
+            },
+            // Bob only sends requests of this protocol.
+            RequestResponseEvent::InboundFailure { .. }
+            | RequestResponseEvent::ResponseSent { .. } => Self::Other,
+        }
+    }
+}
@@ -1,6 +1,5 @@
+use crate::backoff::{Backoff, ExponentialBackoff, Policy};
 use crate::cli;
-use backoff::backoff::Backoff;
-use backoff::ExponentialBackoff;
 use futures::future::FutureExt;
 use libp2p::core::connection::ConnectionId;
 use libp2p::core::Multiaddr;
@@ -34,13 +33,11 @@ impl Behaviour {
         Self {
             peer,
             sleep: None,
-            backoff: ExponentialBackoff {
+            backoff: Policy {
                 initial_interval: interval,
-                current_interval: interval,
-                // give up dialling after 5 minutes
-                max_elapsed_time: Some(Duration::from_secs(5 * 60)),
-                ..ExponentialBackoff::default()
-            },
+                ..Policy::REDIAL
+            }
+            .backoff(),
         }
     }
 
@@ -10,6 +10,16 @@ pub enum XmrBtcNamespace {
 const MAINNET: &str = "xmr-btc-swap-mainnet";
 const TESTNET: &str = "xmr-btc-swap-testnet";
 
+impl XmrBtcNamespace {
+    pub fn from_is_testnet(is_testnet: bool) -> XmrBtcNamespace {
+        if is_testnet {
+            XmrBtcNamespace::Testnet
+        } else {
+            XmrBtcNamespace::Mainnet
+        }
+    }
+}
+
 impl fmt::Display for XmrBtcNamespace {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
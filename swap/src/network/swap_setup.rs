@@ -1,12 +1,18 @@
 use crate::monero;
+use crate::network::protocol_error::ProtocolError;
+use crate::network::swap_setup::version::Capabilities;
 use anyhow::{Context, Result};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use libp2p::core::upgrade;
 use libp2p::swarm::NegotiatedSubstream;
+use libp2p::PeerId;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 pub mod alice;
 pub mod bob;
+pub mod version;
 
 pub const BUF_SIZE: usize = 1024 * 1024;
 
@@ -45,17 +51,75 @@ pub struct BlockchainNetwork {
     pub monero: monero::Network,
 }
 
+/// The nonce the taker draws for a swap and announces with its spot price
+/// request, the funding proof of the taker commits to it.
+///
+/// The taker stores it with the swap, so a retried setup announces the same
+/// nonce and maps onto the same swap.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionNonce([u8; 32]);
+
+impl SessionNonce {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// The id of the swap with the given maker.
+    ///
+    /// The nonce of the maker is its peer id, which the taker knows before it
+    /// stores anything, so both peers store the swap under this id from the
+    /// start. The same nonce yields a different id with every maker.
+    pub fn swap_id(&self, maker: PeerId) -> Uuid {
+        let mut engine = sha256::HashEngine::default();
+        engine.input(b"xmr-btc-swap/swap-id");
+        engine.input(&self.0);
+        engine.input(&maker.to_bytes());
+        let hash = sha256::Hash::from_engine(engine);
+
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&hash[..16]);
+
+        uuid::Builder::from_bytes(bytes)
+            .set_variant(uuid::Variant::RFC4122)
+            .set_version(uuid::Version::Random)
+            .build()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SpotPriceRequest {
     #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
     pub btc: bitcoin::Amount,
     pub blockchain_network: BlockchainNetwork,
+    /// A signed transaction that spends the Bitcoin of the taker, but can
+    /// never be mined. Older takers do not send one.
+    #[serde(default)]
+    pub funding_proof: Option<bitcoin::Transaction>,
+    /// Takers that predate the version negotiation speak version 0.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    /// Only sent by takers that support [`Capabilities::SESSION_NONCES`].
+    #[serde(default)]
+    pub session_nonce: Option<SessionNonce>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SpotPriceResponse {
     Xmr(monero::Amount),
     Error(SpotPriceError),
+    /// Only sent to takers that announce a version, older takers cannot
+    /// decode it.
+    Accepted {
+        xmr: monero::Amount,
+        version: u32,
+        capabilities: Capabilities,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -81,9 +145,19 @@ pub enum SpotPriceError {
         cli: BlockchainNetwork,
         asb: BlockchainNetwork,
     },
+    TooManyRequests,
+    FundingProofRequired,
+    IncompatibleVersion {
+        min_version: u32,
+    },
+    InvalidFundingProof,
+    SwapAlreadyExists,
     /// To be used for errors that cannot be explained on the CLI side (e.g.
     /// rate update problems on the seller side)
     Other,
+    /// Only sent to takers that support [`Capabilities::PROTOCOL_ERRORS`],
+    /// older takers cannot decode it.
+    Refused(ProtocolError),
 }
 
 pub async fn read_cbor_message<T>(substream: &mut NegotiatedSubstream) -> Result<T>
@@ -112,3 +186,42 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retried_setup_derives_the_same_swap_id() {
+        let maker = PeerId::random();
+        let nonce = SessionNonce::random();
+
+        let first = nonce.swap_id(maker);
+        assert_eq!(first, nonce.swap_id(maker));
+        assert_eq!(first.get_version(), Some(uuid::Version::Random));
+
+        assert_ne!(first, SessionNonce::random().swap_id(maker));
+        assert_ne!(first, nonce.swap_id(PeerId::random()));
+    }
+
+    #[test]
+    fn refusal_reasons_are_only_sent_to_takers_that_decode_them() {
+        let error = alice::Error::BalanceTooLow {
+            balance: monero::Amount::ZERO,
+            buy: bitcoin::Amount::ONE_BTC,
+        };
+
+        assert!(matches!(
+            error.to_error_response(Capabilities::SUPPORTED),
+            SpotPriceError::Refused(ProtocolError::InsufficientLiquidity)
+        ));
+        assert!(matches!(
+            error.to_error_response(Capabilities::NONE),
+            SpotPriceError::BalanceTooLow { .. }
+        ));
+        assert_eq!(
+            bob::Error::from(error.to_error_response(Capabilities::SUPPORTED)),
+            bob::Error::Refused(ProtocolError::InsufficientLiquidity)
+        );
+    }
+}
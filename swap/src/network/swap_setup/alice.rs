@@ -1,12 +1,16 @@
 use crate::asb::LatestRate;
+use crate::network::protocol_error::ProtocolError;
 use crate::network::swap_setup;
+use crate::network::swap_setup::version::{
+    self, Capabilities, Negotiated, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use crate::network::swap_setup::{
-    protocol, BlockchainNetwork, SpotPriceError, SpotPriceRequest, SpotPriceResponse,
+    protocol, BlockchainNetwork, SessionNonce, SpotPriceError, SpotPriceRequest, SpotPriceResponse,
 };
-use crate::protocol::alice::{State0, State3};
+use crate::protocol::alice::{KeyPool, State0, State3};
 use crate::protocol::{Message0, Message2, Message4};
 use crate::{asb, bitcoin, env, monero};
-use anyhow::{anyhow, Context, Result};
+use anyhow::{ensure, Context, Result};
 use futures::future::{BoxFuture, OptionFuture};
 use futures::{AsyncWriteExt, FutureExt};
 use libp2p::core::connection::ConnectionId;
@@ -27,7 +31,8 @@ use void::Void;
 #[allow(clippy::large_enum_variant)]
 pub enum OutEvent {
     Initiated {
-        send_wallet_snapshot: bmrng::RequestReceiver<bitcoin::Amount, WalletSnapshot>,
+        peer_id: PeerId,
+        send_wallet_snapshot: bmrng::RequestReceiver<SwapRequest, Result<WalletSnapshot, Error>>,
     },
     Completed {
         peer_id: PeerId,
@@ -40,6 +45,19 @@ pub enum OutEvent {
     },
 }
 
+/// What the taker asks for, checked before we capture a [`WalletSnapshot`] for
+/// the swap.
+#[derive(Debug)]
+pub struct SwapRequest {
+    pub btc: bitcoin::Amount,
+    pub funding_proof: Option<bitcoin::Transaction>,
+    /// The id of the swap, derived from the session nonce of takers that
+    /// support [`Capabilities::SESSION_NONCES`].
+    pub swap_id: Option<Uuid>,
+    /// The nonce the funding proof commits to.
+    pub session_nonce: Option<SessionNonce>,
+}
+
 #[derive(Debug)]
 pub struct WalletSnapshot {
     balance: monero::Amount,
@@ -52,6 +70,10 @@ pub struct WalletSnapshot {
 
     redeem_fee: bitcoin::Amount,
     punish_fee: bitcoin::Amount,
+
+    /// The Monero of a stored setup the taker retries, offered again instead
+    /// of the Monero at the latest rate.
+    stored_xmr: Option<monero::Amount>,
 }
 
 impl WalletSnapshot {
@@ -77,16 +99,27 @@ impl WalletSnapshot {
             punish_address,
             redeem_fee,
             punish_fee,
+            stored_xmr: None,
         })
     }
+
+    /// Sets the swap up again with the Monero amount of the stored setup.
+    pub fn retrying(self, stored_xmr: monero::Amount) -> Self {
+        Self {
+            stored_xmr: Some(stored_xmr),
+            ..self
+        }
+    }
 }
 
 impl From<OutEvent> for asb::OutEvent {
     fn from(event: OutEvent) -> Self {
         match event {
             OutEvent::Initiated {
+                peer_id,
                 send_wallet_snapshot,
             } => asb::OutEvent::SwapSetupInitiated {
+                peer: peer_id,
                 send_wallet_snapshot,
             },
             OutEvent::Completed {
@@ -98,9 +131,16 @@ impl From<OutEvent> for asb::OutEvent {
                 swap_id,
                 state3,
             },
-            OutEvent::Error { peer_id, error } => asb::OutEvent::Failure {
-                peer: peer_id,
-                error: anyhow!(error),
+            // Swaps we decline are not the fault of the peer.
+            OutEvent::Error { peer_id, error } => match error.downcast::<Error>() {
+                Ok(error) => asb::OutEvent::SwapDeclined {
+                    peer: peer_id,
+                    error,
+                },
+                Err(error) => asb::OutEvent::SwapSetupFailed {
+                    peer: peer_id,
+                    error,
+                },
             },
         }
     }
@@ -109,29 +149,36 @@ impl From<OutEvent> for asb::OutEvent {
 #[allow(missing_debug_implementations)]
 pub struct Behaviour<LR> {
     events: VecDeque<OutEvent>,
+    /// Our peer id, the nonce we contribute to the swap id.
+    peer_id: PeerId,
     min_buy: bitcoin::Amount,
     max_buy: bitcoin::Amount,
     env_config: env::Config,
 
     latest_rate: LR,
     resume_only: bool,
+    key_pool: KeyPool,
 }
 
 impl<LR> Behaviour<LR> {
     pub fn new(
+        peer_id: PeerId,
         min_buy: bitcoin::Amount,
         max_buy: bitcoin::Amount,
         env_config: env::Config,
         latest_rate: LR,
         resume_only: bool,
+        key_pool: KeyPool,
     ) -> Self {
         Self {
             events: Default::default(),
+            peer_id,
             min_buy,
             max_buy,
             env_config,
             latest_rate,
             resume_only,
+            key_pool,
         }
     }
 }
@@ -145,11 +192,13 @@ where
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
         Handler::new(
+            self.peer_id,
             self.min_buy,
             self.max_buy,
             self.env_config,
             self.latest_rate.clone(),
             self.resume_only,
+            self.key_pool.clone(),
         )
     }
 
@@ -165,6 +214,7 @@ where
         match event {
             HandlerOutEvent::Initiated(send_wallet_snapshot) => {
                 self.events.push_back(OutEvent::Initiated {
+                    peer_id,
                     send_wallet_snapshot,
                 })
             }
@@ -200,12 +250,14 @@ pub struct Handler<LR> {
     inbound_stream: OptionFuture<InboundStream>,
     events: VecDeque<HandlerOutEvent>,
 
+    peer_id: PeerId,
     min_buy: bitcoin::Amount,
     max_buy: bitcoin::Amount,
     env_config: env::Config,
 
     latest_rate: LR,
     resume_only: bool,
+    key_pool: KeyPool,
 
     timeout: Duration,
     keep_alive: KeepAlive,
@@ -213,20 +265,24 @@ pub struct Handler<LR> {
 
 impl<LR> Handler<LR> {
     fn new(
+        peer_id: PeerId,
         min_buy: bitcoin::Amount,
         max_buy: bitcoin::Amount,
         env_config: env::Config,
         latest_rate: LR,
         resume_only: bool,
+        key_pool: KeyPool,
     ) -> Self {
         Self {
             inbound_stream: OptionFuture::from(None),
             events: Default::default(),
+            peer_id,
             min_buy,
             max_buy,
             env_config,
             latest_rate,
             resume_only,
+            key_pool,
             timeout: Duration::from_secs(120),
             keep_alive: KeepAlive::Until(Instant::now() + Duration::from_secs(10)),
         }
@@ -235,13 +291,13 @@ impl<LR> Handler<LR> {
 
 #[allow(clippy::large_enum_variant)]
 pub enum HandlerOutEvent {
-    Initiated(bmrng::RequestReceiver<bitcoin::Amount, WalletSnapshot>),
+    Initiated(bmrng::RequestReceiver<SwapRequest, Result<WalletSnapshot, Error>>),
     Completed(Result<(Uuid, State3)>),
 }
 
 impl<LR> ProtocolsHandler for Handler<LR>
 where
-    LR: LatestRate + Send + 'static,
+    LR: LatestRate + Send + 'static + Clone,
 {
     type InEvent = ();
     type OutEvent = HandlerOutEvent;
@@ -262,25 +318,64 @@ where
     ) {
         self.keep_alive = KeepAlive::Yes;
 
-        let (sender, receiver) = bmrng::channel_with_timeout::<bitcoin::Amount, WalletSnapshot>(
-            1,
-            Duration::from_secs(5),
-        );
+        // Verifying the funding proof takes a request to Electrum per input.
+        let (sender, receiver) = bmrng::channel_with_timeout::<
+            SwapRequest,
+            Result<WalletSnapshot, Error>,
+        >(1, Duration::from_secs(10));
         let resume_only = self.resume_only;
+        let peer_id = self.peer_id;
         let min_buy = self.min_buy;
         let max_buy = self.max_buy;
-        let latest_rate = self.latest_rate.latest_rate();
+        let mut latest_rate = self.latest_rate.clone();
         let env_config = self.env_config;
+        let key_pool = self.key_pool.clone();
 
         let protocol = tokio::time::timeout(self.timeout, async move {
             let request = swap_setup::read_cbor_message::<SpotPriceRequest>(&mut substream)
                 .await
                 .context("Failed to read spot price request")?;
 
+            let negotiated = match version::negotiate(
+                request.version,
+                request.capabilities,
+                MIN_PROTOCOL_VERSION,
+            ) {
+                Ok(negotiated) => negotiated,
+                Err(error) => {
+                    return Err(decline(
+                        &mut substream,
+                        Error::IncompatibleVersion(error),
+                        request.capabilities,
+                    )
+                    .await)
+                }
+            };
+            tracing::debug!(%negotiated, "Negotiated swap protocol with taker");
+
+            let session_nonce = request.session_nonce.filter(|_| {
+                negotiated
+                    .capabilities
+                    .contains(Capabilities::SESSION_NONCES)
+            });
+            let expected_swap_id = session_nonce.map(|nonce| nonce.swap_id(peer_id));
+
             let wallet_snapshot = sender
-                .send_receive(request.btc)
+                .send_receive(SwapRequest {
+                    btc: request.btc,
+                    funding_proof: request.funding_proof,
+                    swap_id: expected_swap_id,
+                    session_nonce,
+                })
                 .await
-                .context("Failed to receive wallet snapshot")?;
+                .context(LocalFailure("Failed to receive wallet snapshot"))?;
+
+            let wallet_snapshot = match wallet_snapshot {
+                Ok(wallet_snapshot) => wallet_snapshot,
+                Err(error) => {
+                    return Err(decline(&mut substream, error, negotiated.capabilities).await)
+                }
+            };
 
             // wrap all of these into another future so we can `return` from all the
             // different blocks
@@ -317,10 +412,16 @@ where
                     });
                 }
 
-                let rate = latest_rate.map_err(|e| Error::LatestRateFetchFailed(Box::new(e)))?;
-                let xmr = rate
-                    .sell_quote(btc)
-                    .map_err(Error::SellQuoteCalculationFailed)?;
+                let xmr = match wallet_snapshot.stored_xmr {
+                    Some(xmr) => xmr,
+                    None => {
+                        let rate = latest_rate
+                            .latest_rate_for(btc)
+                            .map_err(|e| Error::LatestRateFetchFailed(Box::new(e)))?;
+                        rate.sell_quote(btc)
+                            .map_err(Error::SellQuoteCalculationFailed)?
+                    }
+                };
 
                 if wallet_snapshot.balance < xmr + wallet_snapshot.lock_fee {
                     return Err(Error::BalanceTooLow {
@@ -336,14 +437,15 @@ where
 
             swap_setup::write_cbor_message(
                 &mut substream,
-                SpotPriceResponse::from_result_ref(&result),
+                SpotPriceResponse::from_result_ref(&result, negotiated),
             )
             .await
             .context("Failed to write spot price response")?;
 
             let xmr = result?;
 
-            let state0 = State0::new(
+            let state0 = State0::with_keys(
+                key_pool.take(),
                 request.btc,
                 xmr,
                 env_config,
@@ -351,7 +453,6 @@ where
                 wallet_snapshot.punish_address,
                 wallet_snapshot.redeem_fee,
                 wallet_snapshot.punish_fee,
-                &mut rand::thread_rng(),
             );
 
             let message0 = swap_setup::read_cbor_message::<Message0>(&mut substream)
@@ -360,6 +461,14 @@ where
             let (swap_id, state1) = state0
                 .receive(message0)
                 .context("Failed to transition state0 -> state1 using message0")?;
+            if let Some(expected_swap_id) = expected_swap_id {
+                ensure!(
+                    swap_id == expected_swap_id,
+                    "Taker proposed swap id {} instead of {} derived from its session nonce",
+                    swap_id,
+                    expected_swap_id
+                );
+            }
 
             swap_setup::write_cbor_message(&mut substream, state1.next_message())
                 .await
@@ -455,19 +564,58 @@ where
     }
 }
 
+/// Tells the taker why we decline the swap, returns the error to fail the
+/// swap setup with.
+async fn decline(
+    substream: &mut NegotiatedSubstream,
+    error: Error,
+    capabilities: Capabilities,
+) -> anyhow::Error {
+    let response = error.to_error_response(capabilities);
+    if let SpotPriceError::Other = response {
+        tracing::warn!(
+            "Declining swap without being able to tell the taker why: {:#}",
+            error
+        );
+    }
+    let response = SpotPriceResponse::Error(response);
+
+    match swap_setup::write_cbor_message(substream, response).await {
+        Ok(()) => error.into(),
+        Err(write_error) => write_error.context("Failed to write spot price response"),
+    }
+}
+
 impl SpotPriceResponse {
-    pub fn from_result_ref(result: &Result<monero::Amount, Error>) -> Self {
+    /// Answers takers that predate the version negotiation in the format they
+    /// understand.
+    pub fn from_result_ref(result: &Result<monero::Amount, Error>, negotiated: Negotiated) -> Self {
         match result {
-            Ok(amount) => SpotPriceResponse::Xmr(*amount),
-            Err(error) => SpotPriceResponse::Error(error.to_error_response()),
+            Ok(amount) if negotiated.version == 0 => SpotPriceResponse::Xmr(*amount),
+            Ok(amount) => SpotPriceResponse::Accepted {
+                xmr: *amount,
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::SUPPORTED,
+            },
+            Err(error) => {
+                SpotPriceResponse::Error(error.to_error_response(negotiated.capabilities))
+            }
         }
     }
 }
 
+/// The swap setup failed because of us, e.g. because our wallets could not be
+/// queried, which is not held against the taker.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct LocalFailure(&'static str);
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("ASB is running in resume-only mode")]
     ResumeOnlyMode,
+    #[error("ASB has paused quotes")]
+    QuotesPaused,
     #[error("Amount {buy} below minimum {min}")]
     AmountBelowMinimum {
         min: bitcoin::Amount,
@@ -492,12 +640,54 @@ pub enum Error {
         cli: BlockchainNetwork,
         asb: BlockchainNetwork,
     },
+    #[error("Too many swap requests")]
+    TooManyRequests,
+    #[error("No funding proof was sent")]
+    FundingProofRequired,
+    #[error("Invalid funding proof")]
+    InvalidFundingProof,
+    #[error("Funding proof spends outputs that are spent or reserved for another swap setup")]
+    FundsUnavailable,
+    #[error("Failed to verify funding proof")]
+    FundingProofVerificationFailed(#[source] anyhow::Error),
+    #[error("Incompatible protocol version")]
+    IncompatibleVersion(#[source] version::IncompatibleVersion),
+    #[error("Swap {swap_id} was already set up")]
+    SwapAlreadyExists { swap_id: Uuid },
 }
 
 impl Error {
-    pub fn to_error_response(&self) -> SpotPriceError {
+    /// The reason of the refusal to send to a taker, if it can decode it.
+    pub fn to_protocol_error(&self) -> Option<ProtocolError> {
         match self {
-            Error::ResumeOnlyMode => SpotPriceError::NoSwapsAccepted,
+            Error::AmountBelowMinimum { min, .. } => {
+                Some(ProtocolError::AmountBelowMinimum { min: *min })
+            }
+            Error::BalanceTooLow { .. } => Some(ProtocolError::InsufficientLiquidity),
+            Error::LatestRateFetchFailed(_) => Some(ProtocolError::RateUnavailable),
+            Error::SwapAlreadyExists { .. } => Some(ProtocolError::AlreadySwapping),
+            Error::FundsUnavailable => Some(ProtocolError::FundsUnavailable),
+            Error::IncompatibleVersion(error) => Some(ProtocolError::VersionMismatch {
+                min_version: error.min,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Takers without [`Capabilities::PROTOCOL_ERRORS`] predate the
+    /// negotiation of capabilities and can only decode the errors of the
+    /// first version of the protocol.
+    pub fn to_error_response(&self, capabilities: Capabilities) -> SpotPriceError {
+        let legacy = !capabilities.contains(Capabilities::PROTOCOL_ERRORS);
+
+        if !legacy {
+            if let Some(error) = self.to_protocol_error() {
+                return SpotPriceError::Refused(error);
+            }
+        }
+
+        match self {
+            Error::ResumeOnlyMode | Error::QuotesPaused => SpotPriceError::NoSwapsAccepted,
             Error::AmountBelowMinimum { min, buy } => SpotPriceError::AmountBelowMinimum {
                 min: *min,
                 buy: *buy,
@@ -513,9 +703,101 @@ impl Error {
                     asb: *asb,
                 }
             }
-            Error::LatestRateFetchFailed(_) | Error::SellQuoteCalculationFailed(_) => {
+            Error::TooManyRequests if legacy => SpotPriceError::NoSwapsAccepted,
+            Error::IncompatibleVersion(_) if legacy => SpotPriceError::Other,
+            Error::SwapAlreadyExists { .. } if legacy => SpotPriceError::Other,
+            Error::FundingProofRequired | Error::InvalidFundingProof | Error::FundsUnavailable
+                if legacy =>
+            {
                 SpotPriceError::Other
             }
+            Error::TooManyRequests => SpotPriceError::TooManyRequests,
+            Error::FundingProofRequired => SpotPriceError::FundingProofRequired,
+            Error::InvalidFundingProof | Error::FundsUnavailable => {
+                SpotPriceError::InvalidFundingProof
+            }
+            Error::IncompatibleVersion(error) => SpotPriceError::IncompatibleVersion {
+                min_version: error.min,
+            },
+            Error::SwapAlreadyExists { .. } => SpotPriceError::SwapAlreadyExists,
+            Error::LatestRateFetchFailed(_)
+            | Error::SellQuoteCalculationFailed(_)
+            | Error::FundingProofVerificationFailed(_) => SpotPriceError::Other,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    /// The errors of the first version of the protocol, as takers that
+    /// predate the negotiation of capabilities decode them.
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    enum LegacySpotPriceError {
+        NoSwapsAccepted,
+        AmountBelowMinimum {
+            min: u64,
+            buy: u64,
+        },
+        AmountAboveMaximum {
+            max: u64,
+            buy: u64,
+        },
+        BalanceTooLow {
+            buy: u64,
+        },
+        BlockchainNetworkMismatch {
+            cli: BlockchainNetwork,
+            asb: BlockchainNetwork,
+        },
+        Other,
+    }
+
+    fn decodable_by_legacy_takers(error: &Error) -> bool {
+        let bytes = serde_cbor::to_vec(&error.to_error_response(Capabilities::NONE)).unwrap();
+
+        serde_cbor::from_slice::<LegacySpotPriceError>(&bytes).is_ok()
+    }
+
+    #[test]
+    fn funding_proof_refusals_are_decodable_by_legacy_takers() {
+        for error in &[
+            Error::TooManyRequests,
+            Error::FundingProofRequired,
+            Error::InvalidFundingProof,
+            Error::FundsUnavailable,
+        ] {
+            assert!(decodable_by_legacy_takers(error), "{}", error);
+        }
+    }
+
+    #[test]
+    fn swap_id_refusal_is_decodable_by_legacy_takers() {
+        let error = Error::SwapAlreadyExists {
+            swap_id: Uuid::new_v4(),
+        };
+
+        assert!(decodable_by_legacy_takers(&error));
+    }
+
+    #[test]
+    fn explains_version_refusal_to_outdated_takers() {
+        let error = version::negotiate(0, Capabilities::NONE, PROTOCOL_VERSION)
+            .map_err(Error::IncompatibleVersion)
+            .unwrap_err();
+
+        assert!(matches!(
+            error.to_error_response(Capabilities::NONE),
+            SpotPriceError::Other
+        ));
+        assert!(decodable_by_legacy_takers(&error));
+        assert!(matches!(
+            error.to_error_response(Capabilities::PROTOCOL_ERRORS),
+            SpotPriceError::Refused(ProtocolError::VersionMismatch { min_version })
+                if min_version == PROTOCOL_VERSION
+        ));
+    }
+}
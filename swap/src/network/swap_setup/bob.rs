@@ -1,11 +1,15 @@
+use crate::network::protocol_error::ProtocolError;
+use crate::network::swap_setup::version::{
+    self, Capabilities, IncompatibleVersion, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use crate::network::swap_setup::{
-    protocol, read_cbor_message, write_cbor_message, BlockchainNetwork, SpotPriceError,
-    SpotPriceRequest, SpotPriceResponse,
+    protocol, read_cbor_message, write_cbor_message, BlockchainNetwork, SessionNonce,
+    SpotPriceError, SpotPriceRequest, SpotPriceResponse,
 };
 use crate::protocol::bob::{State0, State2};
 use crate::protocol::{Message1, Message3};
 use crate::{bitcoin, cli, env, monero};
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use futures::future::{BoxFuture, OptionFuture};
 use futures::{AsyncWriteExt, FutureExt};
 use libp2p::core::connection::ConnectionId;
@@ -119,7 +123,9 @@ impl Handler {
 
 #[derive(Debug)]
 pub struct NewSwap {
+    /// Derived from the session nonce and the peer id of the seller.
     pub swap_id: Uuid,
+    pub session_nonce: SessionNonce,
     pub btc: bitcoin::Amount,
     pub tx_refund_fee: bitcoin::Amount,
     pub tx_cancel_fee: bitcoin::Amount,
@@ -154,12 +160,25 @@ impl ProtocolsHandler for Handler {
         let env_config = self.env_config;
 
         let protocol = tokio::time::timeout(self.timeout, async move {
+            let funding_proof = bitcoin_wallet
+                .funding_proof(
+                    info.btc,
+                    info.bitcoin_refund_address.clone(),
+                    info.session_nonce.as_bytes(),
+                )
+                .await
+                .context("Failed to create funding proof")?;
+
             write_cbor_message(&mut substream, SpotPriceRequest {
                 btc: info.btc,
                 blockchain_network: BlockchainNetwork {
                     bitcoin: env_config.bitcoin_network,
                     monero: env_config.monero_network,
                 },
+                funding_proof: Some(funding_proof),
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::SUPPORTED,
+                session_nonce: Some(info.session_nonce),
             })
             .await?;
 
@@ -173,7 +192,7 @@ impl ProtocolsHandler for Handler {
                 env_config.bitcoin_cancel_timelock,
                 env_config.bitcoin_punish_timelock,
                 info.bitcoin_refund_address,
-                env_config.monero_finality_confirmations,
+                env_config.monero_lock_confirmations(info.btc),
                 info.tx_refund_fee,
                 info.tx_cancel_fee,
             );
@@ -251,7 +270,22 @@ impl ProtocolsHandler for Handler {
 impl From<SpotPriceResponse> for Result<monero::Amount, Error> {
     fn from(response: SpotPriceResponse) -> Self {
         match response {
-            SpotPriceResponse::Xmr(amount) => Ok(amount),
+            SpotPriceResponse::Xmr(amount) => {
+                tracing::debug!("Seller predates the swap protocol version negotiation");
+
+                Ok(amount)
+            }
+            SpotPriceResponse::Accepted {
+                xmr,
+                version,
+                capabilities,
+            } => {
+                let negotiated = version::negotiate(version, capabilities, MIN_PROTOCOL_VERSION)
+                    .map_err(Error::OutdatedSeller)?;
+                tracing::debug!(%negotiated, "Negotiated swap protocol with seller");
+
+                Ok(xmr)
+            }
             SpotPriceResponse::Error(e) => Err(e.into()),
         }
     }
@@ -280,6 +314,26 @@ pub enum Error {
         asb: BlockchainNetwork,
     },
 
+    #[error("Seller received too many swap requests from you, please try again later")]
+    TooManyRequests,
+
+    #[error(
+        "Seller requires a proof that you can fund the swap, which this version does not send"
+    )]
+    FundingProofRequired,
+
+    #[error("Seller rejected the proof that you can fund the swap")]
+    InvalidFundingProof,
+
+    #[error("Seller requires at least swap protocol version {min_version}, but this CLI speaks version {version}, please update", version = PROTOCOL_VERSION)]
+    OutdatedVersion { min_version: u32 },
+
+    #[error("Seller already aborted this swap or set it up differently, please start a new swap")]
+    SwapAlreadyExists,
+
+    #[error("Seller runs an outdated version")]
+    OutdatedSeller(#[source] IncompatibleVersion),
+
     #[error("Failed to complete swap setup within {seconds}s")]
     Timeout { seconds: u64 },
 
@@ -287,6 +341,9 @@ pub enum Error {
     /// rate update problems on the seller side)
     #[error("Seller encountered a problem, please try again later.")]
     Other,
+
+    #[error(transparent)]
+    Refused(ProtocolError),
 }
 
 impl From<SpotPriceError> for Error {
@@ -303,7 +360,15 @@ impl From<SpotPriceError> for Error {
             SpotPriceError::BlockchainNetworkMismatch { cli, asb } => {
                 Error::BlockchainNetworkMismatch { cli, asb }
             }
+            SpotPriceError::TooManyRequests => Error::TooManyRequests,
+            SpotPriceError::FundingProofRequired => Error::FundingProofRequired,
+            SpotPriceError::InvalidFundingProof => Error::InvalidFundingProof,
+            SpotPriceError::SwapAlreadyExists => Error::SwapAlreadyExists,
+            SpotPriceError::IncompatibleVersion { min_version } => {
+                Error::OutdatedVersion { min_version }
+            }
             SpotPriceError::Other => Error::Other,
+            SpotPriceError::Refused(error) => Error::Refused(error),
         }
     }
 }
@@ -0,0 +1,146 @@
+//! Negotiating the version of the swap protocol and optional features at the
+//! start of the swap setup.
+//!
+//! Takers announce their version and capabilities in the spot price request
+//! and makers answer with theirs. Peers that predate the negotiation do not
+//! announce anything and speak version 0 without any capabilities.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::fmt;
+use std::ops::BitAnd;
+
+/// The version of the swap protocol spoken by this build.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest version of the swap protocol this build still executes swaps
+/// with.
+///
+/// Raising it refuses peers that predate the negotiation, so it stays at 0
+/// until a version drops support for them.
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// Optional features of the swap protocol a peer supports.
+///
+/// Unknown flags of newer peers are ignored.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// The taker sends a proof that it controls the Bitcoin of the swap.
+    pub const FUNDING_PROOF: Self = Self(1 << 0);
+    /// The swap id is derived from a nonce of the taker and the peer id of
+    /// the maker, see
+    /// [`SessionNonce`](crate::network::swap_setup::SessionNonce).
+    pub const SESSION_NONCES: Self = Self(1 << 1);
+    /// The maker explains refusals with a
+    /// [`ProtocolError`](crate::network::protocol_error::ProtocolError).
+    pub const PROTOCOL_ERRORS: Self = Self(1 << 2);
+
+    /// The capabilities of this build.
+    pub const SUPPORTED: Self =
+        Self(Self::FUNDING_PROOF.0 | Self::SESSION_NONCES.0 | Self::PROTOCOL_ERRORS.0);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// What both peers of a swap agreed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: Capabilities,
+}
+
+impl Negotiated {
+    /// What is agreed on with peers that predate the negotiation.
+    pub const LEGACY: Self = Self {
+        version: 0,
+        capabilities: Capabilities::NONE,
+    };
+}
+
+impl fmt::Display for Negotiated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {} with capabilities {:#b}",
+            self.version, self.capabilities.0
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+#[error("Peer speaks swap protocol version {peer}, but at least version {min} is required")]
+pub struct IncompatibleVersion {
+    pub peer: u32,
+    pub min: u32,
+}
+
+/// Agrees on the lower of both versions and the capabilities both peers
+/// support, refusing peers older than `min_version`.
+pub fn negotiate(
+    peer_version: u32,
+    peer_capabilities: Capabilities,
+    min_version: u32,
+) -> Result<Negotiated, IncompatibleVersion> {
+    if peer_version < min_version {
+        return Err(IncompatibleVersion {
+            peer: peer_version,
+            min: min_version,
+        });
+    }
+
+    Ok(Negotiated {
+        version: min(PROTOCOL_VERSION, peer_version),
+        capabilities: Capabilities::SUPPORTED & peer_capabilities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agrees_on_lower_version_and_common_capabilities() {
+        let newer_peer = Capabilities(Capabilities::SUPPORTED.0 | 1 << 63);
+
+        let negotiated = negotiate(PROTOCOL_VERSION + 1, newer_peer, MIN_PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(negotiated, Negotiated {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::SUPPORTED
+        });
+    }
+
+    #[test]
+    fn legacy_peers_have_no_capabilities() {
+        let negotiated = negotiate(0, Capabilities::default(), MIN_PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(negotiated, Negotiated::LEGACY);
+        assert!(!negotiated
+            .capabilities
+            .contains(Capabilities::FUNDING_PROOF));
+    }
+
+    #[test]
+    fn refuses_peers_below_minimum_version() {
+        let error = negotiate(0, Capabilities::default(), PROTOCOL_VERSION).unwrap_err();
+
+        assert_eq!(error, IncompatibleVersion {
+            peer: 0,
+            min: PROTOCOL_VERSION
+        });
+    }
+}
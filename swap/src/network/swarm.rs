@@ -1,37 +1,42 @@
+use crate::asb::config::ConnectionLimits;
+use crate::asb::rendezous::RendezvousNode;
 use crate::asb::LatestRate;
 use crate::libp2p_ext::MultiAddrExt;
 use crate::network::rendezvous::XmrBtcNamespace;
-use crate::seed::Seed;
+use crate::protocol::alice::KeyPool;
 use crate::{asb, bitcoin, cli, env, tor};
 use anyhow::{Context, Result};
+use libp2p::core::connection;
 use libp2p::swarm::{NetworkBehaviour, SwarmBuilder};
 use libp2p::{identity, Multiaddr, Swarm};
 use std::fmt::Debug;
 
 #[allow(clippy::too_many_arguments)]
 pub fn asb<LR>(
-    seed: &Seed,
+    identity: identity::Keypair,
     min_buy: bitcoin::Amount,
     max_buy: bitcoin::Amount,
     latest_rate: LR,
     resume_only: bool,
     env_config: env::Config,
-    rendezvous_params: Option<(Multiaddr, XmrBtcNamespace)>,
+    namespace: XmrBtcNamespace,
+    rendezvous_addrs: &[Multiaddr],
+    connection_limits: ConnectionLimits,
+    key_pool: KeyPool,
 ) -> Result<Swarm<asb::Behaviour<LR>>>
 where
     LR: LatestRate + Send + 'static + Debug + Clone,
 {
-    let identity = seed.derive_libp2p_identity();
-
-    let rendezvous_params = if let Some((address, namespace)) = rendezvous_params {
-        let peer_id = address
-            .extract_peer_id()
-            .context("Rendezvous node address must contain peer ID")?;
+    let rendezvous_nodes = rendezvous_addrs
+        .iter()
+        .map(|addr| {
+            let peer_id = addr.extract_peer_id().with_context(|| {
+                format!("Rendezvous node address {} must contain peer ID", addr)
+            })?;
 
-        Some((identity.clone(), peer_id, address, namespace))
-    } else {
-        None
-    };
+            Ok(RendezvousNode::new(addr, peer_id, namespace, None))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     let behaviour = asb::Behaviour::new(
         min_buy,
@@ -39,16 +44,26 @@ where
         latest_rate,
         resume_only,
         env_config,
-        rendezvous_params,
+        identity.clone(),
+        rendezvous_nodes,
+        key_pool,
     );
 
     let transport = asb::transport::new(&identity)?;
     let peer_id = identity.public().into_peer_id();
 
+    // The limit per IP address is enforced by the event loop, libp2p does not
+    // know about IP addresses.
+    let limits = connection::ConnectionLimits::default()
+        .with_max_established_per_peer(Some(connection_limits.max_established_per_peer))
+        .with_max_established_incoming(Some(connection_limits.max_established_incoming))
+        .with_max_pending_incoming(Some(connection_limits.max_pending_incoming));
+
     let swarm = SwarmBuilder::new(transport, behaviour, peer_id)
         .executor(Box::new(|f| {
             tokio::spawn(f);
         }))
+        .connection_limits(limits)
         .build();
 
     Ok(swarm)
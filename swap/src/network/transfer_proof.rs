@@ -10,6 +10,9 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 const PROTOCOL: &str = "/comit/xmr/btc/transfer_proof/1.0.0";
+/// The request is a few hundred bytes, anything much larger is not sent by an
+/// honest peer.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
 type OutEvent = RequestResponseEvent<Request, ()>;
 type Message = RequestResponseMessage<Request, ()>;
 
@@ -32,7 +35,7 @@ pub struct Request {
 
 pub fn alice() -> Behaviour {
     Behaviour::new(
-        CborCodec::default(),
+        CborCodec::new(MAX_MESSAGE_SIZE),
         vec![(TransferProofProtocol, ProtocolSupport::Outbound)],
         RequestResponseConfig::default(),
     )
@@ -40,7 +43,7 @@ pub fn alice() -> Behaviour {
 
 pub fn bob() -> Behaviour {
     Behaviour::new(
-        CborCodec::default(),
+        CborCodec::new(MAX_MESSAGE_SIZE),
         vec![(TransferProofProtocol, ProtocolSupport::Inbound)],
         RequestResponseConfig::default(),
     )
@@ -6,9 +6,11 @@ use crate::{asb, bitcoin, monero};
 use std::sync::Arc;
 use uuid::Uuid;
 
+pub use self::key_pool::KeyPool;
 pub use self::state::*;
-pub use self::swap::{run, run_until};
+pub use self::swap::{run, run_until, run_until_shutdown};
 
+pub mod key_pool;
 pub mod state;
 pub mod swap;
 
@@ -20,4 +22,7 @@ pub struct Swap {
     pub env_config: Config,
     pub swap_id: Uuid,
     pub db: Arc<Database>,
+    /// Whether Bob may refund right away if there is not enough Monero to
+    /// lock, see [`AliceState::BtcEarlyRefundable`].
+    pub allow_early_refund: bool,
 }
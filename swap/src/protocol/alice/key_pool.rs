@@ -0,0 +1,100 @@
+//! Keys of upcoming swaps, generated ahead of time.
+//!
+//! Every swap setup needs fresh [`SwapKeys`], whose proof takes long enough
+//! to noticeably delay setups when several takers arrive at once. The pool
+//! keeps a few keys ready, generated in parallel on the blocking thread pool,
+//! and falls back to generating them on demand if it runs dry.
+
+use crate::protocol::alice::SwapKeys;
+use futures::future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(Clone, Debug, Default)]
+pub struct KeyPool {
+    keys: Arc<Mutex<Vec<SwapKeys>>>,
+    taken: Arc<Notify>,
+}
+
+impl KeyPool {
+    /// How many keys are kept ready by default.
+    pub const DEFAULT_SIZE: usize = 4;
+
+    /// Hands out keys generated ahead of time, generating them now if there
+    /// are none.
+    pub fn take(&self) -> SwapKeys {
+        let keys = self.keys.lock().expect("lock is never poisoned").pop();
+        self.taken.notify_one();
+
+        keys.unwrap_or_else(|| {
+            tracing::debug!("Key pool ran dry, generating swap keys during setup");
+            SwapKeys::new_random(&mut rand::thread_rng())
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.lock().expect("lock is never poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keeps `size` keys ready, refilling the pool whenever keys are taken.
+    pub async fn run(self, size: usize) {
+        loop {
+            let missing = size.saturating_sub(self.len());
+
+            if missing == 0 {
+                self.taken.notified().await;
+                continue;
+            }
+
+            let generated = future::join_all((0..missing).map(|_| {
+                tokio::task::spawn_blocking(|| SwapKeys::new_random(&mut rand::thread_rng()))
+            }))
+            .await;
+
+            for keys in generated {
+                match keys {
+                    Ok(keys) => self.keys.lock().expect("lock is never poisoned").push(keys),
+                    Err(error) => {
+                        tracing::error!("Stopped generating swap keys ahead of time: {:#}", error);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn refills_taken_keys() {
+        let pool = KeyPool::default();
+        tokio::spawn(pool.clone().run(2));
+
+        while pool.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let taken = pool.take();
+        assert_eq!(pool.len(), 1);
+
+        while pool.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!pool.keys.lock().unwrap().contains(&taken));
+    }
+
+    #[test]
+    fn empty_pool_generates_keys_on_demand() {
+        let pool = KeyPool::default();
+
+        assert_ne!(pool.take(), pool.take());
+        assert!(pool.is_empty());
+    }
+}
@@ -1,13 +1,13 @@
 use crate::bitcoin::{
     current_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, Transaction, TxCancel,
-    TxPunish, TxRedeem, TxRefund, Txid,
+    TxEarlyRefund, TxPunish, TxRedeem, TxRefund, Txid,
 };
 use crate::env::Config;
 use crate::monero::wallet::{TransferRequest, WatchRequest};
 use crate::monero::TransferProof;
 use crate::monero_ext::ScalarExt;
 use crate::protocol::{Message0, Message1, Message2, Message3, Message4, CROSS_CURVE_PROOF_SYSTEM};
-use crate::{bitcoin, monero};
+use crate::{bitcoin, monero, receipt};
 use anyhow::{anyhow, bail, Context, Result};
 use monero_rpc::wallet::BlockHeight;
 use rand::{CryptoRng, RngCore};
@@ -27,6 +27,10 @@ pub enum AliceState {
     BtcLocked {
         state3: Box<State3>,
     },
+    BtcEarlyRefundable {
+        state3: Box<State3>,
+    },
+    BtcEarlyRefunded,
     XmrLockTransactionSent {
         monero_wallet_restore_blockheight: BlockHeight,
         transfer_proof: TransferProof,
@@ -86,6 +90,8 @@ impl fmt::Display for AliceState {
                 write!(f, "bitcoin lock transaction in mempool")
             }
             AliceState::BtcLocked { .. } => write!(f, "btc is locked"),
+            AliceState::BtcEarlyRefundable { .. } => write!(f, "btc is early refundable"),
+            AliceState::BtcEarlyRefunded => write!(f, "btc is early refunded"),
             AliceState::XmrLockTransactionSent { .. } => write!(f, "xmr lock transaction sent"),
             AliceState::XmrLocked { .. } => write!(f, "xmr is locked"),
             AliceState::XmrLockTransferProofSent { .. } => {
@@ -107,6 +113,91 @@ impl fmt::Display for AliceState {
     }
 }
 
+impl AliceState {
+    /// Amounts, fees and transaction ids that are relevant for the state, but
+    /// none of its secrets.
+    pub fn details(&self) -> String {
+        match self {
+            AliceState::Started { state3 } => format!(
+                "quote accepted: {} for {}",
+                state3.tx_lock.lock_amount(),
+                state3.xmr
+            ),
+            AliceState::BtcLockTransactionSeen { state3 }
+            | AliceState::BtcLocked { state3 }
+            | AliceState::CancelTimelockExpired { state3, .. }
+            | AliceState::BtcPunishable { state3, .. } => {
+                format!("BTC lock @ {}", state3.tx_lock.txid())
+            }
+            AliceState::BtcEarlyRefundable { state3 } => {
+                format!("early refund @ {}", state3.tx_early_refund().txid())
+            }
+            AliceState::XmrLockTransactionSent { transfer_proof, .. }
+            | AliceState::XmrLocked { transfer_proof, .. }
+            | AliceState::XmrLockTransferProofSent { transfer_proof, .. }
+            | AliceState::EncSigLearned { transfer_proof, .. } => {
+                format!("XMR locked @ {}", transfer_proof.tx_hash())
+            }
+            AliceState::BtcRedeemTransactionPublished { state3 } => format!(
+                "redeemed @ {}, redeem fee {}",
+                state3.tx_redeem().txid(),
+                state3.redeem_fee()
+            ),
+            AliceState::BtcCancelled { state3, .. } => {
+                format!("cancelled @ {}", state3.tx_cancel().txid())
+            }
+            AliceState::BtcRefunded { state3, .. } => {
+                format!("refunded @ {}", state3.tx_refund().txid())
+            }
+            AliceState::BtcEarlyRefunded
+            | AliceState::BtcRedeemed
+            | AliceState::BtcPunished
+            | AliceState::SafelyAborted
+            | AliceState::XmrRefunded => String::new(),
+        }
+    }
+}
+
+/// Our key shares of a swap, which can be generated before the swap is set
+/// up.
+///
+/// Proving that the Monero key share matches the Bitcoin one takes a
+/// noticeable amount of CPU time, the [`KeyPool`](super::KeyPool) generates
+/// them in the background.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapKeys {
+    a: bitcoin::SecretKey,
+    s_a: monero::Scalar,
+    v_a: monero::PrivateViewKey,
+    S_a_monero: monero::PublicKey,
+    S_a_bitcoin: bitcoin::PublicKey,
+    dleq_proof_s_a: CrossCurveDLEQProof,
+}
+
+impl SwapKeys {
+    pub fn new_random<R>(rng: &mut R) -> Self
+    where
+        R: RngCore + CryptoRng,
+    {
+        let a = bitcoin::SecretKey::new_random(rng);
+        let v_a = monero::PrivateViewKey::new_random(rng);
+
+        let s_a = monero::Scalar::random(rng);
+        let (dleq_proof_s_a, (S_a_bitcoin, S_a_monero)) = CROSS_CURVE_PROOF_SYSTEM.prove(&s_a, rng);
+
+        Self {
+            a,
+            s_a,
+            v_a,
+            S_a_bitcoin: S_a_bitcoin.into(),
+            S_a_monero: monero::PublicKey {
+                point: S_a_monero.compress(),
+            },
+            dleq_proof_s_a,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct State0 {
     a: bitcoin::SecretKey,
@@ -140,20 +231,44 @@ impl State0 {
     where
         R: RngCore + CryptoRng,
     {
-        let a = bitcoin::SecretKey::new_random(rng);
-        let v_a = monero::PrivateViewKey::new_random(rng);
+        Self::with_keys(
+            SwapKeys::new_random(rng),
+            btc,
+            xmr,
+            env_config,
+            redeem_address,
+            punish_address,
+            tx_redeem_fee,
+            tx_punish_fee,
+        )
+    }
 
-        let s_a = monero::Scalar::random(rng);
-        let (dleq_proof_s_a, (S_a_bitcoin, S_a_monero)) = CROSS_CURVE_PROOF_SYSTEM.prove(&s_a, rng);
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_keys(
+        keys: SwapKeys,
+        btc: bitcoin::Amount,
+        xmr: monero::Amount,
+        env_config: Config,
+        redeem_address: bitcoin::Address,
+        punish_address: bitcoin::Address,
+        tx_redeem_fee: bitcoin::Amount,
+        tx_punish_fee: bitcoin::Amount,
+    ) -> Self {
+        let SwapKeys {
+            a,
+            s_a,
+            v_a,
+            S_a_monero,
+            S_a_bitcoin,
+            dleq_proof_s_a,
+        } = keys;
 
         Self {
             a,
             s_a,
             v_a,
-            S_a_bitcoin: S_a_bitcoin.into(),
-            S_a_monero: monero::PublicKey {
-                point: S_a_monero.compress(),
-            },
+            S_a_bitcoin,
+            S_a_monero,
             dleq_proof_s_a,
             redeem_address,
             punish_address,
@@ -379,7 +494,7 @@ pub struct State3 {
     pub v: monero::PrivateViewKey,
     #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
     btc: bitcoin::Amount,
-    xmr: monero::Amount,
+    pub xmr: monero::Amount,
     pub cancel_timelock: CancelTimelock,
     pub punish_timelock: PunishTimelock,
     refund_address: bitcoin::Address,
@@ -465,6 +580,42 @@ impl State3 {
         TxRedeem::new(&self.tx_lock, &self.redeem_address, self.tx_redeem_fee)
     }
 
+    /// The fee paid from the lock output when we redeem the Bitcoin.
+    pub fn redeem_fee(&self) -> bitcoin::Amount {
+        self.tx_redeem_fee
+    }
+
+    /// The fees paid from the lock output when we cancel the swap and punish
+    /// Bob.
+    pub fn punish_fees(&self) -> bitcoin::Amount {
+        self.tx_cancel_fee + self.tx_punish_fee
+    }
+
+    pub fn tx_early_refund(&self) -> TxEarlyRefund {
+        TxEarlyRefund::new(&self.tx_lock, &self.refund_address, self.tx_refund_fee)
+    }
+
+    /// Lets Bob take back the Bitcoin right away, the Monero must never be
+    /// locked once this signature was handed out.
+    pub fn tx_early_refund_sig(&self) -> bitcoin::Signature {
+        self.a.sign(self.tx_early_refund().digest())
+    }
+
+    /// The receipt of the swap if it completed at the given times.
+    pub fn receipt_terms(&self, swap_id: Uuid, transfer_proof: &TransferProof) -> receipt::Terms {
+        receipt::Terms::new(
+            swap_id,
+            self.tx_lock.lock_amount(),
+            self.xmr,
+            self.tx_lock.txid(),
+            self.tx_redeem().txid(),
+            transfer_proof.tx_hash(),
+            self.a.public(),
+            self.B,
+            self.a.clone(),
+        )
+    }
+
     pub fn extract_monero_private_key(
         &self,
         published_refund_tx: bitcoin::Transaction,
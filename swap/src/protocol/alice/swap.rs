@@ -1,15 +1,24 @@
 //! Run an XMR/BTC swap in the role of Alice.
 //! Alice holds XMR and wishes receive BTC.
-use crate::asb::{EventLoopHandle, LatestRate};
+use crate::asb::metrics::METRICS;
+use crate::asb::reputation::Misbehavior;
+use crate::asb::{withdraw, EventLoopHandle, LatestRate};
 use crate::bitcoin::ExpiredTimelocks;
 use crate::env::Config;
 use crate::protocol::alice::{AliceState, Swap};
+use crate::shutdown::Shutdown;
 use crate::{bitcoin, database, monero};
 use anyhow::{bail, Context, Result};
+use libp2p::PeerId;
+use std::time::{Duration, Instant};
 use tokio::select;
 use tokio::time::timeout;
 use uuid::Uuid;
 
+/// How often to check again whether there is enough Monero to lock, while the
+/// swap cannot be suspended.
+const MONERO_BALANCE_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 pub async fn run<LR>(swap: Swap, rate_service: LR) -> Result<AliceState>
 where
     LR: LatestRate + Clone,
@@ -17,28 +26,81 @@ where
     run_until(swap, |_| false, rate_service).await
 }
 
-#[tracing::instrument(name = "swap", skip(swap,exit_early,rate_service), fields(id = %swap.swap_id), err)]
 pub async fn run_until<LR>(
-    mut swap: Swap,
+    swap: Swap,
     exit_early: fn(&AliceState) -> bool,
     rate_service: LR,
 ) -> Result<AliceState>
+where
+    LR: LatestRate + Clone,
+{
+    let state = advance(swap, exit_early, rate_service, Shutdown::never())
+        .await?
+        .context("Swap was replaced by a new setup of the taker")?;
+
+    Ok(state)
+}
+
+/// Runs the swap like [`run`] but suspends it once shutdown is requested.
+///
+/// Transitions that cannot be repeated safely are completed before the swap
+/// is suspended. Returns `None` if the swap was suspended, its last state is
+/// stored in the database. Also returns `None` if the taker set the swap up
+/// again, the new setup replaces the stored state.
+pub async fn run_until_shutdown<LR>(
+    swap: Swap,
+    rate_service: LR,
+    shutdown: Shutdown,
+) -> Result<Option<AliceState>>
+where
+    LR: LatestRate + Clone,
+{
+    advance(swap, |_| false, rate_service, shutdown).await
+}
+
+#[tracing::instrument(name = "swap", skip(swap,exit_early,rate_service,shutdown), fields(id = %swap.swap_id, peer = %swap.event_loop_handle.peer_id()), err)]
+async fn advance<LR>(
+    mut swap: Swap,
+    exit_early: fn(&AliceState) -> bool,
+    rate_service: LR,
+    mut shutdown: Shutdown,
+) -> Result<Option<AliceState>>
 where
     LR: LatestRate + Clone,
 {
     let mut current_state = swap.state;
+    let mut superseded = swap.event_loop_handle.superseded();
 
     while !is_complete(&current_state) && !exit_early(&current_state) {
-        current_state = next_state(
+        let suspendable = is_suspendable(&current_state);
+        let next = next_state(
             swap.swap_id,
             current_state,
             &mut swap.event_loop_handle,
             swap.bitcoin_wallet.as_ref(),
             swap.monero_wallet.as_ref(),
+            swap.db.as_ref(),
             &swap.env_config,
+            swap.allow_early_refund,
             rate_service.clone(),
-        )
-        .await?;
+        );
+
+        current_state = if suspendable {
+            select! {
+                biased;
+                _ = shutdown.requested() => {
+                    tracing::info!("Suspended swap");
+                    return Ok(None);
+                }
+                _ = superseded.requested() => {
+                    tracing::info!("Stopped swap, the taker set it up again");
+                    return Ok(None);
+                }
+                next = next => next?,
+            }
+        } else {
+            next.await?
+        };
 
         let db_state = (&current_state).into();
         swap.db
@@ -46,16 +108,39 @@ where
             .await?;
     }
 
-    Ok(current_state)
+    Ok(Some(current_state))
+}
+
+/// Whether the transition out of this state can be interrupted and repeated
+/// after a restart.
+///
+/// Locking the Monero twice would lose funds and refunding the Monero switches
+/// the wallet loaded by the monero-wallet-rpc.
+fn is_suspendable(state: &AliceState) -> bool {
+    !matches!(
+        state,
+        AliceState::BtcLocked { .. } | AliceState::BtcRefunded { .. }
+    )
+}
+
+/// Bob did not lock the Bitcoin in time, while we kept Monero reserved for the
+/// swap.
+async fn record_lock_timeout(db: &database::Database, swap_id: Uuid, peer: PeerId) {
+    if let Err(error) = db.record_misbehavior(peer, Misbehavior::LockTimeout).await {
+        tracing::warn!(%swap_id, %peer, "Failed to record lock timeout of peer: {:#}", error);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn next_state<LR>(
     swap_id: Uuid,
     state: AliceState,
     event_loop_handle: &mut EventLoopHandle,
     bitcoin_wallet: &bitcoin::Wallet,
     monero_wallet: &monero::Wallet,
+    db: &database::Database,
     env_config: &Config,
+    allow_early_refund: bool,
     mut rate_service: LR,
 ) -> Result<AliceState>
 where
@@ -81,6 +166,7 @@ where
                         minutes = %env_config.bitcoin_lock_mempool_timeout.as_secs_f64() / 60.0,
                         "TxLock lock was not seen in mempool in time",
                     );
+                    record_lock_timeout(db, swap_id, event_loop_handle.peer_id()).await;
                     AliceState::SafelyAborted
                 }
                 Ok(res) => {
@@ -91,37 +177,114 @@ where
         }
         AliceState::BtcLockTransactionSeen { state3 } => {
             let tx_lock_status = bitcoin_wallet.subscribe_to(state3.tx_lock.clone()).await;
-            match timeout(
-                env_config.bitcoin_lock_confirmed_timeout,
-                tx_lock_status.wait_until_final(),
-            )
-            .await
-            {
-                Err(_) => {
+            let lock_amount = state3.tx_lock.lock_amount();
+            let confirmations_needed = env_config.bitcoin_lock_confirmations(lock_amount);
+            let confirmed_timeout = env_config.bitcoin_lock_confirmed_timeout_for(lock_amount);
+            let deadline = bitcoin_wallet.block_height().await? + confirmed_timeout;
+            // Only used for the metrics, the deadline is expressed in blocks.
+            let started = Instant::now();
+
+            select! {
+                biased; // check the status before the deadline
+                result = tx_lock_status.wait_until_final_with(confirmations_needed) => {
+                    result?;
+                    METRICS.observe_btc_lock_confirmation_duration(started.elapsed());
+                    AliceState::BtcLocked { state3 }
+                }
+                _ = bitcoin_wallet.wait_until_block_height(deadline) => {
                     tracing::info!(
-                        confirmations_needed = %env_config.bitcoin_finality_confirmations,
-                        minutes = %env_config.bitcoin_lock_confirmed_timeout.as_secs_f64() / 60.0,
+                        %confirmations_needed,
+                        blocks = %confirmed_timeout,
+                        deadline = %u32::from(deadline),
                         "TxLock lock did not get enough confirmations in time",
                     );
+                    record_lock_timeout(db, swap_id, event_loop_handle.peer_id()).await;
                     AliceState::SafelyAborted
                 }
-                Ok(res) => {
-                    res?;
-                    AliceState::BtcLocked { state3 }
-                }
             }
         }
         AliceState::BtcLocked { state3 } => {
             match state3.expired_timelocks(bitcoin_wallet).await? {
                 ExpiredTimelocks::None => {
+                    // A reorganisation of the blockchain can undo the confirmations of the lock
+                    // transaction, or even drop it if Bob double-spends its inputs. The Monero is
+                    // only locked if the Bitcoin is still locked deep enough.
+                    let confirmations_needed =
+                        env_config.bitcoin_lock_confirmations(state3.tx_lock.lock_amount());
+                    let tx_lock_status = bitcoin_wallet.status_of_script(&state3.tx_lock).await?;
+                    if !tx_lock_status.is_confirmed_with(confirmations_needed) {
+                        tracing::warn!(
+                            %tx_lock_status,
+                            %confirmations_needed,
+                            "Bitcoin lock transaction lost confirmations, waiting for it to be confirmed again"
+                        );
+
+                        return Ok(AliceState::BtcLockTransactionSeen { state3 });
+                    }
+
+                    // The Monero of other swaps that did not lock yet is spoken for.
+                    let reserved = withdraw::reserved_monero(
+                        db.all_alice()?
+                            .into_iter()
+                            .filter(|(id, _)| *id != swap_id)
+                            .map(|(_, state)| state),
+                    );
+                    let needed = state3.xmr + monero::MONERO_FEE;
+                    let unlocked = monero_wallet
+                        .get_unlocked_balance()
+                        .await?
+                        .saturating_sub(reserved);
+
+                    if unlocked < needed {
+                        // Without enough Monero the swap cannot go on, instead of making Bob wait
+                        // for the cancel timelock we let him take back the Bitcoin right away.
+                        // Monero that is only locked, e.g. the change of another swap, is waited
+                        // for.
+                        let balance = monero_wallet.get_balance().await?.saturating_sub(reserved);
+                        if allow_early_refund && balance < needed {
+                            tracing::warn!(
+                                %balance,
+                                %reserved,
+                                xmr = %state3.xmr,
+                                "Not enough Monero to lock, allowing Bob to refund early"
+                            );
+
+                            return Ok(AliceState::BtcEarlyRefundable { state3 });
+                        }
+
+                        tracing::warn!(
+                            %unlocked,
+                            %balance,
+                            %reserved,
+                            xmr = %state3.xmr,
+                            "Not enough unlocked Monero to lock, waiting for it until the cancel timelock expires"
+                        );
+                        tokio::time::sleep(MONERO_BALANCE_RECHECK_INTERVAL).await;
+
+                        return Ok(AliceState::BtcLocked { state3 });
+                    }
+
                     // Record the current monero wallet block height so we don't have to scan from
                     // block 0 for scenarios where we create a refund wallet.
                     let monero_wallet_restore_blockheight = monero_wallet.block_height().await?;
 
-                    let transfer_proof = monero_wallet
+                    let (transfer_proof, fee) = monero_wallet
                         .transfer(state3.lock_xmr_transfer_request())
                         .await?;
 
+                    if let Err(error) = db.record_xmr_lock_fee(swap_id, fee).await {
+                        tracing::warn!(%fee, "Failed to record the fee of the Monero lock transaction: {:#}", error);
+                    }
+                    if let Err(error) = db
+                        .insert_receipt_terms(
+                            swap_id,
+                            &state3.receipt_terms(swap_id, &transfer_proof),
+                        )
+                        .await
+                    {
+                        tracing::warn!("Failed to record the terms of the receipt: {:#}", error);
+                    }
+
                     AliceState::XmrLockTransactionSent {
                         monero_wallet_restore_blockheight,
                         transfer_proof,
@@ -131,6 +294,36 @@ where
                 _ => AliceState::SafelyAborted,
             }
         }
+        AliceState::BtcEarlyRefundable { state3 } => {
+            let tx_lock_status = bitcoin_wallet.subscribe_to(state3.tx_lock.clone()).await;
+            let tx_early_refund_status =
+                bitcoin_wallet.subscribe_to(state3.tx_early_refund()).await;
+
+            let early_refund = async {
+                if let Err(error) = event_loop_handle
+                    .send_early_refund(state3.tx_early_refund_sig())
+                    .await
+                {
+                    tracing::warn!(
+                        "Failed to send early refund signature, Bob has to wait for the cancel timelock to refund: {:#}",
+                        error
+                    );
+                }
+
+                tx_early_refund_status.wait_until_seen().await
+            };
+
+            select! {
+                seen = early_refund => {
+                    seen.context("Failed to monitor early refund transaction")?;
+
+                    AliceState::BtcEarlyRefunded
+                }
+                _ = tx_lock_status.wait_until_confirmed_with(state3.cancel_timelock) => {
+                    AliceState::SafelyAborted
+                }
+            }
+        }
         AliceState::XmrLockTransactionSent {
             monero_wallet_restore_blockheight,
             transfer_proof,
@@ -390,6 +583,7 @@ where
         AliceState::XmrRefunded => AliceState::XmrRefunded,
         AliceState::BtcRedeemed => AliceState::BtcRedeemed,
         AliceState::BtcPunished => AliceState::BtcPunished,
+        AliceState::BtcEarlyRefunded => AliceState::BtcEarlyRefunded,
         AliceState::SafelyAborted => AliceState::SafelyAborted,
     })
 }
@@ -400,6 +594,7 @@ fn is_complete(state: &AliceState) -> bool {
         AliceState::XmrRefunded
             | AliceState::BtcRedeemed
             | AliceState::BtcPunished
+            | AliceState::BtcEarlyRefunded
             | AliceState::SafelyAborted
     )
 }
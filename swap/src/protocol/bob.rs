@@ -4,13 +4,16 @@ use anyhow::Result;
 use uuid::Uuid;
 
 use crate::database::Database;
+use crate::network::swap_setup::SessionNonce;
 use crate::{bitcoin, cli, env, monero};
 
 pub use self::state::*;
-pub use self::swap::{run, run_until};
+pub use self::swap::{advance_until, run, run_until, run_until_shutdown};
+pub use self::transitions::{Action, StateKind};
 
 pub mod state;
 pub mod swap;
+pub mod transitions;
 
 pub struct Swap {
     pub state: BobState,
@@ -24,10 +27,12 @@ pub struct Swap {
 }
 
 impl Swap {
+    /// The id of the swap is derived from the session nonce and the seller
+    /// the event loop talks to.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         db: Database,
-        id: Uuid,
+        session_nonce: SessionNonce,
         bitcoin_wallet: Arc<bitcoin::Wallet>,
         monero_wallet: Arc<monero::Wallet>,
         env_config: env::Config,
@@ -36,10 +41,14 @@ impl Swap {
         bitcoin_change_address: bitcoin::Address,
         btc_amount: bitcoin::Amount,
     ) -> Self {
+        let id = session_nonce.swap_id(event_loop_handle.peer_id());
+
         Self {
             state: BobState::Started {
                 btc_amount,
                 change_address: bitcoin_change_address,
+                min_monero: None,
+                session_nonce,
             },
             event_loop_handle,
             db,
@@ -51,6 +60,20 @@ impl Swap {
         }
     }
 
+    /// Aborts the swap before the Bitcoin is locked if the seller offers less
+    /// Monero than `min_monero`, e.g. less than the quoted price promises.
+    pub fn with_min_monero(mut self, min_monero: monero::Amount) -> Self {
+        if let BobState::Started {
+            min_monero: ref mut min,
+            ..
+        } = self.state
+        {
+            *min = Some(min_monero);
+        }
+
+        self
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn from_db(
         db: Database,
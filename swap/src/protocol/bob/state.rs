@@ -1,13 +1,14 @@
 use crate::bitcoin::wallet::EstimateFeeRate;
 use crate::bitcoin::{
-    self, current_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, Transaction, TxCancel,
-    TxLock, Txid,
+    self, blocks_until_punish, current_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock,
+    Transaction, TxCancel, TxEarlyRefund, TxLock, Txid,
 };
-use crate::monero;
 use crate::monero::wallet::WatchRequest;
 use crate::monero::{monero_private_key, TransferProof};
 use crate::monero_ext::ScalarExt;
+use crate::network::swap_setup::SessionNonce;
 use crate::protocol::{Message0, Message1, Message2, Message3, Message4, CROSS_CURVE_PROOF_SYSTEM};
+use crate::{monero, receipt};
 use anyhow::{anyhow, bail, Context, Result};
 use bdk::database::BatchDatabase;
 use ecdsa_fun::adaptor::{Adaptor, HashTranscript};
@@ -26,6 +27,12 @@ pub enum BobState {
     Started {
         btc_amount: bitcoin::Amount,
         change_address: bitcoin::Address,
+        /// The least Monero the swap has to get, the swap is aborted before
+        /// the Bitcoin is locked if the seller offers less.
+        min_monero: Option<monero::Amount>,
+        /// The id of the swap is derived from it, a retried setup announces
+        /// it again.
+        session_nonce: SessionNonce,
     },
     SwapSetupCompleted(State2),
     BtcLocked(State3),
@@ -40,6 +47,11 @@ pub enum BobState {
     CancelTimelockExpired(State6),
     BtcCancelled(State6),
     BtcRefunded(State6),
+    BtcEarlyRefundable {
+        state: State3,
+        tx_early_refund_sig: bitcoin::Signature,
+    },
+    BtcEarlyRefunded(State3),
     XmrRedeemed {
         tx_lock_id: bitcoin::Txid,
     },
@@ -51,24 +63,85 @@ pub enum BobState {
 
 impl fmt::Display for BobState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind().fmt(f)
+    }
+}
+
+impl BobState {
+    /// Amounts, fees and transaction ids that are relevant for the state, but
+    /// none of its secrets.
+    pub fn details(&self) -> String {
         match self {
-            BobState::Started { .. } => write!(f, "quote has been requested"),
-            BobState::SwapSetupCompleted(..) => write!(f, "execution setup done"),
-            BobState::BtcLocked(..) => write!(f, "btc is locked"),
-            BobState::XmrLockProofReceived { .. } => {
-                write!(f, "XMR lock transaction transfer proof received")
+            BobState::Started { btc_amount, .. } => format!("requested to swap {}", btc_amount),
+            BobState::SwapSetupCompleted(state2) => format!(
+                "quote accepted: {} for {}",
+                state2.tx_lock_amount(),
+                state2.xmr()
+            ),
+            BobState::BtcLocked(state3) => format!(
+                "{} locked @ {}",
+                state3.tx_lock.lock_amount(),
+                state3.tx_lock_id()
+            ),
+            BobState::XmrLockProofReceived {
+                lock_transfer_proof,
+                ..
+            } => format!("XMR locked @ {}", lock_transfer_proof.tx_hash()),
+            BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => {
+                format!("BTC lock @ {}", state4.tx_lock.txid())
+            }
+            BobState::BtcRedeemed(state5) => format!("BTC lock @ {}", state5.tx_lock_id()),
+            BobState::CancelTimelockExpired(state6) | BobState::BtcCancelled(state6) => {
+                format!("cancel fee {}", state6.tx_cancel_fee)
+            }
+            BobState::BtcRefunded(state6) => format!(
+                "refunded @ {}, refund fee {}",
+                state6.tx_refund().txid(),
+                state6.tx_refund_fee
+            ),
+            BobState::BtcEarlyRefundable { state, .. } => {
+                format!("early refund @ {}", state.tx_early_refund().txid())
+            }
+            BobState::BtcEarlyRefunded(state3) => {
+                format!("early refunded @ {}", state3.tx_early_refund().txid())
+            }
+            BobState::XmrRedeemed { tx_lock_id } | BobState::BtcPunished { tx_lock_id } => {
+                format!("BTC lock @ {}", tx_lock_id)
             }
-            BobState::XmrLocked(..) => write!(f, "xmr is locked"),
-            BobState::EncSigSent(..) => write!(f, "encrypted signature is sent"),
-            BobState::BtcRedeemed(..) => write!(f, "btc is redeemed"),
-            BobState::CancelTimelockExpired(..) => write!(f, "cancel timelock is expired"),
-            BobState::BtcCancelled(..) => write!(f, "btc is cancelled"),
-            BobState::BtcRefunded(..) => write!(f, "btc is refunded"),
-            BobState::XmrRedeemed { .. } => write!(f, "xmr is redeemed"),
-            BobState::BtcPunished { .. } => write!(f, "btc is punished"),
-            BobState::SafelyAborted => write!(f, "safely aborted"),
+            BobState::SafelyAborted => String::new(),
         }
     }
+
+    /// The timelocks that expired for the locked Bitcoin, `None` if no Bitcoin
+    /// is locked in this state.
+    pub async fn expired_timelocks(
+        &self,
+        bitcoin_wallet: &bitcoin::Wallet,
+    ) -> Result<Option<ExpiredTimelocks>> {
+        let expired_timelocks = match self {
+            BobState::BtcLocked(state3)
+            | BobState::XmrLockProofReceived { state: state3, .. }
+            | BobState::BtcEarlyRefundable { state: state3, .. } => {
+                state3.current_epoch(bitcoin_wallet).await?
+            }
+            BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => {
+                state4.expired_timelock(bitcoin_wallet).await?
+            }
+            BobState::CancelTimelockExpired(state6) | BobState::BtcCancelled(state6) => {
+                state6.expired_timelock(bitcoin_wallet).await?
+            }
+            BobState::Started { .. }
+            | BobState::SwapSetupCompleted(..)
+            | BobState::BtcRedeemed(..)
+            | BobState::BtcRefunded(..)
+            | BobState::BtcEarlyRefunded(..)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => return Ok(None),
+        };
+
+        Ok(Some(expired_timelocks))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -353,6 +426,14 @@ impl State2 {
             self.tx_lock,
         ))
     }
+
+    pub fn xmr(&self) -> monero::Amount {
+        self.xmr
+    }
+
+    pub fn tx_lock_amount(&self) -> bitcoin::Amount {
+        self.tx_lock.lock_amount()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -429,6 +510,8 @@ impl State3 {
             tx_refund_encsig: self.tx_refund_encsig.clone(),
             tx_refund_fee: self.tx_refund_fee,
             tx_cancel_fee: self.tx_cancel_fee,
+            redeem: None,
+            btc_locked: Some(Box::new(self.clone())),
         }
     }
 
@@ -436,6 +519,53 @@ impl State3 {
         self.tx_lock.txid()
     }
 
+    pub fn tx_early_refund(&self) -> TxEarlyRefund {
+        TxEarlyRefund::new(&self.tx_lock, &self.refund_address, self.tx_refund_fee)
+    }
+
+    pub fn tx_redeem(&self) -> bitcoin::TxRedeem {
+        bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address, self.tx_redeem_fee)
+    }
+
+    /// The receipt of the swap if it completed at the given times.
+    pub fn receipt_terms(&self, swap_id: Uuid, transfer_proof: &TransferProof) -> receipt::Terms {
+        receipt::Terms::new(
+            swap_id,
+            self.tx_lock.lock_amount(),
+            self.xmr,
+            self.tx_lock.txid(),
+            self.tx_redeem().txid(),
+            transfer_proof.tx_hash(),
+            self.A,
+            self.b.public(),
+            self.b.clone(),
+        )
+    }
+
+    /// The view key of the Monero lock address, it does not allow spending.
+    pub fn view_key(&self) -> monero::PrivateViewKey {
+        self.v
+    }
+
+    /// Signs with the key of Bob in the Bitcoin lock output, returning the
+    /// public key the signature verifies with.
+    pub fn sign_with_lock_key(
+        &self,
+        digest: ::bitcoin::SigHash,
+    ) -> (bitcoin::PublicKey, bitcoin::Signature) {
+        (self.b.public(), self.b.sign(digest))
+    }
+
+    /// Completes the early refund transaction, failing if the signature is not
+    /// the one of Alice.
+    pub fn signed_early_refund_transaction(
+        &self,
+        tx_early_refund_sig_a: bitcoin::Signature,
+    ) -> Result<Transaction> {
+        self.tx_early_refund()
+            .complete_as_bob(self.A, self.b.clone(), tx_early_refund_sig_a)
+    }
+
     pub async fn current_epoch(
         &self,
         bitcoin_wallet: &bitcoin::Wallet,
@@ -484,15 +614,16 @@ pub struct State4 {
 }
 
 impl State4 {
+    pub fn tx_redeem(&self) -> bitcoin::TxRedeem {
+        bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address, self.tx_redeem_fee)
+    }
+
     pub fn tx_redeem_encsig(&self) -> bitcoin::EncryptedSignature {
-        let tx_redeem =
-            bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address, self.tx_redeem_fee);
-        self.b.encsign(self.S_a_bitcoin, tx_redeem.digest())
+        self.b.encsign(self.S_a_bitcoin, self.tx_redeem().digest())
     }
 
     pub async fn watch_for_redeem_btc(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<State5> {
-        let tx_redeem =
-            bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address, self.tx_redeem_fee);
+        let tx_redeem = self.tx_redeem();
         let tx_redeem_encsig = self.b.encsign(self.S_a_bitcoin, tx_redeem.digest());
 
         bitcoin_wallet
@@ -553,6 +684,14 @@ impl State4 {
             tx_refund_encsig: self.tx_refund_encsig,
             tx_refund_fee: self.tx_refund_fee,
             tx_cancel_fee: self.tx_cancel_fee,
+            redeem: Some(RedeemParams {
+                S_a_bitcoin: self.S_a_bitcoin,
+                v: self.v,
+                redeem_address: self.redeem_address,
+                tx_redeem_fee: self.tx_redeem_fee,
+                monero_wallet_restore_blockheight: self.monero_wallet_restore_blockheight,
+            }),
+            btc_locked: None,
         }
     }
 }
@@ -580,6 +719,10 @@ impl State5 {
     }
 }
 
+/// The number of blocks the refund transaction is given to confirm before the
+/// punish timelock expires.
+pub const REFUND_SAFETY_MARGIN: u32 = 3;
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct State6 {
     A: bitcoin::PublicKey,
@@ -595,6 +738,25 @@ pub struct State6 {
     pub tx_refund_fee: bitcoin::Amount,
     #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
     pub tx_cancel_fee: bitcoin::Amount,
+    /// Only known if the swap was cancelled after the Monero was locked, not
+    /// stored by older versions.
+    #[serde(default)]
+    redeem: Option<RedeemParams>,
+    /// Only known if the swap was cancelled while waiting for Alice to lock
+    /// the Monero, not stored by older versions.
+    #[serde(default)]
+    btc_locked: Option<Box<State3>>,
+}
+
+/// What is needed to learn the secret of Alice from her redeem transaction.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct RedeemParams {
+    S_a_bitcoin: bitcoin::PublicKey,
+    v: monero::PrivateViewKey,
+    redeem_address: bitcoin::Address,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    tx_redeem_fee: bitcoin::Amount,
+    monero_wallet_restore_blockheight: BlockHeight,
 }
 
 impl State6 {
@@ -621,6 +783,38 @@ impl State6 {
         ))
     }
 
+    pub async fn blocks_until_punish(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<u32> {
+        let tx_cancel = TxCancel::new(
+            &self.tx_lock,
+            self.cancel_timelock,
+            self.A,
+            self.b.public(),
+            self.tx_cancel_fee,
+        );
+
+        let tx_cancel_status = bitcoin_wallet.status_of_script(&tx_cancel).await?;
+
+        Ok(blocks_until_punish(self.punish_timelock, tx_cancel_status))
+    }
+
+    /// Checks whether the refund transaction still has
+    /// [`REFUND_SAFETY_MARGIN`] blocks to confirm before Alice can punish.
+    ///
+    /// The refund is published regardless, as it is the only way to get the
+    /// Bitcoin back, but the user is warned that it might lose the race.
+    pub async fn check_refund_safety(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<()> {
+        let blocks_left = self.blocks_until_punish(bitcoin_wallet).await?;
+
+        if blocks_left < REFUND_SAFETY_MARGIN {
+            tracing::warn!(
+                %blocks_left,
+                "The punish timelock is about to expire, the refund might not be confirmed in time"
+            );
+        }
+
+        Ok(())
+    }
+
     pub async fn check_for_tx_cancel(
         &self,
         bitcoin_wallet: &bitcoin::Wallet,
@@ -638,22 +832,90 @@ impl State6 {
         Ok(tx)
     }
 
+    /// Learns the secret of Alice if she redeemed the Bitcoin before the cancel
+    /// transaction was published.
+    pub async fn check_for_tx_redeem(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<State5> {
+        let redeem = self
+            .redeem
+            .as_ref()
+            .context("Alice cannot redeem before the Monero is locked")?;
+
+        let tx_redeem =
+            bitcoin::TxRedeem::new(&self.tx_lock, &redeem.redeem_address, redeem.tx_redeem_fee);
+        let tx_redeem_encsig = self.b.encsign(redeem.S_a_bitcoin, tx_redeem.digest());
+
+        let tx_redeem_candidate = bitcoin_wallet.get_raw_transaction(tx_redeem.txid()).await?;
+
+        let tx_redeem_sig =
+            tx_redeem.extract_signature_by_key(tx_redeem_candidate, self.b.public())?;
+        let s_a = bitcoin::recover(redeem.S_a_bitcoin, tx_redeem_sig, tx_redeem_encsig)?;
+        let s_a = monero::private_key_from_secp256k1_scalar(s_a.into());
+
+        Ok(State5 {
+            s_a,
+            s_b: self.s_b,
+            v: redeem.v,
+            tx_lock: self.tx_lock.clone(),
+            monero_wallet_restore_blockheight: redeem.monero_wallet_restore_blockheight,
+        })
+    }
+
+    /// The state to wait for Alice to lock the Monero in again, if a
+    /// reorganisation of the blockchain undid the expiry of the cancel
+    /// timelock and dropped the cancel transaction.
+    ///
+    /// `None` if the swap was cancelled after the Monero was locked, it is
+    /// cancelled again once the timelock expires.
+    pub async fn reverted_by_reorg(
+        &self,
+        bitcoin_wallet: &bitcoin::Wallet,
+    ) -> Result<Option<State3>> {
+        let state3 = match &self.btc_locked {
+            Some(state3) => state3,
+            None => return Ok(None),
+        };
+
+        if !matches!(
+            self.expired_timelock(bitcoin_wallet).await?,
+            ExpiredTimelocks::None
+        ) {
+            return Ok(None);
+        }
+        if bitcoin_wallet
+            .status_of_script(&self.tx_cancel())
+            .await?
+            .has_been_seen()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(state3.as_ref().clone()))
+    }
+
+    /// Waits until the cancel timelock expired, which a reorganisation of
+    /// the blockchain can undo.
+    pub async fn wait_for_cancel_timelock(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<()> {
+        bitcoin_wallet
+            .subscribe_to(self.tx_lock.clone())
+            .await
+            .wait_until_confirmed_with(self.cancel_timelock)
+            .await
+    }
+
     pub async fn submit_tx_cancel(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<Txid> {
-        let transaction = bitcoin::TxCancel::new(
-            &self.tx_lock,
-            self.cancel_timelock,
-            self.A,
-            self.b.public(),
-            self.tx_cancel_fee,
-        )
-        .complete_as_bob(self.A, self.b.clone(), self.tx_cancel_sig_a.clone())
-        .context("Failed to complete Bitcoin cancel transaction")?;
+        let transaction = self.signed_cancel_transaction()?;
 
         let (tx_id, _) = bitcoin_wallet.broadcast(transaction, "cancel").await?;
 
         Ok(tx_id)
     }
 
+    pub fn signed_cancel_transaction(&self) -> Result<Transaction> {
+        self.tx_cancel()
+            .complete_as_bob(self.A, self.b.clone(), self.tx_cancel_sig_a.clone())
+            .context("Failed to complete Bitcoin cancel transaction")
+    }
+
     pub async fn publish_refund_btc(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<()> {
         let signed_tx_refund = self.signed_refund_transaction()?;
         let (_, subscription) = bitcoin_wallet.broadcast(signed_tx_refund, "refund").await?;
@@ -663,15 +925,7 @@ impl State6 {
     }
 
     pub fn signed_refund_transaction(&self) -> Result<Transaction> {
-        let tx_cancel = bitcoin::TxCancel::new(
-            &self.tx_lock,
-            self.cancel_timelock,
-            self.A,
-            self.b.public(),
-            self.tx_cancel_fee,
-        );
-        let tx_refund =
-            bitcoin::TxRefund::new(&tx_cancel, &self.refund_address, self.tx_refund_fee);
+        let tx_refund = self.tx_refund();
 
         let adaptor = Adaptor::<HashTranscript<Sha256>, Deterministic<Sha256>>::default();
 
@@ -684,7 +938,37 @@ impl State6 {
         Ok(signed_tx_refund)
     }
 
+    pub fn tx_lock(&self) -> &bitcoin::TxLock {
+        &self.tx_lock
+    }
+
+    pub fn tx_cancel(&self) -> TxCancel {
+        TxCancel::new(
+            &self.tx_lock,
+            self.cancel_timelock,
+            self.A,
+            self.b.public(),
+            self.tx_cancel_fee,
+        )
+    }
+
+    pub fn tx_refund(&self) -> bitcoin::TxRefund {
+        bitcoin::TxRefund::new(&self.tx_cancel(), &self.refund_address, self.tx_refund_fee)
+    }
+
     pub fn tx_lock_id(&self) -> bitcoin::Txid {
         self.tx_lock.txid()
     }
+
+    pub fn cancel_timelock(&self) -> CancelTimelock {
+        self.cancel_timelock
+    }
+
+    pub fn punish_timelock(&self) -> PunishTimelock {
+        self.punish_timelock
+    }
+
+    pub fn refund_address(&self) -> &bitcoin::Address {
+        &self.refund_address
+    }
 }
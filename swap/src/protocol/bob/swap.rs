@@ -1,22 +1,31 @@
 use crate::bitcoin::{ExpiredTimelocks, TxCancel, TxRefund};
-use crate::cli::EventLoopHandle;
-use crate::database::Swap;
+use crate::cli::view_key::ViewKeyExport;
+use crate::cli::{BtcLockedMessage, EventLoopHandle};
+use crate::database::{Database, Swap, SwapNotFound};
 use crate::network::swap_setup::bob::NewSwap;
 use crate::protocol::bob;
 use crate::protocol::bob::state::*;
+use crate::protocol::bob::StateKind;
+use crate::receipt::{unix_timestamp, SignedReceipt};
+use crate::shutdown::Shutdown;
 use crate::{bitcoin, monero};
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
+use monero_rpc::wallet::BlockHeight;
 use tokio::select;
 use uuid::Uuid;
 
 pub fn is_complete(state: &BobState) -> bool {
-    matches!(
-        state,
-        BobState::BtcRefunded(..)
-            | BobState::XmrRedeemed { .. }
-            | BobState::BtcPunished { .. }
-            | BobState::SafelyAborted
-    )
+    state.kind().is_final()
+}
+
+/// The stored state of the swap, if it is complete.
+///
+/// A complete swap only needs the connection to the seller to exchange the
+/// receipt, losing it does not fail the swap.
+pub fn completed_state(db: &Database, swap_id: Uuid) -> Result<Option<BobState>> {
+    let state = BobState::from(db.get_state(swap_id)?.try_into_bob()?);
+
+    Ok(Some(state).filter(is_complete))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -28,26 +37,193 @@ pub async fn run_until(
     mut swap: bob::Swap,
     is_target_state: fn(&BobState) -> bool,
 ) -> Result<BobState> {
-    let mut current_state = swap.state;
+    advance_until(&mut swap, is_target_state).await
+}
+
+/// Runs the swap like [`run`] but suspends it once shutdown is requested.
+///
+/// Transitions that cannot be repeated safely are completed before the swap
+/// is suspended. Returns `None` if the swap was suspended, its last state is
+/// stored in the database.
+pub async fn run_until_shutdown(
+    mut swap: bob::Swap,
+    shutdown: Shutdown,
+) -> Result<Option<BobState>> {
+    advance(&mut swap, is_complete, shutdown).await
+}
+
+/// Runs the swap until the target state is reached without consuming it, so
+/// the swap can be continued from there.
+pub async fn advance_until(
+    swap: &mut bob::Swap,
+    is_target_state: fn(&BobState) -> bool,
+) -> Result<BobState> {
+    let state = advance(swap, is_target_state, Shutdown::never())
+        .await?
+        .expect("swaps are only suspended on shutdown");
 
-    while !is_target_state(&current_state) {
-        current_state = next_state(
+    Ok(state)
+}
+
+#[tracing::instrument(name = "swap", skip(swap, is_target_state, shutdown), fields(id = %swap.id, peer = %swap.event_loop_handle.peer_id()), err)]
+async fn advance(
+    swap: &mut bob::Swap,
+    is_target_state: fn(&BobState) -> bool,
+    mut shutdown: Shutdown,
+) -> Result<Option<BobState>> {
+    // A swap whose setup failed can be resumed, which retries the setup under
+    // the same id.
+    if let BobState::Started { .. } = swap.state {
+        match swap.db.get_state(swap.id) {
+            Ok(_) => {}
+            Err(error) if error.is::<SwapNotFound>() => {
+                swap.db
+                    .insert_latest_state(swap.id, Swap::Bob(swap.state.clone().into()))
+                    .await?;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    while !is_target_state(&swap.state) {
+        let suspendable = is_suspendable(&swap.state);
+        let next = next_state(
             swap.id,
-            current_state,
+            swap.state.clone(),
             &mut swap.event_loop_handle,
             swap.bitcoin_wallet.as_ref(),
             swap.monero_wallet.as_ref(),
             swap.monero_receive_address,
-        )
-        .await?;
+        );
+
+        let current_state = if suspendable {
+            select! {
+                biased;
+                _ = shutdown.requested() => {
+                    tracing::info!("Suspended swap");
+                    return Ok(None);
+                }
+                next = next => next?,
+            }
+        } else {
+            next.await?
+        };
 
         let db_state = current_state.clone().into();
         swap.db
             .insert_latest_state(swap.id, Swap::Bob(db_state))
             .await?;
+
+        if let BobState::XmrLockProofReceived {
+            state,
+            lock_transfer_proof,
+            monero_wallet_restore_blockheight,
+        } = &current_state
+        {
+            record_xmr_lock(
+                swap,
+                state,
+                lock_transfer_proof,
+                *monero_wallet_restore_blockheight,
+            )
+            .await;
+        }
+
+        swap.state = current_state;
+    }
+
+    if matches!(swap.state, BobState::XmrRedeemed { .. }) && swap.db.get_receipt(swap.id).is_err() {
+        select! {
+            biased;
+            _ = shutdown.requested() => {}
+            result = exchange_receipt(swap) => {
+                if let Err(error) = result {
+                    tracing::warn!("Failed to get a receipt of the swap signed by the seller: {:#}", error);
+                }
+            }
+        }
     }
 
-    Ok(current_state)
+    Ok(Some(swap.state.clone()))
+}
+
+/// Records what later states of the swap no longer hold, but is needed to
+/// sign the receipt and export the view key of the Monero lock.
+///
+/// Neither is needed to complete the swap, which goes on if recording fails.
+async fn record_xmr_lock(
+    swap: &bob::Swap,
+    state: &State3,
+    transfer_proof: &monero::TransferProof,
+    restore_height: BlockHeight,
+) {
+    let terms = state.receipt_terms(swap.id, transfer_proof);
+    if let Err(error) = swap.db.insert_receipt_terms(swap.id, &terms).await {
+        tracing::warn!("Failed to record the terms of the receipt: {:#}", error);
+    }
+
+    let view_key = ViewKeyExport::new(
+        swap.id,
+        state,
+        transfer_proof.clone(),
+        restore_height,
+        swap.env_config.monero_network,
+    );
+    if let Err(error) = swap.db.insert_view_key(swap.id, &view_key).await {
+        tracing::warn!(
+            "Failed to record the view key of the Monero lock: {:#}",
+            error
+        );
+    }
+}
+
+/// Asks the seller to countersign the receipt of the completed swap and
+/// stores it.
+async fn exchange_receipt(swap: &mut bob::Swap) -> Result<()> {
+    let history = swap.db.get_history(swap.id)?;
+
+    let btc_locked_at = history
+        .iter()
+        .find(|transition| transition.state == StateKind::BtcLocked.to_string())
+        .map(|transition| transition.timestamp)
+        .context("The history of the swap does not record when the Bitcoin was locked")?;
+    let completed_at = history
+        .last()
+        .map(|transition| transition.timestamp)
+        .context("The history of the swap is empty")?;
+    let terms = swap.db.get_receipt_terms(swap.id)?;
+
+    let receipt = terms.receipt(unix_timestamp(btc_locked_at), unix_timestamp(completed_at));
+    let buyer_signature = terms.sign(&receipt);
+    let seller_signature = swap
+        .event_loop_handle
+        .request_receipt(receipt.clone(), buyer_signature.clone())
+        .await?;
+
+    let receipt = SignedReceipt {
+        receipt,
+        seller_signature,
+        buyer_signature,
+    };
+    receipt
+        .verify()
+        .context("Seller signed the receipt with the wrong key")?;
+    swap.db.insert_receipt(swap.id, &receipt).await?;
+
+    tracing::info!(
+        "Stored the receipt of the swap, export it with `swap receipt --swap-id {}`",
+        swap.id
+    );
+
+    Ok(())
+}
+
+/// Whether the transition out of this state can be interrupted and repeated
+/// after a restart.
+///
+/// Redeeming the Monero switches the wallet loaded by the monero-wallet-rpc.
+fn is_suspendable(state: &BobState) -> bool {
+    !matches!(state, BobState::BtcRedeemed(..))
 }
 
 async fn next_state(
@@ -64,6 +240,8 @@ async fn next_state(
         BobState::Started {
             btc_amount,
             change_address,
+            min_monero,
+            session_nonce,
         } => {
             let tx_refund_fee = bitcoin_wallet
                 .estimate_fee(TxRefund::weight(), btc_amount)
@@ -75,6 +253,7 @@ async fn next_state(
             let state2 = event_loop_handle
                 .setup_swap(NewSwap {
                     swap_id,
+                    session_nonce,
                     btc: btc_amount,
                     tx_refund_fee,
                     tx_cancel_fee,
@@ -82,6 +261,18 @@ async fn next_state(
                 })
                 .await?;
 
+            if let Some(min_monero) = min_monero {
+                if state2.xmr() < min_monero {
+                    tracing::error!(
+                        xmr = %state2.xmr(),
+                        %min_monero,
+                        "The seller offers less Monero than accepted, aborting the swap before locking the Bitcoin"
+                    );
+
+                    return Ok(BobState::SafelyAborted);
+                }
+            }
+
             BobState::SwapSetupCompleted(state2)
         }
         BobState::SwapSetupCompleted(state2) => {
@@ -101,7 +292,7 @@ async fn next_state(
             let tx_lock_status = bitcoin_wallet.subscribe_to(state3.tx_lock.clone()).await;
 
             if let ExpiredTimelocks::None = state3.current_epoch(bitcoin_wallet).await? {
-                let transfer_proof_watcher = event_loop_handle.recv_transfer_proof();
+                let alice_watcher = wait_for_alice(&state3, event_loop_handle);
                 let cancel_timelock_expires =
                     tx_lock_status.wait_until_confirmed_with(state3.cancel_timelock);
 
@@ -112,15 +303,25 @@ async fn next_state(
                 tracing::info!("Waiting for Alice to lock Monero");
 
                 select! {
-                    transfer_proof = transfer_proof_watcher => {
-                        let transfer_proof = transfer_proof?;
+                    message = alice_watcher => {
+                        match message? {
+                            BtcLockedMessage::TransferProof(transfer_proof) => {
+                                tracing::info!(txid = %transfer_proof.tx_hash(), "Alice locked Monero");
 
-                        tracing::info!(txid = %transfer_proof.tx_hash(), "Alice locked Monero");
+                                BobState::XmrLockProofReceived {
+                                    state: state3,
+                                    lock_transfer_proof: transfer_proof,
+                                    monero_wallet_restore_blockheight
+                                }
+                            }
+                            BtcLockedMessage::EarlyRefund(tx_early_refund_sig) => {
+                                tracing::info!("Alice will not lock Monero and allowed to refund the Bitcoin right away");
 
-                        BobState::XmrLockProofReceived {
-                            state: state3,
-                            lock_transfer_proof: transfer_proof,
-                            monero_wallet_restore_blockheight
+                                BobState::BtcEarlyRefundable {
+                                    state: state3,
+                                    tx_early_refund_sig,
+                                }
+                            }
                         }
                     },
                     _ = cancel_timelock_expires => {
@@ -239,8 +440,24 @@ async fn next_state(
             }
         }
         BobState::CancelTimelockExpired(state4) => {
+            if let Some(state3) = state4.reverted_by_reorg(bitcoin_wallet).await? {
+                tracing::warn!(
+                    "The cancel timelock is no longer expired, the blockchain was reorganised. Waiting for Alice to lock the Monero again"
+                );
+
+                return Ok(BobState::BtcLocked(state3));
+            }
+
             if state4.check_for_tx_cancel(bitcoin_wallet).await.is_err() {
-                state4.submit_tx_cancel(bitcoin_wallet).await?;
+                if let Err(error) = state4.submit_tx_cancel(bitcoin_wallet).await {
+                    // Alice can still redeem once the cancel timelock expired, as long as the
+                    // cancel transaction is not published. Her redeem transaction reveals the
+                    // secret needed to redeem the Monero.
+                    return match state4.check_for_tx_redeem(bitcoin_wallet).await {
+                        Ok(state5) => Ok(BobState::BtcRedeemed(state5)),
+                        Err(_) => Err(error),
+                    };
+                }
             }
 
             BobState::BtcCancelled(state4)
@@ -249,11 +466,29 @@ async fn next_state(
             // Bob has cancelled the swap
             match state.expired_timelock(bitcoin_wallet).await? {
                 ExpiredTimelocks::None => {
-                    bail!(
-                        "Internal error: canceled state reached before cancel timelock was expired"
+                    // The lock transaction lost confirmations, the cancel transaction may have
+                    // been dropped with them.
+                    if let Some(state3) = state.reverted_by_reorg(bitcoin_wallet).await? {
+                        tracing::warn!(
+                            "The cancel timelock is no longer expired and the cancel transaction was dropped, the blockchain was reorganised. Waiting for Alice to lock the Monero again"
+                        );
+
+                        return Ok(BobState::BtcLocked(state3));
+                    }
+
+                    tracing::warn!(
+                        "The cancel timelock is no longer expired, the blockchain was reorganised. Waiting for it to expire again"
                     );
+                    state.wait_for_cancel_timelock(bitcoin_wallet).await?;
+
+                    if state.check_for_tx_cancel(bitcoin_wallet).await.is_err() {
+                        state.submit_tx_cancel(bitcoin_wallet).await?;
+                    }
+
+                    BobState::BtcCancelled(state)
                 }
                 ExpiredTimelocks::Cancel => {
+                    state.check_refund_safety(bitcoin_wallet).await?;
                     state.publish_refund_btc(bitcoin_wallet).await?;
                     BobState::BtcRefunded(state)
                 }
@@ -262,9 +497,65 @@ async fn next_state(
                 },
             }
         }
+        BobState::BtcEarlyRefundable {
+            state,
+            tx_early_refund_sig,
+        } => {
+            let tx_early_refund = state.tx_early_refund();
+
+            if bitcoin_wallet
+                .get_raw_transaction(tx_early_refund.txid())
+                .await
+                .is_err()
+            {
+                let signed_tx = state.signed_early_refund_transaction(tx_early_refund_sig)?;
+
+                if let Err(error) = bitcoin_wallet.broadcast(signed_tx, "early refund").await {
+                    // The lock output can only be spent once, if Bob already cancelled the
+                    // swap he has to refund the usual way.
+                    return match state.current_epoch(bitcoin_wallet).await? {
+                        ExpiredTimelocks::None => Err(error),
+                        _ => Ok(BobState::CancelTimelockExpired(state.cancel())),
+                    };
+                }
+            }
+
+            bitcoin_wallet
+                .subscribe_to(tx_early_refund)
+                .await
+                .wait_until_final()
+                .await?;
+
+            BobState::BtcEarlyRefunded(state)
+        }
         BobState::BtcRefunded(state4) => BobState::BtcRefunded(state4),
+        BobState::BtcEarlyRefunded(state) => BobState::BtcEarlyRefunded(state),
         BobState::BtcPunished { tx_lock_id } => BobState::BtcPunished { tx_lock_id },
         BobState::SafelyAborted => BobState::SafelyAborted,
         BobState::XmrRedeemed { tx_lock_id } => BobState::XmrRedeemed { tx_lock_id },
     })
 }
+
+/// Waits for the transfer proof or a valid early refund signature of Alice,
+/// early refund signatures that don't complete the early refund transaction
+/// are ignored.
+async fn wait_for_alice(
+    state3: &State3,
+    event_loop_handle: &mut EventLoopHandle,
+) -> Result<BtcLockedMessage> {
+    loop {
+        let message = event_loop_handle
+            .recv_transfer_proof_or_early_refund()
+            .await?;
+
+        if let BtcLockedMessage::EarlyRefund(tx_early_refund_sig) = &message {
+            if let Err(error) = state3.signed_early_refund_transaction(tx_early_refund_sig.clone())
+            {
+                tracing::warn!("Ignoring early refund signature: {:#}", error);
+                continue;
+            }
+        }
+
+        return Ok(message);
+    }
+}
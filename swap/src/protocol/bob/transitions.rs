@@ -0,0 +1,190 @@
+use crate::protocol::bob::BobState;
+use std::fmt;
+
+/// The kind of a [`BobState`] without any of its data.
+///
+/// The transitions between the kinds mirror the ones taken in
+/// [`run_until`](crate::protocol::bob::run_until) and are used to explain a
+/// swap to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumIter)]
+pub enum StateKind {
+    Started,
+    SwapSetupCompleted,
+    BtcLocked,
+    XmrLockProofReceived,
+    XmrLocked,
+    EncSigSent,
+    BtcRedeemed,
+    CancelTimelockExpired,
+    BtcCancelled,
+    BtcRefunded,
+    BtcEarlyRefundable,
+    BtcEarlyRefunded,
+    XmrRedeemed,
+    BtcPunished,
+    SafelyAborted,
+}
+
+/// What the user can do with a swap in a given state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Action {
+    Resume,
+    Cancel,
+    Refund,
+}
+
+impl StateKind {
+    /// The states a swap can transition into from this state.
+    pub fn successors(self) -> &'static [StateKind] {
+        use StateKind::*;
+
+        match self {
+            Started => &[SwapSetupCompleted],
+            SwapSetupCompleted => &[BtcLocked],
+            BtcLocked => &[
+                XmrLockProofReceived,
+                CancelTimelockExpired,
+                BtcEarlyRefundable,
+            ],
+            XmrLockProofReceived => &[XmrLocked, CancelTimelockExpired],
+            XmrLocked => &[EncSigSent, CancelTimelockExpired],
+            EncSigSent => &[BtcRedeemed, CancelTimelockExpired],
+            BtcRedeemed => &[XmrRedeemed],
+            CancelTimelockExpired => &[BtcCancelled, BtcRedeemed],
+            BtcCancelled => &[BtcRefunded, BtcPunished],
+            BtcEarlyRefundable => &[BtcEarlyRefunded, CancelTimelockExpired],
+            BtcRefunded | BtcEarlyRefunded | XmrRedeemed | BtcPunished | SafelyAborted => &[],
+        }
+    }
+
+    pub fn is_final(self) -> bool {
+        self.successors().is_empty()
+    }
+
+    /// The actions the user can take, derived from the transitions out of this
+    /// state.
+    ///
+    /// Cancelling is only possible once the cancel timelock expired.
+    pub fn actions(self) -> Vec<Action> {
+        if self.is_final() {
+            return Vec::new();
+        }
+
+        let mut actions = vec![Action::Resume];
+        let successors = self.successors();
+
+        if successors.iter().any(|successor| {
+            matches!(
+                successor,
+                StateKind::CancelTimelockExpired | StateKind::BtcCancelled
+            )
+        }) {
+            actions.push(Action::Cancel);
+        }
+
+        if successors.contains(&StateKind::BtcRefunded) {
+            actions.push(Action::Refund);
+        }
+
+        actions
+    }
+}
+
+impl fmt::Display for StateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateKind::Started => write!(f, "quote has been requested"),
+            StateKind::SwapSetupCompleted => write!(f, "execution setup done"),
+            StateKind::BtcLocked => write!(f, "btc is locked"),
+            StateKind::XmrLockProofReceived => {
+                write!(f, "XMR lock transaction transfer proof received")
+            }
+            StateKind::XmrLocked => write!(f, "xmr is locked"),
+            StateKind::EncSigSent => write!(f, "encrypted signature is sent"),
+            StateKind::BtcRedeemed => write!(f, "btc is redeemed"),
+            StateKind::CancelTimelockExpired => write!(f, "cancel timelock is expired"),
+            StateKind::BtcCancelled => write!(f, "btc is cancelled"),
+            StateKind::BtcRefunded => write!(f, "btc is refunded"),
+            StateKind::BtcEarlyRefundable => write!(f, "btc is early refundable"),
+            StateKind::BtcEarlyRefunded => write!(f, "btc is early refunded"),
+            StateKind::XmrRedeemed => write!(f, "xmr is redeemed"),
+            StateKind::BtcPunished => write!(f, "btc is punished"),
+            StateKind::SafelyAborted => write!(f, "safely aborted"),
+        }
+    }
+}
+
+impl BobState {
+    pub fn kind(&self) -> StateKind {
+        match self {
+            BobState::Started { .. } => StateKind::Started,
+            BobState::SwapSetupCompleted(..) => StateKind::SwapSetupCompleted,
+            BobState::BtcLocked(..) => StateKind::BtcLocked,
+            BobState::XmrLockProofReceived { .. } => StateKind::XmrLockProofReceived,
+            BobState::XmrLocked(..) => StateKind::XmrLocked,
+            BobState::EncSigSent(..) => StateKind::EncSigSent,
+            BobState::BtcRedeemed(..) => StateKind::BtcRedeemed,
+            BobState::CancelTimelockExpired(..) => StateKind::CancelTimelockExpired,
+            BobState::BtcCancelled(..) => StateKind::BtcCancelled,
+            BobState::BtcRefunded(..) => StateKind::BtcRefunded,
+            BobState::BtcEarlyRefundable { .. } => StateKind::BtcEarlyRefundable,
+            BobState::BtcEarlyRefunded(..) => StateKind::BtcEarlyRefunded,
+            BobState::XmrRedeemed { .. } => StateKind::XmrRedeemed,
+            BobState::BtcPunished { .. } => StateKind::BtcPunished,
+            BobState::SafelyAborted => StateKind::SafelyAborted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn every_state_is_reachable_from_started() {
+        let mut reached = HashSet::new();
+        let mut pending = vec![StateKind::Started];
+
+        while let Some(kind) = pending.pop() {
+            if reached.insert(kind) {
+                pending.extend(kind.successors());
+            }
+        }
+
+        let unreachable = StateKind::iter()
+            .filter(|kind| !reached.contains(kind))
+            .collect::<Vec<_>>();
+
+        // The state machine never aborts a swap by itself.
+        assert_eq!(unreachable, vec![StateKind::SafelyAborted]);
+    }
+
+    #[test]
+    fn every_non_final_state_can_reach_a_final_state() {
+        for kind in StateKind::iter() {
+            let mut current = kind;
+            while !current.is_final() {
+                current = current.successors()[0];
+            }
+        }
+    }
+
+    #[test]
+    fn actions_are_derived_from_transitions() {
+        assert_eq!(StateKind::SwapSetupCompleted.actions(), vec![
+            Action::Resume
+        ]);
+        assert_eq!(StateKind::EncSigSent.actions(), vec![
+            Action::Resume,
+            Action::Cancel
+        ]);
+        assert_eq!(StateKind::BtcCancelled.actions(), vec![
+            Action::Resume,
+            Action::Refund
+        ]);
+        assert_eq!(StateKind::XmrRedeemed.actions(), vec![]);
+    }
+}
@@ -0,0 +1,275 @@
+//! Receipts of completed swaps, signed by both parties.
+//!
+//! A receipt states what was traded and names the transactions that moved
+//! the funds. Each party signs it with the key it contributed to the Bitcoin
+//! lock output, so anyone can check that the signatures belong to the two
+//! parties of the lock transaction named in the receipt. Receipts are
+//! exchanged once the swap completed and can be exported as JSON, e.g. for
+//! accounting or to settle a dispute.
+
+use crate::bitcoin::{verify_sig, PublicKey, SecretKey, Signature, Txid};
+use crate::monero::TxHash;
+use crate::{bitcoin, monero};
+use ::bitcoin::hashes::Hash;
+use ::bitcoin::SigHash;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const SIGNATURE_DOMAIN: &[u8] = b"/comit/xmr/btc/receipt/signature";
+
+/// How far the timestamps of the buyer may deviate from the ones of the seller
+/// for the seller to still sign the receipt.
+pub const MAX_TIMESTAMP_DEVIATION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Receipt {
+    /// The id the seller knows the swap by.
+    pub swap_id: Uuid,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub btc: bitcoin::Amount,
+    /// In piconero.
+    pub xmr: monero::Amount,
+    pub tx_lock_id: Txid,
+    pub tx_redeem_id: Txid,
+    pub xmr_lock_tx_hash: TxHash,
+    /// The key of the seller in the Bitcoin lock output.
+    pub seller: PublicKey,
+    /// The key of the buyer in the Bitcoin lock output.
+    pub buyer: PublicKey,
+    /// When the Bitcoin was locked, in seconds since the Unix epoch.
+    pub started_at: u64,
+    /// When the buyer redeemed the Monero, in seconds since the Unix epoch.
+    pub completed_at: u64,
+}
+
+impl Receipt {
+    /// Whether the timestamps, which are taken by the buyer, agree with when
+    /// the seller saw the Bitcoin being locked.
+    pub fn has_plausible_timestamps(&self, btc_locked_at: SystemTime, now: SystemTime) -> bool {
+        let deviation = MAX_TIMESTAMP_DEVIATION.as_secs();
+        let btc_locked_at = unix_timestamp(btc_locked_at);
+
+        self.started_at.max(btc_locked_at) - self.started_at.min(btc_locked_at) <= deviation
+            && self.completed_at >= self.started_at
+            && self.completed_at <= unix_timestamp(now) + deviation
+    }
+
+    pub fn sign(&self, key: &SecretKey) -> Signature {
+        key.sign(self.digest())
+    }
+
+    pub fn verify(&self, key: &PublicKey, signature: &Signature) -> Result<(), InvalidSignature> {
+        verify_sig(key, &self.digest(), signature).map_err(|_| InvalidSignature)
+    }
+
+    fn digest(&self) -> SigHash {
+        SigHash::hash(&self.message())
+    }
+
+    fn message(&self) -> Vec<u8> {
+        let mut message = SIGNATURE_DOMAIN.to_vec();
+        message.extend_from_slice(self.swap_id.as_bytes());
+        message.extend_from_slice(&self.btc.as_sat().to_be_bytes());
+        message.extend_from_slice(&self.xmr.as_piconero().to_be_bytes());
+        message.extend_from_slice(&self.tx_lock_id.into_inner());
+        message.extend_from_slice(&self.tx_redeem_id.into_inner());
+        // The length keeps the variable sized hash from blending into the keys
+        // that follow.
+        message.extend_from_slice(&(self.xmr_lock_tx_hash.0.len() as u64).to_be_bytes());
+        message.extend_from_slice(self.xmr_lock_tx_hash.0.as_bytes());
+        message.extend_from_slice(&::bitcoin::PublicKey::from(self.seller).to_bytes());
+        message.extend_from_slice(&::bitcoin::PublicKey::from(self.buyer).to_bytes());
+        message.extend_from_slice(&self.started_at.to_be_bytes());
+        message.extend_from_slice(&self.completed_at.to_be_bytes());
+        message
+    }
+}
+
+/// What a party needs to sign the receipt of a swap.
+///
+/// The terms are recorded once the Monero is locked, as the later states of a
+/// swap do not hold all of them. Besides the public data of the receipt they
+/// hold the key of the party in the Bitcoin lock output. Until the swap
+/// completes it spends the lock output together with the key of the other
+/// party, so the terms are as sensitive as the state of the swap, which holds
+/// the same key at that point.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Terms {
+    pub swap_id: Uuid,
+    #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
+    pub btc: bitcoin::Amount,
+    pub xmr: monero::Amount,
+    pub tx_lock_id: Txid,
+    pub tx_redeem_id: Txid,
+    pub xmr_lock_tx_hash: TxHash,
+    pub seller: PublicKey,
+    pub buyer: PublicKey,
+    key: SecretKey,
+}
+
+impl Terms {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        swap_id: Uuid,
+        btc: bitcoin::Amount,
+        xmr: monero::Amount,
+        tx_lock_id: Txid,
+        tx_redeem_id: Txid,
+        xmr_lock_tx_hash: TxHash,
+        seller: PublicKey,
+        buyer: PublicKey,
+        key: SecretKey,
+    ) -> Self {
+        Self {
+            swap_id,
+            btc,
+            xmr,
+            tx_lock_id,
+            tx_redeem_id,
+            xmr_lock_tx_hash,
+            seller,
+            buyer,
+            key,
+        }
+    }
+
+    pub fn receipt(&self, started_at: u64, completed_at: u64) -> Receipt {
+        Receipt {
+            swap_id: self.swap_id,
+            btc: self.btc,
+            xmr: self.xmr,
+            tx_lock_id: self.tx_lock_id,
+            tx_redeem_id: self.tx_redeem_id,
+            xmr_lock_tx_hash: self.xmr_lock_tx_hash.clone(),
+            seller: self.seller,
+            buyer: self.buyer,
+            started_at,
+            completed_at,
+        }
+    }
+
+    pub fn sign(&self, receipt: &Receipt) -> Signature {
+        receipt.sign(&self.key)
+    }
+}
+
+/// A receipt with the signatures of both parties.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SignedReceipt {
+    pub receipt: Receipt,
+    pub seller_signature: Signature,
+    pub buyer_signature: Signature,
+}
+
+impl SignedReceipt {
+    pub fn verify(&self) -> Result<(), InvalidSignature> {
+        self.receipt
+            .verify(&self.receipt.seller, &self.seller_signature)?;
+        self.receipt
+            .verify(&self.receipt.buyer, &self.buyer_signature)?;
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("receipts can always be serialized")
+    }
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[error("Receipt is not signed by both parties of the swap")]
+pub struct InvalidSignature;
+
+pub fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn receipt_signed_by_both_parties_verifies() {
+        let seller = SecretKey::new_random(&mut rand::thread_rng());
+        let buyer = SecretKey::new_random(&mut rand::thread_rng());
+        let receipt = receipt(&seller, &buyer);
+
+        let signed = SignedReceipt {
+            seller_signature: receipt.sign(&seller),
+            buyer_signature: receipt.sign(&buyer),
+            receipt,
+        };
+
+        assert!(signed.verify().is_ok());
+    }
+
+    #[test]
+    fn altered_receipt_does_not_verify() {
+        let seller = SecretKey::new_random(&mut rand::thread_rng());
+        let buyer = SecretKey::new_random(&mut rand::thread_rng());
+        let receipt = receipt(&seller, &buyer);
+
+        let mut signed = SignedReceipt {
+            seller_signature: receipt.sign(&seller),
+            buyer_signature: receipt.sign(&buyer),
+            receipt,
+        };
+        signed.receipt.xmr = monero::Amount::from_piconero(1);
+
+        assert!(signed.verify().is_err());
+    }
+
+    #[test]
+    fn signature_of_the_wrong_party_does_not_verify() {
+        let seller = SecretKey::new_random(&mut rand::thread_rng());
+        let buyer = SecretKey::new_random(&mut rand::thread_rng());
+        let receipt = receipt(&seller, &buyer);
+
+        let signed = SignedReceipt {
+            seller_signature: receipt.sign(&buyer),
+            buyer_signature: receipt.sign(&buyer),
+            receipt,
+        };
+
+        assert!(signed.verify().is_err());
+    }
+
+    #[test]
+    fn timestamps_must_agree_with_the_seller() {
+        let key = SecretKey::new_random(&mut rand::thread_rng());
+        let receipt = receipt(&key, &key);
+        let started_at = UNIX_EPOCH + Duration::from_secs(receipt.started_at);
+        let now = UNIX_EPOCH + Duration::from_secs(receipt.completed_at);
+
+        assert!(receipt.has_plausible_timestamps(started_at + Duration::from_secs(60), now));
+        assert!(!receipt.has_plausible_timestamps(started_at + 2 * MAX_TIMESTAMP_DEVIATION, now));
+        assert!(!receipt.has_plausible_timestamps(started_at, now - 2 * MAX_TIMESTAMP_DEVIATION));
+    }
+
+    fn receipt(seller: &SecretKey, buyer: &SecretKey) -> Receipt {
+        Receipt {
+            swap_id: Uuid::new_v4(),
+            btc: bitcoin::Amount::from_sat(1_000_000),
+            xmr: monero::Amount::from_piconero(1_000_000_000_000),
+            tx_lock_id: Txid::from_str(
+                "b9d4e4e1b1a1b3c3e4c1c0e1e3e6f2a7d4e5c1f7c7e0a2b8c1d4e4f1a2b3c4d5",
+            )
+            .unwrap(),
+            tx_redeem_id: Txid::from_str(
+                "c1d4e4e1b1a1b3c3e4c1c0e1e3e6f2a7d4e5c1f7c7e0a2b8c1d4e4f1a2b3c4d5",
+            )
+            .unwrap(),
+            xmr_lock_tx_hash: TxHash(
+                "a2b3c4d5e4e1b1a1b3c3e4c1c0e1e3e6f2a7d4e5c1f7c7e0a2b8c1d4e4f1b9d4".to_owned(),
+            ),
+            seller: seller.public(),
+            buyer: buyer.public(),
+            started_at: 1_600_000_000,
+            completed_at: 1_600_003_600,
+        }
+    }
+}
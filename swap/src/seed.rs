@@ -1,3 +1,4 @@
+use crate::encryption::{KeyParams, Passphrase};
 use crate::fs::ensure_directory_exists;
 use ::bitcoin::secp256k1::constants::SECRET_KEY_SIZE;
 use ::bitcoin::secp256k1::{self, SecretKey};
@@ -7,6 +8,7 @@ use bitcoin::hashes::{sha256, Hash, HashEngine};
 use libp2p::identity;
 use pem::{encode, Pem};
 use rand::prelude::*;
+use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs::{self, File};
@@ -16,6 +18,15 @@ use torut::onion::TorSecretKeyV3;
 
 pub const SEED_LENGTH: usize = 32;
 
+const PEM_TAG: &str = "SEED";
+const ENCRYPTED_PEM_TAG: &str = "ENCRYPTED SEED";
+
+/// Holds the secret of a rotated libp2p identity, stored like the seed.
+const IDENTITY_FILE: &str = "identity.pem";
+
+/// Separates the seed from the rotated identity in a backup.
+const BACKUP_SEPARATOR: char = '.';
+
 #[derive(Eq, PartialEq)]
 pub struct Seed([u8; SEED_LENGTH]);
 
@@ -48,6 +59,60 @@ impl Seed {
         identity::Keypair::Ed25519(key.into())
     }
 
+    /// The libp2p identity of the node.
+    ///
+    /// The identity is derived from the seed unless it was replaced with
+    /// [`Seed::rotate_libp2p_identity`].
+    pub fn libp2p_identity(
+        &self,
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<identity::Keypair, Error> {
+        match Self::rotated_identity(data_dir, passphrase)? {
+            Some(secret) => Ok(secret.derive_libp2p_identity()),
+            None => Ok(self.derive_libp2p_identity()),
+        }
+    }
+
+    /// The secret of the identity stored by [`Seed::rotate_libp2p_identity`],
+    /// if the identity was rotated.
+    fn rotated_identity(
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<Option<Self>, Error> {
+        let file_path = data_dir.join(IDENTITY_FILE);
+
+        if !file_path.exists() {
+            return Ok(None);
+        }
+
+        let pem = read_pem(&file_path)?;
+        let secret = match (pem.tag.as_str(), passphrase) {
+            (ENCRYPTED_PEM_TAG, Some(passphrase)) => Self::from_encrypted_pem(pem, passphrase)?,
+            (ENCRYPTED_PEM_TAG, None) => return Err(Error::Encrypted),
+            _ => Self::from_pem(pem)?,
+        };
+
+        Ok(Some(secret))
+    }
+
+    /// Replaces the libp2p identity of the node with a random one, which is
+    /// stored in the data directory, encrypted if a passphrase is given.
+    pub fn rotate_libp2p_identity(
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<identity::Keypair, Error> {
+        let file_path = data_dir.join(IDENTITY_FILE);
+        let secret = Self::random()?;
+
+        match passphrase {
+            Some(passphrase) => secret.write_encrypted_to(file_path, passphrase)?,
+            None => secret.write_to(file_path)?,
+        }
+
+        Ok(secret.derive_libp2p_identity())
+    }
+
     pub fn derive_torv3_key(&self) -> TorSecretKeyV3 {
         let bytes = self.derive(b"TOR").bytes();
         let sk = ed25519_dalek::SecretKey::from_bytes(&bytes)
@@ -56,6 +121,10 @@ impl Seed {
         esk.to_bytes().into()
     }
 
+    pub fn derive_swap_export_key(&self) -> [u8; 32] {
+        self.derive(b"SWAP_EXPORT").bytes()
+    }
+
     pub fn from_file_or_generate(data_dir: &Path) -> Result<Self, Error> {
         let file_path_buf = data_dir.join("seed.pem");
         let file_path = Path::new(&file_path_buf);
@@ -72,6 +141,161 @@ impl Seed {
         Ok(random_seed)
     }
 
+    /// Like [`Seed::from_file_or_generate`], but the seed is stored encrypted
+    /// with the passphrase.
+    ///
+    /// A seed file that is not encrypted yet is replaced by an encrypted one.
+    pub fn from_encrypted_file_or_generate(
+        data_dir: &Path,
+        passphrase: &Passphrase,
+    ) -> Result<Self, Error> {
+        let file_path = data_dir.join("seed.pem");
+
+        if !file_path.exists() {
+            tracing::debug!(
+                "No seed file found, creating encrypted seed at {}",
+                file_path.display()
+            );
+
+            let random_seed = Seed::random()?;
+            random_seed.write_encrypted_to(file_path, passphrase)?;
+
+            return Ok(random_seed);
+        }
+
+        let pem = read_pem(&file_path)?;
+        if pem.tag == ENCRYPTED_PEM_TAG {
+            return Self::from_encrypted_pem(pem, passphrase);
+        }
+
+        let seed = Self::from_pem(pem)?;
+        seed.write_encrypted_to(file_path.clone(), passphrase)?;
+        tracing::info!("Encrypted seed at {}", file_path.display());
+
+        Ok(seed)
+    }
+
+    /// Reads the seed of the data directory without creating one.
+    ///
+    /// Unlike [`Seed::from_encrypted_file_or_generate`], a seed file that is
+    /// not encrypted is left untouched.
+    pub fn from_existing_file(
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<Self, Error> {
+        let file_path = data_dir.join("seed.pem");
+
+        if !file_path.exists() {
+            return Err(Error::NotFound(file_path));
+        }
+
+        let pem = read_pem(&file_path)?;
+        match (pem.tag == ENCRYPTED_PEM_TAG, passphrase) {
+            (true, Some(passphrase)) => Self::from_encrypted_pem(pem, passphrase),
+            (true, None) => Err(Error::Encrypted),
+            (false, _) => Self::from_pem(pem),
+        }
+    }
+
+    /// Restores a backed up seed into the data directory, encrypted with the
+    /// passphrase if one is given.
+    ///
+    /// Fails if the data directory already contains a different seed, as that
+    /// would make the funds of its Bitcoin wallet and the swaps in its
+    /// database inaccessible.
+    pub fn restore(&self, data_dir: &Path, passphrase: Option<&Passphrase>) -> Result<(), Error> {
+        let file_path = data_dir.join("seed.pem");
+
+        if file_path.exists() {
+            let existing = Self::from_existing_file(data_dir, passphrase)?;
+
+            if existing == *self {
+                return Ok(());
+            }
+
+            return Err(Error::SeedExists(file_path));
+        }
+
+        match passphrase {
+            Some(passphrase) => self.write_encrypted_to(file_path, passphrase),
+            None => self.write_to(file_path),
+        }
+    }
+
+    /// Encodes the seed as base64 so it can be written down or stored as a
+    /// backup.
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.bytes())
+    }
+
+    /// Encodes the seed together with the rotated libp2p identity of the data
+    /// directory, if there is one, so that restoring the backup keeps the
+    /// peer id.
+    ///
+    /// The identity is appended to the seed, separated by a `.`.
+    pub fn to_backup(
+        &self,
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<String, Error> {
+        match Self::rotated_identity(data_dir, passphrase)? {
+            Some(identity) => Ok(format!(
+                "{}{}{}",
+                self.to_base64(),
+                BACKUP_SEPARATOR,
+                identity.to_base64()
+            )),
+            None => Ok(self.to_base64()),
+        }
+    }
+
+    /// Restores a backup created by [`Seed::to_backup`] into the data
+    /// directory, including the rotated libp2p identity if the backup contains
+    /// one.
+    ///
+    /// Fails if the data directory already contains a different seed or a
+    /// different rotated identity.
+    pub fn restore_backup(
+        encoded: &str,
+        data_dir: &Path,
+        passphrase: Option<&Passphrase>,
+    ) -> Result<Self, Error> {
+        let mut parts = encoded.trim().splitn(2, BACKUP_SEPARATOR);
+        let seed = Self::from_base64(parts.next().unwrap_or_default())?;
+        let identity = parts.next().map(Self::from_base64).transpose()?;
+
+        seed.restore(data_dir, passphrase)?;
+
+        if let Some(identity) = identity {
+            match Self::rotated_identity(data_dir, passphrase)? {
+                Some(existing) if existing == identity => {}
+                Some(_) => return Err(Error::IdentityExists(data_dir.join(IDENTITY_FILE))),
+                None => {
+                    let file_path = data_dir.join(IDENTITY_FILE);
+                    match passphrase {
+                        Some(passphrase) => identity.write_encrypted_to(file_path, passphrase)?,
+                        None => identity.write_to(file_path)?,
+                    }
+                }
+            }
+        }
+
+        Ok(seed)
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, Error> {
+        let bytes = base64::decode(encoded.trim())?;
+
+        if bytes.len() != SEED_LENGTH {
+            return Err(Error::IncorrectLength(bytes.len()));
+        }
+
+        let mut array = [0; SEED_LENGTH];
+        array.copy_from_slice(&bytes);
+
+        Ok(Self::from(array))
+    }
+
     /// Derive a new seed using the given scope.
     ///
     /// This function is purposely kept private because it is only a helper
@@ -97,14 +321,39 @@ impl Seed {
         D: AsRef<OsStr>,
     {
         let file = Path::new(&seed_file);
-        let contents = fs::read_to_string(file)?;
-        let pem = pem::parse(contents)?;
+        let pem = read_pem(file)?;
 
-        tracing::debug!("Reading in seed from {}", file.display());
+        if pem.tag == ENCRYPTED_PEM_TAG {
+            return Err(Error::Encrypted);
+        }
 
         Self::from_pem(pem)
     }
 
+    /// The contents of an encrypted seed are laid out as `key parameters |
+    /// nonce | ciphertext`.
+    fn from_encrypted_pem(pem: pem::Pem, passphrase: &Passphrase) -> Result<Self, Error> {
+        if pem.contents.len() < KeyParams::LENGTH {
+            return Err(Error::IncorrectLength(pem.contents.len()));
+        }
+        let (key_params, ciphertext) = pem.contents.split_at(KeyParams::LENGTH);
+        let key_params = KeyParams::from_bytes(
+            key_params
+                .try_into()
+                .expect("split at the length of the key parameters"),
+        );
+
+        let plaintext = key_params
+            .derive(passphrase)
+            .decrypt(ciphertext)
+            .map_err(Error::Decryption)?;
+
+        Self::from_pem(Pem {
+            tag: String::from(PEM_TAG),
+            contents: plaintext,
+        })
+    }
+
     fn from_pem(pem: pem::Pem) -> Result<Self, Error> {
         if pem.contents.len() != SEED_LENGTH {
             Err(Error::IncorrectLength(pem.contents.len()))
@@ -119,23 +368,57 @@ impl Seed {
     }
 
     fn write_to(&self, seed_file: PathBuf) -> Result<(), Error> {
-        ensure_directory_exists(&seed_file)?;
-
         let data = self.bytes();
         let pem = Pem {
-            tag: String::from("SEED"),
+            tag: String::from(PEM_TAG),
             contents: data.to_vec(),
         };
 
-        let pem_string = encode(&pem);
+        write_pem(seed_file, &pem)
+    }
 
-        let mut file = File::create(seed_file)?;
-        file.write_all(pem_string.as_bytes())?;
+    fn write_encrypted_to(&self, seed_file: PathBuf, passphrase: &Passphrase) -> Result<(), Error> {
+        let key_params = KeyParams::random();
+        let ciphertext = key_params
+            .derive(passphrase)
+            .encrypt(&self.bytes())
+            .map_err(Error::Encryption)?;
 
-        Ok(())
+        let mut contents = key_params.to_bytes().to_vec();
+        contents.extend_from_slice(&ciphertext);
+        let pem = Pem {
+            tag: String::from(ENCRYPTED_PEM_TAG),
+            contents,
+        };
+
+        write_pem(seed_file, &pem)
     }
 }
 
+fn read_pem(seed_file: &Path) -> Result<Pem, Error> {
+    let contents = fs::read_to_string(seed_file)?;
+
+    tracing::debug!("Reading in seed from {}", seed_file.display());
+
+    Ok(pem::parse(contents)?)
+}
+
+/// Writes to a temporary file first, so that replacing an existing seed
+/// cannot leave a truncated file behind.
+fn write_pem(seed_file: PathBuf, pem: &Pem) -> Result<(), Error> {
+    ensure_directory_exists(&seed_file)?;
+
+    let pem_string = encode(pem);
+
+    let temporary_file = seed_file.with_extension("pem.tmp");
+    let mut file = File::create(&temporary_file)?;
+    file.write_all(pem_string.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temporary_file, seed_file)?;
+
+    Ok(())
+}
+
 impl fmt::Debug for Seed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Seed([*****])")
@@ -168,6 +451,20 @@ pub enum Error {
     Rand(#[from] rand::Error),
     #[error("no default path")]
     NoDefaultPath,
+    #[error("base64 decode: ")]
+    Base64(#[from] base64::DecodeError),
+    #[error("a different seed already exists at {0}")]
+    SeedExists(PathBuf),
+    #[error("a different network identity already exists at {0}")]
+    IdentityExists(PathBuf),
+    #[error("no seed found at {0}")]
+    NotFound(PathBuf),
+    #[error("the seed is encrypted, a passphrase is required to read it")]
+    Encrypted,
+    #[error("failed to encrypt the seed")]
+    Encryption(#[source] anyhow::Error),
+    #[error("failed to decrypt the seed, is the passphrase correct?")]
+    Decryption(#[source] anyhow::Error),
 }
 
 #[cfg(test)]
@@ -251,4 +548,139 @@ mbKANv2qKGmNVg1qtquj6Hx1pFPelpqOfE2JaJJAMEg1FlFhNRNlFlE=
         let rinsed = Seed::from_file(tmpfile).expect("Read from temp file");
         assert_eq!(seed.0, rinsed.0);
     }
+
+    #[test]
+    fn round_trip_through_base64() {
+        let seed = Seed::random().unwrap();
+
+        let restored = Seed::from_base64(&seed.to_base64()).unwrap();
+
+        assert_eq!(seed, restored);
+    }
+
+    #[test]
+    fn seed_from_base64_fails_for_short_seed() {
+        let result = Seed::from_base64("VnZUNFZ4dlY=");
+
+        assert!(matches!(result, Err(Error::IncorrectLength(8))));
+    }
+
+    #[test]
+    fn restore_does_not_overwrite_different_seed() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let existing = Seed::from_file_or_generate(data_dir.path()).unwrap();
+
+        existing
+            .restore(data_dir.path(), None)
+            .expect("restoring the same seed is a no-op");
+
+        let other = Seed::random().unwrap();
+        let result = other.restore(data_dir.path(), None);
+
+        assert!(matches!(result, Err(Error::SeedExists(_))));
+        assert_eq!(
+            Seed::from_file_or_generate(data_dir.path()).unwrap(),
+            existing
+        );
+    }
+
+    #[test]
+    fn reading_existing_seed_does_not_create_one() {
+        let data_dir = tempfile::tempdir().unwrap();
+
+        let result = Seed::from_existing_file(data_dir.path(), None);
+
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        assert!(!data_dir.path().join("seed.pem").exists());
+
+        let seed = Seed::from_file_or_generate(data_dir.path()).unwrap();
+        assert_eq!(
+            Seed::from_existing_file(data_dir.path(), None).unwrap(),
+            seed
+        );
+    }
+
+    #[test]
+    fn encrypts_plaintext_seed_in_place() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+        let seed = Seed::from_file_or_generate(data_dir.path()).unwrap();
+
+        let encrypted =
+            Seed::from_encrypted_file_or_generate(data_dir.path(), &passphrase).unwrap();
+
+        assert_eq!(encrypted, seed);
+        assert!(matches!(
+            Seed::from_file_or_generate(data_dir.path()),
+            Err(Error::Encrypted)
+        ));
+        assert!(matches!(
+            Seed::from_encrypted_file_or_generate(
+                data_dir.path(),
+                &Passphrase::new("wrong".to_owned())
+            ),
+            Err(Error::Decryption(_))
+        ));
+        assert_eq!(
+            Seed::from_encrypted_file_or_generate(data_dir.path(), &passphrase).unwrap(),
+            seed
+        );
+    }
+
+    #[test]
+    fn rotated_identity_replaces_derived_identity() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+        let seed = Seed::random().unwrap();
+        let peer_id = |identity: identity::Keypair| identity.public().into_peer_id();
+
+        let derived = seed.libp2p_identity(data_dir.path(), None).unwrap();
+        assert_eq!(peer_id(derived), peer_id(seed.derive_libp2p_identity()));
+
+        let rotated = Seed::rotate_libp2p_identity(data_dir.path(), Some(&passphrase)).unwrap();
+        let loaded = seed
+            .libp2p_identity(data_dir.path(), Some(&passphrase))
+            .unwrap();
+        assert_eq!(peer_id(loaded), peer_id(rotated.clone()));
+        assert_ne!(peer_id(rotated), peer_id(seed.derive_libp2p_identity()));
+
+        assert!(matches!(
+            seed.libp2p_identity(data_dir.path(), None),
+            Err(Error::Encrypted)
+        ));
+    }
+
+    #[test]
+    fn backup_keeps_rotated_identity() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let restored_dir = tempfile::tempdir().unwrap();
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+        let seed = Seed::random().unwrap();
+        let peer_id = |identity: identity::Keypair| identity.public().into_peer_id();
+
+        assert_eq!(
+            seed.to_backup(data_dir.path(), None).unwrap(),
+            seed.to_base64()
+        );
+
+        let rotated = Seed::rotate_libp2p_identity(data_dir.path(), Some(&passphrase)).unwrap();
+        let backup = seed.to_backup(data_dir.path(), Some(&passphrase)).unwrap();
+
+        let restored = Seed::restore_backup(&backup, restored_dir.path(), None).unwrap();
+        assert!(restored == seed);
+        assert_eq!(
+            peer_id(restored.libp2p_identity(restored_dir.path(), None).unwrap()),
+            peer_id(rotated)
+        );
+
+        let other = Seed::random().unwrap().to_base64();
+        assert!(matches!(
+            Seed::restore_backup(
+                &format!("{}.{}", seed.to_base64(), other),
+                restored_dir.path(),
+                None
+            ),
+            Err(Error::IdentityExists(_))
+        ));
+    }
 }
@@ -0,0 +1,126 @@
+//! Stopping the binaries without interrupting swaps in the middle of a
+//! transition.
+
+use anyhow::Result;
+use std::future;
+use tokio::sync::watch;
+
+/// Tells swaps to suspend at the next point where this is safe.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+#[derive(Debug)]
+pub struct Trigger {
+    sender: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> (Trigger, Self) {
+        let (sender, receiver) = watch::channel(false);
+
+        (Trigger { sender }, Self { receiver })
+    }
+
+    /// A shutdown that is never requested.
+    pub fn never() -> Self {
+        let (_, shutdown) = Self::new();
+
+        shutdown
+    }
+
+    /// Requests a shutdown once the process receives Ctrl-C or, on unix,
+    /// SIGTERM.
+    ///
+    /// A second signal exits the process immediately.
+    pub fn on_signal() -> Self {
+        let (trigger, shutdown) = Self::new();
+
+        tokio::spawn(async move {
+            if let Err(error) = signal().await {
+                tracing::warn!("Failed to listen for shutdown signals: {:#}", error);
+                return;
+            }
+
+            tracing::info!("Shutting down, press Ctrl-C again to exit immediately");
+            trigger.request();
+
+            if signal().await.is_ok() {
+                std::process::exit(1);
+            }
+        });
+
+        shutdown
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once a shutdown was requested, never if the trigger was
+    /// dropped without requesting one.
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            if self.receiver.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Trigger {
+    pub fn request(&self) {
+        let _ = self.sender.send(true);
+    }
+}
+
+#[cfg(unix)]
+async fn signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn requested_resolves_after_request() {
+        let (trigger, mut shutdown) = Shutdown::new();
+        assert!(!shutdown.is_requested());
+
+        trigger.request();
+
+        timeout(Duration::from_secs(1), shutdown.requested())
+            .await
+            .unwrap();
+        assert!(shutdown.is_requested());
+    }
+
+    #[tokio::test]
+    async fn never_is_never_requested() {
+        let mut shutdown = Shutdown::never();
+
+        let result = timeout(Duration::from_millis(100), shutdown.requested()).await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,51 @@
+pub mod harness;
+
+use harness::alice_run_until::{is_btc_lock_transaction_seen, is_btc_locked};
+use harness::DeepLockConfig;
+use swap::asb::FixedRate;
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+
+#[tokio::test]
+async fn given_lock_transaction_is_reorged_out_alice_waits_for_confirmations_again() {
+    harness::setup_test(DeepLockConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run_until(alice_swap, is_btc_locked, FixedRate::default()).await?;
+        let tx_lock = match alice_state {
+            AliceState::BtcLocked { state3 } => state3.tx_lock,
+            state => panic!("Alice is in state {} instead of btc is locked", state),
+        };
+
+        ctx.reorg_out_btc_lock(&tx_lock).await;
+
+        ctx.restart_alice().await;
+        let alice_swap = ctx.alice_next_swap().await;
+        assert!(matches!(alice_swap.state, AliceState::BtcLocked { .. }));
+
+        let alice_state = alice::run_until(
+            alice_swap,
+            is_btc_lock_transaction_seen,
+            FixedRate::default(),
+        )
+        .await?;
+        assert!(matches!(
+            alice_state,
+            AliceState::BtcLockTransactionSeen { .. }
+        ));
+
+        ctx.resume_bitcoin_mining();
+        ctx.restart_alice().await;
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run(alice_swap, FixedRate::default()).await?;
+        ctx.assert_alice_redeemed(alice_state).await;
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        Ok(())
+    })
+    .await;
+}
@@ -0,0 +1,41 @@
+pub mod harness;
+
+use harness::alice_run_until::is_xmr_lock_transaction_sent;
+use harness::FastCancelConfig;
+use swap::asb::FixedRate;
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+
+/// Alice locks Xmr but stops before she sends Bob the transfer proof, as if
+/// it was dropped. Bob cancels and refunds, Alice refunds the Xmr once she
+/// comes back.
+#[tokio::test]
+async fn given_alice_stops_before_transfer_proof_both_refund() {
+    harness::setup_test(FastCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run_until(
+            alice_swap,
+            is_xmr_lock_transaction_sent,
+            FixedRate::default(),
+        )
+        .await?;
+        assert!(matches!(
+            alice_state,
+            AliceState::XmrLockTransactionSent { .. }
+        ));
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_refunded(bob_state).await;
+
+        ctx.restart_alice().await;
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run(alice_swap, FixedRate::default()).await?;
+        ctx.assert_alice_refunded(alice_state).await;
+
+        Ok(())
+    })
+    .await;
+}
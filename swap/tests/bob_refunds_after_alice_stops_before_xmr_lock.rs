@@ -0,0 +1,32 @@
+pub mod harness;
+
+use harness::alice_run_until::is_btc_locked;
+use harness::FastCancelConfig;
+use swap::asb::FixedRate;
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+
+/// Bob locks Btc but Alice stops before she locks Xmr. Bob cancels and
+/// refunds, Alice aborts the swap once she comes back and keeps her Xmr.
+#[tokio::test]
+async fn given_alice_stops_before_xmr_lock_bob_refunds() {
+    harness::setup_test(FastCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run_until(alice_swap, is_btc_locked, FixedRate::default()).await?;
+        assert!(matches!(alice_state, AliceState::BtcLocked { .. }));
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_refunded(bob_state).await;
+
+        ctx.restart_alice().await;
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run(alice_swap, FixedRate::default()).await?;
+        ctx.assert_alice_safely_aborted(alice_state).await;
+
+        Ok(())
+    })
+    .await;
+}
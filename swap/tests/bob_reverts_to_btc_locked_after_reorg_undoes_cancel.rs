@@ -0,0 +1,58 @@
+pub mod harness;
+
+use harness::{alice_run_until, bob_run_until, FastCancelConfig};
+use swap::asb::FixedRate;
+use swap::protocol::alice::AliceState;
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+
+#[tokio::test]
+async fn given_reorg_undoes_cancel_timelock_bob_waits_for_xmr_lock_again() {
+    harness::setup_test(FastCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap_id = bob_swap.id;
+        let bob_swap = tokio::spawn(bob::run_until(bob_swap, bob_run_until::is_btc_cancelled));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run_until(
+            alice_swap,
+            alice_run_until::is_btc_locked,
+            FixedRate::default(),
+        )
+        .await?;
+        let tx_lock = match alice_state {
+            AliceState::BtcLocked { state3 } => state3.tx_lock,
+            state => panic!("Alice is in state {} instead of btc is locked", state),
+        };
+
+        let bob_state = bob_swap.await??;
+        let tx_cancel = match bob_state {
+            BobState::BtcCancelled(state6) => state6.tx_cancel(),
+            state => panic!("Bob is in state {} instead of btc is cancelled", state),
+        };
+
+        ctx.reorg_out_btc_lock(&tx_lock).await;
+        ctx.wait_until_bob_dropped_tx_cancel(&tx_cancel).await;
+
+        let (bob_swap, bob_join_handle) = ctx
+            .stop_and_resume_bob_from_db(bob_join_handle, bob_swap_id)
+            .await;
+        assert!(matches!(bob_swap.state, BobState::BtcCancelled(..)));
+
+        let bob_state = bob::run_until(bob_swap, bob_run_until::is_btc_locked).await?;
+        assert!(matches!(bob_state, BobState::BtcLocked(..)));
+
+        ctx.resume_bitcoin_mining();
+
+        let (bob_swap, _) = ctx
+            .stop_and_resume_bob_from_db(bob_join_handle, bob_swap_id)
+            .await;
+        assert!(matches!(bob_swap.state, BobState::BtcLocked(..)));
+
+        let bob_state = bob::run(bob_swap).await?;
+        ctx.assert_bob_refunded(bob_state).await;
+
+        Ok(())
+    })
+    .await;
+}
@@ -9,6 +9,7 @@ use tokio::join;
 async fn happy_path() {
     harness::setup_test(SlowCancelConfig, |mut ctx| async move {
         let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap_id = bob_swap.id;
         let bob_swap = tokio::spawn(bob::run(bob_swap));
 
         let alice_swap = ctx.alice_next_swap().await;
@@ -18,6 +19,7 @@ async fn happy_path() {
 
         ctx.assert_alice_redeemed(alice_state??).await;
         ctx.assert_bob_redeemed(bob_state??).await;
+        ctx.assert_bob_has_receipt(bob_swap_id);
 
         Ok(())
     })
@@ -0,0 +1,64 @@
+pub mod harness;
+
+use harness::alice_run_until::{is_transfer_proof_sent, is_xmr_locked};
+use harness::{bob_run_until, SlowCancelConfig};
+use swap::asb::FixedRate;
+use swap::database::Alice;
+use swap::protocol::alice::AliceState;
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+
+/// Alice sends the transfer proof and Bob the encsig a second time, as if the
+/// acknowledgement of the first one was lost. Both ignore the duplicate and
+/// the swap completes.
+#[tokio::test]
+async fn given_transfer_proof_and_encsig_are_sent_twice_swap_completes() {
+    harness::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap_id = bob_swap.id;
+        let bob_swap = tokio::spawn(bob::run_until(bob_swap, bob_run_until::is_xmr_locked));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state = alice::run_until(alice_swap, is_xmr_locked, FixedRate::default()).await?;
+        assert!(matches!(alice_state, AliceState::XmrLocked { .. }));
+        let alice_xmr_locked = Alice::from(&alice_state);
+
+        ctx.restart_alice().await;
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_state =
+            alice::run_until(alice_swap, is_transfer_proof_sent, FixedRate::default()).await?;
+        assert!(matches!(
+            alice_state,
+            AliceState::XmrLockTransferProofSent { .. }
+        ));
+
+        let bob_xmr_locked = bob_swap.await??;
+        assert!(matches!(bob_xmr_locked, BobState::XmrLocked(..)));
+
+        // Alice sends the transfer proof again
+        ctx.restart_alice().await;
+        let mut alice_swap = ctx.alice_next_swap().await;
+        alice_swap.state = alice_xmr_locked.into();
+        let alice_swap = tokio::spawn(alice::run(alice_swap, FixedRate::default()));
+
+        let (bob_swap, bob_join_handle) = ctx
+            .stop_and_resume_bob_from_db(bob_join_handle, bob_swap_id)
+            .await;
+        let bob_state = bob::run_until(bob_swap, bob_run_until::is_encsig_sent).await?;
+        assert!(matches!(bob_state, BobState::EncSigSent(..)));
+
+        // Bob sends the encsig again
+        let (mut bob_swap, _) = ctx
+            .stop_and_resume_bob_from_db(bob_join_handle, bob_swap_id)
+            .await;
+        bob_swap.state = bob_xmr_locked;
+        let bob_state = bob::run(bob_swap).await?;
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        let alice_state = alice_swap.await??;
+        ctx.assert_alice_redeemed(alice_state).await;
+
+        Ok(())
+    })
+    .await;
+}
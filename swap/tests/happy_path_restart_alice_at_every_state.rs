@@ -0,0 +1,54 @@
+pub mod harness;
+
+use harness::alice_run_until::{
+    is_btc_lock_transaction_seen, is_btc_locked, is_encsig_learned, is_xmr_lock_transaction_sent,
+};
+use harness::SlowCancelConfig;
+use swap::asb::FixedRate;
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+
+/// Alice crashes and resumes from the database in every state she stops in on
+/// the happy path, neither party may lose funds.
+#[tokio::test]
+async fn given_alice_restarts_in_every_state_resume_swap() {
+    harness::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let targets: [fn(&AliceState) -> bool; 4] = [
+            is_btc_lock_transaction_seen,
+            is_btc_locked,
+            is_xmr_lock_transaction_sent,
+            is_encsig_learned,
+        ];
+        let mut alice_swap = ctx.alice_next_swap().await;
+
+        for is_target_state in targets.iter().copied() {
+            let alice_state =
+                alice::run_until(alice_swap, is_target_state, FixedRate::default()).await?;
+            assert!(
+                is_target_state(&alice_state),
+                "Alice stopped in {}",
+                alice_state
+            );
+
+            ctx.restart_alice().await;
+            alice_swap = ctx.alice_next_swap().await;
+            assert!(
+                is_target_state(&alice_swap.state),
+                "Alice resumed in {}",
+                alice_swap.state
+            );
+        }
+
+        let alice_state = alice::run(alice_swap, FixedRate::default()).await?;
+        ctx.assert_alice_redeemed(alice_state).await;
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        Ok(())
+    })
+    .await;
+}
@@ -0,0 +1,60 @@
+pub mod harness;
+
+use harness::bob_run_until::{
+    is_btc_locked, is_encsig_sent, is_lock_proof_received, is_xmr_locked,
+};
+use harness::SlowCancelConfig;
+use swap::asb::FixedRate;
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+
+/// Bob crashes and resumes from the database in every state he stops in on
+/// the happy path, neither party may lose funds.
+#[tokio::test]
+async fn given_bob_restarts_in_every_state_resume_swap() {
+    harness::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (mut bob_swap, mut bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap_id = bob_swap.id;
+
+        let targets: [fn(&BobState) -> bool; 4] = [
+            is_btc_locked,
+            is_lock_proof_received,
+            is_xmr_locked,
+            is_encsig_sent,
+        ];
+        let mut alice_swap = None;
+
+        for is_target_state in targets.iter().copied() {
+            let bob_run = tokio::spawn(bob::run_until(bob_swap, is_target_state));
+
+            if alice_swap.is_none() {
+                let swap = ctx.alice_next_swap().await;
+                alice_swap = Some(tokio::spawn(alice::run(swap, FixedRate::default())));
+            }
+
+            let bob_state = bob_run.await??;
+            assert!(is_target_state(&bob_state), "Bob stopped in {}", bob_state);
+
+            let (swap, join_handle) = ctx
+                .stop_and_resume_bob_from_db(bob_join_handle, bob_swap_id)
+                .await;
+            assert!(
+                is_target_state(&swap.state),
+                "Bob resumed in {}",
+                swap.state
+            );
+
+            bob_swap = swap;
+            bob_join_handle = join_handle;
+        }
+
+        let bob_state = bob::run(bob_swap).await?;
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        let alice_state = alice_swap.expect("Alice swap started").await??;
+        ctx.assert_alice_redeemed(alice_state).await;
+
+        Ok(())
+    })
+    .await;
+}
@@ -12,14 +12,18 @@ use monero_harness::{image, Monero};
 use std::cmp::Ordering;
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::time::Duration;
+use swap::asb::config::ConnectionLimits;
 use swap::asb::FixedRate;
 use swap::bitcoin::{CancelTimelock, PunishTimelock, TxCancel, TxPunish, TxRedeem, TxRefund};
 use swap::database::Database;
 use swap::env::{Config, GetConfig};
+use swap::network::rendezvous::XmrBtcNamespace;
+use swap::network::swap_setup::SessionNonce;
 use swap::network::swarm;
-use swap::protocol::alice::{AliceState, Swap};
+use swap::protocol::alice::{AliceState, KeyPool, Swap};
 use swap::protocol::bob::BobState;
 use swap::protocol::{alice, bob};
 use swap::seed::Seed;
@@ -95,6 +99,8 @@ where
 
     let bob_seed = Seed::random().unwrap();
     let bob_starting_balances = StartingBalances::new(btc_amount * 10, monero::Amount::ZERO, None);
+    let bitcoind_url = containers.bitcoind_url.clone();
+    let bitcoin_mining_paused = containers.bitcoin_mining_paused.clone();
 
     let (bob_bitcoin_wallet, bob_monero_wallet) = init_test_wallets(
         MONERO_WALLET_NAME_BOB,
@@ -136,6 +142,8 @@ where
         bob_starting_balances,
         bob_bitcoin_wallet,
         bob_monero_wallet,
+        bitcoind_url,
+        bitcoin_mining_paused,
     };
 
     testfn(test).await.unwrap()
@@ -144,7 +152,7 @@ where
 async fn init_containers(cli: &Cli) -> (Monero, Containers<'_>) {
     let prefix = random_prefix();
     let bitcoind_name = format!("{}_{}", prefix, "bitcoind");
-    let (bitcoind, bitcoind_url) =
+    let (bitcoind, bitcoind_url, bitcoin_mining_paused) =
         init_bitcoind_container(&cli, prefix.clone(), bitcoind_name.clone(), prefix.clone())
             .await
             .expect("could not init bitcoind");
@@ -158,6 +166,7 @@ async fn init_containers(cli: &Cli) -> (Monero, Containers<'_>) {
 
     (monero, Containers {
         bitcoind_url,
+        bitcoin_mining_paused,
         bitcoind,
         monerod_container,
         monero_wallet_rpc_containers,
@@ -170,7 +179,7 @@ async fn init_bitcoind_container(
     volume: String,
     name: String,
     network: String,
-) -> Result<(Container<'_, Cli, bitcoind::Bitcoind>, Url)> {
+) -> Result<(Container<'_, Cli, bitcoind::Bitcoind>, Url, Arc<AtomicBool>)> {
     let image = bitcoind::Bitcoind::default().with_volume(volume);
 
     let run_args = RunArgs::default().with_name(name).with_network(network);
@@ -190,9 +199,10 @@ async fn init_bitcoind_container(
         Url::parse(&input).unwrap()
     };
 
-    init_bitcoind(bitcoind_url.clone(), 5).await?;
+    let mining_paused = Arc::new(AtomicBool::new(false));
+    init_bitcoind(bitcoind_url.clone(), 5, mining_paused.clone()).await?;
 
-    Ok((docker, bitcoind_url.clone()))
+    Ok((docker, bitcoind_url.clone(), mining_paused))
 }
 
 pub async fn init_electrs_container(
@@ -230,19 +240,23 @@ async fn start_alice(
     let resume_only = false;
 
     let mut swarm = swarm::asb(
-        &seed,
+        seed.derive_libp2p_identity(),
         min_buy,
         max_buy,
         latest_rate,
         resume_only,
         env_config,
-        None,
+        XmrBtcNamespace::Testnet,
+        &[],
+        ConnectionLimits::default(),
+        KeyPool::default(),
     )
     .unwrap();
     swarm.listen_on(listen_address).unwrap();
 
     let (event_loop, swap_handle) = asb::EventLoop::new(
         swarm,
+        seed.derive_libp2p_identity(),
         env_config,
         bitcoin_wallet,
         monero_wallet,
@@ -250,6 +264,10 @@ async fn start_alice(
         FixedRate::default(),
         min_buy,
         max_buy,
+        true,
+        false,
+        true,
+        ConnectionLimits::default().max_established_per_ip,
     )
     .unwrap();
 
@@ -296,12 +314,13 @@ async fn init_test_wallets(
     };
 
     let btc_wallet = swap::bitcoin::Wallet::new(
-        electrum_rpc_url,
+        vec![electrum_rpc_url],
         datadir,
         seed.derive_extended_private_key(env_config.bitcoin_network)
             .expect("Could not create extended private key from seed"),
         env_config,
         1,
+        None,
     )
     .await
     .expect("could not init btc wallet");
@@ -421,14 +440,15 @@ impl BobParams {
         &self,
         btc_amount: bitcoin::Amount,
     ) -> Result<(bob::Swap, cli::EventLoop)> {
-        let swap_id = Uuid::new_v4();
+        let session_nonce = SessionNonce::random();
+        let swap_id = session_nonce.swap_id(self.alice_peer_id);
 
         let (event_loop, handle) = self.new_eventloop(swap_id).await?;
         let db = Database::open(&self.db_path)?;
 
         let swap = bob::Swap::new(
             db,
-            swap_id,
+            session_nonce,
             self.bitcoin_wallet.clone(),
             self.monero_wallet.clone(),
             self.env_config,
@@ -506,6 +526,9 @@ pub struct TestContext {
     bob_starting_balances: StartingBalances,
     bob_bitcoin_wallet: Arc<bitcoin::Wallet>,
     bob_monero_wallet: Arc<monero::Wallet>,
+
+    bitcoind_url: Url,
+    bitcoin_mining_paused: Arc<AtomicBool>,
 }
 
 impl TestContext {
@@ -558,6 +581,103 @@ impl TestContext {
         (swap, BobApplicationHandle(join_handle))
     }
 
+    /// Reorganises the Bitcoin blockchain by invalidating the blocks since
+    /// the lock transaction was included, which moves it back into the
+    /// mempool, and waits until Alice noticed.
+    ///
+    /// Mining is paused so the lock transaction stays unconfirmed until
+    /// [`TestContext::resume_bitcoin_mining`] is called.
+    pub async fn reorg_out_btc_lock(&self, tx_lock: &bitcoin::TxLock) {
+        self.bitcoin_mining_paused
+            .store(true, AtomicOrdering::SeqCst);
+
+        // Look the block up in bitcoind instead of deriving it from the
+        // confirmations, electrs may not have caught up with the tip yet.
+        let txid = tx_lock.txid().to_string();
+        let mut block_hash = bitcoind_rpc(
+            &self.bitcoind_url,
+            "getbestblockhash",
+            serde_json::json!([]),
+        )
+        .await
+        .unwrap();
+        loop {
+            let block = bitcoind_rpc(
+                &self.bitcoind_url,
+                "getblock",
+                serde_json::json!([block_hash, 1]),
+            )
+            .await
+            .unwrap();
+            let transactions = block["tx"].as_array().expect("block without transactions");
+
+            if transactions
+                .iter()
+                .any(|tx| tx.as_str() == Some(txid.as_str()))
+            {
+                break;
+            }
+
+            block_hash = block
+                .get("previousblockhash")
+                .cloned()
+                .expect("lock transaction is not confirmed");
+        }
+
+        bitcoind_rpc(
+            &self.bitcoind_url,
+            "invalidateblock",
+            serde_json::json!([block_hash]),
+        )
+        .await
+        .unwrap();
+
+        timeout(Duration::from_secs(20), async {
+            loop {
+                let status = self
+                    .alice_bitcoin_wallet
+                    .status_of_script(tx_lock)
+                    .await
+                    .unwrap();
+
+                if !status.is_confirmed() {
+                    return;
+                }
+
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("Alice did not notice the reorg within 20 seconds");
+    }
+
+    /// Waits until Bob no longer sees the cancel transaction, which a
+    /// reorganisation drops once the cancel timelock is no longer expired.
+    pub async fn wait_until_bob_dropped_tx_cancel(&self, tx_cancel: &TxCancel) {
+        timeout(Duration::from_secs(20), async {
+            loop {
+                let status = self
+                    .bob_bitcoin_wallet
+                    .status_of_script(tx_cancel)
+                    .await
+                    .unwrap();
+
+                if !status.has_been_seen() {
+                    return;
+                }
+
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("Bob did not notice the dropped cancel transaction within 20 seconds");
+    }
+
+    pub fn resume_bitcoin_mining(&self) {
+        self.bitcoin_mining_paused
+            .store(false, AtomicOrdering::SeqCst);
+    }
+
     pub async fn assert_alice_redeemed(&mut self, state: AliceState) {
         assert!(matches!(state, AliceState::BtcRedeemed));
 
@@ -599,6 +719,27 @@ impl TestContext {
         .unwrap();
     }
 
+    /// Alice never locked the Monero and keeps it.
+    pub async fn assert_alice_safely_aborted(&self, state: AliceState) {
+        assert!(matches!(state, AliceState::SafelyAborted));
+
+        assert_eventual_balance(
+            self.alice_bitcoin_wallet.as_ref(),
+            Ordering::Equal,
+            self.alice_starting_balances.btc,
+        )
+        .await
+        .unwrap();
+
+        assert_eventual_balance(
+            self.alice_monero_wallet.as_ref(),
+            Ordering::Equal,
+            self.alice_starting_balances.xmr,
+        )
+        .await
+        .unwrap();
+    }
+
     pub async fn assert_alice_punished(&self, state: AliceState) {
         assert!(matches!(state, AliceState::BtcPunished));
 
@@ -640,6 +781,15 @@ impl TestContext {
         .unwrap();
     }
 
+    /// Asserts that Bob stored the receipt of the swap with the signatures of
+    /// both parties, the swap must not be running anymore.
+    pub fn assert_bob_has_receipt(&self, swap_id: Uuid) {
+        let db = Database::open(&self.bob_params.db_path).unwrap();
+        let receipt = db.get_receipt(swap_id).unwrap();
+
+        assert!(receipt.verify().is_ok());
+    }
+
     pub async fn assert_bob_refunded(&self, state: BobState) {
         self.bob_bitcoin_wallet.sync().await.unwrap();
 
@@ -877,16 +1027,27 @@ fn random_prefix() -> String {
     chars
 }
 
-async fn mine(bitcoind_client: Client, reward_address: bitcoin::Address) -> Result<()> {
+async fn mine(
+    bitcoind_client: Client,
+    reward_address: bitcoin::Address,
+    paused: Arc<AtomicBool>,
+) -> Result<()> {
     loop {
         tokio::time::sleep(Duration::from_secs(1)).await;
+        if paused.load(AtomicOrdering::SeqCst) {
+            continue;
+        }
         bitcoind_client
             .generatetoaddress(1, reward_address.clone(), None)
             .await?;
     }
 }
 
-async fn init_bitcoind(node_url: Url, spendable_quantity: u32) -> Result<Client> {
+async fn init_bitcoind(
+    node_url: Url,
+    spendable_quantity: u32,
+    mining_paused: Arc<AtomicBool>,
+) -> Result<Client> {
     let bitcoind_client = Client::new(node_url.clone());
 
     bitcoind_client
@@ -901,10 +1062,40 @@ async fn init_bitcoind(node_url: Url, spendable_quantity: u32) -> Result<Client>
     bitcoind_client
         .generatetoaddress(101 + spendable_quantity, reward_address.clone(), None)
         .await?;
-    let _ = tokio::spawn(mine(bitcoind_client.clone(), reward_address));
+    let _ = tokio::spawn(mine(bitcoind_client.clone(), reward_address, mining_paused));
     Ok(bitcoind_client)
 }
 
+/// Calls an RPC method of bitcoind that the harness client does not offer.
+async fn bitcoind_rpc(
+    node_url: &Url,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let body = serde_json::json!({
+        "jsonrpc": "1.0",
+        "id": "swap-tests",
+        "method": method,
+        "params": params,
+    });
+
+    let response = reqwest::Client::new()
+        .post(node_url.as_str())
+        .basic_auth(node_url.username(), node_url.password())
+        .body(body.to_string())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let mut response = serde_json::from_str::<serde_json::Value>(&response)?;
+
+    if !response["error"].is_null() {
+        bail!("bitcoind failed to {}: {}", method, response["error"]);
+    }
+
+    Ok(response["result"].take())
+}
+
 /// Send Bitcoin to the specified address, limited to the spendable bitcoin
 /// quantity.
 pub async fn mint(node_url: Url, address: bitcoin::Address, amount: bitcoin::Amount) -> Result<()> {
@@ -930,6 +1121,7 @@ pub async fn mint(node_url: Url, address: bitcoin::Address, amount: bitcoin::Amo
 #[allow(dead_code)]
 struct Containers<'a> {
     bitcoind_url: Url,
+    bitcoin_mining_paused: Arc<AtomicBool>,
     bitcoind: Container<'a, Cli, bitcoind::Bitcoind>,
     monerod_container: Container<'a, Cli, image::Monerod>,
     monero_wallet_rpc_containers: Vec<Container<'a, Cli, image::MoneroWalletRpc>>,
@@ -943,9 +1135,25 @@ pub mod alice_run_until {
         matches!(state, AliceState::XmrLockTransactionSent { .. })
     }
 
+    pub fn is_xmr_locked(state: &AliceState) -> bool {
+        matches!(state, AliceState::XmrLocked { .. })
+    }
+
+    pub fn is_transfer_proof_sent(state: &AliceState) -> bool {
+        matches!(state, AliceState::XmrLockTransferProofSent { .. })
+    }
+
     pub fn is_encsig_learned(state: &AliceState) -> bool {
         matches!(state, AliceState::EncSigLearned { .. })
     }
+
+    pub fn is_btc_locked(state: &AliceState) -> bool {
+        matches!(state, AliceState::BtcLocked { .. })
+    }
+
+    pub fn is_btc_lock_transaction_seen(state: &AliceState) -> bool {
+        matches!(state, AliceState::BtcLockTransactionSeen { .. })
+    }
 }
 
 pub mod bob_run_until {
@@ -955,6 +1163,10 @@ pub mod bob_run_until {
         matches!(state, BobState::BtcLocked(..))
     }
 
+    pub fn is_btc_cancelled(state: &BobState) -> bool {
+        matches!(state, BobState::BtcCancelled(..))
+    }
+
     pub fn is_lock_proof_received(state: &BobState) -> bool {
         matches!(state, BobState::XmrLockProofReceived { .. })
     }
@@ -1001,3 +1213,16 @@ impl GetConfig for FastPunishConfig {
         }
     }
 }
+
+/// Requires enough confirmations of the lock transaction that a reorg can
+/// undo them before they are re-mined.
+pub struct DeepLockConfig;
+
+impl GetConfig for DeepLockConfig {
+    fn get_config() -> Config {
+        Config {
+            bitcoin_finality_confirmations: 10,
+            ..SlowCancelConfig::get_config()
+        }
+    }
+}